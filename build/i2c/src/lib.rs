@@ -775,6 +775,9 @@ impl ConfigGenerator {
     #[allow(dead_code)]
     pub const NMUXEDBUSES: usize = {nmuxedbuses};
 
+    #[allow(dead_code)]
+    pub const NMUXES: usize = {len};
+
     use drv_stm32xx_i2c::I2cMux;
 
     pub fn muxes() -> [I2cMux<'static>; {}] {{"##,
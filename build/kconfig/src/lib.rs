@@ -18,6 +18,96 @@ pub struct KernelConfig {
 
     /// Interrupts hooked by the application, keyed by IRQ number.
     pub irqs: BTreeMap<u32, InterruptConfig>,
+
+    /// Hardware watchdog heartbeat settings, if the kernel is responsible for
+    /// petting the watchdog.
+    #[serde(default)]
+    pub watchdog: Option<WatchdogConfig>,
+
+    /// Round-robin timeslice quantum in kernel ticks, if equal-priority tasks
+    /// should take turns.
+    #[serde(default)]
+    pub timeslice: Option<u32>,
+
+    /// Interrupt storm protection settings, if enabled.
+    #[serde(default)]
+    pub irq_storm: Option<IrqStormConfig>,
+
+    /// Crash-loop restart throttle settings, if enabled.
+    #[serde(default)]
+    pub restart_throttle: Option<RestartThrottleConfig>,
+
+    /// Dead-man settings for servers that don't reply, if enabled.
+    #[serde(default)]
+    pub reply_deadman: Option<ReplyDeadmanConfig>,
+
+    /// Kernel tick rate, in ticks per second.
+    #[serde(default = "default_tick_hz")]
+    pub tick_hz: u32,
+
+    /// Tickless idle settings, if enabled.
+    #[serde(default)]
+    pub tickless_idle: Option<TicklessIdleConfig>,
+}
+
+fn default_tick_hz() -> u32 {
+    1000
+}
+
+/// Configuration for stopping the kernel tick while the system is idle.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct TicklessIdleConfig {
+    /// Index of the idle task. While it's the one running, the kernel skips
+    /// ticks until the next deadline.
+    pub idle_task: usize,
+}
+
+/// Configuration for detecting servers that never reply.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct ReplyDeadmanConfig {
+    /// Number of ticks a client may wait for a reply before the kernel reports
+    /// it.
+    pub ticks: u32,
+    /// Notification mask to post to the supervisor when a client has waited
+    /// too long.
+    pub notification: u64,
+}
+
+/// Configuration for the kernel's restart throttle.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct RestartThrottleConfig {
+    /// Number of faults a task may take within `window` ticks before the
+    /// kernel holds it.
+    pub faults: u32,
+    /// Length of the fault counting window, and initial hold time, in ticks.
+    pub window: u32,
+    /// Notification mask to post to the supervisor when a fault puts a task
+    /// on hold, in place of the usual fault notification.
+    pub held_notification: u64,
+    /// Notification mask to post to the supervisor when a hold expires.
+    pub released_notification: u64,
+}
+
+/// Configuration for interrupt storm protection.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct IrqStormConfig {
+    /// Maximum number of times an interrupt may fire within one kernel tick
+    /// before the kernel masks it.
+    pub limit: u32,
+    /// Notification mask to post to the supervisor when an interrupt is masked
+    /// for storming.
+    pub notification: u64,
+}
+
+/// Configuration for the kernel's watchdog heartbeat.
+///
+/// When this is present, the kernel will only pet the hardware watchdog once
+/// it has observed forward progress from every task within a window.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct WatchdogConfig {
+    /// Length of the progress window, in kernel ticks. Every runnable task must
+    /// be scheduled at least once per window for the watchdog to be petted.
+    pub window: u32,
 }
 
 /// Configuration for a single hooked interrupt.
@@ -40,7 +130,7 @@ pub struct InterruptConfig {
     /// Notification bits that are posted to the task when the interrupt fires.
     /// Note that this is a mask and can have multiple (or zero!) bits set; the
     /// kernel doesn't really care.
-    pub notification: u32,
+    pub notification: u64,
 }
 
 /// Record describing a single task.
@@ -71,6 +161,60 @@ pub struct TaskConfig {
 
     /// Should this task be started automatically on boot?
     pub start_at_boot: bool,
+
+    /// Depth of this task's mailbox for asynchronous messages; zero if it has
+    /// none.
+    #[serde(default)]
+    pub mailbox_depth: u8,
+
+    /// Does this task use the floating-point unit?
+    #[serde(default)]
+    pub uses_fpu: bool,
+
+    /// May this task have more regions than fit in the MPU at once? If so, the
+    /// extras are faulted in on demand.
+    #[serde(default)]
+    pub virtual_regions: bool,
+
+    /// May this task read the state of every task, like the supervisor?
+    #[serde(default)]
+    pub monitor: bool,
+
+    /// May this task read the boot measurements, like the supervisor?
+    #[serde(default)]
+    pub attester: bool,
+
+    /// May this task change the kernel's tick divisor, when it changes the
+    /// clock driving the kernel timer?
+    #[serde(default)]
+    pub clock_owner: bool,
+
+    /// Parts of the task's RAM that the kernel initializes whenever the task
+    /// is (re)started, in order.
+    #[serde(default)]
+    pub ram_init: Vec<RamInitConfig>,
+}
+
+/// A range of task RAM to be initialized by the kernel at task (re)start.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RamInitConfig {
+    /// Start of the range, which must be word-aligned.
+    pub base: OwnedAddress,
+    /// Size of the range in bytes, which must be a multiple of 4.
+    pub size: u32,
+    /// What to put there.
+    pub fill: RamFillConfig,
+}
+
+/// Contents of a `RamInitConfig` range.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum RamFillConfig {
+    /// All zeros.
+    Zero,
+    /// The given word, repeated.
+    Pattern(u32),
+    /// A copy of the same number of bytes at `source`.
+    Copy { source: OwnedAddress },
 }
 
 /// An address within an owned region of memory.
@@ -146,6 +290,10 @@ pub struct RegionAttributes {
     /// Special role assigned to this region, if any. This controls cache
     /// behavior, among other things.
     pub special_role: Option<SpecialRole>,
+    /// Region keeps its contents when the task that owns it restarts. The
+    /// kernel zeroes it once, at boot.
+    #[serde(default)]
+    pub sticky: bool,
 }
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
@@ -153,6 +301,9 @@ pub enum SpecialRole {
     /// Region contains memory mapped registers. This affects cache behavior
     /// on devices that include it, and discourages the kernel from using
     /// `memcpy` in the region.
+    ///
+    /// Peripherals always have this role; memory regions get it with
+    /// `device = true`.
     Device,
     /// Region can be used for DMA or communication with other processors.
     /// This heavily restricts how this memory can be cached and will hurt
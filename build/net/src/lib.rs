@@ -2,7 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use serde::Deserialize;
 use std::collections::{BTreeMap, BTreeSet};
 
@@ -28,6 +28,18 @@ pub struct NetConfig {
     /// Sockets known to the system, indexed by name.
     pub sockets: BTreeMap<String, SocketConfig>,
 
+    /// TCP sockets known to the system, indexed by name.
+    #[serde(default)]
+    pub tcp_sockets: BTreeMap<String, TcpSocketConfig>,
+
+    /// DHCP client configuration, or None. This is checked against enabled
+    /// features during the `net` build, so it must be present iff the `dhcp`
+    /// feature is turned on.
+    pub dhcp: Option<DhcpConfig>,
+
+    /// mDNS responder configuration, or None to not run one.
+    pub mdns: Option<MdnsConfig>,
+
     /// VLAN configuration, or None. This is checked against enabled features
     /// during the `net` build, so it must be non-empty iff the `vlan` feature
     /// is turned on.
@@ -49,12 +61,86 @@ pub struct SocketConfig {
     pub owner: TaskNote,
     pub port: u16,
     pub tx: BufSize,
+    /// Size of the socket's buffer in the stack, and of its owner's receive
+    /// queue.
     pub rx: BufSize,
 
+    /// Further tasks that each receive their own copy of every packet
+    /// arriving on the socket, and may send through it.
+    #[serde(default)]
+    pub clients: Vec<ClientConfig>,
+
     #[serde(default)]
     pub allow_untrusted: bool,
 }
 
+impl SocketConfig {
+    /// Returns the socket's clients, starting with its owner, as (task name,
+    /// notification, receive queue size).
+    pub fn all_clients(&self) -> impl Iterator<Item = (&str, &str, &BufSize)> {
+        std::iter::once((
+            self.owner.name.as_str(),
+            self.owner.notification.as_str(),
+            &self.rx,
+        ))
+        .chain(
+            self.clients
+                .iter()
+                .map(|c| (c.name.as_str(), c.notification.as_str(), &c.rx)),
+        )
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ClientConfig {
+    pub name: String,
+    pub notification: String,
+    pub rx: BufSize,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TcpSocketConfig {
+    pub owner: TaskNote,
+    /// Port to listen on, and to connect from; if 0, the socket can only
+    /// connect, from an ephemeral port.
+    #[serde(default)]
+    pub port: u16,
+    /// Size of the transmit buffer, in bytes.
+    pub tx_bytes: usize,
+    /// Size of the receive buffer, in bytes.
+    pub rx_bytes: usize,
+    /// The VLAN on which the socket lives, which must be given iff the
+    /// `vlan` feature is turned on.
+    pub vlan: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DhcpConfig {
+    /// The VLAN on which to run the client, which must be given iff the
+    /// `vlan` feature is turned on.
+    pub vlan: Option<String>,
+    /// Tasks to notify when addressing changes, as a map from task name to
+    /// notification name (in the target task)
+    #[serde(default)]
+    pub on_address_change: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MdnsConfig {
+    /// Host name to advertise, to which the responder appends the bottom half
+    /// of the MAC address, so that boards of a kind can be told apart.
+    pub hostname: String,
+    /// DNS-SD service type, e.g. `_hubris._udp`.
+    pub service: String,
+    /// Sockets whose ports are advertised, each as an instance of the
+    /// service.
+    pub sockets: Vec<String>,
+}
+
 #[derive(Copy, Clone, Debug, Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct VLanConfig {
@@ -95,9 +181,74 @@ pub fn load_net_config() -> Result<NetConfig> {
         _ => (),
     }
 
+    // Neither TCP sockets nor the DHCP client consult VLAN trust, so they can
+    // only live on VLANs that are trusted from the start.
+    for (name, socket) in &cfg.tcp_sockets {
+        check_trusted_vlan(&cfg, &format!("TCP socket {name}"), &socket.vlan)?;
+    }
+    if let Some(dhcp) = &cfg.dhcp {
+        check_trusted_vlan(&cfg, "DHCP client", &dhcp.vlan)?;
+    }
+    if let Some(mdns) = &cfg.mdns {
+        check_mdns_config(&cfg, mdns)?;
+    }
+
     Ok(cfg)
 }
 
+fn check_mdns_config(cfg: &NetConfig, mdns: &MdnsConfig) -> Result<()> {
+    let is_label = |s: &str| {
+        !s.is_empty()
+            && s.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    };
+    if !is_label(&mdns.hostname) {
+        bail!("mDNS hostname {:?} is not a valid DNS label", mdns.hostname);
+    }
+    match mdns.service.split_once('.') {
+        Some((app, "_udp" | "_tcp"))
+            if app.starts_with('_') && is_label(&app[1..]) => {}
+        _ => bail!(
+            "mDNS service {:?} must look like _name._udp or _name._tcp",
+            mdns.service
+        ),
+    }
+    // The responder keeps track of which instances were asked for in a u32.
+    if mdns.sockets.len() > 32 {
+        bail!("mDNS can advertise at most 32 sockets");
+    }
+    for s in &mdns.sockets {
+        if !cfg.sockets.contains_key(s) {
+            bail!("mDNS advertises unknown socket {s}");
+        }
+        // Instance labels are `<hostname>-xxxxxx-<socket>`, and instance
+        // names (with the service and `local`) must fit the responder's
+        // 96-byte buffers.
+        let label = mdns.hostname.len() + 8 + s.len();
+        if label > 63 || label + mdns.service.len() + 9 > 96 {
+            bail!("mDNS instance name for socket {s} is too long");
+        }
+    }
+    Ok(())
+}
+
+fn check_trusted_vlan(
+    cfg: &NetConfig,
+    what: &str,
+    vlan: &Option<String>,
+) -> Result<()> {
+    match (cfg.vlans.is_empty(), vlan) {
+        (true, None) => (),
+        (true, Some(_)) => bail!("{what} names a VLAN, but there are none"),
+        (false, None) => bail!("{what} must name a VLAN"),
+        (false, Some(v)) => match cfg.vlans.get(v) {
+            Some(vlan) if vlan.trusted => (),
+            Some(_) => bail!("{what} is on untrusted VLAN {v}"),
+            None => bail!("{what} is on unknown VLAN {v}"),
+        },
+    }
+    Ok(())
+}
+
 pub fn generate_port_consts(
     config: &NetConfig,
     mut out: impl std::io::Write,
@@ -148,6 +299,11 @@ pub fn generate_vlan_consts(
     writeln!(out, "{s}")
 }
 
+/// Returns the name of the `VLanId` variant for the VLAN named `name`.
+pub fn vlan_variant(name: &str) -> String {
+    name.to_case(Case::UpperCamel)
+}
+
 pub fn generate_vlan_enum(
     config: &NetConfig,
     mut out: impl std::io::Write,
@@ -171,9 +327,7 @@ pub fn generate_vlan_enum(
         let names = config
             .vlans
             .keys()
-            .map(|b| -> TokenStream {
-                b.to_case(Case::UpperCamel).parse().unwrap()
-            })
+            .map(|b| -> TokenStream { vlan_variant(b).parse().unwrap() })
             .collect::<Vec<_>>();
         let cfgs = config
             .vlans
@@ -278,3 +432,44 @@ pub fn generate_socket_enum(
 
     Ok(())
 }
+
+pub fn generate_tcp_socket_enum(
+    config: &NetConfig,
+    mut out: impl std::io::Write,
+) -> Result<(), std::io::Error> {
+    let count = config.tcp_sockets.len();
+    writeln!(out, "pub const TCP_SOCKET_COUNT: usize = {count};")?;
+
+    writeln!(out, "#[allow(non_camel_case_types)]")?;
+    // An enum with no variants can't have a `repr`, or be cast.
+    if count > 0 {
+        writeln!(out, "#[repr(u8)]")?;
+    }
+    writeln!(
+        out,
+        "#[derive(Copy, Clone, Debug, Eq, PartialEq, userlib::FromPrimitive)]"
+    )?;
+    writeln!(
+        out,
+        "#[derive(serde::Serialize, \
+                  serde::Deserialize, \
+                  hubpack::SerializedSize)]"
+    )?;
+    writeln!(out, "pub enum TcpSocketName {{")?;
+    for (i, name) in config.tcp_sockets.keys().enumerate() {
+        writeln!(out, "    {} = {},", name, i)?;
+    }
+    writeln!(out, "}}")?;
+
+    writeln!(out, "impl TcpSocketName {{")?;
+    writeln!(out, "    pub fn index(self) -> usize {{")?;
+    if count > 0 {
+        writeln!(out, "        self as usize")?;
+    } else {
+        writeln!(out, "        match self {{}}")?;
+    }
+    writeln!(out, "    }}")?;
+    writeln!(out, "}}")?;
+
+    Ok(())
+}
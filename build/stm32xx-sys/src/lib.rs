@@ -13,6 +13,21 @@ pub struct SysConfig {
     /// EXTI interrupts
     #[serde(default)]
     gpio_irqs: BTreeMap<String, GpioIrqConfig>,
+
+    /// Limits for runtime clock tree changes
+    #[serde(default)]
+    clocks: Option<ClockConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ClockConfig {
+    /// Frequency of the external oscillator, if the board has one.
+    #[serde(default)]
+    hse_hz: Option<u32>,
+    /// Fastest the CPU may be clocked; this must not exceed what the flash
+    /// wait states and core voltage set up at boot allow.
+    max_cpu_hz: u32,
 }
 
 #[derive(Deserialize)]
@@ -135,6 +150,29 @@ impl SysConfig {
         !self.gpio_irqs.is_empty()
     }
 
+    pub fn needs_clock_control(&self) -> bool {
+        self.clocks.is_some()
+    }
+
+    pub fn generate_clock_config(
+        &self,
+    ) -> anyhow::Result<proc_macro2::TokenStream> {
+        let Some(clocks) = &self.clocks else {
+            anyhow::bail!(
+                "runtime clock control requires a `clocks` section in the \
+                 sys task's config"
+            );
+        };
+        // Zero means "no external oscillator"; there's no such thing as a 0Hz
+        // one.
+        let hse_hz = clocks.hse_hz.unwrap_or(0);
+        let max_cpu_hz = clocks.max_cpu_hz;
+        Ok(quote! {
+            pub(crate) const HSE_HZ: u32 = #hse_hz;
+            pub(crate) const MAX_CPU_HZ: u32 = #max_cpu_hz;
+        })
+    }
+
     pub fn generate_exti_config(
         &self,
     ) -> anyhow::Result<proc_macro2::TokenStream> {
@@ -30,8 +30,9 @@ SECTIONS
   /*
    * Sections in RAM
    *
-   * NOTE: the userlib runtime assumes that these sections
-   * are 4-byte aligned and padded to 4-byte boundaries.
+   * NOTE: the kernel initializes .data and .bss (and, if asked, .uninit)
+   * at task start, and requires that they be 4-byte aligned and padded to
+   * 4-byte boundaries.
    */
   .data : ALIGN(4) {
     . = ALIGN(4);
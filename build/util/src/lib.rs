@@ -339,13 +339,16 @@ pub fn build_notifications() -> Result<()> {
 }
 
 fn write_task_notifications<W: Write>(out: &mut W, t: &[String]) -> Result<()> {
-    if t.len() > 32 {
-        bail!("Too many notifications; cannot fit in a `u32` mask");
+    if t.len() > 64 {
+        bail!("Too many notifications; cannot fit in a `u64` mask");
     }
     for (i, n) in t.iter().enumerate() {
         let n = n.to_uppercase().replace('-', "_");
+        // The first 32 bits keep `u32` masks, which is what most of userlib
+        // takes; the rest can only be used with the `_wide` calls.
+        let ty = if i < 32 { "u32" } else { "u64" };
         writeln!(out, "pub const {n}_BIT: u8 = {i};")?;
-        writeln!(out, "pub const {n}_MASK: u32 = 1 << {n}_BIT;")?;
+        writeln!(out, "pub const {n}_MASK: {ty} = 1 << {n}_BIT;")?;
     }
     Ok(())
 }
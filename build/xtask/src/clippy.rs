@@ -82,6 +82,7 @@ pub fn run(
                 &toml,
                 &allocs.tasks,
                 &entry_points,
+                &std::collections::HashMap::new(),
                 &toml.image_names[0],
             )?;
             let kconfig = ron::ser::to_string(&kconfig)?;
@@ -39,6 +39,8 @@ struct RawConfig {
     tasks: IndexMap<String, Task>,
     #[serde(default)]
     extratext: IndexMap<String, Peripheral>,
+    #[serde(default)]
+    shared_regions: IndexMap<String, SharedRegion>,
     config: Option<ordered_toml::Value>,
     auxflash: Option<AuxFlash>,
     caboose: Option<CabooseConfig>,
@@ -61,6 +63,7 @@ pub struct Config {
     pub tasks: IndexMap<String, Task>,
     pub peripherals: IndexMap<String, Peripheral>,
     pub extratext: IndexMap<String, Peripheral>,
+    pub shared_regions: IndexMap<String, SharedRegion>,
     pub config: Option<ordered_toml::Value>,
     pub buildhash: u64,
     pub app_toml_path: PathBuf,
@@ -184,6 +187,7 @@ impl Config {
             tasks: toml.tasks,
             peripherals,
             extratext: toml.extratext,
+            shared_regions: toml.shared_regions,
             config: toml.config,
             auxflash,
             buildhash,
@@ -260,6 +264,10 @@ impl Config {
         );
         env.insert("HUBRIS_BUILD_EPOCH".to_string(), format!("{}", self.epoch));
         env.insert("HUBRIS_BOARD".to_string(), self.board.to_string());
+        env.insert(
+            "HUBRIS_TICK_HZ".to_string(),
+            format!("{}", self.kernel.tick_hz.unwrap_or(1000)),
+        );
         env.insert(
             "HUBRIS_APP_TOML".to_string(),
             app_toml_path.to_str().unwrap().to_string(),
@@ -630,6 +638,78 @@ pub struct Kernel {
     pub features: Vec<String>,
     #[serde(default)]
     pub no_default_features: bool,
+    pub watchdog: Option<KernelWatchdog>,
+    /// Round-robin timeslice quantum, in kernel ticks. If set, runnable tasks
+    /// that share a priority level take turns on the CPU, switching after this
+    /// many ticks. If omitted, a task runs until it blocks or is preempted by
+    /// a more important task.
+    pub timeslice: Option<u32>,
+    pub irq_storm: Option<KernelIrqStorm>,
+    pub restart_throttle: Option<KernelRestartThrottle>,
+    pub reply_deadman: Option<KernelReplyDeadman>,
+    /// Kernel tick rate, in ticks per second. Defaults to 1000, so that a tick
+    /// is a millisecond; tasks that assume that should use `userlib::TICK_HZ`
+    /// (or the helpers around it) if this changes.
+    pub tick_hz: Option<u32>,
+    /// If `true`, the kernel stops its tick while the idle task is running,
+    /// waking only for the next deadline or an interrupt. This requires a task
+    /// named `idle`, and isn't compatible with `watchdog`.
+    #[serde(default)]
+    pub tickless_idle: bool,
+    /// If `true`, the build records a CRC of the image in its header, and the
+    /// kernel checks the image against it at startup, to catch corrupted
+    /// flash. The result is available through the `read_image_check` kipc;
+    /// a mismatch doesn't stop the image from booting.
+    #[serde(default)]
+    pub image_check: bool,
+}
+
+/// Settings for detecting servers that never reply, found in
+/// `[kernel.reply-deadman]`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct KernelReplyDeadman {
+    /// Number of ticks a server may hold a message without replying. Past
+    /// this, the kernel posts the supervisor's `reply-deadman` notification.
+    pub ticks: u32,
+}
+
+/// Settings for the kernel's restart throttle, found in
+/// `[kernel.restart-throttle]`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct KernelRestartThrottle {
+    /// Number of faults a task may take within `window` ticks. A task that
+    /// faults more often than this is held at its fault, and can't be
+    /// restarted until its hold expires. The kernel posts the supervisor's
+    /// `restart-held` notification when this happens, and its
+    /// `restart-released` notification when the hold expires.
+    pub faults: u32,
+    /// Length of the counting window, in ticks. This is also the initial hold
+    /// time; it doubles each time the task is held again.
+    pub window: u32,
+}
+
+/// Settings for interrupt storm protection, found in `[kernel.irq-storm]`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct KernelIrqStorm {
+    /// Number of times an interrupt may fire within a single kernel tick. If
+    /// it fires more often than this, the kernel leaves it masked and posts
+    /// the supervisor's `irq-storm` notification instead of notifying the
+    /// owning task.
+    pub limit: u32,
+}
+
+/// Settings for the kernel's watchdog heartbeat, found in `[kernel.watchdog]`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct KernelWatchdog {
+    /// Length of the window (in kernel ticks, typically milliseconds) within
+    /// which every runnable task must be scheduled for the kernel to pet the
+    /// watchdog. This must be comfortably shorter than the hardware watchdog's
+    /// own timeout.
+    pub window: u32,
 }
 
 fn default_name() -> String {
@@ -649,8 +729,14 @@ pub struct Output {
     pub write: bool,
     #[serde(default)]
     pub execute: bool,
+    /// Mapped as uncached memory for DMA; see `SpecialRole::Dma`
     #[serde(default)]
     pub dma: bool,
+    /// Mapped as device memory, for memory-mapped registers that aren't
+    /// described as a peripheral (e.g. an FPGA behind the FMC); see
+    /// `SpecialRole::Device`
+    #[serde(default)]
+    pub device: bool,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -662,6 +748,16 @@ pub struct Peripheral {
     pub interrupts: BTreeMap<String, u32>,
 }
 
+/// A named region of read-only memory (typically a table in flash) that any
+/// number of tasks can map by listing it in their `uses`, found in
+/// `[shared-regions.NAME]`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct SharedRegion {
+    pub address: u32,
+    pub size: u32,
+}
+
 pub use toml_task::Task;
 
 /// Stores arguments and environment variables to run on a particular task.
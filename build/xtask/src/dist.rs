@@ -19,7 +19,7 @@ use lpc55_rom_data::FLASH_PAGE_SIZE as LPC55_FLASH_PAGE_SIZE;
 use multimap::MultiMap;
 use path_slash::{PathBufExt, PathExt};
 use sha3::{Digest, Sha3_256};
-use zerocopy::AsBytes;
+use zerocopy::{AsBytes, FromBytes};
 
 use crate::{
     caboose_pos,
@@ -394,25 +394,34 @@ pub fn package(
     // Build a set of requests for the memory allocator
     let mut task_reqs = HashMap::new();
     for (t, sz) in task_sizes {
-        let n = sz.len()
+        let task = cfg.toml.tasks.get(t).unwrap();
+        let owned = sz.len()
             + cfg
                 .toml
                 .extern_regions_for(t, &cfg.toml.image_names[0])
                 .unwrap()
-                .len()
-            + cfg.toml.tasks.get(t).unwrap().uses.len()
+                .len();
+        let shared = task.uses.len()
             + cfg
                 .toml
                 .caboose
                 .as_ref()
                 .map(|c| c.tasks.contains(&t.to_string()))
                 .unwrap_or(false) as usize;
+        // One MPU region always goes to the null region. Tasks with virtual
+        // regions keep only their owned regions resident, and leave two slots
+        // free for the kernel to load shared regions into.
+        let budget = if task.virtual_regions {
+            5
+        } else {
+            7usize.saturating_sub(shared)
+        };
 
         task_reqs.insert(
             t,
             TaskRequest {
                 memory: sz,
-                spare_regions: 7 - n,
+                spare_regions: budget.saturating_sub(owned),
             },
         );
     }
@@ -454,7 +463,11 @@ pub fn package(
 
         // Build all relevant tasks, collecting entry points into a HashMap.  If
         // we're doing a partial build, then assign a dummy entry point into
-        // the HashMap, because the kernel kconfig will still need it.
+        // the HashMap, because the kernel kconfig will still need it.  Tasks
+        // that we build also get their RAM layouts recorded, so the kernel
+        // can initialize their memory; tasks we skip don't need them, because
+        // a partial build never produces an image.
+        let mut ram_layouts = HashMap::new();
         let mut entry_points: HashMap<_, _> = cfg
             .toml
             .tasks
@@ -465,6 +478,10 @@ pub fn package(
                     // because we don't want to track changes in the other
                     // linker input (task-link.x, memory.x, table.ld, etc)
                     link_task(&cfg, name, image_name, allocs)?;
+                    ram_layouts.insert(
+                        name.clone(),
+                        task_ram_layout(&cfg, name, image_name)?,
+                    );
                     task_entry_point(&cfg, name, image_name)
                 } else {
                     // Dummy entry point
@@ -569,6 +586,7 @@ pub fn package(
                 &mut all_output_sections,
                 &cfg.toml.memories(image_name)?,
                 &entry_points,
+                &ram_layouts,
                 image_name,
             )?)
         } else {
@@ -614,11 +632,23 @@ pub fn package(
             .get(&"flash".to_string())
             .ok_or_else(|| anyhow!("failed to get flash region"))?
             .clone();
-        let raw_output_sections: BTreeMap<u32, Vec<u8>> = all_output_sections
-            .into_iter()
-            .map(|(k, v)| (k, v.data))
-            .filter(|(k, _v)| flash.contains(k))
-            .collect();
+        let mut raw_output_sections: BTreeMap<u32, Vec<u8>> =
+            all_output_sections
+                .into_iter()
+                .map(|(k, v)| (k, v.data))
+                .filter(|(k, _v)| flash.contains(k))
+                .collect();
+        if cfg.toml.kernel.image_check {
+            // The caboose is filled in (and the image signed) after this, so
+            // the check stops short of it.
+            let check_end = allocs.caboose.as_ref().map(|(_, r)| r.start);
+            write_image_check(
+                &cfg.img_file("kernel", image_name),
+                &flash,
+                check_end,
+                &mut raw_output_sections,
+            )?;
+        }
         let raw_image = hubtools::RawHubrisImage::from_segments(
             &raw_output_sections,
             kentry,
@@ -855,6 +885,33 @@ fn build_archive(
         )
         .context("could not write memory.toml")?;
 
+    // Record which tasks map each named shared region, so that tools (like
+    // Humility) can report on sharing without re-deriving it from app.toml.
+    if !cfg.toml.shared_regions.is_empty() {
+        let mut sharing = toml::value::Table::new();
+        for (name, r) in &cfg.toml.shared_regions {
+            let tasks: Vec<toml::Value> = cfg
+                .toml
+                .tasks
+                .iter()
+                .filter(|(_, t)| t.uses.contains(name))
+                .map(|(t, _)| toml::Value::String(t.clone()))
+                .collect();
+            let mut entry = toml::value::Table::new();
+            entry.insert("address".into(), i64::from(r.address).into());
+            entry.insert("size".into(), i64::from(r.size).into());
+            entry.insert("tasks".into(), toml::Value::Array(tasks));
+            sharing.insert(name.clone(), toml::Value::Table(entry));
+        }
+        archive
+            .text(
+                "info/shared-regions.toml",
+                toml::to_string(&sharing)
+                    .context("could not serialize shared-regions.toml")?,
+            )
+            .context("could not write shared-regions.toml")?;
+    }
+
     let elf_dir = PathBuf::from("elf");
     let tasks_dir = elf_dir.join("task");
     for name in cfg.toml.tasks.keys() {
@@ -1459,6 +1516,46 @@ fn task_entry_point(
     get_elf_entry_point(&cfg.img_file(name, image_name))
 }
 
+/// Locations of a task's RAM sections, as linked. The kernel sets these up
+/// each time the task starts, rather than leaving it to the task.
+#[derive(Clone, Debug)]
+pub struct TaskRamLayout {
+    /// Where `.data` lives in RAM.
+    data: Range<u32>,
+    /// Where the initial contents of `.data` live in flash.
+    data_image: u32,
+    /// Where `.bss` lives in RAM.
+    bss: Range<u32>,
+    /// Where `.uninit` lives in RAM.
+    uninit: Range<u32>,
+}
+
+/// Reads the RAM layout of the given task from the symbols that
+/// `task-link.x` defines.
+fn task_ram_layout(
+    cfg: &PackageConfig,
+    name: &str,
+    image_name: &str,
+) -> Result<TaskRamLayout> {
+    let file_image = std::fs::read(cfg.img_file(name, image_name))?;
+    let elf = goblin::elf::Elf::parse(&file_image)?;
+
+    let symbol = |wanted: &str| {
+        elf.syms
+            .iter()
+            .find(|s| elf.strtab.get_at(s.st_name) == Some(wanted))
+            .map(|s| s.st_value as u32)
+            .with_context(|| format!("{name} has no `{wanted}` symbol"))
+    };
+    Ok(TaskRamLayout {
+        data: symbol("__sdata")?..symbol("__edata")?,
+        data_image: symbol("__sidata")?,
+        bss: symbol("__sbss")?..symbol("__ebss")?,
+        // `.uninit` starts right after `.bss`, and the heap after that.
+        uninit: symbol("__ebss")?..symbol("__sheap")?,
+    })
+}
+
 /// Populates `all_output_sections` and checks flash size
 fn load_task_flash(
     cfg: &PackageConfig,
@@ -1492,14 +1589,20 @@ fn build_kernel(
     all_output_sections: &mut BTreeMap<u32, LoadSegment>,
     all_memories: &IndexMap<String, Range<u32>>,
     entry_points: &HashMap<String, u32>,
+    ram_layouts: &HashMap<String, TaskRamLayout>,
     image_name: &str,
 ) -> Result<(u32, BTreeMap<String, u32>)> {
     let mut image_id = fnv::FnvHasher::default();
     all_output_sections.hash(&mut image_id);
 
     // Format the descriptors for the kernel build.
-    let kconfig =
-        make_kconfig(&cfg.toml, &allocs.tasks, entry_points, image_name)?;
+    let kconfig = make_kconfig(
+        &cfg.toml,
+        &allocs.tasks,
+        entry_points,
+        ram_layouts,
+        image_name,
+    )?;
     let kconfig = ron::ser::to_string(&kconfig)?;
 
     kconfig.hash(&mut image_id);
@@ -1608,12 +1711,30 @@ fn update_image_header(
                 let end = end.unwrap_or(flash.start);
 
                 let len = end - flash.start;
+                let (required, optional) = image_features(&cfg.toml);
+
+                // Point bootloaders at the kernel's slot for boot
+                // measurements, if it has one.
+                let (boot_measurements, boot_measurements_len) = elf
+                    .syms
+                    .iter()
+                    .find(|sym| {
+                        elf.strtab.get_at(sym.st_name)
+                            == Some("HUBRIS_BOOT_MEASUREMENTS")
+                    })
+                    .map(|sym| (sym.st_value as u32, sym.st_size as u32))
+                    .unwrap_or((0, 0));
 
                 let header = abi::ImageHeader {
                     version: cfg.toml.version,
                     epoch: cfg.toml.epoch,
                     magic: abi::HEADER_MAGIC,
                     total_image_len: len,
+                    format: abi::HEADER_FORMAT,
+                    required_features: required.bits(),
+                    optional_features: optional.bits(),
+                    boot_measurements,
+                    boot_measurements_len,
                     ..Default::default()
                 };
 
@@ -1631,6 +1752,97 @@ fn update_image_header(
     Ok(false)
 }
 
+/// Records a CRC of the image, from the start of flash to `check_end` or the
+/// end of the image, in the image header, for the kernel to check at startup.
+///
+/// The CRC is computed with its own field in the header taken to be zero, and
+/// with any gaps between sections filled with 0xFF, as erased flash reads.
+fn write_image_check(
+    kernel_elf: &Path,
+    flash: &Range<u32>,
+    check_end: Option<u32>,
+    sections: &mut BTreeMap<u32, Vec<u8>>,
+) -> Result<()> {
+    let kernel = std::fs::read(kernel_elf)?;
+    let elf = goblin::elf::Elf::parse(&kernel)?;
+    let header_addr = elf
+        .section_headers
+        .iter()
+        .find(|sec| elf.shdr_strtab.get_at(sec.sh_name) == Some(".header"))
+        .map(|sec| sec.sh_addr as u32)
+        .context("kernel has no .header section")?;
+
+    let header_size = std::mem::size_of::<abi::ImageHeader>() as u32;
+    let (&seg_addr, seg) = sections
+        .range(..=header_addr)
+        .next_back()
+        .filter(|&(&a, d)| header_addr + header_size <= a + d.len() as u32)
+        .context("image header isn't in any flash section")?;
+    let offset = (header_addr - seg_addr) as usize;
+    let mut header = abi::ImageHeader::read_from_prefix(&seg[offset..])
+        .context("reading image header")?;
+
+    let start = flash.start;
+    let end = check_end.unwrap_or(start + header.total_image_len);
+    header.image_check_start = start;
+    header.image_check_len = end - start;
+    header.image_check_crc = 0;
+    let seg = sections.get_mut(&seg_addr).unwrap();
+    header.write_to_prefix(&mut seg[offset..]).unwrap();
+
+    let mut image = vec![0xFF_u8; (end - start) as usize];
+    for (&addr, data) in sections.range(start..end) {
+        let from = (addr - start) as usize;
+        let n = data.len().min(image.len() - from);
+        image[from..from + n].copy_from_slice(&data[..n]);
+    }
+    let mut crc = abi::Crc32::new();
+    crc.update(&image);
+    header.image_check_crc = crc.finish();
+
+    let seg = sections.get_mut(&seg_addr).unwrap();
+    header.write_to_prefix(&mut seg[offset..]).unwrap();
+    Ok(())
+}
+
+/// Works out which kernel features the image depends on, returning the
+/// features it can't run without and the ones it can do without.
+fn image_features(toml: &Config) -> (abi::ImageFeatures, abi::ImageFeatures) {
+    use abi::ImageFeatures;
+
+    let mut required = ImageFeatures::empty();
+    let mut optional = ImageFeatures::empty();
+
+    // A task with a mailbox will have its SEND_ASYNC calls fault on a kernel
+    // that doesn't know about mailboxes, and a task with virtual regions
+    // would fault on its first access to one.
+    if toml.tasks.values().any(|t| t.mailbox > 0) {
+        required |= ImageFeatures::MAILBOXES;
+    }
+    if toml.tasks.values().any(|t| t.virtual_regions) {
+        required |= ImageFeatures::VIRTUAL_REGIONS;
+    }
+
+    // The rest are protective measures; the image works without them.
+    if toml.kernel.restart_throttle.is_some() {
+        optional |= ImageFeatures::RESTART_THROTTLE;
+    }
+    if toml.kernel.irq_storm.is_some() {
+        optional |= ImageFeatures::IRQ_STORM_LIMIT;
+    }
+    if toml.kernel.watchdog.is_some() {
+        optional |= ImageFeatures::WATCHDOG;
+    }
+    if toml.kernel.reply_deadman.is_some() {
+        optional |= ImageFeatures::REPLY_DEADMAN;
+    }
+    if toml.kernel.image_check {
+        optional |= ImageFeatures::IMAGE_CHECK;
+    }
+
+    (required, optional)
+}
+
 /// Checks our dump config:  that if we have a dump agent, it has a task slot
 /// for Jefe (denoting task dump support); that every memory that the dump
 /// agent is using it also being used by Jefe; that if dumps are enabled, the
@@ -2608,6 +2820,7 @@ pub fn make_kconfig(
     toml: &Config,
     task_allocations: &BTreeMap<String, BTreeMap<String, ContiguousRanges>>,
     entry_points: &HashMap<String, u32>,
+    ram_layouts: &HashMap<String, TaskRamLayout>,
     image_name: &str,
 ) -> Result<build_kconfig::KernelConfig> {
     let mut tasks = vec![];
@@ -2636,6 +2849,7 @@ pub fn make_kconfig(
                     write: true,
                     execute: false,
                     special_role: Some(build_kconfig::SpecialRole::Device),
+                    sticky: false,
                 },
             },
         );
@@ -2659,6 +2873,7 @@ pub fn make_kconfig(
                     write: false,
                     execute: true,
                     special_role: None,
+                    sticky: false,
                 },
             },
         );
@@ -2675,6 +2890,55 @@ pub fn make_kconfig(
                     write: false,
                     execute: false,
                     special_role: None,
+                    sticky: false,
+                },
+            },
+        );
+    }
+
+    for (name, r) in &toml.shared_regions {
+        if p2_required && !r.size.is_power_of_two() {
+            bail!(
+                "shared region '{name}' is required to be a power of two, \
+                 but has size {}",
+                r.size
+            );
+        }
+        if flat_shared.contains_key(name) {
+            bail!("shared region '{name}' has the same name as a peripheral");
+        }
+        let range =
+            u64::from(r.address)..u64::from(r.address) + u64::from(r.size);
+        if let Some((other, _)) = flat_shared.iter().find(|(_, o)| {
+            let o_start = u64::from(o.base);
+            o_start < range.end && range.start < o_start + u64::from(o.size)
+        }) {
+            bail!("shared region '{name}' overlaps '{other}'");
+        }
+        for (task, allocs) in task_allocations {
+            for (mem, chunks) in allocs {
+                for c in chunks.iter() {
+                    let (start, end) = (u64::from(c.start), u64::from(c.end));
+                    if start < range.end && range.start < end {
+                        bail!(
+                            "shared region '{name}' overlaps task '{task}' \
+                             {mem}"
+                        );
+                    }
+                }
+            }
+        }
+        flat_shared.insert(
+            name.to_string(),
+            build_kconfig::RegionConfig {
+                base: r.address,
+                size: r.size,
+                attributes: build_kconfig::RegionAttributes {
+                    read: true,
+                    write: false,
+                    execute: false,
+                    special_role: None,
+                    sticky: false,
                 },
             },
         );
@@ -2696,6 +2960,58 @@ pub fn make_kconfig(
             );
         };
 
+        let mut ram_init = vec![];
+        if let Some(layout) = ram_layouts.get(name) {
+            let ram = &task_allocations[name]["ram"];
+            let ram_offset = |addr: u32| {
+                if !ram.contains(&addr) {
+                    bail!(
+                        "{name} has RAM section at {addr:#x}, outside its \
+                         RAM {ram:#x?}"
+                    );
+                }
+                Ok(build_kconfig::OwnedAddress {
+                    region_name: "ram".to_string(),
+                    offset: addr - ram.start(),
+                })
+            };
+            let mut push = |range: &Range<u32>, fill| -> Result<()> {
+                if !range.is_empty() {
+                    ram_init.push(build_kconfig::RamInitConfig {
+                        base: ram_offset(range.start)?,
+                        size: range.end - range.start,
+                        fill,
+                    });
+                }
+                Ok(())
+            };
+            if !layout.data.is_empty() {
+                if !flash.contains(&layout.data_image) {
+                    bail!(
+                        "{name} has .data image at {:#x}, outside its flash \
+                         {flash:#x?}",
+                        layout.data_image,
+                    );
+                }
+                push(
+                    &layout.data,
+                    build_kconfig::RamFillConfig::Copy {
+                        source: build_kconfig::OwnedAddress {
+                            region_name: "flash".to_string(),
+                            offset: layout.data_image - flash.start(),
+                        },
+                    },
+                )?;
+            }
+            push(&layout.bss, build_kconfig::RamFillConfig::Zero)?;
+            if let Some(word) = task.uninit_fill {
+                push(
+                    &layout.uninit,
+                    build_kconfig::RamFillConfig::Pattern(word),
+                )?;
+            }
+        }
+
         // Mark off the regions this task uses.
         for region in &task.uses {
             used_shared_regions.insert(region.as_str());
@@ -2713,6 +3029,26 @@ pub fn make_kconfig(
             }
         }
 
+        // Sticky regions hold state the task keeps across restarts, so they
+        // must be writable memory of the task's own, and can't be `ram`, which
+        // holds the stack and the sections the kernel reinitializes.
+        for region in &task.sticky_regions {
+            let out = toml
+                .outputs
+                .get(region)
+                .and_then(|outs| outs.iter().find(|o| o.name == image_name));
+            if region == "ram"
+                || !task_allocations[name].contains_key(region)
+                || !out.is_some_and(|o| o.write && !o.device && !o.dma)
+            {
+                bail!(
+                    "task '{name}' can't have '{region}' as a sticky region; \
+                     it must be writable memory (not device or DMA) other \
+                     than `ram`, that the task has space in"
+                );
+            }
+        }
+
         let extern_regions = toml.extern_regions_for(name, image_name)?;
         let mut owned_regions = BTreeMap::new();
         for (out_name, range) in task_allocations[name]
@@ -2735,6 +3071,17 @@ pub fn make_kconfig(
                      is required to be a power of two, but has size {size}"
                 );
             }
+            let special_role = match (out.device, out.dma) {
+                (true, true) => {
+                    bail!("output '{out_name}' can't be both device and dma")
+                }
+                (true, false) if out.execute => {
+                    bail!("device output '{out_name}' can't be executable")
+                }
+                (true, false) => Some(build_kconfig::SpecialRole::Device),
+                (false, true) => Some(build_kconfig::SpecialRole::Dma),
+                (false, false) => None,
+            };
 
             owned_regions
                 .entry(out_name.to_string())
@@ -2745,11 +3092,8 @@ pub fn make_kconfig(
                         read: out.read,
                         write: out.write,
                         execute: out.execute,
-                        special_role: if out.dma {
-                            Some(build_kconfig::SpecialRole::Dma)
-                        } else {
-                            None
-                        },
+                        special_role,
+                        sticky: task.sticky_regions.contains(out_name),
                     },
                 })
                 .sizes
@@ -2769,6 +3113,13 @@ pub fn make_kconfig(
             },
             priority: task.priority,
             start_at_boot: task.start,
+            mailbox_depth: task.mailbox,
+            uses_fpu: task.uses_fpu,
+            virtual_regions: task.virtual_regions,
+            monitor: task.monitor,
+            attester: task.attester,
+            clock_owner: task.clock_owner,
+            ram_init,
         });
 
         // Interrupts.
@@ -2834,10 +3185,111 @@ pub fn make_kconfig(
     // Pare down the list of shared regions.
     flat_shared.retain(|name, _v| used_shared_regions.contains(name.as_str()));
 
+    let watchdog = match &toml.kernel.watchdog {
+        Some(w) if w.window == 0 => {
+            bail!("kernel watchdog window must be non-zero");
+        }
+        Some(w) => Some(build_kconfig::WatchdogConfig { window: w.window }),
+        None => None,
+    };
+    if toml.kernel.timeslice == Some(0) {
+        bail!("kernel timeslice must be non-zero");
+    }
+    let irq_storm = match &toml.kernel.irq_storm {
+        Some(s) if s.limit == 0 || s.limit > u32::from(u16::MAX) => {
+            bail!("kernel irq-storm limit must be between 1 and 65535");
+        }
+        Some(s) => {
+            let (name, supervisor) =
+                toml.tasks.first().ok_or_else(|| anyhow!("no tasks"))?;
+            let notification =
+                supervisor.notification_mask("irq-storm").context(format!(
+                    "kernel irq-storm protection requires the supervisor \
+                     ({name}) to have an `irq-storm` notification"
+                ))?;
+            Some(build_kconfig::IrqStormConfig {
+                limit: s.limit,
+                notification,
+            })
+        }
+        None => None,
+    };
+    let restart_throttle = match &toml.kernel.restart_throttle {
+        Some(t) if t.faults == 0 || t.window == 0 => {
+            bail!("kernel restart-throttle faults and window must be non-zero");
+        }
+        Some(t) => {
+            let (name, supervisor) =
+                toml.tasks.first().ok_or_else(|| anyhow!("no tasks"))?;
+            let mask = |n: &str| {
+                supervisor.notification_mask(n).context(format!(
+                    "kernel restart-throttle requires the supervisor ({name}) \
+                     to have a `{n}` notification"
+                ))
+            };
+            Some(build_kconfig::RestartThrottleConfig {
+                faults: t.faults,
+                window: t.window,
+                held_notification: mask("restart-held")?,
+                released_notification: mask("restart-released")?,
+            })
+        }
+        None => None,
+    };
+    let reply_deadman = match &toml.kernel.reply_deadman {
+        Some(d) if d.ticks == 0 => {
+            bail!("kernel reply-deadman ticks must be non-zero");
+        }
+        Some(d) => {
+            let (name, supervisor) =
+                toml.tasks.first().ok_or_else(|| anyhow!("no tasks"))?;
+            let notification = supervisor
+                .notification_mask("reply-deadman")
+                .context(format!(
+                    "kernel reply-deadman detection requires the supervisor \
+                     ({name}) to have a `reply-deadman` notification"
+                ))?;
+            Some(build_kconfig::ReplyDeadmanConfig {
+                ticks: d.ticks,
+                notification,
+            })
+        }
+        None => None,
+    };
+
+    let tick_hz = toml.kernel.tick_hz.unwrap_or(1000);
+    if tick_hz == 0 || 1_000_000 % tick_hz != 0 {
+        bail!(
+            "kernel tick-hz must divide 1000000, so that a tick is a whole \
+             number of microseconds"
+        );
+    }
+    let tickless_idle = if toml.kernel.tickless_idle {
+        if watchdog.is_some() {
+            bail!(
+                "kernel tickless-idle can't be used with the watchdog \
+                 heartbeat, which is driven by the tick"
+            );
+        }
+        let idle_task = toml.tasks.get_index_of("idle").ok_or_else(|| {
+            anyhow!("kernel tickless-idle requires a task named `idle`")
+        })?;
+        Some(build_kconfig::TicklessIdleConfig { idle_task })
+    } else {
+        None
+    };
+
     Ok(build_kconfig::KernelConfig {
         irqs,
         tasks,
         shared_regions: flat_shared,
+        watchdog,
+        timeslice: toml.kernel.timeslice,
+        irq_storm,
+        restart_throttle,
+        reply_deadman,
+        tick_hz,
+        tickless_idle,
     })
 }
 
@@ -0,0 +1,52 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use serde::Deserialize;
+use std::io::Write;
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let global_config = build_util::config::<GlobalConfig>()?;
+    generate_channels(&global_config.adc)?;
+
+    idol::client::build_client_stub("../../idl/adc.idol", "client_stub.rs")?;
+    Ok(())
+}
+
+/// This represents our _subset_ of global config and _must not_ be marked with
+/// `deny_unknown_fields`!
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct GlobalConfig {
+    adc: AdcConfig,
+}
+
+/// Likewise, the server is the authority on the rest of this.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct AdcConfig {
+    channels: Vec<ChannelConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ChannelConfig {
+    name: String,
+}
+
+fn generate_channels(
+    config: &AdcConfig,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let out_dir = build_util::out_dir();
+    let dest_path = out_dir.join("adc_channels.rs");
+    let mut out = std::fs::File::create(dest_path)?;
+
+    writeln!(out, "pub mod channels {{")?;
+    for (i, channel) in config.channels.iter().enumerate() {
+        let name = channel.name.to_uppercase().replace('-', "_");
+        writeln!(out, "    pub const {name}: u8 = {i};")?;
+    }
+    writeln!(out, "}}")?;
+
+    Ok(())
+}
@@ -0,0 +1,70 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for the ADC server.
+//!
+//! The server samples each of its channels periodically, smooths the
+//! readings, and hands out the latest on request; clients that care about a
+//! reading leaving a range can ask to be notified when it does, rather than
+//! polling. The channels are defined in the `[config.adc]` section of the
+//! app's TOML, and are named by the constants in [`channels`].
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::{sys_send, FromPrimitive};
+use zerocopy::{AsBytes, FromBytes};
+
+#[derive(
+    Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError, counters::Count,
+)]
+pub enum AdcError {
+    /// There's no such channel.
+    BadChannel = 1,
+    /// The smoothing is more than [`MAX_SMOOTHING`].
+    BadSmoothing,
+    /// The watch's range is empty, or its hysteresis is negative.
+    BadThresholds,
+    /// The channel hasn't been sampled yet.
+    NoReading,
+    /// The caller isn't watching the channel.
+    NotWatching,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// The heaviest smoothing `configure` accepts: each new sample is given a
+/// weight of 1/256.
+pub const MAX_SMOOTHING: u8 = 8;
+
+/// Where a watched reading is, relative to its watch's range.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, FromPrimitive)]
+#[repr(u8)]
+pub enum Level {
+    Within = 0,
+    Below = 1,
+    Above = 2,
+}
+
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Reading {
+    pub value: f32,
+    /// The [`Level`] of the reading, if the channel is being watched; if
+    /// not, `Within`.
+    pub level: u8,
+    pub _reserved: [u8; 3],
+    /// When the reading was taken, in kernel ticks.
+    pub timestamp: u64,
+}
+
+impl Reading {
+    pub fn level(&self) -> Level {
+        Level::from_u8(self.level).unwrap_or(Level::Within)
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
+include!(concat!(env!("OUT_DIR"), "/adc_channels.rs"));
@@ -0,0 +1,138 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for a CAN controller server.
+//!
+//! Any number of tasks can share one controller. Each registers filters for
+//! the frames it wants, and is notified when one arrives; it then collects
+//! the frame with `receive`. Frames nobody has asked for are rejected by the
+//! controller's acceptance filters, and never reach the server at all.
+//!
+//! A task that's slow to collect its frames loses them, rather than holding
+//! up anyone else: only [`FRAMES_PER_FILTER`] frames are held for each
+//! filter.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::{sys_send, FromPrimitive};
+use zerocopy::{AsBytes, FromBytes};
+
+/// Largest payload of a CAN FD frame; classic frames carry up to 8 bytes.
+pub const MAX_DATA: usize = 64;
+
+/// Number of received frames held for each filter until they're collected.
+pub const FRAMES_PER_FILTER: usize = 4;
+
+#[derive(
+    Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError, counters::Count,
+)]
+pub enum CanError {
+    /// The payload isn't a length a frame can have: more than 8 bytes for a
+    /// classic frame, or not one of the CAN FD lengths.
+    BadLength = 1,
+    /// The ID doesn't fit in 11 (or, for extended frames, 29) bits.
+    BadId,
+    /// The filter doesn't exist, or belongs to someone else.
+    BadFilter,
+    /// All of the controller's filters of the requested kind are in use.
+    NoFilters,
+    /// The transmit queue is full.
+    QueueFull,
+    /// No frame is waiting.
+    Empty,
+    /// The controller has taken itself off the bus after too many errors,
+    /// and is waiting to rejoin it.
+    BusOff,
+    /// CAN FD frames were requested of a controller configured without a
+    /// data bit rate.
+    FdDisabled,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// Bits in [`Frame::flags`], and the `flags` argument to `transmit`.
+pub mod flags {
+    /// The frame has a 29-bit ID.
+    pub const EXTENDED: u8 = 1 << 0;
+    /// The frame is a CAN FD frame.
+    pub const FD: u8 = 1 << 1;
+    /// The frame's data phase is at the data bit rate (CAN FD only).
+    pub const BIT_RATE_SWITCH: u8 = 1 << 2;
+}
+
+/// A received frame.
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Frame {
+    pub id: u32,
+    pub flags: u8,
+    pub len: u8,
+    /// The filter that matched the frame.
+    pub filter: u8,
+    pub _reserved: u8,
+    pub data: [u8; MAX_DATA],
+}
+
+impl Frame {
+    pub fn data(&self) -> &[u8] {
+        &self.data[..usize::from(self.len).min(MAX_DATA)]
+    }
+
+    pub fn is_extended(&self) -> bool {
+        self.flags & flags::EXTENDED != 0
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new_zeroed()
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct BusStatus {
+    pub transmit_errors: u8,
+    pub receive_errors: u8,
+    /// Non-zero if the controller is error passive.
+    pub error_passive: u8,
+    /// Non-zero if the controller is bus off.
+    pub bus_off: u8,
+}
+
+/// The data length code for a payload of `len` bytes, if it's a length a
+/// frame can have.
+pub fn dlc(len: usize, fd: bool) -> Option<u8> {
+    match len {
+        0..=8 => Some(len as u8),
+        _ if !fd => None,
+        12 => Some(9),
+        16 => Some(10),
+        20 => Some(11),
+        24 => Some(12),
+        32 => Some(13),
+        48 => Some(14),
+        64 => Some(15),
+        _ => None,
+    }
+}
+
+/// The payload length for data length code `dlc`.
+pub fn len(dlc: u8, fd: bool) -> usize {
+    match dlc & 0xF {
+        n @ 0..=8 => usize::from(n),
+        _ if !fd => 8,
+        9 => 12,
+        10 => 16,
+        11 => 20,
+        12 => 24,
+        13 => 32,
+        14 => 48,
+        _ => 64,
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
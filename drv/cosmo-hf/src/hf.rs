@@ -4,8 +4,8 @@
 
 use drv_hash_api::SHA256_SZ;
 use drv_hf_api::{
-    HfDevSelect, HfError, HfMuxState, HfPersistentData, HfProtectMode,
-    HfRawPersistentData, HF_PERSISTENT_DATA_STRIDE,
+    HfDevSelect, HfError, HfMuxState, HfPersistentData, HfProgress,
+    HfProtectMode, HfRawPersistentData, HF_PERSISTENT_DATA_STRIDE,
 };
 use idol_runtime::{
     LeaseBufReader, LeaseBufWriter, Leased, LenLimit, NotificationHandler,
@@ -28,6 +28,7 @@ const SLOT_SIZE_BYTES: u32 = 1024 * 1024 * 32;
 pub struct ServerImpl {
     pub drv: FlashDriver,
     pub dev: HfDevSelect,
+    progress: HfProgress,
 }
 
 impl ServerImpl {
@@ -41,6 +42,7 @@ impl ServerImpl {
         let mut out = Self {
             dev: drv_hf_api::HfDevSelect::Flash0,
             drv,
+            progress: HfProgress::default(),
         };
         out.drv.set_flash_mux_state(HfMuxState::SP);
         out.ensure_persistent_data_is_redundant();
@@ -297,6 +299,49 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
         Ok(())
     }
 
+    /// Erases the sectors in the selected `dev` holding `len` bytes from
+    /// `addr`
+    ///
+    /// The FPGA doesn't let us suspend an erase to serve reads, so this erases
+    /// everything before returning; `progress` reports it as finished.
+    fn erase_range(
+        &mut self,
+        _: &RecvMessage,
+        addr: u32,
+        len: u32,
+        protect: HfProtectMode,
+    ) -> Result<(), RequestError<HfError>> {
+        self.drv.check_flash_mux_state()?;
+        let start = addr - addr % SECTOR_SIZE_BYTES;
+        let end = addr
+            .checked_add(len)
+            .and_then(|end| end.checked_next_multiple_of(SECTOR_SIZE_BYTES))
+            .filter(|&end| end <= SLOT_SIZE_BYTES)
+            .ok_or(HfError::BadRange)?;
+        if start == end {
+            return Ok(());
+        }
+        self.check_addr_writable(start, protect)?;
+        self.progress = HfProgress {
+            busy: true,
+            done: 0,
+            total: end - start,
+        };
+        for offset in (start..end).step_by(SECTOR_SIZE_BYTES as usize) {
+            self.drv.flash_sector_erase(self.flash_addr(offset));
+            self.progress.done += SECTOR_SIZE_BYTES;
+        }
+        self.progress.busy = false;
+        Ok(())
+    }
+
+    fn progress(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<HfProgress, RequestError<HfError>> {
+        Ok(self.progress)
+    }
+
     fn get_mux(
         &mut self,
         _: &RecvMessage,
@@ -497,6 +542,23 @@ impl idl::InOrderHostFlashImpl for FailServer {
         Err(self.err.into())
     }
 
+    fn erase_range(
+        &mut self,
+        _: &RecvMessage,
+        _addr: u32,
+        _len: u32,
+        _protect: HfProtectMode,
+    ) -> Result<(), RequestError<HfError>> {
+        Err(self.err.into())
+    }
+
+    fn progress(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<HfProgress, RequestError<HfError>> {
+        Err(self.err.into())
+    }
+
     fn get_mux(
         &mut self,
         _: &RecvMessage,
@@ -564,7 +626,8 @@ impl NotificationHandler for FailServer {
 
 pub mod idl {
     use drv_hf_api::{
-        HfDevSelect, HfError, HfMuxState, HfPersistentData, HfProtectMode,
+        HfDevSelect, HfError, HfMuxState, HfPersistentData, HfProgress,
+        HfProtectMode,
     };
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
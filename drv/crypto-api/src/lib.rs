@@ -0,0 +1,107 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for a crypto accelerator server.
+//!
+//! Code that only needs digests should use the `Sha256Engine` trait, which
+//! the client implements, and so does `soft::Sha256` (with the `soft`
+//! feature) for boards or tasks without the server. There's no software
+//! fallback for AES: we'd rather not have a table-based implementation
+//! handling keys.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::{sys_send, FromPrimitive};
+
+/// Length of a SHA-256 digest, in bytes.
+pub const SHA256_LEN: usize = 32;
+
+#[derive(
+    Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError, counters::Count,
+)]
+pub enum CryptoError {
+    /// Another task has a digest under way.
+    Busy = 1,
+    /// The caller has no digest under way.
+    NotStarted,
+    /// The DMA manager couldn't move the data.
+    Dma,
+    /// The input and output leases are different lengths.
+    BadLength,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// Something that can compute SHA-256 digests, in hardware or not.
+pub trait Sha256Engine {
+    /// Starts a new digest, abandoning any under way.
+    fn begin(&mut self) -> Result<(), CryptoError>;
+
+    /// Adds `data` to the digest.
+    fn update(&mut self, data: &[u8]) -> Result<(), CryptoError>;
+
+    /// Finishes the digest, and returns it.
+    fn finish(&mut self) -> Result<[u8; SHA256_LEN], CryptoError>;
+
+    /// Returns the digest of `data`, in one go.
+    fn digest(&mut self, data: &[u8]) -> Result<[u8; SHA256_LEN], CryptoError> {
+        self.begin()?;
+        self.update(data)?;
+        self.finish()
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
+
+impl Sha256Engine for Crypto {
+    fn begin(&mut self) -> Result<(), CryptoError> {
+        self.sha256_begin()
+    }
+
+    fn update(&mut self, data: &[u8]) -> Result<(), CryptoError> {
+        self.sha256_update(data)
+    }
+
+    fn finish(&mut self) -> Result<[u8; SHA256_LEN], CryptoError> {
+        self.sha256_finish()
+    }
+
+    fn digest(&mut self, data: &[u8]) -> Result<[u8; SHA256_LEN], CryptoError> {
+        self.sha256(data)
+    }
+}
+
+/// SHA-256 in software, for tasks that can't get at the hardware.
+#[cfg(feature = "soft")]
+pub mod soft {
+    use super::{CryptoError, Sha256Engine, SHA256_LEN};
+    use sha2::Digest;
+
+    #[derive(Default)]
+    pub struct Sha256(sha2::Sha256);
+
+    impl Sha256 {
+        pub fn new() -> Self {
+            Self::default()
+        }
+    }
+
+    impl Sha256Engine for Sha256 {
+        fn begin(&mut self) -> Result<(), CryptoError> {
+            self.0.reset();
+            Ok(())
+        }
+
+        fn update(&mut self, data: &[u8]) -> Result<(), CryptoError> {
+            self.0.update(data);
+            Ok(())
+        }
+
+        fn finish(&mut self) -> Result<[u8; SHA256_LEN], CryptoError> {
+            Ok(self.0.finalize_reset().into())
+        }
+    }
+}
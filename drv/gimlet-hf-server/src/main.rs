@@ -28,7 +28,9 @@
 )]
 mod bsp;
 
-use userlib::{hl, task_slot, FromPrimitive, RecvMessage};
+use userlib::{
+    hl, sys_get_timer, sys_set_timer, task_slot, FromPrimitive, RecvMessage,
+};
 
 use drv_hf_api::SECTOR_SIZE_BYTES;
 use drv_stm32h7_qspi::Qspi;
@@ -52,14 +54,19 @@ use drv_hash_api as hash_api;
 use drv_hash_api::SHA256_SZ;
 
 use drv_hf_api::{
-    HfDevSelect, HfError, HfMuxState, HfPersistentData, HfProtectMode,
-    HfRawPersistentData, HF_PERSISTENT_DATA_STRIDE, PAGE_SIZE_BYTES,
+    HfDevSelect, HfError, HfMuxState, HfPersistentData, HfProgress,
+    HfProtectMode, HfRawPersistentData, HF_PERSISTENT_DATA_STRIDE,
+    PAGE_SIZE_BYTES,
 };
 
 task_slot!(SYS, sys);
 #[cfg(feature = "hash")]
 task_slot!(HASH, hash_driver);
 
+/// How often we check on a background erase, in milliseconds. Erasing a sector
+/// takes hundreds of them.
+const ERASE_POLL_INTERVAL: u64 = 5;
+
 struct Config {
     pub sp_host_mux_select: sys_api::PinSet,
     pub reset: sys_api::PinSet,
@@ -159,6 +166,8 @@ fn main() -> ! {
         dev_state: HfDevSelect::Flash0,
         mux_select_pin: cfg.sp_host_mux_select,
         dev_select_pin: cfg.flash_dev_select,
+        erase: None,
+        progress: HfProgress::default(),
     };
 
     server.ensure_persistent_data_is_redundant().unwrap(); // TODO: log this?
@@ -203,6 +212,19 @@ struct ServerImpl {
     /// changed by `set_dev` without necessarily being persisted to flash.
     dev_state: HfDevSelect,
     dev_select_pin: Option<sys_api::PinSet>,
+
+    /// The background erase under way, if any
+    erase: Option<Erase>,
+    progress: HfProgress,
+}
+
+/// An erase that goes on between requests, a sector at a time.
+#[derive(Copy, Clone)]
+struct Erase {
+    /// The sector being erased
+    sector: u32,
+    /// The end of the last sector to erase
+    end: u32,
 }
 
 impl ServerImpl {
@@ -220,6 +242,60 @@ impl ServerImpl {
         }
     }
 
+    /// Fails if a background erase is under way, for operations that can't
+    /// share the flash with one.
+    fn check_idle(&self) -> Result<(), HfError> {
+        match self.erase {
+            None => Ok(()),
+            Some(_) => Err(HfError::Busy),
+        }
+    }
+
+    /// Runs `f`, which only reads, with any background erase suspended.
+    ///
+    /// Every suspension costs the erase a little progress, but it gets on
+    /// with things whenever we're not reading.
+    fn with_erase_suspended<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let suspend = self.erase.is_some();
+        if suspend {
+            self.qspi.suspend_program_erase();
+            // The chip reports itself ready once it has suspended, or if the
+            // erase had already finished (in which case resuming does
+            // nothing).
+            self.poll_for_write_complete(None);
+        }
+        let out = f(self);
+        if suspend {
+            self.qspi.resume_program_erase();
+        }
+        out
+    }
+
+    /// Moves any background erase along: once a sector is erased, starts on
+    /// the next.
+    fn step_erase(&mut self) {
+        let Some(mut erase) = self.erase else {
+            return;
+        };
+        if self.qspi.read_status() & 1 == 0 {
+            self.progress.done += SECTOR_SIZE_BYTES as u32;
+            erase.sector += SECTOR_SIZE_BYTES as u32;
+            if erase.sector >= erase.end
+                || self.set_and_check_write_enable().is_err()
+            {
+                self.erase = None;
+                self.progress.busy = false;
+                return;
+            }
+            self.qspi.sector_erase(erase.sector);
+            self.erase = Some(erase);
+        }
+        sys_set_timer(
+            Some(sys_get_timer().now + ERASE_POLL_INTERVAL),
+            notifications::TIMER_MASK,
+        );
+    }
+
     fn page_program_raw(&self, addr: u32, data: &[u8]) -> Result<(), HfError> {
         self.set_and_check_write_enable()?;
         self.qspi.page_program(addr, data);
@@ -446,7 +522,7 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
         self.check_muxed_to_sp()?;
 
         let mut idbuf = [0; 20];
-        self.qspi.read_id(&mut idbuf);
+        self.with_erase_suspended(|s| s.qspi.read_id(&mut idbuf));
         Ok(idbuf)
     }
 
@@ -474,6 +550,7 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
             return Err(HfError::Sector0IsReserved.into());
         }
         self.check_muxed_to_sp()?;
+        self.check_idle()?;
         self.set_and_check_write_enable()?;
         self.qspi.bulk_erase();
         self.poll_for_write_complete(Some(100));
@@ -493,6 +570,7 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
             return Err(HfError::Sector0IsReserved.into());
         }
         self.check_muxed_to_sp()?;
+        self.check_idle()?;
         // Read the entire data block into our address space.
         data.read_range(0..data.len(), &mut self.block[..data.len()])
             .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
@@ -509,7 +587,9 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
         dest: LenLimit<Leased<W, [u8]>, PAGE_SIZE_BYTES>,
     ) -> Result<(), RequestError<HfError>> {
         self.check_muxed_to_sp()?;
-        self.qspi.read_memory(addr, &mut self.block[..dest.len()]);
+        self.with_erase_suspended(|s| {
+            s.qspi.read_memory(addr, &mut s.block[..dest.len()])
+        });
 
         dest.write_range(0..dest.len(), &self.block[..dest.len()])
             .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
@@ -523,9 +603,57 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
         addr: u32,
         protect: HfProtectMode,
     ) -> Result<(), RequestError<HfError>> {
+        self.check_idle()?;
         self.sector_erase(addr, protect).map_err(RequestError::from)
     }
 
+    fn erase_range(
+        &mut self,
+        _: &RecvMessage,
+        addr: u32,
+        len: u32,
+        protect: HfProtectMode,
+    ) -> Result<(), RequestError<HfError>> {
+        self.check_muxed_to_sp()?;
+        self.check_idle()?;
+        let sector = SECTOR_SIZE_BYTES as u32;
+        let start = addr - addr % sector;
+        let end = addr
+            .checked_add(len)
+            .and_then(|end| end.checked_next_multiple_of(sector))
+            .filter(|&end| end as usize <= self.capacity)
+            .ok_or(HfError::BadRange)?;
+        if start == end {
+            return Ok(());
+        }
+        if start == 0
+            && !matches!(protect, HfProtectMode::AllowModificationsToSector0)
+        {
+            return Err(HfError::Sector0IsReserved.into());
+        }
+
+        self.set_and_check_write_enable()?;
+        self.qspi.sector_erase(start);
+        self.erase = Some(Erase { sector: start, end });
+        self.progress = HfProgress {
+            busy: true,
+            done: 0,
+            total: end - start,
+        };
+        sys_set_timer(
+            Some(sys_get_timer().now + ERASE_POLL_INTERVAL),
+            notifications::TIMER_MASK,
+        );
+        Ok(())
+    }
+
+    fn progress(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<HfProgress, RequestError<HfError>> {
+        Ok(self.progress)
+    }
+
     fn get_mux(
         &mut self,
         _: &RecvMessage,
@@ -538,6 +666,8 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
         _: &RecvMessage,
         state: HfMuxState,
     ) -> Result<(), RequestError<HfError>> {
+        // Handing the flash to the host would abandon our erase.
+        self.check_idle()?;
         let sys = sys_api::Sys::from(SYS.get_task_id());
 
         match state {
@@ -561,6 +691,7 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
         _: &RecvMessage,
         state: HfDevSelect,
     ) -> Result<(), RequestError<HfError>> {
+        self.check_idle()?;
         self.set_dev(state).map_err(RequestError::from)
     }
 
@@ -601,7 +732,9 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
             } else {
                 end - addr
             };
-            self.qspi.read_memory(addr as u32, &mut self.block[..size]);
+            self.with_erase_suspended(|s| {
+                s.qspi.read_memory(addr as u32, &mut s.block[..size])
+            });
             if hash_driver
                 .update(size as u32, &self.block[..size])
                 .is_err()
@@ -629,6 +762,8 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
         &mut self,
         _: &RecvMessage,
     ) -> Result<HfPersistentData, RequestError<HfError>> {
+        // Looking at both chips means switching between them.
+        self.check_idle()?;
         self.get_persistent_data().map_err(RequestError::from)
     }
 
@@ -640,6 +775,7 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
     ) -> Result<(), RequestError<HfError>> {
         let data = HfPersistentData { dev_select };
         self.check_muxed_to_sp()?;
+        self.check_idle()?;
         if self.dev_select_pin.is_some() {
             let prev_slot = self.dev_state;
 
@@ -715,18 +851,24 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
 
 impl NotificationHandler for ServerImpl {
     fn current_notification_mask(&self) -> u32 {
-        // We don't use notifications, don't listen for any.
-        0
+        // Our timer only runs during a background erase.
+        match self.erase {
+            Some(_) => notifications::TIMER_MASK,
+            None => 0,
+        }
     }
 
-    fn handle_notification(&mut self, _bits: u32) {
-        unreachable!()
+    fn handle_notification(&mut self, bits: u32) {
+        if bits & notifications::TIMER_MASK != 0 {
+            self.step_erase();
+        }
     }
 }
 
 mod idl {
     use super::{
-        HfDevSelect, HfError, HfMuxState, HfPersistentData, HfProtectMode,
+        HfDevSelect, HfError, HfMuxState, HfPersistentData, HfProgress,
+        HfProtectMode,
     };
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
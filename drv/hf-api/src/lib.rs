@@ -33,11 +33,37 @@ pub enum HfError {
     MonotonicCounterOverflow,
     FpgaNotConfigured,
     BadChipId,
+    /// A background operation is using the flash
+    Busy,
+    BadRange,
 
     #[idol(server_death)]
     ServerRestarted,
 }
 
+/// How far a background operation (see `HostFlash::erase_range`) has got.
+///
+/// An operation stops at its first failure, so one that is no longer `busy`
+/// with `done < total` has failed.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Eq,
+    PartialEq,
+    Deserialize,
+    Serialize,
+    SerializedSize,
+)]
+pub struct HfProgress {
+    pub busy: bool,
+    /// Bytes done so far
+    pub done: u32,
+    /// Bytes in the whole operation
+    pub total: u32,
+}
+
 /// Controls whether the SP or host CPU has access to flash
 #[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, AsBytes)]
 #[repr(u8)]
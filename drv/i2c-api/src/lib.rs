@@ -42,6 +42,27 @@ pub struct I2cDevice {
 
 type I2cMessage = (u8, Controller, PortIndex, Option<(Mux, Segment)>);
 
+///
+/// The health of a mux segment, as tracked by the I2C server.  The counts
+/// are cumulative since the server started; `isolated` is non-zero if the
+/// segment has hung the bus too many times in a row and the server is
+/// currently refusing transactions on it.
+///
+#[derive(Copy, Clone, Debug, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct SegmentHealth {
+    /// Transactions that failed for reasons other than the bus hanging
+    pub errors: u32,
+    /// Transactions that left the bus locked or in need of a reset
+    pub hangs: u32,
+    /// Bus recoveries (controller reset plus SCL wiggling) performed
+    pub recoveries: u32,
+    /// Times the segment has been isolated
+    pub isolations: u32,
+    pub isolated: u8,
+    pub _reserved: [u8; 3],
+}
+
 pub trait Marshal<T> {
     fn marshal(&self) -> T;
     fn unmarshal(val: &T) -> Result<Self, ResponseCode>
@@ -287,6 +308,34 @@ impl I2cDevice {
         self.response_code(code, response)
     }
 
+    ///
+    /// Returns the health of the mux segment that this device is on.  It is
+    /// an error (`BadSegment`) to call this on a device that isn't behind a
+    /// mux.  This doesn't talk to the device, or to anything else on the bus.
+    ///
+    pub fn segment_health(&self) -> Result<SegmentHealth, ResponseCode> {
+        if self.segment.is_none() {
+            return Err(ResponseCode::BadSegment);
+        }
+
+        let mut health = SegmentHealth::default();
+
+        let (code, _) = sys_send(
+            self.task,
+            Op::SegmentHealth as u16,
+            &Marshal::marshal(&(
+                self.address,
+                self.controller,
+                self.port,
+                self.segment,
+            )),
+            health.as_bytes_mut(),
+            &[],
+        );
+
+        self.response_code(code, health)
+    }
+
     ///
     /// Writes a buffer to a device. Unlike a register read, this will not
     /// perform any follow-up reads.
@@ -26,6 +26,7 @@
 //! - [`pca9538`]: PCA9538 GPIO expander
 //! - [`pca9956b`]: PCA9956B LED driver
 //! - [`pct2075`]: PCT2075 temperature sensor
+//! - [`pmbus_generic`]: PMBus regulators that need only a table of quirks
 //! - [`raa229618`]: RAA229618 power controller
 //! - [`sbrmi`]: AMD SB-RMI driver
 //! - [`sbtsi`]: AMD SB-TSI temperature sensor
@@ -249,6 +250,7 @@ pub mod nvme_bmc;
 pub mod pca9538;
 pub mod pca9956b;
 pub mod pct2075;
+pub mod pmbus_generic;
 pub mod raa229618;
 pub mod sbrmi;
 pub mod sbtsi;
@@ -0,0 +1,393 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Generic driver for PMBus regulators
+//!
+//! Most regulators implement the standard telemetry commands, and differ only
+//! in how they encode the results, whether they have several rails behind
+//! PAGE, and which measurements they leave out. Rather than a driver apiece,
+//! such a device gets an entry in a table of [`Quirks`], and this driver reads
+//! it according to that entry. Devices that need more than that (phase
+//! currents, blackboxes, mode-dependent coefficients) still get a driver of
+//! their own.
+
+use core::cell::Cell;
+
+use crate::{
+    CurrentSensor, InputCurrentSensor, InputVoltageSensor, TempSensor,
+    Validate, VoltageSensor,
+};
+use drv_i2c_api::*;
+use pmbus::commands::CommandCode;
+use userlib::units::*;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Coefficients for the DIRECT format, in which a reading `Y` is worth
+/// `(Y * 10^-r - b) / m`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Coefficients {
+    pub m: i16,
+    pub b: i16,
+    pub r: i8,
+}
+
+/// How a device encodes one of its measurements.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    /// The device doesn't have this measurement.
+    Absent,
+    /// LINEAR11: a 5-bit exponent and 11-bit mantissa, both signed.
+    Linear11,
+    /// ULINEAR16, with the exponent from VOUT_MODE. Only for VOUT.
+    Linear16,
+    /// ULINEAR16 with a fixed exponent, for devices whose VOUT_MODE doesn't
+    /// describe READ_VOUT.
+    Linear16Exponent(i8),
+    /// DIRECT, with coefficients from the datasheet.
+    Direct(Coefficients),
+    /// DIRECT, with coefficients the device reports through COEFFICIENTS.
+    DirectQueried,
+}
+
+/// How to recognize a device.
+#[derive(Copy, Clone, Debug)]
+pub enum Identity {
+    /// IC_DEVICE_ID reads back exactly this.
+    IcDeviceId(&'static [u8]),
+    /// MFR_ID reads back exactly this.
+    MfrId(&'static [u8]),
+}
+
+/// Everything about a device that the standard leaves open.
+#[derive(Copy, Clone, Debug)]
+pub struct Quirks {
+    pub name: &'static str,
+    pub identity: Identity,
+    /// Whether the device has several rails, selected with PAGE.
+    pub paged: bool,
+    /// Whether VOUT_MODE is per rail; some paged devices have one for all of
+    /// their rails, and NAK it with PAGE set.
+    pub vout_mode_paged: bool,
+    /// Whether the device has STATUS_WORD, rather than just STATUS_BYTE.
+    pub status_word: bool,
+    pub vout: Format,
+    pub iout: Format,
+    pub temperature: Format,
+    pub vin: Format,
+    pub iin: Format,
+}
+
+impl Quirks {
+    /// A device that does everything by the book, on a single rail.
+    pub const STANDARD: Quirks = Quirks {
+        name: "standard",
+        identity: Identity::MfrId(&[]),
+        paged: false,
+        vout_mode_paged: false,
+        status_word: true,
+        vout: Format::Linear16,
+        iout: Format::Linear11,
+        temperature: Format::Linear11,
+        vin: Format::Linear11,
+        iin: Format::Linear11,
+    };
+}
+
+pub static BMR491: Quirks = Quirks {
+    name: "bmr491",
+    identity: Identity::MfrId(b"Flex"),
+    vin: Format::Absent,
+    iin: Format::Absent,
+    ..Quirks::STANDARD
+};
+
+pub static TPS546B24A: Quirks = Quirks {
+    name: "tps546b24a",
+    identity: Identity::IcDeviceId(&[0x54, 0x49, 0x54, 0x6B, 0x24, 0x41]),
+    iin: Format::Absent,
+    ..Quirks::STANDARD
+};
+
+/// Devices that [`identify`] knows about.
+pub static KNOWN: &[&Quirks] = &[&BMR491, &TPS546B24A];
+
+#[derive(Copy, Clone, Debug)]
+enum Measurement {
+    Vout,
+    Iout,
+    Temperature,
+    Vin,
+    Iin,
+}
+
+impl Measurement {
+    const COUNT: usize = 5;
+
+    fn command(self) -> CommandCode {
+        match self {
+            Measurement::Vout => CommandCode::READ_VOUT,
+            Measurement::Iout => CommandCode::READ_IOUT,
+            Measurement::Temperature => CommandCode::READ_TEMPERATURE_1,
+            Measurement::Vin => CommandCode::READ_VIN,
+            Measurement::Iin => CommandCode::READ_IIN,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    BadRead { cmd: u8, code: ResponseCode },
+    BadData { cmd: u8 },
+    Unsupported { cmd: u8 },
+}
+
+impl From<Error> for ResponseCode {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::BadRead { code, .. } => code,
+            Error::BadData { .. } => ResponseCode::BadDeviceState,
+            Error::Unsupported { .. } => ResponseCode::OperationNotSupported,
+        }
+    }
+}
+
+pub struct PmbusDevice {
+    device: I2cDevice,
+    rail: u8,
+    quirks: &'static Quirks,
+    /// Our (cached) VOUT_MODE
+    mode: Cell<Option<u8>>,
+    /// Our (cached) coefficients for `Format::DirectQueried`, by measurement
+    coefficients: [Cell<Option<Coefficients>>; Measurement::COUNT],
+}
+
+/// Returns 2 to the power `exp`, which must be between -126 and 127.
+fn pow2(exp: i32) -> f32 {
+    f32::from_bits(((exp + 127) as u32) << 23)
+}
+
+/// Returns 10 to the power `exp`.
+fn pow10(exp: i8) -> f32 {
+    let mut v = 1.0;
+    for _ in 0..exp.unsigned_abs() {
+        v *= 10.0;
+    }
+    if exp < 0 {
+        1.0 / v
+    } else {
+        v
+    }
+}
+
+/// Sign-extends the low five bits of `v`, as used for exponents.
+fn exponent5(v: u16) -> i32 {
+    i32::from(((v as u8) << 3) as i8 >> 3)
+}
+
+fn linear11(raw: u16) -> f32 {
+    let mantissa = ((raw << 5) as i16) >> 5;
+    f32::from(mantissa) * pow2(exponent5(raw >> 11))
+}
+
+fn direct(raw: u16, c: Coefficients) -> f32 {
+    let y = f32::from(raw as i16);
+    (y / pow10(c.r) - f32::from(c.b)) / f32::from(c.m)
+}
+
+impl PmbusDevice {
+    pub fn new(device: &I2cDevice, rail: u8, quirks: &'static Quirks) -> Self {
+        PmbusDevice {
+            device: *device,
+            rail,
+            quirks,
+            mode: Cell::new(None),
+            coefficients: Default::default(),
+        }
+    }
+
+    pub fn quirks(&self) -> &'static Quirks {
+        self.quirks
+    }
+
+    pub fn i2c_device(&self) -> &I2cDevice {
+        &self.device
+    }
+
+    fn page(&self) -> [u8; 2] {
+        [CommandCode::PAGE as u8, self.rail]
+    }
+
+    fn read<V: AsBytes + FromBytes>(
+        &self,
+        cmd: CommandCode,
+        paged: bool,
+    ) -> Result<V, Error> {
+        let cmd = cmd as u8;
+        let r = if paged {
+            self.device.write_read_reg(cmd, &self.page())
+        } else {
+            self.device.read_reg(cmd)
+        };
+        r.map_err(|code| Error::BadRead { cmd, code })
+    }
+
+    /// Reads VOUT_MODE, which we only do once.
+    pub fn read_mode(&self) -> Result<pmbus::VOutModeCommandData, Error> {
+        let mode = self.vout_mode()?;
+        pmbus::VOutModeCommandData::from_slice(&[mode]).ok_or(Error::BadData {
+            cmd: CommandCode::VOUT_MODE as u8,
+        })
+    }
+
+    fn vout_mode(&self) -> Result<u8, Error> {
+        if let Some(mode) = self.mode.get() {
+            return Ok(mode);
+        }
+        let paged = self.quirks.paged && self.quirks.vout_mode_paged;
+        let mode = self.read(CommandCode::VOUT_MODE, paged)?;
+        self.mode.set(Some(mode));
+        Ok(mode)
+    }
+
+    /// Reads the DIRECT coefficients that the device uses for `cmd`, with
+    /// the COEFFICIENTS process call.
+    pub fn read_coefficients(
+        &self,
+        cmd: CommandCode,
+    ) -> Result<Coefficients, Error> {
+        let coefficients = CommandCode::COEFFICIENTS as u8;
+        // A block write of two bytes: the command, and a 1 for "reading".
+        let call = [coefficients, 2, cmd as u8, 1];
+        let mut buf = [0u8; 5];
+        let n = if self.quirks.paged {
+            self.device.write_read_block(call, &self.page(), &mut buf)
+        } else {
+            self.device.read_block(call, &mut buf)
+        }
+        .map_err(|code| Error::BadRead {
+            cmd: coefficients,
+            code,
+        })?;
+        if n != buf.len() {
+            return Err(Error::BadData { cmd: coefficients });
+        }
+        Ok(Coefficients {
+            m: i16::from_le_bytes([buf[0], buf[1]]),
+            b: i16::from_le_bytes([buf[2], buf[3]]),
+            r: buf[4] as i8,
+        })
+    }
+
+    /// Reads STATUS_WORD; on devices that only have STATUS_BYTE, which is
+    /// its low byte, the high byte is zero.
+    pub fn read_status_word(&self) -> Result<u16, Error> {
+        if self.quirks.status_word {
+            self.read::<u16>(CommandCode::STATUS_WORD, self.quirks.paged)
+        } else {
+            self.read::<u8>(CommandCode::STATUS_BYTE, self.quirks.paged)
+                .map(u16::from)
+        }
+    }
+
+    fn measure(&self, m: Measurement, format: Format) -> Result<f32, Error> {
+        let cmd = m.command();
+        let read = || self.read::<u16>(cmd, self.quirks.paged);
+
+        Ok(match format {
+            Format::Absent => {
+                return Err(Error::Unsupported { cmd: cmd as u8 });
+            }
+            Format::Linear11 => linear11(read()?),
+            Format::Linear16 => {
+                let raw = read()?;
+                let mode = self.vout_mode()?;
+                // The top three bits say which format VOUT is in; only
+                // linear has an exponent here.
+                if mode >> 5 != 0 {
+                    return Err(Error::BadData {
+                        cmd: CommandCode::VOUT_MODE as u8,
+                    });
+                }
+                f32::from(raw) * pow2(exponent5(u16::from(mode)))
+            }
+            Format::Linear16Exponent(exp) => {
+                f32::from(read()?) * pow2(i32::from(exp))
+            }
+            Format::Direct(c) => direct(read()?, c),
+            Format::DirectQueried => {
+                let cache = &self.coefficients[m as usize];
+                let c = match cache.get() {
+                    Some(c) => c,
+                    None => {
+                        let c = self.read_coefficients(cmd)?;
+                        cache.set(Some(c));
+                        c
+                    }
+                };
+                if c.m == 0 {
+                    return Err(Error::BadData {
+                        cmd: CommandCode::COEFFICIENTS as u8,
+                    });
+                }
+                direct(read()?, c)
+            }
+        })
+    }
+}
+
+/// Finds the entry in [`KNOWN`] that matches the device, if any does.
+pub fn identify(device: &I2cDevice) -> Result<Option<&'static Quirks>, Error> {
+    let mut buf = [0u8; 32];
+    for quirks in KNOWN {
+        let (cmd, expected) = match quirks.identity {
+            Identity::IcDeviceId(id) => (CommandCode::IC_DEVICE_ID, id),
+            Identity::MfrId(id) => (CommandCode::MFR_ID, id),
+        };
+        let cmd = cmd as u8;
+        let n = device
+            .read_block(cmd, &mut buf)
+            .map_err(|code| Error::BadRead { cmd, code })?;
+        if buf.get(..n) == Some(expected) {
+            return Ok(Some(quirks));
+        }
+    }
+    Ok(None)
+}
+
+impl Validate<Error> for PmbusDevice {
+    fn validate(device: &I2cDevice) -> Result<bool, Error> {
+        Ok(identify(device)?.is_some())
+    }
+}
+
+impl TempSensor<Error> for PmbusDevice {
+    fn read_temperature(&self) -> Result<Celsius, Error> {
+        let t = self.quirks.temperature;
+        Ok(Celsius(self.measure(Measurement::Temperature, t)?))
+    }
+}
+
+impl CurrentSensor<Error> for PmbusDevice {
+    fn read_iout(&self) -> Result<Amperes, Error> {
+        Ok(Amperes(self.measure(Measurement::Iout, self.quirks.iout)?))
+    }
+}
+
+impl VoltageSensor<Error> for PmbusDevice {
+    fn read_vout(&self) -> Result<Volts, Error> {
+        Ok(Volts(self.measure(Measurement::Vout, self.quirks.vout)?))
+    }
+}
+
+impl InputCurrentSensor<Error> for PmbusDevice {
+    fn read_iin(&self) -> Result<Amperes, Error> {
+        Ok(Amperes(self.measure(Measurement::Iin, self.quirks.iin)?))
+    }
+}
+
+impl InputVoltageSensor<Error> for PmbusDevice {
+    fn read_vin(&self) -> Result<Volts, Error> {
+        Ok(Volts(self.measure(Measurement::Vin, self.quirks.vin)?))
+    }
+}
@@ -36,6 +36,12 @@ pub enum Op {
     /// without interruption, this logic would not work, but that would be a
     /// very strange device indeed.
     WriteReadBlock = 2,
+
+    /// Reports the health of a mux segment: how often transactions on it have
+    /// failed or hung the bus, and whether it's currently isolated. The
+    /// request is marshalled like a `WriteRead`, but the address is ignored
+    /// and the segment is required.
+    SegmentHealth = 3,
 }
 
 /// The response code returned from the I2C server.  These response codes pretty
@@ -109,6 +115,8 @@ pub enum ResponseCode {
     IllegalLeaseCount,
     /// Too much data -- or not enough buffer
     TooMuchData,
+    /// Segment has repeatedly hung the bus, and is isolated for now
+    SegmentIsolated,
 }
 
 ///
@@ -1086,6 +1086,14 @@ impl Register {
         Self::select_phy_port(i, Self::P1MBCR, Self::P2MBCR)
     }
     #[inline(always)]
+    pub fn PxANAR(i: KszPhyPort) -> Self {
+        Self::select_phy_port(i, Self::P1ANAR, Self::P2ANAR)
+    }
+    #[inline(always)]
+    pub fn PxANLPR(i: KszPhyPort) -> Self {
+        Self::select_phy_port(i, Self::P1ANLPR, Self::P2ANLPR)
+    }
+    #[inline(always)]
     pub fn PxCR1(i: KszPort) -> Self {
         Self::select_port(i, Self::P1CR1, Self::P2CR1, Self::P3CR1)
     }
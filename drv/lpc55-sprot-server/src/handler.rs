@@ -602,6 +602,13 @@ impl<'a> Handler {
                 let rsp = self.attest.tq_sign_len().map(AttestRsp::TqSignLen);
                 Ok((RspBody::Attest(rsp), None))
             }
+            ReqBody::Attest(AttestReq::FirmwareVersions) => {
+                let rsp = self
+                    .attest
+                    .firmware_versions()
+                    .map(AttestRsp::FirmwareVersions);
+                Ok((RspBody::Attest(rsp), None))
+            }
             ReqBody::Attest(AttestReq::TqCertChainLen) => {
                 let rsp = self
                     .attest
@@ -21,8 +21,8 @@ use drv_update_api::UpdateError;
 use idol_runtime::{
     ClientError, Leased, LenLimit, NotificationHandler, RequestError, R, W,
 };
+use lib_crypto::{Digest, Sha3_256};
 use ringbuf::*;
-use sha3::{Digest, Sha3_256};
 use stage0_handoff::{
     HandoffData, HandoffDataLoadError, ImageVersion, RotBootState,
     RotBootStateV2,
@@ -12,8 +12,8 @@
 
 use drv_hash_api::SHA256_SZ;
 use drv_hf_api::{
-    HfDevSelect, HfError, HfMuxState, HfPersistentData, HfProtectMode,
-    PAGE_SIZE_BYTES,
+    HfDevSelect, HfError, HfMuxState, HfPersistentData, HfProgress,
+    HfProtectMode, PAGE_SIZE_BYTES,
 };
 use idol_runtime::{
     ClientError, Leased, LenLimit, NotificationHandler, RequestError, R, W,
@@ -108,6 +108,23 @@ impl idl::InOrderHostFlashImpl for ServerImpl {
         Ok(())
     }
 
+    fn erase_range(
+        &mut self,
+        _: &RecvMessage,
+        _addr: u32,
+        _len: u32,
+        _protect: HfProtectMode,
+    ) -> Result<(), RequestError<HfError>> {
+        Ok(())
+    }
+
+    fn progress(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<HfProgress, RequestError<HfError>> {
+        Ok(HfProgress::default())
+    }
+
     fn get_mux(
         &mut self,
         _: &RecvMessage,
@@ -188,7 +205,8 @@ impl NotificationHandler for ServerImpl {
 }
 mod idl {
     use super::{
-        HfDevSelect, HfError, HfMuxState, HfPersistentData, HfProtectMode,
+        HfDevSelect, HfError, HfMuxState, HfPersistentData, HfProgress,
+        HfProtectMode,
     };
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
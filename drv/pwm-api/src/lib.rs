@@ -0,0 +1,64 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for the PWM server.
+//!
+//! The server owns some general-purpose timers, and drives each of their
+//! channels either as a PWM output (say, a fan's speed control, or an LED's
+//! brightness) or as an input capture (say, a fan's tachometer). Clients set
+//! duty cycles and frequencies, and read back measured frequencies, without
+//! touching the timers themselves. The channels are defined in the
+//! `[config.pwm]` section of the app's TOML, and are named by the constants
+//! in [`channels`].
+//!
+//! Every PWM channel on a timer shares its frequency. Changes to a timer's
+//! channels take effect at the end of its current period, so an output never
+//! sees a partial period; to change several channels at once, stage the
+//! changes and then commit them:
+//!
+//! ```ignore
+//! pwm.stage_duty(channels::FAN0, 0.6)?;
+//! pwm.stage_duty(channels::FAN1, 0.6)?;
+//! pwm.commit();
+//! ```
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::{sys_send, FromPrimitive};
+use zerocopy::{AsBytes, FromBytes};
+
+#[derive(
+    Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError, counters::Count,
+)]
+pub enum PwmError {
+    /// There's no such channel.
+    BadChannel = 1,
+    /// The channel is an input capture, not a PWM output.
+    NotPwm,
+    /// The channel is a PWM output, not an input capture.
+    NotCapture,
+    /// The timer can't run at that frequency.
+    BadFrequency,
+    /// The duty cycle isn't between 0.0 and 1.0.
+    BadDuty,
+    /// The input hasn't had two rising edges within its timeout.
+    NoSignal,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Capture {
+    /// Frequency of the input, in Hz.
+    pub frequency: f32,
+    pub _reserved: [u8; 4],
+    /// When the most recent edge arrived, in kernel ticks.
+    pub timestamp: u64,
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
+include!(concat!(env!("OUT_DIR"), "/pwm_channels.rs"));
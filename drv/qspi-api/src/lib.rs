@@ -36,6 +36,9 @@ pub enum Command {
 
     BulkErase = 0xC7,
     SectorErase = 0xDC,
+
+    ProgramEraseSuspend = 0x75,
+    ProgramEraseResume = 0x7A,
 }
 
 impl From<Command> for u8 {
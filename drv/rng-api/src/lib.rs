@@ -22,6 +22,9 @@ pub enum RngError {
     ClockError,
     SeedError,
     UnknownRngError,
+    /// The entropy source failed a continuous health test, and its output
+    /// was thrown away.
+    HealthTestFailed,
 
     #[idol(server_death)]
     ServerRestarted,
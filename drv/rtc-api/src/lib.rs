@@ -0,0 +1,133 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for a real-time clock server.
+//!
+//! The kernel's timer counts milliseconds since boot; the RTC counts calendar
+//! time, in UTC, and keeps counting through resets (and, with a backup
+//! battery, through power loss). Times are between 2000 and 2099, which is
+//! what the hardware can count.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use hubpack::SerializedSize;
+use serde::{Deserialize, Serialize};
+use userlib::{sys_send, FromPrimitive};
+
+#[derive(
+    Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError, counters::Count,
+)]
+pub enum RtcError {
+    /// The clock hasn't been set since it last lost power.
+    NotSet = 1,
+    /// The time isn't a real one, or is outside the years 2000 to 2099.
+    InvalidTime,
+    /// The alarm time has already passed.
+    AlarmInPast,
+    /// The server already has as many alarms as it can keep.
+    TooManyAlarms,
+    /// The clock's oscillator didn't start.
+    ClockFailed,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// A calendar date and time, to the second, in UTC.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    Deserialize,
+    Serialize,
+    SerializedSize,
+)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12
+    pub month: u8,
+    /// 1 to 31
+    pub day: u8,
+    /// 0 to 23
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+/// A reading of the clock.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize, SerializedSize,
+)]
+pub struct Timestamp {
+    pub time: DateTime,
+    /// Microseconds into the second, in steps of the clock's resolution
+    /// (1/256 s, with a 32.768 kHz crystal).
+    pub micros: u32,
+}
+
+/// Seconds from 1970-01-01 to 2000-01-01.
+const UNIX_2000: u64 = 946_684_800;
+
+impl DateTime {
+    /// Checks that this is a real time that the clock can hold.
+    pub fn is_valid(&self) -> bool {
+        (2000..=2099).contains(&self.year)
+            && (1..=12).contains(&self.month)
+            && self.day >= 1
+            && self.day <= days_in_month(self.year, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// Returns the number of whole days from 2000-01-01 to this date, which
+    /// must be valid.
+    pub fn days_since_2000(&self) -> u32 {
+        // Count from March, so that a leap day falls at the end of the year.
+        let (y, m) = if self.month <= 2 {
+            (u32::from(self.year) - 1, u32::from(self.month) + 9)
+        } else {
+            (u32::from(self.year), u32::from(self.month) - 3)
+        };
+        let y = y - 1600;
+        let days = y * 365 + y / 4 - y / 100
+            + y / 400
+            + (153 * m + 2) / 5
+            + u32::from(self.day)
+            - 1;
+        // That counts from 1600-03-01, from which 2000-01-01 is 146037 days.
+        days - 146_037
+    }
+
+    /// Returns the day of the week, from 1 (Monday) to 7 (Sunday).
+    pub fn weekday(&self) -> u8 {
+        // 2000-01-01 was a Saturday.
+        ((self.days_since_2000() + 5) % 7 + 1) as u8
+    }
+
+    /// Returns the number of seconds since the Unix epoch.
+    pub fn unix_seconds(&self) -> u64 {
+        let days = u64::from(self.days_since_2000());
+        let secs = u64::from(self.hour) * 3600
+            + u64::from(self.minute) * 60
+            + u64::from(self.second);
+        UNIX_2000 + days * 86_400 + secs
+    }
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
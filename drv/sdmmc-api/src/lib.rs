@@ -0,0 +1,93 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for an SD/MMC card server.
+//!
+//! The server looks after one card slot, and hands out whole 512-byte blocks
+//! of whatever card is in it. Clients that need to know when the card comes
+//! or goes can ask to be notified, rather than finding out from `NoCard`
+//! errors. [`Sdmmc`] implements [`BlockDevice`], so a filesystem can be
+//! layered on top without knowing where its blocks come from.
+
+#![no_std]
+
+pub use block_device::BLOCK_SIZE;
+
+use block_device::{Block, BlockDevice};
+use derive_idol_err::IdolError;
+use userlib::{sys_send, FromPrimitive};
+use zerocopy::{AsBytes, FromBytes};
+
+#[derive(
+    Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError, counters::Count,
+)]
+pub enum SdmmcError {
+    /// There's no card in the slot, or it didn't respond.
+    NoCard = 1,
+    /// The card responded, but isn't a kind we can use.
+    UnsupportedCard,
+    /// The buffer isn't a whole number of blocks, or is empty.
+    BadLength,
+    /// The transfer runs past the end of the card.
+    OutOfRange,
+    /// The card stopped responding partway through a transfer.
+    Timeout,
+    /// Data was corrupted on the bus.
+    Crc,
+    /// The card reported an error, such as writing to a locked or
+    /// write-protected area.
+    CardError,
+    /// The controller couldn't move data to or from memory in time.
+    Dma,
+    /// The server already has as many watches as it can keep.
+    TooManyWatchers,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// Most blocks that can be read or written in one call.
+pub const MAX_BLOCKS: usize = 4096 / BLOCK_SIZE;
+
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct CardInfo {
+    /// Number of 512-byte blocks on the card.
+    pub blocks: u32,
+    /// Number of times a card has been brought up since the server started.
+    /// This changes when the card is swapped, which a client holding
+    /// filesystem state should look out for.
+    pub insertions: u32,
+    /// Whether the card is block-addressed (SDHC or SDXC).
+    pub high_capacity: u8,
+    pub _reserved: [u8; 3],
+}
+
+impl BlockDevice for Sdmmc {
+    type Error = SdmmcError;
+
+    fn block_count(&self) -> Result<u32, SdmmcError> {
+        Ok(self.card_info()?.blocks)
+    }
+
+    fn read(&self, start: u32, blocks: &mut [Block]) -> Result<(), SdmmcError> {
+        let mut block = start;
+        for chunk in blocks.chunks_mut(MAX_BLOCKS) {
+            self.read_blocks(block, chunk.as_flattened_mut())?;
+            block += chunk.len() as u32;
+        }
+        Ok(())
+    }
+
+    fn write(&self, start: u32, blocks: &[Block]) -> Result<(), SdmmcError> {
+        let mut block = start;
+        for chunk in blocks.chunks(MAX_BLOCKS) {
+            self.write_blocks(block, chunk.as_flattened())?;
+            block += chunk.len() as u32;
+        }
+        Ok(())
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -26,6 +26,7 @@ fn main() -> ! {
 
     loop {
         hl::recv_without_notification(&mut buffer, |op, msg| match op {
+            Op::SegmentHealth => Err(ResponseCode::OperationNotSupported),
             Op::WriteRead | Op::WriteReadBlock => {
                 let (payload, caller) = msg
                     .fixed_with_leases::<[u8; 4], usize>(2)
@@ -250,5 +250,26 @@ impl<S: SpiServer> SpiDevice<S> {
     }
 }
 
+impl SpiDevice<Spi> {
+    /// Variant of `lock` that bounds how long the lock may be held. If your
+    /// task hasn't released the lock within `lease_ms` milliseconds, the
+    /// server releases it (deasserting CS) and goes back to serving other
+    /// tasks. Your task's next `read`/`write`/`exchange` will then fault, so
+    /// that a multi-transfer sequence can't silently carry on without the
+    /// lock; `release` and `lock` still work, and start over.
+    ///
+    /// This is only available when talking to a SPI server task, not a
+    /// `SpiServer` embedded in your own task (which has nobody to be fair
+    /// to).
+    pub fn lock_lease(
+        &self,
+        assert_cs: CsState,
+        lease_ms: u32,
+    ) -> Result<(), idol_runtime::ServerDeath> {
+        self.server
+            .lock_lease(self.device_index, assert_cs, lease_ms)
+    }
+}
+
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
 include!(concat!(env!("OUT_DIR"), "/spi_devices.rs"));
@@ -372,6 +372,8 @@ impl From<AttestOrSprotError> for AttestDataSprotError {
                     Self::AttestSerializeSignature
                 }
                 AttestError::SignatureTooBig => Self::AttestSignatureTooBig,
+                // This comes from the update server failing us
+                AttestError::NoFirmwareInfo => Self::UpdateError,
             },
         }
     }
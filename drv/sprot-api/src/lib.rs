@@ -9,7 +9,7 @@
 extern crate memoffset;
 
 mod error;
-use attest_api::{AttestError, HashAlgorithm};
+use attest_api::{AttestError, FirmwareVersions, HashAlgorithm};
 use drv_caboose::CabooseError;
 use dumper_api::DumperError;
 pub use error::{
@@ -50,7 +50,7 @@ pub const MIN_VERSION: Version = Version(2);
 /// Code between the `CURRENT_VERSION` and `MIN_VERSION` must remain
 /// compatible. Use the rules described in the comments for [`Msg`] to evolve
 /// the protocol such that this remains true.
-pub const CURRENT_VERSION: Version = Version(6);
+pub const CURRENT_VERSION: Version = Version(7);
 
 /// We allow room in the buffer for message evolution
 pub const REQUEST_BUF_SIZE: usize = 1024;
@@ -451,6 +451,8 @@ pub enum AttestReq {
     TqCert { index: u32, offset: u32, size: u32 },
     TqSign { write_size: u32 },
     TqSignLen,
+    // Added in protocol version 7
+    FirmwareVersions,
 }
 
 /// A response used for RoT updates
@@ -489,6 +491,8 @@ pub enum AttestRsp {
     TqCert,
     TqSign,
     TqSignLen(u32),
+    // Added in version 7
+    FirmwareVersions(FirmwareVersions),
 }
 
 /// The body of a sprot response.
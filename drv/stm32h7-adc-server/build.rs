@@ -0,0 +1,176 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::io::Write;
+
+/// This represents our _subset_ of global config and _must not_ be marked with
+/// `deny_unknown_fields`!
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct GlobalConfig {
+    adc: AdcConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct AdcConfig {
+    channels: Vec<ChannelConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ChannelConfig {
+    name: String,
+    kind: Kind,
+    /// ADC3 input, for voltage channels
+    #[serde(default)]
+    input: Option<u8>,
+    /// The pin carrying a voltage channel's input, which we put in analog
+    /// mode
+    #[serde(default)]
+    pin: Option<PinConfig>,
+    /// Ratio of the measured voltage to the voltage at the ADC pin, for
+    /// inputs behind a divider
+    #[serde(default = "default_scale")]
+    scale: f32,
+    #[serde(default = "default_period_ms")]
+    period_ms: u32,
+    #[serde(default)]
+    smoothing: u8,
+    /// The sensor (as named in the sensors task's generated config, without
+    /// the `_SENSOR` suffix) to post readings to
+    #[serde(default)]
+    sensor: Option<String>,
+}
+
+#[derive(Copy, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Kind {
+    Temperature,
+    Vrefint,
+    Voltage,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct PinConfig {
+    port: char,
+    pin: u8,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+fn default_period_ms() -> u32 {
+    1000
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct TaskConfig {
+    /// Frequency of the AHB clock, which (divided by four) clocks the ADC
+    ahb_clock_hz: u32,
+}
+
+/// ADC3 inputs wired to the temperature sensor and the internal reference;
+/// the server has these too.
+const TEMPERATURE_INPUT: u8 = 18;
+const VREFINT_INPUT: u8 = 19;
+
+fn generate_adc_config(config: &AdcConfig, task: &TaskConfig) -> Result<()> {
+    let adc_hz = task.ahb_clock_hz / 4;
+    // The BOOST setting must match the ADC clock (RM0433 25.4.3).
+    let boost = match adc_hz {
+        0..=6_250_000 => 0b00,
+        6_250_001..=12_500_000 => 0b01,
+        12_500_001..=25_000_000 => 0b10,
+        25_000_001..=50_000_000 => 0b11,
+        _ => bail!("ADC clock of {adc_hz} Hz is too fast"),
+    };
+
+    if config.channels.len() > usize::from(u8::MAX) {
+        bail!("too many ADC channels");
+    }
+
+    let out = build_util::out_dir().join("adc_config.rs");
+    let mut file = std::fs::File::create(out)?;
+    writeln!(file, "pub const BOOST: u32 = {boost:#b};")?;
+    writeln!(
+        file,
+        "pub const NUM_CHANNELS: usize = {};",
+        config.channels.len()
+    )?;
+    writeln!(
+        file,
+        "pub static CHANNELS: [ChannelConfig; NUM_CHANNELS] = ["
+    )?;
+    for c in &config.channels {
+        let (kind, input) = match (c.kind, c.input) {
+            (Kind::Temperature, None) => ("Temperature", TEMPERATURE_INPUT),
+            (Kind::Vrefint, None) => ("Vrefint", VREFINT_INPUT),
+            (Kind::Voltage, Some(input)) if input < TEMPERATURE_INPUT => {
+                ("Voltage", input)
+            }
+            (Kind::Voltage, _) => {
+                bail!("channel {} needs an external input", c.name)
+            }
+            _ => bail!("channel {} can't choose its input", c.name),
+        };
+        if c.smoothing > 8 {
+            bail!("channel {} has too much smoothing", c.name);
+        }
+        let pin = match &c.pin {
+            Some(p) => format!(
+                "Some(Port::{}.pin({}))",
+                p.port.to_ascii_uppercase(),
+                p.pin
+            ),
+            None => "None".to_string(),
+        };
+        let sensor = match &c.sensor {
+            Some(s) => format!(
+                "Some(task_sensor_api::config::other_sensors::{}_SENSOR)",
+                s.to_uppercase()
+            ),
+            None => "None".to_string(),
+        };
+        writeln!(
+            file,
+            "    ChannelConfig {{
+        kind: Kind::{kind},
+        input: {input},
+        pin: {pin},
+        scale: {:?},
+        period_ms: {},
+        smoothing: {},
+        sensor: {sensor},
+    }},",
+            c.scale, c.period_ms, c.smoothing,
+        )?;
+    }
+    writeln!(file, "];")?;
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    idol::Generator::new()
+        .with_counters(
+            idol::CounterSettings::default().with_server_counters(false),
+        )
+        .build_server_support(
+            "../../idl/adc.idol",
+            "server_stub.rs",
+            idol::server::ServerStyle::InOrder,
+        )?;
+
+    let global_config = build_util::config::<GlobalConfig>()?;
+    let task_config = build_util::task_config::<TaskConfig>()?;
+    generate_adc_config(&global_config.adc, &task_config)?;
+
+    Ok(())
+}
@@ -0,0 +1,123 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Register-level access to ADC3, for single software-triggered
+//! conversions; offsets and bits are from RM0433 section 25.
+
+const BASE: usize = 0x5802_6000;
+const COMMON: usize = BASE + 0x300;
+
+const ISR: usize = 0x00;
+const CR: usize = 0x08;
+const CFGR: usize = 0x0C;
+const SMPR1: usize = 0x14;
+const SMPR2: usize = 0x18;
+const PCSEL: usize = 0x1C;
+const SQR1: usize = 0x30;
+const DR: usize = 0x40;
+const CCR: usize = 0x08;
+
+const ISR_ADRDY: u32 = 1 << 0;
+const ISR_EOC: u32 = 1 << 2;
+
+const CR_ADEN: u32 = 1 << 0;
+const CR_ADSTART: u32 = 1 << 2;
+const CR_BOOST_SHIFT: u32 = 8;
+const CR_ADCALLIN: u32 = 1 << 16;
+const CR_ADVREGEN: u32 = 1 << 28;
+const CR_ADCAL: u32 = 1 << 31;
+
+/// 16-bit resolution (RES = 0), overwriting on overrun.
+const CFGR_OVRMOD: u32 = 1 << 12;
+
+/// adc_hclk / 4
+const CCR_CKMODE_DIV4: u32 = 0b11 << 16;
+const CCR_VREFEN: u32 = 1 << 22;
+const CCR_TSEN: u32 = 1 << 23;
+
+/// The longest sampling time (810.5 cycles) for every input; the
+/// temperature sensor needs at least 9 us, and we're in no hurry.
+const SMPR_ALL_SLOWEST: u32 = 0x3FFF_FFFF;
+
+// Factory calibration values in system memory (RM0433 25.4.33, 25.4.34),
+// taken at VDDA = 3.3 V in 16-bit mode.
+const TS_CAL1: usize = 0x1FF1_E820;
+const TS_CAL2: usize = 0x1FF1_E840;
+const VREFINT_CAL: usize = 0x1FF1_E860;
+
+/// Temperatures at which `TS_CAL1` and `TS_CAL2` were taken.
+pub const TS_CAL1_TEMP: f32 = 30.0;
+pub const TS_CAL2_TEMP: f32 = 110.0;
+
+/// VDDA at which the calibration values were taken.
+pub const CAL_VDDA: f32 = 3.3;
+
+/// Full scale, at 16-bit resolution.
+pub const FULL_SCALE: f32 = 65535.0;
+
+/// Input wired to the internal voltage reference.
+pub const VREFINT_INPUT: u8 = 19;
+
+pub struct Calibration {
+    pub ts_cal1: u16,
+    pub ts_cal2: u16,
+    pub vrefint_cal: u16,
+}
+
+pub struct Adc;
+
+impl Adc {
+    fn read(&self, addr: usize) -> u32 {
+        unsafe { core::ptr::read_volatile(addr as *const u32) }
+    }
+
+    fn write(&self, addr: usize, value: u32) {
+        unsafe { core::ptr::write_volatile(addr as *mut u32, value) }
+    }
+
+    /// Powers up, calibrates and enables the ADC. The caller must have
+    /// turned on its clock.
+    pub fn init(&self, boost: u32) {
+        self.write(COMMON + CCR, CCR_CKMODE_DIV4 | CCR_VREFEN | CCR_TSEN);
+
+        // Leave deep power-down and start the regulator, which takes up to
+        // 10 us to settle.
+        let boost = boost << CR_BOOST_SHIFT;
+        self.write(BASE + CR, CR_ADVREGEN | boost);
+        userlib::hl::sleep_for(1);
+
+        self.write(BASE + CR, CR_ADVREGEN | boost | CR_ADCALLIN | CR_ADCAL);
+        while self.read(BASE + CR) & CR_ADCAL != 0 {}
+
+        self.write(BASE + CFGR, CFGR_OVRMOD);
+        self.write(BASE + SMPR1, SMPR_ALL_SLOWEST);
+        self.write(BASE + SMPR2, SMPR_ALL_SLOWEST);
+
+        self.write(BASE + ISR, ISR_ADRDY);
+        self.write(BASE + CR, CR_ADVREGEN | boost | CR_ADEN);
+        while self.read(BASE + ISR) & ISR_ADRDY == 0 {}
+    }
+
+    pub fn calibration(&self) -> Calibration {
+        let read16 =
+            |addr| unsafe { core::ptr::read_volatile(addr as *const u16) };
+        Calibration {
+            ts_cal1: read16(TS_CAL1),
+            ts_cal2: read16(TS_CAL2),
+            vrefint_cal: read16(VREFINT_CAL),
+        }
+    }
+
+    /// Converts `input` once, and returns the result.
+    pub fn convert(&self, input: u8) -> u16 {
+        self.write(BASE + PCSEL, 1 << input);
+        // One conversion in the sequence (L = 0), of `input`.
+        self.write(BASE + SQR1, u32::from(input) << 6);
+        self.write(BASE + ISR, ISR_EOC);
+        let cr = self.read(BASE + CR);
+        self.write(BASE + CR, cr | CR_ADSTART);
+        while self.read(BASE + ISR) & ISR_EOC == 0 {}
+        self.read(BASE + DR) as u16
+    }
+}
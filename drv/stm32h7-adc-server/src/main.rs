@@ -0,0 +1,386 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ADC server for the STM32H7's ADC3, which is the one with the internal
+//! temperature sensor and voltage reference.
+//!
+//! Each channel -- the die temperature, VDDA (derived from the internal
+//! reference), or the voltage on an external input -- is sampled on its own
+//! period, and its readings are smoothed with an exponential moving average
+//! before being handed out. Clients can adjust a channel's period and
+//! smoothing, read its latest value, and ask to be notified when the value
+//! leaves (or comes back into) a range. A channel can also be tied to a
+//! sensor, in which case each reading is posted to the sensors task, so
+//! that the MCU's temperature shows up alongside everything else's.
+//!
+//! Conversions are corrected for VDDA, which we measure (by way of the
+//! internal reference) whenever we sample anything else.
+//!
+//! Channels are defined in the app's global config, so that clients can
+//! refer to them by name:
+//!
+//! ```toml
+//! [[config.adc.channels]]
+//! name = "mcu-temperature"
+//! kind = "temperature"
+//! sensor = "stm32h7_mcu_temperature"
+//!
+//! [[config.adc.channels]]
+//! name = "v12-sense"
+//! kind = "voltage"
+//! input = 0
+//! pin = { port = "C", pin = 2 }
+//! scale = 11.0
+//! period-ms = 100
+//! smoothing = 3
+//! ```
+//!
+//! The task itself is configured with the AHB clock frequency. It needs
+//! `adc3` and `system_flash` (where the factory calibration lives) in its
+//! memory map, and task slots for `sys` and `sensor`.
+
+#![no_std]
+#![no_main]
+
+mod adc;
+
+use adc::{Adc, Calibration};
+use drv_adc_api::{AdcError, Level, Reading, MAX_SMOOTHING};
+use drv_stm32xx_sys_api::{
+    Alternate, Mode, OutputType, Peripheral, PinSet, Port, Pull, Speed, Sys,
+};
+use idol_runtime::{NotificationHandler, RequestError};
+use ringbuf::*;
+use task_sensor_api::{Sensor, SensorId};
+use userlib::*;
+
+task_slot!(SYS, sys);
+task_slot!(SENSOR, sensor);
+
+const TIMER_NOTIFICATION: u32 = 1 << 31;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Kind {
+    Temperature,
+    Vrefint,
+    Voltage,
+}
+
+struct ChannelConfig {
+    kind: Kind,
+    input: u8,
+    pin: Option<PinSet>,
+    scale: f32,
+    period_ms: u32,
+    smoothing: u8,
+    sensor: Option<SensorId>,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Sample { channel: u8, raw: u16 },
+    Crossed { channel: u8, level: Level },
+}
+
+ringbuf!(Trace, 32, Trace::None);
+
+#[derive(Copy, Clone)]
+struct Watch {
+    owner: TaskId,
+    notification: u32,
+    low: f32,
+    high: f32,
+    hysteresis: f32,
+}
+
+impl Watch {
+    /// Where `value` falls, given where the last reading fell.
+    fn level(&self, value: f32, last: Level) -> Level {
+        match last {
+            _ if value > self.high => Level::Above,
+            _ if value < self.low => Level::Below,
+            Level::Above if value > self.high - self.hysteresis => Level::Above,
+            Level::Below if value < self.low + self.hysteresis => Level::Below,
+            _ => Level::Within,
+        }
+    }
+}
+
+struct Channel {
+    config: &'static ChannelConfig,
+    period_ms: u32,
+    smoothing: u8,
+    next_due: Option<u64>,
+    value: Option<f32>,
+    timestamp: u64,
+    level: Level,
+    watch: Option<Watch>,
+}
+
+struct ServerImpl {
+    adc: Adc,
+    sensor: Sensor,
+    calibration: Calibration,
+    channels: [Channel; config::NUM_CHANNELS],
+}
+
+impl ServerImpl {
+    fn channel(&mut self, channel: u8) -> Result<&mut Channel, AdcError> {
+        self.channels
+            .get_mut(usize::from(channel))
+            .ok_or(AdcError::BadChannel)
+    }
+
+    fn vdda(&self) -> f32 {
+        let raw = self.adc.convert(adc::VREFINT_INPUT);
+        if raw == 0 {
+            return adc::CAL_VDDA;
+        }
+        adc::CAL_VDDA * f32::from(self.calibration.vrefint_cal) / f32::from(raw)
+    }
+
+    /// Converts a raw sample of a channel of `kind` into its units.
+    fn value(&self, kind: Kind, raw: u16, vdda: f32, scale: f32) -> f32 {
+        let raw = f32::from(raw);
+        match kind {
+            Kind::Temperature => {
+                let cal = &self.calibration;
+                // The calibration values were taken at CAL_VDDA.
+                let at_cal = raw * vdda / adc::CAL_VDDA;
+                let slope = (adc::TS_CAL2_TEMP - adc::TS_CAL1_TEMP)
+                    / (f32::from(cal.ts_cal2) - f32::from(cal.ts_cal1));
+                adc::TS_CAL1_TEMP + slope * (at_cal - f32::from(cal.ts_cal1))
+            }
+            Kind::Vrefint => vdda,
+            Kind::Voltage => raw / adc::FULL_SCALE * vdda * scale,
+        }
+    }
+
+    /// Samples every channel that's due, and returns when the next one will
+    /// be.
+    fn sample(&mut self, now: u64) -> Option<u64> {
+        let due = |c: &Channel| c.next_due.map(|t| t <= now).unwrap_or(false);
+        if self.channels.iter().any(due) {
+            let vdda = self.vdda();
+            for i in 0..self.channels.len() {
+                if due(&self.channels[i]) {
+                    self.sample_channel(i, vdda, now);
+                }
+            }
+        }
+        self.channels.iter().filter_map(|c| c.next_due).min()
+    }
+
+    fn sample_channel(&mut self, i: usize, vdda: f32, now: u64) {
+        let config = self.channels[i].config;
+        let raw = match config.kind {
+            // We've just measured this.
+            Kind::Vrefint => 0,
+            _ => self.adc.convert(config.input),
+        };
+        ringbuf_entry!(Trace::Sample {
+            channel: i as u8,
+            raw
+        });
+        let sample = self.value(config.kind, raw, vdda, config.scale);
+
+        let c = &mut self.channels[i];
+        let value = match c.value {
+            Some(v) => {
+                let weight = 1.0 / f32::from(1u16 << c.smoothing);
+                v + weight * (sample - v)
+            }
+            None => sample,
+        };
+        c.value = Some(value);
+        c.timestamp = now;
+        c.next_due = now.checked_add(u64::from(c.period_ms));
+
+        if let Some(watch) = c.watch {
+            let level = watch.level(value, c.level);
+            if level != c.level {
+                ringbuf_entry!(Trace::Crossed {
+                    channel: i as u8,
+                    level
+                });
+                c.level = level;
+                sys_post(watch.owner, watch.notification);
+            }
+        }
+        if let Some(id) = config.sensor {
+            self.sensor.post(id, value, now);
+        }
+    }
+
+    fn reschedule(&mut self) {
+        let next = self.channels.iter().filter_map(|c| c.next_due).min();
+        sys_set_timer(next, TIMER_NOTIFICATION);
+    }
+}
+
+impl idl::InOrderAdcImpl for ServerImpl {
+    fn read(
+        &mut self,
+        _: &RecvMessage,
+        channel: u8,
+    ) -> Result<Reading, RequestError<AdcError>> {
+        let c = self.channel(channel)?;
+        let value = c.value.ok_or(AdcError::NoReading)?;
+        Ok(Reading {
+            value,
+            level: c.level as u8,
+            _reserved: [0; 3],
+            timestamp: c.timestamp,
+        })
+    }
+
+    fn configure(
+        &mut self,
+        _: &RecvMessage,
+        channel: u8,
+        period_ms: u32,
+        smoothing: u8,
+    ) -> Result<(), RequestError<AdcError>> {
+        if smoothing > MAX_SMOOTHING {
+            return Err(AdcError::BadSmoothing.into());
+        }
+        let c = self.channel(channel)?;
+        c.period_ms = period_ms;
+        c.smoothing = smoothing;
+        c.next_due = if period_ms == 0 {
+            None
+        } else {
+            // Take the first sample at the new rate straight away.
+            Some(sys_get_timer().now)
+        };
+        self.reschedule();
+        Ok(())
+    }
+
+    fn watch(
+        &mut self,
+        msg: &RecvMessage,
+        channel: u8,
+        low: f32,
+        high: f32,
+        hysteresis: f32,
+        notification: u32,
+    ) -> Result<(), RequestError<AdcError>> {
+        // Written so that NaNs are rejected too.
+        if !(low <= high && hysteresis >= 0.0) {
+            return Err(AdcError::BadThresholds.into());
+        }
+        let c = self.channel(channel)?;
+        let watch = Watch {
+            owner: msg.sender,
+            notification,
+            low,
+            high,
+            hysteresis,
+        };
+        c.watch = Some(watch);
+        // Judge the current reading afresh, and tell the caller at once if
+        // it's already out of range.
+        c.level = Level::Within;
+        if let Some(v) = c.value {
+            c.level = watch.level(v, Level::Within);
+            if c.level != Level::Within {
+                sys_post(watch.owner, watch.notification);
+            }
+        }
+        Ok(())
+    }
+
+    fn unwatch(
+        &mut self,
+        msg: &RecvMessage,
+        channel: u8,
+    ) -> Result<(), RequestError<AdcError>> {
+        let c = self.channel(channel)?;
+        match c.watch {
+            Some(w) if w.owner == msg.sender => {
+                c.watch = None;
+                c.level = Level::Within;
+                Ok(())
+            }
+            _ => Err(AdcError::NotWatching.into()),
+        }
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        TIMER_NOTIFICATION
+    }
+
+    fn handle_notification(&mut self, bits: u32) {
+        if bits & TIMER_NOTIFICATION != 0 {
+            let next = self.sample(sys_get_timer().now);
+            sys_set_timer(next, TIMER_NOTIFICATION);
+        }
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let sys = Sys::from(SYS.get_task_id());
+    sys.enable_clock(Peripheral::Adc3);
+    sys.leave_reset(Peripheral::Adc3);
+    for c in &config::CHANNELS {
+        if let Some(pin) = c.pin {
+            sys.gpio_configure(
+                pin.port,
+                pin.pin_mask,
+                Mode::Analog,
+                OutputType::PushPull,
+                Speed::Low,
+                Pull::None,
+                Alternate::AF0,
+            );
+        }
+    }
+
+    let adc = Adc;
+    adc.init(config::BOOST);
+    let calibration = adc.calibration();
+
+    let now = sys_get_timer().now;
+    let mut server = ServerImpl {
+        adc,
+        sensor: Sensor::from(SENSOR.get_task_id()),
+        calibration,
+        channels: core::array::from_fn(|i| {
+            let config = &config::CHANNELS[i];
+            Channel {
+                config,
+                period_ms: config.period_ms,
+                smoothing: config.smoothing,
+                next_due: (config.period_ms != 0).then_some(now),
+                value: None,
+                timestamp: 0,
+                level: Level::Within,
+                watch: None,
+            }
+        }),
+    };
+    server.reschedule();
+
+    let mut buffer = [0; idl::INCOMING_SIZE];
+    loop {
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
+
+mod config {
+    use super::*;
+
+    include!(concat!(env!("OUT_DIR"), "/adc_config.rs"));
+}
+
+mod idl {
+    use drv_adc_api::{AdcError, Reading};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
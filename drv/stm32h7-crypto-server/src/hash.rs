@@ -0,0 +1,92 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The HASH engine, fed by DMA (RM0433 section 36).
+//!
+//! We run it in multiple-DMA-transfer mode, which lets a digest take any
+//! number of transfers without the engine finishing it at the end of each.
+//! DMA moves whole words, so the last few bytes of a message, and the
+//! command to finish it, go in by hand.
+
+use crate::device;
+use drv_crypto_api::SHA256_LEN;
+
+pub struct Hash {
+    reg: &'static device::hash::RegisterBlock,
+}
+
+impl Hash {
+    pub fn new(reg: &'static device::hash::RegisterBlock) -> Self {
+        Self { reg }
+    }
+
+    /// The address DMA writes message words to.
+    pub fn din_address(&self) -> u32 {
+        &self.reg.din as *const _ as u32
+    }
+
+    /// Starts a SHA-256 digest, taking its input from DMA.
+    pub fn begin(&self) {
+        while self.is_busy() {}
+        self.reg.cr.write(|w| unsafe {
+            w.algo1()
+                .set_bit()
+                .algo0()
+                .set_bit() // algo=0b11 is SHA256
+                .mode()
+                .clear_bit() // HASH mode, not HMAC
+                .datatype()
+                .bits(0b10) // bytes, as they are in memory
+                .mdmat()
+                .set_bit()
+                .dmae()
+                .set_bit()
+        });
+        self.reg.str.write(|w| unsafe { w.nblw().bits(0) });
+        self.reg.cr.modify(|_, w| w.init().set_bit());
+    }
+
+    /// Ends the message with `tail`, up to three bytes that didn't make up a
+    /// word for DMA, and returns the digest.
+    pub fn finish(&self, tail: &[u8]) -> [u8; SHA256_LEN] {
+        while self.is_busy() {}
+        // Any more words come from us.
+        self.reg.cr.modify(|_, w| w.dmae().clear_bit());
+
+        let nblw = (tail.len() * 8) as u8;
+        self.reg.str.write(|w| unsafe { w.nblw().bits(nblw) });
+        if !tail.is_empty() {
+            let mut word = [0; 4];
+            word[..tail.len()].copy_from_slice(tail);
+            self.reg.din.write(|w| unsafe {
+                w.datain().bits(u32::from_le_bytes(word))
+            });
+        }
+        self.reg.str.modify(|_, w| w.dcal().set_bit());
+
+        // The last block takes well under a microsecond, so this isn't worth
+        // an interrupt.
+        while !self.reg.sr.read().dcis().bit() {}
+
+        let words = [
+            self.reg.hash_hr0.read().bits(),
+            self.reg.hash_hr1.read().bits(),
+            self.reg.hash_hr2.read().bits(),
+            self.reg.hash_hr3.read().bits(),
+            self.reg.hash_hr4.read().bits(),
+            self.reg.hash_hr5.read().bits(),
+            self.reg.hash_hr6.read().bits(),
+            self.reg.hash_hr7.read().bits(),
+        ];
+        let mut out = [0; SHA256_LEN];
+        for (chunk, word) in out.chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+
+    fn is_busy(&self) -> bool {
+        self.reg.sr.read().busy().bit()
+    }
+}
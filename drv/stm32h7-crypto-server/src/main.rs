@@ -0,0 +1,414 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Crypto accelerator server for the STM32H753.
+//!
+//! This task owns the HASH and CRYP engines, and moves data through them with
+//! DMA streams from the DMA manager, so that digesting an image or
+//! decrypting a blob costs the CPU little more than copying it. Each request
+//! takes its data from a lease of any length, which we take in pieces the
+//! size of a DMA transfer.
+//!
+//! The HASH engine holds the state of one digest, so only one can be under
+//! way at a time: a task that starts one with `sha256_begin` has it until it
+//! calls `sha256_finish`, or restarts, and until then others get `Busy`.
+//! (The engine can save and restore its context, which would let us
+//! interleave digests, but nobody needs that yet.) AES requests finish within
+//! the call, so never contend.
+//!
+//! The H7 has no PKA; signatures are checked in software.
+//!
+//! The task needs `uses = ["cryp", "hash"]`, task slots for `sys` and `dma`,
+//! and notifications named `dma-in` and `dma-out` for the DMA manager to
+//! post.
+
+#![no_std]
+#![no_main]
+
+mod cryp;
+mod hash;
+
+use cryp::Cryp;
+use drv_crypto_api::{CryptoError, SHA256_LEN};
+use drv_stm32h7_dma_api::{Dma, DmaError, MAX_TRANSFER};
+use drv_stm32xx_sys_api::{Peripheral, Sys};
+use hash::Hash;
+use idol_runtime::{
+    ClientError, Leased, NotificationHandler, RequestError, R, W,
+};
+use ringbuf::*;
+use userlib::*;
+
+#[cfg(feature = "h753")]
+use stm32h7::stm32h753 as device;
+
+task_slot!(SYS, sys);
+task_slot!(DMA, dma);
+
+// DMAMUX1 request lines (RM0433 table 121).
+const CRYP_IN_REQUEST: u8 = 76;
+const CRYP_OUT_REQUEST: u8 = 77;
+const HASH_IN_REQUEST: u8 = 78;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Begin(TaskId),
+    Reaped(TaskId),
+    Digest { len: u32 },
+    Aes { len: u32 },
+    DmaFailed(DmaError),
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+#[derive(Copy, Clone, PartialEq, counters::Count)]
+enum Event {
+    Sha256,
+    Aes256Ctr,
+    DmaFailed,
+}
+
+counters::counters!(Event);
+
+/// A digest under way.
+struct Digest {
+    /// The task it's for, or `None` for a one-shot `sha256` call.
+    owner: Option<TaskId>,
+    /// Input we haven't yet given the engine, because it isn't a full DMA
+    /// transfer's worth; a message's last few bytes have to go in by hand.
+    pending: [u8; MAX_TRANSFER],
+    pending_len: usize,
+    /// Message length so far, for the ringbuf
+    len: u32,
+}
+
+struct ServerImpl {
+    hash: Hash,
+    cryp: Cryp,
+    dma: Dma,
+    /// Streams claimed from the DMA manager
+    hash_in: u8,
+    cryp_in: u8,
+    cryp_out: u8,
+    digest: Option<Digest>,
+}
+
+impl ServerImpl {
+    /// Runs a DMA write of `data` to `peripheral`, and waits for it.
+    fn dma_write(
+        &self,
+        stream: u8,
+        peripheral: u32,
+        data: &[u8],
+    ) -> Result<(), CryptoError> {
+        self.dma
+            .start_write(stream, peripheral, 4, data)
+            .map_err(dma_failed)?;
+        self.dma_wait(stream, notifications::DMA_IN_MASK, &mut [])
+    }
+
+    /// Waits for the transfer on `stream` to finish, copying anything it
+    /// read into `dest`.
+    fn dma_wait(
+        &self,
+        stream: u8,
+        mask: u32,
+        dest: &mut [u8],
+    ) -> Result<(), CryptoError> {
+        loop {
+            sys_recv_notification(mask);
+            match self.dma.finish(stream, dest) {
+                Ok(_) => return Ok(()),
+                // Not ours yet; the bit was left over from an earlier
+                // transfer that failed.
+                Err(DmaError::Busy) => continue,
+                Err(e) => return Err(dma_failed(e)),
+            }
+        }
+    }
+
+    /// Drops the digest under way if its owner has restarted since it
+    /// began it.
+    fn reap(&mut self) {
+        if let Some(Digest {
+            owner: Some(owner), ..
+        }) = self.digest
+        {
+            if sys_refresh_task_id(owner) != owner {
+                ringbuf_entry!(Trace::Reaped(owner));
+                self.digest = None;
+            }
+        }
+    }
+
+    fn begin(&mut self, owner: Option<TaskId>) -> Result<(), CryptoError> {
+        self.reap();
+        if let Some(d) = &self.digest {
+            if d.owner != owner || owner.is_none() {
+                return Err(CryptoError::Busy);
+            }
+        }
+        self.hash.begin();
+        self.digest = Some(Digest {
+            owner,
+            pending: [0; MAX_TRANSFER],
+            pending_len: 0,
+            len: 0,
+        });
+        Ok(())
+    }
+
+    /// Feeds the engine all of `data`, a transfer at a time.
+    fn feed(
+        &mut self,
+        data: &Leased<R, [u8]>,
+    ) -> Result<(), RequestError<CryptoError>> {
+        let Some(mut d) = self.digest.take() else {
+            return Err(CryptoError::NotStarted.into());
+        };
+        let r = self.feed_digest(&mut d, data);
+        if r.is_ok() {
+            self.digest = Some(d);
+        }
+        r
+    }
+
+    fn feed_digest(
+        &self,
+        d: &mut Digest,
+        data: &Leased<R, [u8]>,
+    ) -> Result<(), RequestError<CryptoError>> {
+        let mut offset = 0;
+        while offset < data.len() {
+            let n = (data.len() - offset).min(MAX_TRANSFER - d.pending_len);
+            data.read_range(
+                offset..offset + n,
+                &mut d.pending[d.pending_len..d.pending_len + n],
+            )
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+            offset += n;
+            d.pending_len += n;
+            d.len = d.len.wrapping_add(n as u32);
+
+            if d.pending_len == MAX_TRANSFER {
+                self.dma_write(
+                    self.hash_in,
+                    self.hash.din_address(),
+                    &d.pending,
+                )?;
+                d.pending_len = 0;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(
+        &mut self,
+        owner: Option<TaskId>,
+    ) -> Result<[u8; SHA256_LEN], CryptoError> {
+        self.reap();
+        let d = match self.digest.take() {
+            Some(d) if d.owner == owner => d,
+            other => {
+                self.digest = other;
+                return Err(CryptoError::NotStarted);
+            }
+        };
+        let words = d.pending_len & !3;
+        if words != 0 {
+            self.dma_write(
+                self.hash_in,
+                self.hash.din_address(),
+                &d.pending[..words],
+            )?;
+        }
+        ringbuf_entry!(Trace::Digest { len: d.len });
+        counters::count!(Event::Sha256);
+        Ok(self.hash.finish(&d.pending[words..d.pending_len]))
+    }
+
+    /// Runs AES-256-CTR over all of `input`, into `output`.
+    fn aes256_ctr_inner(
+        &self,
+        input: &Leased<R, [u8]>,
+        output: &Leased<W, [u8]>,
+    ) -> Result<(), RequestError<CryptoError>> {
+        let mut buf = [0u8; MAX_TRANSFER];
+        let mut offset = 0;
+        let r = loop {
+            if offset >= input.len() {
+                break Ok(());
+            }
+            let n = (input.len() - offset).min(MAX_TRANSFER);
+            if input.read_range(offset..offset + n, &mut buf[..n]).is_err() {
+                break Err(RequestError::Fail(ClientError::WentAway));
+            }
+            // Only the last piece can be short; counter mode doesn't care
+            // what we pad it with, as we throw that part away.
+            let padded = n.next_multiple_of(cryp::BLOCK);
+            buf[n..padded].fill(0);
+
+            if let Err(e) = self.aes_chunk(&mut buf[..padded]) {
+                break Err(e.into());
+            }
+            if output.write_range(offset..offset + n, &buf[..n]).is_err() {
+                break Err(RequestError::Fail(ClientError::WentAway));
+            }
+            offset += n;
+        };
+        buf.fill(0);
+        r
+    }
+
+    fn aes_chunk(&self, buf: &mut [u8]) -> Result<(), CryptoError> {
+        // Have the output stream waiting before the input starts, so that the
+        // engine never stalls with a full output FIFO.
+        self.dma
+            .start_read(
+                self.cryp_out,
+                self.cryp.dout_address(),
+                4,
+                buf.len() as u32,
+            )
+            .map_err(dma_failed)?;
+        let r = self.dma_write(self.cryp_in, self.cryp.din_address(), buf);
+        if r.is_err() {
+            let _ = self.dma.abort(self.cryp_out);
+            return r;
+        }
+        self.dma_wait(self.cryp_out, notifications::DMA_OUT_MASK, buf)
+    }
+}
+
+/// Notes a DMA failure, and turns it into ours -- unless the DMA manager has
+/// restarted and forgotten our streams, in which case we restart to claim
+/// them again.
+fn dma_failed(e: DmaError) -> CryptoError {
+    ringbuf_entry!(Trace::DmaFailed(e));
+    counters::count!(Event::DmaFailed);
+    if e == DmaError::ServerRestarted {
+        panic!();
+    }
+    CryptoError::Dma
+}
+
+impl idl::InOrderCryptoImpl for ServerImpl {
+    fn sha256(
+        &mut self,
+        _: &RecvMessage,
+        data: Leased<R, [u8]>,
+    ) -> Result<[u8; SHA256_LEN], RequestError<CryptoError>> {
+        self.begin(None)?;
+        self.feed(&data)?;
+        Ok(self.finish(None)?)
+    }
+
+    fn sha256_begin(
+        &mut self,
+        msg: &RecvMessage,
+    ) -> Result<(), RequestError<CryptoError>> {
+        ringbuf_entry!(Trace::Begin(msg.sender));
+        Ok(self.begin(Some(msg.sender))?)
+    }
+
+    fn sha256_update(
+        &mut self,
+        msg: &RecvMessage,
+        data: Leased<R, [u8]>,
+    ) -> Result<(), RequestError<CryptoError>> {
+        self.reap();
+        match &self.digest {
+            Some(d) if d.owner == Some(msg.sender) => self.feed(&data),
+            Some(_) => Err(CryptoError::Busy.into()),
+            None => Err(CryptoError::NotStarted.into()),
+        }
+    }
+
+    fn sha256_finish(
+        &mut self,
+        msg: &RecvMessage,
+    ) -> Result<[u8; SHA256_LEN], RequestError<CryptoError>> {
+        Ok(self.finish(Some(msg.sender))?)
+    }
+
+    fn aes256_ctr(
+        &mut self,
+        _: &RecvMessage,
+        key: [u8; 32],
+        iv: [u8; 16],
+        input: Leased<R, [u8]>,
+        output: Leased<W, [u8]>,
+    ) -> Result<(), RequestError<CryptoError>> {
+        if input.len() != output.len() {
+            return Err(CryptoError::BadLength.into());
+        }
+        let mut key = key;
+        self.cryp.start_aes256_ctr(&key, &iv);
+        key.fill(0);
+        let r = self.aes256_ctr_inner(&input, &output);
+        self.cryp.stop();
+
+        ringbuf_entry!(Trace::Aes {
+            len: input.len() as u32
+        });
+        counters::count!(Event::Aes256Ctr);
+        r
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        // We only wait for DMA from within requests.
+        0
+    }
+
+    fn handle_notification(&mut self, _bits: u32) {
+        unreachable!()
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let sys = Sys::from(SYS.get_task_id());
+    for p in [Peripheral::Hash, Peripheral::Crypt] {
+        sys.enter_reset(p);
+        sys.enable_clock(p);
+        sys.leave_reset(p);
+    }
+
+    let dma = Dma::from(DMA.get_task_id());
+    let claim = |request, mask| match dma.claim(request, mask) {
+        Ok(stream) => stream,
+        Err(e) => {
+            ringbuf_entry!(Trace::DmaFailed(e));
+            panic!();
+        }
+    };
+    let hash_in = claim(HASH_IN_REQUEST, notifications::DMA_IN_MASK);
+    let cryp_in = claim(CRYP_IN_REQUEST, notifications::DMA_IN_MASK);
+    let cryp_out = claim(CRYP_OUT_REQUEST, notifications::DMA_OUT_MASK);
+
+    let mut server = ServerImpl {
+        hash: Hash::new(unsafe { &*device::HASH::ptr() }),
+        cryp: Cryp::new(unsafe { &*device::CRYP::ptr() }),
+        dma,
+        hash_in,
+        cryp_in,
+        cryp_out,
+        digest: None,
+    };
+    let mut buffer = [0; idl::INCOMING_SIZE];
+    loop {
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
+
+mod idl {
+    use drv_crypto_api::CryptoError;
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
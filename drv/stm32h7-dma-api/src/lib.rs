@@ -0,0 +1,60 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! API crate for the STM32H7 DMA manager.
+//!
+//! The DMA manager owns DMA1, DMA2 and DMAMUX1. A driver that wants DMA
+//! claims a stream for its peripheral's DMAMUX request line, and then runs
+//! transfers on it by lending the server its buffers. The server posts the
+//! notification given at claim time when each transfer finishes, after which
+//! the driver calls `finish` to collect the result.
+//!
+//! For peripherals that receive data at any time (a UART, say), a stream can
+//! instead be run in circular mode, which reads into a ring buffer in the
+//! server until it's aborted. The server posts the notification each time
+//! half of the ring fills, and the driver calls `drain` to collect what has
+//! arrived so far.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::{sys_send, FromPrimitive};
+
+/// Largest transfer the server will take in one go, in bytes.
+pub const MAX_TRANSFER: usize = 256;
+
+#[derive(
+    Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError, counters::Count,
+)]
+pub enum DmaError {
+    /// Every stream is already claimed.
+    NoStream = 1,
+    /// The stream number is out of range.
+    BadStream,
+    /// The stream is claimed by another task, or not at all.
+    NotOwner,
+    /// A transfer is still running on the stream.
+    Busy,
+    /// There's no finished transfer to collect.
+    Idle,
+    /// The peripheral address is outside peripheral space, or isn't aligned
+    /// to the transfer width.
+    BadAddress,
+    /// The transfer width isn't 1, 2 or 4 bytes.
+    BadWidth,
+    /// The transfer length is zero, too long, or not a multiple of the width.
+    BadLength,
+    /// The DMAMUX request line is out of range.
+    BadRequest,
+    /// The controller reported a bus or direct mode error.
+    TransferError,
+    /// A circular stream went all the way around its ring since the last
+    /// `drain`, so data was lost.
+    Overrun,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -0,0 +1,651 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! DMA manager for the STM32H7.
+//!
+//! This task owns DMA1, DMA2 and DMAMUX1, and hands out their sixteen streams
+//! to other drivers on request, so that they don't each have to poke at the
+//! controllers themselves and trip over each other's channels. Use the
+//! `drv-stm32h7-dma-api` crate to talk to it.
+//!
+//! We can't point the controllers at a client's memory -- we don't know where
+//! its leases are, and it may not be memory the controllers can reach -- so
+//! each stream has a bounce buffer here. A write copies the client's buffer
+//! into the bounce buffer before starting the stream; a read lands in the
+//! bounce buffer, and is copied out to the client in `finish`.
+//!
+//! Because of that, this task's RAM has to be somewhere the controllers can
+//! reach (i.e. not DTCM), and must not be cached.
+//!
+//! A circular read runs continuously into the stream's bounce buffer, and
+//! `drain` copies out whatever has arrived since it was last called. We can't
+//! tell exactly how much has arrived if the stream has lapped the reader, so
+//! we count the half-buffer interrupts between drains, and report an overrun
+//! once there have been enough of them that the stream must have done so.
+//!
+//! The task needs `uses = ["dma1", "dma2", "dmamux1"]`, and the interrupts
+//! of all sixteen streams mapped to notifications named `dma1-stream0`
+//! through `dma2-stream7`.
+
+#![no_std]
+#![no_main]
+
+use drv_stm32h7_dma_api::{DmaError, MAX_TRANSFER};
+use drv_stm32xx_sys_api::{Peripheral, Sys};
+use idol_runtime::{
+    ClientError, Leased, LenLimit, NotificationHandler, RequestError, R, W,
+};
+use ringbuf::*;
+use userlib::*;
+
+#[cfg(feature = "h743")]
+use stm32h7::stm32h743 as device;
+
+#[cfg(feature = "h753")]
+use stm32h7::stm32h753 as device;
+
+task_slot!(SYS, sys);
+
+/// Streams per controller.
+const STREAMS_PER_DMA: usize = 8;
+/// Total streams; stream `n` is DMA1 stream `n` for `n < 8`, and DMA2 stream
+/// `n - 8` after that. DMAMUX1 channel `n` feeds stream `n`.
+const STREAMS: usize = 2 * STREAMS_PER_DMA;
+
+/// Highest request line DMAMUX1 will take.
+const MAX_REQUEST: u8 = 127;
+
+/// Notification bits for each stream's interrupt, in stream order.
+const STREAM_IRQS: [u32; STREAMS] = [
+    notifications::DMA1_STREAM0_MASK,
+    notifications::DMA1_STREAM1_MASK,
+    notifications::DMA1_STREAM2_MASK,
+    notifications::DMA1_STREAM3_MASK,
+    notifications::DMA1_STREAM4_MASK,
+    notifications::DMA1_STREAM5_MASK,
+    notifications::DMA1_STREAM6_MASK,
+    notifications::DMA1_STREAM7_MASK,
+    notifications::DMA2_STREAM0_MASK,
+    notifications::DMA2_STREAM1_MASK,
+    notifications::DMA2_STREAM2_MASK,
+    notifications::DMA2_STREAM3_MASK,
+    notifications::DMA2_STREAM4_MASK,
+    notifications::DMA2_STREAM5_MASK,
+    notifications::DMA2_STREAM6_MASK,
+    notifications::DMA2_STREAM7_MASK,
+];
+
+// Bits in a stream's configuration register (SxCR).
+const CR_EN: u32 = 1 << 0;
+const CR_DMEIE: u32 = 1 << 1;
+const CR_TEIE: u32 = 1 << 2;
+const CR_HTIE: u32 = 1 << 3;
+const CR_TCIE: u32 = 1 << 4;
+const CR_DIR_M2P: u32 = 0b01 << 6;
+const CR_CIRC: u32 = 1 << 8;
+const CR_MINC: u32 = 1 << 10;
+const CR_PSIZE_SHIFT: u32 = 11;
+const CR_MSIZE_SHIFT: u32 = 13;
+
+// Bits in a stream's group of interrupt status/clear bits.
+const ISR_DMEIF: u32 = 1 << 2;
+const ISR_TEIF: u32 = 1 << 3;
+const ISR_HTIF: u32 = 1 << 4;
+const ISR_TCIF: u32 = 1 << 5;
+const ISR_ALL: u32 = 0b11_1101;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Claim {
+        stream: u8,
+        request: u8,
+        owner: TaskId,
+    },
+    Release(u8),
+    Reclaim(u8),
+    Start {
+        stream: u8,
+        len: u16,
+        write: bool,
+    },
+    Circular(u8),
+    Overrun(u8),
+    Irq {
+        stream: u8,
+        flags: u32,
+    },
+}
+
+ringbuf!(Trace, 32, Trace::None);
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    /// Nothing has been started since the last `finish`.
+    Idle,
+    /// A transfer is running.
+    Running,
+    /// A transfer finished, and hasn't been collected.
+    Done,
+    /// A transfer failed, and hasn't been collected.
+    Failed,
+    /// A circular read is running.
+    Circular,
+}
+
+impl State {
+    fn busy(self) -> bool {
+        matches!(self, State::Running | State::Circular)
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Claim {
+    owner: TaskId,
+    notification: u32,
+    state: State,
+    /// Whether the current transfer is a read into the bounce buffer.
+    read: bool,
+    len: usize,
+    /// For a circular read, the offset in the bounce buffer that `drain` will
+    /// copy from next.
+    tail: usize,
+    /// For a circular read, the number of half-buffer boundaries the stream
+    /// has crossed since the last `drain`.
+    halves: u8,
+}
+
+struct ServerImpl {
+    dma1: &'static device::dma1::RegisterBlock,
+    dma2: &'static device::dma1::RegisterBlock,
+    dmamux: &'static device::dmamux1::RegisterBlock,
+    claims: [Option<Claim>; STREAMS],
+    bounce: &'static mut [[u8; MAX_TRANSFER]; STREAMS],
+}
+
+impl ServerImpl {
+    fn controller(&self, stream: usize) -> &device::dma1::RegisterBlock {
+        if stream < STREAMS_PER_DMA {
+            self.dma1
+        } else {
+            self.dma2
+        }
+    }
+
+    /// Reads the interrupt flags for `stream`, shifted down to the bottom of
+    /// the word.
+    fn flags(&self, stream: usize) -> u32 {
+        let dma = self.controller(stream);
+        let n = stream % STREAMS_PER_DMA;
+        let isr = if n < 4 {
+            dma.lisr.read().bits()
+        } else {
+            dma.hisr.read().bits()
+        };
+        (isr >> flag_shift(n)) & ISR_ALL
+    }
+
+    fn clear_flags(&self, stream: usize) {
+        let dma = self.controller(stream);
+        let n = stream % STREAMS_PER_DMA;
+        let bits = ISR_ALL << flag_shift(n);
+        if n < 4 {
+            dma.lifcr.write(|w| unsafe { w.bits(bits) });
+        } else {
+            dma.hifcr.write(|w| unsafe { w.bits(bits) });
+        }
+    }
+
+    /// Disables `stream` and waits for the controller to let go of it.
+    fn stop(&self, stream: usize) {
+        let st = &self.controller(stream).st[stream % STREAMS_PER_DMA];
+        st.cr.modify(|r, w| unsafe { w.bits(r.bits() & !CR_EN) });
+        while st.cr.read().bits() & CR_EN != 0 {}
+        self.clear_flags(stream);
+    }
+
+    /// Checks that `stream` is valid and claimed by `sender`, returning its
+    /// claim.
+    fn claim_mut(
+        &mut self,
+        sender: TaskId,
+        stream: u8,
+    ) -> Result<&mut Claim, DmaError> {
+        let stream = usize::from(stream);
+        match self.claims.get_mut(stream) {
+            None => Err(DmaError::BadStream),
+            Some(Some(c)) if c.owner == sender => Ok(c),
+            Some(_) => Err(DmaError::NotOwner),
+        }
+    }
+
+    /// Gives back any streams whose owners have restarted since claiming
+    /// them.
+    fn reclaim_stale(&mut self) {
+        for stream in 0..STREAMS {
+            let Some(c) = self.claims[stream] else {
+                continue;
+            };
+            if sys_refresh_task_id(c.owner) != c.owner {
+                ringbuf_entry!(Trace::Reclaim(stream as u8));
+                self.stop(stream);
+                self.claims[stream] = None;
+            }
+        }
+    }
+
+    /// Programs and enables `stream` for a transfer of `len` bytes to or from
+    /// its bounce buffer. A circular transfer keeps going around the buffer,
+    /// interrupting at each half, until stopped.
+    fn start(
+        &mut self,
+        stream: usize,
+        peripheral: u32,
+        width: u8,
+        len: usize,
+        write: bool,
+        circular: bool,
+    ) {
+        ringbuf_entry!(Trace::Start {
+            stream: stream as u8,
+            len: len as u16,
+            write,
+        });
+        let size = match width {
+            1 => 0b00,
+            2 => 0b01,
+            _ => 0b10,
+        };
+        let mut cr = CR_TCIE
+            | CR_TEIE
+            | CR_DMEIE
+            | CR_MINC
+            | size << CR_PSIZE_SHIFT
+            | size << CR_MSIZE_SHIFT;
+        if write {
+            cr |= CR_DIR_M2P;
+        }
+        if circular {
+            cr |= CR_CIRC | CR_HTIE;
+        }
+
+        let memory = self.bounce[stream].as_ptr() as u32;
+        let st = &self.controller(stream).st[stream % STREAMS_PER_DMA];
+        self.clear_flags(stream);
+        st.par.write(|w| unsafe { w.bits(peripheral) });
+        st.m0ar.write(|w| unsafe { w.bits(memory) });
+        st.ndtr
+            .write(|w| unsafe { w.bits((len / usize::from(width)) as u32) });
+        // Direct mode: the FIFO is bypassed, and each request moves one item.
+        st.fcr.write(|w| unsafe { w.bits(0) });
+        st.cr.write(|w| unsafe { w.bits(cr) });
+
+        // Make sure the bounce buffer contents are out before the stream can
+        // start reading them.
+        cortex_m::asm::dsb();
+        st.cr.write(|w| unsafe { w.bits(cr | CR_EN) });
+        sys_irq_control(STREAM_IRQS[stream], true);
+    }
+}
+
+/// Position of stream `n`'s flags within LISR/HISR (and the clear registers).
+fn flag_shift(n: usize) -> u32 {
+    [0, 6, 16, 22][n % 4]
+}
+
+/// Checks a transfer's peripheral address, width and length.
+fn check_transfer(
+    peripheral: u32,
+    width: u8,
+    len: usize,
+) -> Result<(), DmaError> {
+    if !matches!(width, 1 | 2 | 4) {
+        return Err(DmaError::BadWidth);
+    }
+    // Only peripheral registers are fair game; we're not going to let a client
+    // aim the controller at somebody else's RAM.
+    if !(0x4000_0000..0x6000_0000).contains(&peripheral)
+        || peripheral % u32::from(width) != 0
+    {
+        return Err(DmaError::BadAddress);
+    }
+    if len == 0 || len > MAX_TRANSFER || len % usize::from(width) != 0 {
+        return Err(DmaError::BadLength);
+    }
+    Ok(())
+}
+
+impl idl::InOrderDmaImpl for ServerImpl {
+    fn claim(
+        &mut self,
+        msg: &RecvMessage,
+        request: u8,
+        notification: u32,
+    ) -> Result<u8, RequestError<DmaError>> {
+        if request == 0 || request > MAX_REQUEST {
+            return Err(DmaError::BadRequest.into());
+        }
+        if !self.claims.iter().any(Option::is_none) {
+            self.reclaim_stale();
+        }
+        let stream = self
+            .claims
+            .iter()
+            .position(Option::is_none)
+            .ok_or(DmaError::NoStream)?;
+
+        ringbuf_entry!(Trace::Claim {
+            stream: stream as u8,
+            request,
+            owner: msg.sender,
+        });
+        self.dmamux.ccr[stream].write(|w| unsafe { w.bits(request.into()) });
+        self.claims[stream] = Some(Claim {
+            owner: msg.sender,
+            notification,
+            state: State::Idle,
+            read: false,
+            len: 0,
+            tail: 0,
+            halves: 0,
+        });
+        Ok(stream as u8)
+    }
+
+    fn release(
+        &mut self,
+        msg: &RecvMessage,
+        stream: u8,
+    ) -> Result<(), RequestError<DmaError>> {
+        self.claim_mut(msg.sender, stream)?;
+        ringbuf_entry!(Trace::Release(stream));
+        let stream = usize::from(stream);
+        self.stop(stream);
+        self.dmamux.ccr[stream].write(|w| unsafe { w.bits(0) });
+        self.claims[stream] = None;
+        Ok(())
+    }
+
+    fn start_write(
+        &mut self,
+        msg: &RecvMessage,
+        stream: u8,
+        peripheral: u32,
+        width: u8,
+        source: LenLimit<Leased<R, [u8]>, MAX_TRANSFER>,
+    ) -> Result<(), RequestError<DmaError>> {
+        let source = source.into_inner();
+        let claim = self.claim_mut(msg.sender, stream)?;
+        if claim.state.busy() {
+            return Err(DmaError::Busy.into());
+        }
+        let len = source.len();
+        check_transfer(peripheral, width, len)?;
+
+        let stream = usize::from(stream);
+        source
+            .read_range(0..len, &mut self.bounce[stream][..len])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        // Borrowck won't let us hold on to `claim` across that.
+        self.claims[stream] = Some(Claim {
+            state: State::Running,
+            read: false,
+            len,
+            ..self.claims[stream].unwrap()
+        });
+        self.start(stream, peripheral, width, len, true, false);
+        Ok(())
+    }
+
+    fn start_read(
+        &mut self,
+        msg: &RecvMessage,
+        stream: u8,
+        peripheral: u32,
+        width: u8,
+        len: u32,
+    ) -> Result<(), RequestError<DmaError>> {
+        let claim = self.claim_mut(msg.sender, stream)?;
+        if claim.state.busy() {
+            return Err(DmaError::Busy.into());
+        }
+        let len = len as usize;
+        check_transfer(peripheral, width, len)?;
+        claim.state = State::Running;
+        claim.read = true;
+        claim.len = len;
+
+        self.start(usize::from(stream), peripheral, width, len, false, false);
+        Ok(())
+    }
+
+    fn start_circular_read(
+        &mut self,
+        msg: &RecvMessage,
+        stream: u8,
+        peripheral: u32,
+        width: u8,
+    ) -> Result<(), RequestError<DmaError>> {
+        let claim = self.claim_mut(msg.sender, stream)?;
+        if claim.state.busy() {
+            return Err(DmaError::Busy.into());
+        }
+        check_transfer(peripheral, width, MAX_TRANSFER)?;
+        claim.state = State::Circular;
+        claim.read = true;
+        claim.len = MAX_TRANSFER;
+        claim.tail = 0;
+        claim.halves = 0;
+
+        ringbuf_entry!(Trace::Circular(stream));
+        let stream = usize::from(stream);
+        self.start(stream, peripheral, width, MAX_TRANSFER, false, true);
+        Ok(())
+    }
+
+    fn drain(
+        &mut self,
+        msg: &RecvMessage,
+        stream: u8,
+        dest: LenLimit<Leased<W, [u8]>, MAX_TRANSFER>,
+    ) -> Result<usize, RequestError<DmaError>> {
+        let dest = dest.into_inner();
+        let claim = *self.claim_mut(msg.sender, stream)?;
+        let stream = usize::from(stream);
+        match claim.state {
+            State::Circular => (),
+            State::Failed => {
+                self.claims[stream] = Some(Claim {
+                    state: State::Idle,
+                    ..claim
+                });
+                return Err(DmaError::TransferError.into());
+            }
+            _ => return Err(DmaError::Idle.into()),
+        }
+
+        // NDTR counts down the items left before the stream wraps; work out
+        // where in the buffer the next byte will land.
+        let st = &self.controller(stream).st[stream % STREAMS_PER_DMA];
+        let remaining = st.ndtr.read().bits() as usize;
+        let width = 1 << ((st.cr.read().bits() >> CR_PSIZE_SHIFT) & 0b11);
+        let head = (claim.len - remaining * width) % claim.len;
+
+        // Crossing three half-buffer boundaries means the stream has written
+        // at least a whole buffer past our tail.
+        if claim.halves >= 3 {
+            ringbuf_entry!(Trace::Overrun(stream as u8));
+            self.claims[stream] = Some(Claim {
+                tail: head,
+                halves: 0,
+                ..claim
+            });
+            return Err(DmaError::Overrun.into());
+        }
+
+        let available = (head + claim.len - claim.tail) % claim.len;
+        let n = available.min(dest.len());
+        let first = n.min(claim.len - claim.tail);
+        let buf = &self.bounce[stream];
+        dest.write_range(0..first, &buf[claim.tail..claim.tail + first])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        dest.write_range(first..n, &buf[..n - first])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+
+        self.claims[stream] = Some(Claim {
+            tail: (claim.tail + n) % claim.len,
+            halves: 0,
+            ..claim
+        });
+        Ok(n)
+    }
+
+    fn finish(
+        &mut self,
+        msg: &RecvMessage,
+        stream: u8,
+        dest: LenLimit<Leased<W, [u8]>, MAX_TRANSFER>,
+    ) -> Result<usize, RequestError<DmaError>> {
+        let dest = dest.into_inner();
+        let claim = *self.claim_mut(msg.sender, stream)?;
+        let stream = usize::from(stream);
+        match claim.state {
+            State::Running | State::Circular => {
+                return Err(DmaError::Busy.into())
+            }
+            State::Idle => return Err(DmaError::Idle.into()),
+            State::Failed => {
+                self.claims[stream] = Some(Claim {
+                    state: State::Idle,
+                    ..claim
+                });
+                return Err(DmaError::TransferError.into());
+            }
+            State::Done => (),
+        }
+
+        if claim.read {
+            if dest.len() < claim.len {
+                return Err(RequestError::Fail(ClientError::BadLease));
+            }
+            dest.write_range(0..claim.len, &self.bounce[stream][..claim.len])
+                .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        }
+        self.claims[stream] = Some(Claim {
+            state: State::Idle,
+            ..claim
+        });
+        Ok(claim.len)
+    }
+
+    fn abort(
+        &mut self,
+        msg: &RecvMessage,
+        stream: u8,
+    ) -> Result<(), RequestError<DmaError>> {
+        let claim = self.claim_mut(msg.sender, stream)?;
+        claim.state = State::Idle;
+        self.stop(usize::from(stream));
+        Ok(())
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        STREAM_IRQS.iter().fold(0, |mask, bits| mask | bits)
+    }
+
+    fn handle_notification(&mut self, bits: u32) {
+        for stream in 0..STREAMS {
+            if bits & STREAM_IRQS[stream] == 0 {
+                continue;
+            }
+            let flags = self.flags(stream);
+            ringbuf_entry!(Trace::Irq {
+                stream: stream as u8,
+                flags,
+            });
+
+            let Some(claim) = &mut self.claims[stream] else {
+                // Released while the interrupt was in flight.
+                self.clear_flags(stream);
+                continue;
+            };
+            let mut half = false;
+            match claim.state {
+                State::Running | State::Circular
+                    if flags & (ISR_TEIF | ISR_DMEIF) != 0 =>
+                {
+                    claim.state = State::Failed;
+                }
+                State::Running if flags & ISR_TCIF != 0 => {
+                    claim.state = State::Done;
+                }
+                State::Circular if flags & (ISR_HTIF | ISR_TCIF) != 0 => {
+                    // Both flags can be set if we were slow to get here.
+                    let crossed = u8::from(flags & ISR_HTIF != 0)
+                        + u8::from(flags & ISR_TCIF != 0);
+                    claim.halves = claim.halves.saturating_add(crossed);
+                    half = true;
+                }
+                _ => (),
+            }
+            let (owner, notification, state) =
+                (claim.owner, claim.notification, claim.state);
+
+            if state.busy() {
+                // A circular stream keeps going, and anything else is
+                // spurious or a flag we don't care about; keep waiting.
+                self.clear_flags(stream);
+                sys_irq_control(STREAM_IRQS[stream], true);
+                if half {
+                    sys_post(sys_refresh_task_id(owner), notification);
+                }
+            } else {
+                self.stop(stream);
+                sys_post(sys_refresh_task_id(owner), notification);
+            }
+        }
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let sys = Sys::from(SYS.get_task_id());
+    sys.enable_clock(Peripheral::Dma1);
+    sys.enable_clock(Peripheral::Dma2);
+    sys.leave_reset(Peripheral::Dma1);
+    sys.leave_reset(Peripheral::Dma2);
+
+    let bounce = mutable_statics::mutable_statics! {
+        static mut BOUNCE: [[u8; MAX_TRANSFER]; STREAMS] =
+            [|| [0; MAX_TRANSFER]; _];
+    };
+
+    let mut server = ServerImpl {
+        dma1: unsafe { &*device::DMA1::ptr() },
+        dma2: unsafe { &*device::DMA2::ptr() },
+        dmamux: unsafe { &*device::DMAMUX1::ptr() },
+        claims: [None; STREAMS],
+        bounce,
+    };
+    // Whatever a previous incarnation of this task left running, stop it.
+    for stream in 0..STREAMS {
+        server.stop(stream);
+        server.dmamux.ccr[stream].write(|w| unsafe { w.bits(0) });
+    }
+
+    let mut buffer = [0; idl::INCOMING_SIZE];
+    loop {
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
+
+mod idl {
+    use drv_stm32h7_dma_api::DmaError;
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
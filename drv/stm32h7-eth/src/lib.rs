@@ -20,6 +20,7 @@ use stm32h7::stm32h743 as device;
 #[cfg(feature = "h753")]
 use stm32h7::stm32h753 as device;
 
+pub mod phy;
 pub mod ring;
 
 use crate::ring::BUFSZ;
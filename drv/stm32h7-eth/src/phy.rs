@@ -0,0 +1,270 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Ethernet PHY management.
+//!
+//! PHYs differ in how they're reached (over our SMI, or through the registers
+//! of a switch that contains them) and in where they report what
+//! autonegotiation settled on, but they all have the IEEE 802.3 clause 22
+//! basic registers. `PhyRegs` is how we reach a PHY's registers, and `Phy` is
+//! what we want to know from it, with implementations for the PHYs we use.
+//! `LinkMonitor` follows one PHY's link from poll to poll, turning its state
+//! into up and down events and counting flaps.
+
+use crate::Ethernet;
+
+/// Basic mode control register
+pub const BMCR: u8 = 0;
+/// Basic mode status register
+pub const BMSR: u8 = 1;
+/// Autonegotiation advertisement register
+pub const ANAR: u8 = 4;
+/// Autonegotiation link partner ability register
+pub const ANLPAR: u8 = 5;
+
+const BMCR_SPEED_100: u16 = 1 << 13;
+const BMCR_AN_ENABLE: u16 = 1 << 12;
+const BMCR_AN_RESTART: u16 = 1 << 9;
+const BMCR_FULL_DUPLEX: u16 = 1 << 8;
+const BMCR_SPEED_1000: u16 = 1 << 6;
+
+const BMSR_AN_COMPLETE: u16 = 1 << 5;
+const BMSR_LINK_UP: u16 = 1 << 2;
+
+const AN_100_FULL: u16 = 1 << 8;
+const AN_100_HALF: u16 = 1 << 7;
+const AN_10_FULL: u16 = 1 << 6;
+
+/// Access to one PHY's registers.
+pub trait PhyRegs {
+    type Error;
+
+    fn read(&self, reg: u8) -> Result<u16, Self::Error>;
+    fn write(&self, reg: u8, value: u16) -> Result<(), Self::Error>;
+}
+
+/// A PHY on our SMI, at address `addr`.
+pub struct SmiPhy<'a> {
+    pub eth: &'a Ethernet,
+    pub addr: u8,
+}
+
+impl PhyRegs for SmiPhy<'_> {
+    type Error = core::convert::Infallible;
+
+    fn read(&self, reg: u8) -> Result<u16, Self::Error> {
+        Ok(self.eth.smi_read(self.addr, reg))
+    }
+
+    fn write(&self, reg: u8, value: u16) -> Result<(), Self::Error> {
+        self.eth.smi_write(self.addr, reg, value);
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Speed {
+    Mbps10,
+    Mbps100,
+    Mbps1000,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct LinkStatus {
+    pub up: bool,
+    /// Whether the link has gone down since the PHY was last asked, even if
+    /// it has since come back up
+    pub went_down: bool,
+    /// Whether autonegotiation is enabled and has completed; if so, `speed`
+    /// and `full_duplex` are what it settled on, and otherwise they're what
+    /// the PHY has been told to use.
+    pub autoneg_complete: bool,
+    pub speed: Speed,
+    pub full_duplex: bool,
+}
+
+pub trait Phy {
+    type Error;
+
+    fn link_status(&self) -> Result<LinkStatus, Self::Error>;
+
+    /// Enables autonegotiation, if it wasn't already, and starts it over.
+    fn restart_autoneg(&self) -> Result<(), Self::Error>;
+}
+
+/// A PHY that reports only through the clause 22 basic registers. The result
+/// of autonegotiation is worked out from what both ends advertised, which
+/// covers 10 and 100 Mb/s; use a specific implementation for a gigabit PHY.
+///
+/// This suits the KSZ8463's PHYs, whose basic registers the switch exposes.
+pub struct Generic<R>(pub R);
+
+impl<R: PhyRegs> Phy for Generic<R> {
+    type Error = R::Error;
+
+    fn link_status(&self) -> Result<LinkStatus, Self::Error> {
+        let mut status = basic_status(&self.0)?;
+        if status.autoneg_complete {
+            let common = self.0.read(ANAR)? & self.0.read(ANLPAR)?;
+            (status.speed, status.full_duplex) = if common & AN_100_FULL != 0 {
+                (Speed::Mbps100, true)
+            } else if common & AN_100_HALF != 0 {
+                (Speed::Mbps100, false)
+            } else if common & AN_10_FULL != 0 {
+                (Speed::Mbps10, true)
+            } else {
+                (Speed::Mbps10, false)
+            };
+        }
+        Ok(status)
+    }
+
+    fn restart_autoneg(&self) -> Result<(), Self::Error> {
+        restart_autoneg(&self.0)
+    }
+}
+
+/// Microchip LAN87xx (e.g. the LAN8742A on Nucleo boards), which reports the
+/// autonegotiation result in its special control/status register.
+pub struct Lan87xx<R>(pub R);
+
+impl<R: PhyRegs> Lan87xx<R> {
+    const SPECIAL_STATUS: u8 = 31;
+}
+
+impl<R: PhyRegs> Phy for Lan87xx<R> {
+    type Error = R::Error;
+
+    fn link_status(&self) -> Result<LinkStatus, Self::Error> {
+        let mut status = basic_status(&self.0)?;
+        if status.autoneg_complete {
+            // Bits 4:2 are the speed indication: bit 4 is full duplex, and
+            // bits 3:2 are 0b01 for 10 Mb/s and 0b10 for 100 Mb/s.
+            let r = self.0.read(Self::SPECIAL_STATUS)?;
+            status.full_duplex = r & (1 << 4) != 0;
+            status.speed = match (r >> 2) & 0b11 {
+                0b10 => Speed::Mbps100,
+                _ => Speed::Mbps10,
+            };
+        }
+        Ok(status)
+    }
+
+    fn restart_autoneg(&self) -> Result<(), Self::Error> {
+        restart_autoneg(&self.0)
+    }
+}
+
+/// Microsemi VSC85xx, which reports the autonegotiation result in its
+/// auxiliary control and status register.
+pub struct Vsc85xx<R>(pub R);
+
+impl<R: PhyRegs> Vsc85xx<R> {
+    const AUX_STATUS: u8 = 28;
+    /// Extended page access register; the registers above are on page 0.
+    const PAGE: u8 = 31;
+}
+
+impl<R: PhyRegs> Phy for Vsc85xx<R> {
+    type Error = R::Error;
+
+    fn link_status(&self) -> Result<LinkStatus, Self::Error> {
+        // Others may have left us on another page.
+        self.0.write(Self::PAGE, 0)?;
+        let mut status = basic_status(&self.0)?;
+        if status.autoneg_complete {
+            // Bit 5 is full duplex, and bits 4:3 the speed.
+            let r = self.0.read(Self::AUX_STATUS)?;
+            status.full_duplex = r & (1 << 5) != 0;
+            status.speed = match (r >> 3) & 0b11 {
+                0b00 => Speed::Mbps10,
+                0b01 => Speed::Mbps100,
+                _ => Speed::Mbps1000,
+            };
+        }
+        Ok(status)
+    }
+
+    fn restart_autoneg(&self) -> Result<(), Self::Error> {
+        self.0.write(Self::PAGE, 0)?;
+        restart_autoneg(&self.0)
+    }
+}
+
+/// Reads the link state from the basic registers, with the speed and duplex
+/// from the control register, which are right unless autonegotiation has
+/// completed.
+fn basic_status<R: PhyRegs>(r: &R) -> Result<LinkStatus, R::Error> {
+    let bmcr = r.read(BMCR)?;
+    // Link status latches low until read, so the first read tells us whether
+    // the link has gone down since we last looked, and the second whether
+    // it's up now.
+    let latched = r.read(BMSR)?;
+    let bmsr = r.read(BMSR)?;
+
+    let speed = if bmcr & BMCR_SPEED_1000 != 0 {
+        Speed::Mbps1000
+    } else if bmcr & BMCR_SPEED_100 != 0 {
+        Speed::Mbps100
+    } else {
+        Speed::Mbps10
+    };
+    Ok(LinkStatus {
+        up: bmsr & BMSR_LINK_UP != 0,
+        went_down: latched & BMSR_LINK_UP == 0,
+        autoneg_complete: bmcr & BMCR_AN_ENABLE != 0
+            && bmsr & BMSR_AN_COMPLETE != 0,
+        speed,
+        full_duplex: bmcr & BMCR_FULL_DUPLEX != 0,
+    })
+}
+
+fn restart_autoneg<R: PhyRegs>(r: &R) -> Result<(), R::Error> {
+    let bmcr = r.read(BMCR)?;
+    r.write(BMCR, bmcr | BMCR_AN_ENABLE | BMCR_AN_RESTART)
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LinkEvent {
+    Up,
+    Down,
+    /// The link went down and came back up between polls.
+    Bounced,
+}
+
+/// Follows one PHY's link from poll to poll.
+#[derive(Copy, Clone, Default)]
+pub struct LinkMonitor {
+    last: Option<LinkStatus>,
+    flaps: u32,
+}
+
+impl LinkMonitor {
+    /// Takes in the PHY's latest status, and returns what happened to the
+    /// link since the last.
+    pub fn update(&mut self, status: LinkStatus) -> Option<LinkEvent> {
+        let was_up = self.last.map(|s| s.up).unwrap_or(false);
+        self.last = Some(status);
+        let event = match (was_up, status.up) {
+            (false, true) => LinkEvent::Up,
+            (true, false) => LinkEvent::Down,
+            (true, true) if status.went_down => LinkEvent::Bounced,
+            _ => return None,
+        };
+        if event != LinkEvent::Up {
+            self.flaps = self.flaps.wrapping_add(1);
+        }
+        Some(event)
+    }
+
+    /// The status last reported, if any.
+    pub fn last(&self) -> Option<LinkStatus> {
+        self.last
+    }
+
+    /// How many times the link has gone down after being up.
+    pub fn flaps(&self) -> u32 {
+        self.flaps
+    }
+}
@@ -0,0 +1,164 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::io::Write;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct TaskConfig {
+    /// Which FDCAN to use, "fdcan1" or "fdcan2"
+    instance: String,
+    /// Frequency of the FDCAN kernel clock
+    clock_hz: u32,
+    /// Nominal (arbitration phase) bit rate
+    bit_rate: u32,
+    /// Data phase bit rate; if given, CAN FD is enabled
+    #[serde(default)]
+    data_bit_rate: Option<u32>,
+    pins: Vec<PinConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct PinConfig {
+    port: char,
+    pins: Vec<u8>,
+    af: u8,
+}
+
+/// A bit timing, in units of the prescaled clock (time quanta).
+struct Timing {
+    prescaler: u32,
+    seg1: u32,
+    seg2: u32,
+}
+
+/// Finds the timing with the most time quanta per bit (and so the finest
+/// control of the sample point) that hits `bit_rate` exactly, sampling at
+/// 87.5%, as CiA 601 recommends, or as near as the segment limits allow.
+fn timing(
+    clock_hz: u32,
+    bit_rate: u32,
+    max_prescaler: u32,
+    max_seg1: u32,
+    max_seg2: u32,
+) -> Option<Timing> {
+    (1..=max_prescaler).find_map(|prescaler| {
+        let div = prescaler.checked_mul(bit_rate)?;
+        if clock_hz % div != 0 {
+            return None;
+        }
+        let quanta = clock_hz / div;
+        // One quantum goes to the sync segment.
+        let seg2 = (quanta / 8).clamp(1, max_seg2);
+        let seg1 = quanta.checked_sub(1 + seg2)?;
+        (1..=max_seg1).contains(&seg1).then_some(Timing {
+            prescaler,
+            seg1,
+            seg2,
+        })
+    })
+}
+
+fn generate_can_config(config: &TaskConfig) -> Result<()> {
+    let (base, ram_offset) = match config.instance.as_str() {
+        "fdcan1" => (0x4000_a000u32, 0),
+        // Each instance gets half of the message RAM.
+        "fdcan2" => (0x4000_a400, 0x1400),
+        _ => bail!("unknown FDCAN {:?}", config.instance),
+    };
+    if config.pins.is_empty() {
+        bail!("no pins configured for {}", config.instance);
+    }
+
+    let Some(nominal) = timing(config.clock_hz, config.bit_rate, 512, 256, 128)
+    else {
+        bail!(
+            "can't make {} bit/s from a {} Hz clock",
+            config.bit_rate,
+            config.clock_hz
+        );
+    };
+    let nbtp = (nominal.seg2 - 1) << 25
+        | (nominal.prescaler - 1) << 16
+        | (nominal.seg1 - 1) << 8
+        | (nominal.seg2 - 1);
+
+    let out = build_util::out_dir().join("can_config.rs");
+    let mut file = std::fs::File::create(out)?;
+    writeln!(file, "pub const BASE: usize = {base:#x};")?;
+    writeln!(file, "pub const RAM_OFFSET: usize = {ram_offset:#x};")?;
+    writeln!(file, "pub const NBTP: u32 = {nbtp:#x};")?;
+
+    match config.data_bit_rate {
+        Some(rate) => {
+            let Some(data) = timing(config.clock_hz, rate, 32, 32, 16) else {
+                bail!(
+                    "can't make {rate} bit/s from a {} Hz clock",
+                    config.clock_hz
+                );
+            };
+            let mut dbtp = (data.prescaler - 1) << 16
+                | (data.seg1 - 1) << 8
+                | (data.seg2 - 1) << 4
+                | (data.seg2 - 1);
+            // At high data rates, the transceiver's loop delay is a good
+            // part of a bit, and the controller has to allow for it when
+            // checking what it sent; we put the secondary sample point
+            // where the primary one would be.
+            let mut tdcr = 0;
+            if rate > 1_000_000 {
+                dbtp |= 1 << 23;
+                let offset = data.prescaler * (data.seg1 + 1);
+                if offset > 127 {
+                    bail!("data bit rate {rate} needs a smaller prescaler");
+                }
+                tdcr = offset << 8;
+            }
+            writeln!(file, "pub const DBTP: Option<u32> = Some({dbtp:#x});")?;
+            writeln!(file, "pub const TDCR: u32 = {tdcr:#x};")?;
+        }
+        None => {
+            writeln!(file, "pub const DBTP: Option<u32> = None;")?;
+            writeln!(file, "pub const TDCR: u32 = 0;")?;
+        }
+    }
+
+    writeln!(file, "pub const PINS: &[(PinSet, Alternate)] = &[")?;
+    for pin in &config.pins {
+        if pin.pins.is_empty() {
+            bail!("empty pin list for port {}", pin.port);
+        }
+        let mut set = format!("Port::{}", pin.port.to_ascii_uppercase());
+        for (i, n) in pin.pins.iter().enumerate() {
+            let f = if i == 0 { "pin" } else { "and_pin" };
+            set.push_str(&format!(".{f}({n})"));
+        }
+        writeln!(file, "    ({set}, Alternate::AF{}),", pin.af)?;
+    }
+    writeln!(file, "];")?;
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    idol::Generator::new()
+        .with_counters(
+            idol::CounterSettings::default().with_server_counters(false),
+        )
+        .build_server_support(
+            "../../idl/can.idol",
+            "server_stub.rs",
+            idol::server::ServerStyle::InOrder,
+        )?;
+
+    build_util::build_notifications()?;
+
+    let task_config = build_util::task_config::<TaskConfig>()?;
+    generate_can_config(&task_config)?;
+
+    Ok(())
+}
@@ -0,0 +1,302 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Register-level access to an FDCAN (a Bosch M_CAN) and its slice of the
+//! message RAM; offsets and bits are from RM0433 section 56.
+
+use crate::config;
+use drv_can_api::flags;
+
+/// The message RAM, shared by both instances, which must be accessed a word
+/// at a time.
+const RAM_BASE: usize = 0x4000_AC00;
+
+const DBTP: usize = 0x0C;
+const CCCR: usize = 0x18;
+const NBTP: usize = 0x1C;
+const ECR: usize = 0x40;
+const PSR: usize = 0x44;
+const TDCR: usize = 0x48;
+pub const IR: usize = 0x50;
+const IE: usize = 0x54;
+const ILE: usize = 0x5C;
+const GFC: usize = 0x80;
+const SIDFC: usize = 0x84;
+const XIDFC: usize = 0x88;
+const RXF0C: usize = 0xA0;
+const RXF0S: usize = 0xA4;
+const RXF0A: usize = 0xA8;
+const RXESC: usize = 0xBC;
+const TXBC: usize = 0xC0;
+const TXFQS: usize = 0xC4;
+const TXESC: usize = 0xC8;
+const TXBAR: usize = 0xD0;
+const TXBTIE: usize = 0xE0;
+
+const CCCR_INIT: u32 = 1 << 0;
+const CCCR_CCE: u32 = 1 << 1;
+const CCCR_FDOE: u32 = 1 << 8;
+const CCCR_BRSE: u32 = 1 << 9;
+
+// IR and IE.
+pub const RF0N: u32 = 1 << 0;
+pub const RF0L: u32 = 1 << 3;
+pub const TC: u32 = 1 << 9;
+pub const BO: u32 = 1 << 25;
+
+const ILE_EINT0: u32 = 1 << 0;
+
+/// Reject frames that match no filter, and all remote frames.
+const GFC_REJECT_ALL: u32 = 0b10 << 4 | 0b10 << 2 | 1 << 1 | 1 << 0;
+
+const PSR_EP: u32 = 1 << 5;
+const PSR_BO: u32 = 1 << 7;
+const ECR_RP: u32 = 1 << 15;
+
+const TXBC_TFQM: u32 = 1 << 30;
+const TXFQS_TFQF: u32 = 1 << 21;
+
+/// 64-byte data fields, for both RX FIFO 0 and the TX buffers.
+const ELEMENT_64: u32 = 0b111;
+
+pub const STD_FILTERS: usize = 16;
+pub const EXT_FILTERS: usize = 8;
+const RX_FIFO_DEPTH: usize = 16;
+/// Hardware TX buffers. We keep this small, so that a frame queued at a high
+/// priority doesn't wait behind many already handed to the controller.
+const TX_BUFFERS: usize = 2;
+
+/// Words in an RX or TX element: a two word header, and 64 bytes of data.
+const ELEMENT_WORDS: usize = 2 + 16;
+
+// Word offsets of each section within our slice of the message RAM.
+const STD_FILTER_START: usize = 0;
+const EXT_FILTER_START: usize = STD_FILTER_START + STD_FILTERS;
+const RX_FIFO_START: usize = EXT_FILTER_START + 2 * EXT_FILTERS;
+const TX_BUFFER_START: usize = RX_FIFO_START + RX_FIFO_DEPTH * ELEMENT_WORDS;
+
+const ID_STD_SHIFT: u32 = 18;
+const ID_EXT_MASK: u32 = 0x1FFF_FFFF;
+const XTD: u32 = 1 << 30;
+const FDF: u32 = 1 << 21;
+const BRS: u32 = 1 << 20;
+const DLC_SHIFT: u32 = 16;
+
+/// A frame as stored in a message RAM element.
+pub struct Element {
+    pub id: u32,
+    pub flags: u8,
+    pub dlc: u8,
+    /// The index of the filter that matched (within its list)
+    pub filter: u8,
+}
+
+pub struct Fdcan;
+
+impl Fdcan {
+    fn read(&self, offset: usize) -> u32 {
+        unsafe {
+            core::ptr::read_volatile((config::BASE + offset) as *const u32)
+        }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe {
+            core::ptr::write_volatile(
+                (config::BASE + offset) as *mut u32,
+                value,
+            )
+        }
+    }
+
+    fn modify(&self, offset: usize, f: impl FnOnce(u32) -> u32) {
+        self.write(offset, f(self.read(offset)));
+    }
+
+    /// Byte offset of word `word` of our slice within the message RAM.
+    fn ram_offset(word: usize) -> usize {
+        config::RAM_OFFSET + 4 * word
+    }
+
+    fn ram_read(&self, word: usize) -> u32 {
+        let addr = RAM_BASE + Self::ram_offset(word);
+        unsafe { core::ptr::read_volatile(addr as *const u32) }
+    }
+
+    fn ram_write(&self, word: usize, value: u32) {
+        let addr = RAM_BASE + Self::ram_offset(word);
+        unsafe { core::ptr::write_volatile(addr as *mut u32, value) }
+    }
+
+    pub fn status(&self, offset: usize) -> u32 {
+        self.read(offset)
+    }
+
+    /// Configures the controller, with every filter disabled, and starts it.
+    /// The caller must have turned on the peripheral's clock.
+    pub fn init(&self) {
+        self.write(CCCR, CCCR_INIT);
+        while self.read(CCCR) & CCCR_INIT == 0 {}
+        self.write(CCCR, CCCR_INIT | CCCR_CCE);
+
+        self.write(NBTP, config::NBTP);
+        if let Some(dbtp) = config::DBTP {
+            self.write(DBTP, dbtp);
+            self.write(TDCR, config::TDCR);
+            self.modify(CCCR, |r| r | CCCR_FDOE | CCCR_BRSE);
+        }
+
+        for i in 0..STD_FILTERS {
+            self.set_std_filter(i, None);
+        }
+        for i in 0..EXT_FILTERS {
+            self.set_ext_filter(i, None);
+        }
+        let section = |words: usize| Self::ram_offset(words) as u32 & 0xFFFC;
+        self.write(GFC, GFC_REJECT_ALL);
+        self.write(
+            SIDFC,
+            (STD_FILTERS as u32) << 16 | section(STD_FILTER_START),
+        );
+        self.write(
+            XIDFC,
+            (EXT_FILTERS as u32) << 16 | section(EXT_FILTER_START),
+        );
+        self.write(
+            RXF0C,
+            (RX_FIFO_DEPTH as u32) << 16 | section(RX_FIFO_START),
+        );
+        self.write(RXESC, ELEMENT_64);
+        self.write(TXESC, ELEMENT_64);
+        self.write(
+            TXBC,
+            TXBC_TFQM | (TX_BUFFERS as u32) << 24 | section(TX_BUFFER_START),
+        );
+        self.write(TXBTIE, (1 << TX_BUFFERS) - 1);
+
+        self.write(IR, !0);
+        self.write(IE, RF0N | RF0L | TC | BO);
+        self.write(ILE, ILE_EINT0);
+
+        // Leaving initialization also clears CCE.
+        self.start();
+    }
+
+    /// Takes the controller out of initialization, which after a bus-off
+    /// starts the recovery sequence.
+    pub fn start(&self) {
+        self.modify(CCCR, |r| r & !CCCR_INIT);
+    }
+
+    pub fn clear_interrupts(&self, bits: u32) {
+        self.write(IR, bits);
+    }
+
+    /// Sets standard filter element `i` to match IDs equal to `id` in the
+    /// bits of `mask`, or disables it.
+    pub fn set_std_filter(&self, i: usize, filter: Option<(u32, u32)>) {
+        let element = match filter {
+            // Classic filter, storing matches in RX FIFO 0.
+            Some((id, mask)) => {
+                0b10 << 30 | 0b001 << 27 | (id & 0x7FF) << 16 | mask & 0x7FF
+            }
+            None => 0,
+        };
+        self.ram_write(STD_FILTER_START + i, element);
+    }
+
+    /// Sets extended filter element `i`, as for `set_std_filter`.
+    pub fn set_ext_filter(&self, i: usize, filter: Option<(u32, u32)>) {
+        let word = EXT_FILTER_START + 2 * i;
+        match filter {
+            Some((id, mask)) => {
+                // Write the type word first, so the element is never live
+                // with a stale one.
+                self.ram_write(word + 1, 0b10 << 30 | mask & ID_EXT_MASK);
+                self.ram_write(word, 0b001 << 29 | id & ID_EXT_MASK);
+            }
+            None => self.ram_write(word, 0),
+        }
+    }
+
+    /// Reads the oldest frame from RX FIFO 0 into `data`, if there is one.
+    pub fn receive(&self, data: &mut [u8; 64]) -> Option<Element> {
+        let status = self.read(RXF0S);
+        if status & 0x7F == 0 {
+            return None;
+        }
+        let index = ((status >> 8) & 0x3F) as usize;
+        let word = RX_FIFO_START + index * ELEMENT_WORDS;
+        let r0 = self.ram_read(word);
+        let r1 = self.ram_read(word + 1);
+        for (i, chunk) in data.chunks_mut(4).enumerate() {
+            chunk.copy_from_slice(&self.ram_read(word + 2 + i).to_le_bytes());
+        }
+        self.write(RXF0A, index as u32);
+
+        let mut flags = 0;
+        let id = if r0 & XTD != 0 {
+            flags |= flags::EXTENDED;
+            r0 & ID_EXT_MASK
+        } else {
+            (r0 >> ID_STD_SHIFT) & 0x7FF
+        };
+        if r1 & FDF != 0 {
+            flags |= flags::FD;
+        }
+        if r1 & BRS != 0 {
+            flags |= flags::BIT_RATE_SWITCH;
+        }
+        Some(Element {
+            id,
+            flags,
+            dlc: ((r1 >> DLC_SHIFT) & 0xF) as u8,
+            filter: ((r1 >> 24) & 0x7F) as u8,
+        })
+    }
+
+    /// Whether the controller has room for another frame to transmit.
+    pub fn can_transmit(&self) -> bool {
+        self.read(TXFQS) & TXFQS_TFQF == 0
+    }
+
+    /// Hands a frame to the controller; `can_transmit` must be true.
+    pub fn transmit(&self, frame: &Element, data: &[u8]) {
+        let index = ((self.read(TXFQS) >> 16) & 0x1F) as usize;
+        let word = TX_BUFFER_START + index * ELEMENT_WORDS;
+
+        let t0 = if frame.flags & flags::EXTENDED != 0 {
+            XTD | frame.id & ID_EXT_MASK
+        } else {
+            (frame.id & 0x7FF) << ID_STD_SHIFT
+        };
+        let mut t1 = u32::from(frame.dlc) << DLC_SHIFT;
+        if frame.flags & flags::FD != 0 {
+            t1 |= FDF;
+            if frame.flags & flags::BIT_RATE_SWITCH != 0 {
+                t1 |= BRS;
+            }
+        }
+        self.ram_write(word, t0);
+        self.ram_write(word + 1, t1);
+        for (i, chunk) in data.chunks(4).enumerate() {
+            let mut bytes = [0; 4];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            self.ram_write(word + 2 + i, u32::from_le_bytes(bytes));
+        }
+        self.write(TXBAR, 1 << index);
+    }
+
+    pub fn is_bus_off(&self) -> bool {
+        self.read(PSR) & PSR_BO != 0
+    }
+
+    /// Returns the transmit and receive error counts, and whether the
+    /// controller is error passive.
+    pub fn errors(&self) -> (u8, u8, bool) {
+        let ecr = self.read(ECR);
+        let passive = self.read(PSR) & PSR_EP != 0 || ecr & ECR_RP != 0;
+        (ecr as u8, (ecr >> 8) as u8 & 0x7F, passive)
+    }
+}
@@ -0,0 +1,432 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! CAN / CAN FD server for the STM32H7's FDCAN controllers.
+//!
+//! This task owns one FDCAN, and shares it between any number of clients.
+//!
+//! Clients register ID filters, which we program into the controller's
+//! acceptance filters, so frames that nobody wants are rejected in hardware.
+//! Each frame the controller accepts records which filter matched it, and so
+//! which client it's for; we hold it for that client and post the filter's
+//! notification. To keep one slow client from hurting the others, we hold at
+//! most `FRAMES_PER_FILTER` frames for each filter (and `POOL_SIZE` in all),
+//! dropping and counting any beyond that.
+//!
+//! Frames to transmit are queued in order of the priority the client gives,
+//! and handed to the controller as it has room. The controller itself holds
+//! only a couple at a time, sending the lowest ID first, so a high-priority
+//! frame never waits behind more than that.
+//!
+//! If the controller goes bus-off, we start its recovery straight away; it
+//! rejoins the bus once it has seen 128 runs of 11 recessive bits.
+//! Transmissions fail with `BusOff` until then.
+//!
+//! The task is configured with the FDCAN, its kernel clock, bit rates, and
+//! pins; giving a data bit rate enables CAN FD:
+//!
+//! ```toml
+//! [tasks.can.config]
+//! instance = "fdcan1"
+//! clock-hz = 80_000_000
+//! bit-rate = 500_000
+//! data-bit-rate = 2_000_000
+//! pins = [{ port = "D", pins = [0, 1], af = 9 }]
+//! ```
+//!
+//! It needs the controller's first interrupt line (`it0`) mapped to a
+//! notification named `can-irq`, the FDCAN's registers and the message RAM
+//! (`fdcan_ram`) in its memory map, and a task slot for `sys`.
+
+#![no_std]
+#![no_main]
+
+mod fdcan;
+
+use drv_can_api::{
+    flags, BusStatus, CanError, Frame, FRAMES_PER_FILTER, MAX_DATA,
+};
+use drv_stm32xx_sys_api::{
+    Alternate, OutputType, Peripheral, PinSet, Port, Pull, Speed, Sys,
+};
+use fdcan::{Element, Fdcan, EXT_FILTERS, STD_FILTERS};
+use heapless::Vec;
+use idol_runtime::{
+    ClientError, Leased, LenLimit, NotificationHandler, RequestError, R,
+};
+use ringbuf::*;
+use userlib::*;
+
+task_slot!(SYS, sys);
+
+/// Total number of received frames we'll hold for clients.
+const POOL_SIZE: usize = 16;
+
+/// Number of frames we'll queue for transmission.
+const TX_QUEUE_SIZE: usize = 8;
+
+/// Filters are numbered with the standard filters first.
+const FILTERS: usize = STD_FILTERS + EXT_FILTERS;
+
+#[derive(Copy, Clone, PartialEq, counters::Count)]
+enum Event {
+    Received,
+    /// A frame was dropped because its filter already had as many frames
+    /// waiting as we'll hold.
+    FilterFull,
+    /// A frame was dropped because we were holding as many frames as we can.
+    PoolFull,
+    /// The controller's receive FIFO overflowed.
+    FifoOverrun,
+    /// A frame matched a filter that's since been removed.
+    Orphaned,
+    Transmitted,
+    BusOff,
+}
+
+counters::counters!(Event);
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    FilterAdded { filter: u8, id: u32, mask: u32 },
+    FilterRemoved(u8),
+    Stale(u8),
+    BusOff,
+}
+
+ringbuf!(Trace, 32, Trace::None);
+
+#[derive(Copy, Clone)]
+struct Filter {
+    owner: TaskId,
+    notification: u32,
+    /// Frames waiting for the owner.
+    waiting: u8,
+}
+
+/// A frame waiting for transmission.
+struct Pending {
+    priority: u8,
+    /// Order of queueing, so frames of equal priority go in order.
+    sequence: u32,
+    frame: Element,
+    len: u8,
+    data: [u8; MAX_DATA],
+}
+
+struct ServerImpl {
+    fdcan: Fdcan,
+    filters: [Option<Filter>; FILTERS],
+    pool: Vec<Frame, POOL_SIZE>,
+    tx_queue: Vec<Pending, TX_QUEUE_SIZE>,
+    sequence: u32,
+}
+
+impl ServerImpl {
+    /// Frees any filters left behind by an earlier incarnation of `task`.
+    fn reclaim_stale(&mut self, task: TaskId) {
+        for i in 0..FILTERS {
+            let Some(f) = self.filters[i] else {
+                continue;
+            };
+            if f.owner.index() == task.index() && f.owner != task {
+                ringbuf_entry!(Trace::Stale(i as u8));
+                self.free_filter(i);
+            }
+        }
+    }
+
+    fn free_filter(&mut self, i: usize) {
+        if i < STD_FILTERS {
+            self.fdcan.set_std_filter(i, None);
+        } else {
+            self.fdcan.set_ext_filter(i - STD_FILTERS, None);
+        }
+        self.filters[i] = None;
+        self.pool.retain(|f| usize::from(f.filter) != i);
+    }
+
+    /// Moves frames from the controller to the clients they're for.
+    fn collect(&mut self) {
+        let mut data = [0u8; MAX_DATA];
+        while let Some(element) = self.fdcan.receive(&mut data) {
+            let filter = if element.flags & flags::EXTENDED != 0 {
+                STD_FILTERS + usize::from(element.filter)
+            } else {
+                usize::from(element.filter)
+            };
+            let Some(Some(f)) = self.filters.get_mut(filter) else {
+                counters::count!(Event::Orphaned);
+                continue;
+            };
+            if usize::from(f.waiting) >= FRAMES_PER_FILTER {
+                counters::count!(Event::FilterFull);
+                continue;
+            }
+            let len =
+                drv_can_api::len(element.dlc, element.flags & flags::FD != 0);
+            let frame = Frame {
+                id: element.id,
+                flags: element.flags,
+                len: len as u8,
+                filter: filter as u8,
+                _reserved: 0,
+                data,
+            };
+            if self.pool.push(frame).is_err() {
+                counters::count!(Event::PoolFull);
+                continue;
+            }
+            counters::count!(Event::Received);
+            f.waiting += 1;
+            sys_post(f.owner, f.notification);
+        }
+    }
+
+    /// Hands queued frames to the controller, highest priority first, for
+    /// as long as it has room.
+    fn feed(&mut self) {
+        while self.fdcan.can_transmit() {
+            let best = self
+                .tx_queue
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, p)| (p.priority, u32::MAX - p.sequence))
+                .map(|(i, _)| i);
+            let Some(i) = best else {
+                break;
+            };
+            let p = self.tx_queue.swap_remove(i);
+            self.fdcan.transmit(&p.frame, &p.data[..usize::from(p.len)]);
+            counters::count!(Event::Transmitted);
+        }
+    }
+}
+
+impl idl::InOrderCanImpl for ServerImpl {
+    fn add_filter(
+        &mut self,
+        msg: &RecvMessage,
+        id: u32,
+        mask: u32,
+        extended: bool,
+        notification: u32,
+    ) -> Result<u8, RequestError<CanError>> {
+        self.reclaim_stale(msg.sender);
+
+        let (range, limit) = if extended {
+            (STD_FILTERS..FILTERS, 0x1FFF_FFFF)
+        } else {
+            (0..STD_FILTERS, 0x7FF)
+        };
+        if id > limit || mask > limit {
+            return Err(CanError::BadId.into());
+        }
+        let i = range
+            .find(|&i| self.filters[i].is_none())
+            .ok_or(CanError::NoFilters)?;
+
+        self.filters[i] = Some(Filter {
+            owner: msg.sender,
+            notification,
+            waiting: 0,
+        });
+        if extended {
+            self.fdcan.set_ext_filter(i - STD_FILTERS, Some((id, mask)));
+        } else {
+            self.fdcan.set_std_filter(i, Some((id, mask)));
+        }
+        ringbuf_entry!(Trace::FilterAdded {
+            filter: i as u8,
+            id,
+            mask
+        });
+        Ok(i as u8)
+    }
+
+    fn remove_filter(
+        &mut self,
+        msg: &RecvMessage,
+        filter: u8,
+    ) -> Result<(), RequestError<CanError>> {
+        let i = usize::from(filter);
+        match self.filters.get(i) {
+            Some(Some(f)) if f.owner == msg.sender => (),
+            _ => return Err(CanError::BadFilter.into()),
+        }
+        ringbuf_entry!(Trace::FilterRemoved(filter));
+        self.free_filter(i);
+        Ok(())
+    }
+
+    fn receive(
+        &mut self,
+        msg: &RecvMessage,
+    ) -> Result<Frame, RequestError<CanError>> {
+        let filters = &self.filters;
+        let i = self
+            .pool
+            .iter()
+            .position(|frame| {
+                matches!(
+                    filters[usize::from(frame.filter)],
+                    Some(f) if f.owner == msg.sender
+                )
+            })
+            .ok_or(CanError::Empty)?;
+        let frame = self.pool.remove(i);
+        if let Some(f) = &mut self.filters[usize::from(frame.filter)] {
+            f.waiting -= 1;
+        }
+        Ok(frame)
+    }
+
+    fn transmit(
+        &mut self,
+        _: &RecvMessage,
+        id: u32,
+        flags: u8,
+        priority: u8,
+        data: LenLimit<Leased<R, [u8]>, 64>,
+    ) -> Result<(), RequestError<CanError>> {
+        let fd = flags & flags::FD != 0;
+        if fd && config::DBTP.is_none() {
+            return Err(CanError::FdDisabled.into());
+        }
+        let limit = if flags & flags::EXTENDED != 0 {
+            0x1FFF_FFFF
+        } else {
+            0x7FF
+        };
+        if id > limit {
+            return Err(CanError::BadId.into());
+        }
+        let data = data.into_inner();
+        let dlc =
+            drv_can_api::dlc(data.len(), fd).ok_or(CanError::BadLength)?;
+        if self.fdcan.is_bus_off() {
+            return Err(CanError::BusOff.into());
+        }
+        if self.tx_queue.is_full() {
+            return Err(CanError::QueueFull.into());
+        }
+
+        let mut pending = Pending {
+            priority,
+            sequence: self.sequence,
+            frame: Element {
+                id,
+                flags,
+                dlc,
+                filter: 0,
+            },
+            len: data.len() as u8,
+            data: [0; MAX_DATA],
+        };
+        data.read_range(0..data.len(), &mut pending.data[..data.len()])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        self.sequence = self.sequence.wrapping_add(1);
+
+        // We checked there was room above.
+        let _ = self.tx_queue.push(pending);
+        self.feed();
+        Ok(())
+    }
+
+    fn status(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<BusStatus, RequestError<core::convert::Infallible>> {
+        let (transmit_errors, receive_errors, passive) = self.fdcan.errors();
+        Ok(BusStatus {
+            transmit_errors,
+            receive_errors,
+            error_passive: passive as u8,
+            bus_off: self.fdcan.is_bus_off() as u8,
+        })
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::CAN_IRQ_MASK
+    }
+
+    fn handle_notification(&mut self, bits: u32) {
+        if bits & notifications::CAN_IRQ_MASK == 0 {
+            return;
+        }
+
+        let status = self.fdcan.status(fdcan::IR);
+        self.fdcan.clear_interrupts(status);
+
+        if status & fdcan::BO != 0 && self.fdcan.is_bus_off() {
+            ringbuf_entry!(Trace::BusOff);
+            counters::count!(Event::BusOff);
+            // Whatever we had queued is stale by the time we're back.
+            self.tx_queue.clear();
+            self.fdcan.start();
+        }
+        if status & fdcan::RF0L != 0 {
+            counters::count!(Event::FifoOverrun);
+        }
+        if status & (fdcan::RF0N | fdcan::RF0L) != 0 {
+            self.collect();
+        }
+        if status & fdcan::TC != 0 {
+            self.feed();
+        }
+
+        sys_irq_control(notifications::CAN_IRQ_MASK, true);
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let sys = Sys::from(SYS.get_task_id());
+    // Both FDCANs share a clock enable and reset, so we mustn't reset the
+    // peripheral under another instance's server.
+    sys.enable_clock(Peripheral::Fdcan);
+    sys.leave_reset(Peripheral::Fdcan);
+    for &(pins, af) in config::PINS {
+        sys.gpio_configure_alternate(
+            pins,
+            OutputType::PushPull,
+            Speed::High,
+            Pull::None,
+            af,
+        );
+    }
+
+    let fdcan = Fdcan;
+    fdcan.init();
+    sys_irq_control(notifications::CAN_IRQ_MASK, true);
+
+    let mut server = ServerImpl {
+        fdcan,
+        filters: [None; FILTERS],
+        pool: Vec::new(),
+        tx_queue: Vec::new(),
+        sequence: 0,
+    };
+    let mut buffer = [0; idl::INCOMING_SIZE];
+    loop {
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
+
+mod config {
+    use super::*;
+
+    include!(concat!(env!("OUT_DIR"), "/can_config.rs"));
+}
+
+mod idl {
+    use drv_can_api::{BusStatus, CanError, Frame};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
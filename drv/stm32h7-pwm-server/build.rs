@@ -0,0 +1,284 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::io::Write;
+
+/// This represents our _subset_ of global config and _must not_ be marked with
+/// `deny_unknown_fields`!
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct GlobalConfig {
+    pwm: PwmConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct PwmConfig {
+    channels: Vec<ChannelConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ChannelConfig {
+    name: String,
+    /// The timer, `tim2` through `tim5`
+    timer: String,
+    /// The timer's channel, 1 to 4
+    channel: u8,
+    mode: Mode,
+    pin: PinConfig,
+    /// Starting frequency, for PWM channels; every PWM channel on a timer
+    /// must agree
+    #[serde(default)]
+    frequency_hz: Option<u32>,
+    /// Starting duty cycle, for PWM channels
+    #[serde(default)]
+    duty: f32,
+    /// Whether a PWM output is active low
+    #[serde(default)]
+    inverted: bool,
+    /// Input filter setting (ICxF), for capture channels
+    #[serde(default)]
+    filter: u8,
+    /// How long a capture channel can go without an edge before we decide
+    /// its signal has gone
+    #[serde(default = "default_timeout_ms")]
+    timeout_ms: u32,
+}
+
+#[derive(Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Mode {
+    Pwm,
+    Capture,
+}
+
+#[derive(Copy, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Pull {
+    None,
+    Up,
+    Down,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct PinConfig {
+    port: char,
+    pin: u8,
+    af: u8,
+    #[serde(default = "default_pull")]
+    pull: Pull,
+}
+
+fn default_timeout_ms() -> u32 {
+    1000
+}
+
+fn default_pull() -> Pull {
+    Pull::None
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct TaskConfig {
+    /// Frequency of the timers' kernel clock (twice the APB1 clock, unless
+    /// APB1 is undivided)
+    timer_clock_hz: u32,
+    /// Rate at which the counters of timers with capture channels tick
+    #[serde(default = "default_capture_clock_hz")]
+    capture_clock_hz: u32,
+}
+
+fn default_capture_clock_hz() -> u32 {
+    1_000_000
+}
+
+/// What we know about each timer: its registers, its `Peripheral`, and whether
+/// its counter is 32 bits wide.
+fn timer_info(name: &str) -> Option<(usize, &'static str, bool)> {
+    match name {
+        "tim2" => Some((0x4000_0000, "Tim2", true)),
+        "tim3" => Some((0x4000_0400, "Tim3", false)),
+        "tim4" => Some((0x4000_0800, "Tim4", false)),
+        "tim5" => Some((0x4000_0C00, "Tim5", true)),
+        _ => None,
+    }
+}
+
+fn generate_pwm_config(config: &PwmConfig, task: &TaskConfig) -> Result<()> {
+    if config.channels.len() > usize::from(u8::MAX) {
+        bail!("too many PWM channels");
+    }
+    if task.capture_clock_hz == 0 || task.capture_clock_hz > task.timer_clock_hz
+    {
+        bail!("capture clock must be between 1 Hz and the timer clock");
+    }
+    let capture_psc = task.timer_clock_hz / task.capture_clock_hz - 1;
+    if capture_psc > 0xFFFF {
+        bail!("capture clock is too slow");
+    }
+
+    // Timers, in order of first use, with their mode and PWM frequency.
+    let mut timers: Vec<(&str, Mode, Option<u32>)> = vec![];
+    for c in &config.channels {
+        if timer_info(&c.timer).is_none() {
+            bail!("channel {}: no such timer {}", c.name, c.timer);
+        }
+        if !(1..=4).contains(&c.channel) || c.pin.pin > 15 || c.pin.af > 15 {
+            bail!("channel {}: bad channel or pin", c.name);
+        }
+        match c.mode {
+            Mode::Pwm if !(0.0..=1.0).contains(&c.duty) => {
+                bail!("channel {}: duty must be from 0 to 1", c.name)
+            }
+            Mode::Capture if c.filter > 15 => {
+                bail!("channel {}: filter must be from 0 to 15", c.name)
+            }
+            Mode::Capture if c.frequency_hz.is_some() => {
+                bail!("channel {}: inputs don't have a frequency", c.name)
+            }
+            _ => (),
+        }
+        let others = config.channels.iter().filter(|o| o.timer == c.timer);
+        if others.filter(|o| o.channel == c.channel).count() > 1 {
+            bail!(
+                "channel {}: {} channel {} is taken",
+                c.name,
+                c.timer,
+                c.channel
+            );
+        }
+
+        match timers.iter_mut().find(|(t, ..)| *t == c.timer) {
+            None => timers.push((c.timer.as_str(), c.mode, c.frequency_hz)),
+            Some((_, mode, _)) if *mode != c.mode => {
+                bail!("{} can't mix PWM and capture channels", c.timer)
+            }
+            Some((_, _, freq)) => match (*freq, c.frequency_hz) {
+                (Some(a), Some(b)) if a != b => {
+                    bail!("{}'s PWM channels disagree on frequency", c.timer)
+                }
+                (None, f) => *freq = f,
+                _ => (),
+            },
+        }
+    }
+
+    let out = build_util::out_dir().join("pwm_config.rs");
+    let mut file = std::fs::File::create(out)?;
+    writeln!(
+        file,
+        "pub const TIMER_CLOCK_HZ: u32 = {};",
+        task.timer_clock_hz
+    )?;
+    writeln!(file, "pub const CAPTURE_PSC: u32 = {capture_psc};")?;
+    writeln!(
+        file,
+        "pub const CAPTURE_HZ: u32 = {};",
+        task.timer_clock_hz / (capture_psc + 1)
+    )?;
+    writeln!(file, "pub const NUM_TIMERS: usize = {};", timers.len())?;
+    writeln!(file, "pub static TIMERS: [TimerConfig; NUM_TIMERS] = [")?;
+    for (name, mode, freq) in &timers {
+        let (base, peripheral, wide) = timer_info(name).unwrap();
+        let (capture, irq) = match mode {
+            Mode::Pwm => (false, "0".to_string()),
+            Mode::Capture => (
+                true,
+                format!("notifications::{}_IRQ_MASK", name.to_uppercase()),
+            ),
+        };
+        if *mode == Mode::Pwm {
+            // This must agree with the server's `timebase`.
+            let total = match freq {
+                Some(f) if *f != 0 => task.timer_clock_hz / f,
+                _ => bail!("{name} needs a PWM frequency"),
+            };
+            if total < 100 || (total - 1) / 0x1_0000 > 0xFFFF {
+                bail!("{name} can't run at {} Hz", freq.unwrap());
+            }
+        }
+        writeln!(
+            file,
+            "    TimerConfig {{
+        base: {base:#x},
+        peripheral: Peripheral::{peripheral},
+        wide: {wide},
+        capture: {capture},
+        frequency_hz: {},
+        irq: {irq},
+    }},",
+            freq.unwrap_or(0),
+        )?;
+    }
+    writeln!(file, "];")?;
+
+    writeln!(
+        file,
+        "pub const NUM_CHANNELS: usize = {};",
+        config.channels.len()
+    )?;
+    writeln!(
+        file,
+        "pub static CHANNELS: [ChannelConfig; NUM_CHANNELS] = ["
+    )?;
+    for c in &config.channels {
+        let timer = timers.iter().position(|(t, ..)| *t == c.timer).unwrap();
+        let pull = match c.pin.pull {
+            Pull::None => "None",
+            Pull::Up => "Up",
+            Pull::Down => "Down",
+        };
+        writeln!(
+            file,
+            "    ChannelConfig {{
+        timer: {timer},
+        channel: {},
+        capture: {},
+        pin: Port::{}.pin({}),
+        af: Alternate::AF{},
+        pull: Pull::{pull},
+        duty: {:?},
+        inverted: {},
+        filter: {},
+        timeout_ms: {},
+    }},",
+            c.channel,
+            c.mode == Mode::Capture,
+            c.pin.port.to_ascii_uppercase(),
+            c.pin.pin,
+            c.pin.af,
+            c.duty,
+            c.inverted,
+            c.filter,
+            c.timeout_ms,
+        )?;
+    }
+    writeln!(file, "];")?;
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    build_util::build_notifications()?;
+
+    idol::Generator::new()
+        .with_counters(
+            idol::CounterSettings::default().with_server_counters(false),
+        )
+        .build_server_support(
+            "../../idl/pwm.idol",
+            "server_stub.rs",
+            idol::server::ServerStyle::InOrder,
+        )?;
+
+    let global_config = build_util::config::<GlobalConfig>()?;
+    let task_config = build_util::task_config::<TaskConfig>()?;
+    generate_pwm_config(&global_config.pwm, &task_config)?;
+
+    Ok(())
+}
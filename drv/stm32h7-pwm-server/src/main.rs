@@ -0,0 +1,456 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! PWM server for the STM32H7's general-purpose timers, TIM2 through TIM5.
+//!
+//! Each timer either drives up to four PWM outputs, which share its
+//! frequency, or measures the frequency of up to four inputs, such as fan
+//! tachometers. Having one task own the timers means fan control and
+//! indicators don't each have to poke at timer registers, and can't trip over
+//! each other when two of them want channels on the same timer.
+//!
+//! New duty cycles and frequencies are loaded by the hardware at the end of a
+//! period, so outputs never see a period that's partly old and partly new.
+//! While we're rewriting a timer's registers, we hold its updates back, so
+//! the hardware can't load half of them; `stage_duty` just leaves them held
+//! until `commit`, so that changes to several channels land together.
+//!
+//! A capture timer runs freely at the capture clock, and interrupts on each
+//! rising edge of its inputs, and on each overflow, which we count to extend
+//! the counter to 64 bits. An input's frequency is measured from its two most
+//! recent edges.
+//!
+//! Channels are defined in the app's global config, so that clients can
+//! refer to them by name:
+//!
+//! ```toml
+//! [[config.pwm.channels]]
+//! name = "fan0"
+//! timer = "tim3"
+//! channel = 1
+//! mode = "pwm"
+//! pin = { port = "B", pin = 4, af = 2 }
+//! frequency-hz = 25000
+//! duty = 0.5
+//!
+//! [[config.pwm.channels]]
+//! name = "fan0-tach"
+//! timer = "tim5"
+//! channel = 1
+//! mode = "capture"
+//! pin = { port = "A", pin = 0, af = 2, pull = "up" }
+//! filter = 4
+//! ```
+//!
+//! The task itself is configured with the timers' kernel clock frequency,
+//! and optionally the rate at which capture timers count (by default, 1
+//! MHz). It needs each timer it uses in its memory map, each capture timer's
+//! interrupt mapped to a notification named after it (`tim5-irq`, say), and
+//! a task slot for `sys`.
+
+#![no_std]
+#![no_main]
+
+mod timer;
+
+use drv_pwm_api::{Capture, PwmError};
+use drv_stm32xx_sys_api::{
+    Alternate, OutputType, Peripheral, PinSet, Port, Pull, Speed, Sys,
+};
+use idol_runtime::{NotificationHandler, RequestError};
+use ringbuf::*;
+use timer::Timer;
+use userlib::*;
+
+task_slot!(SYS, sys);
+
+/// The fewest timer ticks we'll allow in a PWM period, so that duty cycles
+/// can be set to within 1%.
+const MIN_PERIOD: u32 = 100;
+
+struct TimerConfig {
+    base: usize,
+    peripheral: Peripheral,
+    /// Whether the counter is 32 bits, rather than 16.
+    wide: bool,
+    capture: bool,
+    /// Starting frequency, for PWM timers.
+    frequency_hz: u32,
+    /// Notification for the timer's interrupt, for capture timers.
+    irq: u32,
+}
+
+impl TimerConfig {
+    /// The number of ticks in one lap of the counter.
+    fn span(&self) -> u64 {
+        if self.wide {
+            1 << 32
+        } else {
+            1 << 16
+        }
+    }
+}
+
+struct ChannelConfig {
+    /// Index into `config::TIMERS`.
+    timer: usize,
+    /// The timer's channel, 1 to 4.
+    channel: u8,
+    capture: bool,
+    pin: PinSet,
+    af: Alternate,
+    pull: Pull,
+    duty: f32,
+    inverted: bool,
+    filter: u8,
+    timeout_ms: u32,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Timebase { timer: u8, psc: u32, arr: u32 },
+    Staged { channel: u8, compare: u32 },
+    Committed { timer: u8 },
+}
+
+ringbuf!(Trace, 32, Trace::None);
+
+struct TimerState {
+    config: &'static TimerConfig,
+    regs: Timer,
+    /// A PWM timer's auto-reload value, which is one less than its period
+    /// in ticks.
+    arr: u32,
+    /// Whether the timer's updates are being held back.
+    held: bool,
+    /// Ticks a capture timer has counted before the current lap of its
+    /// counter.
+    laps: u64,
+}
+
+#[derive(Copy, Clone)]
+struct Edge {
+    /// The extended counter value it was captured at.
+    count: u64,
+    /// When we saw it, in kernel ticks.
+    time: u64,
+}
+
+struct Channel {
+    config: &'static ChannelConfig,
+    duty: f32,
+    last_edge: Option<Edge>,
+    /// Ticks between the last two edges, if they were close enough together
+    /// to count.
+    period: Option<u64>,
+}
+
+struct ServerImpl {
+    timers: [TimerState; config::NUM_TIMERS],
+    channels: [Channel; config::NUM_CHANNELS],
+}
+
+/// Picks the prescaler and auto-reload values for a PWM frequency of `hz`.
+fn timebase(hz: u32) -> Option<(u32, u32)> {
+    let total = config::TIMER_CLOCK_HZ.checked_div(hz)?;
+    if total < MIN_PERIOD {
+        return None;
+    }
+    // We keep the auto-reload value within 16 bits even on the wide timers,
+    // so that the period fits in a u32.
+    let psc = (total - 1) / (timer::MAX_16 + 1);
+    if psc > timer::MAX_16 {
+        return None;
+    }
+    Some((psc, total / (psc + 1) - 1))
+}
+
+/// Returns the compare value for `duty` in a period of `arr + 1` ticks.
+fn compare(duty: f32, arr: u32) -> u32 {
+    (duty * (arr as f32 + 1.0) + 0.5) as u32
+}
+
+impl ServerImpl {
+    fn pwm_channel(&self, channel: u8) -> Result<usize, PwmError> {
+        let c = self
+            .channels
+            .get(usize::from(channel))
+            .ok_or(PwmError::BadChannel)?;
+        if c.config.capture {
+            return Err(PwmError::NotPwm);
+        }
+        Ok(usize::from(channel))
+    }
+
+    /// Writes channel `i`'s compare value, holding back its timer's updates
+    /// until it's released.
+    fn write_duty(&mut self, i: usize) {
+        let c = &self.channels[i];
+        let t = &mut self.timers[c.config.timer];
+        let compare = compare(c.duty, t.arr);
+        t.regs.hold_updates(true);
+        t.held = true;
+        t.regs.set_compare(c.config.channel, compare);
+        ringbuf_entry!(Trace::Staged {
+            channel: i as u8,
+            compare
+        });
+    }
+
+    fn release(&mut self, timer: usize) {
+        let t = &mut self.timers[timer];
+        if t.held {
+            t.regs.hold_updates(false);
+            t.held = false;
+            ringbuf_entry!(Trace::Committed { timer: timer as u8 });
+        }
+    }
+
+    fn stage(&mut self, channel: u8, duty: f32) -> Result<usize, PwmError> {
+        let i = self.pwm_channel(channel)?;
+        // This rejects NaN, too.
+        if !(0.0..=1.0).contains(&duty) {
+            return Err(PwmError::BadDuty);
+        }
+        self.channels[i].duty = duty;
+        self.write_duty(i);
+        Ok(self.channels[i].config.timer)
+    }
+
+    /// Handles an interrupt from capture timer `t`.
+    fn service(&mut self, t: usize, now: u64) {
+        let timer = &mut self.timers[t];
+        let span = timer.config.span();
+        let events = timer.regs.take_events();
+        let wrapped = events & timer::SR_UIF != 0;
+
+        for c in self.channels.iter_mut() {
+            let ch = c.config.channel;
+            if c.config.timer != t || events & 1 << ch == 0 {
+                continue;
+            }
+            let value = u64::from(timer.regs.capture(ch));
+            // If the counter wrapped as well, a capture from early in the
+            // new lap came after the wrap, and one from late in the old lap
+            // came before it.
+            let lap = if wrapped && value < span / 2 {
+                timer.laps + span
+            } else {
+                timer.laps
+            };
+            let edge = Edge {
+                count: lap + value,
+                time: now,
+            };
+            // An edge after a long silence tells us the signal is back, but
+            // not how fast it is.
+            let timeout = hl::ms_to_ticks(u64::from(c.config.timeout_ms));
+            c.period = match c.last_edge {
+                Some(last) if now - last.time <= timeout => {
+                    Some(edge.count.wrapping_sub(last.count))
+                }
+                _ => None,
+            };
+            c.last_edge = Some(edge);
+        }
+        if wrapped {
+            timer.laps += span;
+        }
+    }
+}
+
+impl idl::InOrderPwmImpl for ServerImpl {
+    fn set_frequency(
+        &mut self,
+        _: &RecvMessage,
+        channel: u8,
+        hz: u32,
+    ) -> Result<(), RequestError<PwmError>> {
+        let i = self.pwm_channel(channel)?;
+        let timer = self.channels[i].config.timer;
+        let (psc, arr) = timebase(hz).ok_or(PwmError::BadFrequency)?;
+
+        let t = &mut self.timers[timer];
+        t.regs.hold_updates(true);
+        t.held = true;
+        t.regs.set_timebase(psc, arr);
+        t.arr = arr;
+        ringbuf_entry!(Trace::Timebase {
+            timer: timer as u8,
+            psc,
+            arr
+        });
+        // The compare values are in ticks, so they need redoing to keep the
+        // duty cycles.
+        for j in 0..self.channels.len() {
+            if self.channels[j].config.timer == timer {
+                self.write_duty(j);
+            }
+        }
+        self.release(timer);
+        Ok(())
+    }
+
+    fn set_duty(
+        &mut self,
+        _: &RecvMessage,
+        channel: u8,
+        duty: f32,
+    ) -> Result<(), RequestError<PwmError>> {
+        let timer = self.stage(channel, duty)?;
+        self.release(timer);
+        Ok(())
+    }
+
+    fn stage_duty(
+        &mut self,
+        _: &RecvMessage,
+        channel: u8,
+        duty: f32,
+    ) -> Result<(), RequestError<PwmError>> {
+        self.stage(channel, duty)?;
+        Ok(())
+    }
+
+    fn commit(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        for t in 0..self.timers.len() {
+            self.release(t);
+        }
+        Ok(())
+    }
+
+    fn read_capture(
+        &mut self,
+        _: &RecvMessage,
+        channel: u8,
+    ) -> Result<Capture, RequestError<PwmError>> {
+        let c = self
+            .channels
+            .get(usize::from(channel))
+            .ok_or(PwmError::BadChannel)?;
+        if !c.config.capture {
+            return Err(PwmError::NotCapture.into());
+        }
+        let timeout = hl::ms_to_ticks(u64::from(c.config.timeout_ms));
+        let now = sys_get_timer().now;
+        match (c.last_edge, c.period) {
+            (Some(edge), Some(period))
+                if period != 0 && now - edge.time <= timeout =>
+            {
+                Ok(Capture {
+                    frequency: config::CAPTURE_HZ as f32 / period as f32,
+                    _reserved: [0; 4],
+                    timestamp: edge.time,
+                })
+            }
+            _ => Err(PwmError::NoSignal.into()),
+        }
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        config::TIMERS.iter().fold(0, |mask, t| mask | t.irq)
+    }
+
+    fn handle_notification(&mut self, bits: u32) {
+        let now = sys_get_timer().now;
+        for t in 0..self.timers.len() {
+            let irq = self.timers[t].config.irq;
+            if bits & irq != 0 {
+                self.service(t, now);
+                sys_irq_control(irq, true);
+            }
+        }
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let sys = Sys::from(SYS.get_task_id());
+    for t in &config::TIMERS {
+        sys.enable_clock(t.peripheral);
+        sys.enter_reset(t.peripheral);
+        sys.leave_reset(t.peripheral);
+    }
+
+    let mut server = ServerImpl {
+        timers: core::array::from_fn(|i| {
+            let config = &config::TIMERS[i];
+            let regs = Timer::new(config.base);
+            let arr = if config.capture {
+                let arr = (config.span() - 1) as u32;
+                regs.set_timebase(config::CAPTURE_PSC, arr);
+                arr
+            } else {
+                // The build has checked that the timer can manage this.
+                let (psc, arr) = timebase(config.frequency_hz).unwrap_lite();
+                regs.set_timebase(psc, arr);
+                arr
+            };
+            TimerState {
+                config,
+                regs,
+                arr,
+                held: false,
+                laps: 0,
+            }
+        }),
+        channels: core::array::from_fn(|i| {
+            let config = &config::CHANNELS[i];
+            Channel {
+                config,
+                duty: config.duty,
+                last_edge: None,
+                period: None,
+            }
+        }),
+    };
+
+    for c in &config::CHANNELS {
+        let t = &server.timers[c.timer];
+        if c.capture {
+            t.regs.set_input(c.channel, c.filter);
+        } else {
+            t.regs.set_output(c.channel, c.inverted);
+            t.regs.set_compare(c.channel, compare(c.duty, t.arr));
+        }
+        sys.gpio_configure_alternate(
+            c.pin,
+            OutputType::PushPull,
+            Speed::Low,
+            c.pull,
+            c.af,
+        );
+    }
+    for t in &server.timers {
+        t.regs.start(t.config.capture);
+        if t.config.capture {
+            sys_irq_control(t.config.irq, true);
+        }
+    }
+
+    let mut buffer = [0; idl::INCOMING_SIZE];
+    loop {
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
+
+mod config {
+    use super::*;
+
+    include!(concat!(env!("OUT_DIR"), "/pwm_config.rs"));
+}
+
+mod idl {
+    use drv_pwm_api::{Capture, PwmError};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
@@ -0,0 +1,140 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Register-level access to the general-purpose timers TIM2 through TIM5,
+//! which share a layout; offsets and bits are from RM0433 section 38.
+
+const CR1: usize = 0x00;
+const DIER: usize = 0x0C;
+const SR: usize = 0x10;
+const EGR: usize = 0x14;
+const CCMR1: usize = 0x18;
+const CCER: usize = 0x20;
+const PSC: usize = 0x28;
+const ARR: usize = 0x2C;
+const CCR1: usize = 0x34;
+
+const CR1_CEN: u32 = 1 << 0;
+const CR1_UDIS: u32 = 1 << 1;
+/// Only overflows set UIF; `EGR_UG` doesn't.
+const CR1_URS: u32 = 1 << 2;
+const CR1_ARPE: u32 = 1 << 7;
+
+const DIER_UIE: u32 = 1 << 0;
+
+pub const SR_UIF: u32 = 1 << 0;
+
+const EGR_UG: u32 = 1 << 0;
+
+/// Each channel's byte of CCMR1 or CCMR2.
+const CCMR_CC_INPUT: u32 = 0b01;
+const CCMR_OC_PRELOAD: u32 = 1 << 3;
+const CCMR_OC_PWM1: u32 = 0b110 << 4;
+const CCMR_IC_FILTER_SHIFT: u32 = 4;
+
+/// Each channel's nibble of CCER.
+const CCER_CCE: u32 = 1 << 0;
+const CCER_CCP: u32 = 1 << 1;
+
+/// Largest value of the prescaler, and of the auto-reload register on the
+/// 16-bit timers.
+pub const MAX_16: u32 = 0xFFFF;
+
+pub struct Timer {
+    base: usize,
+}
+
+impl Timer {
+    pub const fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { core::ptr::read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe {
+            core::ptr::write_volatile((self.base + offset) as *mut u32, value)
+        }
+    }
+
+    fn modify(&self, offset: usize, f: impl FnOnce(u32) -> u32) {
+        self.write(offset, f(self.read(offset)));
+    }
+
+    /// Sets channel `ch`'s byte of CCMR1 or CCMR2 (which follows it).
+    fn set_ccmr(&self, ch: u8, bits: u32) {
+        let offset = CCMR1 + usize::from((ch - 1) / 2) * 4;
+        let shift = u32::from((ch - 1) % 2) * 8;
+        self.modify(offset, |v| v & !(0xFF << shift) | bits << shift);
+    }
+
+    fn set_ccer(&self, ch: u8, bits: u32) {
+        let shift = u32::from(ch - 1) * 4;
+        self.modify(CCER, |v| v & !(0xF << shift) | bits << shift);
+    }
+
+    /// Makes channel `ch` (1 to 4) a PWM output, active while the counter is
+    /// below its compare value; `inverted` makes active low.
+    pub fn set_output(&self, ch: u8, inverted: bool) {
+        self.set_ccmr(ch, CCMR_OC_PWM1 | CCMR_OC_PRELOAD);
+        let polarity = if inverted { CCER_CCP } else { 0 };
+        self.set_ccer(ch, CCER_CCE | polarity);
+    }
+
+    /// Makes channel `ch` (1 to 4) capture the counter on each rising edge
+    /// of its input, after the input `filter` (0 to 15) has had its say, and
+    /// interrupt when it does.
+    pub fn set_input(&self, ch: u8, filter: u8) {
+        let filter = u32::from(filter & 0xF) << CCMR_IC_FILTER_SHIFT;
+        self.set_ccmr(ch, CCMR_CC_INPUT | filter);
+        self.set_ccer(ch, CCER_CCE);
+        self.modify(DIER, |v| v | 1 << ch);
+    }
+
+    /// Sets the prescaler and auto-reload values, which the timer loads at
+    /// its next update.
+    pub fn set_timebase(&self, psc: u32, arr: u32) {
+        self.write(PSC, psc);
+        self.write(ARR, arr);
+    }
+
+    /// Sets channel `ch`'s compare value, which it loads at the next update.
+    pub fn set_compare(&self, ch: u8, value: u32) {
+        self.write(CCR1 + usize::from(ch - 1) * 4, value);
+    }
+
+    /// Returns the counter value channel `ch` most recently captured.
+    pub fn capture(&self, ch: u8) -> u32 {
+        self.read(CCR1 + usize::from(ch - 1) * 4)
+    }
+
+    /// Holds back (or releases) the loading of new timebase and compare
+    /// values at the end of each period.
+    pub fn hold_updates(&self, hold: bool) {
+        self.modify(CR1, |v| if hold { v | CR1_UDIS } else { v & !CR1_UDIS });
+    }
+
+    /// Loads the timebase and compare values, and starts counting. With
+    /// `interrupt`, overflows raise the timer's interrupt.
+    pub fn start(&self, interrupt: bool) {
+        self.write(CR1, CR1_ARPE | CR1_URS);
+        self.write(EGR, EGR_UG);
+        if interrupt {
+            self.modify(DIER, |v| v | DIER_UIE);
+        }
+        self.modify(CR1, |v| v | CR1_CEN);
+    }
+
+    /// Returns and clears the timer's status flags: `SR_UIF` for an
+    /// overflow, and bit `n` for a capture on channel `n`.
+    pub fn take_events(&self) -> u32 {
+        let sr = self.read(SR);
+        // The flags are cleared by writing zero, and writing one leaves
+        // them alone, so this can't lose one that arrives in between.
+        self.write(SR, !sr);
+        sr
+    }
+}
@@ -114,6 +114,21 @@ impl Qspi {
         self.write_impl(Command::SectorErase, Some(addr), &[])
     }
 
+    /// Suspends any program or erase under way, so that the flash can be read.
+    ///
+    /// The suspend takes effect some microseconds later: wait for the
+    /// write-in-progress bit of the Status register to clear before reading.
+    /// Reading the sector being erased (or the page being programmed) while
+    /// suspended returns garbage. If nothing is under way, this does nothing.
+    pub fn suspend_program_erase(&self) {
+        self.write_impl(Command::ProgramEraseSuspend, None, &[])
+    }
+
+    /// Resumes a program or erase suspended by `suspend_program_erase`.
+    pub fn resume_program_erase(&self) {
+        self.write_impl(Command::ProgramEraseResume, None, &[])
+    }
+
     /// Writes `data` into flash memory beginning at `addr`.
     ///
     /// Any zero bits in `data` will clear the corresponding bits in flash; any
@@ -2,7 +2,22 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// RNG task configuration.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Config {
+    /// Tasks to notify when the RNG fails a health test, as a map from task
+    /// name to notification name (in the target task)
+    #[serde(default)]
+    on_failure: BTreeMap<String, String>,
+}
+
+fn main() -> Result<()> {
     idol::Generator::new()
         .with_counters(
             idol::CounterSettings::default().with_server_counters(false),
@@ -11,6 +26,31 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             "../../idl/rng.idol",
             "server_stub.rs",
             idol::server::ServerStyle::InOrder,
+        )
+        .unwrap();
+
+    build_util::build_notifications()?;
+
+    let cfg = build_util::task_maybe_config::<Config>()?.unwrap_or_default();
+
+    let out_dir = build_util::out_dir();
+    let dest_path = out_dir.join("rng_config.rs");
+    let mut out =
+        std::fs::File::create(dest_path).context("creating rng_config.rs")?;
+
+    let task = "hubris_num_tasks::Task";
+    let count = cfg.on_failure.len();
+    writeln!(
+        out,
+        "pub(crate) const MAILING_LIST: [({task}, u32); {count}] = [",
+    )?;
+    for (name, rec) in cfg.on_failure {
+        writeln!(
+            out,
+            "    ({task}::{name}, crate::notifications::{name}::{}_MASK),",
+            rec.to_ascii_uppercase().replace('-', "_"),
         )?;
+    }
+    writeln!(out, "];")?;
     Ok(())
 }
@@ -5,6 +5,17 @@
 //! Driver for the STM32H7 random number generator.
 //!
 //! Use the rng-api crate to interact with this driver.
+//!
+//! Every byte handed out first passes the continuous health tests in
+//! `rng-health`. When one fails, the request fails with `HealthTestFailed`,
+//! the output is thrown away, and the RNG is restarted. Tasks that want to
+//! know -- to stop trusting keys made just before, say -- can ask to be
+//! notified:
+//!
+//! ```toml
+//! [tasks.rng_driver.config.on-failure]
+//! attest = "rng-failed"
+//! ```
 
 #![no_std]
 #![no_main]
@@ -12,6 +23,8 @@
 use drv_rng_api::RngError;
 use drv_stm32xx_sys_api::{Peripheral, Sys};
 use idol_runtime::{ClientError, NotificationHandler, RequestError};
+use ringbuf::*;
+use rng_health::{Failure, HealthTests};
 
 #[cfg(feature = "h743")]
 use stm32h7::stm32h743 as device;
@@ -23,6 +36,25 @@ use userlib::*;
 
 task_slot!(SYS, sys);
 
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    HealthTestFailed(Failure),
+    SeedError,
+    Restarted,
+}
+
+ringbuf!(Trace, 8, Trace::None);
+
+#[derive(Copy, Clone, PartialEq, counters::Count)]
+enum Event {
+    RepetitionCount,
+    AdaptiveProportion,
+    SeedError,
+}
+
+counters::counters!(Event);
+
 struct Stm32h7Rng {
     cr: &'static device::rng::CR,
     dr: &'static device::rng::DR,
@@ -71,6 +103,14 @@ impl Stm32h7Rng {
         self.cr.modify(|_, w| w.rngen().set_bit());
     }
 
+    /// Turns the RNG off and on again, which restarts its entropy source and
+    /// clears a seed error (RM0433 section 34.3.7).
+    fn restart(&self) {
+        self.cr.modify(|_, w| w.rngen().clear_bit());
+        self.sr.modify(|_, w| w.seis().clear_bit());
+        self.enable_rng();
+    }
+
     fn is_clock_error_detect(&self) -> bool {
         self.cr.read().ced().bits()
     }
@@ -95,11 +135,56 @@ impl Stm32h7Rng {
 
 struct Stm32h7RngServer {
     rng: Stm32h7Rng,
+    health: HealthTests,
 }
 
 impl Stm32h7RngServer {
     fn new(rng: Stm32h7Rng) -> Self {
-        Stm32h7RngServer { rng }
+        Stm32h7RngServer {
+            rng,
+            health: HealthTests::new(),
+        }
+    }
+
+    /// Reads a word from the RNG, and runs it through the health tests. On
+    /// any failure, restarts the RNG, since the RNG is done with that seed
+    /// either way.
+    fn read(&mut self) -> Result<u32, RngError> {
+        let err = match self.rng.read() {
+            Ok(ent) => match self.health.check(&ent.to_ne_bytes()) {
+                Ok(()) => return Ok(ent),
+                Err(f) => {
+                    ringbuf_entry!(Trace::HealthTestFailed(f));
+                    counters::count!(match f {
+                        Failure::RepetitionCount => Event::RepetitionCount,
+                        Failure::AdaptiveProportion => {
+                            Event::AdaptiveProportion
+                        }
+                    });
+                    self.notify_failure();
+                    RngError::HealthTestFailed
+                }
+            },
+            Err(RngError::SeedError) => {
+                ringbuf_entry!(Trace::SeedError);
+                counters::count!(Event::SeedError);
+                self.notify_failure();
+                RngError::SeedError
+            }
+            Err(e) => return Err(e),
+        };
+        self.rng.restart();
+        self.health.reset();
+        ringbuf_entry!(Trace::Restarted);
+        Err(err)
+    }
+
+    fn notify_failure(&self) {
+        for (task, mask) in config::MAILING_LIST {
+            let taskid =
+                TaskId::for_index_and_gen(task as usize, Generation::ZERO);
+            sys_post(sys_refresh_task_id(taskid), mask);
+        }
     }
 }
 
@@ -111,7 +196,7 @@ impl idl::InOrderRngImpl for Stm32h7RngServer {
     ) -> Result<usize, RequestError<RngError>> {
         let mut cnt = 0;
         for _ in 0..(dest.len() / 4) {
-            let ent = self.rng.read()?;
+            let ent = self.read()?;
             dest.write_range(cnt..cnt + 4, &ent.to_ne_bytes()[0..4])
                 .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
             cnt += 4;
@@ -122,7 +207,7 @@ impl idl::InOrderRngImpl for Stm32h7RngServer {
             panic!("RNG state machine bork");
         }
         if remain > 0 {
-            let ent = self.rng.read()?;
+            let ent = self.read()?;
             dest.write_range(cnt..dest.len(), &ent.to_ne_bytes()[0..remain])
                 .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
             cnt += remain;
@@ -156,8 +241,14 @@ fn main() -> ! {
     }
 }
 
+mod config {
+    include!(concat!(env!("OUT_DIR"), "/rng_config.rs"));
+}
+
 mod idl {
     use drv_rng_api::RngError;
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
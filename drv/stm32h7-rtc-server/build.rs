@@ -0,0 +1,54 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::Result;
+use serde::Deserialize;
+use std::io::Write;
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct TaskConfig {
+    /// Which oscillator clocks the RTC
+    #[serde(default)]
+    clock: Clock,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Clock {
+    #[default]
+    Lse,
+    Lsi,
+}
+
+fn generate_rtc_config(config: &TaskConfig) -> Result<()> {
+    let source = match config.clock {
+        Clock::Lse => "Lse",
+        Clock::Lsi => "Lsi",
+    };
+    let out = build_util::out_dir().join("rtc_config.rs");
+    let mut file = std::fs::File::create(out)?;
+    writeln!(file, "pub const SOURCE: Source = Source::{source};")?;
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    idol::Generator::new()
+        .with_counters(
+            idol::CounterSettings::default().with_server_counters(false),
+        )
+        .build_server_support(
+            "../../idl/rtc.idol",
+            "server_stub.rs",
+            idol::server::ServerStyle::InOrder,
+        )?;
+
+    build_util::build_notifications()?;
+
+    let task_config =
+        build_util::task_maybe_config::<TaskConfig>()?.unwrap_or_default();
+    generate_rtc_config(&task_config)?;
+
+    Ok(())
+}
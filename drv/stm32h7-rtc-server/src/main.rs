@@ -0,0 +1,246 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! RTC server for the STM32H7.
+//!
+//! This task keeps wall-clock time in the on-chip RTC, which lives in the
+//! backup domain along with its 32.768 kHz crystal: a system reset doesn't
+//! stop it, and with a backup battery, neither does losing main power. On
+//! startup we find it running and leave it be, unless it isn't, or is
+//! running from the wrong clock. Until the time is first set, `now` fails
+//! with `NotSet`.
+//!
+//! Alarms are kept here rather than in the RTC's alarm units, whose
+//! interrupts come through an EXTI line that `sys` owns, and which couldn't
+//! serve more than two clients anyway. We set a kernel timer for the earliest
+//! alarm, and on waking check the RTC, which the kernel's tick may have
+//! drifted from, setting the timer again if we're early.
+//!
+//! The task needs `uses = ["rtc", "pwr", "rcc"]`. Of the RCC, we touch only
+//! `RCC_BDCR` and `RCC_CSR`, which `sys` leaves alone. Its config can choose
+//! the clock, which defaults to the external crystal:
+//!
+//! ```toml
+//! [tasks.rtc.config]
+//! clock = "lsi"
+//! ```
+
+#![no_std]
+#![no_main]
+
+mod rtc;
+
+use drv_rtc_api::{DateTime, RtcError, Timestamp};
+use idol_runtime::{NotificationHandler, RequestError};
+use ringbuf::*;
+use rtc::{Rtc, Source, Startup};
+use userlib::*;
+
+#[cfg(feature = "h743")]
+use stm32h7::stm32h743 as device;
+#[cfg(feature = "h753")]
+use stm32h7::stm32h753 as device;
+
+const TIMER_NOTIFICATION: u32 = 1 << 31;
+
+/// Most alarms that can be set at once; each task can have one.
+const MAX_ALARMS: usize = 8;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Started(Startup),
+    ClockFailed,
+    Set(DateTime),
+    Alarm { task: u16, at: DateTime },
+    Fired { task: u16 },
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+#[derive(Copy, Clone)]
+struct Alarm {
+    owner: TaskId,
+    /// Seconds since the Unix epoch
+    at: u64,
+    notification: u32,
+}
+
+struct ServerImpl {
+    /// The RTC, unless its clock wouldn't start
+    rtc: Option<Rtc>,
+    alarms: [Option<Alarm>; MAX_ALARMS],
+}
+
+impl ServerImpl {
+    fn rtc(&self) -> Result<&Rtc, RtcError> {
+        self.rtc.as_ref().ok_or(RtcError::ClockFailed)
+    }
+
+    /// Posts any alarms that are due, and sets the timer for the next one.
+    fn check_alarms(&mut self) {
+        let Some(now) = self.rtc.as_ref().and_then(|r| r.now().ok()) else {
+            // Alarms can't be set until the clock is, so there's nothing to
+            // do; a clock that's been set doesn't become unset.
+            return;
+        };
+        let now_secs = now.time.unix_seconds();
+
+        let mut next = None;
+        for slot in &mut self.alarms {
+            let Some(alarm) = slot else {
+                continue;
+            };
+            if alarm.at <= now_secs {
+                ringbuf_entry!(Trace::Fired {
+                    task: alarm.owner.index() as u16
+                });
+                sys_post(sys_refresh_task_id(alarm.owner), alarm.notification);
+                *slot = None;
+            } else {
+                next = Some(next.map_or(alarm.at, |n: u64| n.min(alarm.at)));
+            }
+        }
+
+        match next {
+            Some(at) => {
+                let ms = (at - now_secs) * 1000 - u64::from(now.micros / 1000);
+                let deadline = sys_get_timer().now.saturating_add(ms);
+                sys_set_timer(Some(deadline), TIMER_NOTIFICATION);
+            }
+            None => sys_set_timer(None, TIMER_NOTIFICATION),
+        }
+    }
+}
+
+impl idl::InOrderRtcImpl for ServerImpl {
+    fn now(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<Timestamp, RequestError<RtcError>> {
+        Ok(self.rtc()?.now()?)
+    }
+
+    fn set_time(
+        &mut self,
+        _: &RecvMessage,
+        time: DateTime,
+    ) -> Result<(), RequestError<RtcError>> {
+        if !time.is_valid() {
+            return Err(RtcError::InvalidTime.into());
+        }
+        self.rtc()?.set(&time);
+        ringbuf_entry!(Trace::Set(time));
+        // The clock may have jumped past some alarms, or back from them.
+        self.check_alarms();
+        Ok(())
+    }
+
+    fn set_alarm(
+        &mut self,
+        msg: &RecvMessage,
+        time: DateTime,
+        notification: u32,
+    ) -> Result<(), RequestError<RtcError>> {
+        if !time.is_valid() {
+            return Err(RtcError::InvalidTime.into());
+        }
+        let now = self.rtc()?.now()?;
+        let at = time.unix_seconds();
+        if at <= now.time.unix_seconds() {
+            return Err(RtcError::AlarmInPast.into());
+        }
+
+        let mine = |a: &Option<Alarm>| {
+            a.is_some_and(|a| a.owner.index() == msg.sender.index())
+        };
+        let slot = match self.alarms.iter().position(mine) {
+            Some(i) => i,
+            None => self
+                .alarms
+                .iter()
+                .position(Option::is_none)
+                .ok_or(RtcError::TooManyAlarms)?,
+        };
+        self.alarms[slot] = Some(Alarm {
+            owner: msg.sender,
+            at,
+            notification,
+        });
+        ringbuf_entry!(Trace::Alarm {
+            task: msg.sender.index() as u16,
+            at: time,
+        });
+        self.check_alarms();
+        Ok(())
+    }
+
+    fn cancel_alarm(
+        &mut self,
+        msg: &RecvMessage,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        for slot in &mut self.alarms {
+            if slot.is_some_and(|a| a.owner.index() == msg.sender.index()) {
+                *slot = None;
+            }
+        }
+        self.check_alarms();
+        Ok(())
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        TIMER_NOTIFICATION
+    }
+
+    fn handle_notification(&mut self, bits: u32) {
+        if bits & TIMER_NOTIFICATION != 0 {
+            self.check_alarms();
+        }
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    // SAFETY: we share the RCC with `sys`, but only touch registers it
+    // doesn't.
+    let rcc = unsafe { &*device::RCC::ptr() };
+    let pwr = unsafe { &*device::PWR::ptr() };
+    let regs = unsafe { &*device::RTC::ptr() };
+
+    let rtc = match Rtc::start(regs, rcc, pwr, config::SOURCE) {
+        Ok((rtc, startup)) => {
+            ringbuf_entry!(Trace::Started(startup));
+            Some(rtc)
+        }
+        Err(_) => {
+            ringbuf_entry!(Trace::ClockFailed);
+            None
+        }
+    };
+
+    let mut server = ServerImpl {
+        rtc,
+        alarms: [None; MAX_ALARMS],
+    };
+    let mut buffer = [0; idl::INCOMING_SIZE];
+    loop {
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
+
+mod config {
+    use super::*;
+
+    include!(concat!(env!("OUT_DIR"), "/rtc_config.rs"));
+}
+
+mod idl {
+    use drv_rtc_api::{DateTime, RtcError, Timestamp};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
@@ -0,0 +1,270 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The RTC, and its clock in the backup domain. Registers and bits are from
+//! RM0433 sections 8.7.27 (RCC_BDCR) and 46 (RTC).
+//!
+//! The calendar is kept in BCD, in 24-hour format, with the prescalers set so
+//! that the synchronous prescaler counts 1/256ths of a second (with the LSE).
+
+use crate::device;
+use drv_rtc_api::{DateTime, RtcError, Timestamp};
+use userlib::hl;
+
+const BDCR_LSEON: u32 = 1 << 0;
+const BDCR_LSERDY: u32 = 1 << 1;
+const BDCR_RTCSEL_MASK: u32 = 0b11 << 8;
+const BDCR_RTCSEL_LSE: u32 = 0b01 << 8;
+const BDCR_RTCSEL_LSI: u32 = 0b10 << 8;
+const BDCR_RTCEN: u32 = 1 << 15;
+const BDCR_BDRST: u32 = 1 << 16;
+
+const CSR_LSION: u32 = 1 << 0;
+const CSR_LSIRDY: u32 = 1 << 1;
+
+const ISR_RSF: u32 = 1 << 5;
+const ISR_INITF: u32 = 1 << 6;
+const ISR_INIT: u32 = 1 << 7;
+
+const CR_FMT: u32 = 1 << 6;
+
+/// Asynchronous prescaler, giving 256 Hz from the LSE and 250 Hz from the LSI.
+const PREDIV_A: u32 = 127;
+
+/// The backup register we mark once the calendar has been set. The update
+/// server uses the first few for trial boots.
+const SET_REG: usize = 31;
+const SET_MAGIC: u32 = 0x7274_6321;
+
+/// How many times, 10 ms apart, we check whether the LSE has started. A
+/// crystal can take a couple of seconds.
+const LSE_START_TRIES: u32 = 500;
+
+// Only the configured source is ever constructed.
+#[allow(dead_code)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum Source {
+    /// The 32.768 kHz external crystal, which keeps running on the backup
+    /// battery.
+    Lse,
+    /// The internal 32 kHz oscillator, which is much less accurate, and which
+    /// stops on reset until we start it again.
+    Lsi,
+}
+
+impl Source {
+    fn rtcsel(self) -> u32 {
+        match self {
+            Source::Lse => BDCR_RTCSEL_LSE,
+            Source::Lsi => BDCR_RTCSEL_LSI,
+        }
+    }
+
+    /// Synchronous prescaler, dividing the asynchronous one's output down to
+    /// 1 Hz.
+    fn prediv_s(self) -> u32 {
+        match self {
+            Source::Lse => 255,
+            Source::Lsi => 249,
+        }
+    }
+}
+
+/// How `Rtc::start` found the backup domain.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Startup {
+    /// The RTC was already running from the right clock, and we left it be.
+    Running,
+    /// The RTC wasn't running, and we started it.
+    Started,
+    /// The RTC was running from a different clock; we had to reset the backup
+    /// domain to change it, which lost the time and the backup registers.
+    Reset,
+}
+
+pub struct Rtc {
+    rtc: &'static device::rtc::RegisterBlock,
+    prediv_s: u32,
+}
+
+impl Rtc {
+    /// Opens up the backup domain, and starts the RTC from `source` unless
+    /// it's already running from it -- in which case the calendar has kept
+    /// counting through the reset, and we mustn't disturb it.
+    pub fn start(
+        rtc: &'static device::rtc::RegisterBlock,
+        rcc: &device::rcc::RegisterBlock,
+        pwr: &device::pwr::RegisterBlock,
+        source: Source,
+    ) -> Result<(Self, Startup), RtcError> {
+        pwr.cr1.modify(|_, w| w.dbp().set_bit());
+        while !pwr.cr1.read().dbp().bit() {}
+
+        if source == Source::Lsi {
+            // The LSI isn't in the backup domain, and so is turned off by a
+            // system reset.
+            rcc.csr
+                .modify(|r, w| unsafe { w.bits(r.bits() | CSR_LSION) });
+            while rcc.csr.read().bits() & CSR_LSIRDY == 0 {}
+        }
+
+        let bdcr = rcc.bdcr.read().bits();
+        let sel = bdcr & BDCR_RTCSEL_MASK;
+        let startup = if sel == source.rtcsel() && bdcr & BDCR_RTCEN != 0 {
+            Startup::Running
+        } else {
+            // RTCSEL can only be set once between backup domain resets.
+            let startup = if sel != 0 && sel != source.rtcsel() {
+                rcc.bdcr.write(|w| unsafe { w.bits(BDCR_BDRST) });
+                rcc.bdcr.write(|w| unsafe { w.bits(0) });
+                Startup::Reset
+            } else {
+                Startup::Started
+            };
+            if source == Source::Lse {
+                rcc.bdcr
+                    .modify(|r, w| unsafe { w.bits(r.bits() | BDCR_LSEON) });
+                let mut tries = 0;
+                while rcc.bdcr.read().bits() & BDCR_LSERDY == 0 {
+                    tries += 1;
+                    if tries > LSE_START_TRIES {
+                        return Err(RtcError::ClockFailed);
+                    }
+                    hl::sleep_for(10);
+                }
+            }
+            rcc.bdcr.modify(|r, w| unsafe {
+                w.bits(
+                    r.bits() & !BDCR_RTCSEL_MASK | source.rtcsel() | BDCR_RTCEN,
+                )
+            });
+            startup
+        };
+
+        let this = Self {
+            rtc,
+            prediv_s: source.prediv_s(),
+        };
+        // The shadow registers we read the calendar through were reset along
+        // with us, and need to catch up before they can be trusted.
+        this.unlock();
+        this.resync();
+        this.lock();
+        Ok((this, startup))
+    }
+
+    fn unlock(&self) {
+        self.rtc.wpr.write(|w| unsafe { w.bits(0xCA) });
+        self.rtc.wpr.write(|w| unsafe { w.bits(0x53) });
+    }
+
+    fn lock(&self) {
+        // Any wrong key locks the registers again.
+        self.rtc.wpr.write(|w| unsafe { w.bits(0xFF) });
+    }
+
+    /// Waits for the shadow registers to pick up the calendar. Must be called
+    /// unlocked.
+    fn resync(&self) {
+        self.rtc
+            .isr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !ISR_RSF) });
+        while self.rtc.isr.read().bits() & ISR_RSF == 0 {}
+    }
+
+    /// Checks whether the calendar has been set since the backup domain last
+    /// lost power.
+    pub fn is_set(&self) -> bool {
+        self.backup(SET_REG) == SET_MAGIC
+    }
+
+    /// Reads the calendar.
+    pub fn now(&self) -> Result<Timestamp, RtcError> {
+        if !self.is_set() {
+            return Err(RtcError::NotSet);
+        }
+        // Reading SSR (or TR) freezes the shadow registers until DR is read,
+        // so these three are consistent.
+        let ss = self.rtc.ssr.read().bits() & 0xFFFF;
+        let tr = self.rtc.tr.read().bits();
+        let dr = self.rtc.dr.read().bits();
+
+        let time = DateTime {
+            year: 2000 + u16::from(from_bcd(dr, 16, 0xF)),
+            month: from_bcd(dr, 8, 0x1),
+            day: from_bcd(dr, 0, 0x3),
+            hour: from_bcd(tr, 16, 0x3),
+            minute: from_bcd(tr, 8, 0x7),
+            second: from_bcd(tr, 0, 0x7),
+        };
+        // The sub-second counter counts down from PREDIV_S.
+        let ticks = self.prediv_s.saturating_sub(ss);
+        let micros = ticks * 1_000_000 / (self.prediv_s + 1);
+        Ok(Timestamp { time, micros })
+    }
+
+    /// Sets the calendar, which must be valid.
+    pub fn set(&self, time: &DateTime) {
+        let tr = to_bcd(time.hour) << 16
+            | to_bcd(time.minute) << 8
+            | to_bcd(time.second);
+        let dr = to_bcd((time.year - 2000) as u8) << 16
+            | u32::from(time.weekday()) << 13
+            | to_bcd(time.month) << 8
+            | to_bcd(time.day);
+
+        self.unlock();
+        self.rtc
+            .isr
+            .modify(|r, w| unsafe { w.bits(r.bits() | ISR_INIT) });
+        while self.rtc.isr.read().bits() & ISR_INITF == 0 {}
+
+        // The prescalers take two separate writes, synchronous first.
+        self.rtc.prer.write(|w| unsafe { w.bits(self.prediv_s) });
+        self.rtc
+            .prer
+            .write(|w| unsafe { w.bits(PREDIV_A << 16 | self.prediv_s) });
+        self.rtc
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !CR_FMT) });
+        self.rtc.tr.write(|w| unsafe { w.bits(tr) });
+        self.rtc.dr.write(|w| unsafe { w.bits(dr) });
+
+        self.rtc
+            .isr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !ISR_INIT) });
+        self.resync();
+        self.lock();
+
+        self.set_backup(SET_REG, SET_MAGIC);
+    }
+
+    // RM0433 Rev 7 section 46.6.20: RTC_BKPxR are at 0x100 + 4 * x, and have
+    // no fields for the PAC to name.
+    fn backup_reg(&self, index: usize) -> *mut u32 {
+        (device::RTC::ptr() as usize + 0x100 + 4 * index) as *mut u32
+    }
+
+    fn backup(&self, index: usize) -> u32 {
+        // SAFETY: this is a backup register, which nothing else uses.
+        unsafe { core::ptr::read_volatile(self.backup_reg(index)) }
+    }
+
+    fn set_backup(&self, index: usize, value: u32) {
+        // SAFETY: this is a backup register, which nothing else uses.
+        unsafe { core::ptr::write_volatile(self.backup_reg(index), value) }
+    }
+}
+
+/// Decodes a BCD field with its units at `shift`, and its tens (masked by
+/// `tens`) above them.
+fn from_bcd(reg: u32, shift: u32, tens: u32) -> u8 {
+    let units = (reg >> shift) & 0xF;
+    let tens = (reg >> (shift + 4)) & tens;
+    (tens * 10 + units) as u8
+}
+
+fn to_bcd(n: u8) -> u32 {
+    u32::from(n / 10) << 4 | u32::from(n % 10)
+}
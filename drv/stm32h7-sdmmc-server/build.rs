@@ -0,0 +1,129 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::io::Write;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct TaskConfig {
+    /// Which SDMMC to use, "sdmmc1" or "sdmmc2"
+    sdmmc: String,
+    /// Frequency of the SDMMC kernel clock
+    clock_hz: u32,
+    /// Number of data lines wired to the slot, 1 or 4
+    #[serde(default = "default_bus_width")]
+    bus_width: u8,
+    pins: Vec<PinConfig>,
+    /// Card detect switch, if the slot has one
+    card_detect: Option<CardDetectConfig>,
+}
+
+fn default_bus_width() -> u8 {
+    4
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct PinConfig {
+    port: char,
+    pins: Vec<u8>,
+    af: u8,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct CardDetectConfig {
+    port: char,
+    pin: u8,
+    /// Whether the pin reads high when a card is present; most slots' switches
+    /// pull it low instead
+    #[serde(default)]
+    active_high: bool,
+}
+
+fn generate_sdmmc_config(config: &TaskConfig) -> Result<()> {
+    let title = match config.sdmmc.as_str() {
+        "sdmmc1" => "Sdmmc1",
+        "sdmmc2" => "Sdmmc2",
+        other => bail!("unknown SDMMC {other:?}"),
+    };
+    let wide = match config.bus_width {
+        1 => false,
+        4 => true,
+        other => bail!("unsupported bus width {other}; must be 1 or 4"),
+    };
+    if config.pins.is_empty() {
+        bail!("no pins configured for {}", config.sdmmc);
+    }
+
+    let out = build_util::out_dir().join("sdmmc_config.rs");
+    let mut file = std::fs::File::create(out)?;
+    writeln!(file, "pub const CLOCK_HZ: u32 = {};", config.clock_hz)?;
+    writeln!(file, "pub const WIDE_BUS: bool = {wide};")?;
+    writeln!(
+        file,
+        "pub const PERIPHERAL: Peripheral = Peripheral::{title};"
+    )?;
+    writeln!(file, "pub const PINS: &[(PinSet, Alternate)] = &[")?;
+    for pin in &config.pins {
+        if pin.pins.is_empty() {
+            bail!("empty pin list for port {}", pin.port);
+        }
+        let mut set = format!("Port::{}", pin.port.to_ascii_uppercase());
+        for (i, n) in pin.pins.iter().enumerate() {
+            let f = if i == 0 { "pin" } else { "and_pin" };
+            set.push_str(&format!(".{f}({n})"));
+        }
+        writeln!(file, "    ({set}, Alternate::AF{}),", pin.af)?;
+    }
+    writeln!(file, "];")?;
+    match &config.card_detect {
+        Some(cd) => {
+            if cd.pin > 15 {
+                bail!("card detect pin {} doesn't exist", cd.pin);
+            }
+            writeln!(
+                file,
+                "pub const CARD_DETECT: Option<CardDetect> = \
+                 Some(CardDetect {{ port: Port::{}, pin: {}, \
+                 active_high: {} }});",
+                cd.port.to_ascii_uppercase(),
+                cd.pin,
+                cd.active_high,
+            )?;
+        }
+        None => {
+            writeln!(file, "pub const CARD_DETECT: Option<CardDetect> = None;")?
+        }
+    }
+    writeln!(
+        file,
+        "pub fn registers() -> &'static device::sdmmc1::RegisterBlock {{\n    \
+         unsafe {{ &*device::{}::ptr() }}\n}}",
+        config.sdmmc.to_uppercase(),
+    )?;
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    idol::Generator::new()
+        .with_counters(
+            idol::CounterSettings::default().with_server_counters(false),
+        )
+        .build_server_support(
+            "../../idl/sdmmc.idol",
+            "server_stub.rs",
+            idol::server::ServerStyle::InOrder,
+        )?;
+
+    build_util::build_notifications()?;
+
+    let task_config = build_util::task_config::<TaskConfig>()?;
+    generate_sdmmc_config(&task_config)?;
+
+    Ok(())
+}
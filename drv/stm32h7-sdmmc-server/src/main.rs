@@ -0,0 +1,425 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! SD card server for the STM32H7.
+//!
+//! This task owns one SDMMC controller and the card slot wired to it, and
+//! hands out whole 512-byte blocks of the card over leases. Use the
+//! `drv-sdmmc-api` crate to talk to it; its `Sdmmc` client implements
+//! `block_device::BlockDevice`, so a filesystem task can sit on top.
+//!
+//! Transfers go through the controller's internal DMA, via a buffer here: a
+//! write copies the client's lease into the buffer and cleans it out of the
+//! data cache before the transfer starts, and a read invalidates the buffer
+//! around the transfer before copying it out to the client. The buffer lives
+//! in this task's RAM, which therefore has to be somewhere the controller can
+//! reach -- AXI SRAM, for SDMMC1.
+//!
+//! If the slot has a card detect switch, we watch it, and bring a card up as
+//! soon as it's inserted; otherwise, we bring the card up on the first request
+//! that needs it, and again after it stops responding. Either way, clients can
+//! `watch` for cards coming and going, and `card_info` counts insertions so
+//! that a client can tell that the card has been swapped.
+//!
+//! The task is configured with the controller, its kernel clock, the width of
+//! the data bus, its pins, and the card detect switch, if there is one:
+//!
+//! ```toml
+//! [tasks.sdmmc.config]
+//! sdmmc = "sdmmc1"
+//! clock-hz = 200_000_000
+//! bus-width = 4
+//! pins = [
+//!     { port = "C", pins = [8, 9, 10, 11, 12], af = 12 },
+//!     { port = "D", pins = [2], af = 12 },
+//! ]
+//! card-detect = { port = "G", pin = 2 }
+//! ```
+//!
+//! It needs `uses = ["sdmmc1"]` (or `sdmmc2`), the controller's interrupt
+//! mapped to a notification named `sdmmc-irq`, and a task slot for `sys`.
+//! Bus errors are counted in `__COUNTERS` in the `sdmmc` module, and card
+//! events in `__COUNTERS` here.
+
+#![no_std]
+#![no_main]
+
+mod sdmmc;
+
+use drv_sdmmc_api::{CardInfo, SdmmcError, BLOCK_SIZE, MAX_BLOCKS};
+use drv_stm32xx_sys_api::{
+    Alternate, IrqControl, OutputType, Peripheral, PinSet, Port, Pull,
+    Sensitivity, Speed, Sys,
+};
+use idol_runtime::{
+    ClientError, Leased, LenLimit, NotificationHandler, RequestError, R, W,
+};
+use ringbuf::*;
+use sdmmc::{Card, Sdmmc};
+use userlib::*;
+
+#[cfg(feature = "h743")]
+use stm32h7::stm32h743 as device;
+#[cfg(feature = "h753")]
+use stm32h7::stm32h753 as device;
+
+task_slot!(SYS, sys);
+
+/// Largest transfer, in bytes; this is the size of the DMA buffer, and of the
+/// leases in the IPC interface.
+const MAX_TRANSFER: usize = MAX_BLOCKS * BLOCK_SIZE;
+
+/// Posted by `sys` when the card detect switch changes.
+const CARD_DETECT_NOTIFICATION: u32 = 1 << 30;
+
+/// How long the card detect switch has to settle, in ms.
+const CARD_DETECT_DEBOUNCE_MS: u32 = 50;
+
+/// Most clients that can watch for cards at once.
+const MAX_WATCHERS: usize = 4;
+
+/// A switch that closes when a card is in the slot.
+struct CardDetect {
+    port: Port,
+    pin: u8,
+    /// Whether the pin reads high when a card is present.
+    active_high: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, counters::Count)]
+enum Event {
+    Inserted,
+    Removed,
+    /// A card was found, but couldn't be brought up.
+    InitFailed(#[count(children)] SdmmcError),
+    /// A transfer failed.
+    TransferFailed(#[count(children)] SdmmcError),
+}
+
+counters::counters!(Event);
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Up { blocks: u32, high_capacity: bool },
+    Down,
+    Read { block: u32, count: u8 },
+    Wrote { block: u32, count: u8 },
+    Failed(SdmmcError),
+}
+
+ringbuf!(Trace, 32, Trace::None);
+
+/// The buffer the controller's DMA reads and writes, aligned to a cache line
+/// so that cache maintenance on it doesn't touch anything else.
+#[repr(C, align(32))]
+struct DmaBuffer([u8; MAX_TRANSFER]);
+
+struct ServerImpl {
+    sys: Sys,
+    sdmmc: Sdmmc,
+    buffer: &'static mut DmaBuffer,
+    card: Option<Card>,
+    insertions: u32,
+    watchers: [Option<(TaskId, u32)>; MAX_WATCHERS],
+}
+
+impl ServerImpl {
+    /// Reads the card detect switch, if there is one. With no switch, we
+    /// assume there's a card until it fails to respond.
+    fn card_present(&self) -> bool {
+        match &config::CARD_DETECT {
+            Some(cd) => {
+                let level =
+                    self.sys.gpio_read(cd.port.pin(usize::from(cd.pin))) != 0;
+                level == cd.active_high
+            }
+            None => true,
+        }
+    }
+
+    /// Returns the card, bringing it up first if need be.
+    fn card(&mut self) -> Result<Card, SdmmcError> {
+        if let Some(card) = self.card {
+            return Ok(card);
+        }
+        if !self.card_present() {
+            return Err(SdmmcError::NoCard);
+        }
+
+        // Start from a clean controller, whatever state the last card left
+        // it in.
+        self.sys.enter_reset(config::PERIPHERAL);
+        self.sys.leave_reset(config::PERIPHERAL);
+        match self.sdmmc.init() {
+            Ok(card) => {
+                ringbuf_entry!(Trace::Up {
+                    blocks: card.blocks,
+                    high_capacity: card.high_capacity,
+                });
+                counters::count!(Event::Inserted);
+                self.card = Some(card);
+                self.insertions = self.insertions.wrapping_add(1);
+                self.notify_watchers();
+                Ok(card)
+            }
+            Err(e) => {
+                ringbuf_entry!(Trace::Failed(e));
+                counters::count!(Event::InitFailed(e));
+                self.sdmmc.power_off();
+                Err(e)
+            }
+        }
+    }
+
+    /// Forgets the card, and powers the slot down.
+    fn card_gone(&mut self) {
+        if self.card.take().is_some() {
+            ringbuf_entry!(Trace::Down);
+            counters::count!(Event::Removed);
+            self.notify_watchers();
+        }
+        self.sdmmc.power_off();
+    }
+
+    fn notify_watchers(&self) {
+        for &(task, mask) in self.watchers.iter().flatten() {
+            sys_post(sys_refresh_task_id(task), mask);
+        }
+    }
+
+    /// Moves `len` bytes (a whole number of blocks, checked by the caller)
+    /// between the card, starting at `block`, and the DMA buffer.
+    fn transfer(
+        &mut self,
+        block: u32,
+        len: usize,
+        read: bool,
+    ) -> Result<(), SdmmcError> {
+        let card = self.card()?;
+        let buf = &mut self.buffer.0[..len];
+        if read {
+            // Anything of the buffer's in the cache would otherwise be
+            // written back over the incoming data at some point.
+            kipc::dcache_invalidate(buf);
+        } else {
+            kipc::dcache_clean(buf);
+        }
+
+        let result = self.sdmmc.transfer(&card, block, buf, read);
+        if read {
+            // The CPU may have speculatively fetched lines of the buffer
+            // while the transfer was running.
+            kipc::dcache_invalidate(buf);
+        }
+
+        result.map_err(|e| {
+            ringbuf_entry!(Trace::Failed(e));
+            counters::count!(Event::TransferFailed(e));
+            // A card that's stopped responding has probably been pulled out;
+            // start over with it next time.
+            if matches!(e, SdmmcError::Timeout | SdmmcError::NoCard) {
+                self.card_gone();
+            }
+            e
+        })
+    }
+}
+
+/// Checks that a transfer of `len` bytes is a whole number of blocks.
+fn check_len(len: usize) -> Result<(), SdmmcError> {
+    if len == 0 || len % BLOCK_SIZE != 0 {
+        Err(SdmmcError::BadLength)
+    } else {
+        Ok(())
+    }
+}
+
+impl idl::InOrderSdmmcImpl for ServerImpl {
+    fn card_info(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<CardInfo, RequestError<SdmmcError>> {
+        let card = self.card()?;
+        Ok(CardInfo {
+            blocks: card.blocks,
+            insertions: self.insertions,
+            high_capacity: card.high_capacity as u8,
+            _reserved: [0; 3],
+        })
+    }
+
+    fn read_blocks(
+        &mut self,
+        _: &RecvMessage,
+        block: u32,
+        dest: LenLimit<Leased<W, [u8]>, MAX_TRANSFER>,
+    ) -> Result<(), RequestError<SdmmcError>> {
+        let dest = dest.into_inner();
+        let len = dest.len();
+        check_len(len)?;
+
+        self.transfer(block, len, true)?;
+        dest.write_range(0..len, &self.buffer.0[..len])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        ringbuf_entry!(Trace::Read {
+            block,
+            count: (len / BLOCK_SIZE) as u8,
+        });
+        Ok(())
+    }
+
+    fn write_blocks(
+        &mut self,
+        _: &RecvMessage,
+        block: u32,
+        source: LenLimit<Leased<R, [u8]>, MAX_TRANSFER>,
+    ) -> Result<(), RequestError<SdmmcError>> {
+        let source = source.into_inner();
+        let len = source.len();
+        check_len(len)?;
+
+        source
+            .read_range(0..len, &mut self.buffer.0[..len])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        self.transfer(block, len, false)?;
+        ringbuf_entry!(Trace::Wrote {
+            block,
+            count: (len / BLOCK_SIZE) as u8,
+        });
+        Ok(())
+    }
+
+    fn watch(
+        &mut self,
+        msg: &RecvMessage,
+        notification: u32,
+    ) -> Result<(), RequestError<SdmmcError>> {
+        let mine = |w: &Option<(TaskId, u32)>| {
+            w.is_some_and(|(t, _)| t.index() == msg.sender.index())
+        };
+        let slot = match self.watchers.iter().position(mine) {
+            Some(i) => i,
+            None => self
+                .watchers
+                .iter()
+                .position(Option::is_none)
+                .ok_or(SdmmcError::TooManyWatchers)?,
+        };
+        self.watchers[slot] = Some((msg.sender, notification));
+        Ok(())
+    }
+
+    fn unwatch(
+        &mut self,
+        msg: &RecvMessage,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        for w in &mut self.watchers {
+            if w.is_some_and(|(t, _)| t.index() == msg.sender.index()) {
+                *w = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        if config::CARD_DETECT.is_some() {
+            CARD_DETECT_NOTIFICATION
+        } else {
+            0
+        }
+    }
+
+    fn handle_notification(&mut self, bits: u32) {
+        if bits & CARD_DETECT_NOTIFICATION != 0 {
+            if self.card_present() {
+                // Failures are counted, and the card will be tried again on
+                // the next request.
+                let _ = self.card();
+            } else {
+                self.card_gone();
+            }
+            let _ = self
+                .sys
+                .gpio_irq_control(CARD_DETECT_NOTIFICATION, IrqControl::Enable);
+        }
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let sys = Sys::from(SYS.get_task_id());
+    sys.enable_clock(config::PERIPHERAL);
+    sys.leave_reset(config::PERIPHERAL);
+    for &(pins, af) in config::PINS {
+        sys.gpio_configure_alternate(
+            pins,
+            OutputType::PushPull,
+            Speed::VeryHigh,
+            Pull::Up,
+            af,
+        );
+    }
+
+    if let Some(cd) = &config::CARD_DETECT {
+        let pull = if cd.active_high { Pull::Down } else { Pull::Up };
+        sys.gpio_configure_input(cd.port.pin(usize::from(cd.pin)), pull);
+        sys.gpio_irq_subscribe(
+            cd.port,
+            cd.pin,
+            CARD_DETECT_NOTIFICATION,
+            Sensitivity::Both,
+            CARD_DETECT_DEBOUNCE_MS,
+        )
+        .unwrap_lite();
+        let _ =
+            sys.gpio_irq_control(CARD_DETECT_NOTIFICATION, IrqControl::Enable);
+    }
+
+    let [dma_buffer] = mutable_statics::mutable_statics! {
+        static mut BUFFER: [DmaBuffer; 1] =
+            [|| DmaBuffer([0; MAX_TRANSFER]); _];
+    };
+
+    let mut server = ServerImpl {
+        sys,
+        sdmmc: Sdmmc::new(
+            config::registers(),
+            config::CLOCK_HZ,
+            config::WIDE_BUS,
+            notifications::SDMMC_IRQ_MASK,
+        ),
+        buffer: dma_buffer,
+        card: None,
+        insertions: 0,
+        watchers: [None; MAX_WATCHERS],
+    };
+
+    // If there's already a card in the slot, bring it up now rather than
+    // on the first request.
+    if config::CARD_DETECT.is_some() && server.card_present() {
+        let _ = server.card();
+    }
+
+    let mut buffer = [0; idl::INCOMING_SIZE];
+    loop {
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
+
+mod config {
+    use super::*;
+
+    include!(concat!(env!("OUT_DIR"), "/sdmmc_config.rs"));
+}
+
+mod idl {
+    use drv_sdmmc_api::{CardInfo, SdmmcError};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
@@ -0,0 +1,447 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Register-level access to an SDMMC controller, and the SD card protocol on
+//! top of it. Registers and bits are from RM0433 section 55; commands and
+//! responses are from the SD Physical Layer Simplified Specification.
+//!
+//! Commands are short, so we poll for their completion; data transfers go
+//! through the controller's internal DMA, and we sleep on its interrupt until
+//! they're done.
+
+use crate::device;
+use drv_sdmmc_api::{SdmmcError, BLOCK_SIZE};
+use userlib::{hl, sys_irq_control, sys_recv_notification};
+
+// Bits in STAR, which are also the bits of MASKR and ICR.
+const STA_CCRCFAIL: u32 = 1 << 0;
+const STA_DCRCFAIL: u32 = 1 << 1;
+const STA_CTIMEOUT: u32 = 1 << 2;
+const STA_DTIMEOUT: u32 = 1 << 3;
+const STA_TXUNDERR: u32 = 1 << 4;
+const STA_RXOVERR: u32 = 1 << 5;
+const STA_CMDREND: u32 = 1 << 6;
+const STA_CMDSENT: u32 = 1 << 7;
+const STA_DATAEND: u32 = 1 << 8;
+const STA_BUSYD0: u32 = 1 << 20;
+const STA_IDMATE: u32 = 1 << 27;
+/// Every flag that ICR can clear.
+const STA_ALL: u32 = 0x1FE0_0FFF;
+const STA_DATA_ERRORS: u32 =
+    STA_DCRCFAIL | STA_DTIMEOUT | STA_TXUNDERR | STA_RXOVERR | STA_IDMATE;
+
+const CMDR_CMDTRANS: u32 = 1 << 6;
+const CMDR_CMDSTOP: u32 = 1 << 7;
+const CMDR_WAITRESP_SHIFT: u32 = 8;
+const CMDR_CPSMEN: u32 = 1 << 12;
+
+const CLKCR_WIDBUS_4: u32 = 0b01 << 14;
+/// Stop the card clock rather than let the FIFO overrun or underrun.
+const CLKCR_HWFC_EN: u32 = 1 << 17;
+
+const POWER_ON: u32 = 0b11;
+
+const DCTRL_DTDIR_READ: u32 = 1 << 1;
+/// 2^9 = 512-byte blocks.
+const DCTRL_BLOCK_512: u32 = 9 << 4;
+
+const IDMACTRLR_IDMAEN: u32 = 1 << 0;
+
+/// Bits of an R1 card status that mean something went wrong.
+const R1_ERRORS: u32 = 0xFDFF_E008;
+const R1_OUT_OF_RANGE: u32 = 1 << 31;
+const R1_READY_FOR_DATA: u32 = 1 << 8;
+const R1_STATE_SHIFT: u32 = 9;
+const R1_STATE_TRAN: u32 = 4;
+
+/// Voltage window we ask for in ACMD41: 2.7 to 3.6 V.
+const OCR_VOLTAGES: u32 = 0x00FF_8000;
+/// Host (in ACMD41) or card (in its response) supports block addressing.
+const OCR_HCS: u32 = 1 << 30;
+/// The card has finished powering up.
+const OCR_READY: u32 = 1 << 31;
+
+/// Check pattern and voltage range for CMD8.
+const IF_COND: u32 = 0x1AA;
+
+/// Card clock during identification, which the spec caps at 400 kHz.
+const INIT_HZ: u32 = 400_000;
+/// Card clock for transfers, at default speed.
+const TRANSFER_HZ: u32 = 25_000_000;
+
+/// How long a card gets to respond to a data transfer, in card clocks: 250 ms
+/// at transfer speed, which is the spec's limit for writes.
+const DATA_TIMEOUT: u32 = TRANSFER_HZ / 4;
+
+/// How many times, 10 ms apart, we ask a card if it's finished powering up,
+/// which the spec gives up to a second.
+const POWER_UP_TRIES: u32 = 100;
+
+/// How long, in ms, a card gets to finish programming after a write.
+const PROGRAM_TIMEOUT_MS: u32 = 500;
+
+#[derive(Copy, Clone, PartialEq)]
+#[repr(u32)]
+enum Response {
+    None = 0b00,
+    Short = 0b01,
+    /// A short response without a valid CRC, like the R3 to ACMD41.
+    ShortNoCrc = 0b10,
+    Long = 0b11,
+}
+
+/// Kinds of error seen on the bus, for counting.
+#[derive(Copy, Clone, PartialEq, counters::Count)]
+pub enum BusError {
+    CommandTimeout,
+    CommandCrc,
+    DataTimeout,
+    DataCrc,
+    FifoOverrun,
+    DmaError,
+}
+
+counters::counters!(BusError);
+
+/// A card that's been brought up and selected.
+#[derive(Copy, Clone)]
+pub struct Card {
+    rca: u16,
+    pub high_capacity: bool,
+    pub blocks: u32,
+}
+
+pub struct Sdmmc {
+    regs: &'static device::sdmmc1::RegisterBlock,
+    clock_hz: u32,
+    wide: bool,
+    irq: u32,
+}
+
+impl Sdmmc {
+    /// Wraps a controller whose kernel clock runs at `clock_hz`, and whose
+    /// interrupt is mapped to notification `irq`. The caller must have
+    /// turned on its clock and configured its pins.
+    pub fn new(
+        regs: &'static device::sdmmc1::RegisterBlock,
+        clock_hz: u32,
+        wide: bool,
+        irq: u32,
+    ) -> Self {
+        Self {
+            regs,
+            clock_hz,
+            wide,
+            irq,
+        }
+    }
+
+    fn set_clock(&self, hz: u32, extra: u32) {
+        // SDMMC_CK is the kernel clock divided by twice CLKDIV.
+        let div = self.clock_hz.div_ceil(2 * hz).clamp(1, 0x3FF);
+        self.regs
+            .clkcr
+            .write(|w| unsafe { w.bits(div | CLKCR_HWFC_EN | extra) });
+    }
+
+    /// Cuts power to the card.
+    pub fn power_off(&self) {
+        self.regs.power.write(|w| unsafe { w.bits(0) });
+    }
+
+    /// Sends a command and returns the first word of its response.
+    fn command(
+        &self,
+        index: u8,
+        arg: u32,
+        response: Response,
+        flags: u32,
+    ) -> Result<u32, SdmmcError> {
+        let r = self.regs;
+        r.icr
+            .write(|w| unsafe { w.bits(STA_ALL & !STA_DATA_ERRORS) });
+        r.argr.write(|w| unsafe { w.bits(arg) });
+        r.cmdr.write(|w| unsafe {
+            w.bits(
+                u32::from(index)
+                    | (response as u32) << CMDR_WAITRESP_SHIFT
+                    | CMDR_CPSMEN
+                    | flags,
+            )
+        });
+
+        // The controller gives up on a response after 64 card clocks, so this
+        // doesn't wait long.
+        let done = if response == Response::None {
+            STA_CMDSENT
+        } else {
+            STA_CMDREND | STA_CCRCFAIL | STA_CTIMEOUT
+        };
+        let sta = loop {
+            let sta = r.star.read().bits();
+            if sta & done != 0 {
+                break sta;
+            }
+        };
+        if sta & STA_CTIMEOUT != 0 {
+            counters::count!(BusError::CommandTimeout);
+            return Err(SdmmcError::Timeout);
+        }
+        if sta & STA_CCRCFAIL != 0 {
+            counters::count!(BusError::CommandCrc);
+            return Err(SdmmcError::Crc);
+        }
+        Ok(r.resp1r.read().bits())
+    }
+
+    /// Sends a command with an R1 response, and checks the card status in it.
+    fn command_r1(
+        &self,
+        index: u8,
+        arg: u32,
+        flags: u32,
+    ) -> Result<u32, SdmmcError> {
+        let status = self.command(index, arg, Response::Short, flags)?;
+        if status & R1_OUT_OF_RANGE != 0 {
+            Err(SdmmcError::OutOfRange)
+        } else if status & R1_ERRORS != 0 {
+            Err(SdmmcError::CardError)
+        } else {
+            Ok(status)
+        }
+    }
+
+    /// Sends an application-specific command (that is, CMD55 and then the
+    /// command).
+    fn app_command(
+        &self,
+        rca: u16,
+        index: u8,
+        arg: u32,
+        response: Response,
+    ) -> Result<u32, SdmmcError> {
+        self.command_r1(55, u32::from(rca) << 16, 0)?;
+        self.command(index, arg, response, 0)
+    }
+
+    /// Waits for the card to release DAT0, which it holds low while busy.
+    fn wait_not_busy(&self) {
+        while self.regs.star.read().bits() & STA_BUSYD0 != 0 {}
+    }
+
+    /// Powers up and identifies the card in the slot, and selects it for
+    /// transfers.
+    pub fn init(&self) -> Result<Card, SdmmcError> {
+        self.set_clock(INIT_HZ, 0);
+        self.regs.power.write(|w| unsafe { w.bits(POWER_ON) });
+        // The card needs 74 clocks after power-up before its first command;
+        // at 400 kHz, that's under 200 us.
+        hl::sleep_for(1);
+
+        // From here until it's given an address, a card that doesn't answer
+        // isn't there.
+        let absent = |e: SdmmcError| match e {
+            SdmmcError::Timeout => SdmmcError::NoCard,
+            e => e,
+        };
+
+        self.command(0, 0, Response::None, 0)?;
+
+        // Version 2 cards answer CMD8 by echoing its check pattern; older
+        // ones ignore it.
+        let v2 = match self.command(8, IF_COND, Response::Short, 0) {
+            Ok(r) if r & 0xFFF == IF_COND => true,
+            Ok(_) => return Err(SdmmcError::UnsupportedCard),
+            Err(SdmmcError::Timeout) => false,
+            Err(e) => return Err(e),
+        };
+
+        let hcs = if v2 { OCR_HCS } else { 0 };
+        let mut ocr = 0;
+        for _ in 0..POWER_UP_TRIES {
+            ocr = self
+                .app_command(0, 41, OCR_VOLTAGES | hcs, Response::ShortNoCrc)
+                .map_err(absent)?;
+            if ocr & OCR_READY != 0 {
+                break;
+            }
+            hl::sleep_for(10);
+        }
+        if ocr & OCR_READY == 0 {
+            return Err(SdmmcError::UnsupportedCard);
+        }
+        let high_capacity = ocr & OCR_HCS != 0;
+
+        // ALL_SEND_CID, which we don't need, but which moves the card on to
+        // where it'll accept an address.
+        self.command(2, 0, Response::Long, 0).map_err(absent)?;
+        let rca = (self.command(3, 0, Response::Short, 0).map_err(absent)?
+            >> 16) as u16;
+
+        self.command(9, u32::from(rca) << 16, Response::Long, 0)?;
+        let r = self.regs;
+        let csd = [
+            r.resp1r.read().bits(),
+            r.resp2r.read().bits(),
+            r.resp3r.read().bits(),
+            r.resp4r.read().bits(),
+        ];
+        let blocks = csd_blocks(&csd).ok_or(SdmmcError::UnsupportedCard)?;
+
+        self.command_r1(7, u32::from(rca) << 16, 0)?;
+        self.wait_not_busy();
+
+        if !high_capacity {
+            self.command_r1(16, BLOCK_SIZE as u32, 0)?;
+        }
+        let widbus = if self.wide {
+            // Argument 2 selects a 4-bit bus.
+            self.app_command(rca, 6, 2, Response::Short)?;
+            CLKCR_WIDBUS_4
+        } else {
+            0
+        };
+        self.set_clock(TRANSFER_HZ, widbus);
+
+        Ok(Card {
+            rca,
+            high_capacity,
+            blocks,
+        })
+    }
+
+    /// Reads (if `read`) or writes the blocks starting at `block` from or to
+    /// `buf`, which must be a whole number of blocks in memory the
+    /// controller's DMA can reach. Keeping the data cache out of the way is up
+    /// to the caller.
+    pub fn transfer(
+        &self,
+        card: &Card,
+        block: u32,
+        buf: &mut [u8],
+        read: bool,
+    ) -> Result<(), SdmmcError> {
+        let count = buf.len() / BLOCK_SIZE;
+        match block.checked_add(count as u32) {
+            Some(end) if end <= card.blocks => (),
+            _ => return Err(SdmmcError::OutOfRange),
+        }
+        // Standard capacity cards are addressed in bytes.
+        let addr = if card.high_capacity {
+            block
+        } else {
+            block * BLOCK_SIZE as u32
+        };
+
+        let r = self.regs;
+        r.dtimer.write(|w| unsafe { w.bits(DATA_TIMEOUT) });
+        r.dlenr.write(|w| unsafe { w.bits(buf.len() as u32) });
+        r.idmabase0r
+            .write(|w| unsafe { w.bits(buf.as_mut_ptr() as u32) });
+        r.idmactrlr.write(|w| unsafe { w.bits(IDMACTRLR_IDMAEN) });
+        let dir = if read { DCTRL_DTDIR_READ } else { 0 };
+        r.dctrl.write(|w| unsafe { w.bits(DCTRL_BLOCK_512 | dir) });
+        r.icr.write(|w| unsafe { w.bits(STA_ALL) });
+        r.maskr
+            .write(|w| unsafe { w.bits(STA_DATAEND | STA_DATA_ERRORS) });
+
+        let multiple = count > 1;
+        let index = match (read, multiple) {
+            (true, false) => 17,
+            (true, true) => 18,
+            (false, false) => 24,
+            (false, true) => 25,
+        };
+        let result = self
+            .command_r1(index, addr, CMDR_CMDTRANS)
+            .and_then(|_| self.wait_data());
+
+        r.maskr.write(|w| unsafe { w.bits(0) });
+        r.idmactrlr.write(|w| unsafe { w.bits(0) });
+        if result.is_err() {
+            r.dctrl.write(|w| unsafe { w.bits(0) });
+        }
+
+        // A multiple block transfer runs until it's told to stop, which we
+        // must do even if it went wrong.
+        if multiple {
+            let stop = self.command_r1(12, 0, CMDR_CMDSTOP);
+            self.wait_not_busy();
+            result?;
+            stop?;
+        } else {
+            result?;
+        }
+
+        if !read {
+            self.wait_programmed(card)?;
+        }
+        Ok(())
+    }
+
+    /// Sleeps until the data path finishes, and reports how it went.
+    fn wait_data(&self) -> Result<(), SdmmcError> {
+        let sta = loop {
+            let sta = self.regs.star.read().bits();
+            if sta & (STA_DATAEND | STA_DATA_ERRORS) != 0 {
+                break sta;
+            }
+            sys_irq_control(self.irq, true);
+            sys_recv_notification(self.irq);
+        };
+        if sta & STA_DTIMEOUT != 0 {
+            counters::count!(BusError::DataTimeout);
+            Err(SdmmcError::Timeout)
+        } else if sta & STA_DCRCFAIL != 0 {
+            counters::count!(BusError::DataCrc);
+            Err(SdmmcError::Crc)
+        } else if sta & (STA_TXUNDERR | STA_RXOVERR) != 0 {
+            counters::count!(BusError::FifoOverrun);
+            Err(SdmmcError::Dma)
+        } else if sta & STA_IDMATE != 0 {
+            counters::count!(BusError::DmaError);
+            Err(SdmmcError::Dma)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Waits for the card to finish programming written blocks and come back
+    /// to the transfer state.
+    fn wait_programmed(&self, card: &Card) -> Result<(), SdmmcError> {
+        for _ in 0..PROGRAM_TIMEOUT_MS {
+            let status = self.command_r1(13, u32::from(card.rca) << 16, 0)?;
+            let state = (status >> R1_STATE_SHIFT) & 0xF;
+            if status & R1_READY_FOR_DATA != 0 && state == R1_STATE_TRAN {
+                return Ok(());
+            }
+            hl::sleep_for(1);
+        }
+        Err(SdmmcError::Timeout)
+    }
+}
+
+/// Works out the number of 512-byte blocks on a card from its CSD register,
+/// given as the four response words, most significant first.
+fn csd_blocks(csd: &[u32; 4]) -> Option<u32> {
+    match csd[0] >> 30 {
+        // Version 1: capacity is (C_SIZE + 1) * 2^(C_SIZE_MULT + 2) blocks of
+        // 2^READ_BL_LEN bytes.
+        0 => {
+            let read_bl_len = (csd[1] >> 16) & 0xF;
+            let c_size = (csd[1] & 0x3FF) << 2 | csd[2] >> 30;
+            let c_size_mult = (csd[2] >> 15) & 0x7;
+            if !(9..=11).contains(&read_bl_len) {
+                return None;
+            }
+            Some((c_size + 1) << (c_size_mult + 2) << (read_bl_len - 9))
+        }
+        // Version 2: capacity is (C_SIZE + 1) * 512 KiB.
+        1 => {
+            let c_size = (csd[1] & 0x3F) << 16 | csd[2] >> 16;
+            (c_size + 1).checked_mul(1024)
+        }
+        _ => None,
+    }
+}
@@ -301,6 +301,19 @@ impl SpiServerCore {
         }
     }
 
+    /// Forcibly releases the lock, on behalf of the server rather than the
+    /// holder, returning the task that held it. Like `release`, this
+    /// deasserts CS.
+    pub fn revoke(&self) -> Option<TaskId> {
+        let lockstate = self.lock_holder.take()?;
+
+        for pin in CONFIG.devices[lockstate.device_index].cs {
+            self.sys.gpio_set(*pin);
+        }
+
+        Some(lockstate.task)
+    }
+
     fn ready_writey<'b, BufRead: BufReader<'b>, BufWrite: BufWriter<'b>>(
         &self,
         op: SpiOperation,
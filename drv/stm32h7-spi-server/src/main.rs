@@ -6,6 +6,21 @@
 //!
 //! This is a thin wrapper around `stm32h7-spi-server-core`, which can be used
 //! both in this task and embedded into other tasks.
+//!
+//! # Locking and fairness
+//!
+//! While a task holds the lock, we only receive from that task; everyone else
+//! queues up in the kernel, and is served in priority order once the lock is
+//! released. A task that takes the lock and sits on it can therefore keep the
+//! bus from everyone else indefinitely. To bound that, a task can take the
+//! lock with a lease (`lock_lease`): if it's still holding the lock when the
+//! lease runs out, we revoke the lock and go back to serving whoever is
+//! waiting. The revoked task's next transfer faults, rather than carrying on
+//! with CS in the wrong state.
+//!
+//! To help find out who is hogging the bus, we count lock grants, releases and
+//! revocations for each task, and record how long each lock was held in our
+//! ringbuf.
 
 #![no_std]
 #![no_main]
@@ -21,6 +36,8 @@ use userlib::*;
 
 use drv_stm32h7_spi_server_core::SpiServerCore;
 use drv_stm32xx_sys_api as sys_api;
+use hubris_num_tasks::NUM_TASKS;
+use ringbuf::*;
 
 task_slot!(SYS, sys);
 
@@ -29,6 +46,40 @@ task_slot!(SYS, sys);
 // the FIFO depth; for simplicity we set:
 const BUFSIZ: usize = 16;
 
+const TIMER_NOTIFICATION: u32 = 1 << 31;
+
+#[derive(Copy, Clone, PartialEq, counters::Count)]
+enum LockEvent {
+    Granted,
+    Released,
+    Revoked,
+    Died,
+}
+
+type LockCounters = <LockEvent as counters::Count>::Counters;
+
+/// Lock events, by task index.
+#[used]
+static LOCK_COUNTERS: [LockCounters; NUM_TASKS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NEW: LockCounters = <LockEvent as counters::Count>::NEW_COUNTERS;
+    [NEW; NUM_TASKS]
+};
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    /// A lock was given up by (or taken from) a task, after being held for
+    /// this many milliseconds.
+    Held {
+        task: u16,
+        ms: u64,
+    },
+    Revoked(TaskId),
+    None,
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
 #[export_name = "main"]
 fn main() -> ! {
     let sys = sys_api::Sys::from(SYS.get_task_id());
@@ -36,7 +87,12 @@ fn main() -> ! {
         sys,
         notifications::SPI_IRQ_MASK
     );
-    let mut server = ServerImpl { core };
+    let mut server = ServerImpl {
+        core,
+        held_since: 0,
+        lease_deadline: None,
+        revoked: None,
+    };
     let mut incoming = [0u8; INCOMING_SIZE];
     loop {
         idol_runtime::dispatch(&mut incoming, &mut server);
@@ -45,6 +101,86 @@ fn main() -> ! {
 
 struct ServerImpl {
     core: SpiServerCore,
+
+    /// When the current lock was taken.
+    held_since: u64,
+
+    /// When the current lock's lease runs out, if it has one.
+    lease_deadline: Option<u64>,
+
+    /// A task whose lock we revoked, and which hasn't sent us anything since.
+    revoked: Option<TaskId>,
+}
+
+impl ServerImpl {
+    fn take_lock(
+        &mut self,
+        sender: TaskId,
+        devidx: u8,
+        cs_state: CsState,
+        lease_ms: Option<u32>,
+    ) -> Result<(), RequestError<Infallible>> {
+        let relock = self.core.recv_source().is_some();
+
+        self.core.lock(sender, devidx, cs_state).map_err(|_| {
+            idol_runtime::ClientError::BadMessageContents.fail()
+        })?;
+
+        if self.revoked == Some(sender) {
+            self.revoked = None;
+        }
+
+        let now = sys_get_timer().now;
+
+        if !relock {
+            self.held_since = now;
+            counters::count!(
+                LOCK_COUNTERS[usize::from(sender.index())],
+                LockEvent::Granted
+            );
+        }
+
+        //
+        // A lease runs from when it was asked for. Relocking with a plain
+        // `lock` (to change CS state, say) leaves any lease in place.
+        //
+        if let Some(ms) = lease_ms {
+            let deadline = now.saturating_add(u64::from(ms));
+            self.lease_deadline = Some(deadline);
+            sys_set_timer(Some(deadline), TIMER_NOTIFICATION);
+        }
+
+        Ok(())
+    }
+
+    /// Bookkeeping for the end of a lock held by `task`, however it ended.
+    fn unlocked(&mut self, task: TaskId, event: LockEvent) {
+        if self.lease_deadline.take().is_some() {
+            sys_set_timer(None, TIMER_NOTIFICATION);
+        }
+
+        let ms = sys_get_timer().now.saturating_sub(self.held_since);
+        ringbuf_entry!(Trace::Held {
+            task: task.index(),
+            ms
+        });
+        counters::count!(LOCK_COUNTERS[usize::from(task.index())], event);
+    }
+
+    ///
+    /// Faults a task whose lock we revoked if it tries to carry on as though
+    /// it still had it.
+    ///
+    fn check_revoked(
+        &mut self,
+        sender: TaskId,
+    ) -> Result<(), RequestError<SpiError>> {
+        if self.revoked == Some(sender) {
+            self.revoked = None;
+            return Err(idol_runtime::ClientError::BadMessageContents.fail());
+        }
+        Ok(())
+    }
 }
 
 impl InOrderSpiImpl for ServerImpl {
@@ -53,15 +189,19 @@ impl InOrderSpiImpl for ServerImpl {
     }
 
     fn closed_recv_fail(&mut self) {
+        if let Some(task) = self.core.recv_source() {
+            self.unlocked(task, LockEvent::Died);
+        }
         self.core.closed_recv_fail()
     }
 
     fn read(
         &mut self,
-        _: &RecvMessage,
+        rm: &RecvMessage,
         device_index: u8,
         dest: LenLimit<Leased<W, [u8]>, 65535>,
     ) -> Result<(), RequestError<SpiError>> {
+        self.check_revoked(rm.sender)?;
         self.core
             .read::<LeaseBufWriter<_, BUFSIZ>>(
                 device_index,
@@ -72,10 +212,11 @@ impl InOrderSpiImpl for ServerImpl {
 
     fn write(
         &mut self,
-        _: &RecvMessage,
+        rm: &RecvMessage,
         device_index: u8,
         src: LenLimit<Leased<R, [u8]>, 65535>,
     ) -> Result<(), RequestError<SpiError>> {
+        self.check_revoked(rm.sender)?;
         self.core
             .write::<LeaseBufReader<_, BUFSIZ>>(
                 device_index,
@@ -86,11 +227,12 @@ impl InOrderSpiImpl for ServerImpl {
 
     fn exchange(
         &mut self,
-        _: &RecvMessage,
+        rm: &RecvMessage,
         device_index: u8,
         src: LenLimit<Leased<R, [u8]>, 65535>,
         dest: LenLimit<Leased<W, [u8]>, 65535>,
     ) -> Result<(), RequestError<SpiError>> {
+        self.check_revoked(rm.sender)?;
         self.core
             .exchange::<LeaseBufReader<_, BUFSIZ>, LeaseBufWriter<_, BUFSIZ>>(
                 device_index,
@@ -106,29 +248,61 @@ impl InOrderSpiImpl for ServerImpl {
         devidx: u8,
         cs_state: CsState,
     ) -> Result<(), RequestError<Infallible>> {
-        self.core
-            .lock(rm.sender, devidx, cs_state)
-            .map_err(|_| idol_runtime::ClientError::BadMessageContents.fail())
+        self.take_lock(rm.sender, devidx, cs_state, None)
+    }
+
+    fn lock_lease(
+        &mut self,
+        rm: &RecvMessage,
+        devidx: u8,
+        cs_state: CsState,
+        lease_ms: u32,
+    ) -> Result<(), RequestError<Infallible>> {
+        self.take_lock(rm.sender, devidx, cs_state, Some(lease_ms))
     }
 
     fn release(
         &mut self,
         rm: &RecvMessage,
     ) -> Result<(), RequestError<Infallible>> {
-        self.core
-            .release(rm.sender)
-            .map_err(|_| idol_runtime::ClientError::BadMessageContents.fail())
+        //
+        // A task whose lock we revoked doesn't know that yet; let it release
+        // the lock it thinks it has.
+        //
+        if self.revoked == Some(rm.sender) {
+            self.revoked = None;
+            return Ok(());
+        }
+
+        self.core.release(rm.sender).map_err(|_| {
+            idol_runtime::ClientError::BadMessageContents.fail()
+        })?;
+        self.unlocked(rm.sender, LockEvent::Released);
+        Ok(())
     }
 }
 
 impl NotificationHandler for ServerImpl {
     fn current_notification_mask(&self) -> u32 {
-        // We don't use notifications, don't listen for any.
-        0
+        // We only need the timer, and only while a lease is running.
+        if self.lease_deadline.is_some() {
+            TIMER_NOTIFICATION
+        } else {
+            0
+        }
     }
 
     fn handle_notification(&mut self, _bits: u32) {
-        unreachable!()
+        match self.lease_deadline {
+            Some(deadline) if sys_get_timer().now >= deadline => (),
+            _ => return,
+        }
+
+        if let Some(task) = self.core.revoke() {
+            ringbuf_entry!(Trace::Revoked(task));
+            self.unlocked(task, LockEvent::Revoked);
+            self.revoked = Some(task);
+        }
     }
 }
 
@@ -7,7 +7,8 @@
 #![deny(elided_lifetimes_in_paths)]
 
 use attest_api::{
-    AttestError, HashAlgorithm, NONCE_MAX_SIZE, NONCE_MIN_SIZE, TQ_HASH_SIZE,
+    AttestError, FirmwareVersions, HashAlgorithm, NONCE_MAX_SIZE,
+    NONCE_MIN_SIZE, TQ_HASH_SIZE,
 };
 use drv_lpc55_update_api::{
     RotBootInfo, RotComponent, RotPage, SlotId, SwitchDuration, UpdateTarget,
@@ -1527,6 +1528,27 @@ impl<S: SpiServer> idl::InOrderSpRotImpl for ServerImpl<S> {
             Err(e) => Err(AttestOrSprotError::Sprot(e).into()),
         }
     }
+
+    fn firmware_versions(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<FirmwareVersions, idol_runtime::RequestError<AttestOrSprotError>>
+    {
+        let body = ReqBody::Attest(AttestReq::FirmwareVersions);
+        let tx_size = Request::pack(&body, self.tx_buf);
+        let rsp = self.do_send_recv_retries(tx_size, TIMEOUT_QUICK, 1)?;
+        match rsp.body {
+            Ok(RspBody::Attest(Ok(AttestRsp::FirmwareVersions(v)))) => Ok(v),
+            Ok(RspBody::Attest(Err(e))) => {
+                Err(AttestOrSprotError::Attest(e).into())
+            }
+            Ok(RspBody::Attest(_)) | Ok(_) => Err(AttestOrSprotError::Sprot(
+                SprotError::Protocol(SprotProtocolError::UnexpectedResponse),
+            )
+            .into()),
+            Err(e) => Err(AttestOrSprotError::Sprot(e).into()),
+        }
+    }
 }
 
 impl<S: SpiServer> NotificationHandler for ServerImpl<S> {
@@ -1543,10 +1565,11 @@ impl<S: SpiServer> NotificationHandler for ServerImpl<S> {
 
 mod idl {
     use super::{
-        AttestOrSprotError, DumpOrSprotError, HashAlgorithm, LifecycleState,
-        PulseStatus, RawCabooseOrSprotError, RotBootInfo, RotComponent,
-        RotPage, RotState, SlotId, SprotError, SprotIoStats, SprotStatus,
-        StateOrSprotError, SwitchDuration, UpdateTarget, VersionedRotBootInfo,
+        AttestOrSprotError, DumpOrSprotError, FirmwareVersions, HashAlgorithm,
+        LifecycleState, PulseStatus, RawCabooseOrSprotError, RotBootInfo,
+        RotComponent, RotPage, RotState, SlotId, SprotError, SprotIoStats,
+        SprotStatus, StateOrSprotError, SwitchDuration, UpdateTarget,
+        VersionedRotBootInfo,
     };
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
@@ -0,0 +1,112 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::io::Write;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct TaskConfig {
+    /// Which USART or UART to use, e.g. "usart3" or "uart7"
+    usart: String,
+    baud_rate: u32,
+    /// Frequency of the USART's kernel clock
+    clock_hz: u32,
+    /// Whether to use RTS/CTS hardware flow control; the RTS and CTS pins
+    /// must then be included in `pins`
+    #[serde(default)]
+    flow_control: bool,
+    pins: Vec<PinConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct PinConfig {
+    port: char,
+    pins: Vec<u8>,
+    af: u8,
+}
+
+/// DMAMUX1 request line for each USART's receiver (RM0433 table 121).
+fn rx_request(usart: &str) -> Option<u8> {
+    Some(match usart {
+        "usart1" => 41,
+        "usart2" => 43,
+        "usart3" => 45,
+        "uart4" => 63,
+        "uart5" => 65,
+        "usart6" => 71,
+        "uart7" => 79,
+        "uart8" => 81,
+        _ => return None,
+    })
+}
+
+fn generate_uart_config(config: &TaskConfig) -> Result<()> {
+    let Some(request) = rx_request(&config.usart) else {
+        bail!("unknown USART {:?}", config.usart);
+    };
+    if config.pins.is_empty() {
+        bail!("no pins configured for {}", config.usart);
+    }
+
+    // "usart3" becomes USART3 and Usart3.
+    let upper = config.usart.to_uppercase();
+    let title = format!("{}{}", &upper[..1], &config.usart[1..]);
+
+    let out = build_util::out_dir().join("uart_config.rs");
+    let mut file = std::fs::File::create(out)?;
+    writeln!(file, "pub const BAUD_RATE: u32 = {};", config.baud_rate)?;
+    writeln!(file, "pub const CLOCK_HZ: u32 = {};", config.clock_hz)?;
+    writeln!(
+        file,
+        "pub const FLOW_CONTROL: bool = {};",
+        config.flow_control
+    )?;
+    writeln!(file, "pub const DMA_REQUEST: u8 = {request};")?;
+    writeln!(
+        file,
+        "pub const PERIPHERAL: Peripheral = Peripheral::{title};"
+    )?;
+    writeln!(file, "pub const PINS: &[(PinSet, Alternate)] = &[")?;
+    for pin in &config.pins {
+        if pin.pins.is_empty() {
+            bail!("empty pin list for port {}", pin.port);
+        }
+        let mut set = format!("Port::{}", pin.port.to_ascii_uppercase());
+        for (i, n) in pin.pins.iter().enumerate() {
+            let f = if i == 0 { "pin" } else { "and_pin" };
+            set.push_str(&format!(".{f}({n})"));
+        }
+        writeln!(file, "    ({set}, Alternate::AF{}),", pin.af)?;
+    }
+    writeln!(file, "];")?;
+    writeln!(
+        file,
+        "pub fn registers() -> &'static device::usart1::RegisterBlock {{\n    \
+         unsafe {{ &*device::{upper}::ptr() }}\n}}"
+    )?;
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    idol::Generator::new()
+        .with_counters(
+            idol::CounterSettings::default().with_server_counters(false),
+        )
+        .build_server_support(
+            "../../idl/uart.idol",
+            "server_stub.rs",
+            idol::server::ServerStyle::InOrder,
+        )?;
+
+    build_util::build_notifications()?;
+
+    let task_config = build_util::task_config::<TaskConfig>()?;
+    generate_uart_config(&task_config)?;
+
+    Ok(())
+}
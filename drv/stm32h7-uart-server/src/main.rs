@@ -0,0 +1,304 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! UART server for the STM32H7.
+//!
+//! This task owns one USART, and receives from it continuously: a circular
+//! stream from the DMA manager lands received bytes in the DMA manager's ring,
+//! and we move them into a larger ring of our own whenever half of the DMA
+//! ring has filled or the line goes idle. Clients read from our ring, waiting
+//! (with a timeout) if it's empty. This means a client that's slow to read --
+//! a console, say, or a telemetry logger that's busy writing to flash --
+//! doesn't cost us bytes until our whole ring has filled.
+//!
+//! Once our ring doesn't have room for another DMA ring's worth of data, we
+//! stop the USART making DMA requests until a client has read enough to make
+//! room. Received bytes then pile up in the USART's FIFO; with hardware flow
+//! control configured, the USART deasserts RTS when the FIFO fills, which
+//! holds off the sender and loses nothing. Without it, the FIFO overruns, and
+//! the next read reports `Overrun`.
+//!
+//! Transmission goes through the TX FIFO directly; `write` returns once the
+//! last byte is in the FIFO.
+//!
+//! The task is configured with the USART, its baud rate and kernel clock, and
+//! its pins:
+//!
+//! ```toml
+//! [tasks.uart.config]
+//! usart = "usart3"
+//! baud-rate = 3_000_000
+//! clock-hz = 100_000_000
+//! flow-control = true
+//! pins = [{ port = "D", pins = [8, 9, 11, 12], af = 7 }]
+//! ```
+//!
+//! It needs the USART's interrupt mapped to a notification named `usart-irq`,
+//! a notification named `dma` for the DMA manager to post, and task slots for
+//! `sys` and `dma`.
+
+#![no_std]
+#![no_main]
+
+use drv_stm32h7_dma_api::{Dma, DmaError, MAX_TRANSFER};
+use drv_stm32h7_usart::{device, Usart};
+use drv_stm32xx_sys_api::{Alternate, Peripheral, PinSet, Port, Sys};
+use drv_uart_api::UartError;
+use heapless::Deque;
+use idol_runtime::{
+    ClientError, Leased, LenLimit, NotificationHandler, RequestError, R, W,
+};
+use ringbuf::*;
+use userlib::*;
+
+task_slot!(SYS, sys);
+task_slot!(DMA, dma);
+
+/// Size of our receive ring, in bytes.
+const RING_SIZE: usize = 2048;
+
+const TIMER_NOTIFICATION: u32 = 1 << 31;
+
+#[derive(Copy, Clone, PartialEq, counters::Count)]
+enum Event {
+    /// The USART's RX FIFO overran.
+    UartOverrun,
+    /// We didn't drain the DMA ring before it lapped us.
+    DmaOverrun,
+    /// We stopped taking bytes from the USART, because our ring was full.
+    Paused,
+    Resumed,
+}
+
+counters::counters!(Event);
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Received(u16),
+    Read(u16),
+    Wrote(u16),
+    DrainFailed(DmaError),
+}
+
+ringbuf!(Trace, 32, Trace::None);
+
+struct ServerImpl {
+    uart: Usart,
+    dma: Dma,
+    stream: u8,
+    ring: Deque<u8, RING_SIZE>,
+    /// Whether we have stopped the USART's DMA requests for want of room.
+    paused: bool,
+    /// Whether received data has been lost since the last read.
+    lost: bool,
+}
+
+impl ServerImpl {
+    /// Moves whatever the DMA stream has received into our ring, as far as
+    /// it will fit.
+    fn pull(&mut self) {
+        if self.uart.check_and_clear_rx_overrun() {
+            counters::count!(Event::UartOverrun);
+            self.lost = true;
+        }
+
+        let mut buf = [0u8; MAX_TRANSFER];
+        loop {
+            let room = (RING_SIZE - self.ring.len()).min(MAX_TRANSFER);
+            if room == 0 {
+                break;
+            }
+            let n = match self.dma.drain(self.stream, &mut buf[..room]) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(DmaError::Overrun) => {
+                    counters::count!(Event::DmaOverrun);
+                    self.lost = true;
+                    continue;
+                }
+                Err(e) => {
+                    // The DMA manager has restarted, or the stream has
+                    // failed; either way our stream is gone, and so are we.
+                    ringbuf_entry!(Trace::DrainFailed(e));
+                    panic!();
+                }
+            };
+            ringbuf_entry!(Trace::Received(n as u16));
+            for &b in &buf[..n] {
+                // We never take more than there's room for.
+                let _ = self.ring.push_back(b);
+            }
+        }
+
+        self.update_flow();
+    }
+
+    /// Stops or restarts the USART's DMA requests, depending on whether
+    /// there's room in our ring for another DMA ring's worth of data.
+    fn update_flow(&mut self) {
+        let room = RING_SIZE - self.ring.len();
+        if !self.paused && room < MAX_TRANSFER {
+            self.uart.disable_rx_dma();
+            self.paused = true;
+            counters::count!(Event::Paused);
+        } else if self.paused && room >= MAX_TRANSFER {
+            self.uart.enable_rx_dma();
+            self.paused = false;
+            counters::count!(Event::Resumed);
+        }
+    }
+
+    /// Waits for something to happen, and deals with it.
+    fn wait(&mut self, extra: u32) {
+        let bits = sys_recv_notification(
+            notifications::USART_IRQ_MASK | notifications::DMA_MASK | extra,
+        );
+        self.handle_notification(bits);
+    }
+}
+
+impl idl::InOrderUartImpl for ServerImpl {
+    fn read(
+        &mut self,
+        _: &RecvMessage,
+        timeout_ms: u32,
+        dest: LenLimit<Leased<W, [u8]>, 256>,
+    ) -> Result<usize, RequestError<UartError>> {
+        let dest = dest.into_inner();
+        let deadline =
+            sys_get_timer().now.saturating_add(u64::from(timeout_ms));
+
+        loop {
+            self.pull();
+            if self.lost {
+                self.lost = false;
+                return Err(UartError::Overrun.into());
+            }
+            if !self.ring.is_empty() {
+                break;
+            }
+            if sys_get_timer().now >= deadline {
+                return Ok(0);
+            }
+            sys_set_timer(Some(deadline), TIMER_NOTIFICATION);
+            self.wait(TIMER_NOTIFICATION);
+        }
+        sys_set_timer(None, TIMER_NOTIFICATION);
+
+        let n = self.ring.len().min(dest.len());
+        let (a, b) = self.ring.as_slices();
+        let first = n.min(a.len());
+        dest.write_range(0..first, &a[..first])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        dest.write_range(first..n, &b[..n - first])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        for _ in 0..n {
+            self.ring.pop_front();
+        }
+        ringbuf_entry!(Trace::Read(n as u16));
+
+        self.update_flow();
+        Ok(n)
+    }
+
+    fn write(
+        &mut self,
+        _: &RecvMessage,
+        source: LenLimit<Leased<R, [u8]>, 256>,
+    ) -> Result<(), RequestError<UartError>> {
+        let source = source.into_inner();
+        let mut buf = [0u8; 256];
+        let buf = &mut buf[..source.len()];
+        source
+            .read_range(0..buf.len(), buf)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+
+        for &byte in buf.iter() {
+            while !self.uart.try_tx_push(byte) {
+                // The FIFO is full; wait for it to empty, keeping up with
+                // reception while we do.
+                self.uart.enable_tx_fifo_empty_interrupt();
+                self.wait(0);
+                self.uart.disable_tx_fifo_empty_interrupt();
+            }
+        }
+        ringbuf_entry!(Trace::Wrote(buf.len() as u16));
+        Ok(())
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::USART_IRQ_MASK | notifications::DMA_MASK
+    }
+
+    fn handle_notification(&mut self, bits: u32) {
+        if bits & notifications::USART_IRQ_MASK != 0 {
+            // The line going idle means the end of a burst, which may not
+            // have filled half of the DMA ring; go and get it.
+            if self.uart.check_and_clear_idle() {
+                self.pull();
+            }
+            sys_irq_control(notifications::USART_IRQ_MASK, true);
+        }
+        if bits & notifications::DMA_MASK != 0 {
+            self.pull();
+        }
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let sys = Sys::from(SYS.get_task_id());
+    let uart = Usart::turn_on(
+        &sys,
+        config::registers(),
+        config::PERIPHERAL,
+        config::PINS,
+        config::CLOCK_HZ,
+        config::BAUD_RATE,
+        config::FLOW_CONTROL,
+    );
+    // The DMA stream takes received bytes, so we don't want an interrupt for
+    // each one; just for the line going idle.
+    uart.disable_rx_interrupt();
+    uart.enable_idle_interrupt();
+
+    let dma = Dma::from(DMA.get_task_id());
+    let stream = dma
+        .claim(config::DMA_REQUEST, notifications::DMA_MASK)
+        .unwrap_lite();
+    dma.start_circular_read(stream, uart.rdr_address(), 1)
+        .unwrap_lite();
+    uart.enable_rx_dma();
+    sys_irq_control(notifications::USART_IRQ_MASK, true);
+
+    let mut server = ServerImpl {
+        uart,
+        dma,
+        stream,
+        ring: Deque::new(),
+        paused: false,
+        lost: false,
+    };
+    let mut buffer = [0; idl::INCOMING_SIZE];
+    loop {
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
+
+mod config {
+    use super::*;
+
+    include!(concat!(env!("OUT_DIR"), "/uart_config.rs"));
+}
+
+mod idl {
+    use drv_uart_api::UartError;
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
@@ -43,6 +43,36 @@ pub enum SlotId {
     Inactive = 1,
 }
 
+/// Where a trial boot stands.
+#[derive(
+    Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub enum TrialState {
+    /// No image is on trial.
+    None,
+    /// The next boot will try the image in the inactive bank.
+    Armed,
+    /// The running image is on trial, and will be rolled back unless it's
+    /// committed; it has started `boots` times.
+    Running { boots: u32 },
+}
+
+/// A run of chunks in a chunked update, from `start` up to but not including
+/// `end`.
+#[derive(
+    Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize, SerializedSize,
+)]
+pub struct ChunkRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl ChunkRange {
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+}
+
 impl TryFrom<u16> for SlotId {
     type Error = ();
     fn try_from(i: u16) -> Result<Self, Self::Error> {
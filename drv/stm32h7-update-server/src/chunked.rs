@@ -0,0 +1,262 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Chunked updates.
+//!
+//! A chunked update streams the image in chunks of `BLOCK_SIZE_BYTES`, in any
+//! order, each with its own CRC-32. A chunk either carries its data, or names
+//! an offset in the running image to copy it from, so an update that leaves
+//! most of the image alone only has to send what changed.
+//!
+//! Progress survives resets and restarts of this task. The last sector of
+//! bank 2 holds a header naming the image, followed by a log with a record
+//! for each chunk written. (A flash word can only be programmed once between
+//! erases, so a log of words is how we keep a bitmap in flash.) Starting the
+//! same update again rebuilds the bitmap from the log, checking each chunk it
+//! names against the CRC it logged, and carries on from there.
+//!
+//! The catch is that a chunk interrupted while it was being written can't be
+//! written again until its sector is erased, and that takes any finished
+//! chunks in the sector with it. Picking up an update finds those sectors, so
+//! that they can be erased before anything else happens.
+
+use crate::{__REGION_BANK1_BASE, __REGION_BANK1_END, CRC32};
+use crate::{__REGION_BANK2_BASE, __REGION_BANK2_END};
+use drv_stm32h7_update_api::{ChunkRange, BLOCK_SIZE_BYTES, FLASH_WORD_BYTES};
+
+/// Flash sectors are 128 KiB (RM0433 Rev 7 section 4.3.6).
+pub const SECTOR_BYTES: usize = 128 * 1024;
+
+/// The most chunks an image can have: bank 2 is 1 MiB on every part we
+/// support, and the last sector is the log.
+pub const MAX_CHUNKS: usize = (1024 * 1024 - SECTOR_BYTES) / BLOCK_SIZE_BYTES;
+
+const WORDS: usize = FLASH_WORD_BYTES / 4;
+pub type Record = [u32; WORDS];
+
+const SLOTS: usize = SECTOR_BYTES / FLASH_WORD_BYTES;
+const ERASED: Record = [!0; WORDS];
+
+const HEADER_MAGIC: u32 = 0x6368_6b48;
+const CHUNK_MAGIC: u32 = 0x6368_6b43;
+
+/// Where things are in the two banks.
+pub struct Layout {
+    running: &'static [u8],
+    bank: usize,
+    log: usize,
+}
+
+impl Layout {
+    pub fn new() -> Self {
+        // SAFETY: these are symbols populated by the linker.
+        let running_start = unsafe { __REGION_BANK1_BASE.as_ptr() } as usize;
+        let running_end = unsafe { __REGION_BANK1_END.as_ptr() } as usize;
+        let bank = unsafe { __REGION_BANK2_BASE.as_ptr() } as usize;
+        let bank_end = unsafe { __REGION_BANK2_END.as_ptr() } as usize;
+
+        // SAFETY: bank 1 is an extern region of ours, and is only ever
+        // written by a debugger.
+        let running = unsafe {
+            core::slice::from_raw_parts(
+                running_start as *const u8,
+                running_end - running_start,
+            )
+        };
+        Self {
+            running,
+            bank,
+            log: bank_end - SECTOR_BYTES,
+        }
+    }
+
+    /// How many chunks an image can have.
+    pub fn max_chunks(&self) -> usize {
+        ((self.log - self.bank) / BLOCK_SIZE_BYTES).min(MAX_CHUNKS)
+    }
+
+    /// Returns `len` bytes of the running image, starting at `offset`, if
+    /// they're all in bank 1.
+    pub fn running(&self, offset: usize, len: usize) -> Option<&'static [u8]> {
+        self.running.get(offset..offset.checked_add(len)?)
+    }
+
+    /// Returns the first `len` bytes of `chunk` in bank 2.
+    pub fn chunk(&self, chunk: usize, len: usize) -> &'static [u8] {
+        let start = self.bank + chunk * BLOCK_SIZE_BYTES;
+        // SAFETY: callers only ask for chunks below `max_chunks`, which are
+        // within bank 2.
+        unsafe { core::slice::from_raw_parts(start as *const u8, len) }
+    }
+
+    /// Returns the flash word number, within bank 2, of slot `slot` in the
+    /// log.
+    pub fn slot_word(&self, slot: usize) -> usize {
+        (self.log - self.bank) / FLASH_WORD_BYTES + slot
+    }
+
+    /// Returns the sector of bank 2 that holds `chunk`.
+    pub fn sector_of(&self, chunk: usize) -> u8 {
+        (chunk * BLOCK_SIZE_BYTES / SECTOR_BYTES) as u8
+    }
+
+    fn slot(&self, slot: usize) -> Record {
+        let addr = (self.log + slot * FLASH_WORD_BYTES) as *const Record;
+        // SAFETY: `slot` is below `SLOTS`, so this is within the log.
+        unsafe { core::ptr::read_volatile(addr) }
+    }
+}
+
+/// Where a chunked update stands.
+pub struct Progress {
+    pub image_len: u32,
+    pub image_crc: u32,
+    chunks: usize,
+    done: [u32; MAX_CHUNKS.div_ceil(32)],
+    next_slot: usize,
+}
+
+impl Progress {
+    /// Starts tracking an update that has nothing written yet. The log must
+    /// be erased, and `header()` written to slot 0 of it.
+    pub fn new(
+        layout: &Layout,
+        image_len: u32,
+        image_crc: u32,
+    ) -> Option<Self> {
+        let chunks = (image_len as usize).div_ceil(BLOCK_SIZE_BYTES);
+        if image_len == 0 || chunks > layout.max_chunks() {
+            return None;
+        }
+        Some(Self {
+            image_len,
+            image_crc,
+            chunks,
+            done: [0; MAX_CHUNKS.div_ceil(32)],
+            next_slot: 1,
+        })
+    }
+
+    /// Picks up the update of the given image from the log, if the log is for
+    /// that image and has room to finish it. Also returns a bitmask of the
+    /// sectors that need erasing, for `forget_sector()`.
+    pub fn resume(
+        layout: &Layout,
+        image_len: u32,
+        image_crc: u32,
+    ) -> Option<(Self, u8)> {
+        let mut p = Self::new(layout, image_len, image_crc)?;
+        if layout.slot(0) != p.header() {
+            return None;
+        }
+
+        // Records go in order, so the first erased slot is the end of the
+        // log. A slot that isn't erased but isn't a record either was being
+        // written when we lost power; its chunk is caught below.
+        while p.next_slot < SLOTS {
+            let r = layout.slot(p.next_slot);
+            if r == ERASED {
+                break;
+            }
+            p.next_slot += 1;
+
+            let chunk = r[1] as usize;
+            if r[0] != CHUNK_MAGIC || r[3] != !r[2] || chunk >= p.chunks {
+                continue;
+            }
+            // A chunk in a sector erased since it was logged no longer
+            // matches its record.
+            let data = layout.chunk(chunk, p.chunk_len(chunk));
+            if CRC32.checksum(data) == r[2] {
+                p.done[chunk / 32] |= 1 << (chunk % 32);
+            }
+        }
+
+        // Every chunk gets logged at most once more, which needs room.
+        if SLOTS - p.next_slot < p.chunks {
+            return None;
+        }
+
+        let mut dirty = 0u8;
+        for chunk in 0..p.chunks {
+            if !p.is_done(chunk)
+                && !is_erased(layout.chunk(chunk, BLOCK_SIZE_BYTES))
+            {
+                dirty |= 1 << layout.sector_of(chunk);
+            }
+        }
+        Some((p, dirty))
+    }
+
+    /// The record that names the image, in slot 0 of the log.
+    pub fn header(&self) -> Record {
+        let mut r = ERASED;
+        r[0] = HEADER_MAGIC;
+        r[1] = self.image_len;
+        r[2] = self.image_crc;
+        r[3] = !self.image_crc;
+        r
+    }
+
+    pub fn chunks(&self) -> usize {
+        self.chunks
+    }
+
+    /// How many bytes of the image are in `chunk`; the last may be short.
+    pub fn chunk_len(&self, chunk: usize) -> usize {
+        let start = chunk * BLOCK_SIZE_BYTES;
+        (self.image_len as usize - start).min(BLOCK_SIZE_BYTES)
+    }
+
+    pub fn is_done(&self, chunk: usize) -> bool {
+        self.done[chunk / 32] & (1 << (chunk % 32)) != 0
+    }
+
+    pub fn is_complete(&self) -> bool {
+        (0..self.chunks).all(|c| self.is_done(c))
+    }
+
+    /// Marks `chunk`, whose data has the given CRC, as written, and returns
+    /// the record to log and the slot to put it in.
+    pub fn finish_chunk(&mut self, chunk: usize, crc: u32) -> (usize, Record) {
+        let mut r = ERASED;
+        r[0] = CHUNK_MAGIC;
+        r[1] = chunk as u32;
+        r[2] = crc;
+        r[3] = !crc;
+
+        let slot = self.next_slot;
+        self.next_slot += 1;
+        self.done[chunk / 32] |= 1 << (chunk % 32);
+        (slot, r)
+    }
+
+    /// Forgets the chunks in `sector`, because it's been erased.
+    pub fn forget_sector(&mut self, layout: &Layout, sector: u8) {
+        for chunk in 0..self.chunks {
+            if layout.sector_of(chunk) == sector {
+                self.done[chunk / 32] &= !(1 << (chunk % 32));
+            }
+        }
+    }
+
+    /// Returns the first run of chunks not yet written at or after `from`,
+    /// which is empty if there are none.
+    pub fn next_needed(&self, from: usize) -> ChunkRange {
+        let start = (from..self.chunks)
+            .find(|&c| !self.is_done(c))
+            .unwrap_or(self.chunks);
+        let end = (start..self.chunks)
+            .find(|&c| self.is_done(c))
+            .unwrap_or(self.chunks);
+        ChunkRange {
+            start: start as u32,
+            end: end as u32,
+        }
+    }
+}
+
+fn is_erased(data: &[u8]) -> bool {
+    data.iter().all(|&b| b == 0xff)
+}
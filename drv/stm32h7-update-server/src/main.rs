@@ -9,11 +9,16 @@
 #![no_std]
 #![no_main]
 
+mod chunked;
+mod trial;
+
+use chunked::{Layout, Progress};
 use core::convert::Infallible;
+use crc::{Crc, CRC_32_ISO_HDLC};
 use drv_caboose::{CabooseError, CabooseReader};
 use drv_stm32h7_update_api::{
-    ImageVersion, SlotId, BLOCK_SIZE_BYTES, FLASH_WORDS_PER_BLOCK,
-    FLASH_WORD_BYTES,
+    ChunkRange, ImageVersion, SlotId, TrialState, BLOCK_SIZE_BYTES,
+    FLASH_WORDS_PER_BLOCK, FLASH_WORD_BYTES,
 };
 use drv_update_api::UpdateError;
 use idol_runtime::{
@@ -21,9 +26,13 @@ use idol_runtime::{
 };
 use ringbuf::*;
 use stm32h7::stm32h753 as device;
+use task_jefe_api::Jefe;
+use trial::{BackupRegs, Trial};
 use userlib::*;
 use zerocopy::AsBytes;
 
+task_slot!(JEFE, jefe);
+
 // Internally we deal with flash blocks in groups of u32 words.
 const FLASH_WORD_WORDS: usize = FLASH_WORD_BYTES / 4;
 
@@ -37,10 +46,19 @@ const FLASH_KEY2: u32 = 0xCDEF_89AB;
 const FLASH_OPT_KEY1: u32 = 0x0819_2A3B;
 const FLASH_OPT_KEY2: u32 = 0x4C5D_6E7F;
 
+// The image header is at a fixed location at the end of the vector table. The
+// length of the vector table is fixed in hardware, so this should never change.
+const HEADER_OFFSET: u32 = 0x298;
+
+const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
 extern "C" {
     // Symbols injected by the linker.
     //
-    // This requires adding `extern-regions = ["bank2"]` to the task config
+    // This requires adding `extern-regions = ["bank1", "bank2"]` to the task
+    // config
+    pub static mut __REGION_BANK1_BASE: [u32; 0];
+    pub static mut __REGION_BANK1_END: [u32; 0];
     pub static mut __REGION_BANK2_BASE: [u32; 0];
     pub static mut __REGION_BANK2_END: [u32; 0];
 }
@@ -49,11 +67,19 @@ extern "C" {
 enum Trace {
     EraseStart,
     EraseEnd,
+    SectorErase(u8),
     WriteStart,
     WriteEnd,
     FinishStart,
     FinishEnd,
     WriteBlock(usize),
+    ChunkedStart { resumed: bool },
+    WriteChunk(usize),
+    TrialArmed,
+    TrialBoot(u32),
+    TrialCommitted,
+    TrialEnded,
+    RollBack,
     None,
 }
 
@@ -69,6 +95,11 @@ struct ServerImpl<'a> {
     flash: &'a device::flash::RegisterBlock,
     state: UpdateState,
     pending: SlotId,
+    backup: BackupRegs,
+    trial: TrialState,
+    layout: Layout,
+    /// Where a chunked update stands, if that's the kind under way
+    progress: Option<Progress>,
 }
 
 impl<'a> ServerImpl<'a> {
@@ -102,6 +133,56 @@ impl<'a> ServerImpl<'a> {
         Ok(())
     }
 
+    /// Returns the image staged in bank 2, if its header says it fits there.
+    fn staged_image(&self) -> Result<&'static [u8], UpdateError> {
+        // SAFETY: these are symbols populated by the linker.
+        let image_start = unsafe { __REGION_BANK2_BASE.as_ptr() } as u32;
+        let bank_end = unsafe { __REGION_BANK2_END.as_ptr() } as u32;
+
+        // SAFETY: the header is within bank 2.
+        let header: ImageHeader = unsafe {
+            core::ptr::read_volatile(
+                (image_start + HEADER_OFFSET) as *const ImageHeader,
+            )
+        };
+        if header.magic != HEADER_MAGIC {
+            return Err(UpdateError::MissingHeaderBlock);
+        }
+        let len = header.total_image_len;
+        if len > bank_end - image_start {
+            return Err(UpdateError::InvalidHeaderBlock);
+        }
+
+        // SAFETY: we've checked that this is within bank 2.
+        Ok(unsafe {
+            core::slice::from_raw_parts(image_start as *const u8, len as usize)
+        })
+    }
+
+    /// Stops tracking a trial, because it's over one way or another.
+    fn end_trial(&mut self) {
+        ringbuf_entry!(Trace::TrialEnded);
+        self.backup.clear();
+        sys_set_timer(None, notifications::TIMER_MASK);
+        self.trial = TrialState::None;
+    }
+
+    /// Points the next boot back at the image that was running before the
+    /// trial, and resets.
+    fn roll_back(&mut self) -> ! {
+        ringbuf_entry!(Trace::RollBack);
+        // Swap first: if we lose power after clearing the record but before
+        // swapping, the image on trial would be kept.
+        if self.pending == SlotId::Active {
+            // Swapping banks has no way to fail, despite its signature.
+            let _ = self.swap_banks();
+        }
+        self.backup.clear();
+        Jefe::from(JEFE.get_task_id()).request_reset();
+        // If `request_reset()` returns, something has gone very wrong.
+        panic!();
+    }
+
     fn poll_flash_done(&mut self) -> Result<(), RequestError<UpdateError>> {
         // This method should implement step 5 of the Single Write Sequence from
         // RM0433 Rev 7 section 4.3.9, which states
@@ -253,8 +334,15 @@ impl<'a> ServerImpl<'a> {
             .modify(|_, w| w.clr_rdperr().set_bit().clr_rdserr().set_bit());
     }
 
-    fn bank_erase(&mut self) -> Result<(), RequestError<UpdateError>> {
-        ringbuf_entry!(Trace::EraseStart);
+    /// Erases one sector of bank 2, or all of it if `sector` is `None`.
+    fn erase(
+        &mut self,
+        sector: Option<u8>,
+    ) -> Result<(), RequestError<UpdateError>> {
+        ringbuf_entry!(match sector {
+            Some(n) => Trace::SectorErase(n),
+            None => Trace::EraseStart,
+        });
 
         self.clear_errors();
         // Enable relevant interrupts for completion (or failure) of erasing
@@ -275,10 +363,19 @@ impl<'a> ServerImpl<'a> {
                 .set_bit()
         });
 
-        self.flash
-            .bank2()
-            .cr
-            .modify(|_, w| w.start().set_bit().ber().set_bit());
+        match sector {
+            Some(n) => {
+                self.flash.bank2().cr.modify(|_, w| {
+                    // SAFETY: there are eight sectors, and the field is three
+                    // bits wide, so every value names one.
+                    unsafe { w.snb().bits(n) }.ser().set_bit().ber().clear_bit()
+                });
+                self.flash.bank2().cr.modify(|_, w| w.start().set_bit());
+            }
+            None => self.flash.bank2().cr.modify(|_, w| {
+                w.start().set_bit().ber().set_bit().ser().clear_bit()
+            }),
+        }
 
         // Wait for EOP notification via interrupt.
         loop {
@@ -294,6 +391,84 @@ impl<'a> ServerImpl<'a> {
         ringbuf_entry!(Trace::EraseEnd);
         b
     }
+
+    /// Erases a sector of bank 2, and forgets any chunks written to it.
+    fn erase_sector(
+        &mut self,
+        sector: u8,
+    ) -> Result<(), RequestError<UpdateError>> {
+        self.erase(Some(sector))?;
+        if let Some(progress) = &mut self.progress {
+            progress.forget_sector(&self.layout, sector);
+        }
+        Ok(())
+    }
+
+    /// Checks that a chunked update is under way and that `chunk` is one of
+    /// its chunks, and returns the chunk's index and length.
+    fn chunk_to_write(
+        &self,
+        chunk: u32,
+    ) -> Result<(usize, usize), RequestError<UpdateError>> {
+        match self.state {
+            UpdateState::NoUpdate => {
+                return Err(UpdateError::UpdateNotStarted.into())
+            }
+            UpdateState::Finished => {
+                return Err(UpdateError::UpdateAlreadyFinished.into())
+            }
+            UpdateState::InProgress => (),
+        }
+        let Some(progress) = &self.progress else {
+            // The update under way is a block-by-block one.
+            return Err(UpdateError::UpdateInProgress.into());
+        };
+        let chunk = chunk as usize;
+        if chunk >= progress.chunks() {
+            return Err(UpdateError::OutOfBounds.into());
+        }
+        Ok((chunk, progress.chunk_len(chunk)))
+    }
+
+    /// Writes a chunk of a chunked update, whose first `len` bytes should
+    /// match `crc`, and logs it.
+    fn program_chunk(
+        &mut self,
+        chunk: usize,
+        len: usize,
+        crc: u32,
+        page: &[[u32; FLASH_WORD_WORDS]; FLASH_WORDS_PER_BLOCK],
+    ) -> Result<(), RequestError<UpdateError>> {
+        if CRC32.checksum(&page.as_bytes()[..len]) != crc {
+            return Err(UpdateError::ChunkCrcMismatch.into());
+        }
+        if self.progress.as_ref().is_some_and(|p| p.is_done(chunk)) {
+            // A client that missed our reply will send the chunk again.
+            return if CRC32.checksum(self.layout.chunk(chunk, len)) == crc {
+                Ok(())
+            } else {
+                Err(UpdateError::ChunkCrcMismatch.into())
+            };
+        }
+
+        ringbuf_entry!(Trace::WriteChunk(chunk));
+        for (i, fw) in page.iter().enumerate() {
+            if let Err(e) =
+                self.write_word(chunk * FLASH_WORDS_PER_BLOCK + i, fw)
+            {
+                // The part of the chunk we wrote can't be written again until
+                // its sector is erased.
+                self.erase_sector(self.layout.sector_of(chunk))?;
+                return Err(e);
+            }
+        }
+
+        if let Some(progress) = &mut self.progress {
+            let (slot, record) = progress.finish_chunk(chunk, crc);
+            self.write_word(self.layout.slot_word(slot), &record)?;
+        }
+        Ok(())
+    }
 }
 
 impl idl::InOrderUpdateImpl for ServerImpl<'_> {
@@ -302,6 +477,11 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
         _: &RecvMessage,
         slot: SlotId,
     ) -> Result<(), RequestError<UpdateError>> {
+        // Choosing a slot outright settles any trial: choosing the running
+        // image on trial keeps it, and choosing the other rolls it back.
+        if self.trial != TrialState::None {
+            self.end_trial();
+        }
         if slot != self.pending {
             self.swap_banks()?;
         }
@@ -328,9 +508,14 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
             }
             UpdateState::NoUpdate => (),
         }
+        // Bank 2 holds either the image we're about to try or the one to
+        // roll back to.
+        if self.trial != TrialState::None {
+            return Err(UpdateError::UpdateInProgress.into());
+        }
 
         self.unlock();
-        self.bank_erase()?;
+        self.erase(None)?;
         self.state = UpdateState::InProgress;
         Ok(())
     }
@@ -349,6 +534,9 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
             UpdateState::InProgress => (),
         }
 
+        // A chunked update's log stays in flash, so beginning it again picks
+        // up where it left off.
+        self.progress = None;
         self.state = UpdateState::NoUpdate;
         Ok(())
     }
@@ -368,6 +556,9 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
             }
             UpdateState::InProgress => (),
         }
+        if self.progress.is_some() {
+            return Err(UpdateError::UpdateInProgress.into());
+        }
 
         let len = block.len();
         // While our input arrives as unstructured borrowed bytes, we want to
@@ -416,6 +607,16 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
             UpdateState::InProgress => (),
         }
 
+        if let Some(progress) = &self.progress {
+            if !progress.is_complete() {
+                return Err(UpdateError::ChunksMissing.into());
+            }
+            let image = self.layout.chunk(0, progress.image_len as usize);
+            if CRC32.checksum(image) != progress.image_crc {
+                return Err(UpdateError::ImageMismatch.into());
+            }
+        }
+
         self.state = UpdateState::Finished;
         Ok(())
     }
@@ -451,10 +652,7 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
         // flashed into the other slot, delimited by `__REGION_BANK2_BASE` and
         // `__REGION_BASE2_END` (which are symbols injected by the linker).
         //
-        // We'll first want to read the image header, which is at a fixed
-        // location at the end of the vector table.  The length of the vector
-        // table is fixed in hardware, so this should never change.
-        const HEADER_OFFSET: u32 = 0x298;
+        // We'll first want to read the image header.
         let header: ImageHeader = unsafe {
             core::ptr::read_volatile(
                 (image_start + HEADER_OFFSET) as *const ImageHeader,
@@ -537,16 +735,170 @@ impl idl::InOrderUpdateImpl for ServerImpl<'_> {
 
         Ok(chunk.len() as u32)
     }
+
+    fn arm_trial_boot(
+        &mut self,
+        _: &RecvMessage,
+        timeout_ms: u32,
+        crc: u32,
+    ) -> Result<(), RequestError<UpdateError>> {
+        match self.state {
+            UpdateState::NoUpdate => {
+                return Err(UpdateError::UpdateNotStarted.into())
+            }
+            UpdateState::InProgress => {
+                return Err(UpdateError::UpdateInProgress.into())
+            }
+            UpdateState::Finished => (),
+        }
+        if self.trial != TrialState::None {
+            return Err(UpdateError::UpdateInProgress.into());
+        }
+
+        let image = self.staged_image()?;
+        if CRC32.checksum(image) != crc {
+            return Err(UpdateError::ImageMismatch.into());
+        }
+
+        ringbuf_entry!(Trace::TrialArmed);
+        self.backup.write(&Trial {
+            swap_bank: !self.flash.optcr().read().swap_bank().bit(),
+            boots: 0,
+            timeout_ms,
+        });
+        if self.pending != SlotId::Inactive {
+            self.swap_banks()?;
+        }
+        self.trial = TrialState::Armed;
+        Ok(())
+    }
+
+    fn commit_image(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<(), RequestError<Infallible>> {
+        if let TrialState::Running { .. } = self.trial {
+            ringbuf_entry!(Trace::TrialCommitted);
+            self.end_trial();
+        }
+        Ok(())
+    }
+
+    fn begin_chunked_update(
+        &mut self,
+        _: &RecvMessage,
+        image_len: u32,
+        image_crc: u32,
+    ) -> Result<(), RequestError<UpdateError>> {
+        match self.state {
+            UpdateState::InProgress => {
+                return Err(UpdateError::UpdateInProgress.into())
+            }
+            UpdateState::Finished => {
+                return Err(UpdateError::UpdateAlreadyFinished.into())
+            }
+            UpdateState::NoUpdate => (),
+        }
+        if self.trial != TrialState::None {
+            return Err(UpdateError::UpdateInProgress.into());
+        }
+
+        self.unlock();
+        match Progress::resume(&self.layout, image_len, image_crc) {
+            Some((progress, dirty)) => {
+                ringbuf_entry!(Trace::ChunkedStart { resumed: true });
+                self.progress = Some(progress);
+                for sector in 0..u8::BITS as u8 {
+                    if dirty & (1 << sector) != 0 {
+                        self.erase_sector(sector)?;
+                    }
+                }
+            }
+            None => {
+                let progress =
+                    Progress::new(&self.layout, image_len, image_crc)
+                        .ok_or(UpdateError::BadLength)?;
+                ringbuf_entry!(Trace::ChunkedStart { resumed: false });
+                self.erase(None)?;
+                self.write_word(self.layout.slot_word(0), &progress.header())?;
+                self.progress = Some(progress);
+            }
+        }
+        self.state = UpdateState::InProgress;
+        Ok(())
+    }
+
+    fn write_chunk(
+        &mut self,
+        _: &RecvMessage,
+        chunk: u32,
+        crc: u32,
+        data: LenLimit<Leased<R, [u8]>, BLOCK_SIZE_BYTES>,
+    ) -> Result<(), RequestError<UpdateError>> {
+        let (chunk, len) = self.chunk_to_write(chunk)?;
+        if data.len() != len {
+            return Err(UpdateError::BadLength.into());
+        }
+
+        let mut flash_page: [[u32; FLASH_WORD_WORDS]; FLASH_WORDS_PER_BLOCK] =
+            [[0; FLASH_WORD_WORDS]; FLASH_WORDS_PER_BLOCK];
+        data.read_range(0..len, &mut flash_page.as_bytes_mut()[..len])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        self.program_chunk(chunk, len, crc, &flash_page)
+    }
+
+    fn copy_chunk(
+        &mut self,
+        _: &RecvMessage,
+        chunk: u32,
+        offset: u32,
+        crc: u32,
+    ) -> Result<(), RequestError<UpdateError>> {
+        let (chunk, len) = self.chunk_to_write(chunk)?;
+        let src = self
+            .layout
+            .running(offset as usize, len)
+            .ok_or(UpdateError::OutOfBounds)?;
+
+        let mut flash_page: [[u32; FLASH_WORD_WORDS]; FLASH_WORDS_PER_BLOCK] =
+            [[0; FLASH_WORD_WORDS]; FLASH_WORDS_PER_BLOCK];
+        flash_page.as_bytes_mut()[..len].copy_from_slice(src);
+        self.program_chunk(chunk, len, crc, &flash_page)
+    }
+
+    fn next_needed_chunks(
+        &mut self,
+        _: &RecvMessage,
+        from: u32,
+    ) -> Result<ChunkRange, RequestError<UpdateError>> {
+        match &self.progress {
+            Some(progress) => Ok(progress.next_needed(from as usize)),
+            None => Err(UpdateError::UpdateNotStarted.into()),
+        }
+    }
+
+    fn trial_state(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<TrialState, RequestError<Infallible>> {
+        Ok(self.trial)
+    }
 }
 
 impl NotificationHandler for ServerImpl<'_> {
     fn current_notification_mask(&self) -> u32 {
-        // We don't use notifications, don't listen for any.
-        0
+        // Our timer only runs while an image is on trial.
+        match self.trial {
+            TrialState::Running { .. } => notifications::TIMER_MASK,
+            _ => 0,
+        }
     }
 
-    fn handle_notification(&mut self, _bits: u32) {
-        unreachable!()
+    fn handle_notification(&mut self, bits: u32) {
+        if bits & notifications::TIMER_MASK != 0 {
+            // The image on trial wasn't committed in time.
+            self.roll_back();
+        }
     }
 }
 
@@ -568,11 +920,44 @@ fn main() -> ! {
         SlotId::Inactive
     };
 
+    // SAFETY: we only use PWR to enable writes to the backup registers.
+    let backup = BackupRegs::new(unsafe { &*device::PWR::ptr() });
+
     let mut server = ServerImpl {
         flash,
         state: UpdateState::NoUpdate,
         pending,
+        backup,
+        trial: TrialState::None,
+        layout: Layout::new(),
+        progress: None,
     };
+
+    if let Some(mut trial) = server.backup.read() {
+        if trial.swap_bank == flash.optcr().read().swap_bank().bit() {
+            // We're the image on trial.
+            trial.boots += 1;
+            ringbuf_entry!(Trace::TrialBoot(trial.boots));
+            if trial.boots > trial::MAX_BOOTS {
+                server.roll_back();
+            }
+            server.backup.write(&trial);
+            server.trial = TrialState::Running { boots: trial.boots };
+            if trial.timeout_ms != 0 {
+                sys_set_timer(
+                    Some(sys_get_timer().now + u64::from(trial.timeout_ms)),
+                    notifications::TIMER_MASK,
+                );
+            }
+        } else if pending == SlotId::Inactive {
+            // We're the image that armed the trial, and it's yet to boot.
+            server.trial = TrialState::Armed;
+        } else {
+            // A rollback lost power between swapping banks back and
+            // clearing the record; there's nothing left to track.
+            server.backup.clear();
+        }
+    }
     let mut incoming = [0u8; idl::INCOMING_SIZE];
 
     loop {
@@ -582,7 +967,7 @@ fn main() -> ! {
 
 include!(concat!(env!("OUT_DIR"), "/consts.rs"));
 mod idl {
-    use super::{CabooseError, ImageVersion, SlotId};
+    use super::{CabooseError, ChunkRange, ImageVersion, SlotId, TrialState};
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
@@ -0,0 +1,96 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Trial boots.
+//!
+//! Arming a trial records it in RTC backup registers, which survive a system
+//! reset, and points the next boot at the other bank. When the update server
+//! in the trial image starts, it finds the record and counts a boot. Unless
+//! someone commits the image before the timeout, or if it starts more than
+//! `MAX_BOOTS` times without being committed, we point the next boot back at
+//! the previous image and reset.
+//!
+//! The backup registers are lost if main and battery power both go away; an
+//! image on trial when that happens is treated as committed.
+
+use stm32h7::stm32h753 as device;
+
+/// How many times a trial image may start without being committed.
+///
+/// Every start of this task counts, so a trial image in which the update
+/// server crashes is also rolled back.
+pub const MAX_BOOTS: u32 = 1;
+
+// The low bit of the state register is the `SWAP_BANK` setting that the trial
+// boots with, which is how the trial image recognizes itself.
+const MAGIC: u32 = 0x7472_6900;
+const MAGIC_MASK: u32 = !1;
+
+const STATE_REG: usize = 0;
+const BOOTS_REG: usize = 1;
+const TIMEOUT_REG: usize = 2;
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct Trial {
+    /// The `SWAP_BANK` setting that boots the image on trial
+    pub swap_bank: bool,
+    /// How many times the image on trial has started
+    pub boots: u32,
+    /// How long the image on trial has to be committed, in milliseconds, or
+    /// zero for no limit beyond `MAX_BOOTS`
+    pub timeout_ms: u32,
+}
+
+pub struct BackupRegs(());
+
+impl BackupRegs {
+    /// Enables writes to the backup domain.
+    ///
+    /// The RTC's APB clock is enabled out of reset, so we don't need to ask
+    /// `sys` for it.
+    pub fn new(pwr: &device::pwr::RegisterBlock) -> Self {
+        pwr.cr1.modify(|_, w| w.dbp().set_bit());
+        while !pwr.cr1.read().dbp().bit() {}
+        Self(())
+    }
+
+    pub fn read(&self) -> Option<Trial> {
+        let state = self.get(STATE_REG);
+        if state & MAGIC_MASK != MAGIC {
+            return None;
+        }
+        Some(Trial {
+            swap_bank: state & 1 != 0,
+            boots: self.get(BOOTS_REG),
+            timeout_ms: self.get(TIMEOUT_REG),
+        })
+    }
+
+    pub fn write(&self, trial: &Trial) {
+        // The state register goes last, so that we never see a torn record.
+        self.set(BOOTS_REG, trial.boots);
+        self.set(TIMEOUT_REG, trial.timeout_ms);
+        self.set(STATE_REG, MAGIC | u32::from(trial.swap_bank));
+    }
+
+    pub fn clear(&self) {
+        self.set(STATE_REG, 0);
+    }
+
+    // RM0433 Rev 7 section 46.6.20: RTC_BKPxR are at 0x100 + 4 * x, and have
+    // no fields for the PAC to name.
+    fn reg(&self, index: usize) -> *mut u32 {
+        (device::RTC::ptr() as usize + 0x100 + 4 * index) as *mut u32
+    }
+
+    fn get(&self, index: usize) -> u32 {
+        // SAFETY: this is a backup register, which nothing else uses.
+        unsafe { core::ptr::read_volatile(self.reg(index)) }
+    }
+
+    fn set(&self, index: usize, value: u32) {
+        // SAFETY: this is a backup register, which nothing else uses.
+        unsafe { core::ptr::write_volatile(self.reg(index), value) }
+    }
+}
@@ -149,6 +149,39 @@ impl Usart {
         self.usart.cr3.modify(|_, w| w.txftie().clear_bit());
     }
 
+    /// Address of the receive data register, for pointing a DMA stream at.
+    pub fn rdr_address(&self) -> u32 {
+        self.usart.rdr.as_ptr() as u32
+    }
+
+    /// Has the USART raise a DMA request for each received byte, rather than
+    /// (or as well as) an interrupt.
+    pub fn enable_rx_dma(&self) {
+        self.usart.cr3.modify(|_, w| w.dmar().set_bit());
+    }
+
+    /// Stops raising DMA requests for received bytes, which are then left in
+    /// the RX FIFO. With hardware flow control on, RTS is deasserted once the
+    /// FIFO fills.
+    pub fn disable_rx_dma(&self) {
+        self.usart.cr3.modify(|_, w| w.dmar().clear_bit());
+    }
+
+    /// Enables an interrupt when the RX line goes idle after receiving data,
+    /// which is how a DMA receiver finds out about the tail end of a burst.
+    pub fn enable_idle_interrupt(&self) {
+        self.usart.cr1.modify(|_, w| w.idleie().set_bit());
+    }
+
+    pub fn check_and_clear_idle(&self) -> bool {
+        if self.usart.isr.read().idle().bit() {
+            self.usart.icr.write(|w| w.idlecf().set_bit());
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn send_break(&self) {
         self.usart.rqr.write(|w| w.sbkrq().set_bit());
         // TODO: should we wait for the flag (SBKF) to clear?
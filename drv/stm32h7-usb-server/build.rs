@@ -0,0 +1,95 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::io::Write;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct TaskConfig {
+    vendor_id: u16,
+    product_id: u16,
+    manufacturer: String,
+    product: String,
+    /// Number of interfaces the class tasks will claim between them; the
+    /// device connects once all of them have been described
+    interfaces: u8,
+    pins: Vec<PinConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct PinConfig {
+    port: char,
+    pins: Vec<u8>,
+    af: u8,
+}
+
+fn generate_usb_config(config: &TaskConfig) -> Result<()> {
+    if config.interfaces == 0 {
+        bail!("the device needs at least one interface");
+    }
+    // String descriptors are UTF-16, behind a two byte header, and we send
+    // them in one packet.
+    for s in [&config.manufacturer, &config.product] {
+        if s.encode_utf16().count() > 31 {
+            bail!("string {s:?} is too long for a string descriptor");
+        }
+    }
+
+    let out = build_util::out_dir().join("usb_config.rs");
+    let mut file = std::fs::File::create(out)?;
+    writeln!(
+        file,
+        "pub const VENDOR_ID: u16 = {:#06x};",
+        config.vendor_id
+    )?;
+    writeln!(
+        file,
+        "pub const PRODUCT_ID: u16 = {:#06x};",
+        config.product_id
+    )?;
+    writeln!(
+        file,
+        "pub const MANUFACTURER: &str = {:?};",
+        config.manufacturer
+    )?;
+    writeln!(file, "pub const PRODUCT: &str = {:?};", config.product)?;
+    writeln!(file, "pub const INTERFACES: u8 = {};", config.interfaces)?;
+    writeln!(file, "pub const PINS: &[(PinSet, Alternate)] = &[")?;
+    for pin in &config.pins {
+        if pin.pins.is_empty() {
+            bail!("empty pin list for port {}", pin.port);
+        }
+        let mut set = format!("Port::{}", pin.port.to_ascii_uppercase());
+        for (i, n) in pin.pins.iter().enumerate() {
+            let f = if i == 0 { "pin" } else { "and_pin" };
+            set.push_str(&format!(".{f}({n})"));
+        }
+        writeln!(file, "    ({set}, Alternate::AF{}),", pin.af)?;
+    }
+    writeln!(file, "];")?;
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    idol::Generator::new()
+        .with_counters(
+            idol::CounterSettings::default().with_server_counters(false),
+        )
+        .build_server_support(
+            "../../idl/usb.idol",
+            "server_stub.rs",
+            idol::server::ServerStyle::InOrder,
+        )?;
+
+    build_util::build_notifications()?;
+
+    let task_config = build_util::task_config::<TaskConfig>()?;
+    generate_usb_config(&task_config)?;
+
+    Ok(())
+}
@@ -0,0 +1,887 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! USB device controller task for the STM32H7's full-speed OTG controller.
+//!
+//! This task runs the bus side of a USB device: it answers resets, handles
+//! the standard requests on endpoint 0 (addressing, descriptors,
+//! configuration, endpoint halts), and moves packets between the FIFOs and
+//! its clients. It doesn't implement any USB class itself. Instead, class
+//! tasks claim interface numbers and endpoints, hand over their descriptors,
+//! and get notified when there's something for them to do; see `drv-usb-api`
+//! for the details. This keeps each class (a serial console, a firmware
+//! update channel, ...) in its own task, where it can fail on its own.
+//!
+//! The device stays disconnected from the bus until the configured number of
+//! interfaces have been described, so that the host never sees a partial
+//! configuration. Claims are for the life of the controller; a class task
+//! that restarts can't claim anything once the device has connected, so
+//! class tasks should be restarted along with this one.
+//!
+//! Each OUT endpoint holds one received packet in our RAM, and NAKs the host
+//! until its owner has collected it with `read`; IN packets are written
+//! straight into the endpoint's TX FIFO, and `write` returns `Busy` until the
+//! previous one has gone.
+//!
+//! The controller needs a 48 MHz kernel clock, and its transceiver needs the
+//! USB regulator (`PWR_CR3.USB33DEN`); setting both up is the board's job,
+//! before this task starts. VBUS sensing isn't used: the device acts as
+//! though it's always powered by the bus. The task is configured with the
+//! device's identity, the number of interfaces to wait for, and its pins:
+//!
+//! ```toml
+//! [tasks.usb.config]
+//! vendor-id = 0x1209
+//! product-id = 0x0001
+//! manufacturer = "Oxide Computer Company"
+//! product = "Console"
+//! interfaces = 2
+//! pins = [{ port = "A", pins = [11, 12], af = 10 }]
+//! ```
+//!
+//! It needs the controller's interrupt mapped to a notification named
+//! `usb-irq`, and a task slot for `sys`.
+
+#![no_std]
+#![no_main]
+
+mod otg;
+
+use drv_stm32xx_sys_api::{
+    Alternate, OutputType, Peripheral, PinSet, Port, Pull, Speed, Sys,
+};
+use drv_usb_api::{
+    descriptor, ControlRequest, Direction, EndpointKind, UsbError, MAX_PACKET,
+};
+use idol_runtime::{
+    ClientError, Leased, LenLimit, NotificationHandler, RequestError, R, W,
+};
+use otg::{Otg, ENDPOINTS};
+use ringbuf::*;
+use userlib::*;
+use zerocopy::FromBytes;
+
+task_slot!(SYS, sys);
+
+/// Room for class tasks' descriptors in the configuration descriptor.
+const DESCRIPTORS_SIZE: usize = 448;
+
+/// Length of the configuration descriptor's own header.
+const CONFIG_HEADER_SIZE: usize = 9;
+
+/// The only configuration we have.
+const CONFIGURATION: u8 = 1;
+
+// Standard request codes (USB 2.0 table 9-4).
+const GET_STATUS: u8 = 0;
+const CLEAR_FEATURE: u8 = 1;
+const SET_FEATURE: u8 = 3;
+const SET_ADDRESS: u8 = 5;
+const GET_DESCRIPTOR: u8 = 6;
+const GET_CONFIGURATION: u8 = 8;
+const SET_CONFIGURATION: u8 = 9;
+const GET_INTERFACE: u8 = 10;
+const SET_INTERFACE: u8 = 11;
+
+// Descriptor types.
+const DEVICE: u8 = 1;
+const CONFIG: u8 = 2;
+const STRING: u8 = 3;
+
+const ENDPOINT_HALT: u16 = 0;
+
+#[derive(Copy, Clone, PartialEq, counters::Count)]
+enum Event {
+    Reset,
+    Suspend,
+    Setup,
+    Stalled,
+    Configured,
+}
+
+counters::counters!(Event);
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Reset,
+    Setup {
+        request_type: u8,
+        request: u8,
+        value: u16,
+    },
+    Stall,
+    Address(u8),
+    Configuration(u8),
+    Connected,
+}
+
+ringbuf!(Trace, 32, Trace::None);
+
+/// Who to tell about activity on an interface or endpoint.
+#[derive(Copy, Clone)]
+struct Owner {
+    task: TaskId,
+    notification: u32,
+}
+
+impl Owner {
+    fn is(&self, task: TaskId) -> bool {
+        self.task.index() == task.index()
+    }
+
+    fn notify(&self) {
+        sys_post(self.task, self.notification);
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Endpoint {
+    owner: Owner,
+    kind: EndpointKind,
+    max_packet: u16,
+}
+
+/// Where endpoint 0 is in a control transfer.
+#[derive(Copy, Clone)]
+enum Control {
+    Idle,
+    /// We're receiving the data stage of a class request.
+    Receiving(ControlRequest),
+    /// A class request is waiting for its interface's owner to answer it.
+    Forwarded(ControlRequest, Owner),
+}
+
+struct ServerImpl {
+    otg: Otg,
+    interfaces: [Option<Owner>; config::INTERFACES as usize],
+    /// Interfaces that have been described, which must reach
+    /// `config::INTERFACES` before we connect.
+    described: u8,
+    in_endpoints: [Option<Endpoint>; ENDPOINTS],
+    out_endpoints: [Option<Endpoint>; ENDPOINTS],
+    /// Whether each IN endpoint has a packet in flight.
+    in_busy: [bool; ENDPOINTS],
+    /// The packet waiting on each OUT endpoint, if any.
+    out_packets: [[u8; MAX_PACKET]; ENDPOINTS],
+    out_lens: [Option<usize>; ENDPOINTS],
+    /// The configuration descriptor, whose header we fill in on request.
+    config_descriptor: [u8; CONFIG_HEADER_SIZE + DESCRIPTORS_SIZE],
+    config_len: usize,
+    connected: bool,
+    configuration: u8,
+
+    control: Control,
+    /// The setup packet being received.
+    setup: [u8; 8],
+    /// Data received with a control request.
+    control_data: [u8; MAX_PACKET],
+    control_len: usize,
+    /// The data stage we're sending on endpoint 0.
+    ep0_in: [u8; CONFIG_HEADER_SIZE + DESCRIPTORS_SIZE],
+    ep0_pos: usize,
+    ep0_len: usize,
+    /// Whether the data stage must end with a zero-length packet, because
+    /// it's shorter than the host asked for and a multiple of the packet
+    /// size.
+    ep0_zlp: bool,
+}
+
+impl ServerImpl {
+    fn endpoint(
+        &self,
+        task: TaskId,
+        address: u8,
+        direction: Direction,
+    ) -> Result<usize, UsbError> {
+        let ep = usize::from(address & 0x7F);
+        let table = match direction {
+            Direction::In => &self.in_endpoints,
+            Direction::Out => &self.out_endpoints,
+        };
+        if descriptor::direction(address) != direction {
+            return Err(UsbError::BadEndpoint);
+        }
+        match table.get(ep) {
+            Some(Some(e)) if ep != 0 && e.owner.is(task) => Ok(ep),
+            _ => Err(UsbError::BadEndpoint),
+        }
+    }
+
+    fn owners(&self) -> impl Iterator<Item = &Owner> {
+        self.interfaces.iter().flatten()
+    }
+
+    fn bus_reset(&mut self) {
+        ringbuf_entry!(Trace::Reset);
+        counters::count!(Event::Reset);
+        self.otg.reset();
+        self.in_busy = [false; ENDPOINTS];
+        self.out_lens = [None; ENDPOINTS];
+        self.control = Control::Idle;
+        if self.configuration != 0 {
+            self.configuration = 0;
+            self.owners().for_each(Owner::notify);
+        }
+    }
+
+    fn stall(&mut self) {
+        ringbuf_entry!(Trace::Stall);
+        counters::count!(Event::Stalled);
+        self.otg.stall_ep0();
+        self.control = Control::Idle;
+    }
+
+    /// Starts the data stage of a control read, sending (at most as much of)
+    /// `data` as the host asked for.
+    fn control_in(&mut self, request: &ControlRequest, data: &[u8]) {
+        let len = data.len().min(usize::from(request.length));
+        self.ep0_in[..len].copy_from_slice(&data[..len]);
+        self.control_in_prepared(request, len);
+    }
+
+    /// Starts the data stage of a control read, sending the first `len`
+    /// bytes of `ep0_in`.
+    fn control_in_prepared(&mut self, request: &ControlRequest, len: usize) {
+        let len = len.min(usize::from(request.length));
+        self.ep0_pos = 0;
+        self.ep0_len = len;
+        self.ep0_zlp = len < usize::from(request.length);
+        self.control = Control::Idle;
+        self.ep0_send_next();
+    }
+
+    /// Sends a zero-length status stage, acknowledging a request.
+    fn control_ack(&mut self) {
+        self.ep0_pos = 0;
+        self.ep0_len = 0;
+        self.ep0_zlp = false;
+        self.control = Control::Idle;
+        self.otg.send(0, &[]);
+    }
+
+    fn ep0_send_next(&mut self) {
+        let remaining = self.ep0_len - self.ep0_pos;
+        if remaining > 0 {
+            let n = remaining.min(MAX_PACKET);
+            self.otg
+                .send(0, &self.ep0_in[self.ep0_pos..self.ep0_pos + n]);
+            self.ep0_pos += n;
+            if self.ep0_pos == self.ep0_len && n < MAX_PACKET {
+                // A short packet ends the transfer.
+                self.ep0_zlp = false;
+            }
+        } else if self.ep0_zlp {
+            self.otg.send(0, &[]);
+            self.ep0_zlp = false;
+        }
+    }
+
+    fn handle_setup(&mut self) {
+        let request = ControlRequest::read_from(&self.setup[..]).unwrap_lite();
+        ringbuf_entry!(Trace::Setup {
+            request_type: request.request_type,
+            request: request.request,
+            value: request.value,
+        });
+        counters::count!(Event::Setup);
+
+        // A new setup packet supersedes whatever was going on.
+        self.control = Control::Idle;
+        self.otg.arm_out(0, MAX_PACKET as u16);
+
+        if request.request_type & 0x60 == 0 {
+            self.standard_request(&request);
+            return;
+        }
+
+        // Anything else goes to the interface's owner, if it has one.
+        let owner = request
+            .interface()
+            .and_then(|i| self.interfaces.get(usize::from(i)).copied())
+            .flatten();
+        match owner {
+            Some(owner) => {
+                if !request.is_in() && request.length != 0 {
+                    self.control_len = 0;
+                    self.control = Control::Receiving(request);
+                } else {
+                    self.control_len = 0;
+                    self.forward(request, owner);
+                }
+            }
+            None => self.stall(),
+        }
+    }
+
+    fn forward(&mut self, request: ControlRequest, owner: Owner) {
+        self.control = Control::Forwarded(request, owner);
+        owner.notify();
+    }
+
+    fn standard_request(&mut self, request: &ControlRequest) {
+        let recipient = request.request_type & 0x1F;
+        match (recipient, request.request) {
+            (0, GET_DESCRIPTOR) => self.get_descriptor(request),
+            (0, SET_ADDRESS) => {
+                // The controller wants the address as soon as we have it,
+                // and applies it after the status stage.
+                let address = request.value as u8 & 0x7F;
+                ringbuf_entry!(Trace::Address(address));
+                self.otg.set_address(address);
+                self.control_ack();
+            }
+            (0, SET_CONFIGURATION) => match request.value {
+                0 => {
+                    self.set_configuration(0);
+                    self.control_ack();
+                }
+                v if v == u16::from(CONFIGURATION) => {
+                    self.set_configuration(CONFIGURATION);
+                    self.control_ack();
+                }
+                _ => self.stall(),
+            },
+            (0, GET_CONFIGURATION) => {
+                self.control_in(request, &[self.configuration]);
+            }
+            (0 | 1, GET_STATUS) => self.control_in(request, &[0, 0]),
+            (1, GET_INTERFACE) => self.control_in(request, &[0]),
+            (1, SET_INTERFACE) if request.value == 0 => self.control_ack(),
+            (2, GET_STATUS) => {
+                let address = request.index as u8;
+                let ep = usize::from(address & 0x7F);
+                let dir_in = address & 0x80 != 0;
+                if ep >= ENDPOINTS {
+                    self.stall();
+                } else {
+                    let halted = self.otg.halted(ep, dir_in) as u8;
+                    self.control_in(request, &[halted, 0]);
+                }
+            }
+            (2, CLEAR_FEATURE | SET_FEATURE)
+                if request.value == ENDPOINT_HALT =>
+            {
+                let address = request.index as u8;
+                let ep = usize::from(address & 0x7F);
+                let dir_in = address & 0x80 != 0;
+                if ep >= ENDPOINTS {
+                    self.stall();
+                    return;
+                }
+                if request.request == SET_FEATURE {
+                    self.otg.set_halt(ep, dir_in);
+                } else {
+                    self.otg.clear_halt(ep, dir_in);
+                }
+                self.control_ack();
+            }
+            _ => self.stall(),
+        }
+    }
+
+    fn get_descriptor(&mut self, request: &ControlRequest) {
+        let [index, kind] = request.value.to_le_bytes();
+        match (kind, index) {
+            (DEVICE, 0) => {
+                let [vid_lo, vid_hi] = config::VENDOR_ID.to_le_bytes();
+                let [pid_lo, pid_hi] = config::PRODUCT_ID.to_le_bytes();
+                let device = [
+                    18,
+                    DEVICE,
+                    0x00,
+                    0x02, // USB 2.0
+                    // Miscellaneous device class, using interface
+                    // association descriptors.
+                    0xEF,
+                    0x02,
+                    0x01,
+                    MAX_PACKET as u8,
+                    vid_lo,
+                    vid_hi,
+                    pid_lo,
+                    pid_hi,
+                    0x00,
+                    0x01, // device release 1.00
+                    1,    // manufacturer string
+                    2,    // product string
+                    0,    // no serial number
+                    1,    // configurations
+                ];
+                self.control_in(request, &device);
+            }
+            (CONFIG, 0) => {
+                let [len_lo, len_hi] = (self.config_len as u16).to_le_bytes();
+                self.config_descriptor[..CONFIG_HEADER_SIZE].copy_from_slice(
+                    &[
+                        CONFIG_HEADER_SIZE as u8,
+                        CONFIG,
+                        len_lo,
+                        len_hi,
+                        config::INTERFACES,
+                        CONFIGURATION,
+                        0,
+                        0x80, // bus powered
+                        50,   // 100 mA
+                    ],
+                );
+                let len = self.config_len;
+                self.ep0_in[..len]
+                    .copy_from_slice(&self.config_descriptor[..len]);
+                self.control_in_prepared(request, len);
+            }
+            (STRING, 0) => {
+                // We only speak US English.
+                self.control_in(request, &[4, STRING, 0x09, 0x04]);
+            }
+            (STRING, 1 | 2) => {
+                let s = if index == 1 {
+                    config::MANUFACTURER
+                } else {
+                    config::PRODUCT
+                };
+                let mut buf = [0u8; MAX_PACKET];
+                let mut len = 2;
+                for unit in s.encode_utf16() {
+                    buf[len..len + 2].copy_from_slice(&unit.to_le_bytes());
+                    len += 2;
+                }
+                buf[0] = len as u8;
+                buf[1] = STRING;
+                self.control_in(request, &buf[..len]);
+            }
+            _ => self.stall(),
+        }
+    }
+
+    fn set_configuration(&mut self, configuration: u8) {
+        ringbuf_entry!(Trace::Configuration(configuration));
+        for ep in 1..ENDPOINTS {
+            self.otg.deactivate(ep);
+        }
+        self.in_busy = [false; ENDPOINTS];
+        self.out_lens = [None; ENDPOINTS];
+
+        if configuration != 0 {
+            counters::count!(Event::Configured);
+            for ep in 1..ENDPOINTS {
+                let i = self.in_endpoints[ep];
+                let o = self.out_endpoints[ep];
+                // An endpoint number in use in both directions must be of
+                // one kind; `claim_endpoint` hands out numbers so that it
+                // is.
+                let Some(kind) = i.or(o).map(|e| e.kind) else {
+                    continue;
+                };
+                self.otg.activate(
+                    ep,
+                    kind as u8,
+                    i.map(|e| e.max_packet),
+                    o.map(|e| e.max_packet),
+                );
+            }
+        }
+
+        if configuration != self.configuration {
+            self.configuration = configuration;
+            self.owners().for_each(Owner::notify);
+        }
+    }
+
+    fn out_packet(&mut self, ep: usize, len: usize) {
+        if ep == 0 {
+            let start = self.control_len.min(MAX_PACKET);
+            self.otg.read_packet(&mut self.control_data[start..], len);
+            self.control_len = (self.control_len + len).min(MAX_PACKET);
+        } else {
+            self.otg.read_packet(&mut self.out_packets[ep], len);
+            self.out_lens[ep] = Some(len.min(MAX_PACKET));
+        }
+    }
+
+    fn out_complete(&mut self, ep: usize) {
+        if ep == 0 {
+            if let Control::Receiving(request) = self.control {
+                if self.control_len >= usize::from(request.length) {
+                    let owner = request
+                        .interface()
+                        .and_then(|i| self.interfaces[usize::from(i)]);
+                    match owner {
+                        Some(owner) => self.forward(request, owner),
+                        None => self.stall(),
+                    }
+                    return;
+                }
+            }
+            self.otg.arm_out(0, MAX_PACKET as u16);
+        } else if let Some(e) = self.out_endpoints[ep] {
+            e.owner.notify();
+        }
+    }
+
+    fn in_complete(&mut self, ep: usize) {
+        if ep == 0 {
+            self.ep0_send_next();
+        } else {
+            self.in_busy[ep] = false;
+            if let Some(e) = self.in_endpoints[ep] {
+                e.owner.notify();
+            }
+        }
+    }
+
+    /// The request `task` has been forwarded, if any.
+    fn forwarded(&self, task: TaskId) -> Result<ControlRequest, UsbError> {
+        match self.control {
+            Control::Forwarded(request, owner) if owner.is(task) => Ok(request),
+            _ => Err(UsbError::Empty),
+        }
+    }
+}
+
+impl idl::InOrderUsbImpl for ServerImpl {
+    fn claim_interfaces(
+        &mut self,
+        msg: &RecvMessage,
+        count: u8,
+        notification: u32,
+    ) -> Result<u8, RequestError<UsbError>> {
+        if self.connected {
+            return Err(UsbError::AlreadyConnected.into());
+        }
+        let first = self
+            .interfaces
+            .iter()
+            .position(Option::is_none)
+            .unwrap_or(self.interfaces.len());
+        let end = first + usize::from(count);
+        if count == 0 || end > self.interfaces.len() {
+            return Err(UsbError::NoInterfaces.into());
+        }
+        let owner = Owner {
+            task: msg.sender,
+            notification,
+        };
+        for slot in &mut self.interfaces[first..end] {
+            *slot = Some(owner);
+        }
+        Ok(first as u8)
+    }
+
+    fn claim_endpoint(
+        &mut self,
+        msg: &RecvMessage,
+        kind: EndpointKind,
+        direction: Direction,
+        max_packet: u16,
+        notification: u32,
+    ) -> Result<u8, RequestError<UsbError>> {
+        if self.connected {
+            return Err(UsbError::AlreadyConnected.into());
+        }
+        if max_packet == 0 || usize::from(max_packet) > MAX_PACKET {
+            return Err(UsbError::BadMaxPacket.into());
+        }
+
+        // Use an endpoint number that's free in the requested direction,
+        // and either free or of the same kind in the other.
+        let (table, other) = match direction {
+            Direction::In => (&self.in_endpoints, &self.out_endpoints),
+            Direction::Out => (&self.out_endpoints, &self.in_endpoints),
+        };
+        let ep = (1..ENDPOINTS)
+            .find(|&ep| {
+                table[ep].is_none()
+                    && other[ep].map(|e| e.kind == kind).unwrap_or(true)
+            })
+            .ok_or(UsbError::NoEndpoint)?;
+
+        let endpoint = Endpoint {
+            owner: Owner {
+                task: msg.sender,
+                notification,
+            },
+            kind,
+            max_packet,
+        };
+        match direction {
+            Direction::In => self.in_endpoints[ep] = Some(endpoint),
+            Direction::Out => self.out_endpoints[ep] = Some(endpoint),
+        }
+        Ok(ep as u8 | (direction as u8) << 7)
+    }
+
+    fn add_descriptors(
+        &mut self,
+        _: &RecvMessage,
+        descriptors: LenLimit<Leased<R, [u8]>, 192>,
+    ) -> Result<(), RequestError<UsbError>> {
+        if self.connected {
+            return Err(UsbError::AlreadyConnected.into());
+        }
+        let descriptors = descriptors.into_inner();
+        let start = self.config_len;
+        let end = start + descriptors.len();
+        if end > self.config_descriptor.len() {
+            return Err(UsbError::DescriptorsFull.into());
+        }
+        descriptors
+            .read_range(
+                0..descriptors.len(),
+                &mut self.config_descriptor[start..end],
+            )
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+
+        // Count the interfaces described (ignoring alternate settings), and
+        // check that the descriptors are well-formed while we're at it.
+        let mut pos = start;
+        let mut interfaces = 0;
+        while pos < end {
+            let d = &self.config_descriptor[pos..end];
+            let len = usize::from(d[0]);
+            if len < 2 || len > d.len() {
+                return Err(RequestError::Fail(ClientError::BadLease));
+            }
+            if d[1] == descriptor::INTERFACE && len >= 4 && d[3] == 0 {
+                interfaces += 1;
+            }
+            pos += len;
+        }
+
+        self.config_len = end;
+        self.described = self.described.saturating_add(interfaces);
+        if self.described >= config::INTERFACES {
+            ringbuf_entry!(Trace::Connected);
+            self.connected = true;
+            self.otg.connect(true);
+        }
+        Ok(())
+    }
+
+    fn write(
+        &mut self,
+        msg: &RecvMessage,
+        endpoint: u8,
+        data: LenLimit<Leased<R, [u8]>, 64>,
+    ) -> Result<(), RequestError<UsbError>> {
+        let ep = self.endpoint(msg.sender, endpoint, Direction::In)?;
+        if self.configuration == 0 {
+            return Err(UsbError::NotConfigured.into());
+        }
+        if self.in_busy[ep] {
+            return Err(UsbError::Busy.into());
+        }
+        let data = data.into_inner();
+        let mut buf = [0u8; MAX_PACKET];
+        let buf = &mut buf[..data.len()];
+        data.read_range(0..buf.len(), buf)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+
+        self.in_busy[ep] = true;
+        self.otg.send(ep, buf);
+        Ok(())
+    }
+
+    fn read(
+        &mut self,
+        msg: &RecvMessage,
+        endpoint: u8,
+        data: LenLimit<Leased<W, [u8]>, 64>,
+    ) -> Result<usize, RequestError<UsbError>> {
+        let ep = self.endpoint(msg.sender, endpoint, Direction::Out)?;
+        if self.configuration == 0 {
+            return Err(UsbError::NotConfigured.into());
+        }
+        let len = self.out_lens[ep].ok_or(UsbError::Empty)?;
+        let data = data.into_inner();
+        let n = len.min(data.len());
+        data.write_range(0..n, &self.out_packets[ep][..n])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+
+        self.out_lens[ep] = None;
+        let max_packet = self.out_endpoints[ep].map(|e| e.max_packet);
+        self.otg
+            .arm_out(ep, max_packet.unwrap_or(MAX_PACKET as u16));
+        Ok(n)
+    }
+
+    fn control_request(
+        &mut self,
+        msg: &RecvMessage,
+        data: LenLimit<Leased<W, [u8]>, 64>,
+    ) -> Result<ControlRequest, RequestError<UsbError>> {
+        let request = self.forwarded(msg.sender)?;
+        let data = data.into_inner();
+        let n = self.control_len.min(data.len());
+        data.write_range(0..n, &self.control_data[..n])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        Ok(request)
+    }
+
+    fn control_reply(
+        &mut self,
+        msg: &RecvMessage,
+        data: LenLimit<Leased<R, [u8]>, 64>,
+    ) -> Result<(), RequestError<UsbError>> {
+        let request = self.forwarded(msg.sender)?;
+        if request.is_in() {
+            let data = data.into_inner();
+            let mut buf = [0u8; MAX_PACKET];
+            let buf = &mut buf[..data.len()];
+            data.read_range(0..buf.len(), buf)
+                .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+            self.control_in(&request, buf);
+        } else {
+            self.control_ack();
+        }
+        Ok(())
+    }
+
+    fn control_stall(
+        &mut self,
+        msg: &RecvMessage,
+    ) -> Result<(), RequestError<UsbError>> {
+        self.forwarded(msg.sender)?;
+        self.stall();
+        Ok(())
+    }
+
+    fn configured(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<bool, RequestError<core::convert::Infallible>> {
+        Ok(self.configuration != 0)
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::USB_IRQ_MASK
+    }
+
+    fn handle_notification(&mut self, bits: u32) {
+        if bits & notifications::USB_IRQ_MASK == 0 {
+            return;
+        }
+
+        let status = self.otg.status(otg::GINTSTS);
+        if status & otg::USBRST != 0 {
+            self.otg.clear_interrupts(otg::USBRST);
+            self.bus_reset();
+        }
+        if status & otg::ENUMDNE != 0 {
+            // We only do full speed, so there's nothing to learn here.
+            self.otg.clear_interrupts(otg::ENUMDNE);
+        }
+        if status & otg::USBSUSP != 0 {
+            self.otg.clear_interrupts(otg::USBSUSP);
+            counters::count!(Event::Suspend);
+        }
+
+        while self.otg.status(otg::GINTSTS) & otg::RXFLVL != 0 {
+            let rx = self.otg.pop_rx_status();
+            match rx.status {
+                otg::PKTSTS_SETUP_DATA => {
+                    let mut setup = [0; 8];
+                    self.otg.read_packet(&mut setup, rx.len);
+                    self.setup = setup;
+                }
+                otg::PKTSTS_OUT_DATA if rx.len > 0 => {
+                    self.out_packet(rx.endpoint, rx.len);
+                }
+                // Completion entries carry no data; we act on the endpoint
+                // interrupts instead.
+                _ => (),
+            }
+        }
+
+        let daint = self.otg.status(otg::DAINT);
+        if status & otg::OEPINT != 0 {
+            for ep in 0..ENDPOINTS {
+                if daint & 1 << (16 + ep) == 0 {
+                    continue;
+                }
+                let ints = self.otg.take_out_interrupts(ep);
+                if ep == 0 && ints & otg::STUP != 0 {
+                    self.handle_setup();
+                } else if ints & otg::XFRC != 0 {
+                    self.out_complete(ep);
+                }
+            }
+        }
+        if status & otg::IEPINT != 0 {
+            for ep in 0..ENDPOINTS {
+                if daint & 1 << ep == 0 {
+                    continue;
+                }
+                if self.otg.take_in_interrupts(ep) & otg::XFRC != 0 {
+                    self.in_complete(ep);
+                }
+            }
+        }
+
+        sys_irq_control(notifications::USB_IRQ_MASK, true);
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let sys = Sys::from(SYS.get_task_id());
+    sys.enable_clock(Peripheral::Usb2Otg);
+    sys.leave_reset(Peripheral::Usb2Otg);
+    for &(pins, af) in config::PINS {
+        sys.gpio_configure_alternate(
+            pins,
+            OutputType::PushPull,
+            Speed::VeryHigh,
+            Pull::None,
+            af,
+        );
+    }
+
+    let otg = Otg;
+    otg.init();
+    sys_irq_control(notifications::USB_IRQ_MASK, true);
+
+    let mut server = ServerImpl {
+        otg,
+        interfaces: [None; config::INTERFACES as usize],
+        described: 0,
+        in_endpoints: [None; ENDPOINTS],
+        out_endpoints: [None; ENDPOINTS],
+        in_busy: [false; ENDPOINTS],
+        out_packets: [[0; MAX_PACKET]; ENDPOINTS],
+        out_lens: [None; ENDPOINTS],
+        config_descriptor: [0; CONFIG_HEADER_SIZE + DESCRIPTORS_SIZE],
+        config_len: CONFIG_HEADER_SIZE,
+        connected: false,
+        configuration: 0,
+        control: Control::Idle,
+        setup: [0; 8],
+        control_data: [0; MAX_PACKET],
+        control_len: 0,
+        ep0_in: [0; CONFIG_HEADER_SIZE + DESCRIPTORS_SIZE],
+        ep0_pos: 0,
+        ep0_len: 0,
+        ep0_zlp: false,
+    };
+    let mut buffer = [0; idl::INCOMING_SIZE];
+    loop {
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
+
+mod config {
+    use super::*;
+
+    include!(concat!(env!("OUT_DIR"), "/usb_config.rs"));
+}
+
+mod idl {
+    use drv_usb_api::{ControlRequest, Direction, EndpointKind, UsbError};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
@@ -0,0 +1,352 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Register-level access to the OTG_FS controller (a Synopsys DWC2 core) in
+//! device mode.
+//!
+//! The PAC's view of this block varies a lot between versions, so we go at
+//! the registers by offset; the offsets and bits are from RM0433 section 57.
+
+/// OTG_FS (called USB2_OTG_FS on the H743) in the AHB1 domain.
+const BASE: usize = 0x4008_0000;
+
+// Core global registers.
+const GOTGCTL: usize = 0x000;
+const GAHBCFG: usize = 0x008;
+const GUSBCFG: usize = 0x00C;
+const GRSTCTL: usize = 0x010;
+pub const GINTSTS: usize = 0x014;
+const GINTMSK: usize = 0x018;
+const GRXSTSP: usize = 0x020;
+const GRXFSIZ: usize = 0x024;
+const DIEPTXF0: usize = 0x028;
+const GCCFG: usize = 0x038;
+const DIEPTXF1: usize = 0x104;
+
+// Device registers.
+const DCFG: usize = 0x800;
+const DCTL: usize = 0x804;
+const DIEPMSK: usize = 0x810;
+const DOEPMSK: usize = 0x814;
+pub const DAINT: usize = 0x818;
+const DAINTMSK: usize = 0x81C;
+const PCGCCTL: usize = 0xE00;
+
+const fn diepctl(ep: usize) -> usize {
+    0x900 + 0x20 * ep
+}
+const fn diepint(ep: usize) -> usize {
+    0x908 + 0x20 * ep
+}
+const fn dieptsiz(ep: usize) -> usize {
+    0x910 + 0x20 * ep
+}
+const fn doepctl(ep: usize) -> usize {
+    0xB00 + 0x20 * ep
+}
+const fn doepint(ep: usize) -> usize {
+    0xB08 + 0x20 * ep
+}
+const fn doeptsiz(ep: usize) -> usize {
+    0xB10 + 0x20 * ep
+}
+const fn fifo(ep: usize) -> usize {
+    0x1000 + 0x1000 * ep
+}
+
+// GOTGCTL: override the B-session valid signal, so that we don't need VBUS
+// sensing.
+const BVALOEN: u32 = 1 << 6;
+const BVALOVAL: u32 = 1 << 7;
+
+const GAHBCFG_GINT: u32 = 1 << 0;
+
+const GUSBCFG_PHYSEL: u32 = 1 << 6;
+const GUSBCFG_TRDT_MASK: u32 = 0xF << 10;
+/// Turnaround time for an AHB clock over 32 MHz.
+const GUSBCFG_TRDT: u32 = 6 << 10;
+const GUSBCFG_FDMOD: u32 = 1 << 30;
+
+const GRSTCTL_CSRST: u32 = 1 << 0;
+const GRSTCTL_RXFFLSH: u32 = 1 << 4;
+const GRSTCTL_TXFFLSH: u32 = 1 << 5;
+const GRSTCTL_TXFNUM_ALL: u32 = 0x10 << 6;
+const GRSTCTL_AHBIDL: u32 = 1 << 31;
+
+// GINTSTS and GINTMSK.
+pub const RXFLVL: u32 = 1 << 4;
+pub const USBSUSP: u32 = 1 << 11;
+pub const USBRST: u32 = 1 << 12;
+pub const ENUMDNE: u32 = 1 << 13;
+pub const IEPINT: u32 = 1 << 18;
+pub const OEPINT: u32 = 1 << 19;
+
+const GCCFG_PWRDWN: u32 = 1 << 16;
+
+/// Full speed, using the embedded PHY.
+const DCFG_DSPD_FS: u32 = 0b11;
+const DCFG_DAD_SHIFT: u32 = 4;
+const DCFG_DAD_MASK: u32 = 0x7F << DCFG_DAD_SHIFT;
+
+const DCTL_SDIS: u32 = 1 << 1;
+
+// Endpoint interrupts.
+pub const XFRC: u32 = 1 << 0;
+pub const STUP: u32 = 1 << 3;
+
+// DIEPCTL and DOEPCTL.
+const EPCTL_USBAEP: u32 = 1 << 15;
+const EPCTL_EPTYP_SHIFT: u32 = 18;
+const EPCTL_STALL: u32 = 1 << 21;
+const EPCTL_TXFNUM_SHIFT: u32 = 22;
+const EPCTL_CNAK: u32 = 1 << 26;
+const EPCTL_SNAK: u32 = 1 << 27;
+const EPCTL_SD0PID: u32 = 1 << 28;
+const EPCTL_EPDIS: u32 = 1 << 30;
+const EPCTL_EPENA: u32 = 1 << 31;
+
+// DIEPTSIZ and DOEPTSIZ.
+const TSIZ_PKTCNT_1: u32 = 1 << 19;
+const DOEPTSIZ0_STUPCNT_3: u32 = 3 << 29;
+
+// GRXSTSP packet status values.
+pub const PKTSTS_OUT_DATA: u32 = 2;
+pub const PKTSTS_SETUP_DATA: u32 = 6;
+
+/// Number of endpoints in each direction, including endpoint 0.
+pub const ENDPOINTS: usize = 9;
+
+/// Sizes of the FIFOs, in words. The RX FIFO is shared by all OUT
+/// endpoints; each IN endpoint has a TX FIFO big enough for one full-speed
+/// packet.
+const RX_FIFO_WORDS: u32 = 128;
+const TX_FIFO_WORDS: u32 = 16;
+
+/// A received packet's status, popped from the RX FIFO.
+pub struct RxStatus {
+    pub endpoint: usize,
+    pub len: usize,
+    pub status: u32,
+}
+
+pub struct Otg;
+
+impl Otg {
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { core::ptr::read_volatile((BASE + offset) as *const u32) }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { core::ptr::write_volatile((BASE + offset) as *mut u32, value) }
+    }
+
+    fn modify(&self, offset: usize, f: impl FnOnce(u32) -> u32) {
+        self.write(offset, f(self.read(offset)));
+    }
+
+    pub fn status(&self, offset: usize) -> u32 {
+        self.read(offset)
+    }
+
+    /// Resets the core and brings it up in device mode, disconnected from
+    /// the bus. The caller must have turned on the peripheral's clock.
+    pub fn init(&self) {
+        self.modify(GUSBCFG, |r| r | GUSBCFG_PHYSEL);
+
+        while self.read(GRSTCTL) & GRSTCTL_AHBIDL == 0 {}
+        self.write(GRSTCTL, GRSTCTL_CSRST);
+        while self.read(GRSTCTL) & GRSTCTL_CSRST != 0 {}
+
+        self.write(GCCFG, GCCFG_PWRDWN);
+        self.modify(GOTGCTL, |r| r | BVALOEN | BVALOVAL);
+        self.modify(GUSBCFG, |r| {
+            r & !GUSBCFG_TRDT_MASK | GUSBCFG_TRDT | GUSBCFG_FDMOD
+        });
+        // Forcing device mode takes up to 25 ms to take effect.
+        userlib::hl::sleep_for(25);
+
+        self.write(PCGCCTL, 0);
+        self.write(DCFG, DCFG_DSPD_FS);
+        self.write(DCTL, DCTL_SDIS);
+
+        self.write(GRXFSIZ, RX_FIFO_WORDS);
+        let mut start = RX_FIFO_WORDS;
+        self.write(DIEPTXF0, TX_FIFO_WORDS << 16 | start);
+        for ep in 1..ENDPOINTS {
+            start += TX_FIFO_WORDS;
+            self.write(DIEPTXF1 + 4 * (ep - 1), TX_FIFO_WORDS << 16 | start);
+        }
+        self.flush_fifos();
+
+        self.write(DIEPMSK, XFRC);
+        self.write(DOEPMSK, XFRC | STUP);
+        self.write(GINTSTS, !0);
+        self.write(
+            GINTMSK,
+            RXFLVL | USBSUSP | USBRST | ENUMDNE | IEPINT | OEPINT,
+        );
+        self.write(GAHBCFG, GAHBCFG_GINT);
+    }
+
+    fn flush_fifos(&self) {
+        self.write(GRSTCTL, GRSTCTL_TXFFLSH | GRSTCTL_TXFNUM_ALL);
+        while self.read(GRSTCTL) & GRSTCTL_TXFFLSH != 0 {}
+        self.write(GRSTCTL, GRSTCTL_RXFFLSH);
+        while self.read(GRSTCTL) & GRSTCTL_RXFFLSH != 0 {}
+    }
+
+    /// Connects to (or disconnects from) the bus, by way of the pull-up on
+    /// D+.
+    pub fn connect(&self, on: bool) {
+        self.modify(DCTL, |r| if on { r & !DCTL_SDIS } else { r | DCTL_SDIS });
+    }
+
+    pub fn clear_interrupts(&self, bits: u32) {
+        self.write(GINTSTS, bits);
+    }
+
+    /// Handles a bus reset: the address goes back to 0, every endpoint but
+    /// endpoint 0 is deactivated, and endpoint 0 is readied for a SETUP.
+    pub fn reset(&self) {
+        self.set_address(0);
+        for ep in 1..ENDPOINTS {
+            self.deactivate(ep);
+        }
+        self.flush_fifos();
+        self.write(DAINTMSK, 1 << 16 | 1);
+        self.arm_out(0, 64);
+    }
+
+    pub fn set_address(&self, address: u8) {
+        self.modify(DCFG, |r| {
+            r & !DCFG_DAD_MASK | u32::from(address) << DCFG_DAD_SHIFT
+        });
+    }
+
+    /// Activates endpoint `ep` in both directions it's used in, with a DATA0
+    /// toggle, and arms its OUT side.
+    pub fn activate(
+        &self,
+        ep: usize,
+        kind: u8,
+        in_mps: Option<u16>,
+        out_mps: Option<u16>,
+    ) {
+        let typ = u32::from(kind) << EPCTL_EPTYP_SHIFT;
+        if let Some(mps) = in_mps {
+            self.write(
+                diepctl(ep),
+                u32::from(mps)
+                    | EPCTL_USBAEP
+                    | typ
+                    | (ep as u32) << EPCTL_TXFNUM_SHIFT
+                    | EPCTL_SD0PID
+                    | EPCTL_SNAK,
+            );
+            self.modify(DAINTMSK, |r| r | 1 << ep);
+        }
+        if let Some(mps) = out_mps {
+            self.write(
+                doepctl(ep),
+                u32::from(mps) | EPCTL_USBAEP | typ | EPCTL_SD0PID,
+            );
+            self.modify(DAINTMSK, |r| r | 1 << (16 + ep));
+            self.arm_out(ep, mps);
+        }
+    }
+
+    pub fn deactivate(&self, ep: usize) {
+        for ctl in [diepctl(ep), doepctl(ep)] {
+            if self.read(ctl) & EPCTL_EPENA != 0 {
+                self.write(ctl, EPCTL_EPDIS | EPCTL_SNAK);
+            }
+            self.write(ctl, 0);
+        }
+        self.modify(DAINTMSK, |r| r & !(1 << ep | 1 << (16 + ep)));
+    }
+
+    /// Resets an endpoint's data toggle to DATA0, and clears any stall.
+    pub fn clear_halt(&self, ep: usize, dir_in: bool) {
+        let ctl = if dir_in { diepctl(ep) } else { doepctl(ep) };
+        self.modify(ctl, |r| r & !EPCTL_STALL | EPCTL_SD0PID);
+    }
+
+    pub fn set_halt(&self, ep: usize, dir_in: bool) {
+        let ctl = if dir_in { diepctl(ep) } else { doepctl(ep) };
+        self.modify(ctl, |r| r | EPCTL_STALL);
+    }
+
+    pub fn halted(&self, ep: usize, dir_in: bool) -> bool {
+        let ctl = if dir_in { diepctl(ep) } else { doepctl(ep) };
+        self.read(ctl) & EPCTL_STALL != 0
+    }
+
+    /// Stalls both directions of endpoint 0, rejecting a control request.
+    /// The hardware clears this on the next SETUP.
+    pub fn stall_ep0(&self) {
+        self.modify(diepctl(0), |r| r | EPCTL_STALL);
+        self.modify(doepctl(0), |r| r | EPCTL_STALL);
+    }
+
+    /// Lets the host send one packet of up to `len` bytes on OUT endpoint
+    /// `ep`. Endpoint 0 can always receive a SETUP as well.
+    pub fn arm_out(&self, ep: usize, len: u16) {
+        let mut size = TSIZ_PKTCNT_1 | u32::from(len);
+        if ep == 0 {
+            size |= DOEPTSIZ0_STUPCNT_3;
+        }
+        self.write(doeptsiz(ep), size);
+        self.modify(doepctl(ep), |r| r | EPCTL_EPENA | EPCTL_CNAK);
+    }
+
+    /// Sends one packet on IN endpoint `ep`, which must not have one in
+    /// flight. An empty `data` sends a zero-length packet.
+    pub fn send(&self, ep: usize, data: &[u8]) {
+        self.write(dieptsiz(ep), TSIZ_PKTCNT_1 | data.len() as u32);
+        self.modify(diepctl(ep), |r| r | EPCTL_EPENA | EPCTL_CNAK);
+        // The TX FIFO holds a whole packet, so there's always room.
+        for chunk in data.chunks(4) {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.write(fifo(ep), u32::from_le_bytes(word));
+        }
+    }
+
+    /// Pops the status of the next entry in the RX FIFO.
+    pub fn pop_rx_status(&self) -> RxStatus {
+        let r = self.read(GRXSTSP);
+        RxStatus {
+            endpoint: (r & 0xF) as usize,
+            len: ((r >> 4) & 0x7FF) as usize,
+            status: (r >> 17) & 0xF,
+        }
+    }
+
+    /// Reads `len` bytes of a received packet from the RX FIFO into `buf`,
+    /// discarding any that don't fit.
+    pub fn read_packet(&self, buf: &mut [u8], len: usize) {
+        for i in (0..len).step_by(4) {
+            let word = self.read(fifo(0)).to_le_bytes();
+            for (j, b) in word.iter().enumerate().take(len - i) {
+                if let Some(slot) = buf.get_mut(i + j) {
+                    *slot = *b;
+                }
+            }
+        }
+    }
+
+    /// Reads and clears the interrupts pending on IN endpoint `ep`.
+    pub fn take_in_interrupts(&self, ep: usize) -> u32 {
+        let bits = self.read(diepint(ep));
+        self.write(diepint(ep), bits);
+        bits
+    }
+
+    /// Reads and clears the interrupts pending on OUT endpoint `ep`.
+    pub fn take_out_interrupts(&self, ep: usize) -> u32 {
+        let bits = self.read(doepint(ep));
+        self.write(doepint(ep), bits);
+        bits
+    }
+}
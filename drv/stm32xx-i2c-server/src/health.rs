@@ -0,0 +1,160 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Per-segment health tracking and isolation.
+//!
+//! A device on a mux segment (a FRU, say) can fail in a way that hangs the
+//! whole bus -- and while we can recover the bus by resetting the controller,
+//! wiggling SCL and resetting the muxes, a device that does this on every
+//! transaction will take the rest of the bus down with it over and over.  To
+//! limit the damage, we count how many transactions in a row have hung the bus
+//! on each segment; once that reaches [`ISOLATION_THRESHOLD`], we stop
+//! enabling the segment and fail transactions on it with `SegmentIsolated`.
+//! After [`ISOLATION_HOLDOFF_MS`] we let one transaction through as a probe:
+//! if it succeeds, the segment is back in service; if it hangs the bus again,
+//! the segment is isolated for another holdoff period.
+//!
+//! The cumulative event counts for each segment are kept in counters (for
+//! the debugger) and are reported to clients via `Op::SegmentHealth`.
+
+use crate::i2c_config::NMUXES;
+use counters::CounterSet;
+use drv_i2c_api::{
+    Controller, Mux, PortIndex, ResponseCode, Segment, SegmentHealth,
+};
+use drv_stm32xx_i2c::I2cMux;
+
+/// Number of consecutive bus hangs on a segment before we isolate it.
+const ISOLATION_THRESHOLD: u8 = 3;
+
+/// How long a segment stays isolated before we'll try it again.
+const ISOLATION_HOLDOFF_MS: u64 = 10_000;
+
+/// Number of segments on each mux.
+const SEGMENTS_PER_MUX: usize = 8;
+
+const NSEGMENTS: usize = NMUXES * SEGMENTS_PER_MUX;
+
+#[derive(Copy, Clone, PartialEq, counters::Count)]
+enum SegmentEvent {
+    Error,
+    Hang,
+    Recovery,
+    Isolated,
+}
+
+type SegmentCounters = <SegmentEvent as counters::Count>::Counters;
+
+#[used]
+static SEGMENT_COUNTERS: [SegmentCounters; NSEGMENTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NEW: SegmentCounters =
+        <SegmentEvent as counters::Count>::NEW_COUNTERS;
+    [NEW; NSEGMENTS]
+};
+
+#[derive(Copy, Clone, Default)]
+struct SegmentState {
+    /// Transactions in a row that have hung the bus
+    consecutive_hangs: u8,
+    /// If isolated, the time at which we'll next allow a probe
+    isolated_until: Option<u64>,
+}
+
+pub struct Health {
+    segments: [SegmentState; NSEGMENTS],
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self {
+            segments: [SegmentState::default(); NSEGMENTS],
+        }
+    }
+
+    ///
+    /// Returns the index of the specified segment, or `MuxNotFound` if there
+    /// is no such mux on the specified controller and port.
+    ///
+    pub fn slot(
+        muxes: &[I2cMux<'_>],
+        controller: Controller,
+        port: PortIndex,
+        (id, segment): (Mux, Segment),
+    ) -> Result<usize, ResponseCode> {
+        let mux = muxes
+            .iter()
+            .position(|m| {
+                m.controller == controller && m.port == port && m.id == id
+            })
+            .ok_or(ResponseCode::MuxNotFound)?;
+
+        // Segments are numbered from 1.
+        Ok(mux * SEGMENTS_PER_MUX + (segment as usize - 1))
+    }
+
+    ///
+    /// Checks that a transaction may be performed on the segment at `slot`.
+    ///
+    pub fn check(&self, slot: usize, now: u64) -> Result<(), ResponseCode> {
+        match self.segments[slot].isolated_until {
+            Some(until) if now < until => Err(ResponseCode::SegmentIsolated),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn record_success(&mut self, slot: usize) {
+        self.segments[slot] = SegmentState::default();
+    }
+
+    ///
+    /// Records a failed transaction on the segment at `slot`.  `hang`
+    /// indicates that the failure left the bus needing a reset, which is
+    /// what counts towards isolation; returns true if the segment has just
+    /// been isolated.
+    ///
+    pub fn record_error(&mut self, slot: usize, hang: bool, now: u64) -> bool {
+        if !hang {
+            counters::count!(SEGMENT_COUNTERS[slot], SegmentEvent::Error);
+            return false;
+        }
+
+        counters::count!(SEGMENT_COUNTERS[slot], SegmentEvent::Hang);
+
+        let state = &mut self.segments[slot];
+        state.consecutive_hangs = state.consecutive_hangs.saturating_add(1);
+
+        if state.consecutive_hangs < ISOLATION_THRESHOLD {
+            return false;
+        }
+
+        //
+        // Either we have just crossed the threshold, or this was the probe
+        // after a holdoff and it hung the bus again.  Either way, the segment
+        // is (re)isolated.
+        //
+        state.isolated_until = Some(now.saturating_add(ISOLATION_HOLDOFF_MS));
+        counters::count!(SEGMENT_COUNTERS[slot], SegmentEvent::Isolated);
+        true
+    }
+
+    pub fn record_recovery(&mut self, slot: usize) {
+        counters::count!(SEGMENT_COUNTERS[slot], SegmentEvent::Recovery);
+    }
+
+    pub fn report(&self, slot: usize, now: u64) -> SegmentHealth {
+        // The counters are iterated over in the order of `SegmentEvent`.
+        let mut counts = SEGMENT_COUNTERS[slot].iter().map(|(_, n)| n);
+        let mut next = || counts.next().unwrap_or(0);
+
+        SegmentHealth {
+            errors: next(),
+            hangs: next(),
+            recoveries: next(),
+            isolations: next(),
+            isolated: self.check(slot, now).is_err() as u8,
+            _reserved: [0; 3],
+        }
+    }
+}
@@ -15,6 +15,10 @@ use fixedmap::*;
 use ringbuf::*;
 use userlib::*;
 
+mod health;
+
+use health::Health;
+
 task_slot!(SYS, sys);
 
 fn lookup_controller<'a, 'b>(
@@ -237,6 +241,7 @@ enum Trace {
     SegmentFailed(ResponseCodeU8),
     ConfigureFailed(ResponseCodeU8),
     Wiggles(u8),
+    SegmentIsolated((Mux, Segment)),
     None,
 }
 
@@ -338,6 +343,36 @@ fn reset_and_wiggle_if_needed(
     }
 }
 
+///
+/// Records a failed transaction against the segment it was on, if any.  Bus
+/// hangs will have been recovered from by [`reset_and_wiggle_if_needed`], and
+/// count towards isolating the segment.  A missing device says nothing about
+/// the health of its segment, so `NoDevice` isn't counted.
+///
+fn record_failure(
+    health: &mut Health,
+    segment: Option<((Mux, Segment), usize)>,
+    code: ResponseCode,
+) {
+    let Some((mux, slot)) = segment else {
+        return;
+    };
+
+    if code == ResponseCode::NoDevice {
+        return;
+    }
+
+    let hang = reset_needed(code);
+
+    if hang {
+        health.record_recovery(slot);
+    }
+
+    if health.record_error(slot, hang, sys_get_timer().now) {
+        ringbuf_entry!(Trace::SegmentIsolated(mux));
+    }
+}
+
 include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
 
 type PortMap = FixedMap<Controller, PortIndex, { i2c_config::NCONTROLLERS }>;
@@ -369,6 +404,7 @@ fn main() -> ! {
     // This is our actual mutable state
     let mut portmap = PortMap::default();
     let mut muxmap = MuxMap::default();
+    let mut health = Health::new();
 
     // Turn the actual peripheral on so that we can interact with it.
     turn_on_i2c(&controllers);
@@ -415,6 +451,23 @@ fn main() -> ! {
 
     loop {
         hl::recv_without_notification(&mut buffer, |op, msg| match op {
+            Op::SegmentHealth => {
+                let (payload, caller) = msg
+                    .fixed::<[u8; 4], SegmentHealth>()
+                    .ok_or(ResponseCode::BadArg)?;
+
+                let (_, controller, port, mux) = Marshal::unmarshal(payload)?;
+                let mux = mux.ok_or(ResponseCode::BadSegment)?;
+
+                let controller = lookup_controller(&controllers, controller)?;
+                validate_port(&pins, controller.controller, port)?;
+
+                let slot =
+                    Health::slot(&muxes, controller.controller, port, mux)?;
+
+                caller.reply(health.report(slot, sys_get_timer().now));
+                Ok(())
+            }
             Op::WriteRead | Op::WriteReadBlock => {
                 let lease_count = msg.lease_count();
 
@@ -436,6 +489,24 @@ fn main() -> ! {
                 let controller = lookup_controller(&controllers, controller)?;
                 validate_port(&pins, controller.controller, port)?;
 
+                //
+                // If the segment has been isolated for repeatedly hanging the
+                // bus, don't go near it.
+                //
+                let segment = match mux {
+                    Some(mux) => {
+                        let slot = Health::slot(
+                            &muxes,
+                            controller.controller,
+                            port,
+                            mux,
+                        )?;
+                        health.check(slot, sys_get_timer().now)?;
+                        Some((mux, slot))
+                    }
+                    None => None,
+                };
+
                 configure_port(&mut portmap, controller, port, &pins);
 
                 match configure_mux(
@@ -449,13 +520,15 @@ fn main() -> ! {
                     Ok(_) => {}
                     Err(code) => {
                         ringbuf_entry!(Trace::MuxError(code.into()));
-                        reset_if_needed(
+                        reset_and_wiggle_if_needed(
                             code,
                             controller,
                             port,
                             &muxes,
                             &mut muxmap,
+                            &pins,
                         );
+                        record_failure(&mut health, segment, code);
                         return Err(code);
                     }
                 }
@@ -537,6 +610,7 @@ fn main() -> ! {
                                 &mut muxmap,
                                 &pins,
                             );
+                            record_failure(&mut health, segment, code);
                             return Err(code);
                         }
                         Ok(_) => {
@@ -545,6 +619,10 @@ fn main() -> ! {
                     }
                 }
 
+                if let Some((_, slot)) = segment {
+                    health.record_success(slot);
+                }
+
                 caller.reply(total);
                 Ok(())
             }
@@ -96,6 +96,7 @@ pub enum Peripheral {
     BackupRam = periph(Group::Ahb4, 28),
 
     Hsem = periph(Group::Ahb4, 25), // 43/47: differs from B3
+    Adc3 = periph(Group::Ahb4, 24), // 43/47 only
 
     #[cfg(feature = "h743")]
     Bdma = periph(Group::Ahb4, 21),
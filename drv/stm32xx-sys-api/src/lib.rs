@@ -20,7 +20,7 @@ cfg_if::cfg_if! {
 
 use derive_idol_err::IdolError;
 use userlib::*;
-use zerocopy::AsBytes;
+use zerocopy::{AsBytes, FromBytes};
 
 pub use drv_stm32xx_gpio_common::{
     Alternate, Mode, OutputType, PinSet, Port, Pull, Speed,
@@ -33,6 +33,86 @@ pub enum RccError {
     NoSuchPeripheral = 1,
 }
 
+/// Errors from runtime clock tree changes.
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+#[repr(u32)]
+#[derive(counters::Count)]
+pub enum ClockError {
+    /// The change would run the CPU faster than the configured maximum.
+    TooFast = 1,
+    /// The requested source isn't available: the external oscillator isn't
+    /// configured, or the PLL isn't running.
+    SourceNotReady,
+    /// The PLL can't be changed while it's driving the system clock.
+    PllInUse,
+    /// The PLL dividers are out of range, or would put the PLL outside its
+    /// operating range.
+    BadDivider,
+    /// The table of tasks to notify of clock changes is full.
+    TooManySubscribers,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// Errors from subscribing to GPIO interrupts at runtime.
+#[derive(Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError)]
+#[repr(u32)]
+#[derive(counters::Count)]
+pub enum GpioIrqError {
+    /// There's no such pin; pins are numbered 0 through 15.
+    BadPin = 1,
+    /// The pin's interrupt line is routed to another task, either in the
+    /// `app.toml` or by another task's subscription. Each line serves one pin
+    /// number, on any one port.
+    LineInUse,
+    /// The caller has no subscription for that pin.
+    NotSubscribed,
+    /// This image's `sys` wasn't built with the `exti` feature.
+    Unsupported,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// Sources for the system clock.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    FromPrimitive,
+    PartialEq,
+    Eq,
+    AsBytes,
+    serde::Deserialize,
+)]
+#[repr(u8)]
+pub enum ClockSource {
+    /// The internal high-speed oscillator.
+    Internal = 0,
+    /// The external high-speed oscillator or crystal.
+    External,
+    /// The main PLL (PLL1 on the H7).
+    Pll,
+}
+
+/// Current frequencies of the system clock and the buses derived from it, in
+/// Hz. Buses the part doesn't have read as zero.
+#[derive(Copy, Clone, Debug, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct ClockFrequencies {
+    pub sysclk: u32,
+    pub cpu: u32,
+    pub ahb: u32,
+    pub apb1: u32,
+    pub apb2: u32,
+    pub apb3: u32,
+    pub apb4: u32,
+    /// The PLL's Q output, which feeds many peripheral kernel clocks; zero if
+    /// the PLL is off.
+    pub pll_q: u32,
+}
+
 /// Configures edge sensitivity for a GPIO interrupt
 #[derive(
     Copy, Clone, FromPrimitive, PartialEq, Eq, AsBytes, serde::Deserialize,
@@ -52,6 +132,28 @@ pub enum Edge {
     Both = 0b11,
 }
 
+/// What makes a GPIO interrupt subscribed with [`Sys::gpio_irq_subscribe`]
+/// fire.
+#[derive(
+    Copy, Clone, FromPrimitive, PartialEq, Eq, AsBytes, serde::Deserialize,
+)]
+// As with `Edge`, the representation makes the `is_*` methods cheap.
+#[repr(u8)]
+pub enum Sensitivity {
+    /// Fires on the rising edge only.
+    Rising = 0b001,
+    /// Fires on the falling edge only.
+    Falling = 0b010,
+    /// Fires on both edges.
+    Both = 0b011,
+    /// Fires while the pin is high: on the rising edge, and again each time
+    /// the interrupt is re-enabled with the pin still high.
+    High = 0b101,
+    /// Fires while the pin is low: on the falling edge, and again each time
+    /// the interrupt is re-enabled with the pin still low.
+    Low = 0b110,
+}
+
 /// Describes which operation is performed by the [`Sys::gpio_irq_control`] IPC.
 #[derive(
     Copy, Clone, FromPrimitive, PartialEq, Eq, AsBytes, serde::Deserialize,
@@ -338,6 +440,40 @@ impl Edge {
     }
 }
 
+impl Sensitivity {
+    const LEVEL: u8 = 0b100;
+
+    /// Returns `true` if this sensitivity should trigger on the rising edge.
+    pub fn is_rising(&self) -> bool {
+        *self as u8 & Self::Rising as u8 != 0
+    }
+
+    /// Returns `true` if this sensitivity should trigger on the falling edge.
+    pub fn is_falling(&self) -> bool {
+        *self as u8 & Self::Falling as u8 != 0
+    }
+
+    /// Returns the pin level that keeps this sensitivity firing, if it's
+    /// level-triggered.
+    pub fn active_level(&self) -> Option<bool> {
+        if *self as u8 & Self::LEVEL != 0 {
+            Some(self.is_rising())
+        } else {
+            None
+        }
+    }
+}
+
+impl From<Edge> for Sensitivity {
+    fn from(edge: Edge) -> Self {
+        match edge {
+            Edge::Rising => Self::Rising,
+            Edge::Falling => Self::Falling,
+            Edge::Both => Self::Both,
+        }
+    }
+}
+
 impl core::ops::BitOr for Edge {
     type Output = Self;
 
@@ -35,5 +35,22 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         ).into());
     }
 
+    const CLOCK_FEATURE: &str = "clock-control";
+
+    if build_util::has_feature(CLOCK_FEATURE) {
+        let out_dir = build_util::out_dir();
+        let dest_path = out_dir.join("clock_config.rs");
+
+        let mut out = std::fs::File::create(dest_path)?;
+
+        let generated = cfg.generate_clock_config()?;
+        writeln!(out, "{generated}")?;
+    } else if cfg.needs_clock_control() {
+        return Err(format!(
+            "the \"drv-stm32xx-sys/{CLOCK_FEATURE}\" feature is required in order to \
+            configure runtime clock control"
+        ).into());
+    }
+
     Ok(())
 }
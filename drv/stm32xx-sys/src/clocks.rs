@@ -0,0 +1,320 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Runtime clock tree control for the STM32H7.
+//!
+//! The clock tree is set up at boot by the kernel's startup code, which also
+//! sets the flash wait states and core voltage for the fastest clock the board
+//! means to run. We don't touch either of those here: instead, we refuse any
+//! change that would run the CPU faster than the configured `max-cpu-hz`, which
+//! must be no faster than the boot configuration. That leaves us free to slow
+//! the clock down (to save power, say) and speed it back up again.
+//!
+//! Changing the system clock changes the CPU clock, which drives the kernel's
+//! tick, so after each change we tell the kernel the new tick divisor. We then
+//! post a notification to each task that has subscribed to clock changes, so
+//! that it can recompute whatever it derived from the old frequencies.
+//!
+//! We only ever change the system clock mux and PLL1. The bus prescalers are
+//! left as they were set at boot, so the bus clocks scale with the CPU clock.
+
+use crate::device;
+use drv_stm32xx_sys_api::{ClockError, ClockFrequencies, ClockSource};
+use userlib::{kipc, sys_post, sys_refresh_task_id, TaskId};
+
+/// Number of tasks that can subscribe to clock change notifications.
+const MAX_SUBSCRIBERS: usize = 8;
+
+const HSI_HZ: u32 = 64_000_000;
+const CSI_HZ: u32 = 4_000_000;
+
+// RCC_CR bits.
+const CR_HSIDIV_SHIFT: u32 = 3;
+const CR_HSEON: u32 = 1 << 16;
+const CR_HSERDY: u32 = 1 << 17;
+const CR_PLL1ON: u32 = 1 << 24;
+const CR_PLL1RDY: u32 = 1 << 25;
+
+// RCC_CFGR system clock switch values, for both SW and SWS.
+const SW_HSI: u32 = 0b000;
+const SW_CSI: u32 = 0b001;
+const SW_HSE: u32 = 0b010;
+const SW_PLL1: u32 = 0b011;
+const CFGR_SWS_SHIFT: u32 = 3;
+
+// RCC_PLLCKSELR fields.
+const PLLSRC_HSI: u32 = 0b00;
+const PLLSRC_CSI: u32 = 0b01;
+const PLLSRC_HSE: u32 = 0b10;
+const DIVM1_SHIFT: u32 = 4;
+const DIVM1_MASK: u32 = 0x3F << DIVM1_SHIFT;
+
+// RCC_PLLCFGR bits for PLL1.
+const PLL1RGE_SHIFT: u32 = 2;
+const PLL1_CFG_MASK: u32 = 0b1111 | 0b111 << 16;
+const DIVP1EN: u32 = 1 << 16;
+const DIVQ1EN: u32 = 1 << 17;
+const DIVR1EN: u32 = 1 << 18;
+
+pub(crate) struct Clocks {
+    subscribers: [Option<(TaskId, u32)>; MAX_SUBSCRIBERS],
+}
+
+impl Clocks {
+    pub(crate) fn new() -> Self {
+        Self {
+            subscribers: [None; MAX_SUBSCRIBERS],
+        }
+    }
+
+    /// Switches the system clock to `source`.
+    pub(crate) fn set_source(
+        &mut self,
+        rcc: &device::rcc::RegisterBlock,
+        source: ClockSource,
+    ) -> Result<(), ClockError> {
+        let (sw, sysclk) = match source {
+            ClockSource::Internal => (SW_HSI, hsi_hz(rcc)),
+            ClockSource::External => {
+                if generated::HSE_HZ == 0 {
+                    return Err(ClockError::SourceNotReady);
+                }
+                (SW_HSE, generated::HSE_HZ)
+            }
+            ClockSource::Pll => {
+                if rcc.cr.read().bits() & CR_PLL1RDY == 0 {
+                    return Err(ClockError::SourceNotReady);
+                }
+                (SW_PLL1, pll1(rcc).0)
+            }
+        };
+        let new = with_prescalers(rcc, sysclk, pll1(rcc).1);
+        if new.cpu > generated::MAX_CPU_HZ {
+            return Err(ClockError::TooFast);
+        }
+
+        if sw == SW_HSE {
+            rcc.cr.modify(|r, w| unsafe { w.bits(r.bits() | CR_HSEON) });
+            while rcc.cr.read().bits() & CR_HSERDY == 0 {
+                // spin
+            }
+        }
+
+        rcc.cfgr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !0b111 | sw) });
+        while (rcc.cfgr.read().bits() >> CFGR_SWS_SHIFT) & 0b111 != sw {
+            // spin
+        }
+
+        // The kernel runs its tick off the CPU clock, in kHz.
+        kipc::set_tick_divisor(new.cpu / 1000);
+        self.notify();
+        Ok(())
+    }
+
+    /// Reprograms PLL1 with the given divisors. It mustn't be the system
+    /// clock at the time.
+    pub(crate) fn configure_pll(
+        &mut self,
+        rcc: &device::rcc::RegisterBlock,
+        divm: u8,
+        divn: u16,
+        divp: u8,
+        divq: u8,
+        divr: u8,
+    ) -> Result<(), ClockError> {
+        if (rcc.cfgr.read().bits() >> CFGR_SWS_SHIFT) & 0b111 == SW_PLL1 {
+            return Err(ClockError::PllInUse);
+        }
+        // PLL1's P output can only divide by even numbers.
+        if !(1..=63).contains(&divm)
+            || !(4..=512).contains(&divn)
+            || !(2..=128).contains(&divp)
+            || divp % 2 != 0
+            || !(1..=128).contains(&divq)
+            || !(1..=128).contains(&divr)
+        {
+            return Err(ClockError::BadDivider);
+        }
+
+        // We use the wide-range VCO, which wants a 2-16MHz reference and runs
+        // at 192-836MHz.
+        let reference = pll_source_hz(rcc) / u32::from(divm);
+        let vco = u64::from(reference) * u64::from(divn);
+        let range = match reference {
+            2_000_000..=3_999_999 => 0b01,
+            4_000_000..=7_999_999 => 0b10,
+            8_000_000..=16_000_000 => 0b11,
+            _ => return Err(ClockError::BadDivider),
+        };
+        if !(192_000_000..=836_000_000).contains(&vco) {
+            return Err(ClockError::BadDivider);
+        }
+
+        rcc.cr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !CR_PLL1ON) });
+        while rcc.cr.read().bits() & CR_PLL1RDY != 0 {
+            // spin
+        }
+
+        rcc.pllckselr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !DIVM1_MASK | u32::from(divm) << DIVM1_SHIFT)
+        });
+        // This also clears PLL1FRACEN and PLL1VCOSEL, selecting the integer
+        // mode and the wide VCO.
+        rcc.pllcfgr.modify(|r, w| unsafe {
+            w.bits(
+                r.bits() & !PLL1_CFG_MASK
+                    | range << PLL1RGE_SHIFT
+                    | DIVP1EN
+                    | DIVQ1EN
+                    | DIVR1EN,
+            )
+        });
+        // The divider fields all hold one less than the divisor.
+        rcc.pll1divr.write(|w| unsafe {
+            w.bits(
+                u32::from(divn - 1)
+                    | u32::from(divp - 1) << 9
+                    | u32::from(divq - 1) << 16
+                    | u32::from(divr - 1) << 24,
+            )
+        });
+
+        rcc.cr
+            .modify(|r, w| unsafe { w.bits(r.bits() | CR_PLL1ON) });
+        while rcc.cr.read().bits() & CR_PLL1RDY == 0 {
+            // spin
+        }
+
+        // The system clock hasn't changed, but peripheral kernel clocks fed
+        // from the PLL's Q output may have.
+        self.notify();
+        Ok(())
+    }
+
+    /// Adds `task` to the set of tasks to be posted `notification` on clock
+    /// changes, replacing any earlier subscription it made.
+    pub(crate) fn subscribe(
+        &mut self,
+        task: TaskId,
+        notification: u32,
+    ) -> Result<(), ClockError> {
+        // Reuse the task's old slot if it has one, including from a previous
+        // incarnation; otherwise take a free one.
+        let slot = self
+            .subscribers
+            .iter()
+            .position(
+                |s| matches!(s, Some((t, _)) if t.index() == task.index()),
+            )
+            .or_else(|| self.subscribers.iter().position(Option::is_none))
+            .ok_or(ClockError::TooManySubscribers)?;
+        self.subscribers[slot] = Some((task, notification));
+        Ok(())
+    }
+
+    fn notify(&self) {
+        for &(task, bits) in self.subscribers.iter().flatten() {
+            sys_post(sys_refresh_task_id(task), bits);
+        }
+    }
+}
+
+/// Works out the current clock frequencies from the RCC configuration.
+pub(crate) fn frequencies(
+    rcc: &device::rcc::RegisterBlock,
+) -> ClockFrequencies {
+    let cfgr = rcc.cfgr.read().bits();
+    let sysclk = match (cfgr >> CFGR_SWS_SHIFT) & 0b111 {
+        SW_HSI => hsi_hz(rcc),
+        SW_CSI => CSI_HZ,
+        SW_HSE => generated::HSE_HZ,
+        _ => pll1(rcc).0,
+    };
+    with_prescalers(rcc, sysclk, pll1(rcc).1)
+}
+
+/// Frequency of the HSI oscillator after its divider.
+fn hsi_hz(rcc: &device::rcc::RegisterBlock) -> u32 {
+    HSI_HZ >> ((rcc.cr.read().bits() >> CR_HSIDIV_SHIFT) & 0b11)
+}
+
+/// Frequency of the oscillator feeding the PLLs, or zero if none is.
+fn pll_source_hz(rcc: &device::rcc::RegisterBlock) -> u32 {
+    match rcc.pllckselr.read().bits() & 0b11 {
+        PLLSRC_HSI => hsi_hz(rcc),
+        PLLSRC_CSI => CSI_HZ,
+        PLLSRC_HSE => generated::HSE_HZ,
+        _ => 0,
+    }
+}
+
+/// Returns the frequencies of PLL1's P and Q outputs, or zeros if it's off.
+fn pll1(rcc: &device::rcc::RegisterBlock) -> (u32, u32) {
+    if rcc.cr.read().bits() & CR_PLL1RDY == 0 {
+        return (0, 0);
+    }
+    let divm = (rcc.pllckselr.read().bits() & DIVM1_MASK) >> DIVM1_SHIFT;
+    if divm == 0 {
+        return (0, 0);
+    }
+    let divr = rcc.pll1divr.read().bits();
+    let divn = (divr & 0x1FF) + 1;
+    let divp = ((divr >> 9) & 0x7F) + 1;
+    let divq = ((divr >> 16) & 0x7F) + 1;
+
+    // We never enable the fractional divider, so this is exact.
+    let vco = u64::from(pll_source_hz(rcc) / divm) * u64::from(divn);
+    (
+        (vco / u64::from(divp)) as u32,
+        (vco / u64::from(divq)) as u32,
+    )
+}
+
+/// Applies the bus prescalers to `sysclk`.
+fn with_prescalers(
+    rcc: &device::rcc::RegisterBlock,
+    sysclk: u32,
+    pll_q: u32,
+) -> ClockFrequencies {
+    let d1 = rcc.d1cfgr.read().bits();
+    let d2 = rcc.d2cfgr.read().bits();
+    let d3 = rcc.d3cfgr.read().bits();
+
+    let cpu = sysclk >> ahb_shift(d1 >> 8);
+    let ahb = cpu >> ahb_shift(d1);
+    ClockFrequencies {
+        sysclk,
+        cpu,
+        ahb,
+        apb1: ahb >> apb_shift(d2 >> 4),
+        apb2: ahb >> apb_shift(d2 >> 8),
+        apb3: ahb >> apb_shift(d1 >> 4),
+        apb4: ahb >> apb_shift(d3 >> 4),
+        pll_q,
+    }
+}
+
+/// Decodes a 4-bit AHB-style prescaler field into a shift.
+fn ahb_shift(field: u32) -> u32 {
+    match field & 0xF {
+        0b1000..=0b1011 => (field & 0xF) - 0b0111,
+        // There's no divide-by-32; the encoding skips from 16 to 64.
+        0b1100..=0b1111 => (field & 0xF) - 0b0110,
+        _ => 0,
+    }
+}
+
+/// Decodes a 3-bit APB-style prescaler field into a shift.
+fn apb_shift(field: u32) -> u32 {
+    match field & 0b111 {
+        f @ 0b100..=0b111 => f - 0b011,
+        _ => 0,
+    }
+}
+
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/clock_config.rs"));
+}
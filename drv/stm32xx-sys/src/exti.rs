@@ -0,0 +1,134 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Runtime subscriptions to EXTI pin interrupts.
+//!
+//! The `app.toml` can route a pin's interrupt to a task, but that fixes the
+//! routing when the image is built. Tasks can also subscribe at runtime, naming
+//! the pin and the notification they'd like. Either way, there are only 16
+//! EXTI lines, one per pin number, each of which can listen to one port at a
+//! time; so a line that's routed in the `app.toml` can't be subscribed to, and
+//! a line can only have one subscriber.
+//!
+//! This module keeps the table of subscriptions. Programming the EXTI and
+//! SYSCFG to match is left to the caller, which has the registers.
+
+use drv_stm32xx_sys_api::{GpioIrqError, Port, Sensitivity};
+use userlib::TaskId;
+
+/// Number of EXTI lines that serve GPIO pins.
+pub(crate) const LINES: usize = 16;
+
+#[derive(Copy, Clone)]
+pub(crate) struct Subscription {
+    pub task: TaskId,
+    pub port: Port,
+    /// Notification bits to post to `task`.
+    pub mask: u32,
+    pub sensitivity: Sensitivity,
+    /// Events this many ticks or fewer after one that was delivered are
+    /// dropped.
+    pub debounce: u64,
+    /// Time at which the most recent event was delivered.
+    pub last_delivered: Option<u64>,
+}
+
+impl Subscription {
+    /// Decides whether an event at time `now` should be delivered, or dropped
+    /// as a bounce, and records it if it's delivered.
+    pub fn accept(&mut self, now: u64) -> bool {
+        match self.last_delivered {
+            Some(t) if now <= t.saturating_add(self.debounce) => false,
+            _ => {
+                self.last_delivered = Some(now);
+                true
+            }
+        }
+    }
+}
+
+pub(crate) struct Subscriptions {
+    lines: [Option<Subscription>; LINES],
+}
+
+impl Subscriptions {
+    pub(crate) const fn new() -> Self {
+        Self {
+            lines: [None; LINES],
+        }
+    }
+
+    /// Returns the subscription for EXTI line `line`, if there is one.
+    pub(crate) fn get_mut(&mut self, line: usize) -> Option<&mut Subscription> {
+        self.lines.get_mut(line)?.as_mut()
+    }
+
+    /// Subscribes `task` to interrupts from `port` pin `pin`, replacing any
+    /// subscription it already had for that pin number. The caller must check
+    /// that the line isn't routed in the `app.toml`.
+    pub(crate) fn subscribe(
+        &mut self,
+        task: TaskId,
+        port: Port,
+        pin: u8,
+        mask: u32,
+        sensitivity: Sensitivity,
+        debounce: u64,
+    ) -> Result<(), GpioIrqError> {
+        let slot = self
+            .lines
+            .get_mut(usize::from(pin))
+            .ok_or(GpioIrqError::BadPin)?;
+        if let Some(other) = slot {
+            if other.task.index() != task.index() {
+                return Err(GpioIrqError::LineInUse);
+            }
+        }
+        *slot = Some(Subscription {
+            task,
+            port,
+            mask,
+            sensitivity,
+            debounce,
+            last_delivered: None,
+        });
+        Ok(())
+    }
+
+    /// Removes `task`'s subscription for pin number `pin`.
+    pub(crate) fn unsubscribe(
+        &mut self,
+        task: TaskId,
+        pin: u8,
+    ) -> Result<(), GpioIrqError> {
+        let slot = self
+            .lines
+            .get_mut(usize::from(pin))
+            .ok_or(GpioIrqError::BadPin)?;
+        match slot {
+            Some(s) if s.task.index() == task.index() => {
+                *slot = None;
+                Ok(())
+            }
+            _ => Err(GpioIrqError::NotSubscribed),
+        }
+    }
+
+    /// Iterates over the lines that `task` has subscribed to with any of the
+    /// notification bits in `mask`.
+    pub(crate) fn matching(
+        &self,
+        task: TaskId,
+        mask: u32,
+    ) -> impl Iterator<Item = (usize, &Subscription)> {
+        (0..LINES).zip(&self.lines).filter_map(move |(i, slot)| {
+            let sub = slot.as_ref()?;
+            if sub.task.index() == task.index() && sub.mask & mask != 0 {
+                Some((i, sub))
+            } else {
+                None
+            }
+        })
+    }
+}
@@ -286,6 +286,63 @@
 //! STM32H7-NUCLEO dev board when the user button is pressed.
 //!
 //! [`nucleo-user-button`]: https://github.com/oxidecomputer/hubris/tree/master/task/nucleo-user-button
+//!
+//!
+//! ## Subscribing at runtime
+//!
+//! A task can also ask for a pin's interrupts at runtime, rather than having
+//! them routed in the `app.toml`, with [`Sys::gpio_irq_subscribe`]. It names
+//! the port and pin, the notification bits it would like, and a
+//! [`Sensitivity`]: an edge, as above, or a level. A level-triggered
+//! subscription fires on the edge into its level and then, each time the task
+//! re-enables it, fires again straight away if the pin is still there, so a
+//! task can't miss an interrupt that's held asserted. The subscription can
+//! also ask for events that come within some number of milliseconds of the
+//! last one delivered to be dropped, which debounces switches and the like.
+//!
+//! ```rust,no-run
+//! # mod notifications { pub const BUTTON_MASK: u32 = 1 << 0; }
+//! sys.gpio_irq_subscribe(
+//!     Port::C,
+//!     13,
+//!     notifications::BUTTON_MASK,
+//!     Sensitivity::Falling,
+//!     20, // ms
+//! )?;
+//! ```
+//!
+//! From there, the task uses `gpio_irq_control` with its notification mask
+//! just as it would for a pin routed in the `app.toml`. Each EXTI line serves
+//! one pin number, on one port at a time, so subscribing fails with
+//! `LineInUse` if the line is routed in the `app.toml` or another task has
+//! subscribed to it. This still needs the `exti` feature, and the EXTI
+//! interrupts mapped to `exti-wildcard-irq`, but `gpio-irqs` may be empty.
+//!
+//!
+//! # Runtime clock control
+//!
+//! With the `clock-control` feature (STM32H7 only), `sys` can also switch the
+//! system clock between the internal oscillator, the external oscillator, and
+//! PLL1, and reprogram PLL1 while it isn't in use. This is for power saving and
+//! clock scaling in the field; see the `clocks` module for the rules. Since it
+//! has to tell the kernel when the CPU clock changes, the task must be marked
+//! `clock-owner = true`, and it needs a config section giving the limits:
+//!
+//! ```toml
+//! [tasks.sys]
+//! clock-owner = true
+//!
+//! [tasks.sys.config.clocks]
+//! # Frequency of the external oscillator, if the board has one.
+//! hse-hz = 8_000_000
+//! # Fastest the CPU may be run. This must not exceed the boot clock
+//! # configuration, which sets the flash wait states to suit it.
+//! max-cpu-hz = 400_000_000
+//! ```
+//!
+//! Drivers whose baud rates or prescalers depend on bus clocks should call
+//! `Sys::clock_subscribe` with a notification bit, and recompute them using
+//! `Sys::clock_frequencies` when it arrives.
 
 #![no_std]
 #![no_main]
@@ -321,7 +378,10 @@ cfg_if! {
 }
 
 use drv_stm32xx_gpio_common::{server::get_gpio_regs, Port};
-use drv_stm32xx_sys_api::{Edge, Group, IrqControl, RccError};
+use drv_stm32xx_sys_api::{
+    ClockError, ClockFrequencies, ClockSource, Edge, GpioIrqError, Group,
+    IrqControl, RccError, Sensitivity,
+};
 use idol_runtime::{ClientError, NotificationHandler, RequestError};
 #[cfg(not(feature = "test"))]
 use task_jefe_api::{Jefe, ResetReason};
@@ -331,6 +391,15 @@ use userlib::*;
 #[cfg(not(feature = "test"))]
 task_slot!(JEFE, jefe);
 
+#[cfg(feature = "clock-control")]
+mod clocks;
+
+#[cfg(feature = "exti")]
+mod exti;
+
+#[cfg(all(feature = "clock-control", not(feature = "family-stm32h7")))]
+compile_error!("runtime clock control is only implemented for the STM32H7");
+
 /// This part of the world contains a _lot_ of registers that are essentially
 /// indexed arrays of bits. The PAC wants us to decide which bit we are altering
 /// at compile time. While there are several places in this driver where we
@@ -386,76 +455,14 @@ fn main() -> ! {
         if #[cfg(feature = "exti")] {
             // EXTI routing global setup.
 
-            // Set up external interrupt routing. This routing is not in EXTI --
-            // that would be too easy! It's in SYSCFG.
-            //
-            // Over in SYSCFG we assign one GPIO port to each of 16 channels, or
-            // rather, to the subset of channels that are actually being used in
-            // our configuration table.
-
-            // Safety: same complaint as above, this is needlessly unsafe in the
-            // API the way we use peripherals.
-            let syscfg = unsafe { &*device::SYSCFG::ptr() };
-
+            // Set up external interrupt routing. We assign one GPIO port to
+            // each of 16 channels, or rather, to the subset of channels that
+            // are actually being used in our configuration table. Others get
+            // routed when tasks subscribe to them.
             for (i, entry) in dispatch_table_iter() {
                 // Process entries that are filled in...
                 if let &Some(ExtiDispatch { port, .. }) = entry {
-                    let register = i >> 2;
-                    let slot = i & 0b11;
-
-                    // This is an array of 4-bit fields spread across 4 32-bit
-                    // registers. We're indexing them with i. There is really no
-                    // good way to do this with the PAC, so we get the vaguely
-                    // horrible nest of match statements you see below. Its goal
-                    // is to
-                    // 1. Select a register based on the top two bits of the
-                    //    index, and then
-                    // 2. Select a field within that register based on the
-                    //    bottom two.
-                    // 3. Stuff the port number into that field.
-                    // 4. Write it back.
-                    match register {
-                        0 => syscfg.exticr1.modify(|_, w| match slot {
-                            // Safety: field modeled incorrectly in PAC
-                            0 => unsafe { w.exti0().bits(port as u8) },
-                            // Safety: field modeled incorrectly in PAC
-                            1 => unsafe { w.exti1().bits(port as u8) },
-                            // Safety: field modeled incorrectly in PAC
-                            2 => unsafe { w.exti2().bits(port as u8) },
-                            // Safety: field modeled incorrectly in PAC
-                            _ => unsafe { w.exti3().bits(port as u8) },
-                        }),
-                        1 => syscfg.exticr2.modify(|_, w| match slot {
-                            // Safety: field modeled incorrectly in PAC
-                            0 => unsafe { w.exti4().bits(port as u8) },
-                            // Safety: field modeled incorrectly in PAC
-                            1 => unsafe { w.exti5().bits(port as u8) },
-                            // Safety: field modeled incorrectly in PAC
-                            2 => unsafe { w.exti6().bits(port as u8) },
-                            // Safety: field modeled incorrectly in PAC
-                            _ => unsafe { w.exti7().bits(port as u8) },
-                        }),
-                        2 => syscfg.exticr3.modify(|_, w| match slot {
-                            // Safety: field modeled incorrectly in PAC
-                            0 => unsafe { w.exti8().bits(port as u8) },
-                            // Safety: field modeled incorrectly in PAC
-                            1 => unsafe { w.exti9().bits(port as u8) },
-                            // Safety: field modeled incorrectly in PAC
-                            2 => unsafe { w.exti10().bits(port as u8) },
-                            // Safety: field modeled incorrectly in PAC
-                            _ => unsafe { w.exti11().bits(port as u8) },
-                        }),
-                        _ => syscfg.exticr4.modify(|_, w| match slot {
-                            // Safety: field modeled incorrectly in PAC
-                            0 => unsafe { w.exti12().bits(port as u8) },
-                            // Safety: field modeled incorrectly in PAC
-                            1 => unsafe { w.exti13().bits(port as u8) },
-                            // Safety: field modeled incorrectly in PAC
-                            2 => unsafe { w.exti14().bits(port as u8) },
-                            // Safety: field modeled incorrectly in PAC
-                            _ => unsafe { w.exti15().bits(port as u8) },
-                        }),
-                    }
+                    route_exti_line(i, port);
                 }
             }
         }
@@ -529,6 +536,12 @@ fn main() -> ! {
 
         #[cfg(feature = "exti")]
         exti_cpupr_2: 0,
+
+        #[cfg(feature = "exti")]
+        exti_subs: exti::Subscriptions::new(),
+
+        #[cfg(feature = "clock-control")]
+        clocks: clocks::Clocks::new(),
     };
 
     #[cfg(feature = "exti")]
@@ -558,9 +571,47 @@ struct ServerImpl<'a> {
     /// interrupt asks us for it.
     #[cfg(feature = "exti")]
     exti_cpupr_2: u16,
+
+    /// EXTI lines that tasks have subscribed to at runtime, rather than
+    /// having them routed in the `app.toml`.
+    #[cfg(feature = "exti")]
+    exti_subs: exti::Subscriptions,
+
+    /// Clock change subscribers.
+    #[cfg(feature = "clock-control")]
+    clocks: clocks::Clocks,
 }
 
 impl ServerImpl<'_> {
+    /// Masks the EXTI lines in `bits`, sets which edges they trigger on, and
+    /// clears anything they had pending. They stay masked until their owner
+    /// enables them with `gpio_irq_control`.
+    #[cfg(feature = "exti")]
+    fn set_exti_line(&self, bits: u32, rising: bool, falling: bool) {
+        // Safety (for all of these): not actually unsafe, the PAC didn't
+        // model these fields right.
+        self.exti
+            .cpuimr1
+            .modify(|r, w| unsafe { w.bits(r.bits() & !bits) });
+        self.exti.rtsr1.modify(|r, w| {
+            let v = if rising {
+                r.bits() | bits
+            } else {
+                r.bits() & !bits
+            };
+            unsafe { w.bits(v) }
+        });
+        self.exti.ftsr1.modify(|r, w| {
+            let v = if falling {
+                r.bits() | bits
+            } else {
+                r.bits() & !bits
+            };
+            unsafe { w.bits(v) }
+        });
+        self.exti.cpupr1.write(|w| unsafe { w.bits(bits) });
+    }
+
     fn unpack_raw(raw: u32) -> Result<(Group, u8), RequestError<RccError>> {
         let bit: u8 = (raw & 0x1F) as u8;
         let bus =
@@ -675,76 +726,83 @@ impl idl::InOrderSysImpl for ServerImpl<'_> {
                 // This mask will later be used for checking the stored
                 // interrupt pending state in `self.exti_cpupr_2` --- we'll put
                 // a 1 here for the index of every slot that's mapped to a
-                // notification in the enable/disable masks.
-                //
-                // We'll also use the presence of any bits in this mask in order
-                // to determine whether the caller actually provided masks that
-                // map to anything interesting, and reply-fault if they
-                // didn't.
+                // notification in the caller's mask, whether in the
+                // `app.toml` or by a subscription.
                 let mut slot_mask = 0u16;
-
                 for (i, _) in exti_dispatch_for(rm.sender, mask) {
-                    // What bit do we touch for this entry? (Mask is to ensure
-                    // that the compiler understands this shift cannot
-                    // overflow.)
-                    let bit = 1 << (i & 0xF);
-
-                    // Record that these bits meant something.
-                    slot_mask |= bit;
-
-                    match op {
-                        IrqControl::Enable => {
-                            // Enable this source by _setting_ the
-                            // corresponding mask bit.
-                            self.exti.cpuimr1.modify(|r, w| {
-                                let new_value = r.bits() | (bit as u32);
-                                // Safety: not actually unsafe, PAC didn't
-                                // model this field right
-                                unsafe { w.bits(new_value) }
-                            });
-                        },
-                        IrqControl::Disable => {
-                            // Disable this source by _clearing_ the
-                            // corresponding mask bit.
-                            self.exti.cpuimr1.modify(|r, w| {
-                                let new_value = r.bits() & !(bit as u32);
-                                // Safety: not actually unsafe, PAC didn't
-                                // model this field right
-                                unsafe {
-                                    w.bits(new_value)
-                                }
-                            });
-                        },
-                        IrqControl::Check => {
-                            // We are just checking if an IRQ has triggered,
-                            // so don't actually mess with the source's mask
-                            // register at all.
-                        }
-                    }
+                    // (Mask is to ensure that the compiler understands this
+                    // shift cannot overflow.)
+                    slot_mask |= 1 << (i & 0xF);
+                }
+                for (i, _) in self.exti_subs.matching(rm.sender, mask) {
+                    slot_mask |= 1 << (i & 0xF);
                 }
 
-                // Check that all the set bits in the caller's provided masks
-                // described interrupts that they actually define. This helps to
-                // catch cases where the mask is wrong, which mostly happens
-                // during development and test, and is annoying to find
-                // otherwise.
-                //
-                // It is unfortunate that we're doing this _after_ potentially
-                // having side effects above. This is a compromise: because this
-                // error basically can't happen in a real program -- you have to
-                // work around the configuration system to achieve it -- we
-                // don't want to add too much cost to the common case of "no
-                // error." But we also don't want the error to go undetected.
+                // Check that the caller's mask described interrupts that they
+                // actually have. This helps to catch cases where the mask is
+                // wrong, which mostly happens during development and test, and
+                // is annoying to find otherwise.
                 if slot_mask == 0 {
                     return Err(ClientError::BadMessageContents.fail());
                 }
 
+                // A level-triggered subscription whose pin is already at its
+                // level won't see the edge it's waiting for, so it fires now
+                // instead, and stays disabled until it's re-enabled.
+                let mut fire_now = 0u16;
+                let mut fire_bits = 0u32;
+                if op == IrqControl::Enable {
+                    for (i, sub) in self.exti_subs.matching(rm.sender, mask) {
+                        if let Some(level) = sub.sensitivity.active_level() {
+                            let input =
+                                unsafe { get_gpio_regs(sub.port) }.read();
+                            if (input & 1 << (i & 0xF) != 0) == level {
+                                fire_now |= 1 << (i & 0xF);
+                                fire_bits |= sub.mask;
+                            }
+                        }
+                    }
+                }
+
+                match op {
+                    IrqControl::Enable => {
+                        // Enable these sources by _setting_ the corresponding
+                        // mask bits.
+                        let bits = u32::from(slot_mask & !fire_now);
+                        self.exti.cpuimr1.modify(|r, w| {
+                            // Safety: not actually unsafe, PAC didn't model
+                            // this field right
+                            unsafe { w.bits(r.bits() | bits) }
+                        });
+                    }
+                    IrqControl::Disable => {
+                        // Disable these sources by _clearing_ the
+                        // corresponding mask bits.
+                        let bits = u32::from(slot_mask);
+                        self.exti.cpuimr1.modify(|r, w| {
+                            // Safety: not actually unsafe, PAC didn't model
+                            // this field right
+                            unsafe { w.bits(r.bits() & !bits) }
+                        });
+                    }
+                    IrqControl::Check => {
+                        // We are just checking if an IRQ has triggered, so
+                        // don't actually mess with the sources' mask register
+                        // at all.
+                    }
+                }
+
                 // Check if any interrupts are pending for the slots mapped to
                 // the caller's notification masks.
                 let pending = self.exti_cpupr_2 & slot_mask != 0;
                 // ...and clear those bits for the next interrupt.
                 self.exti_cpupr_2 &= !slot_mask;
 
+                if fire_now != 0 {
+                    self.exti_cpupr_2 |= fire_now;
+                    sys_post(rm.sender, fire_bits);
+                }
+
                 Ok(pending)
 
             } else {
@@ -838,6 +896,140 @@ impl idl::InOrderSysImpl for ServerImpl<'_> {
             }
         }
     }
+
+    fn gpio_irq_subscribe(
+        &mut self,
+        rm: &RecvMessage,
+        port: Port,
+        pin: u8,
+        notification: u32,
+        sensitivity: Sensitivity,
+        debounce_ms: u32,
+    ) -> Result<(), RequestError<GpioIrqError>> {
+        cfg_if! {
+            if #[cfg(feature = "exti")] {
+                let line = usize::from(pin);
+                match generated::EXTI_DISPATCH_TABLE.get(line) {
+                    None => return Err(GpioIrqError::BadPin.into()),
+                    // Routed in the `app.toml`, so not ours to give out.
+                    Some(Some(_)) => return Err(GpioIrqError::LineInUse.into()),
+                    Some(None) => (),
+                }
+                self.exti_subs.subscribe(
+                    rm.sender,
+                    port,
+                    pin,
+                    notification,
+                    sensitivity,
+                    userlib::hl::ms_to_ticks(u64::from(debounce_ms)),
+                )?;
+
+                // Start from a clean slate: masked, pointed at the right port,
+                // with the requested edges, and with nothing pending.
+                let bit = 1u32 << (line & 0xF);
+                self.set_exti_line(bit, false, false);
+                route_exti_line(line, port);
+                self.set_exti_line(
+                    bit,
+                    sensitivity.is_rising(),
+                    sensitivity.is_falling(),
+                );
+                self.exti_cpupr_2 &= !(bit as u16);
+                Ok(())
+            } else {
+                let _ = (rm, port, pin, notification, sensitivity, debounce_ms);
+                Err(ClientError::UnknownOperation.fail())
+            }
+        }
+    }
+
+    fn gpio_irq_unsubscribe(
+        &mut self,
+        rm: &RecvMessage,
+        pin: u8,
+    ) -> Result<(), RequestError<GpioIrqError>> {
+        cfg_if! {
+            if #[cfg(feature = "exti")] {
+                self.exti_subs.unsubscribe(rm.sender, pin)?;
+                let bit = 1u32 << (pin & 0xF);
+                self.set_exti_line(bit, false, false);
+                self.exti_cpupr_2 &= !(bit as u16);
+                Ok(())
+            } else {
+                let _ = (rm, pin);
+                Err(ClientError::UnknownOperation.fail())
+            }
+        }
+    }
+
+    // The clock control operations, like the EXTI ones, are available
+    // unconditionally, but fault clients in images that don't include them.
+
+    fn clock_frequencies(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<ClockFrequencies, RequestError<core::convert::Infallible>> {
+        cfg_if! {
+            if #[cfg(feature = "clock-control")] {
+                Ok(clocks::frequencies(self.rcc))
+            } else {
+                Err(ClientError::UnknownOperation.fail())
+            }
+        }
+    }
+
+    fn set_clock_source(
+        &mut self,
+        _: &RecvMessage,
+        source: ClockSource,
+    ) -> Result<(), RequestError<ClockError>> {
+        cfg_if! {
+            if #[cfg(feature = "clock-control")] {
+                self.clocks.set_source(self.rcc, source)?;
+                Ok(())
+            } else {
+                let _ = source;
+                Err(ClientError::UnknownOperation.fail())
+            }
+        }
+    }
+
+    fn configure_pll(
+        &mut self,
+        _: &RecvMessage,
+        divm: u8,
+        divn: u16,
+        divp: u8,
+        divq: u8,
+        divr: u8,
+    ) -> Result<(), RequestError<ClockError>> {
+        cfg_if! {
+            if #[cfg(feature = "clock-control")] {
+                self.clocks
+                    .configure_pll(self.rcc, divm, divn, divp, divq, divr)?;
+                Ok(())
+            } else {
+                let _ = (divm, divn, divp, divq, divr);
+                Err(ClientError::UnknownOperation.fail())
+            }
+        }
+    }
+
+    fn clock_subscribe(
+        &mut self,
+        rm: &RecvMessage,
+        notification: u32,
+    ) -> Result<(), RequestError<ClockError>> {
+        cfg_if! {
+            if #[cfg(feature = "clock-control")] {
+                self.clocks.subscribe(rm.sender, notification)?;
+                Ok(())
+            } else {
+                let _ = (rm, notification);
+                Err(ClientError::UnknownOperation.fail())
+            }
+        }
+    }
 }
 
 #[cfg(feature = "exti")]
@@ -904,6 +1096,10 @@ impl NotificationHandler for ServerImpl<'_> {
                     let pending_and_enabled = pending & enabled;
 
                     let mut bits_to_acknowledge = 0u16;
+                    // Events on subscribed lines that are dropped as bounces;
+                    // these are acknowledged, but left enabled.
+                    let mut bounces = 0u16;
+                    let now = sys_get_timer().now;
 
                     for pin_idx in 0..16 {
                         // TODO: this sure looks like it should be using
@@ -926,6 +1122,15 @@ impl NotificationHandler for ServerImpl<'_> {
 
                                 let task = sys_refresh_task_id(task);
                                 sys_post(task, mask);
+                            } else if let Some(sub) =
+                                self.exti_subs.get_mut(pin_idx)
+                            {
+                                if sub.accept(now) {
+                                    let task = sys_refresh_task_id(sub.task);
+                                    sys_post(task, sub.mask);
+                                } else {
+                                    bounces |= 1 << pin_idx;
+                                }
                             } else {
                                 // spurious interrupt.
                                 // TODO: probably add this to a counter; it's
@@ -941,22 +1146,24 @@ impl NotificationHandler for ServerImpl<'_> {
                         // Save pending bits so that when the tasks that own the
                         // interrupt(s) that fired call `Sys.gpio_irq_control` to
                         // check if their IRQs fired, we'll be able to tell them.
-                        self.exti_cpupr_2 |= bits_to_acknowledge;
+                        self.exti_cpupr_2 |= bits_to_acknowledge & !bounces;
 
                         // Mask and unpend interrupts en masse to save like six
                         // cycles because we'll totally notice in practice
                         // </mild-sarcasm>
 
                         // Zero-extend for convenience below:
+                        let bits_to_mask =
+                            u32::from(bits_to_acknowledge & !bounces);
                         let bits_to_acknowledge = u32::from(bits_to_acknowledge);
 
-                        // Mask the sources we're handling by clearing the
-                        // corresponding mask bits. The EXTI unfortunately has
-                        // no way to do this atomically, we need to RMW.
-                        // Fortunately the mask bits are only changed by
-                        // software, and only by _this_ software.
+                        // Mask the sources we're delivering by clearing the
+                        // corresponding mask bits; bounces stay enabled. The
+                        // EXTI unfortunately has no way to do this atomically,
+                        // we need to RMW. Fortunately the mask bits are only
+                        // changed by software, and only by _this_ software.
                         self.exti.cpuimr1.modify(|r, w| {
-                            let new_value = r.bits() & !bits_to_acknowledge;
+                            let new_value = r.bits() & !bits_to_mask;
                             // Safety: this operation is unsafe because the PAC
                             // hasn't thought about it. Enabling an interrupt
                             // source is basically always "safe" in the Rust
@@ -1225,6 +1432,70 @@ cfg_if! {
     }
 }
 
+/// Points EXTI line `line` at pin `line` of `port`.
+#[cfg(feature = "exti")]
+fn route_exti_line(line: usize, port: Port) {
+    // This routing is not in EXTI -- that would be too easy! It's in SYSCFG.
+    //
+    // Safety: same complaint as for the RCC in `main`, this is needlessly
+    // unsafe in the API the way we use peripherals.
+    let syscfg = unsafe { &*device::SYSCFG::ptr() };
+
+    let register = line >> 2;
+    let slot = line & 0b11;
+
+    // This is an array of 4-bit fields spread across 4 32-bit registers. We're
+    // indexing them with `line`. There is really no good way to do this with
+    // the PAC, so we get the vaguely horrible nest of match statements you see
+    // below. Its goal is to
+    // 1. Select a register based on the top two bits of the index, and then
+    // 2. Select a field within that register based on the bottom two.
+    // 3. Stuff the port number into that field.
+    // 4. Write it back.
+    match register {
+        0 => syscfg.exticr1.modify(|_, w| match slot {
+            // Safety: field modeled incorrectly in PAC
+            0 => unsafe { w.exti0().bits(port as u8) },
+            // Safety: field modeled incorrectly in PAC
+            1 => unsafe { w.exti1().bits(port as u8) },
+            // Safety: field modeled incorrectly in PAC
+            2 => unsafe { w.exti2().bits(port as u8) },
+            // Safety: field modeled incorrectly in PAC
+            _ => unsafe { w.exti3().bits(port as u8) },
+        }),
+        1 => syscfg.exticr2.modify(|_, w| match slot {
+            // Safety: field modeled incorrectly in PAC
+            0 => unsafe { w.exti4().bits(port as u8) },
+            // Safety: field modeled incorrectly in PAC
+            1 => unsafe { w.exti5().bits(port as u8) },
+            // Safety: field modeled incorrectly in PAC
+            2 => unsafe { w.exti6().bits(port as u8) },
+            // Safety: field modeled incorrectly in PAC
+            _ => unsafe { w.exti7().bits(port as u8) },
+        }),
+        2 => syscfg.exticr3.modify(|_, w| match slot {
+            // Safety: field modeled incorrectly in PAC
+            0 => unsafe { w.exti8().bits(port as u8) },
+            // Safety: field modeled incorrectly in PAC
+            1 => unsafe { w.exti9().bits(port as u8) },
+            // Safety: field modeled incorrectly in PAC
+            2 => unsafe { w.exti10().bits(port as u8) },
+            // Safety: field modeled incorrectly in PAC
+            _ => unsafe { w.exti11().bits(port as u8) },
+        }),
+        _ => syscfg.exticr4.modify(|_, w| match slot {
+            // Safety: field modeled incorrectly in PAC
+            0 => unsafe { w.exti12().bits(port as u8) },
+            // Safety: field modeled incorrectly in PAC
+            1 => unsafe { w.exti13().bits(port as u8) },
+            // Safety: field modeled incorrectly in PAC
+            2 => unsafe { w.exti14().bits(port as u8) },
+            // Safety: field modeled incorrectly in PAC
+            _ => unsafe { w.exti15().bits(port as u8) },
+        }),
+    }
+}
+
 #[cfg(feature = "exti")]
 #[inline(always)]
 fn dispatch_table_iter(
@@ -1239,7 +1510,10 @@ fn dispatch_table_iter(
 include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
 
 mod idl {
-    use super::{Edge, IrqControl, Port, RccError};
+    use super::{
+        ClockError, ClockFrequencies, ClockSource, Edge, GpioIrqError,
+        IrqControl, Port, RccError, Sensitivity,
+    };
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
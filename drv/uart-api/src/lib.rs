@@ -0,0 +1,30 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for a UART server.
+//!
+//! The server receives continuously into a ring buffer of its own, so bytes
+//! arriving while no client is reading aren't lost unless the ring fills --
+//! and if hardware flow control is configured, the server holds the sender
+//! off before that happens.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::{sys_send, FromPrimitive};
+
+#[derive(
+    Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError, counters::Count,
+)]
+pub enum UartError {
+    /// Received data was lost since the last read, because the ring buffer
+    /// filled (without flow control) or the UART itself overran. Reads after
+    /// this one pick up with the data received since.
+    Overrun = 1,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
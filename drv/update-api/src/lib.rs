@@ -65,6 +65,8 @@ pub enum UpdateError {
     ImageMismatch,
     SignatureNotValidated,
     VersionNotSupported,
+    ChunkCrcMismatch,
+    ChunksMissing,
 }
 
 impl From<UpdateError> for GwUpdateError {
@@ -103,6 +105,9 @@ impl From<UpdateError> for GwUpdateError {
             UpdateError::ImageMismatch => Self::ImageMismatch,
             UpdateError::SignatureNotValidated => Self::SignatureNotValidated,
             UpdateError::VersionNotSupported => Self::VersionNotSupported,
+            // The gateway protocol has nothing finer-grained for these.
+            UpdateError::ChunkCrcMismatch => Self::ImageMismatch,
+            UpdateError::ChunksMissing => Self::UpdateInProgress,
         }
     }
 }
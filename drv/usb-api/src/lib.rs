@@ -0,0 +1,242 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for the USB device controller task.
+//!
+//! The controller task handles the bus and the device-level parts of USB:
+//! enumeration, the device and configuration descriptors, and the standard
+//! requests on endpoint 0. Everything a host sees beyond that is provided by
+//! *class tasks*, each of which implements one or more interfaces:
+//!
+//! 1. A class task claims interface numbers (`claim_interfaces`) and
+//!    endpoints (`claim_endpoint`) from the controller.
+//! 2. It builds its descriptors using those numbers -- [`descriptor`] has
+//!    helpers for this -- and hands them to the controller with
+//!    `add_descriptors`. Once every interface the controller is configured to
+//!    expect has been described, the device connects to the bus.
+//! 3. It moves packets with `read` and `write`, on notification from the
+//!    controller, and answers control requests addressed to its interfaces
+//!    using [`class::handle_control`].
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::{sys_send, FromPrimitive};
+use zerocopy::{AsBytes, FromBytes};
+
+/// Largest packet on any endpoint; we only do full speed.
+pub const MAX_PACKET: usize = 64;
+
+#[derive(
+    Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError, counters::Count,
+)]
+pub enum UsbError {
+    /// There aren't enough interface numbers left.
+    NoInterfaces = 1,
+    /// There are no endpoints left in the requested direction.
+    NoEndpoint,
+    /// The endpoint doesn't exist, or belongs to someone else.
+    BadEndpoint,
+    /// The maximum packet size is zero or more than [`MAX_PACKET`].
+    BadMaxPacket,
+    /// The descriptors don't fit in the configuration descriptor.
+    DescriptorsFull,
+    /// The device has already connected, so its descriptors can't change.
+    AlreadyConnected,
+    /// The host hasn't configured the device.
+    NotConfigured,
+    /// The previous packet on the endpoint hasn't been sent yet.
+    Busy,
+    /// There's no packet, or no control request, waiting.
+    Empty,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, FromPrimitive, AsBytes)]
+#[repr(u8)]
+pub enum EndpointKind {
+    Bulk = 2,
+    Interrupt = 3,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, FromPrimitive, AsBytes)]
+#[repr(u8)]
+pub enum Direction {
+    /// Host to device
+    Out = 0,
+    /// Device to host
+    In = 1,
+}
+
+/// The setup packet of a control request.
+#[derive(Copy, Clone, Debug, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct ControlRequest {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+}
+
+impl ControlRequest {
+    /// Whether the host expects data back.
+    pub fn is_in(&self) -> bool {
+        self.request_type & 0x80 != 0
+    }
+
+    /// Whether this is a class-specific request (as opposed to a standard or
+    /// vendor one).
+    pub fn is_class(&self) -> bool {
+        self.request_type & 0x60 == 0x20
+    }
+
+    /// The interface the request is addressed to, if it's addressed to one.
+    pub fn interface(&self) -> Option<u8> {
+        (self.request_type & 0x1F == 1).then_some(self.index as u8)
+    }
+}
+
+/// Helpers for building the descriptors a class task hands to the
+/// controller.
+pub mod descriptor {
+    use super::{Direction, EndpointKind};
+
+    pub const INTERFACE: u8 = 4;
+    pub const ENDPOINT: u8 = 5;
+    pub const INTERFACE_ASSOCIATION: u8 = 11;
+    pub const CS_INTERFACE: u8 = 0x24;
+
+    /// Builds a run of descriptors in a caller-provided buffer.
+    pub struct Writer<'a> {
+        buf: &'a mut [u8],
+        len: usize,
+    }
+
+    impl<'a> Writer<'a> {
+        pub fn new(buf: &'a mut [u8]) -> Self {
+            Self { buf, len: 0 }
+        }
+
+        /// Appends a descriptor of type `kind`, whose body is `body`; the
+        /// length and type bytes are filled in. Panics if `buf` is too
+        /// small, which is a bug in the class task.
+        pub fn descriptor(&mut self, kind: u8, body: &[u8]) -> &mut Self {
+            let len = body.len() + 2;
+            let out = &mut self.buf[self.len..self.len + len];
+            out[0] = len as u8;
+            out[1] = kind;
+            out[2..].copy_from_slice(body);
+            self.len += len;
+            self
+        }
+
+        /// Groups `count` interfaces starting at `first` into one function,
+        /// which a composite device needs for classes (like CDC) that use
+        /// more than one interface.
+        pub fn association(
+            &mut self,
+            first: u8,
+            count: u8,
+            class: u8,
+            subclass: u8,
+            protocol: u8,
+        ) -> &mut Self {
+            self.descriptor(
+                INTERFACE_ASSOCIATION,
+                &[first, count, class, subclass, protocol, 0],
+            )
+        }
+
+        pub fn interface(
+            &mut self,
+            number: u8,
+            endpoints: u8,
+            class: u8,
+            subclass: u8,
+            protocol: u8,
+        ) -> &mut Self {
+            self.descriptor(
+                INTERFACE,
+                &[number, 0, endpoints, class, subclass, protocol, 0],
+            )
+        }
+
+        /// `interval` is in frames, and only matters for interrupt
+        /// endpoints.
+        pub fn endpoint(
+            &mut self,
+            address: u8,
+            kind: EndpointKind,
+            max_packet: u16,
+            interval: u8,
+        ) -> &mut Self {
+            let [lo, hi] = max_packet.to_le_bytes();
+            self.descriptor(ENDPOINT, &[address, kind as u8, lo, hi, interval])
+        }
+
+        pub fn finish(&self) -> &[u8] {
+            &self.buf[..self.len]
+        }
+    }
+
+    /// The direction of the endpoint at `address`.
+    pub fn direction(address: u8) -> Direction {
+        if address & 0x80 != 0 {
+            Direction::In
+        } else {
+            Direction::Out
+        }
+    }
+}
+
+/// A minimal framework for class tasks' control request handling.
+pub mod class {
+    use super::{ControlRequest, Usb, UsbError, MAX_PACKET};
+
+    pub trait ControlHandler {
+        /// Handles a control request addressed to one of this class's
+        /// interfaces. `data` holds whatever the host sent with it; for a
+        /// request that wants data back, write it into `reply`. Returns the
+        /// number of bytes of `reply` to send, or `None` to reject the
+        /// request.
+        fn control(
+            &mut self,
+            request: &ControlRequest,
+            data: &[u8],
+            reply: &mut [u8],
+        ) -> Option<usize>;
+    }
+
+    /// Answers any control request waiting for the calling task, by handing
+    /// it to `handler`. Call this whenever the notification given to
+    /// `claim_interfaces` is posted.
+    pub fn handle_control(
+        usb: &Usb,
+        handler: &mut impl ControlHandler,
+    ) -> Result<(), UsbError> {
+        let mut data = [0u8; MAX_PACKET];
+        let request = match usb.control_request(&mut data) {
+            Ok(r) => r,
+            Err(UsbError::Empty) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let len = if request.is_in() {
+            0
+        } else {
+            usize::from(request.length).min(MAX_PACKET)
+        };
+        let data = &data[..len];
+
+        let mut reply = [0u8; MAX_PACKET];
+        match handler.control(&request, data, &mut reply) {
+            Some(n) => usb.control_reply(&reply[..n]),
+            None => usb.control_stall(),
+        }
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
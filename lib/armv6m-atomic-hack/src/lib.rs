@@ -35,6 +35,13 @@ pub trait AtomicU32Ext {
     fn swap(&self, val: u32, order: Ordering) -> u32;
     fn fetch_add(&self, val: u32, order: Ordering) -> u32;
     fn fetch_sub(&self, val: u32, order: Ordering) -> u32;
+    fn compare_exchange(
+        &self,
+        current: u32,
+        new: u32,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<u32, u32>;
 }
 
 #[cfg(armv6m)]
@@ -62,6 +69,24 @@ impl AtomicU32Ext for AtomicU32 {
         self.store(rv.wrapping_sub(val), so);
         rv
     }
+
+    #[inline]
+    fn compare_exchange(
+        &self,
+        current: u32,
+        new: u32,
+        success: Ordering,
+        _failure: Ordering,
+    ) -> Result<u32, u32> {
+        let (lo, so) = rmw_ordering(success);
+        let rv = self.load(lo);
+        if rv == current {
+            self.store(new, so);
+            Ok(rv)
+        } else {
+            Err(rv)
+        }
+    }
 }
 
 #[cfg(not(armv6m))]
@@ -80,6 +105,19 @@ impl AtomicU32Ext for AtomicU32 {
     fn fetch_sub(&self, val: u32, order: Ordering) -> u32 {
         core::sync::atomic::AtomicU32::fetch_sub(self, val, order)
     }
+
+    #[inline]
+    fn compare_exchange(
+        &self,
+        current: u32,
+        new: u32,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<u32, u32> {
+        core::sync::atomic::AtomicU32::compare_exchange(
+            self, current, new, success, failure,
+        )
+    }
 }
 
 pub trait AtomicBoolExt {
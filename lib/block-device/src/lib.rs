@@ -0,0 +1,37 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Block devices
+//!
+//! This is the interface between block storage drivers (or rather, their
+//! client APIs) and whatever sits on top of them, such as a filesystem task:
+//! a device is an array of 512-byte blocks, addressed by a 32-bit block
+//! number. That's the shape FAT expects, and it can address up to 2 TiB,
+//! which covers any SD card a microcontroller is likely to meet.
+//!
+//! Transfers are in whole blocks, so a caller can't ask for a partial block
+//! and leave the driver to work out a read-modify-write.
+
+#![no_std]
+
+/// Size of a block, in bytes.
+pub const BLOCK_SIZE: usize = 512;
+
+/// One block's worth of data.
+pub type Block = [u8; BLOCK_SIZE];
+
+/// A device made of `BLOCK_SIZE`-byte blocks.
+pub trait BlockDevice {
+    type Error;
+
+    /// Returns the number of blocks on the device.
+    fn block_count(&self) -> Result<u32, Self::Error>;
+
+    /// Reads consecutive blocks into `blocks`, starting at block `start`.
+    fn read(&self, start: u32, blocks: &mut [Block])
+        -> Result<(), Self::Error>;
+
+    /// Writes `blocks` to consecutive blocks, starting at block `start`.
+    fn write(&self, start: u32, blocks: &[Block]) -> Result<(), Self::Error>;
+}
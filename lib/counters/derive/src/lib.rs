@@ -17,6 +17,41 @@ use syn::{
 ///
 /// Note that this macro can currently only be used on `enum` types.
 ///
+/// Variants may carry data, in which case they're counted by variant alone,
+/// ignoring the values of their fields -- unless a field is annotated with
+/// `#[count(children)]`, as described below. This means an enum used for
+/// ringbuf entries can usually derive `Count` directly, without a parallel
+/// fieldless enum to count. The enum may be generic, including over lifetimes
+/// and with bounds on its parameters; the fields that aren't counted need not
+/// implement anything.
+///
+/// In addition to the counter struct and the `Count` implementation, this
+/// generates an implementation of `counters::CounterSet` for the counter
+/// struct, yielding one entry per counted variant. Variants annotated with
+/// `#[count(children)]` are reported as the total of their nested counters.
+///
+/// # Type Attributes
+///
+/// The following attributes may be added to the `enum` type deriving `Count`:
+///
+/// - `#[count(width = 64)]`: Use a [`counters::SaturatingU64`] for each
+///   counter, rather than the default 32-bit `AtomicU32`. This doubles the
+///   amount of RAM used by the counter struct, but means that the counters
+///   will saturate rather than wrapping, which is useful for events that may
+///   occur more than `u32::MAX` times over a long uptime. The only valid
+///   widths are 32 (the default) and 64. This does not affect the width of
+///   counters for fields annotated with `#[count(children)]`, which is
+///   determined by the child type's own `Count` implementation.
+///
+/// - `#[count(timestamp)]` or `#[count(timestamp = path::to::now)]`: Also
+///   record when each event last happened, using a [`counters::Timestamped`]
+///   cell for each counter. With a path, the timestamp comes from calling the
+///   named `fn() -> u32`; without one, it's the low 32 bits of
+///   `userlib::sys_get_timer().now`, which is the kernel tick count (so the
+///   deriving crate must depend on `userlib`). This lets tooling tell apart
+///   events that happened a lot at boot from ones that are happening now. It
+///   may be combined with `width`, as in `#[count(width = 64, timestamp)]`.
+///
 /// # Variant Attributes
 ///
 /// The following attributes may be added on one or more of the variants of the
@@ -35,6 +70,11 @@ use syn::{
 ///   `Count` trait, however, the `#[count(children)]` attribute can be used to
 ///   generate an instance of the field type's counter struct, and implement
 ///   those counters instead.
+///
+///   The `#[count(children)]` attribute may be placed either on the field
+///   itself, or on a variant which has exactly one field. These are
+///   equivalent: `Foo(#[count(children)] Bar)` and
+///   `#[count(children)] Foo(Bar)` both generate nested counters for `Bar`.
 #[proc_macro_derive(Count, attributes(count))]
 pub fn derive_count(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
@@ -52,7 +92,8 @@ fn gen_count_impl(input: DeriveInput) -> Result<impl ToTokens, syn::Error> {
         ));
     };
     let variants = &data_enum.variants;
-    let mut state = CountGenerator::new(&input, variants.len());
+    let cell = parse_cell(&input.attrs)?;
+    let mut state = CountGenerator::new(&input, variants.len(), cell);
 
     for variant in variants {
         state.add_variant(variant)?;
@@ -67,20 +108,23 @@ struct CountGenerator<'input> {
     field_defs: Vec<proc_macro2::TokenStream>,
     field_inits: Vec<proc_macro2::TokenStream>,
     variant_patterns: Vec<proc_macro2::TokenStream>,
+    counter_values: Vec<proc_macro2::TokenStream>,
     needed_generics: HashSet<syn::Ident>,
     all_generics: HashSet<syn::Ident>,
     where_clause_types: HashSet<syn::Type>,
     any_skipped: bool,
+    cell: Cell,
 }
 
 impl<'input> CountGenerator<'input> {
-    fn new(input: &'input DeriveInput, variants: usize) -> Self {
+    fn new(input: &'input DeriveInput, variants: usize, cell: Cell) -> Self {
         Self {
             enum_name: &input.ident,
             input,
             field_defs: Vec::with_capacity(variants),
             field_inits: Vec::with_capacity(variants),
             variant_patterns: Vec::with_capacity(variants),
+            counter_values: Vec::with_capacity(variants),
             all_generics: input
                 .generics
                 .type_params()
@@ -89,6 +133,7 @@ impl<'input> CountGenerator<'input> {
             needed_generics: HashSet::new(),
             where_clause_types: HashSet::new(),
             any_skipped: false,
+            cell,
         }
     }
 
@@ -99,13 +144,20 @@ impl<'input> CountGenerator<'input> {
             field_defs,
             field_inits,
             mut variant_patterns,
+            counter_values,
             any_skipped,
             needed_generics,
             where_clause_types,
             ..
         } = self;
 
-        let generics = &input.generics;
+        let (impl_generics, ty_generics, enum_where_clause) =
+            input.generics.split_for_impl();
+        // The enum's own bounds have to hold for the `Count` impl, too.
+        let enum_predicates = enum_where_clause
+            .map(|w| w.predicates.iter())
+            .into_iter()
+            .flatten();
 
         // If we skipped any variants, generate a catchall case.
         if any_skipped {
@@ -119,6 +171,15 @@ impl<'input> CountGenerator<'input> {
                 quote! { #ty: counters::Count }
             })
             .collect::<Vec<_>>();
+        let counter_set_where_clauses = where_clause_types
+            .iter()
+            .map(|ty| {
+                quote! {
+                    <#ty as counters::Count>::Counters: counters::CounterSet
+                }
+            })
+            .collect::<Vec<_>>();
+        let num_counters = counter_values.len();
         // I'm not sure why we have to do this, but quote gets mad if it's not a vec...
         let needed_generics = needed_generics.iter().collect::<Vec<_>>();
         quote! {
@@ -132,8 +193,11 @@ impl<'input> CountGenerator<'input> {
             }
 
             #[automatically_derived]
-            impl #generics counters::Count for #enum_name #generics
-            where #(#where_clauses, )* {
+            impl #impl_generics counters::Count for #enum_name #ty_generics
+            where
+                #(#enum_predicates, )*
+                #(#where_clauses, )*
+            {
                 type Counters = #counts_ty<#( #needed_generics, )*>;
 
                 // This is intended for use in a static initializer, so the fact that every
@@ -157,6 +221,20 @@ impl<'input> CountGenerator<'input> {
                     };
                 }
             }
+
+            #[automatically_derived]
+            impl<#( #needed_generics, )*> counters::CounterSet for #counts_ty<#( #needed_generics, )*>
+            where
+                #(#where_clauses, )*
+                #(#counter_set_where_clauses, )*
+            {
+                fn iter(&self) -> impl Iterator<Item = (&'static str, u32)> {
+                    let values: [(&'static str, u32); #num_counters] = [
+                        #(#counter_values),*
+                    ];
+                    values.into_iter()
+                }
+            }
         }
     }
 
@@ -164,25 +242,45 @@ impl<'input> CountGenerator<'input> {
         &mut self,
         variant: &syn::Variant,
     ) -> Result<(), syn::Error> {
+        let mut children = false;
         for attr in &variant.attrs {
             if !attr.path().is_ident("count") {
                 continue;
             }
-            attr.parse_args_with(SkipAttr::parse)?;
-            self.any_skipped = true;
-            return Ok(());
+            match attr.parse_args_with(VariantAttr::parse)? {
+                VariantAttr::Skip => {
+                    self.any_skipped = true;
+                    return Ok(());
+                }
+                VariantAttr::Children => children = true,
+            }
         }
         let enum_name = self.enum_name;
         let variant_name = &variant.ident;
         match &variant.fields {
+            syn::Fields::Unit if children => {
+                return Err(syn::Error::new_spanned(
+                    variant,
+                    "`#[count(children)]` may only be used on variants \
+                    with fields",
+                ));
+            }
             syn::Fields::Unit => {
-                self.variant_patterns.push(quote! { #enum_name::#variant_name => {
-                    counters.#variant_name.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
-                } });
+                let incr = self.cell.increment(variant_name);
+                self.variant_patterns.push(
+                    quote! { #enum_name::#variant_name => {
+                        #incr
+                    } },
+                );
                 self.add_def_init(variant_name);
             }
             ref fields => {
-                if let Some((i, counted_field)) = find_counted_field(fields)? {
+                let counted_field = if children {
+                    Some(find_variant_children_field(variant)?)
+                } else {
+                    find_counted_field(fields)?
+                };
+                if let Some((i, counted_field)) = counted_field {
                     self.add_count_children_def_init(
                         variant_name,
                         &counted_field.ty,
@@ -212,16 +310,17 @@ impl<'input> CountGenerator<'input> {
                     }
                 } else {
                     self.add_def_init(variant_name);
+                    let incr = self.cell.increment(variant_name);
                     if let syn::Fields::Named(_) = fields {
                         self.variant_patterns.push(quote! {
                             #enum_name::#variant_name { .. } => {
-                                counters.#variant_name.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+                                #incr
                             }
                         });
                     } else {
                         self.variant_patterns.push(quote! {
                             #enum_name::#variant_name(..) => {
-                                counters.#variant_name.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+                                #incr
                             }
                         });
                     }
@@ -238,9 +337,13 @@ impl<'input> CountGenerator<'input> {
         let Self {
             field_defs,
             field_inits,
+            counter_values,
             enum_name,
+            cell,
             ..
         } = self;
+        let (cell_ty, cell_init) = cell.cell();
+        let value = cell.value(variant_name);
         field_defs.push(quote! {
             #[doc = concat!(
                 " The total number of times a [`",
@@ -248,11 +351,10 @@ impl<'input> CountGenerator<'input> {
                 "`]"
             )]
             #[doc = " has been recorded by this set of counters."]
-            pub #variant_name: core::sync::atomic::AtomicU32
+            pub #variant_name: #cell_ty
         });
-        field_inits.push(
-            quote! { #variant_name: core::sync::atomic::AtomicU32::new(0) },
-        );
+        field_inits.push(quote! { #variant_name: #cell_init });
+        counter_values.push(quote! { (stringify!(#variant_name), #value) });
     }
 
     /// Generate a field def and field initializer for a variant *with*
@@ -265,6 +367,7 @@ impl<'input> CountGenerator<'input> {
         let Self {
             field_defs,
             field_inits,
+            counter_values,
             enum_name,
             needed_generics,
             all_generics,
@@ -283,6 +386,12 @@ impl<'input> CountGenerator<'input> {
         field_inits.push(quote! {
             #variant_name: <#variant_type as counters::Count>::NEW_COUNTERS
         });
+        counter_values.push(quote! {
+            (
+                stringify!(#variant_name),
+                counters::CounterSet::total(&self.#variant_name),
+            )
+        });
         where_clause_types.insert(variant_type.clone());
         if let syn::Type::Path(ty_path) = variant_type {
             if let Some(ident) = ty_path.path.get_ident() {
@@ -321,25 +430,201 @@ fn find_counted_field(
     Ok(counted_field)
 }
 
+/// The width of the counters generated for an `enum` type.
 #[derive(Copy, Clone, PartialEq, Eq)]
-struct SkipAttr;
+enum Width {
+    /// Wrapping 32-bit counters (the default).
+    U32,
+    /// Saturating 64-bit counters, selected by `#[count(width = 64)]`.
+    U64,
+}
+
+impl Width {
+    /// Returns the type of a counter cell of this width, and an expression
+    /// initializing one.
+    fn cell(self) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+        match self {
+            Width::U32 => (
+                quote! { core::sync::atomic::AtomicU32 },
+                quote! { core::sync::atomic::AtomicU32::new(0) },
+            ),
+            Width::U64 => (
+                quote! { counters::SaturatingU64 },
+                quote! { counters::SaturatingU64::new() },
+            ),
+        }
+    }
+
+    /// Returns an expression reading the value of the counter `cell` as a
+    /// `u32`, in the `CounterSet` implementation.
+    fn value(self, cell: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        match self {
+            Width::U32 => quote! {
+                #cell.load(core::sync::atomic::Ordering::Relaxed)
+            },
+            Width::U64 => quote! {
+                #cell.load_u32()
+            },
+        }
+    }
+
+    /// Returns an expression incrementing the counter `cell`.
+    fn increment(
+        self,
+        cell: proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        match self {
+            Width::U32 => quote! {
+                #cell.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+            },
+            Width::U64 => quote! {
+                #cell.increment();
+            },
+        }
+    }
+}
+
+/// The kind of cell generated for each counter of an `enum` type.
+struct Cell {
+    width: Width,
+    /// If set, an expression producing the current time as a `u32`, and each
+    /// counter is wrapped in a `counters::Timestamped`.
+    timestamp: Option<proc_macro2::TokenStream>,
+}
+
+impl Cell {
+    /// Returns the type of a counter cell, and an expression initializing one.
+    fn cell(&self) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+        let (ty, init) = self.width.cell();
+        if self.timestamp.is_some() {
+            (
+                quote! { counters::Timestamped<#ty> },
+                quote! { counters::Timestamped::new(#init) },
+            )
+        } else {
+            (ty, init)
+        }
+    }
+
+    /// Returns an expression reading the value of the counter for
+    /// `variant_name` as a `u32`, in the `CounterSet` implementation.
+    fn value(&self, variant_name: &syn::Ident) -> proc_macro2::TokenStream {
+        if self.timestamp.is_some() {
+            self.width.value(quote! { self.#variant_name.count })
+        } else {
+            self.width.value(quote! { self.#variant_name })
+        }
+    }
+
+    /// Returns an expression incrementing the counter for `variant_name`.
+    fn increment(&self, variant_name: &syn::Ident) -> proc_macro2::TokenStream {
+        match &self.timestamp {
+            Some(now) => {
+                let incr = self
+                    .width
+                    .increment(quote! { counters.#variant_name.count });
+                quote! {
+                    #incr
+                    counters.#variant_name.stamp(#now);
+                }
+            }
+            None => self.width.increment(quote! { counters.#variant_name }),
+        }
+    }
+}
+
+/// Parses the `#[count(width = N)]` and `#[count(timestamp)]` attributes on
+/// the type deriving `Count`, if there are any.
+fn parse_cell(attrs: &[syn::Attribute]) -> syn::Result<Cell> {
+    let mut width = Width::U32;
+    let mut timestamp = None;
+    for attr in attrs {
+        if !attr.path().is_ident("count") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("timestamp") {
+                timestamp = Some(if meta.input.peek(syn::Token![=]) {
+                    let path: syn::Path = meta.value()?.parse()?;
+                    quote! { #path() }
+                } else {
+                    quote! { userlib::sys_get_timer().now as u32 }
+                });
+                return Ok(());
+            }
+            if !meta.path.is_ident("width") {
+                return Err(meta.error(
+                    "unrecognized `#[count]` attribute, expected \
+                    `#[count(width = ...)]` or `#[count(timestamp)]`",
+                ));
+            }
+            let lit: syn::LitInt = meta.value()?.parse()?;
+            width = match lit.base10_parse::<u32>()? {
+                32 => Width::U32,
+                64 => Width::U64,
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        lit,
+                        "counter width must be either 32 or 64",
+                    ))
+                }
+            };
+            Ok(())
+        })?;
+    }
+    Ok(Cell { width, timestamp })
+}
+
+/// When a variant is annotated with `#[count(children)]`, rather than one of
+/// its fields, the variant must have exactly one field, which is counted.
+fn find_variant_children_field(
+    variant: &syn::Variant,
+) -> syn::Result<(usize, &syn::Field)> {
+    let fields = &variant.fields;
+    if find_counted_field(fields)?.is_some() {
+        return Err(syn::Error::new_spanned(
+            variant,
+            "a variant annotated with `#[count(children)]` may not also \
+            have a field annotated with `#[count(children)]`",
+        ));
+    }
+    let mut iter = fields.iter();
+    match (iter.next(), iter.next()) {
+        (Some(field), None) => Ok((0, field)),
+        _ => Err(syn::Error::new_spanned(
+            variant,
+            "`#[count(children)]` may only be used on variants with a \
+            single field; annotate the counted field instead",
+        )),
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum VariantAttr {
+    Skip,
+    Children,
+}
 
 #[derive(Copy, Clone, PartialEq, Eq)]
 struct ChildrenAttr;
 
-impl Parse for SkipAttr {
+impl Parse for VariantAttr {
     fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
         let ident = input.fork().parse::<syn::Ident>()?;
-        if ident == "skip" {
-            // consume the token
-            let _: syn::Ident = input.parse()?;
-            Ok(Self)
+        let attr = if ident == "skip" {
+            Self::Skip
+        } else if ident == "children" {
+            Self::Children
         } else {
-            Err(syn::Error::new(
+            return Err(syn::Error::new(
                 ident.span(),
-                "unrecognized `#[count]` attribute, expected `#[count(skip)]`",
-            ))
-        }
+                "unrecognized `#[count]` attribute, expected `#[count(skip)]` \
+                or `#[count(children)]`",
+            ));
+        };
+        // consume the token
+        let _: syn::Ident = input.parse()?;
+        Ok(attr)
     }
 }
 
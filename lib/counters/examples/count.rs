@@ -14,15 +14,66 @@ pub enum Event {
     SayHello(#[count(children)] people::Person),
     SomeNumber(u32),
     ToBeOrNotToBe(#[count(children)] bool),
+    #[count(children)]
+    MaybeSo(Option<bool>),
+    #[count(skip)]
+    Nothing,
 }
 
 counters!(Event);
 
+/// Events that may happen more than `u32::MAX` times can use saturating 64-bit
+/// counters instead.
+#[derive(Count, Debug, Copy, Clone, PartialEq, Eq)]
+#[count(width = 64)]
+pub enum Message {
+    Sent,
+    Received(u32),
+}
+
+counters!(MESSAGES, Message);
+
+/// Stand-in for a clock. In a task, `#[count(timestamp)]` without a path uses
+/// the kernel tick count instead.
+fn now() -> u32 {
+    1234
+}
+
+/// Events can also record when they last happened.
+#[derive(Count, Debug, Copy, Clone, PartialEq, Eq)]
+#[count(timestamp = now)]
+pub enum Alarm {
+    Overtemp,
+    Undervolt,
+}
+
+counters!(ALARMS, Alarm);
+
 fn main() {
     count!(Event::SomethingHappened);
     count!(Event::SomeNumber(42));
+    count!(Event::MaybeSo(Some(true)));
+    count!(MESSAGES, Message::Sent);
+    count!(MESSAGES, Message::Received(1));
+    count!(ALARMS, Alarm::Overtemp);
 
     people::say_hello();
+
+    // Counters can also be read back at runtime.
+    for (name, count) in __COUNTERS.iter() {
+        println!("{name}: {count}");
+    }
+    for (name, count) in __COUNTERS.SayHello.iter() {
+        println!("SayHello.{name}: {count}");
+    }
+    println!(
+        "Overtemp: {} (last at {})",
+        ALARMS
+            .Overtemp
+            .count
+            .load(core::sync::atomic::Ordering::Relaxed),
+        ALARMS.Overtemp.last(),
+    );
 }
 
 mod people {
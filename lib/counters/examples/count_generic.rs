@@ -23,6 +23,23 @@ pub enum SaySomething<P, T> {
     Value(T),
 }
 
+/// Bounds on type parameters, lifetimes, and `where` clauses are all carried
+/// over to the generated `Count` impl. Variants with data are counted by
+/// variant, whatever their fields hold.
+#[derive(Count, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Reading<'a, T: Copy + Default, U>
+where
+    U: Copy,
+{
+    Sensor { name: &'a str, value: T },
+    Raw(U, U),
+    Missing,
+}
+
+type SensorReading = Reading<'static, f32, u8>;
+
+counters!(READINGS, SensorReading);
+
 #[derive(Count, Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Person {
     Cliff,
@@ -44,4 +61,14 @@ fn main() {
     count!(Event::SomethingHappened(SaySomething::Value(
         "Hello, world!"
     )));
+
+    count!(
+        READINGS,
+        SensorReading::Sensor {
+            name: "thermistor",
+            value: 21.5
+        }
+    );
+    count!(READINGS, SensorReading::Raw(0x12, 0x34));
+    count!(READINGS, SensorReading::Missing);
 }
@@ -9,6 +9,18 @@
 //!
 //! This crate provides the [`Count`] trait, which defines a countable event,
 //! and the [`counters!`] macro, which declares a set of static counters
+//!
+//! Counters are normally read by a debugger (i.e. Humility) directly out of
+//! the task's RAM. For tasks that want to report their own counters --- over
+//! an Idol interface, say, or into a ringbuf --- the [`CounterSet`] trait
+//! provides a way to iterate over the current values of a set of counters.
+//!
+//! By default, each counter is a 32-bit [`AtomicU32`] that wraps on overflow.
+//! For events that may plausibly occur more than four billion times over the
+//! uptime of a system, the [`SaturatingU64`] counter cell may be selected
+//! using the `#[count(width = 64)]` attribute on the derived type. Adding
+//! `#[count(timestamp)]` wraps each counter in a [`Timestamped`] cell, which
+//! also records when the event last happened.
 
 #![no_std]
 pub use armv6m_atomic_hack;
@@ -36,6 +48,32 @@ pub trait Count {
     fn count(&self, counters: &Self::Counters);
 }
 
+/// A set of counters whose values can be read at runtime.
+///
+/// This is implemented for the counter struct generated by
+/// [`#[derive(Count)]`][drv], as well as for the counters for the types in
+/// this crate that implement [`Count`].
+///
+/// [drv]: counters_derive::Count
+pub trait CounterSet {
+    /// Returns an iterator over the name and current value of each counter
+    /// in this set.
+    ///
+    /// Nested counter sets (those generated by `#[count(children)]`) are
+    /// reported as a single entry whose value is their [`total`]; the nested
+    /// counters can be iterated over separately. Counters wider than 32 bits
+    /// saturate at [`u32::MAX`].
+    ///
+    /// [`total`]: CounterSet::total
+    fn iter(&self) -> impl Iterator<Item = (&'static str, u32)>;
+
+    /// Returns the sum of all counters in this set, saturating at
+    /// [`u32::MAX`].
+    fn total(&self) -> u32 {
+        self.iter().fold(0, |sum, (_, n)| sum.saturating_add(n))
+    }
+}
+
 /// Declares a set of event counters.
 ///
 /// `counters!(NAME, Type)` creates a set of counters named `NAME`, counting
@@ -79,6 +117,109 @@ macro_rules! count {
     };
 }
 
+/// A 64-bit event counter that saturates rather than wrapping.
+///
+/// Cortex-M parts don't have 64-bit atomic operations, so this is made of a
+/// pair of [`AtomicU32`]s: the low word is incremented on every event, and the
+/// high word is incremented when the low word carries out. Once both words
+/// are at their maximum value, the counter stops incrementing.
+///
+/// Like the rest of this crate, this relies on the counters being owned by a
+/// single task, and not being touched from more than one thread of execution.
+/// A debugger reading the counter while it is being incremented may observe a
+/// torn value, but the next read will be consistent.
+pub struct SaturatingU64 {
+    lo: AtomicU32,
+    hi: AtomicU32,
+}
+
+impl SaturatingU64 {
+    /// Returns a new counter with a value of 0.
+    pub const fn new() -> Self {
+        Self {
+            lo: AtomicU32::new(0),
+            hi: AtomicU32::new(0),
+        }
+    }
+
+    /// Increments the counter by 1, saturating at [`u64::MAX`].
+    pub fn increment(&self) {
+        let lo = self.lo.load(Ordering::Relaxed);
+        if lo != u32::MAX {
+            self.lo.store(lo + 1, Ordering::Relaxed);
+            return;
+        }
+        let hi = self.hi.load(Ordering::Relaxed);
+        if hi != u32::MAX {
+            // Bump the high word before wrapping the low word, so that a
+            // debugger never sees the counter go backwards.
+            self.hi.store(hi + 1, Ordering::Relaxed);
+            self.lo.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the current value of the counter.
+    pub fn load(&self) -> u64 {
+        let hi = self.hi.load(Ordering::Relaxed);
+        let lo = self.lo.load(Ordering::Relaxed);
+        (u64::from(hi) << 32) | u64::from(lo)
+    }
+
+    /// Returns the current value of the counter, saturating at [`u32::MAX`].
+    pub fn load_u32(&self) -> u32 {
+        if self.hi.load(Ordering::Relaxed) != 0 {
+            u32::MAX
+        } else {
+            self.lo.load(Ordering::Relaxed)
+        }
+    }
+}
+
+impl Default for SaturatingU64 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A counter cell that also records when its event last happened.
+///
+/// This is selected with the `#[count(timestamp)]` attribute on a type deriving
+/// [`Count`]; `C` is the underlying counter, either an [`AtomicU32`] or a
+/// [`SaturatingU64`]. The timestamp is whatever 32-bit time the deriving type
+/// was configured with -- by default, the low bits of the kernel tick count --
+/// and is 0 if the event has never happened.
+///
+/// Comparing `last` against the current time tells a debugger whether an event
+/// is still occurring, which the count alone can't: a large count may have
+/// been racked up entirely during boot.
+pub struct Timestamped<C> {
+    /// The number of times the event has happened.
+    pub count: C,
+    /// The time at which the event most recently happened.
+    pub last: AtomicU32,
+}
+
+impl<C> Timestamped<C> {
+    /// Returns a new cell wrapping `count`, with a timestamp of 0.
+    pub const fn new(count: C) -> Self {
+        Self {
+            count,
+            last: AtomicU32::new(0),
+        }
+    }
+
+    /// Records that the event happened at time `now`.
+    pub fn stamp(&self, now: u32) {
+        self.last.store(now, Ordering::Relaxed);
+    }
+
+    /// Returns the time at which the event most recently happened, or 0 if it
+    /// never has.
+    pub fn last(&self) -> u32 {
+        self.last.load(Ordering::Relaxed)
+    }
+}
+
 /// Counters for [`Result`]`<T, E>`s where `T` and `E` implement [`Count`].
 #[allow(nonstandard_style)]
 pub struct ResultCounters<T: Count, E: Count> {
@@ -97,6 +238,29 @@ pub struct OptionCounters<T: Count> {
     pub None: AtomicU32,
 }
 
+impl<T: Count, E: Count> CounterSet for ResultCounters<T, E>
+where
+    T::Counters: CounterSet,
+    E::Counters: CounterSet,
+{
+    fn iter(&self) -> impl Iterator<Item = (&'static str, u32)> {
+        [("Ok", self.Ok.total()), ("Err", self.Err.total())].into_iter()
+    }
+}
+
+impl<T: Count> CounterSet for OptionCounters<T>
+where
+    T::Counters: CounterSet,
+{
+    fn iter(&self) -> impl Iterator<Item = (&'static str, u32)> {
+        [
+            ("Some", self.Some.total()),
+            ("None", self.None.load(Ordering::Relaxed)),
+        ]
+        .into_iter()
+    }
+}
+
 impl<T: Count, E: Count> Count for Result<T, E> {
     type Counters = ResultCounters<T, E>;
     const NEW_COUNTERS: Self::Counters = ResultCounters {
@@ -184,6 +348,12 @@ impl Count for core::convert::Infallible {
     }
 }
 
+impl CounterSet for () {
+    fn iter(&self) -> impl Iterator<Item = (&'static str, u32)> {
+        core::iter::empty()
+    }
+}
+
 impl Count for () {
     type Counters = AtomicU32;
     #[allow(clippy::declare_interior_mutable_const)]
@@ -210,6 +380,16 @@ pub struct BoolCounts {
     pub r#false: AtomicU32,
 }
 
+impl CounterSet for BoolCounts {
+    fn iter(&self) -> impl Iterator<Item = (&'static str, u32)> {
+        [
+            ("true", self.r#true.load(Ordering::Relaxed)),
+            ("false", self.r#false.load(Ordering::Relaxed)),
+        ]
+        .into_iter()
+    }
+}
+
 impl Count for bool {
     type Counters = BoolCounts;
 
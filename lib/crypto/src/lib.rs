@@ -0,0 +1,181 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The cryptographic primitives tasks may use, in one place.
+//!
+//! Tasks that hash, MAC, or sign should get those operations from here rather
+//! than depending on `sha2`, `sha3`, `hmac` or `salty` themselves. That keeps
+//! every task on the same implementations with the same features, and gives
+//! one place to review what we claim about side channels:
+//!
+//! - The hashes (`sha256`, `sha3_256`) take time that depends only on the
+//!   length of their input. We don't use the `sha2` crate's assembly or
+//!   hardware backends, which none of our targets have anyway.
+//! - HMAC inherits that, and `hmac_sha256_verify` (like `Mac::verify_slice`)
+//!   compares tags in constant time.
+//!   Don't compare tags (or anything else secret) with `==`; use `ct_eq`.
+//! - Ed25519 comes from `salty`, whose field arithmetic and scalar
+//!   multiplication are constant-time, and which was written for Cortex-M.
+//!   Signing handles secrets; verifying (`ed25519::verify`) only handles public
+//!   values, so its timing doesn't matter.
+//!
+//! Nothing here clears secrets when dropped. Keys live where their owner
+//! keeps them, and it's the owner's job to wipe them.
+//!
+//! For bulk SHA-256 on parts with a hash engine, see `drv-crypto-api`, whose
+//! software fallback is the same `sha2` code as here.
+
+#![no_std]
+
+pub use hmac::Mac;
+pub use sha2::{Digest, Sha256};
+pub use sha3::Sha3_256;
+
+/// Length of a SHA-256 or SHA3-256 digest, in bytes.
+pub const DIGEST_LEN: usize = 32;
+
+/// Returns the SHA-256 digest of `data`.
+pub fn sha256(data: &[u8]) -> [u8; DIGEST_LEN] {
+    Sha256::digest(data).into()
+}
+
+/// Returns the SHA3-256 digest of `data`.
+pub fn sha3_256(data: &[u8]) -> [u8; DIGEST_LEN] {
+    Sha3_256::digest(data).into()
+}
+
+/// HMAC-SHA-256.
+pub type HmacSha256 = hmac::Hmac<Sha256>;
+/// HMAC-SHA3-256.
+pub type HmacSha3_256 = hmac::Hmac<Sha3_256>;
+
+fn hmac_sha256_of(key: &[u8], data: &[u8]) -> HmacSha256 {
+    // HMAC takes keys of any length, so this can't fail.
+    let Ok(mut mac) = HmacSha256::new_from_slice(key) else {
+        unreachable!();
+    };
+    mac.update(data);
+    mac
+}
+
+/// Returns the HMAC-SHA-256 tag of `data` under `key`.
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; DIGEST_LEN] {
+    hmac_sha256_of(key, data).finalize().into_bytes().into()
+}
+
+/// Checks, in constant time, that `tag` is the HMAC-SHA-256 tag of `data`
+/// under `key`.
+pub fn hmac_sha256_verify(key: &[u8], data: &[u8], tag: &[u8]) -> bool {
+    hmac_sha256_of(key, data).verify_slice(tag).is_ok()
+}
+
+/// Compares `a` and `b` in time that depends only on their lengths, which
+/// aren't secret.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    // Keep the compiler from noticing it could stop at the first difference.
+    core::hint::black_box(diff) == 0
+}
+
+pub mod ed25519 {
+    //! Ed25519 signatures, from `salty`.
+
+    pub use salty::constants::{
+        PUBLICKEY_SERIALIZED_LENGTH as PUBLIC_KEY_LEN,
+        SECRETKEY_SEED_LENGTH as SEED_LEN,
+        SIGNATURE_SERIALIZED_LENGTH as SIGNATURE_LEN,
+    };
+    pub use salty::signature::{Keypair, PublicKey, Signature};
+
+    /// Checks that `signature` is `public_key`'s signature of `message`.
+    /// Returns `false` for a signature that doesn't verify, and for a public
+    /// key that isn't a point on the curve.
+    pub fn verify(
+        public_key: &[u8; PUBLIC_KEY_LEN],
+        message: &[u8],
+        signature: &[u8; SIGNATURE_LEN],
+    ) -> bool {
+        let Ok(public_key) = PublicKey::try_from(public_key) else {
+            return false;
+        };
+        let signature = Signature::from(signature);
+        public_key.verify(message, &signature).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> [u8; 32] {
+        let mut out = [0; 32];
+        for (i, b) in out.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn sha256_abc() {
+        let digest = hex(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        );
+        assert_eq!(sha256(b"abc"), digest);
+    }
+
+    #[test]
+    fn sha3_256_abc() {
+        let digest = hex(
+            "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532",
+        );
+        assert_eq!(sha3_256(b"abc"), digest);
+    }
+
+    // RFC 4231, test case 2.
+    #[test]
+    fn hmac_sha256_jefe() {
+        let tag = hex(
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+        );
+        let data = b"what do ya want for nothing?";
+        assert_eq!(hmac_sha256(b"Jefe", data), tag);
+        assert!(hmac_sha256_verify(b"Jefe", data, &tag));
+
+        let mut bad = tag;
+        bad[31] ^= 1;
+        assert!(!hmac_sha256_verify(b"Jefe", data, &bad));
+        assert!(!hmac_sha256_verify(b"Jefe", data, &tag[..16]));
+    }
+
+    #[test]
+    fn ct_eq_works() {
+        assert!(ct_eq(b"", b""));
+        assert!(ct_eq(b"hubris", b"hubris"));
+        assert!(!ct_eq(b"hubris", b"hubrix"));
+        assert!(!ct_eq(b"hubris", b"hubri"));
+    }
+
+    // RFC 8032, section 7.1, test 1.
+    #[test]
+    fn ed25519_verify() {
+        let public_key = hex(
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+        );
+        let mut signature = [0; ed25519::SIGNATURE_LEN];
+        signature[..32].copy_from_slice(&hex(
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155",
+        ));
+        signature[32..].copy_from_slice(&hex(
+            "5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+        ));
+        assert!(ed25519::verify(&public_key, b"", &signature));
+        assert!(!ed25519::verify(&public_key, b"x", &signature));
+
+        signature[0] ^= 1;
+        assert!(!ed25519::verify(&public_key, b"", &signature));
+    }
+}
@@ -0,0 +1,115 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Deferred replies for Idol servers.
+//!
+//! The server code generated by Idol replies to each message as soon as the
+//! handler returns. That suits most servers, but a driver that starts a DMA
+//! transfer and then waits for the completion interrupt can't receive new
+//! work until the transfer finishes -- every other client waits, even if
+//! the hardware could have queued its request too.
+//!
+//! Instead, a handler can capture a `ReplyToken` for the message, stash it
+//! along with whatever it needs to finish the job, and return
+//! `Err(deferred())`. That tells the generated dispatch code not to reply,
+//! so the client stays blocked while the server goes back to its loop. When
+//! the work completes -- typically in the notification handler -- the server
+//! answers through the token:
+//!
+//! ```ignore
+//! fn write(
+//!     &mut self,
+//!     msg: &userlib::RecvMessage,
+//!     data: LenLimit<Leased<R, [u8]>, 256>,
+//! ) -> Result<(), RequestError<SpiError>> {
+//!     self.start_dma(&data)?;
+//!     self.pending = Some(ReplyToken::new(msg));
+//!     Err(deferred())
+//! }
+//!
+//! fn handle_notification(&mut self, bits: u32) {
+//!     if let Some(token) = self.pending.take() {
+//!         token.reply(());
+//!     }
+//! }
+//! ```
+//!
+//! The client's leases stay valid until it gets its reply, so the server can
+//! keep using them in the meantime. If the client is restarted before then,
+//! the kernel drops the late reply, since the token names the client's old
+//! generation. The token also carries the message's reply nonce, so if a bug
+//! leaves a token lying around until the client has sent another message, the
+//! kernel faults the server rather than answering the new message with the
+//! old reply.
+
+#![no_std]
+
+use core::marker::PhantomData;
+
+use idol_runtime::{ClientError, RequestError};
+use userlib::{
+    sys_reply_checked, sys_reply_fault_checked, RecvMessage, ReplyFaultReason,
+    TaskId,
+};
+use zerocopy::AsBytes;
+
+/// Returns the error that a handler returns, after capturing a `ReplyToken`,
+/// to keep the generated dispatch code from replying.
+///
+/// This is the error for a client that has gone away, which Idol never
+/// replies to; the client hasn't actually gone anywhere, but we'd like it to
+/// be treated as though it had, for now.
+pub fn deferred<E>() -> RequestError<E> {
+    RequestError::Fail(ClientError::WentAway)
+}
+
+/// A captured client, waiting for the reply to an operation that returns
+/// `Result<T, E>`.
+///
+/// Dropping a token without replying leaves the client blocked until it's
+/// restarted, which is almost never what you want.
+#[must_use = "the client stays blocked until it gets a reply"]
+pub struct ReplyToken<T, E> {
+    sender: TaskId,
+    nonce: u32,
+    _marker: PhantomData<fn(T, E)>,
+}
+
+impl<T, E> ReplyToken<T, E> {
+    /// Captures the sender of `msg`, the message currently being handled.
+    pub fn new(msg: &RecvMessage) -> Self {
+        Self {
+            sender: msg.sender,
+            nonce: msg.reply_nonce(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the ID of the waiting client.
+    pub fn task_id(&self) -> TaskId {
+        self.sender
+    }
+
+    /// Replies with success, and the operation's return value.
+    pub fn reply(self, value: T)
+    where
+        T: AsBytes,
+    {
+        sys_reply_checked(self.sender, self.nonce, 0, value.as_bytes());
+    }
+
+    /// Replies with one of the operation's errors.
+    pub fn reply_err(self, err: E)
+    where
+        E: Into<u16>,
+    {
+        sys_reply_checked(self.sender, self.nonce, u32::from(err.into()), &[]);
+    }
+
+    /// Faults the client, as the dispatch code does when it returns
+    /// `RequestError::Fail`.
+    pub fn reply_fault(self, reason: ReplyFaultReason) {
+        sys_reply_fault_checked(self.sender, self.nonce, reason);
+    }
+}
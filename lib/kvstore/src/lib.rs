@@ -0,0 +1,797 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A small log-structured key-value store over flash.
+//!
+//! Values (of up to [`MAX_VALUE_LEN`] bytes) are stored under `u32` keys in a
+//! ring of erasable sectors, provided by an implementation of [`Flash`].
+//! Writing a value, or removing one, appends a record to the newest sector;
+//! nothing is ever rewritten in place. Each record carries a CRC, so a record
+//! torn by a loss of power is ignored when the store is next mounted, leaving
+//! the previous value in place. A write is committed once its record has been
+//! programmed.
+//!
+//! When the newest sector fills up we move on to the next one in the ring.
+//! Before the last free sector is used, the oldest sector is compacted: any
+//! values in it that are still current are copied forward, and it is erased.
+//! Going around the ring like this spreads erases evenly over the sectors.
+//! Compaction is also safe against losing power: the oldest sector is marked
+//! as retired (and then erased) only once its live records have been copied,
+//! and if we're interrupted before then, the copies are thrown away and the
+//! compaction starts over.
+//!
+//! To find values, we keep an index in RAM of where each key's current record
+//! is. It holds up to `N` keys, and is rebuilt by scanning the sectors when the
+//! store is mounted.
+
+#![cfg_attr(not(test), no_std)]
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+/// Largest value we can store, in bytes.
+pub const MAX_VALUE_LEN: usize = 256;
+
+/// Marks a sector as part of the store ("KVS1").
+const SECTOR_MAGIC: u32 = 0x3153_564B;
+/// Magic, sequence number, and its complement.
+const SECTOR_HEADER_LEN: usize = 12;
+/// Marks a sector whose live records have been copied elsewhere.
+const RETIRED: u32 = 0;
+
+/// Key, length, kind and CRC.
+const RECORD_HEADER_LEN: usize = 12;
+const MAX_RECORD_LEN: usize = RECORD_HEADER_LEN + MAX_VALUE_LEN;
+
+/// Record kinds. Anything else (including erased flash) isn't a record.
+const KIND_VALUE: u16 = 0x5641;
+const KIND_REMOVED: u16 = 0x4452;
+
+const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// The flash a [`Store`] lives in.
+///
+/// Addresses are relative to the start of the store, which is made up of
+/// `sector_count()` sectors of `SECTOR_SIZE` bytes each.
+pub trait Flash {
+    /// Size of an erasable sector, in bytes.
+    const SECTOR_SIZE: u32;
+
+    /// Size of the smallest unit that can be programmed, in bytes. Each unit
+    /// is programmed at most once between erases.
+    const WRITE_SIZE: u32;
+
+    /// Number of sectors in the store; must be at least 2.
+    fn sector_count(&self) -> u32;
+
+    fn read(&mut self, addr: u32, buf: &mut [u8]) -> Result<(), FlashError>;
+
+    /// Programs `data` at `addr`, which is a multiple of `WRITE_SIZE`.
+    ///
+    /// If `data` doesn't fill its last unit, the rest of the unit is
+    /// programmed with `0xFF` (or left erased).
+    fn program(&mut self, addr: u32, data: &[u8]) -> Result<(), FlashError>;
+
+    /// Erases the given sector, setting every byte to `0xFF`.
+    fn erase(&mut self, sector: u32) -> Result<(), FlashError>;
+}
+
+/// The flash reported a failure.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FlashError;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The flash reported a failure
+    Flash,
+    /// There's no value for this key
+    NotFound,
+    /// The value is longer than `MAX_VALUE_LEN`
+    TooLong,
+    /// The index has no room for another key
+    IndexFull,
+    /// The live values leave no room for this one
+    StoreFull,
+    /// A record failed its check when read back
+    Corrupt,
+}
+
+impl From<FlashError> for Error {
+    fn from(_: FlashError) -> Self {
+        Error::Flash
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Entry {
+    key: u32,
+    /// Address of the key's current record
+    addr: u32,
+    len: u16,
+}
+
+/// What we need to know about a record after reading it into `Store::buf`.
+#[derive(Copy, Clone)]
+struct Record {
+    key: u32,
+    kind: u16,
+    len: usize,
+}
+
+pub struct Store<F, const N: usize> {
+    flash: F,
+    index: [Entry; N],
+    /// Number of entries in use in `index`
+    len: usize,
+    /// The sector we're appending to, which is the newest in the ring
+    head: u32,
+    head_seq: u32,
+    /// The oldest sector in the ring
+    tail: u32,
+    /// Where the next record goes, in `head`
+    next: u32,
+    buf: [u8; MAX_RECORD_LEN],
+}
+
+impl<F: Flash, const N: usize> Store<F, N> {
+    /// A sector must be able to hold at least one record of any size.
+    const CHECK_SIZES: () = assert!(
+        round_up(SECTOR_HEADER_LEN as u32, F::WRITE_SIZE)
+            + F::WRITE_SIZE
+            + round_up(MAX_RECORD_LEN as u32, F::WRITE_SIZE)
+            <= F::SECTOR_SIZE
+    );
+
+    /// Mounts the store in `flash`, building the index from what's there.
+    ///
+    /// If `flash` doesn't hold a store (e.g. because it's blank), a new, empty
+    /// one is made. This only fails if the flash does, or if there are more
+    /// keys than the index can hold.
+    pub fn mount(flash: F) -> Result<Self, Error> {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::CHECK_SIZES;
+        let n = flash.sector_count();
+        assert!(n >= 2);
+
+        let mut store = Self {
+            flash,
+            index: [Entry {
+                key: 0,
+                addr: 0,
+                len: 0,
+            }; N],
+            len: 0,
+            head: 0,
+            head_seq: 0,
+            tail: 0,
+            next: 0,
+            buf: [0; MAX_RECORD_LEN],
+        };
+        store.load()?;
+        Ok(store)
+    }
+
+    /// Number of keys with values.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The keys with values, in no particular order.
+    pub fn keys(&self) -> impl Iterator<Item = u32> + '_ {
+        self.index[..self.len].iter().map(|e| e.key)
+    }
+
+    /// Reads the value stored under `key`.
+    pub fn read(&mut self, key: u32) -> Result<&[u8], Error> {
+        let e = self
+            .find(key)
+            .map(|i| self.index[i])
+            .ok_or(Error::NotFound)?;
+        let end = self.sector_end(e.addr / F::SECTOR_SIZE);
+        match self.read_record(e.addr, end)? {
+            Some(r) if r.key == key && r.kind == KIND_VALUE => {
+                Ok(&self.buf[RECORD_HEADER_LEN..][..r.len])
+            }
+            _ => Err(Error::Corrupt),
+        }
+    }
+
+    /// Stores `value` under `key`, replacing any value already there.
+    ///
+    /// Once this returns `Ok`, the value will survive a loss of power.
+    pub fn write(&mut self, key: u32, value: &[u8]) -> Result<(), Error> {
+        if value.len() > MAX_VALUE_LEN {
+            return Err(Error::TooLong);
+        }
+        if self.find(key).is_none() && self.len == N {
+            return Err(Error::IndexFull);
+        }
+
+        // Check that the live values leave room. The value we're replacing
+        // counts, since compaction may have to copy it before the new one goes
+        // in. Records don't span sectors, so this isn't the whole story;
+        // `make_room` has the last word.
+        let size = record_size::<F>(value.len());
+        let live: u32 = self.index[..self.len]
+            .iter()
+            .map(|e| record_size::<F>(e.len as usize))
+            .sum();
+        let usable = F::SECTOR_SIZE - Self::records_offset();
+        if live + size > (self.flash.sector_count() - 1) * usable {
+            return Err(Error::StoreFull);
+        }
+
+        let addr = self.append(key, KIND_VALUE, value)?;
+        let e = Entry {
+            key,
+            addr,
+            len: value.len() as u16,
+        };
+        // Recovering from a failed compaction rebuilds the index, so look
+        // again.
+        match self.find(key) {
+            Some(i) => self.index[i] = e,
+            None => {
+                self.index[self.len] = e;
+                self.len += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the value stored under `key`.
+    pub fn remove(&mut self, key: u32) -> Result<(), Error> {
+        if self.find(key).is_none() {
+            return Err(Error::NotFound);
+        }
+        self.append(key, KIND_REMOVED, &[])?;
+        if let Some(i) = self.find(key) {
+            self.remove_entry(i);
+        }
+        Ok(())
+    }
+
+    /// Gives back the flash.
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// Finds the ring in flash and builds the index from it.
+    fn load(&mut self) -> Result<(), Error> {
+        let n = self.flash.sector_count();
+        self.len = 0;
+
+        // The newest sector is the one with the highest sequence number.
+        let mut newest = None;
+        for s in 0..n {
+            if let Some(seq) = self.sector_seq(s) {
+                if newest.is_none_or(|(_, q)| seq > q) {
+                    newest = Some((s, seq));
+                }
+            }
+        }
+        let Some((head, head_seq)) = newest else {
+            self.tail = 0;
+            return self.open(0, 1);
+        };
+        self.head = head;
+        self.head_seq = head_seq;
+
+        // The ring runs back from there through consecutive sequence numbers;
+        // everything else is free.
+        self.tail = head;
+        let mut seq = head_seq;
+        while self.used() < n {
+            let prev = (self.tail + n - 1) % n;
+            if seq == 1 || self.sector_seq(prev) != Some(seq - 1) {
+                break;
+            }
+            self.tail = prev;
+            seq -= 1;
+        }
+
+        // If there are no free sectors, a compaction didn't finish copying
+        // out of the oldest sector. The newest holds nothing but copies, so
+        // we can throw it away and start again; a damaged copy might
+        // otherwise leave it without room for the rest.
+        if self.used() == n {
+            self.flash.erase(self.head)?;
+            self.head = (self.head + n - 1) % n;
+            self.head_seq -= 1;
+        }
+
+        // Replay the records, oldest first, so newer ones win.
+        let mut s = self.tail;
+        loop {
+            let end = self.scan(s)?;
+            if s == self.head {
+                self.next = end;
+                return Ok(());
+            }
+            s = (s + 1) % n;
+        }
+    }
+
+    fn find(&self, key: u32) -> Option<usize> {
+        self.index[..self.len].iter().position(|e| e.key == key)
+    }
+
+    fn remove_entry(&mut self, i: usize) {
+        self.len -= 1;
+        self.index[i] = self.index[self.len];
+    }
+
+    /// Number of sectors in the ring, from `tail` to `head`.
+    fn used(&self) -> u32 {
+        let n = self.flash.sector_count();
+        (self.head + n - self.tail) % n + 1
+    }
+
+    fn sector_end(&self, sector: u32) -> u32 {
+        (sector + 1) * F::SECTOR_SIZE
+    }
+
+    /// Returns the sequence number of `sector`, or `None` if it isn't part of
+    /// the store.
+    fn sector_seq(&mut self, sector: u32) -> Option<u32> {
+        let base = sector * F::SECTOR_SIZE;
+        let mut hdr = [0; SECTOR_HEADER_LEN];
+        self.flash.read(base, &mut hdr).ok()?;
+        let magic = u32::from_le_bytes(hdr[0..4].try_into().unwrap());
+        let seq = u32::from_le_bytes(hdr[4..8].try_into().unwrap());
+        let check = u32::from_le_bytes(hdr[8..12].try_into().unwrap());
+        if magic != SECTOR_MAGIC || check != !seq {
+            return None;
+        }
+
+        // Any sign of a retirement mark counts, even if it's torn: it's only
+        // written once everything live has been copied out.
+        let mut mark = [0; 4];
+        self.flash
+            .read(base + Self::retire_offset(), &mut mark)
+            .ok()?;
+        mark.iter().all(|&b| b == 0xFF).then_some(seq)
+    }
+
+    /// Where a sector's retirement mark goes, just after its header.
+    fn retire_offset() -> u32 {
+        round_up(SECTOR_HEADER_LEN as u32, F::WRITE_SIZE)
+    }
+
+    /// Where a sector's first record goes, after its header and retirement
+    /// mark.
+    fn records_offset() -> u32 {
+        Self::retire_offset() + F::WRITE_SIZE
+    }
+
+    /// Erases `sector` and makes it the head of the ring, with sequence
+    /// number `seq`.
+    fn open(&mut self, sector: u32, seq: u32) -> Result<(), Error> {
+        // Compaction leaves sectors erased, so we can usually save an erase.
+        if !self.is_blank(sector)? {
+            self.flash.erase(sector)?;
+        }
+        let mut hdr = [0; SECTOR_HEADER_LEN];
+        hdr[0..4].copy_from_slice(&SECTOR_MAGIC.to_le_bytes());
+        hdr[4..8].copy_from_slice(&seq.to_le_bytes());
+        hdr[8..12].copy_from_slice(&(!seq).to_le_bytes());
+        let base = sector * F::SECTOR_SIZE;
+        self.flash.program(base, &hdr)?;
+        self.head = sector;
+        self.head_seq = seq;
+        self.next = base + Self::records_offset();
+        Ok(())
+    }
+
+    fn is_blank(&mut self, sector: u32) -> Result<bool, Error> {
+        let base = sector * F::SECTOR_SIZE;
+        for addr in (base..self.sector_end(sector)).step_by(self.buf.len()) {
+            let n = (self.sector_end(sector) - addr).min(self.buf.len() as u32);
+            let chunk = &mut self.buf[..n as usize];
+            self.flash.read(addr, chunk)?;
+            if chunk.iter().any(|&b| b != 0xFF) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Applies the records in `sector` to the index, returning the address
+    /// just past the last one.
+    ///
+    /// If we find a record that's damaged, we stop there and return the end
+    /// of the sector, so that nothing else gets written to it.
+    fn scan(&mut self, sector: u32) -> Result<u32, Error> {
+        let end = self.sector_end(sector);
+        let mut pos = sector * F::SECTOR_SIZE + Self::records_offset();
+        while pos + RECORD_HEADER_LEN as u32 <= end {
+            let r = match self.read_record(pos, end) {
+                Ok(Some(r)) => r,
+                Ok(None) => return Ok(pos),
+                Err(_) => return Ok(end),
+            };
+            let found = self.find(r.key);
+            match (r.kind, found) {
+                (KIND_VALUE, Some(i)) => {
+                    self.index[i].addr = pos;
+                    self.index[i].len = r.len as u16;
+                }
+                (KIND_VALUE, None) => {
+                    if self.len == N {
+                        return Err(Error::IndexFull);
+                    }
+                    self.index[self.len] = Entry {
+                        key: r.key,
+                        addr: pos,
+                        len: r.len as u16,
+                    };
+                    self.len += 1;
+                }
+                (_, Some(i)) => self.remove_entry(i),
+                (_, None) => (),
+            }
+            pos += record_size::<F>(r.len);
+        }
+        Ok(end)
+    }
+
+    /// Reads the record at `addr` into `self.buf` and checks it.
+    ///
+    /// Returns `None` if there's no record there, just erased flash.
+    fn read_record(
+        &mut self,
+        addr: u32,
+        end: u32,
+    ) -> Result<Option<Record>, Error> {
+        let (hdr, data) = self.buf.split_at_mut(RECORD_HEADER_LEN);
+        self.flash.read(addr, hdr)?;
+        if hdr.iter().all(|&b| b == 0xFF) {
+            return Ok(None);
+        }
+        let key = u32::from_le_bytes(hdr[0..4].try_into().unwrap());
+        let len = u16::from_le_bytes(hdr[4..6].try_into().unwrap()) as usize;
+        let kind = u16::from_le_bytes(hdr[6..8].try_into().unwrap());
+        let crc = u32::from_le_bytes(hdr[8..12].try_into().unwrap());
+        let valid_len = match kind {
+            KIND_VALUE => len <= MAX_VALUE_LEN,
+            KIND_REMOVED => len == 0,
+            _ => false,
+        };
+        if !valid_len || addr + record_size::<F>(len) > end {
+            return Err(Error::Corrupt);
+        }
+        self.flash
+            .read(addr + RECORD_HEADER_LEN as u32, &mut data[..len])?;
+        if record_crc(hdr, &data[..len]) != crc {
+            return Err(Error::Corrupt);
+        }
+        Ok(Some(Record { key, kind, len }))
+    }
+
+    /// Writes a new record at the end of the log, returning its address.
+    fn append(
+        &mut self,
+        key: u32,
+        kind: u16,
+        data: &[u8],
+    ) -> Result<u32, Error> {
+        self.make_room(record_size::<F>(data.len()))?;
+        let (hdr, body) = self.buf.split_at_mut(RECORD_HEADER_LEN);
+        hdr[0..4].copy_from_slice(&key.to_le_bytes());
+        hdr[4..6].copy_from_slice(&(data.len() as u16).to_le_bytes());
+        hdr[6..8].copy_from_slice(&kind.to_le_bytes());
+        body[..data.len()].copy_from_slice(data);
+        let crc = record_crc(hdr, data);
+        hdr[8..12].copy_from_slice(&crc.to_le_bytes());
+        self.program_record(RECORD_HEADER_LEN + data.len())
+    }
+
+    /// Programs the first `len` bytes of `self.buf` as a record at `next`,
+    /// returning its address.
+    fn program_record(&mut self, len: usize) -> Result<u32, Error> {
+        let addr = self.next;
+        if let Err(e) = self.flash.program(addr, &self.buf[..len]) {
+            // We don't know how much got programmed, so stay away from the
+            // rest of this sector.
+            self.next = self.sector_end(self.head);
+            return Err(e.into());
+        }
+        self.next += record_size::<F>(len - RECORD_HEADER_LEN);
+        Ok(addr)
+    }
+
+    /// Moves along the ring until there's room for a record of `size` bytes
+    /// at `next`.
+    fn make_room(&mut self, size: u32) -> Result<(), Error> {
+        for _ in 0..self.flash.sector_count() {
+            if self.next + size <= self.sector_end(self.head) {
+                return Ok(());
+            }
+            self.advance()?;
+        }
+        Err(Error::StoreFull)
+    }
+
+    /// Opens the next sector in the ring, compacting the oldest one if that
+    /// leaves no sectors free.
+    fn advance(&mut self) -> Result<(), Error> {
+        let n = self.flash.sector_count();
+        // If a compaction failed partway, there are no free sectors; `load`
+        // will undo it, so that we can try again.
+        if self.used() == n {
+            self.load()?;
+        }
+        self.open((self.head + 1) % n, self.head_seq + 1)?;
+        if self.used() < n {
+            return Ok(());
+        }
+        self.compact()
+    }
+
+    /// Copies any live records in the oldest sector to `head`, then retires
+    /// and erases it.
+    fn compact(&mut self) -> Result<(), Error> {
+        let tail = self.tail;
+        let base = tail * F::SECTOR_SIZE;
+        let end = self.sector_end(tail);
+        let mut i = 0;
+        while i < self.len {
+            let e = self.index[i];
+            if !(base..end).contains(&e.addr) {
+                i += 1;
+                continue;
+            }
+            match self.read_record(e.addr, end) {
+                Ok(Some(r)) if r.key == e.key && r.kind == KIND_VALUE => {
+                    let size = record_size::<F>(r.len);
+                    if self.next + size > self.sector_end(self.head) {
+                        return Err(Error::StoreFull);
+                    }
+                    self.index[i].addr =
+                        self.program_record(RECORD_HEADER_LEN + r.len)?;
+                    i += 1;
+                }
+                Err(Error::Flash) => return Err(Error::Flash),
+                // The value's already lost; don't let it hold up the rest.
+                _ => self.remove_entry(i),
+            }
+        }
+        // Once the mark is down, the sector is free, even if the erase doesn't
+        // finish.
+        self.flash
+            .program(base + Self::retire_offset(), &RETIRED.to_le_bytes())?;
+        self.tail = (tail + 1) % self.flash.sector_count();
+        self.flash.erase(tail)?;
+        Ok(())
+    }
+}
+
+/// Space taken up in flash by a record holding `len` bytes.
+fn record_size<F: Flash>(len: usize) -> u32 {
+    round_up((RECORD_HEADER_LEN + len) as u32, F::WRITE_SIZE)
+}
+
+/// Checks a record's key, length, kind and data.
+fn record_crc(hdr: &[u8], data: &[u8]) -> u32 {
+    let mut digest = CRC32.digest();
+    digest.update(&hdr[..8]);
+    digest.update(data);
+    digest.finalize()
+}
+
+const fn round_up(n: u32, to: u32) -> u32 {
+    n.div_ceil(to) * to
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECTOR_SIZE: usize = 1024;
+    const WRITE_SIZE: usize = 32;
+
+    /// RAM pretending to be flash, which checks that nothing is programmed
+    /// twice, and can be made to fail partway through programming.
+    struct FakeFlash {
+        mem: Vec<u8>,
+        /// Bytes that may be programmed before we "lose power", leaving the
+        /// rest of the operation undone
+        budget: Option<usize>,
+        erases: Vec<u32>,
+    }
+
+    impl FakeFlash {
+        fn new(sectors: usize) -> Self {
+            Self {
+                mem: vec![0xFF; sectors * SECTOR_SIZE],
+                budget: None,
+                erases: vec![0; sectors],
+            }
+        }
+    }
+
+    impl Flash for FakeFlash {
+        const SECTOR_SIZE: u32 = SECTOR_SIZE as u32;
+        const WRITE_SIZE: u32 = WRITE_SIZE as u32;
+
+        fn sector_count(&self) -> u32 {
+            (self.mem.len() / SECTOR_SIZE) as u32
+        }
+
+        fn read(
+            &mut self,
+            addr: u32,
+            buf: &mut [u8],
+        ) -> Result<(), FlashError> {
+            let addr = addr as usize;
+            buf.copy_from_slice(&self.mem[addr..addr + buf.len()]);
+            Ok(())
+        }
+
+        fn program(
+            &mut self,
+            addr: u32,
+            data: &[u8],
+        ) -> Result<(), FlashError> {
+            let addr = addr as usize;
+            assert_eq!(addr % WRITE_SIZE, 0);
+            let units = data.len().div_ceil(WRITE_SIZE) * WRITE_SIZE;
+            assert!(self.mem[addr..addr + units].iter().all(|&b| b == 0xFF));
+            for (i, &b) in data.iter().enumerate() {
+                if let Some(budget) = &mut self.budget {
+                    if *budget == 0 {
+                        return Err(FlashError);
+                    }
+                    *budget -= 1;
+                }
+                self.mem[addr + i] = b;
+            }
+            Ok(())
+        }
+
+        fn erase(&mut self, sector: u32) -> Result<(), FlashError> {
+            if self.budget == Some(0) {
+                return Err(FlashError);
+            }
+            let s = sector as usize;
+            self.mem[s * SECTOR_SIZE..][..SECTOR_SIZE].fill(0xFF);
+            self.erases[s] += 1;
+            Ok(())
+        }
+    }
+
+    type TestStore = Store<FakeFlash, 8>;
+
+    fn remount(store: TestStore) -> TestStore {
+        let mut flash = store.into_inner();
+        flash.budget = None;
+        TestStore::mount(flash).unwrap()
+    }
+
+    #[test]
+    fn read_write_remove() {
+        let mut store = TestStore::mount(FakeFlash::new(4)).unwrap();
+        assert!(store.is_empty());
+        assert_eq!(store.read(1), Err(Error::NotFound));
+
+        store.write(1, b"one").unwrap();
+        store.write(2, b"two").unwrap();
+        store.write(1, b"uno").unwrap();
+        assert_eq!(store.read(1).unwrap(), b"uno");
+        assert_eq!(store.read(2).unwrap(), b"two");
+        assert_eq!(store.len(), 2);
+
+        store.remove(2).unwrap();
+        assert_eq!(store.read(2), Err(Error::NotFound));
+        assert_eq!(store.remove(2), Err(Error::NotFound));
+
+        let mut store = remount(store);
+        assert_eq!(store.read(1).unwrap(), b"uno");
+        assert_eq!(store.read(2), Err(Error::NotFound));
+        assert_eq!(store.keys().collect::<Vec<_>>(), [1]);
+    }
+
+    #[test]
+    fn limits() {
+        let mut store = TestStore::mount(FakeFlash::new(2)).unwrap();
+        assert_eq!(
+            store.write(0, &[0; MAX_VALUE_LEN + 1]),
+            Err(Error::TooLong)
+        );
+        for key in 0..8 {
+            store.write(key, &[key as u8]).unwrap();
+        }
+        assert_eq!(store.write(8, b"x"), Err(Error::IndexFull));
+        // Replacing a value doesn't need another entry.
+        store.write(7, b"seven").unwrap();
+
+        // One sector's worth of big values is all that fits.
+        let mut store = TestStore::mount(FakeFlash::new(2)).unwrap();
+        store.write(0, &[0; MAX_VALUE_LEN]).unwrap();
+        store.write(1, &[1; MAX_VALUE_LEN]).unwrap();
+        store.write(2, &[2; MAX_VALUE_LEN]).unwrap();
+        assert_eq!(store.write(3, &[3; MAX_VALUE_LEN]), Err(Error::StoreFull));
+        store.write(1, b"smaller").unwrap();
+        store.write(3, &[3; MAX_VALUE_LEN]).unwrap();
+        assert_eq!(store.read(1).unwrap(), b"smaller");
+        assert_eq!(store.read(3).unwrap(), &[3; MAX_VALUE_LEN]);
+    }
+
+    #[test]
+    fn compaction_spreads_wear() {
+        let mut store = TestStore::mount(FakeFlash::new(4)).unwrap();
+        store.write(100, b"keep me").unwrap();
+        for i in 0..1000u32 {
+            store.write(i % 4, &i.to_le_bytes()).unwrap();
+        }
+        store.remove(3).unwrap();
+
+        let mut store = remount(store);
+        assert_eq!(store.read(100).unwrap(), b"keep me");
+        assert_eq!(store.read(0).unwrap(), 996u32.to_le_bytes());
+        assert_eq!(store.read(2).unwrap(), 998u32.to_le_bytes());
+        assert_eq!(store.read(3), Err(Error::NotFound));
+
+        let erases = &store.into_inner().erases;
+        let (min, max) = (erases.iter().min(), erases.iter().max());
+        assert!(max.unwrap() - min.unwrap() <= 1, "{erases:?}");
+    }
+
+    #[test]
+    fn torn_writes_are_ignored() {
+        let mut store = TestStore::mount(FakeFlash::new(2)).unwrap();
+        store.write(1, b"old").unwrap();
+
+        store.flash.budget = Some(RECORD_HEADER_LEN + 2);
+        assert_eq!(store.write(1, b"new"), Err(Error::Flash));
+
+        let mut store = remount(store);
+        assert_eq!(store.read(1).unwrap(), b"old");
+        // We don't write after the damage, but carry on elsewhere.
+        store.write(1, b"newer").unwrap();
+        let mut store = remount(store);
+        assert_eq!(store.read(1).unwrap(), b"newer");
+    }
+
+    #[test]
+    fn interrupted_compaction() {
+        // Find out how many bytes a compaction programs, then lose power at
+        // every point along the way.
+        for budget in 0.. {
+            let mut store = TestStore::mount(FakeFlash::new(2)).unwrap();
+            // Fill the first sector, so that the next write compacts it.
+            for key in 0..3 {
+                store.write(key, &[key as u8; 100]).unwrap();
+            }
+            for i in 0..4 {
+                store.write(0, &[i; 100]).unwrap();
+            }
+
+            store.flash.budget = Some(budget);
+            let done = store.write(0, &[9; 100]).is_ok();
+
+            let mut store = remount(store);
+            let expected = if done { 9 } else { 3 };
+            assert_eq!(store.read(0).unwrap(), &[expected; 100]);
+            assert_eq!(store.read(1).unwrap(), &[1; 100]);
+            assert_eq!(store.read(2).unwrap(), &[2; 100]);
+
+            // Whatever state we were left in, we can carry on.
+            for i in 0..20 {
+                store.write(1, &[i; 100]).unwrap();
+            }
+            let mut store = remount(store);
+            assert_eq!(store.read(0).unwrap(), &[expected; 100]);
+            assert_eq!(store.read(1).unwrap(), &[19; 100]);
+            assert_eq!(store.read(2).unwrap(), &[2; 100]);
+
+            if done {
+                break;
+            }
+        }
+    }
+}
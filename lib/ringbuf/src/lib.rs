@@ -163,6 +163,50 @@
 //! counted_ringbuf!(MyEvent, 16, MyEvent::NothingHappened, no_dedup);
 //! ```
 //!
+//! ### Timestamps
+//!
+//! Entries record the order in which things happened, but not how far apart
+//! they were. To record that too, give the [`ringbuf!`] macro a clock: a
+//! function returning a `u64` time, which is called as each entry is recorded.
+//! The time is kept in each entry's `timestamp` field. Any monotonic source
+//! will do; the kernel timer is the usual choice, and the cycle counter gives
+//! finer resolution where it's enabled:
+//!
+//! ```
+//! fn now() -> u64 {
+//!     userlib::sys_get_timer().now
+//! }
+//!
+//! ringbuf!(u32, 16, 0, timestamp = now);
+//! ringbuf!(CYCLES_RINGBUF, u32, 16, 0, no_dedup, timestamp = || {
+//!     u64::from(cortex_m::peripheral::DWT::cycle_count())
+//! });
+//! ```
+//!
+//! When an entry is de-duplicated, its timestamp remains that of the first
+//! occurrence. Recording a timestamp costs a call to the clock and eight bytes
+//! per entry. Timestamps aren't available with [`counted_ringbuf!`].
+//!
+//! ### Recording from more than one context
+//!
+//! Recording into a ring buffer declared with [`ringbuf!`] borrows it for the
+//! duration, and an entry recorded while it's borrowed -- say, by a panic
+//! handler or callback that interrupted a recording in progress -- is quietly
+//! dropped. Where that matters, [`atomic_ringbuf!`] declares an
+//! [`AtomicRingbuf`], which hands out slots with a compare-and-swap on its
+//! index, so that each recording gets a slot of its own without taking a lock:
+//!
+//! ```
+//! atomic_ringbuf!(Trace, 16, Trace::None);
+//!
+//! ringbuf_entry!(Trace::Panicked);
+//! ```
+//!
+//! An entry is only lost if the ring buffer wraps all the way around to a slot
+//! whose recording is still in progress; such entries are tallied in the
+//! `dropped` field. Atomic ring buffers don't de-duplicate entries, as that
+//! would mean changing an entry that another recording might be writing.
+//!
 //! ## Inspecting a ring buffer via Humility
 //!
 //! Humility has built-in support for dumping a ring buffer, and will (by
@@ -243,17 +287,38 @@ pub use counters::Count;
 /// macros is guaranteed to be able to find them.
 pub use static_cell::StaticCell;
 
+use armv6m_atomic_hack::{AtomicBoolExt, AtomicU32Ext};
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
 #[cfg(feature = "disabled")]
 #[macro_export]
 macro_rules! ringbuf {
+    (
+        $name:ident, $t:ty, $n:expr, $init:expr, no_dedup,
+        timestamp = $clock:expr
+    ) => {
+        $crate::ringbuf!($name, $t, $n, $init, timestamp = $clock);
+    };
+    ($name:ident, $t:ty, $n:expr, $init:expr, timestamp = $clock:expr) => {
+        #[allow(dead_code)]
+        const _: ($t, fn() -> u64) = ($init, $clock);
+        static $name: () = ();
+    };
     ($name:ident, $t:ty, $n:expr, $init:expr, no_dedup) => {
-        $crate::ringbuf!($name, $t, $n, $init)
+        $crate::ringbuf!($name, $t, $n, $init);
     };
     ($name:ident, $t:ty, $n:expr, $init:expr) => {
         #[allow(dead_code)]
         const _: $t = $init;
         static $name: () = ();
     };
+    ($t:ty, $n:expr, $init:expr, no_dedup, timestamp = $clock:expr) => {
+        $crate::ringbuf!(__RINGBUF, $t, $n, $init, timestamp = $clock);
+    };
+    ($t:ty, $n:expr, $init:expr, timestamp = $clock:expr) => {
+        $crate::ringbuf!(__RINGBUF, $t, $n, $init, timestamp = $clock);
+    };
     ($t:ty, $n:expr, $init:expr, no_dedup) => {
         $crate::ringbuf!(__RINGBUF, $t, $n, $init);
     };
@@ -262,6 +327,43 @@ macro_rules! ringbuf {
     };
 }
 
+#[cfg(feature = "disabled")]
+#[macro_export]
+macro_rules! atomic_ringbuf {
+    ($name:ident, $t:ty, $n:expr, $init:expr) => {
+        #[allow(dead_code)]
+        const _: $t = $init;
+        static $name: () = ();
+    };
+    ($t:ty, $n:expr, $init:expr) => {
+        $crate::atomic_ringbuf!(__RINGBUF, $t, $n, $init);
+    };
+}
+
+/// Declares a ringbuffer that can be recorded into from more than one context
+/// at once; see [the crate-level
+/// documentation](crate#recording-from-more-than-one-context).
+///
+/// `atomic_ringbuf!(NAME, Type, N, expr)` makes an [`AtomicRingbuf`] named
+/// `NAME`, containing entries of type `Type`, with room for `N` such entries,
+/// all of which are initialized to `expr`. As with [`ringbuf!`], the name
+/// defaults to `__RINGBUF`.
+#[cfg(not(feature = "disabled"))]
+#[macro_export]
+macro_rules! atomic_ringbuf {
+    ($name:ident, $t:ty, $n:expr, $init:expr) => {
+        #[used]
+        static $name: $crate::AtomicRingbuf<$t, $n> = {
+            const EMPTY: $crate::AtomicRingbufEntry<$t> =
+                $crate::AtomicRingbufEntry::new($init);
+            $crate::AtomicRingbuf::new([EMPTY; $n])
+        };
+    };
+    ($t:ty, $n:expr, $init:expr) => {
+        $crate::atomic_ringbuf!(__RINGBUF, $t, $n, $init);
+    };
+}
+
 /// Declares a ringbuffer in the current module or context.
 ///
 /// `ringbuf!(NAME, Type, N, expr)` makes a ringbuffer named `NAME`,
@@ -276,9 +378,49 @@ macro_rules! ringbuf {
 ///
 /// To support the common case of having one quickly-installed ringbuffer per
 /// module, if you omit the name, it will default to `__RINGBUF`.
+///
+/// Ending the arguments with `timestamp = clock`, where `clock` is a
+/// `fn() -> u64`, records the time of each entry; the type of `name` is then
+/// `StaticCell<TimestampedRingbuf<T, N>>`. See [the crate-level
+/// documentation](crate#timestamps).
 #[cfg(not(feature = "disabled"))]
 #[macro_export]
 macro_rules! ringbuf {
+    ($name:ident, $t:ty, $n:expr, $init:expr, timestamp = $clock:expr) => {
+        #[used]
+        static $name: $crate::StaticCell<
+            $crate::TimestampedRingbuf<$t, u16, $n>,
+        > = $crate::StaticCell::new($crate::TimestampedRingbuf {
+            last: None,
+            clock: $clock,
+            buffer: [$crate::TimestampedEntry {
+                line: 0,
+                generation: 0,
+                timestamp: 0,
+                count: 0,
+                payload: $init,
+            }; $n],
+        });
+    };
+    (
+        $name:ident, $t:ty, $n:expr, $init:expr, no_dedup,
+        timestamp = $clock:expr
+    ) => {
+        #[used]
+        static $name: $crate::StaticCell<
+            $crate::TimestampedRingbuf<$t, (), $n>,
+        > = $crate::StaticCell::new($crate::TimestampedRingbuf {
+            last: None,
+            clock: $clock,
+            buffer: [$crate::TimestampedEntry {
+                line: 0,
+                generation: 0,
+                timestamp: 0,
+                count: (),
+                payload: $init,
+            }; $n],
+        });
+    };
     ($name:ident, $t:ty, $n:expr, $init:expr) => {
         #[used]
         static $name: $crate::StaticCell<$crate::Ringbuf<$t, u16, $n>> =
@@ -294,7 +436,7 @@ macro_rules! ringbuf {
     };
     ($name:ident, $t:ty, $n:expr, $init:expr, no_dedup) => {
         #[used]
-        static $name: $crate::StaticCell<$crate::Ringbuf<$t, () $n>> =
+        static $name: $crate::StaticCell<$crate::Ringbuf<$t, (), $n>> =
             $crate::StaticCell::new($crate::Ringbuf {
                 last: None,
                 buffer: [$crate::RingbufEntry {
@@ -305,6 +447,19 @@ macro_rules! ringbuf {
                 }; $n],
             });
     };
+    ($t:ty, $n:expr, $init:expr, no_dedup, timestamp = $clock:expr) => {
+        $crate::ringbuf!(
+            __RINGBUF,
+            $t,
+            $n,
+            $init,
+            no_dedup,
+            timestamp = $clock
+        );
+    };
+    ($t:ty, $n:expr, $init:expr, timestamp = $clock:expr) => {
+        $crate::ringbuf!(__RINGBUF, $t, $n, $init, timestamp = $clock);
+    };
     ($t:ty, $n:expr, $init:expr, no_dedup) => {
         $crate::ringbuf!(__RINGBUF, $t, $n, $init, no_dedup);
     };
@@ -514,6 +669,104 @@ pub struct Ringbuf<T: Copy, C, const N: usize> {
     pub buffer: [RingbufEntry<T, C>; N],
 }
 
+///
+/// A [`RingbufEntry`] that also records when it was recorded.
+///
+#[derive(Debug, Copy, Clone)]
+pub struct TimestampedEntry<T: Copy, C> {
+    pub line: u16,
+    pub generation: u16,
+    /// The time of the entry's first occurrence, from the ring buffer's
+    /// clock.
+    pub timestamp: u64,
+    pub payload: T,
+    pub count: C,
+}
+
+///
+/// A ring buffer whose entries record when they were recorded, from `clock`.
+/// As with [`Ringbuf`], see the [`ringbuf!`] macro rather than instantiating
+/// this directly.
+///
+#[derive(Debug)]
+pub struct TimestampedRingbuf<T: Copy, C, const N: usize> {
+    pub last: Option<usize>,
+    pub clock: fn() -> u64,
+    pub buffer: [TimestampedEntry<T, C>; N],
+}
+
+///
+/// A slot in an [`AtomicRingbuf`].
+///
+#[derive(Debug)]
+pub struct AtomicRingbufEntry<T: Copy> {
+    /// Set while a recording is writing `entry`.
+    pub busy: AtomicBool,
+    pub entry: UnsafeCell<RingbufEntry<T, ()>>,
+}
+
+impl<T: Copy> AtomicRingbufEntry<T> {
+    pub const fn new(payload: T) -> Self {
+        Self {
+            busy: AtomicBool::new(false),
+            entry: UnsafeCell::new(RingbufEntry {
+                line: 0,
+                generation: 0,
+                payload,
+                count: (),
+            }),
+        }
+    }
+}
+
+///
+/// A ring buffer that can be recorded into from more than one context at
+/// once. Again, see the [`atomic_ringbuf!`] macro rather than instantiating
+/// this directly.
+///
+#[derive(Debug)]
+pub struct AtomicRingbuf<T: Copy, const N: usize> {
+    /// Index of the most recently claimed entry, or `u32::MAX` before the
+    /// first.
+    pub last: AtomicU32,
+    /// Number of entries dropped because their slot was still being written.
+    pub dropped: AtomicU32,
+    pub buffer: [AtomicRingbufEntry<T>; N],
+}
+
+// Safety: each entry is only written by the recording that holds its `busy`
+// flag, and only read by debuggers, from outside the task.
+unsafe impl<T: Copy + Send, const N: usize> Sync for AtomicRingbuf<T, N> {}
+
+impl<T: Copy, const N: usize> AtomicRingbuf<T, N> {
+    pub const fn new(buffer: [AtomicRingbufEntry<T>; N]) -> Self {
+        Self {
+            last: AtomicU32::new(u32::MAX),
+            dropped: AtomicU32::new(0),
+            buffer,
+        }
+    }
+
+    /// Claims the slot after the most recently claimed one.
+    fn claim(&self) -> usize {
+        let mut last = self.last.load(Ordering::Relaxed);
+        loop {
+            // `u32::MAX` is out of range, so the first claim gets slot 0.
+            let ndx = next_index(last as usize, N);
+            match AtomicU32Ext::compare_exchange(
+                &self.last,
+                last,
+                ndx as u32,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return ndx,
+                Err(actual) => last = actual,
+            }
+        }
+    }
+}
+
 ///
 /// A ring buffer of parametrized type and size, plus counters tracking the
 /// total number of times each entry variant has been recorded.
@@ -632,6 +885,65 @@ impl<T: Copy, const N: usize> RecordEntry<T>
     }
 }
 
+impl<T: Copy + PartialEq, const N: usize> RecordEntry<T>
+    for StaticCell<TimestampedRingbuf<T, u16, { N }>>
+{
+    fn record_entry(&self, line: u16, payload: T) {
+        // See the `Ringbuf` implementation for why these are the way they
+        // are.
+        let Some(mut ring) = self.try_borrow_mut() else {
+            return;
+        };
+        let last = ring.last.unwrap_or(usize::MAX);
+
+        if let Some(ent) = ring.buffer.get_mut(last) {
+            if ent.line == line && ent.payload == payload {
+                if let Some(new_count) = ent.count.checked_add(1) {
+                    ent.count = new_count;
+                    return;
+                }
+            }
+        }
+
+        ring.do_record(last, line, 1, payload);
+    }
+}
+
+impl<T: Copy, const N: usize> RecordEntry<T>
+    for StaticCell<TimestampedRingbuf<T, (), { N }>>
+{
+    fn record_entry(&self, line: u16, payload: T) {
+        let Some(mut ring) = self.try_borrow_mut() else {
+            return;
+        };
+        let last = ring.last.unwrap_or(usize::MAX);
+        ring.do_record(last, line, (), payload);
+    }
+}
+
+impl<T: Copy, const N: usize> RecordEntry<T> for AtomicRingbuf<T, { N }> {
+    fn record_entry(&self, line: u16, payload: T) {
+        let Some(slot) = self.buffer.get(self.claim()) else {
+            return;
+        };
+        if AtomicBoolExt::swap(&slot.busy, true, Ordering::Acquire) {
+            // We've come all the way around the ring to a recording that
+            // we've interrupted. Leave it be.
+            AtomicU32Ext::fetch_add(&self.dropped, 1, Ordering::Relaxed);
+            return;
+        }
+        // Safety: holding `busy` means nothing else is touching the entry.
+        let ent = unsafe { &mut *slot.entry.get() };
+        *ent = RingbufEntry {
+            line,
+            payload,
+            count: (),
+            generation: ent.generation.wrapping_add(1),
+        };
+        slot.busy.store(false, Ordering::Release);
+    }
+}
+
 #[cfg(feature = "counters")]
 impl<T, C, const N: usize> RecordEntry<T> for CountedRingbuf<T, C, { N }>
 where
@@ -653,25 +965,28 @@ where
     fn record_entry(&self, _: u16, _: T) {}
 }
 
+/// Returns the index after `last` in a buffer of `len` entries.
+fn next_index(last: usize, len: usize) -> usize {
+    // Either we were unable to reuse the entry, or the last index was out of
+    // range (perhaps because this is the first insertion). We're going to
+    // advance last and wrap if required. This uses a wrapping_add because if
+    // last is usize::MAX already, we want it to wrap to zero regardless -- and
+    // this avoids a checked arithmetic panic on the +1.
+    let last_plus_1 = last.wrapping_add(1);
+    // You're probably wondering why this isn't a remainder operation. This is
+    // because none of our target platforms currently have hardware modulus,
+    // and many of them don't even have hardware divide, making remainder quite
+    // expensive.
+    if last_plus_1 >= len {
+        0
+    } else {
+        last_plus_1
+    }
+}
+
 impl<T: Copy, C, const N: usize> Ringbuf<T, C, N> {
     fn do_record(&mut self, last: usize, line: u16, count: C, payload: T) {
-        // Either we were unable to reuse the entry, or the last index was out
-        // of range (perhaps because this is the first insertion). We're going
-        // to advance last and wrap if required. This uses a wrapping_add
-        // because if last is usize::MAX already, we want it to wrap to zero
-        // regardless -- and this avoids a checked arithmetic panic on the +1.
-        let ndx = {
-            let last_plus_1 = last.wrapping_add(1);
-            // You're probably wondering why this isn't a remainder operation.
-            // This is because none of our target platforms currently have
-            // hardware modulus, and many of them don't even have hardware
-            // divide, making remainder quite expensive.
-            if last_plus_1 >= self.buffer.len() {
-                0
-            } else {
-                last_plus_1
-            }
-        };
+        let ndx = next_index(last, self.buffer.len());
         let ent = unsafe {
             // Safety: the code above guarantees that `ndx` is within the length
             // of the buffer --- we checked whether it's greater than or equal
@@ -694,3 +1009,24 @@ impl<T: Copy, C, const N: usize> Ringbuf<T, C, N> {
         self.last = Some(ndx);
     }
 }
+
+impl<T: Copy, C, const N: usize> TimestampedRingbuf<T, C, N> {
+    fn do_record(&mut self, last: usize, line: u16, count: C, payload: T) {
+        let ndx = next_index(last, self.buffer.len());
+        let timestamp = (self.clock)();
+        let ent = unsafe {
+            // Safety: `next_index` only returns indices within the buffer; see
+            // `Ringbuf::do_record`.
+            self.buffer.get_unchecked_mut(ndx)
+        };
+        *ent = TimestampedEntry {
+            line,
+            timestamp,
+            payload,
+            count,
+            generation: ent.generation.wrapping_add(1),
+        };
+
+        self.last = Some(ndx);
+    }
+}
@@ -0,0 +1,189 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Continuous health tests for a hardware entropy source, after NIST SP
+//! 800-90B section 4.4.
+//!
+//! Both tests look at the source's output a byte at a time, and catch it
+//! failing in ways that leave it much less random than it should be -- stuck
+//! on a value, or favouring one -- rather than proving that it's good. They're
+//! tuned for a source claimed to give at least [`MIN_ENTROPY`] bits of
+//! min-entropy a byte, with a false alarm rate of 2^-40 per sample: that's
+//! well below what a conditioned hardware RNG gives, so a working one should
+//! never trip them.
+
+#![no_std]
+
+/// Bits of min-entropy per byte that the cutoffs assume.
+pub const MIN_ENTROPY: u32 = 4;
+
+/// Repetition count cutoff: 1 + ceil(40 / H).
+pub const REPETITION_CUTOFF: u32 = 1 + 40_u32.div_ceil(MIN_ENTROPY);
+
+/// Number of samples in each adaptive proportion window, as SP 800-90B
+/// recommends for non-binary samples.
+pub const PROPORTION_WINDOW: u32 = 512;
+
+/// Adaptive proportion cutoff: 1 + CRITBINOM(512, 2^-4, 1 - 2^-40).
+pub const PROPORTION_CUTOFF: u32 = 78;
+
+/// Which test a sample failed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Failure {
+    /// The same byte came up `REPETITION_CUTOFF` times in a row.
+    RepetitionCount,
+    /// The first byte of a window came up `PROPORTION_CUTOFF` times in it.
+    AdaptiveProportion,
+}
+
+/// The state of both tests.
+pub struct HealthTests {
+    last: u8,
+    repeats: u32,
+    /// The byte the current window is counting, and how many times it's
+    /// come up, and how many samples are in the window. A window of 0 means
+    /// the next sample starts a new one.
+    window_byte: u8,
+    window_hits: u32,
+    window_len: u32,
+}
+
+impl Default for HealthTests {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HealthTests {
+    pub const fn new() -> Self {
+        Self {
+            last: 0,
+            repeats: 0,
+            window_byte: 0,
+            window_hits: 0,
+            window_len: 0,
+        }
+    }
+
+    /// Forgets everything seen so far, as after the source has been reset.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Runs both tests on the next byte from the source.
+    ///
+    /// After a failure, the tests keep going from where they were; it's up to
+    /// the caller to throw away the output and, most likely, `reset` along
+    /// with the source.
+    pub fn sample(&mut self, b: u8) -> Result<(), Failure> {
+        let mut result = Ok(());
+
+        if self.repeats != 0 && b == self.last {
+            self.repeats += 1;
+            if self.repeats >= REPETITION_CUTOFF {
+                result = Err(Failure::RepetitionCount);
+            }
+        } else {
+            self.last = b;
+            self.repeats = 1;
+        }
+
+        if self.window_len == 0 {
+            self.window_byte = b;
+            self.window_hits = 1;
+        } else if b == self.window_byte {
+            self.window_hits += 1;
+            if self.window_hits >= PROPORTION_CUTOFF {
+                result = result.and(Err(Failure::AdaptiveProportion));
+            }
+        }
+        self.window_len += 1;
+        if self.window_len == PROPORTION_WINDOW {
+            self.window_len = 0;
+        }
+
+        result
+    }
+
+    /// Runs both tests on each byte of `bytes`, stopping at the first failure.
+    pub fn check(&mut self, bytes: &[u8]) -> Result<(), Failure> {
+        bytes.iter().try_for_each(|&b| self.sample(b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cheap stand-in for a working source: an LCG's top byte.
+    fn good_bytes(n: usize) -> impl Iterator<Item = u8> {
+        let mut x = 0x1234_5678_u32;
+        (0..n).map(move |_| {
+            x = x.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (x >> 24) as u8
+        })
+    }
+
+    #[test]
+    fn good_source_passes() {
+        let mut h = HealthTests::new();
+        for b in good_bytes(100_000) {
+            assert_eq!(h.sample(b), Ok(()));
+        }
+    }
+
+    #[test]
+    fn stuck_source_fails_repetition() {
+        let mut h = HealthTests::new();
+        for _ in 1..REPETITION_CUTOFF {
+            assert_eq!(h.sample(0xA5), Ok(()));
+        }
+        assert_eq!(h.sample(0xA5), Err(Failure::RepetitionCount));
+    }
+
+    #[test]
+    fn biased_source_fails_proportion() {
+        // Alternate with other bytes, so that the repetition count never
+        // trips, until the first byte of the window has come up too often.
+        let mut h = HealthTests::new();
+        let mut failed = None;
+        for i in 0..PROPORTION_WINDOW {
+            let b = if i % 2 == 0 { 0x42 } else { i as u8 | 1 };
+            if let Err(f) = h.sample(b) {
+                failed = Some((i, f));
+                break;
+            }
+        }
+        assert_eq!(
+            failed,
+            Some((2 * (PROPORTION_CUTOFF - 1), Failure::AdaptiveProportion))
+        );
+    }
+
+    #[test]
+    fn windows_restart() {
+        // Just under the cutoff in each of several windows is fine.
+        let mut h = HealthTests::new();
+        for _ in 0..4 {
+            for i in 0..PROPORTION_WINDOW {
+                let b = if i < 2 * (PROPORTION_CUTOFF - 1) && i % 2 == 0 {
+                    0x42
+                } else {
+                    i as u8 | 1
+                };
+                assert_eq!(h.sample(b), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn reset_forgets() {
+        let mut h = HealthTests::new();
+        for _ in 1..REPETITION_CUTOFF {
+            h.sample(0).unwrap();
+        }
+        h.reset();
+        assert_eq!(h.sample(0), Ok(()));
+    }
+}
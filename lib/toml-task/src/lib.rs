@@ -21,6 +21,40 @@ pub struct Task<T = ordered_toml::Value> {
     pub stacksize: Option<u32>,
     #[serde(default)]
     pub start: bool,
+    /// Number of messages sent with `SEND_ASYNC` that can be queued for this
+    /// task. Zero (the default) means the task can't receive them.
+    #[serde(default)]
+    pub mailbox: u8,
+    /// Whether this task uses the floating-point unit. Only tasks that set this
+    /// get an FPU context set up for them; see `TaskFlags::USES_FPU` in the
+    /// kernel.
+    #[serde(default)]
+    pub uses_fpu: bool,
+    /// Whether this task may use more memory regions than the MPU has slots
+    /// for. Regions that don't fit are loaded into the MPU on demand when the
+    /// task touches them; see `TaskDesc::extra_regions` in the kernel.
+    #[serde(default)]
+    pub virtual_regions: bool,
+    /// Whether this task may read every task's state, as the supervisor can,
+    /// to build a health or telemetry view; see `TaskFlags::MONITOR` in the
+    /// kernel.
+    #[serde(default)]
+    pub monitor: bool,
+    /// Whether this task may read the measurements the bootloader handed to
+    /// the kernel, as the supervisor can, to attest to them; see
+    /// `TaskFlags::ATTESTER` in the kernel.
+    #[serde(default)]
+    pub attester: bool,
+    /// Whether this task owns the clock tree, and so may tell the kernel when
+    /// the clock driving its timer changes; see `TaskFlags::CLOCK_OWNER` in
+    /// the kernel.
+    #[serde(default)]
+    pub clock_owner: bool,
+    /// A word for the kernel to fill the task's `.uninit` section with
+    /// whenever it starts, so that reads of memory the task never wrote stand
+    /// out. By default, `.uninit` keeps whatever was there.
+    #[serde(default)]
+    pub uninit_fill: Option<u32>,
 
     #[serde(default)]
     pub uses: Vec<String>,
@@ -35,6 +69,12 @@ pub struct Task<T = ordered_toml::Value> {
     #[serde(default)]
     pub extern_regions: Vec<String>,
 
+    /// Names of this task's own memory regions (other than `ram`) whose
+    /// contents survive when the task restarts. Put data there with the
+    /// `sections` table; see `RegionAttributes::STICKY` in the kernel.
+    #[serde(default)]
+    pub sticky_regions: Vec<String>,
+
     // Order matters here:
     // TOML serialization doesn't allow us to put a value type after any Table
     // type, so we put all of our `IndexMap` (and `config`, which often contains
@@ -66,10 +106,10 @@ impl<T> Task<T> {
     pub fn notification_bit(&self, name: &str) -> Result<u8> {
         match self.notifications.iter().position(|n| n == name) {
             Some(i) => {
-                if i < 32 {
+                if i < 64 {
                     Ok(i.try_into().unwrap())
                 } else {
-                    bail!("too many IRQs; {i} cannot fit in a `u32`")
+                    bail!("too many notifications; {i} cannot fit in a `u64`")
                 }
             }
             None => bail!(
@@ -79,8 +119,8 @@ impl<T> Task<T> {
             ),
         }
     }
-    pub fn notification_mask(&self, name: &str) -> Result<u32> {
-        Ok(1u32 << self.notification_bit(name)?)
+    pub fn notification_mask(&self, name: &str) -> Result<u64> {
+        Ok(1u64 << self.notification_bit(name)?)
     }
 }
 
@@ -87,6 +87,12 @@ impl From<u8> for Generation {
     }
 }
 
+impl From<Generation> for u8 {
+    fn from(x: Generation) -> Self {
+        x.0
+    }
+}
+
 /// Newtype wrapper for an interrupt index
 #[derive(
     Copy,
@@ -136,13 +142,17 @@ pub struct InterruptOwner {
     /// Which task to notify, by index.
     pub task: u32,
     /// Which notification bits to set.
-    pub notification: u32,
+    pub notification: u64,
 }
 impl phash::PerfectHash for InterruptOwner {
     fn phash(&self, v: u32) -> usize {
+        // Fold the notification bits down to 32, so that owners using only the
+        // low bits hash as they did when notifications were 32 bits wide.
+        let notification =
+            self.notification as u32 ^ (self.notification >> 32) as u32;
         self.task
             .wrapping_mul(v)
-            .wrapping_add(self.notification.wrapping_mul(!v)) as usize
+            .wrapping_add(notification.wrapping_mul(!v)) as usize
     }
 }
 impl InterruptOwner {
@@ -193,9 +203,126 @@ bitflags::bitflags! {
         const READ = 1 << 0;
         /// Allow the borrower to write this memory.
         const WRITE = 1 << 1;
+        /// The lease's base and length describe a table of `ULeaseSegment`s,
+        /// rather than the leased memory itself. The borrower sees the
+        /// segments concatenated in order. The table must be aligned, and its
+        /// length a whole number of entries.
+        const SEGMENTED = 1 << 2;
+    }
+}
+
+/// One piece of a scatter-gather lease, found in the table referenced by a
+/// `ULease` with the `SEGMENTED` attribute.
+#[derive(Copy, Clone, Debug, FromBytes)]
+#[repr(C)]
+pub struct ULeaseSegment {
+    /// Base address of this segment of leased memory.
+    pub base_address: u32,
+    /// Length of this segment, in bytes.
+    pub length: u32,
+}
+
+/// Maximum number of segments the kernel will accept in a `SEGMENTED` lease.
+pub const MAX_LEASE_SEGMENTS: usize = 8;
+
+/// One transfer in a `BORROW_VECTORED` syscall: `length` bytes starting at
+/// `offset` within the lease, to or from the borrower's memory at
+/// `base_address`.
+#[derive(Copy, Clone, Debug, FromBytes)]
+#[repr(C)]
+pub struct UBorrow {
+    /// Offset into the lease, in bytes.
+    pub offset: u32,
+    /// Base address of the borrower's local buffer.
+    pub base_address: u32,
+    /// Length of the borrower's local buffer, in bytes.
+    pub length: u32,
+}
+
+/// Maximum number of transfers the kernel will accept in a single
+/// `BORROW_VECTORED` syscall. This bounds the time spent in the kernel.
+pub const MAX_BORROW_VECTORS: usize = 16;
+
+/// Version of the kernel response code registry below. This is bumped
+/// whenever a code is added, so that tools can tell whether they know every
+/// code a given kernel might produce. Codes are never renumbered or reused.
+pub const KERNEL_CODE_REGISTRY_VERSION: u32 = 1;
+
+/// Response codes that the kernel, rather than a server, puts in a task's
+/// response register.
+///
+/// This is the registry of every such code. The kernel only produces each one
+/// from particular syscalls, listed here, and servers' own error codes share
+/// the same space; a code's meaning depends on the syscall it came from.
+/// Servers must not use the reserved codes, at or above `FIRST_RESERVED_CODE`.
+/// These include the dead codes, which any IPC to a peer can produce, and
+/// codes like `IpcTimeout` that a client must be able to tell apart from
+/// anything the server says.
+///
+/// To add a code, give it the next unused value, add it to the conversions
+/// below, and bump `KERNEL_CODE_REGISTRY_VERSION`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum KernelCode {
+    /// A lender has defected: its lease table or leased memory couldn't be
+    /// used. Produced by `BORROW_READ`, `BORROW_WRITE`, `BORROW_INFO`, and
+    /// `BORROW_VECTORED`.
+    Defect,
+    /// The recipient's mailbox is full. Produced by `SEND_ASYNC`.
+    MailboxFull,
+    /// The timeout expired before the callee replied. Produced by `SEND`,
+    /// from the reserved range.
+    IpcTimeout,
+    /// The peer died or was restarted, and now has the given generation.
+    /// Produced by any IPC that names a peer.
+    Dead(Generation),
+}
+
+impl KernelCode {
+    /// Returns the numeric code.
+    pub const fn code(self) -> u32 {
+        match self {
+            Self::Defect => 1,
+            Self::MailboxFull => 2,
+            Self::IpcTimeout => FIRST_RESERVED_CODE,
+            Self::Dead(g) => FIRST_DEAD_CODE | g.0 as u32,
+        }
+    }
+
+    /// Interprets a numeric code, if it's one the kernel produces. As noted on
+    /// `KernelCode`, this can't tell a kernel code from a server's code with
+    /// the same value; only the syscall it came from can.
+    pub const fn from_code(code: u32) -> Option<Self> {
+        match code {
+            1 => Some(Self::Defect),
+            2 => Some(Self::MailboxFull),
+            FIRST_RESERVED_CODE => Some(Self::IpcTimeout),
+            _ => match extract_new_generation(code) {
+                Some(g) => Some(Self::Dead(g)),
+                None => None,
+            },
+        }
     }
 }
 
+impl From<KernelCode> for u32 {
+    fn from(c: KernelCode) -> Self {
+        c.code()
+    }
+}
+
+impl core::convert::TryFrom<u32> for KernelCode {
+    type Error = u32;
+
+    fn try_from(code: u32) -> Result<Self, Self::Error> {
+        Self::from_code(code).ok_or(code)
+    }
+}
+
+/// Lowest of the codes reserved for the kernel; see `KernelCode`. The dead
+/// codes are at the top of this range.
+pub const FIRST_RESERVED_CODE: u32 = 0xffff_fe00;
+
+/// Lowest of the dead codes; see `KernelCode::Dead`.
 pub const FIRST_DEAD_CODE: u32 = 0xffff_ff00;
 
 /// Response code returned by the kernel if the peer died or was restarted.
@@ -203,7 +330,7 @@ pub const FIRST_DEAD_CODE: u32 = 0xffff_ff00;
 /// This always has the top 24 bits set to 1, with the `generation` in the
 /// bottom 8 bits.
 pub const fn dead_response_code(new_generation: Generation) -> u32 {
-    FIRST_DEAD_CODE | new_generation.0 as u32
+    KernelCode::Dead(new_generation).code()
 }
 
 /// Utility for checking whether a code indicates that the peer was restarted
@@ -217,7 +344,37 @@ pub const fn extract_new_generation(code: u32) -> Option<Generation> {
 }
 
 /// Response code returned by the kernel if a lender has defected.
-pub const DEFECT: u32 = 1;
+pub const DEFECT: u32 = KernelCode::Defect.code();
+
+/// Response code returned by `SEND_ASYNC` if the recipient's mailbox is full.
+pub const MAILBOX_FULL: u32 = KernelCode::MailboxFull.code();
+
+/// Response code returned by `SEND` if its timeout expired before the callee
+/// replied.
+pub const IPC_TIMEOUT: u32 = KernelCode::IpcTimeout.code();
+
+/// Maximum size of a message sent with `SEND_ASYNC`, in bytes.
+pub const ASYNC_MESSAGE_SIZE: usize = 16;
+
+/// Bit set in the operation code reported by `RECV` when the message came from
+/// a task's mailbox (i.e. was sent with `SEND_ASYNC`). The sender is not
+/// waiting for a reply, and must not be replied to.
+pub const ASYNC_OPERATION_FLAG: u32 = 1 << 31;
+
+/// Bit set in the lease count passed to `SEND` to mark the message urgent. An
+/// urgent sender is received ahead of non-urgent senders of the same priority.
+pub const SEND_URGENT_FLAG: u32 = 1 << 31;
+
+/// Position of the timeout field in the lease count passed to `SEND`. The field
+/// holds the number of ticks the caller is willing to wait for a reply, or 0 to
+/// wait indefinitely.
+pub const SEND_TIMEOUT_SHIFT: u32 = 16;
+
+/// Largest timeout that can be passed to `SEND`, in ticks.
+pub const SEND_TIMEOUT_MAX: u32 = 0x7FFF;
+
+/// Bits of the argument passed to `SEND` that hold the number of leases.
+pub const SEND_LEASE_COUNT_MASK: u32 = 0xFFFF;
 
 /// State used to make scheduling decisions.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
@@ -334,8 +491,65 @@ pub enum FaultInfo {
     Panic,
     /// A fault has been injected into this task by another task
     Injected(TaskId),
-    /// A fault has been delivered by a server task.
-    FromServer(TaskId, ReplyFaultReason),
+    /// A fault has been delivered by a server task, with the reason it gave,
+    /// and any detail it attached.
+    FromServer(TaskId, ReplyFaultReason, Option<ReplyFaultDetail>),
+    /// A memory protection or bus fault taken by task code, with enough detail
+    /// to tell (say) a stack overflow from a wild pointer. On architectures
+    /// that can provide it, this is used instead of `MemoryAccess` and
+    /// `BusError` for faults with `source` `User`.
+    MemoryFault(MemoryFaultDetail),
+}
+
+/// Details of a memory fault taken by a task; see `FaultInfo::MemoryFault`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct MemoryFaultDetail {
+    /// Which part of the system rejected the access.
+    pub kind: MemoryFaultKind,
+    /// Address the task accessed, if the processor reported it.
+    pub address: Option<u32>,
+    /// What the task was trying to do.
+    pub access: MemoryAccessKind,
+    /// Address of the faulting instruction, if it could be recovered from the
+    /// task's exception frame. (It can't if the fault happened while stacking
+    /// the frame.)
+    pub pc: Option<u32>,
+    /// The region in the task's memory map nearest to `address`, if any.
+    pub nearest_region: Option<NearestRegion>,
+}
+
+/// Source of a `MemoryFaultDetail`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub enum MemoryFaultKind {
+    /// The memory protection unit rejected the access.
+    Protection,
+    /// The bus rejected the access, e.g. because nothing is mapped there.
+    Bus,
+}
+
+/// The kind of memory access that caused a `MemoryFaultDetail`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub enum MemoryAccessKind {
+    /// A data read, including unstacking an exception frame.
+    Read,
+    /// A data write, including stacking an exception frame.
+    Write,
+    /// An instruction fetch.
+    Execute,
+    /// A data access whose direction the processor doesn't report.
+    Data,
+}
+
+/// Describes the task memory region closest to a faulting address.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct NearestRegion {
+    /// Base address of the region.
+    pub base: u32,
+    /// Size of the region, in bytes.
+    pub size: u32,
+    /// Whether this region holds the task's stack. A fault just below the
+    /// stack region is almost certainly a stack overflow.
+    pub stack: bool,
 }
 
 /// We're using an explicit `TryFrom` impl for `Sysnum` instead of
@@ -357,6 +571,12 @@ impl core::convert::TryFrom<u32> for ReplyFaultReason {
     }
 }
 
+impl From<ReplyFaultReason> for u32 {
+    fn from(r: ReplyFaultReason) -> Self {
+        r as u32
+    }
+}
+
 impl From<UsageError> for FaultInfo {
     fn from(e: UsageError) -> Self {
         Self::SyscallUsage(e)
@@ -364,28 +584,82 @@ impl From<UsageError> for FaultInfo {
 }
 
 /// A kernel-defined fault, arising from how a user task behaved.
+///
+/// Each has a stable number, for tools that record faults numerically. As
+/// with `KernelCode`, new variants take the next unused number, and numbers
+/// are never reused.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub enum UsageError {
     /// A program used an undefined syscall number.
-    BadSyscallNumber,
+    BadSyscallNumber = 0,
     /// A program specified a slice as a syscall argument, but the slice is
     /// patently invalid: it is either unaligned for its type, or it is
     /// expressed such that it would wrap around the end of the address space.
     /// Neither of these conditions is ever legal, so this represents a
     /// malfunction in the caller.
-    InvalidSlice,
+    InvalidSlice = 1,
     /// A program named a task ID that will never be valid, as it's out of
     /// range.
-    TaskOutOfRange,
+    TaskOutOfRange = 2,
     /// A program named a valid task ID, but attempted to perform an operation
     /// on it that is illegal or otherwise forbidden.
-    IllegalTask,
-    LeaseOutOfRange,
-    OffsetOutOfRange,
-    NoIrq,
-    BadKernelMessage,
-    BadReplyFaultReason,
-    NotSupervisor,
+    IllegalTask = 3,
+    LeaseOutOfRange = 4,
+    OffsetOutOfRange = 5,
+    NoIrq = 6,
+    BadKernelMessage = 7,
+    BadReplyFaultReason = 8,
+    NotSupervisor = 9,
+    /// A program lent a `SEGMENTED` lease whose segment table is too long, or
+    /// contains a segment that is patently invalid.
+    BadLeaseSegments = 10,
+    /// A program passed a `BORROW_VECTORED` transfer table that is too long.
+    TooManyBorrows = 11,
+    /// A program that is neither the supervisor nor marked as a monitor in
+    /// the `app.toml` used a kipc reserved for monitors.
+    NotMonitor = 12,
+    /// A program replied, citing a reply nonce, to a client that is waiting
+    /// for it to reply to a different message -- say, because it replied to
+    /// the wrong client, or replied twice to the same one.
+    WrongReplyNonce = 13,
+    /// A program that is neither the supervisor nor marked as an attester in
+    /// the `app.toml` asked for the boot measurements.
+    NotAttester = 14,
+    /// A program that is neither the supervisor nor marked as the clock owner
+    /// in the `app.toml` tried to change the kernel's tick divisor.
+    NotClockOwner = 15,
+}
+
+impl From<UsageError> for u32 {
+    fn from(e: UsageError) -> Self {
+        e as u32
+    }
+}
+
+impl core::convert::TryFrom<u32> for UsageError {
+    type Error = ();
+
+    fn try_from(x: u32) -> Result<Self, Self::Error> {
+        match x {
+            0 => Ok(Self::BadSyscallNumber),
+            1 => Ok(Self::InvalidSlice),
+            2 => Ok(Self::TaskOutOfRange),
+            3 => Ok(Self::IllegalTask),
+            4 => Ok(Self::LeaseOutOfRange),
+            5 => Ok(Self::OffsetOutOfRange),
+            6 => Ok(Self::NoIrq),
+            7 => Ok(Self::BadKernelMessage),
+            8 => Ok(Self::BadReplyFaultReason),
+            9 => Ok(Self::NotSupervisor),
+            10 => Ok(Self::BadLeaseSegments),
+            11 => Ok(Self::TooManyBorrows),
+            12 => Ok(Self::NotMonitor),
+            13 => Ok(Self::WrongReplyNonce),
+            14 => Ok(Self::NotAttester),
+            15 => Ok(Self::NotClockOwner),
+            _ => Err(()),
+        }
+    }
 }
 
 /// Origin of a fault.
@@ -427,6 +701,82 @@ pub enum ReplyFaultReason {
     AccessViolation = 5,
 }
 
+/// Detail a server can attach to a `REPLY_FAULT`, to help whoever debugs the
+/// faulted client work out what it did wrong. The meaning of both fields is up
+/// to the server's protocol.
+#[derive(
+    Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize,
+)]
+pub struct ReplyFaultDetail {
+    /// Which part of the message the server objected to -- say, the index of
+    /// an argument or lease.
+    pub argument: u32,
+    /// Further detail, such as the offending offset or length.
+    pub value: u32,
+}
+
+/// Value of `PanicRecord::magic` in a valid record.
+pub const PANIC_RECORD_MAGIC: u32 = 0x9A41_C5EC;
+
+/// Number of bytes of source file path kept in a `PanicRecord`.
+pub const PANIC_FILE_LEN: usize = 32;
+
+/// Number of bytes of message kept in a `PanicRecord`.
+pub const PANIC_MESSAGE_LEN: usize = 64;
+
+/// Where and why a task panicked, as recorded by userlib's panic handler.
+///
+/// The handler fills one of these in, in the task's RAM, and passes it to the
+/// `PANIC` syscall as the panic message. The supervisor can fetch it with
+/// `read_panic_record`, and a debugger can find it at `HUBRIS_PANIC_RECORD` in
+/// the task's image.
+///
+/// Both strings are UTF-8 but may be truncated, even mid-character. The file
+/// path keeps its end, which is the more telling part, and the message keeps
+/// its start. The message is only recorded if the panic's message is a plain
+/// string literal, since formatting arguments needs machinery that most tasks
+/// don't otherwise carry; its length is 0 if not.
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct PanicRecord {
+    /// `PANIC_RECORD_MAGIC`.
+    pub magic: u32,
+    pub line: u32,
+    pub column: u32,
+    /// Number of valid bytes in `file`.
+    pub file_len: u8,
+    /// Number of valid bytes in `message`.
+    pub message_len: u8,
+    pub _pad: [u8; 2],
+    pub file: [u8; PANIC_FILE_LEN],
+    pub message: [u8; PANIC_MESSAGE_LEN],
+}
+
+impl PanicRecord {
+    pub const EMPTY: Self = Self {
+        magic: 0,
+        line: 0,
+        column: 0,
+        file_len: 0,
+        message_len: 0,
+        _pad: [0; 2],
+        file: [0; PANIC_FILE_LEN],
+        message: [0; PANIC_MESSAGE_LEN],
+    };
+
+    /// Returns the recorded (tail of the) source file path.
+    pub fn file(&self) -> &[u8] {
+        let n = usize::from(self.file_len).min(PANIC_FILE_LEN);
+        &self.file[..n]
+    }
+
+    /// Returns the recorded (start of the) panic message.
+    pub fn message(&self) -> &[u8] {
+        let n = usize::from(self.message_len).min(PANIC_MESSAGE_LEN);
+        &self.message[..n]
+    }
+}
+
 /// Enumeration of syscall numbers.
 #[repr(u32)]
 pub enum Sysnum {
@@ -444,6 +794,10 @@ pub enum Sysnum {
     Post = 11,
     ReplyFault = 12,
     IrqStatus = 13,
+    SendAsync = 14,
+    ReplyLease = 15,
+    BorrowVectored = 16,
+    SetTimerPeriodic = 17,
 }
 
 /// We're using an explicit `TryFrom` impl for `Sysnum` instead of
@@ -468,6 +822,10 @@ impl core::convert::TryFrom<u32> for Sysnum {
             11 => Ok(Self::Post),
             12 => Ok(Self::ReplyFault),
             13 => Ok(Self::IrqStatus),
+            14 => Ok(Self::SendAsync),
+            15 => Ok(Self::ReplyLease),
+            16 => Ok(Self::BorrowVectored),
+            17 => Ok(Self::SetTimerPeriodic),
             _ => Err(()),
         }
     }
@@ -480,6 +838,168 @@ pub struct TaskDumpRegion {
     pub size: u32,
 }
 
+/// CPU time accounting for a task, as returned by the `read_cpu_time` kipc.
+///
+/// All values are measured in architecture-specific cycles since boot; on ARM
+/// M-profile these are CPU clock cycles.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CpuTime {
+    /// Cycles spent running the requested task. This is not reset if the task
+    /// is restarted.
+    pub task: u64,
+    /// Cycles spent in the kernel, on behalf of any task.
+    pub kernel: u64,
+    /// Total cycles elapsed, for computing utilization.
+    pub total: u64,
+}
+
+/// Number of syscalls a task has made, by kind, as returned by the
+/// `read_syscall_counts` kipc.
+///
+/// Like `CpuTime`, these count from boot and are not reset if the task is
+/// restarted. Each wraps on overflow.
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize,
+)]
+pub struct SyscallCounts {
+    /// `SEND` and `SEND_ASYNC`.
+    pub send: u32,
+    /// `RECV`.
+    pub recv: u32,
+    /// `REPLY`, `REPLY_FAULT`, and `REPLY_LEASE`.
+    pub reply: u32,
+    /// `SET_TIMER`, `SET_TIMER_PERIODIC`, and `GET_TIMER`.
+    pub timer: u32,
+    /// The `BORROW_*` family.
+    pub borrow: u32,
+    /// `IRQ_CONTROL` and `IRQ_STATUS`.
+    pub irq: u32,
+    /// `POST`.
+    pub post: u32,
+    /// Everything else, including bogus syscall numbers.
+    pub other: u32,
+}
+
+/// High-resolution monotonic time, as returned by the `read_monotonic_time`
+/// kipc.
+///
+/// This counts cycles of the kernel's timebase, which ticks much faster than
+/// the kernel tick: on ARM M-profile, it counts CPU clock cycles.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MonotonicTime {
+    /// Timebase cycles elapsed since the kernel started.
+    pub cycles: u64,
+    /// Timebase cycles per kernel tick.
+    pub cycles_per_tick: u32,
+    /// Kernel ticks per second (1000, unless the application says otherwise).
+    pub tick_hz: u32,
+}
+
+impl MonotonicTime {
+    /// Converts this time to microseconds since the kernel started.
+    pub fn as_micros(&self) -> u64 {
+        // The build system makes sure that a tick is a whole number of
+        // microseconds.
+        let us_per_tick = u64::from(1_000_000 / self.tick_hz.max(1));
+        let per_tick = u64::from(self.cycles_per_tick.max(1));
+        let ticks = self.cycles / per_tick;
+        let rem = self.cycles % per_tick;
+        ticks * us_per_tick + rem * us_per_tick / per_tick
+    }
+}
+
+/// A client that has been waiting on a server's reply for longer than the
+/// application's dead-man limit, as returned by the `find_stuck_reply` kipc.
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize,
+)]
+pub struct StuckReply {
+    /// Index of the waiting client.
+    pub client: u32,
+    /// Index of the server that received its message but hasn't replied.
+    pub server: u32,
+    /// Number of ticks the client has been waiting, saturating.
+    pub ticks: u32,
+}
+
+/// Coarse scheduling state of a task, as reported in a `TaskSnapshot`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TaskSnapshotState {
+    Stopped,
+    Runnable,
+    InSend,
+    InReply,
+    InRecv,
+    Faulted,
+}
+
+impl From<&TaskState> for TaskSnapshotState {
+    fn from(s: &TaskState) -> Self {
+        match s {
+            TaskState::Healthy(SchedState::Stopped) => Self::Stopped,
+            TaskState::Healthy(SchedState::Runnable) => Self::Runnable,
+            TaskState::Healthy(SchedState::InSend(_)) => Self::InSend,
+            TaskState::Healthy(SchedState::InReply(_)) => Self::InReply,
+            TaskState::Healthy(SchedState::InRecv(_)) => Self::InRecv,
+            TaskState::Faulted { .. } => Self::Faulted,
+        }
+    }
+}
+
+/// A compact summary of one task's state, as returned by the
+/// `read_task_snapshot` kipc.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TaskSnapshot {
+    pub state: TaskSnapshotState,
+    /// Generation number, which changes each time the task is restarted.
+    pub generation: u8,
+    /// Current priority, which may differ from the one in the `app.toml` if
+    /// the task has inherited a client's.
+    pub priority: u8,
+    /// Number of notification bits posted to the task that it hasn't yet
+    /// received.
+    pub pending_notifications: u8,
+    /// Deadline of the task's timer, if it's set.
+    pub timer_deadline: Option<u64>,
+    /// Whether the kernel's restart throttle is holding the task down, after
+    /// it faulted too often. Until this clears, attempts to restart the task
+    /// fail with `RESTART_HELD`.
+    pub restart_held: bool,
+}
+
+/// Size of a data cache line, which `CacheOp::Invalidate` requests must be
+/// aligned to. The Cortex-M7 is the only core we run on with an L1 data cache,
+/// and its lines are 32 bytes; other targets use the same size, so that what's
+/// accepted doesn't depend on the target.
+pub const DCACHE_LINE_SIZE: u32 = 32;
+
+/// Data cache maintenance operation, as requested by the `cache_maintenance`
+/// kipc.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CacheOp {
+    /// Write any dirty lines in the range back to memory, so that a DMA
+    /// engine reading it sees what the CPU wrote.
+    Clean,
+    /// Discard any lines in the range, so that the CPU sees what a DMA engine
+    /// wrote. Unwritten CPU stores to the range are lost.
+    Invalidate,
+    /// Write back, then discard, any lines in the range.
+    CleanInvalidate,
+}
+
+/// Most bytes of a task's memory that one `read_task_memory` or
+/// `write_task_memory` call moves. Anything past this in the caller's buffer
+/// is ignored.
+pub const TASK_MEMORY_WINDOW: usize = 256;
+
+/// Response code from `read_task_memory` and `write_task_memory` when the
+/// target task can't itself access the whole window as normal memory.
+pub const TASK_MEMORY_INACCESSIBLE: u32 = 1;
+
+/// Response code from `restart_task` when the kernel's restart throttle is
+/// holding the task down. The task is left as it was.
+pub const RESTART_HELD: u32 = 1;
+
 /// Representation of kipc numbers
 pub enum Kipcnum {
     ReadTaskStatus = 1,
@@ -491,6 +1011,27 @@ pub enum Kipcnum {
     ReadTaskDumpRegion = 7,
     SoftwareIrq = 8,
     FindFaultedTask = 9,
+    ReadCpuTime = 10,
+    BroadcastNotification = 11,
+    ReadTaskStackUsage = 12,
+    ReadMonotonicTime = 13,
+    SuspendTask = 14,
+    ResumeTask = 15,
+    ReadSyscallCounts = 16,
+    FindStuckReply = 17,
+    ReadRegisterHash = 18,
+    SetTickDivisor = 19,
+    CacheMaintenance = 20,
+    Idle = 21,
+    ReadTaskSnapshot = 22,
+    TransferIrq = 23,
+    TakeFiredIrqs = 24,
+    ReadPanicRecord = 25,
+    WatchTask = 26,
+    ReadBootMeasurements = 27,
+    ReadImageCheck = 28,
+    ReadTaskMemory = 29,
+    WriteTaskMemory = 30,
 }
 
 impl core::convert::TryFrom<u16> for Kipcnum {
@@ -507,6 +1048,27 @@ impl core::convert::TryFrom<u16> for Kipcnum {
             7 => Ok(Self::ReadTaskDumpRegion),
             8 => Ok(Self::SoftwareIrq),
             9 => Ok(Self::FindFaultedTask),
+            10 => Ok(Self::ReadCpuTime),
+            11 => Ok(Self::BroadcastNotification),
+            12 => Ok(Self::ReadTaskStackUsage),
+            13 => Ok(Self::ReadMonotonicTime),
+            14 => Ok(Self::SuspendTask),
+            15 => Ok(Self::ResumeTask),
+            16 => Ok(Self::ReadSyscallCounts),
+            17 => Ok(Self::FindStuckReply),
+            18 => Ok(Self::ReadRegisterHash),
+            19 => Ok(Self::SetTickDivisor),
+            20 => Ok(Self::CacheMaintenance),
+            21 => Ok(Self::Idle),
+            22 => Ok(Self::ReadTaskSnapshot),
+            23 => Ok(Self::TransferIrq),
+            24 => Ok(Self::TakeFiredIrqs),
+            25 => Ok(Self::ReadPanicRecord),
+            26 => Ok(Self::WatchTask),
+            27 => Ok(Self::ReadBootMeasurements),
+            28 => Ok(Self::ReadImageCheck),
+            29 => Ok(Self::ReadTaskMemory),
+            30 => Ok(Self::WriteTaskMemory),
             _ => Err(()),
         }
     }
@@ -515,6 +1077,14 @@ impl core::convert::TryFrom<u16> for Kipcnum {
 pub const HEADER_MAGIC: u32 = 0x64_CE_D6_CA;
 pub const CABOOSE_MAGIC: u32 = 0xCAB0_005E;
 
+/// Current version of the `ImageHeader` layout, as recorded in its `format`
+/// field. Images from before the field existed have zero there.
+///
+/// Format 2 added `boot_measurements` and `boot_measurements_len`, and format
+/// 3 added the `image_check_*` fields; readers must ignore those words in
+/// older headers.
+pub const HEADER_FORMAT: u32 = 3;
+
 /// TODO: Add hash for integrity check
 /// Later this will also be a signature block
 #[repr(C)]
@@ -522,11 +1092,225 @@ pub const CABOOSE_MAGIC: u32 = 0xCAB0_005E;
 pub struct ImageHeader {
     pub magic: u32,
     pub total_image_len: u32,
-    pub _pad: [u32; 16], // previous location of SAU entries
+    /// Version of this header's layout; see `HEADER_FORMAT`. A reader should
+    /// reject a header with a format newer than it understands.
+    pub format: u32,
+    /// `ImageFeatures` bits that the image can't run without. A kernel that
+    /// doesn't support all of these must refuse to boot the image.
+    pub required_features: u32,
+    /// `ImageFeatures` bits that the image can make use of, but can safely do
+    /// without.
+    pub optional_features: u32,
+    /// Address of the RAM the bootloader should leave a `BootMeasurements`
+    /// in, or zero if the kernel doesn't take them.
+    pub boot_measurements: u32,
+    /// Size of that RAM, in bytes.
+    pub boot_measurements_len: u32,
+    /// Start address and length of the part of flash covered by
+    /// `image_check_crc`, or zeros if the image wasn't built with one.
+    pub image_check_start: u32,
+    pub image_check_len: u32,
+    /// `Crc32` of those bytes, taking this field itself to be zero.
+    pub image_check_crc: u32,
+    pub _pad: [u32; 8], // previous location of SAU entries
     pub version: u32,
     pub epoch: u32,
 }
 
+bitflags::bitflags! {
+    /// Kernel features that an image may depend on, as recorded in its
+    /// `ImageHeader`.
+    ///
+    /// Bits are never reused, so that kernels and images built at different
+    /// times can tell which features they have in common.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub struct ImageFeatures: u32 {
+        /// Some task has a mailbox for `SEND_ASYNC` messages.
+        const MAILBOXES = 1 << 0;
+        /// Some task has more regions than fit in the MPU, and relies on the
+        /// kernel loading them on demand.
+        const VIRTUAL_REGIONS = 1 << 1;
+        /// The image configures the kernel's restart throttle.
+        const RESTART_THROTTLE = 1 << 2;
+        /// The image configures interrupt storm protection.
+        const IRQ_STORM_LIMIT = 1 << 3;
+        /// The image configures the watchdog heartbeat.
+        const WATCHDOG = 1 << 4;
+        /// The image configures dead-man detection for servers that don't
+        /// reply.
+        const REPLY_DEADMAN = 1 << 5;
+        /// The image header carries a CRC of the image, for the kernel to
+        /// check at startup.
+        const IMAGE_CHECK = 1 << 6;
+    }
+}
+
+/// CRC-32 as used by Ethernet and zip (the one `crc` calls `CRC_32_ISO_HDLC`).
+///
+/// This is what the build system stamps into `ImageHeader::image_check_crc`,
+/// and what the kernel checks the image against at startup; it's here so that
+/// they share one implementation. It goes a nibble at a time, trading some
+/// speed for a table small enough not to matter in the kernel.
+#[derive(Copy, Clone, Debug)]
+pub struct Crc32(u32);
+
+const CRC32_NIBBLES: [u32; 16] = {
+    let mut table = [0; 16];
+    let mut i = 0;
+    while i < 16 {
+        let mut c = i as u32;
+        let mut bit = 0;
+        while bit < 4 {
+            c = if c & 1 != 0 {
+                (c >> 1) ^ 0xEDB8_8320
+            } else {
+                c >> 1
+            };
+            bit += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Self(!0)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            let mut c = self.0 ^ u32::from(b);
+            c = (c >> 4) ^ CRC32_NIBBLES[(c & 0xf) as usize];
+            c = (c >> 4) ^ CRC32_NIBBLES[(c & 0xf) as usize];
+            self.0 = c;
+        }
+    }
+
+    pub fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What the kernel found when it checked the image against the CRC in its
+/// header, as returned by the `read_image_check` kipc.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ImageCheck {
+    /// The image wasn't built with a CRC, so there was nothing to check.
+    Skipped,
+    /// The image matched its CRC.
+    Passed,
+    /// The image didn't match its CRC: some of its flash has changed since it
+    /// was built. `actual` is what the kernel computed.
+    Failed { expected: u32, actual: u32 },
+}
+
+/// Value of `BootMeasurements::magic` in a record left by a bootloader.
+pub const BOOT_MEASUREMENTS_MAGIC: u32 = 0xB007_3EA5;
+
+/// Current version of the `BootMeasurements` layout.
+pub const BOOT_MEASUREMENTS_VERSION: u32 = 1;
+
+/// What the bootloader found out about the image it booted, handed to the
+/// kernel in the RAM named by the image header's `boot_measurements`.
+///
+/// The bootloader fills one of these in, calls `seal`, and writes it there
+/// just before jumping to the image. The kernel checks it at startup, copies
+/// it somewhere tasks can't touch, and wipes the original, so a reset that
+/// doesn't go through the bootloader leaves no stale record behind. An
+/// attester (or the supervisor) can then fetch the copy with
+/// `read_boot_measurements`.
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct BootMeasurements {
+    /// `BOOT_MEASUREMENTS_MAGIC`.
+    pub magic: u32,
+    /// `BOOT_MEASUREMENTS_VERSION`.
+    pub version: u32,
+    /// `BootFlags` bits.
+    pub flags: u32,
+    /// The image's version and epoch, as the bootloader read them from the
+    /// image header before checking it.
+    pub image_version: u32,
+    pub image_epoch: u32,
+    /// How many times the bootloader has booted this image, if it keeps
+    /// count; zero otherwise.
+    pub boot_count: u32,
+    /// The bootloader's digest of the image, SHA3-256 unless the bootloader
+    /// says otherwise.
+    pub image_digest: [u8; 32],
+    /// The bootloader's digest of itself, or zeros.
+    pub bootloader_digest: [u8; 32],
+    /// FNV-1a of everything above; see `checksum`.
+    pub check: u32,
+}
+
+bitflags::bitflags! {
+    /// What a bootloader can tell the image about how it was booted.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub struct BootFlags: u32 {
+        /// Secure boot is enabled: the bootloader only boots signed images.
+        const SECURE_BOOT = 1 << 0;
+        /// The bootloader checked this image's signature, and it was good.
+        const IMAGE_VERIFIED = 1 << 1;
+        /// Debug access is locked out.
+        const DEBUG_LOCKED = 1 << 2;
+        /// The bootloader fell back to this image because the one it would
+        /// have preferred failed its checks.
+        const FALLBACK = 1 << 3;
+    }
+}
+
+impl BootMeasurements {
+    pub const EMPTY: Self = Self {
+        magic: 0,
+        version: 0,
+        flags: 0,
+        image_version: 0,
+        image_epoch: 0,
+        boot_count: 0,
+        image_digest: [0; 32],
+        bootloader_digest: [0; 32],
+        check: 0,
+    };
+
+    /// Returns the 32-bit FNV-1a hash of every field before `check`. This
+    /// catches RAM that holds garbage or a torn write, not tampering: the
+    /// bootloader is trusted, and nothing else runs before the kernel.
+    pub fn checksum(&self) -> u32 {
+        let bytes = self.as_bytes();
+        let body = &bytes[..bytes.len() - core::mem::size_of::<u32>()];
+        body.iter().fold(0x811c_9dc5_u32, |h, &b| {
+            (h ^ u32::from(b)).wrapping_mul(0x0100_0193)
+        })
+    }
+
+    /// Fills in the magic number, version, and checksum.
+    pub fn seal(&mut self) {
+        self.magic = BOOT_MEASUREMENTS_MAGIC;
+        self.version = BOOT_MEASUREMENTS_VERSION;
+        self.check = self.checksum();
+    }
+
+    /// Checks the magic number, version, and checksum.
+    pub fn is_valid(&self) -> bool {
+        self.magic == BOOT_MEASUREMENTS_MAGIC
+            && self.version == BOOT_MEASUREMENTS_VERSION
+            && self.check == self.checksum()
+    }
+
+    pub fn flags(&self) -> BootFlags {
+        BootFlags::from_bits_retain(self.flags)
+    }
+}
+
 // Corresponds to the ARM vector table, limited to what we need
 // see ARMv8m B3.30 and B1.5.3 ARMv7m for the full description
 #[repr(C)]
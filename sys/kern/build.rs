@@ -8,12 +8,17 @@ use std::io::Write;
 
 use anyhow::{bail, Context, Result};
 use build_kconfig::{
-    InterruptConfig, KernelConfig, OwnedAddress, RegionAttributes,
-    RegionConfig, SpecialRole,
+    InterruptConfig, KernelConfig, OwnedAddress, RamFillConfig,
+    RegionAttributes, RegionConfig, SpecialRole,
 };
 use indexmap::IndexMap;
 use proc_macro2::TokenStream;
 
+/// Number of MPU slots left free for faulted-in regions in tasks that use
+/// virtual regions. This must match the kernel's expectations; see
+/// `TaskDesc::extra_regions`.
+const VIRTUAL_REGION_SLOTS: usize = 2;
+
 fn main() -> Result<()> {
     build_util::expose_m_profile()?;
 
@@ -33,6 +38,16 @@ struct Generated {
     tasks: Vec<TokenStream>,
     regions: Vec<TokenStream>,
     irq_code: TokenStream,
+    watchdog_window: Option<u32>,
+    timeslice: Option<u32>,
+    irq_storm: Option<build_kconfig::IrqStormConfig>,
+    irq_storm_slots: usize,
+    irq_fired_words: usize,
+    restart_throttle: Option<build_kconfig::RestartThrottleConfig>,
+    reply_deadman: Option<build_kconfig::ReplyDeadmanConfig>,
+    tick_hz: u32,
+    tickless_idle: Option<build_kconfig::TicklessIdleConfig>,
+    mailbox_slots: usize,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
@@ -84,6 +99,7 @@ fn process_config() -> Result<Generated> {
                 write: false,
                 execute: false,
                 special_role: None,
+                sticky: false,
             },
         },
     );
@@ -154,6 +170,30 @@ fn process_config() -> Result<Generated> {
             );
         }
 
+        // A task with virtual regions keeps its owned regions (which include
+        // its code and stack) resident, along with as many shared regions as
+        // fit while leaving `VIRTUAL_REGION_SLOTS` MPU slots free; any others
+        // are faulted in by the kernel on demand.
+        let mut extra_regions = vec![];
+        if task.virtual_regions && regions.len() > 8 {
+            let target = build_util::target();
+            if target.starts_with("thumbv6m") {
+                bail!(
+                    "task {i} needs virtual regions, which aren't supported \
+                     on {target}"
+                );
+            }
+            let resident = 8 - VIRTUAL_REGION_SLOTS;
+            let owned = 1 + task
+                .owned_regions
+                .values()
+                .map(|r| r.sizes.len())
+                .sum::<usize>();
+            if owned > resident {
+                bail!("too many owned regions ({owned}) for task {i}");
+            }
+            extra_regions = regions.split_off(resident);
+        }
         if regions.len() > 8 {
             bail!("too many regions ({}) for task {i}", regions.len());
         }
@@ -164,6 +204,8 @@ fn process_config() -> Result<Generated> {
         // THIS IS IMPORTANT. The kernel exploits this property to do cheaper
         // access tests.
         regions.sort_by_key(|i| region_table.get_index(*i).unwrap().1.base);
+        extra_regions
+            .sort_by_key(|i| region_table.get_index(*i).unwrap().1.base);
 
         // Translate abstract addresses in the task description into concrete
         // addresses.
@@ -172,21 +214,67 @@ fn process_config() -> Result<Generated> {
         let initial_stack =
             translate_address(&region_table, i, task.initial_stack.clone());
 
+        let mut ram_init = vec![];
+        for init in &task.ram_init {
+            let base = translate_address(&region_table, i, init.base.clone());
+            let size = init.size;
+            if base % 4 != 0 || size % 4 != 0 {
+                bail!(
+                    "task {i} RAM init range {base:#x} ({size} bytes) isn't \
+                     word-aligned"
+                );
+            }
+            let fill = match &init.fill {
+                RamFillConfig::Zero => quote::quote! { RamFill::Zero },
+                RamFillConfig::Pattern(word) => {
+                    quote::quote! { RamFill::Pattern(#word) }
+                }
+                RamFillConfig::Copy { source } => {
+                    let source =
+                        translate_address(&region_table, i, source.clone());
+                    quote::quote! { RamFill::Copy { source: #source } }
+                }
+            };
+            ram_init.push(quote::quote! {
+                RamInit { base: #base, size: #size, fill: #fill }
+            });
+        }
+
         let index = u16::try_from(i).expect("over 2**16 tasks??");
         let priority = task.priority;
-        let flags = if task.start_at_boot {
-            quote::quote! { TaskFlags::START_AT_BOOT }
-        } else {
+        let mailbox_depth = task.mailbox_depth;
+        let mut flags = vec![];
+        if task.start_at_boot {
+            flags.push(quote::quote! { TaskFlags::START_AT_BOOT });
+        }
+        if task.uses_fpu {
+            flags.push(quote::quote! { TaskFlags::USES_FPU });
+        }
+        if task.monitor {
+            flags.push(quote::quote! { TaskFlags::MONITOR });
+        }
+        if task.attester {
+            flags.push(quote::quote! { TaskFlags::ATTESTER });
+        }
+        if task.clock_owner {
+            flags.push(quote::quote! { TaskFlags::CLOCK_OWNER });
+        }
+        let flags = if flags.is_empty() {
             quote::quote! { TaskFlags::empty() }
+        } else {
+            quote::quote! { #(#flags)|* }
         };
         task_descs.push(quote::quote! {
             TaskDesc {
                 regions: [#(&HUBRIS_REGION_DESCS[#regions]),*],
+                extra_regions: &[#(&HUBRIS_REGION_DESCS[#extra_regions]),*],
                 entry_point: #entry_point,
                 initial_stack: #initial_stack,
                 priority: #priority,
                 index: #index,
                 flags: #flags,
+                mailbox_depth: #mailbox_depth,
+                ram_init: &[#(#ram_init),*],
             }
         });
     }
@@ -331,6 +419,37 @@ fn process_config() -> Result<Generated> {
         tasks: task_descs,
         regions: region_descs,
         irq_code,
+        watchdog_window: kconfig.watchdog.map(|w| w.window),
+        timeslice: kconfig.timeslice,
+        irq_storm: kconfig.irq_storm,
+        restart_throttle: kconfig.restart_throttle,
+        reply_deadman: kconfig.reply_deadman,
+        tick_hz: kconfig.tick_hz,
+        tickless_idle: kconfig.tickless_idle,
+        // Storm counters are indexed by IRQ number, so we need one more than
+        // the highest IRQ in use -- but only if the feature is on.
+        irq_storm_slots: if kconfig.irq_storm.is_some() {
+            kconfig
+                .irqs
+                .keys()
+                .max()
+                .map(|&n| n as usize + 1)
+                .unwrap_or(0)
+        } else {
+            0
+        },
+        // One bit per IRQ number, up to the highest in use.
+        irq_fired_words: kconfig
+            .irqs
+            .keys()
+            .max()
+            .map(|&n| n as usize / 32 + 1)
+            .unwrap_or(0),
+        mailbox_slots: kconfig
+            .tasks
+            .iter()
+            .map(|t| usize::from(t.mailbox_depth))
+            .sum(),
     })
 }
 
@@ -373,6 +492,9 @@ fn fmt_region(region: &RegionConfig) -> TokenStream {
             SpecialRole::Dma => quote::quote! { DMA },
         });
     }
+    if attributes.sticky {
+        atts.push(quote::quote! { STICKY });
+    }
 
     let atts = if atts.is_empty() {
         quote::quote! { RegionAttributes::empty() }
@@ -418,13 +540,24 @@ fn generate_statics(gen: &Generated) -> Result<()> {
         file,
         "{}",
         quote::quote! {
-            const HUBRIS_TASK_COUNT: usize = #task_count;
+            pub(crate) const HUBRIS_TASK_COUNT: usize = #task_count;
             #[no_mangle]
             pub static HUBRIS_IMAGE_ID: u64 = #image_id;
 
             static mut HUBRIS_TASK_TABLE_SPACE:
                 core::mem::MaybeUninit<[crate::task::Task; HUBRIS_TASK_COUNT]> =
                 core::mem::MaybeUninit::uninit();
+
+            #[no_mangle]
+            pub(crate) static HUBRIS_SYSCALL_COUNTERS: [
+                <crate::syscalls::SyscallKind as counters::Count>::Counters;
+                HUBRIS_TASK_COUNT
+            ] = {
+                #[allow(clippy::declare_interior_mutable_const)]
+                const NEW: <crate::syscalls::SyscallKind as counters::Count>::Counters =
+                    <crate::syscalls::SyscallKind as counters::Count>::NEW_COUNTERS;
+                [NEW; HUBRIS_TASK_COUNT]
+            };
         },
     )?;
 
@@ -463,6 +596,178 @@ fn generate_statics(gen: &Generated) -> Result<()> {
 
     writeln!(file, "{}", gen.irq_code)?;
 
+    /////////////////////////////////////////////////////////
+    // Mailbox space, to be divided up among tasks at startup
+
+    let mailbox_slots = gen.mailbox_slots;
+    writeln!(
+        file,
+        "{}",
+        quote::quote! {
+            static mut HUBRIS_MAILBOX_SPACE:
+                [crate::task::AsyncMessage; #mailbox_slots] =
+                [crate::task::AsyncMessage::EMPTY; #mailbox_slots];
+        },
+    )?;
+
+    /////////////////////////////////////////////////////////
+    // Watchdog heartbeat
+
+    let watchdog_window = match gen.watchdog_window {
+        Some(w) => quote::quote! { Some(#w) },
+        None => quote::quote! { None },
+    };
+    writeln!(
+        file,
+        "{}",
+        quote::quote! {
+            pub(crate) const HUBRIS_WATCHDOG_WINDOW: Option<u32> =
+                #watchdog_window;
+        },
+    )?;
+
+    /////////////////////////////////////////////////////////
+    // Round-robin timeslicing
+
+    let timeslice = match gen.timeslice {
+        Some(q) => quote::quote! { Some(#q) },
+        None => quote::quote! { None },
+    };
+    writeln!(
+        file,
+        "{}",
+        quote::quote! {
+            pub(crate) const HUBRIS_TIMESLICE: Option<u32> = #timeslice;
+        },
+    )?;
+
+    /////////////////////////////////////////////////////////
+    // Interrupt storm protection
+
+    let (storm_limit, storm_notification) = match gen.irq_storm {
+        Some(s) => {
+            let (limit, notification) = (s.limit, s.notification);
+            (
+                quote::quote! { Some(#limit) },
+                quote::quote! { #notification },
+            )
+        }
+        None => (quote::quote! { None }, quote::quote! { 0 }),
+    };
+    let storm_slots = gen.irq_storm_slots;
+    writeln!(
+        file,
+        "{}",
+        quote::quote! {
+            pub(crate) const HUBRIS_IRQ_STORM_LIMIT: Option<u32> = #storm_limit;
+            pub(crate) const HUBRIS_IRQ_STORM_NOTIFICATION: u64 =
+                #storm_notification;
+            pub(crate) static HUBRIS_IRQ_STORM_COUNTS:
+                [core::sync::atomic::AtomicU32; #storm_slots] = {
+                #[allow(clippy::declare_interior_mutable_const)]
+                const ZERO: core::sync::atomic::AtomicU32 =
+                    core::sync::atomic::AtomicU32::new(0);
+                [ZERO; #storm_slots]
+            };
+        },
+    )?;
+
+    /////////////////////////////////////////////////////////
+    // Record of which interrupts have fired
+
+    let fired_words = gen.irq_fired_words;
+    writeln!(
+        file,
+        "{}",
+        quote::quote! {
+            pub(crate) static HUBRIS_IRQ_FIRED:
+                [core::sync::atomic::AtomicU32; #fired_words] = {
+                #[allow(clippy::declare_interior_mutable_const)]
+                const ZERO: core::sync::atomic::AtomicU32 =
+                    core::sync::atomic::AtomicU32::new(0);
+                [ZERO; #fired_words]
+            };
+        },
+    )?;
+
+    /////////////////////////////////////////////////////////
+    // Restart throttle
+
+    let (restart_throttle, held_notification, released_notification) =
+        match gen.restart_throttle {
+            Some(t) => {
+                let (faults, window) = (t.faults, t.window);
+                let (held, released) =
+                    (t.held_notification, t.released_notification);
+                (
+                    quote::quote! { Some((#faults, #window)) },
+                    quote::quote! { #held },
+                    quote::quote! { #released },
+                )
+            }
+            None => (
+                quote::quote! { None },
+                quote::quote! { 0 },
+                quote::quote! { 0 },
+            ),
+        };
+    writeln!(
+        file,
+        "{}",
+        quote::quote! {
+            pub(crate) const HUBRIS_RESTART_THROTTLE: Option<(u32, u32)> =
+                #restart_throttle;
+            pub(crate) const HUBRIS_RESTART_HELD_NOTIFICATION: u64 =
+                #held_notification;
+            pub(crate) const HUBRIS_RESTART_RELEASED_NOTIFICATION: u64 =
+                #released_notification;
+        },
+    )?;
+
+    /////////////////////////////////////////////////////////
+    // Reply dead-man
+
+    let (deadman_ticks, deadman_notification) = match gen.reply_deadman {
+        Some(d) => {
+            let (ticks, notification) = (d.ticks, d.notification);
+            (
+                quote::quote! { Some(#ticks) },
+                quote::quote! { #notification },
+            )
+        }
+        None => (quote::quote! { None }, quote::quote! { 0 }),
+    };
+    writeln!(
+        file,
+        "{}",
+        quote::quote! {
+            pub(crate) const HUBRIS_REPLY_DEADMAN: Option<u32> = #deadman_ticks;
+            pub(crate) const HUBRIS_REPLY_DEADMAN_NOTIFICATION: u64 =
+                #deadman_notification;
+        },
+    )?;
+
+    /////////////////////////////////////////////////////////
+    // Tick rate and tickless idle
+
+    let tick_hz = gen.tick_hz;
+    let tickless_idle = match gen.tickless_idle {
+        Some(t) => {
+            let idle_task = t.idle_task;
+            quote::quote! { Some(#idle_task) }
+        }
+        None => quote::quote! { None },
+    };
+    writeln!(
+        file,
+        "{}",
+        quote::quote! {
+            pub(crate) const HUBRIS_TICK_HZ: u32 = #tick_hz;
+            pub(crate) const HUBRIS_TICKLESS_IDLE: Option<usize> =
+                #tickless_idle;
+        },
+    )?;
+
     drop(file);
     call_rustfmt::rustfmt(kconfig_path)?;
 
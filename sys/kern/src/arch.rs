@@ -21,10 +21,18 @@ cfg_if::cfg_if! {
 
     if #[cfg(not(target_pointer_width = "32"))] {
         compile_error!("non-32-bit targets not supported (even for simulation)");
+    } else if #[cfg(not(target_os = "none"))] {
+        #[macro_use]
+        pub mod sim;
+        pub use sim::*;
     } else if #[cfg(target_arch = "arm")] {
         #[macro_use]
         pub mod arm_m;
         pub use arm_m::*;
+
+        // The parts of ARMv8-M that work differently, which `arm_m` uses.
+        #[cfg(armv8m)]
+        pub mod arm_v8m;
     } else {
         compile_error!("support for this architecture not implemented");
     }
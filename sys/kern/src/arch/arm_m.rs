@@ -4,6 +4,8 @@
 
 //! Architecture support for ARMv{6,7,8}-M.
 //!
+//! The MPU and stack limit handling particular to ARMv8-M is in `arm_v8m`.
+//!
 //! # ARM-M timer
 //!
 //! We use the system tick timer as the kernel timer, but it's only suitable for
@@ -75,17 +77,19 @@ use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, Ordering};
 
 use zerocopy::FromBytes;
 
+#[cfg(armv8m)]
+use crate::arch::arm_v8m;
 use crate::atomic::AtomicExt;
-use crate::descs::RegionAttributes;
-use crate::startup::with_task_table;
+use crate::descs::{RegionAttributes, RegionDesc, TaskFlags, REGIONS_PER_TASK};
+use crate::startup::{with_task_table, HUBRIS_TASK_COUNT};
 use crate::task;
 use crate::time::Timestamp;
 use crate::umem::USlice;
+use abi::{CacheOp, FaultInfo, InterruptNum};
 #[cfg(any(armv7m, armv8m))]
-use abi::FaultSource;
-use abi::{FaultInfo, InterruptNum};
-#[cfg(armv8m)]
-use armv8_m_mpu::{disable_mpu, enable_mpu};
+use abi::{
+    MemoryAccessKind, MemoryFaultDetail, MemoryFaultKind, NearestRegion,
+};
 use unwrap_lite::UnwrapLite;
 
 macro_rules! uassert {
@@ -212,6 +216,12 @@ impl task::ArchState for SavedState {
     fn ret5(&mut self, x: u32) {
         self.r9 = x
     }
+    fn ret6(&mut self, x: u32) {
+        self.r10 = x
+    }
+    fn ret7(&mut self, x: u32) {
+        self.r11 = x
+    }
 }
 
 /// Stuff placed on the stack at exception entry whether or not an FPU is
@@ -268,14 +278,104 @@ const INITIAL_FPSCR: u32 = 0;
 /// that support it) (and that bit 6 and bit 0 can always be set).
 const EXC_RETURN_CONST: u32 = 0xFFFFFFED;
 
+/// EXC_RETURN for tasks without an FPU context. This is `EXC_RETURN_CONST`
+/// with the FType bit (bit 4) set, which selects the basic (integer-only)
+/// exception frame.
+///
+/// Throughout this file, a task's saved EXC_RETURN is what tells us whether it
+/// has live FPU state: the hardware clears FType whenever the task had used the
+/// FPU at exception entry, and stacked the extended frame. We only save and
+/// restore `s16`-`s31` in that case.
+#[cfg(any(armv7m, armv8m))]
+const EXC_RETURN_NO_FPU: u32 = EXC_RETURN_CONST | 1 << 4;
+
 // Because debuggers need to know the clock frequency to set the SWO clock
 // scaler that enables ITM, and because ITM is particularly useful when
 // debugging boot failures, this should be set as early in boot as it can
 // be.
-pub unsafe fn set_clock_freq(tick_divisor: u32) {
-    CLOCK_FREQ_KHZ.store(tick_divisor, Ordering::Relaxed);
+pub unsafe fn set_clock_freq(cycles_per_ms: u32) {
+    CLOCK_FREQ_KHZ.store(cycles_per_ms, Ordering::Relaxed);
+}
+
+/// Largest tick divisor the SysTick timer can count, since its reload register
+/// is 24 bits wide.
+pub const MAX_TICK_DIVISOR: u32 = 1 << 24;
+
+/// Changes the number of CPU cycles in a kernel tick, for when the CPU clock has
+/// been changed at runtime. The caller is responsible for checking that
+/// `tick_divisor` is in `1..=MAX_TICK_DIVISOR`.
+///
+/// The tick in progress is stretched (or shrunk) to end one new period from
+/// now, so it may be a little longer or shorter than any other tick.
+pub fn set_tick_divisor(tick_divisor: u32) {
+    // Rebase `cycle_count` so that it carries on from where it is, rather
+    // than jumping to reflect the new period. If a tick is pending, it's
+    // already counted in `cycle_count`, so it goes in the base too.
+    let cycles = cycle_count();
+    let pending = cortex_m::peripheral::SCB::is_pendst_pending();
+    store_split(&CYCLE_BASE, cycles);
+    store_split(&TICK_BASE, u64::from(now()) + u64::from(pending));
+
+    // Safety: we're the kernel, and the timer is ours.
+    unsafe {
+        let syst = &*cortex_m::peripheral::SYST::PTR;
+        TICK_DIVISOR.store(tick_divisor, Ordering::Relaxed);
+        syst.rvr.write(tick_divisor - 1);
+        // Writing the current value makes the timer reload from the new
+        // period on its next count.
+        syst.cvr.write(0);
+    }
 }
 
+/// Performs `op` on every data cache line overlapping `len` bytes at `base`.
+/// The caller is responsible for checking that the task asking for this can
+/// access the range, and that `base + len` doesn't wrap.
+///
+/// This does nothing if the data cache is off -- including on cores that don't
+/// have one, where the enable bit reads as zero -- and on ARMv6-M.
+pub fn dcache_maintain(op: CacheOp, base: u32, len: u32) {
+    cfg_if::cfg_if! {
+        if #[cfg(any(armv7m, armv8m))] {
+            if len == 0 || !cortex_m::peripheral::SCB::dcache_enabled() {
+                return;
+            }
+            // Safety: we're the kernel, and the cache maintenance registers
+            // are write-only and stateless.
+            let cbp = unsafe { &*cortex_m::peripheral::CBP::PTR };
+            let end = u64::from(base) + u64::from(len);
+            let line = abi::DCACHE_LINE_SIZE;
+            let mut addr = base & !(line - 1);
+
+            // Make sure any stores the task made to the range have reached
+            // the cache before we act on it.
+            cortex_m::asm::dsb();
+            while u64::from(addr) < end {
+                // Safety: maintenance by address doesn't change memory that
+                // the task can't already write, which has been checked.
+                unsafe {
+                    match op {
+                        CacheOp::Clean => cbp.dccmvac.write(addr),
+                        CacheOp::Invalidate => cbp.dcimvac.write(addr),
+                        CacheOp::CleanInvalidate => cbp.dccimvac.write(addr),
+                    }
+                }
+                addr = addr.wrapping_add(line);
+                if addr == 0 {
+                    break;
+                }
+            }
+            cortex_m::asm::dsb();
+            cortex_m::asm::isb();
+        } else {
+            let _ = (op, base, len);
+        }
+    }
+}
+
+/// Pattern written over a task's unused stack by `reinitialize`, so that we can
+/// tell later how far down the stack has been used.
+const STACK_WATERMARK: u32 = 0xbaddcafe;
+
 pub fn reinitialize(task: &mut task::Task) {
     *task.save_mut() = SavedState::default();
     let initial_stack = task.descriptor().initial_stack as usize;
@@ -285,15 +385,24 @@ pub fn reinitialize(task: &mut task::Task) {
     // the task table is corrupted -- this is deliberate.
     uassert!(initial_stack & 0x7 == 0);
 
+    // Only tasks flagged as using the FPU start out with an FPU context. Other
+    // tasks get the smaller basic frame, and we skip saving and restoring FPU
+    // registers for them on every context switch. (A task that uses the FPU
+    // without the flag still works -- the hardware will stack the extended
+    // frame for it from then on -- but it's better to say so.)
+    let uses_fpu = cfg!(any(armv7m, armv8m))
+        && task.descriptor().flags.contains(TaskFlags::USES_FPU);
+
     // The remaining state is stored on the stack.
-    // Use checked operations to get a reference to the exception frame.
-    let frame_size = core::mem::size_of::<ExtendedExceptionFrame>();
+    let frame_size = if uses_fpu {
+        core::mem::size_of::<ExtendedExceptionFrame>()
+    } else {
+        core::mem::size_of::<BaseExceptionFrame>()
+    };
     // The subtract below can overflow if the task table is corrupt -- let's
     // make that failure a little easier to read:
     uassert!(initial_stack >= frame_size);
-    // Ok. Generate a uslice for the task's starting stack frame.
-    let mut frame_uslice: USlice<ExtendedExceptionFrame> =
-        USlice::from_raw(initial_stack - frame_size, 1).unwrap_lite();
+    let frame_addr = initial_stack - frame_size;
 
     // Before we set our frame, find the region that contains the top word of
     // the stack -- one word below the initial stack pointer -- and zap the
@@ -311,36 +420,241 @@ pub fn reinitialize(task: &mut task::Task) {
     {
         let mut uslice: USlice<u32> = USlice::from_raw(
             region.base as usize,
-            (initial_stack - frame_size - region.base as usize) >> 2,
+            (frame_addr - region.base as usize) >> 2,
         )
         .unwrap_lite();
 
         let zap = task.try_write(&mut uslice).unwrap_lite();
         for word in zap.iter_mut() {
-            *word = 0xbaddcafe;
+            *word = STACK_WATERMARK;
         }
     }
 
-    let descriptor = task.descriptor();
-    let frame = &mut task.try_write(&mut frame_uslice).unwrap_lite()[0];
-
-    // Conservatively/defensively zero the entire frame.
-    *frame = ExtendedExceptionFrame::default();
-    // Now fill in the bits we actually care about.
-    frame.base.pc = descriptor.entry_point | 1; // for thumb
-    frame.base.xpsr = INITIAL_PSR;
-    frame.base.lr = 0xFFFF_FFFF; // trap on return from main
-    #[cfg(any(armv7m, armv8m))]
-    {
-        frame.fpscr = INITIAL_FPSCR;
+    // Conservatively/defensively zero the entire frame, then fill in the bits
+    // we actually care about.
+    let base = BaseExceptionFrame {
+        pc: task.descriptor().entry_point | 1, // for thumb
+        xpsr: INITIAL_PSR,
+        lr: 0xFFFF_FFFF, // trap on return from main
+        ..BaseExceptionFrame::default()
+    };
+    if uses_fpu {
+        #[cfg(any(armv7m, armv8m))]
+        {
+            let mut frame_uslice: USlice<ExtendedExceptionFrame> =
+                USlice::from_raw(frame_addr, 1).unwrap_lite();
+            task.try_write(&mut frame_uslice).unwrap_lite()[0] =
+                ExtendedExceptionFrame {
+                    base,
+                    fpscr: INITIAL_FPSCR,
+                    ..ExtendedExceptionFrame::default()
+                };
+        }
+    } else {
+        let mut frame_uslice: USlice<BaseExceptionFrame> =
+            USlice::from_raw(frame_addr, 1).unwrap_lite();
+        task.try_write(&mut frame_uslice).unwrap_lite()[0] = base;
     }
 
     // Set the initial stack pointer, *not* to the stack top, but to the base of
     // this frame.
-    task.save_mut().psp = frame as *const _ as u32;
+    task.save_mut().psp = frame_addr as u32;
+
+    // Finally, record the EXC_RETURN we'll use to enter the task, which tells
+    // the hardware which kind of frame to expect.
+    cfg_if::cfg_if! {
+        if #[cfg(any(armv7m, armv8m))] {
+            task.save_mut().exc_return = if uses_fpu {
+                EXC_RETURN_CONST
+            } else {
+                EXC_RETURN_NO_FPU
+            };
+        } else {
+            task.save_mut().exc_return = EXC_RETURN_CONST;
+        }
+    }
+}
+
+/// Estimates the peak stack usage of `task` since it was last initialized, in
+/// bytes, by scanning up from the bottom of its stack region for the first word
+/// that no longer holds the watermark pattern written by `reinitialize`.
+///
+/// This can underestimate if the task happened to write the watermark value
+/// itself, or skipped over words (e.g. with a large uninitialized local), but is
+/// good enough to judge headroom. Returns `None` if the task's initial stack
+/// pointer doesn't fall in any of its regions.
+pub fn stack_high_water_mark(task: &task::Task) -> Option<u32> {
+    let initial_stack = task.descriptor().initial_stack as usize;
+    let region = task
+        .region_table()
+        .iter()
+        .find(|region| region.contains(initial_stack.saturating_sub(4)))?;
+    let stack: USlice<u32> = USlice::from_raw(
+        region.base as usize,
+        (initial_stack - region.base as usize) >> 2,
+    )
+    .ok()?;
+    let words = task.try_read(&stack).ok()?;
+    let untouched = words
+        .iter()
+        .take_while(|&&word| word == STACK_WATERMARK)
+        .count();
+    Some(((words.len() - untouched) * 4) as u32)
+}
+
+/// MPU register values for one task, computed once at startup by
+/// `precompute_memory_protection` so that `apply_memory_protection` only has
+/// to copy them into the MPU on each context switch.
+#[derive(Copy, Clone, Debug)]
+struct TaskMpuRegs {
+    rbar: [u32; REGIONS_PER_TASK],
+    /// Region attribute and size registers. The enable bit (bit 0) is *not*
+    /// set in these, because it's possible to hard-fault midway through region
+    /// configuration if address and size are incompatible while the region is
+    /// enabled.
+    #[cfg(any(armv6m, armv7m))]
+    rasr: [u32; REGIONS_PER_TASK],
+    /// Region limit registers. As with `rasr` on older profiles, the enable bit
+    /// is not set in these.
+    #[cfg(armv8m)]
+    rlar: [u32; REGIONS_PER_TASK],
+    /// Memory attribute indirection registers, one attribute byte per region.
+    #[cfg(armv8m)]
+    mair: [u32; 2],
+    /// Bitmask of MPU slots holding the null region, which can be used to load
+    /// the task's virtual regions on demand.
+    #[cfg_attr(armv6m, allow(dead_code))]
+    spare: u8,
+}
+
+impl TaskMpuRegs {
+    const EMPTY: Self = Self {
+        rbar: [0; REGIONS_PER_TASK],
+        #[cfg(any(armv6m, armv7m))]
+        rasr: [0; REGIONS_PER_TASK],
+        #[cfg(armv8m)]
+        rlar: [0; REGIONS_PER_TASK],
+        #[cfg(armv8m)]
+        mair: [0; 2],
+        spare: 0,
+    };
+}
+
+/// Precomputed MPU settings for every task, indexed by task index.
+///
+/// This lives in RAM both so that we can store the values in the format the
+/// hardware wants, and so that reading them doesn't incur flash wait states.
+///
+/// This is written only by `precompute_memory_protection`, during startup,
+/// and is read-only from then on.
+static mut TASK_MPU_REGS: [TaskMpuRegs; HUBRIS_TASK_COUNT] =
+    [TaskMpuRegs::EMPTY; HUBRIS_TASK_COUNT];
+
+/// Returns the precomputed MPU settings for `task`.
+fn task_mpu_regs(task: &task::Task) -> &'static TaskMpuRegs {
+    // Safety: the table is only written during startup, so reading it is fine.
+    unsafe {
+        &(*core::ptr::addr_of!(TASK_MPU_REGS))
+            [usize::from(task.descriptor().index)]
+    }
+}
+
+/// Computes the MPU register values for `task` and records them for use by
+/// `apply_memory_protection`. A task's regions never change, so this need only
+/// be done once per task, at startup.
+///
+/// # Safety
+///
+/// This must only be called during startup, before any task has been
+/// scheduled, since it updates state that `apply_memory_protection` reads
+/// without synchronization.
+pub unsafe fn precompute_memory_protection(task: &task::Task) {
+    let mut regs = TaskMpuRegs::EMPTY;
+    for (i, region) in task.region_table().iter().enumerate() {
+        cfg_if::cfg_if! {
+            if #[cfg(armv8m)] {
+                let (rbar, rlar, mair) = arm_v8m::region_registers(i, region);
+                regs.rbar[i] = rbar;
+                regs.rlar[i] = rlar;
+                regs.mair[i / 4] |= mair << ((i % 4) * 8);
+            } else {
+                let (rbar, rasr) = region_registers(region);
+                regs.rbar[i] = rbar;
+                regs.rasr[i] = rasr;
+            }
+        }
+        if region.attributes.is_empty() {
+            regs.spare |= 1 << i;
+        }
+    }
+    let index = usize::from(task.descriptor().index);
+    // Safety: our contract ensures nobody else is looking at this table.
+    unsafe {
+        (*core::ptr::addr_of_mut!(TASK_MPU_REGS))[index] = regs;
+    }
+}
 
-    // Finally, record the EXC_RETURN we'll use to enter the task.
-    task.save_mut().exc_return = EXC_RETURN_CONST;
+/// Computes the `RBAR` and `RASR` values (the latter without the enable bit)
+/// for `region`.
+#[cfg(any(armv6m, armv7m))]
+fn region_registers(region: &RegionDesc) -> (u32, u32) {
+    let ratts = region.attributes;
+    let xn = !ratts.contains(RegionAttributes::EXECUTE);
+    // These AP encodings are chosen such that we never deny *privileged* code
+    // (i.e. us) access to the memory.
+    let ap = if ratts.contains(RegionAttributes::WRITE) {
+        0b011
+    } else if ratts.contains(RegionAttributes::READ) {
+        0b010
+    } else {
+        0b001
+    };
+    // Set the TEX/SCB bits to configure memory type, caching policy, and
+    // shareability (with other cores or masters). See table B3-13 in the
+    // ARMv7-M ARM. (Settings are identical on v6-M but the sharability and TEX
+    // bits tend to be ignored.)
+    let (tex, scb) = if ratts.contains(RegionAttributes::DEVICE) {
+        // Shareable Device memory, which is what ARMv8-M calls Device-nGnRE:
+        // accesses aren't cached, merged or reordered, but writes may be
+        // buffered.
+        (0b000, 0b001)
+    } else if ratts.contains(RegionAttributes::DMA) {
+        // Conservative settings for normal memory assuming that DMA might be a
+        // problem:
+        // - Outer and inner non-cacheable.
+        // - Shared.
+        (0b001, 0b100)
+    } else {
+        // Aggressive settings for normal memory assume that it is used only by
+        // this processor:
+        // - Outer and inner write-back
+        // - Read and write allocate.
+        // - Not shared.
+        (0b001, 0b011)
+    };
+    // On v6/7-M the MPU expresses size of a region in log2 form _minus one._
+    // So, the minimum allowed size of 32 bytes is represented as 4, because
+    // `2**(4 + 1) == 32`.
+    //
+    // We store sizes in the region table in an architecture-independent form
+    // (number of bytes) because it simplifies basically everything else but
+    // this routine. Here we must convert between the two.
+    //
+    // The image-generation tools check at build time that region sizes are
+    // powers of two. So, we can assume that the size has a single 1 bit, and
+    // compute log2 _minus one_ as
+    //
+    //   log2_m1(N) = bits_in_word - 2 - clz(N)
+    //
+    // If the size is zero or one, this subtraction will underflow. This should
+    // not occur in a valid image, but could occur due to flash corruption. Any
+    // region size under 32 bytes is illegal on ARMv7-M anyway, so panicking is
+    // better than triggering possibly undefined hardware behavior.
+    let l2size = 30 - region.size.leading_zeros();
+
+    let rasr =
+        (xn as u32) << 28 | ap << 24 | tex << 19 | scb << 16 | l2size << 1;
+    (region.base, rasr)
 }
 
 #[cfg(any(armv6m, armv7m))]
@@ -353,164 +667,117 @@ pub fn apply_memory_protection(task: &task::Task) {
         // aliasing....
         &*cortex_m::peripheral::MPU::PTR
     };
+    let regs = task_mpu_regs(task);
 
-    for (i, region) in task.region_table().iter().enumerate() {
-        let ratts = region.attributes;
-        let xn = !ratts.contains(RegionAttributes::EXECUTE);
-        // These AP encodings are chosen such that we never deny *privileged*
-        // code (i.e. us) access to the memory.
-        let ap = if ratts.contains(RegionAttributes::WRITE) {
-            0b011
-        } else if ratts.contains(RegionAttributes::READ) {
-            0b010
-        } else {
-            0b001
-        };
-        // Set the TEX/SCB bits to configure memory type, caching policy, and
-        // shareability (with other cores or masters). See table B3-13 in the
-        // ARMv7-M ARM. (Settings are identical on v6-M but the sharability and
-        // TEX bits tend to be ignored.)
-        let (tex, scb) = if ratts.contains(RegionAttributes::DEVICE) {
-            // Device memory.
-            (0b000, 0b001)
-        } else if ratts.contains(RegionAttributes::DMA) {
-            // Conservative settings for normal memory assuming that DMA might
-            // be a problem:
-            // - Outer and inner non-cacheable.
-            // - Shared.
-            (0b001, 0b100)
-        } else {
-            // Aggressive settings for normal memory assume that it is used only
-            // by this processor:
-            // - Outer and inner write-back
-            // - Read and write allocate.
-            // - Not shared.
-            (0b001, 0b011)
-        };
-        // On v6/7-M the MPU expresses size of a region in log2 form _minus
-        // one._ So, the minimum allowed size of 32 bytes is represented as 4,
-        // because `2**(4 + 1) == 32`.
-        //
-        // We store sizes in the region table in an architecture-independent
-        // form (number of bytes) because it simplifies basically everything
-        // else but this routine. Here we must convert between the two -- and
-        // quickly, because this is called on every context switch.
-        //
-        // The image-generation tools check at build time that region sizes are
-        // powers of two. So, we can assume that the size has a single 1 bit. We
-        // can cheaply compute log2 of this by counting trailing zeroes, but
-        // ARMv7-M doesn't have a native instruction for that -- only leading
-        // zeroes. The equivalent using leading zeroes is
-        //
-        //   log2(N) = bits_in_word - 1 - clz(N)
-        //
-        // Because we want log2 _minus one_ we compute it as...
-        //
-        //   log2_m1(N) = bits_in_word - 2 - clz(N)
-        //
-        // If the size is zero or one, this subtraction will underflow. This
-        // should not occur in a valid image, but could occur due to runtime
-        // flash corruption. Any region size under 32 bytes is illegal on
-        // ARMv7-M anyway, so panicking is better than triggering possibly
-        // undefined hardware behavior.
-        //
-        // On ARMv6-M, there is no CLZ instruction either. This winds up
-        // generating decent intrinsic code for `leading_zeros` so we'll live
-        // with it.
-        let l2size = 30 - region.size.leading_zeros();
-
-        // Region attribute and size register; note that enable (bit 0) is not
-        // set here, because it's possible to hard-fault midway through region
-        // configuration if address and size are incompatible while the region
-        // is enabled.
-        let rasr =
-            (xn as u32) << 28 | ap << 24 | tex << 19 | scb << 16 | l2size << 1;
+    // Let any stores we made under the outgoing task's memory types finish
+    // before those types change.
+    cortex_m::asm::dsb();
+    for (i, (&rbar, &rasr)) in regs.rbar.iter().zip(&regs.rasr).enumerate() {
         unsafe {
             mpu.rnr.write(i as u32); // Select the region
             mpu.rasr.write(rasr); // configure, but leave disabled
-            mpu.rbar.write(region.base); // set region address
+            mpu.rbar.write(rbar); // set region address
             mpu.rasr.write(rasr | 1); // enable the region
         }
     }
+    // Make sure the new settings, and in particular the memory types, are in
+    // place before the task makes any accesses. The exception return into the
+    // task does the job of an ISB.
+    cortex_m::asm::dsb();
+
+    #[cfg(armv7m)]
+    forget_virtual_regions();
 }
 
+/// Configures the MPU and the process stack limit for `task`. See `arm_v8m`
+/// for how this differs from older profiles.
 #[cfg(armv8m)]
 pub fn apply_memory_protection(task: &task::Task) {
-    let mpu = unsafe {
-        // At least by not taking a &mut we're confident we're not violating
-        // aliasing....
-        &*cortex_m::peripheral::MPU::PTR
-    };
-    unsafe {
-        disable_mpu(mpu);
-    }
+    let regs = task_mpu_regs(task);
+    arm_v8m::load_mpu(&regs.rbar, &regs.rlar, &regs.mair);
+    forget_virtual_regions();
+    arm_v8m::set_stack_limit(task);
+}
 
-    for (i, region) in task.region_table().iter().enumerate() {
-        // This MPU requires that all regions are 32-byte aligned...in part
-        // because it stuffs extra stuff into the bottom five bits.
-        debug_assert_eq!(region.base & 0x1F, 0);
-
-        let rnr = i as u32;
-
-        let ratts = region.attributes;
-        let xn = !ratts.contains(RegionAttributes::EXECUTE);
-        // ARMv8m has less granularity than ARMv7m for privilege
-        // vs non-privilege so there's no way to say that privilege
-        // can be read write but non-privilge can only be read only
-        // This _should_ be okay?
-        let ap = if ratts.contains(RegionAttributes::WRITE) {
-            0b01 // RW by any privilege level
-        } else if ratts.contains(RegionAttributes::READ) {
-            0b11 // Read only by any privilege level
-        } else {
-            0b00 // RW by privilege code only
-        };
+/// For each MPU slot, records which of the current task's virtual regions (as
+/// an index into `TaskDesc::extra_regions`, plus one) has been loaded into it,
+/// or zero if none.
+#[cfg(any(armv7m, armv8m))]
+static VIRTUAL_SLOTS: [AtomicU32; REGIONS_PER_TASK] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU32 = AtomicU32::new(0);
+    [ZERO; REGIONS_PER_TASK]
+};
 
-        let (mair, sh) = if ratts.contains(RegionAttributes::DEVICE) {
-            // Most restrictive: device memory, outer shared.
-            (0b00000000, 0b10)
-        } else if ratts.contains(RegionAttributes::DMA) {
-            // Outer/inner non-cacheable, outer shared.
-            (0b01000100, 0b10)
-        } else {
-            let rw = u32::from(ratts.contains(RegionAttributes::READ)) << 1
-                | u32::from(ratts.contains(RegionAttributes::WRITE));
-            // write-back transient, not shared
-            (0b0100_0100 | rw | rw << 4, 0b00)
-        };
+/// Slot to consider first when next loading a virtual region, so that we cycle
+/// through the spare slots rather than thrashing one.
+#[cfg(any(armv7m, armv8m))]
+static NEXT_VIRTUAL_SLOT: AtomicU32 = AtomicU32::new(0);
 
-        // RLAR = our upper bound; note that enable (bit 0) is not set, because
-        // it's possible to hard-fault midway through region configuration if
-        // address and size are incompatible while the region is enabled.
-        let rlar = (region.base + region.size - 32) // upper bound
-            | (i as u32) << 1; // AttrIndx
+/// Notes that the MPU has been reloaded from a task's precomputed settings, so
+/// no virtual regions are loaded.
+#[cfg(any(armv7m, armv8m))]
+fn forget_virtual_regions() {
+    for slot in &VIRTUAL_SLOTS {
+        slot.store(0, Ordering::Relaxed);
+    }
+}
 
-        // RBAR = the base
-        let rbar = (xn as u32)
-            | ap << 1
-            | (sh as u32) << 3  // sharability
-            | region.base;
+/// Attempts to resolve a memory management fault at `address` in `task` by
+/// loading one of the task's virtual regions into the MPU.
+///
+/// Returns `true` if a region was loaded and the task should simply retry the
+/// access. Returns `false` if the fault is genuine: `address` isn't in any of
+/// the task's virtual regions, or the region containing it is already loaded
+/// (so the access must have been one the region doesn't permit).
+#[cfg(any(armv7m, armv8m))]
+fn load_virtual_region(task: &task::Task, address: u32) -> bool {
+    let extras = task.descriptor().extra_regions;
+    let Some(n) = extras.iter().position(|r| r.contains(address as usize))
+    else {
+        return false;
+    };
+    let tag = n as u32 + 1;
+    if VIRTUAL_SLOTS
+        .iter()
+        .any(|s| s.load(Ordering::Relaxed) == tag)
+    {
+        return false;
+    }
 
-        unsafe {
-            mpu.rnr.write(rnr);
-            mpu.rlar.write(rlar); // configure but leave disabled
-            if rnr < 4 {
-                let mut mair0 = mpu.mair[0].read();
-                mair0 |= mair << (rnr * 8);
-                mpu.mair[0].write(mair0);
-            } else {
-                let mut mair1 = mpu.mair[1].read();
-                mair1 |= mair << ((rnr - 4) * 8);
-                mpu.mair[1].write(mair1);
-            }
-            mpu.rbar.write(rbar);
-            mpu.rlar.write(rlar | 1); // enable the region
+    // Pick the next spare slot in rotation.
+    let spare = task_mpu_regs(task).spare;
+    if spare == 0 {
+        return false;
+    }
+    let mut slot = NEXT_VIRTUAL_SLOT.load(Ordering::Relaxed) as usize;
+    for _ in 0..REGIONS_PER_TASK {
+        slot = (slot + 1) % REGIONS_PER_TASK;
+        if spare & 1 << slot != 0 {
+            break;
         }
     }
+    NEXT_VIRTUAL_SLOT.store(slot as u32, Ordering::Relaxed);
+    VIRTUAL_SLOTS[slot].store(tag, Ordering::Relaxed);
 
-    unsafe {
-        enable_mpu(mpu, true);
+    let region = extras[n];
+    cfg_if::cfg_if! {
+        if #[cfg(armv8m)] {
+            arm_v8m::load_region(slot, region);
+        } else {
+            let mpu = unsafe { &*cortex_m::peripheral::MPU::PTR };
+            let (rbar, rasr) = region_registers(region);
+            unsafe {
+                mpu.rnr.write(slot as u32);
+                mpu.rasr.write(rasr); // disable while we reconfigure
+                mpu.rbar.write(rbar);
+                mpu.rasr.write(rasr | 1);
+            }
+        }
     }
+    // As in `apply_memory_protection`; we return to the task from the fault.
+    cortex_m::asm::dsb();
+    true
 }
 
 pub fn start_first_task(tick_divisor: u32, task: &mut task::Task) -> ! {
@@ -577,6 +844,41 @@ pub fn start_first_task(tick_divisor: u32, task: &mut task::Task) -> ! {
             scb.ccr.modify(|x| x | DIV_0_TRP);
         }
 
+        #[cfg(any(armv7m, armv8m))]
+        {
+            // Turn on automatic and lazy FPU state preservation. With these
+            // set, taking an exception from a task with an FPU context only
+            // reserves room for `s0`-`s15` and FPSCR in the extended frame; the
+            // registers are actually stored the first time the kernel touches
+            // the FPU, which in practice it rarely does. Tasks without an FPU
+            // context pay nothing.
+            const ASPEN: u32 = 1 << 31;
+            const LSPEN: u32 = 1 << 30;
+            let fpu = &*cortex_m::peripheral::FPU::PTR;
+            fpu.fpccr.modify(|x| x | ASPEN | LSPEN);
+        }
+
+        #[cfg(any(armv7m, armv8m))]
+        {
+            // Turn on the instruction and data caches, on cores that have them
+            // (among those we support, the Cortex-M7). Board startup code may
+            // have done this already, to speed up its own clock setup; doing
+            // it again changes nothing. The level 1 cache type in CLIDR reads
+            // as zero on cores without caches.
+            //
+            // This is safe with respect to tasks because the MPU gives memory
+            // marked `dma` a non-cacheable type, and startup has checked that
+            // no other region maps the same memory as cacheable.
+            let mut p = cortex_m::Peripherals::steal();
+            let ctype1 = p.CPUID.clidr.read() & 0b111;
+            if matches!(ctype1, 0b001 | 0b011) {
+                p.SCB.enable_icache();
+            }
+            if matches!(ctype1, 0b010 | 0b011 | 0b100) {
+                p.SCB.enable_dcache(&mut p.CPUID);
+            }
+        }
+
         // Configure the priority of all external interrupts so that they can't
         // preempt the kernel.
         let nvic = &*cortex_m::peripheral::NVIC::PTR;
@@ -625,6 +927,7 @@ pub fn start_first_task(tick_divisor: u32, task: &mut task::Task) -> ! {
         // Configure the timer.
         let syst = &*cortex_m::peripheral::SYST::PTR;
         // Program reload value.
+        TICK_DIVISOR.store(tick_divisor, Ordering::Relaxed);
         syst.rvr.write(tick_divisor - 1);
         // Clear current value.
         syst.cvr.write(0);
@@ -851,7 +1154,11 @@ cfg_if::cfg_if! {
                 mrs r12, PSP
                 @ now, store volatile registers, plus the PSP in r12, plus LR.
                 stm r2!, {{r4-r12, lr}}
-                vstm r2, {{s16-s31}}
+                @ save FPU callee-saved registers only if the task has an FPU
+                @ context, as indicated by EXC_RETURN.FType being clear.
+                tst lr, #0x10
+                it eq
+                vstmeq r2, {{s16-s31}}
 
                 @ syscall number is passed in r11. Move it into r0 to pass it as
                 @ an argument to the handler, then call the handler.
@@ -864,7 +1171,11 @@ cfg_if::cfg_if! {
                 ldr r0, [r0]
                 @ restore volatile registers, plus load PSP into r12
                 ldm r0!, {{r4-r12, lr}}
-                vldm r0, {{s16-s31}}
+                @ likewise, restore FPU registers only if the task has an FPU
+                @ context.
+                tst lr, #0x10
+                it eq
+                vldmeq r0, {{s16-s31}}
                 msr PSP, r12
 
                 @ resume
@@ -876,12 +1187,16 @@ cfg_if::cfg_if! {
                                     @ note: now barrier here because exc return
                                     @ serves as barrier
 
-                mov lr, {exc_return}    @ materialize EXC_RETURN value to
-                                        @ return into thread mode, PSP, FP on
+                @ Load the first task's EXC_RETURN, which returns into
+                @ thread mode on the PSP, with or without an FPU context
+                @ depending on the task.
+                movw r0, #:lower16:CURRENT_TASK_PTR
+                movt r0, #:upper16:CURRENT_TASK_PTR
+                ldr r0, [r0]
+                ldr lr, [r0, #(9 * 4)]
 
                 bx lr                   @ branch into user mode
             ",
-            exc_return = const EXC_RETURN_CONST,
         }
     } else {
         compile_error!("missing SVCall impl for ARM profile.");
@@ -896,7 +1211,13 @@ cfg_if::cfg_if! {
 /// pointer while you have access to `task`, and as long as the `task` being
 /// stored is actually in the task table, you'll be okay.
 pub unsafe fn set_current_task(task: &mut task::Task) {
+    task.note_scheduled();
     CURRENT_TASK_PTR.store(task, Ordering::Relaxed);
+    crate::trace::record(
+        crate::trace::TraceEvent::ContextSwitch,
+        usize::from(task.descriptor().index),
+        0,
+    );
     crate::profiling::event_context_switch(task as *mut _ as usize);
 }
 
@@ -931,39 +1252,423 @@ static TICKS: [AtomicU32; 2] = {
 pub unsafe extern "C" fn SysTick() {
     crate::profiling::event_timer_isr_enter();
     with_task_table(|tasks| {
-        // Load the time before this tick event.
-        let t0 = TICKS[0].load(Ordering::Relaxed);
-        let t1 = TICKS[1].load(Ordering::Relaxed);
-
-        // Advance the kernel's notion of time by adding 1. Laboriously.
-        let (t0, t1) = if let Some(t0p) = t0.checked_add(1) {
-            // Incrementing t0 did not roll over, no need to update t1.
-            TICKS[0].store(t0p, Ordering::Relaxed);
-            (t0p, t1)
+        // If this is the end of a tickless idle period, that brings the tick
+        // count up to date for all the ticks we skipped, this one included.
+        let stretched = exit_tickless(true);
+        account_kernel_entry(tasks);
+
+        let now = if stretched {
+            now()
         } else {
-            // Incrementing t0 overflowed. We need to also increment t1. We use
-            // normal checked addition for this, not wrapping, because this
-            // should not be able to overflow under normal operation, and would
-            // almost certainly indicate state corruption that we'd like to
-            // discover.
-            TICKS[0].store(0, Ordering::Relaxed);
-            TICKS[1].store(t1 + 1, Ordering::Relaxed);
-            (0, t1 + 1)
+            // Load the time before this tick event.
+            let t0 = TICKS[0].load(Ordering::Relaxed);
+            let t1 = TICKS[1].load(Ordering::Relaxed);
+
+            // Advance the kernel's notion of time by adding 1. Laboriously.
+            let (t0, t1) = if let Some(t0p) = t0.checked_add(1) {
+                // Incrementing t0 did not roll over, no need to update t1.
+                TICKS[0].store(t0p, Ordering::Relaxed);
+                (t0p, t1)
+            } else {
+                // Incrementing t0 overflowed. We need to also increment t1. We
+                // use normal checked addition for this, not wrapping, because
+                // this should not be able to overflow under normal operation,
+                // and would almost certainly indicate state corruption that
+                // we'd like to discover.
+                TICKS[0].store(0, Ordering::Relaxed);
+                TICKS[1].store(t1 + 1, Ordering::Relaxed);
+                (0, t1 + 1)
+            };
+            Timestamp::from([t0, t1])
         };
 
         // Process any timers.
-        let now = Timestamp::from([t0, t1]);
         let switch = task::process_timers(tasks, now);
 
+        // Release any crash-looping tasks whose restart holds have expired.
+        let switch = switch.combine(task::process_restart_holds(tasks, now));
+
+        // Give up on any IPCs that have timed out.
+        let switch = switch.combine(task::process_ipc_timeouts(tasks, now));
+
+        // Report any servers that have been sitting on a message too long.
+        let switch = switch.combine(task::process_reply_deadman(tasks, now));
+
+        // Rotate among equal-priority tasks if the current one has used up its
+        // timeslice.
+        let switch =
+            switch.combine(task::timeslice_tick(tasks, current_task_index()));
+
+        // Check on forward progress for the watchdog heartbeat.
+        crate::watchdog::tick(tasks, current_task_index());
+
         // If any timers fired, we need to defer a context switch, because the entry
         // sequence to this ISR doesn't save state correctly for efficiency.
         if switch != task::NextTask::Same {
             pend_context_switch_from_isr();
+        } else {
+            // Otherwise, if we're idle, we may be able to skip some ticks.
+            enter_tickless(tasks, now);
         }
     });
+    account_kernel_exit();
     crate::profiling::event_timer_isr_exit();
 }
 
+/// Returns the index of the current task, for use in ISRs that are not handed
+/// it by their entry sequence.
+fn current_task_index() -> usize {
+    let current = CURRENT_TASK_PTR.load(Ordering::Relaxed);
+    uassert!(!current.is_null()); // irq before kernel started?
+
+    // Safety: we're dereferencing the current task pointer, which we're
+    // trusting the rest of this module to maintain correctly. We only read the
+    // descriptor, which is never modified.
+    usize::from(unsafe { (*current).descriptor().index })
+}
+
+/// Returns the index of the current task, or `None` if the kernel hasn't
+/// started one yet. Unlike `current_task_index`, this doesn't assert, so it's
+/// usable on the way down in `fail::die`.
+pub fn try_current_task_index() -> Option<usize> {
+    let current = CURRENT_TASK_PTR.load(Ordering::Relaxed);
+    if current.is_null() {
+        return None;
+    }
+    // Safety: as in current_task_index.
+    Some(usize::from(unsafe { (*current).descriptor().index }))
+}
+
+/// Reads the fault status registers for a crash dump: CFSR, HFSR, MMFAR, and
+/// BFAR, in that order. ARMv6-M has none of these, and reports zeros.
+pub fn fault_status_registers() -> [u32; 4] {
+    cfg_if::cfg_if! {
+        if #[cfg(any(armv7m, armv8m))] {
+            // Safety: these are read-only accesses to registers with no read
+            // side effects.
+            let scb = unsafe { &*cortex_m::peripheral::SCB::PTR };
+            [
+                scb.cfsr.read(),
+                scb.hfsr.read(),
+                scb.mmfar.read(),
+                scb.bfar.read(),
+            ]
+        } else {
+            [0; 4]
+        }
+    }
+}
+
+/// Reads a free-running count of CPU cycles since the kernel started.
+///
+/// This is derived from the tick counter and the current value of the SysTick
+/// timer, so it works on all M-profile parts (including ARMv6-M, which lacks
+/// the DWT cycle counter). It's only meaningful when called from the kernel.
+pub fn cycle_count() -> u64 {
+    // Safety: we're only reading these registers.
+    let syst = unsafe { &*cortex_m::peripheral::SYST::PTR };
+    let reload = u64::from(syst.rvr.read()) + 1;
+
+    // The timer may have wrapped since the last time the SysTick ISR ran, since
+    // the ISR can't preempt kernel code. If so, the tick will be pending and
+    // we need to account for it ourselves. Sample the pending flag on either
+    // side of the timer so that we don't mix a pre-wrap timer value with a
+    // post-wrap pending flag.
+    let pending0 = cortex_m::peripheral::SCB::is_pendst_pending();
+    let mut cvr = syst.cvr.read();
+    let pending = cortex_m::peripheral::SCB::is_pendst_pending();
+    if pending != pending0 {
+        cvr = syst.cvr.read();
+    }
+
+    let ticks = u64::from(now()) + u64::from(pending);
+    let ticks = ticks - load_split(&TICK_BASE);
+    load_split(&CYCLE_BASE) + ticks * reload + (reload - 1 - u64::from(cvr))
+}
+
+/// Values of `cycle_count` and the tick count when the tick period was last
+/// changed by `set_tick_divisor`, represented like `LAST_ACCOUNTED`. Cycles
+/// are counted from there at the current period.
+static CYCLE_BASE: [AtomicU32; 2] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU32 = AtomicU32::new(0);
+    [ZERO; 2]
+};
+static TICK_BASE: [AtomicU32; 2] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU32 = AtomicU32::new(0);
+    [ZERO; 2]
+};
+
+/// Reads the high-resolution monotonic clock: `cycle_count`, along with the
+/// number of cycles in each tick.
+pub fn monotonic_time() -> abi::MonotonicTime {
+    abi::MonotonicTime {
+        cycles: cycle_count(),
+        cycles_per_tick: TICK_DIVISOR.load(Ordering::Relaxed),
+        tick_hz: crate::startup::HUBRIS_TICK_HZ,
+    }
+}
+
+/// Number of SysTick counts in a kernel tick. The timer reloads with this
+/// (minus one), except during tickless idle, when the reload is stretched.
+static TICK_DIVISOR: AtomicU32 = AtomicU32::new(0);
+
+/// Set while the SysTick period has been stretched for tickless idle.
+static TICKLESS: AtomicBool = AtomicBool::new(false);
+
+/// The tick count and `cycle_count` when the current tickless idle period
+/// began, represented like `LAST_ACCOUNTED`.
+static TICKLESS_START_TICK: [AtomicU32; 2] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU32 = AtomicU32::new(0);
+    [ZERO; 2]
+};
+static TICKLESS_START_CYCLES: [AtomicU32; 2] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU32 = AtomicU32::new(0);
+    [ZERO; 2]
+};
+
+/// Number of cycles of the tick at `TICKLESS_START_TICK` that had already
+/// passed when the timer was stretched.
+static TICKLESS_PHASE: AtomicU32 = AtomicU32::new(0);
+
+/// Loads `syst` with a period of `period` counts, starting now, and waits for
+/// the timer to pick it up. Until it does, the counter reads as zero, which
+/// would confuse `cycle_count`. That takes one count, since we run SysTick
+/// from the CPU clock.
+fn restart_systick(
+    syst: &cortex_m::peripheral::syst::RegisterBlock,
+    period: u32,
+) {
+    // Safety: we're the kernel, and the timer is ours.
+    unsafe {
+        syst.rvr.write(period - 1);
+        syst.cvr.write(0);
+    }
+    while syst.cvr.read() == 0 {}
+}
+
+/// If the application has enabled tickless idle and the idle task is
+/// running, stretches the current SysTick period to skip ticks until the
+/// next thing the timer ISR has to do (or as many as the timer can count), so
+/// that an idle CPU isn't woken every tick. `exit_tickless` puts the timer
+/// back.
+///
+/// This should be called from the end of the SysTick ISR, once it has done
+/// everything else; waking the idle task from sleep is what we're avoiding.
+fn enter_tickless(tasks: &[task::Task], now: Timestamp) {
+    let Some(idle) = crate::startup::HUBRIS_TICKLESS_IDLE else {
+        return;
+    };
+    if current_task_index() != idle
+        || TICKLESS.load(Ordering::Relaxed)
+        || cortex_m::peripheral::SCB::is_pendst_pending()
+    {
+        return;
+    }
+
+    let ticks = match task::next_deadline(tasks) {
+        Some(d) => u64::from(d).saturating_sub(u64::from(now)),
+        None => u64::MAX,
+    };
+    // Safety: we're only reading this register.
+    let syst = unsafe { &*cortex_m::peripheral::SYST::PTR };
+    let divisor = TICK_DIVISOR.load(Ordering::Relaxed);
+    // Counts left until the end of the current tick.
+    let remaining = syst.cvr.read() + 1;
+    let max = (MAX_TICK_DIVISOR - remaining) / divisor + 1;
+    let ticks = ticks.min(u64::from(max)) as u32;
+    if ticks < 2 {
+        return;
+    }
+
+    // Start counting from here, so that `cycle_count` carries on smoothly at
+    // the new period. The period ends where the `ticks`th tick from now would
+    // have, give or take the few cycles it takes to get it going.
+    let cycles = cycle_count();
+    store_split(&TICKLESS_START_CYCLES, cycles);
+    store_split(&TICKLESS_START_TICK, u64::from(now));
+    TICKLESS_PHASE.store(divisor - remaining, Ordering::Relaxed);
+    store_split(&CYCLE_BASE, cycles);
+    store_split(&TICK_BASE, u64::from(now));
+    restart_systick(syst, remaining + (ticks - 1) * divisor);
+    TICKLESS.store(true, Ordering::Relaxed);
+}
+
+/// Ends a tickless idle period, if one is in progress: counts the ticks that
+/// were skipped, and puts the SysTick timer back to a period of one tick,
+/// keeping its phase. Returns `true` if a period was ended.
+///
+/// `expired` should be `true` when called from the SysTick ISR, meaning the
+/// stretched period has run out. In that case the tick count includes the
+/// tick that's ending, and the ISR shouldn't count it again. Otherwise, if the
+/// period ran out just before we got here, the tick is left pending for the
+/// ISR to count as usual.
+fn exit_tickless(expired: bool) -> bool {
+    if !TICKLESS.load(Ordering::Relaxed) {
+        return false;
+    }
+    TICKLESS.store(false, Ordering::Relaxed);
+
+    // Safety: we're only reading this register, for now.
+    let syst = unsafe { &*cortex_m::peripheral::SYST::PTR };
+    let divisor = TICK_DIVISOR.load(Ordering::Relaxed);
+    let period = syst.rvr.read() + 1;
+
+    // As in `cycle_count`, sample the pending flag on either side of the
+    // counter so that we know which side of a wrap the value is from.
+    let pending0 = cortex_m::peripheral::SCB::is_pendst_pending();
+    let mut cvr = syst.cvr.read();
+    let pending = cortex_m::peripheral::SCB::is_pendst_pending();
+    if pending != pending0 {
+        cvr = syst.cvr.read();
+    }
+    let wrapped = expired || pending;
+
+    // Counts since the period began, and since the start of the tick it began
+    // in.
+    let elapsed = u64::from(period - 1 - cvr)
+        + if wrapped { u64::from(period) } else { 0 };
+    let total = u64::from(TICKLESS_PHASE.load(Ordering::Relaxed)) + elapsed;
+    let ticks = total / u64::from(divisor);
+    let rem = (total % u64::from(divisor)) as u32;
+    let tick = load_split(&TICKLESS_START_TICK) + ticks;
+    store_split(&TICKS, if pending { tick - 1 } else { tick });
+
+    // Finish the current tick on time, then carry on as normal. A period of
+    // one count would stop the timer, so in that unlikely case the tick runs
+    // a count long.
+    restart_systick(syst, (divisor - rem).max(2));
+    // Safety: we're the kernel, and the timer is ours.
+    unsafe {
+        syst.rvr.write(divisor - 1);
+    }
+
+    // Rebase `cycle_count` on the start of the current tick, which a pending
+    // tick counts as having begun.
+    store_split(
+        &CYCLE_BASE,
+        load_split(&TICKLESS_START_CYCLES) + elapsed - u64::from(rem),
+    );
+    store_split(&TICK_BASE, tick);
+    true
+}
+
+/// Moves the tick count forward by `ticks`, for time that passed with the
+/// SysTick timer stopped (in a low-power state, say).
+pub fn skip_ticks(ticks: u64) {
+    store_split(&TICKS, load_split(&TICKS) + ticks);
+}
+
+/// Sleeps until an interrupt is pending. This is meant for the kernel, where
+/// interrupts can't preempt us: `WFI` only wakes for an interrupt that could
+/// be taken, but with `SEVONPEND` set, any interrupt becoming pending is an
+/// event that wakes `WFE`.
+pub fn wait_for_pending_interrupt() {
+    // Safety: we're the kernel, and setting SEVONPEND only adds wakeups.
+    unsafe {
+        let scb = &*cortex_m::peripheral::SCB::PTR;
+        scb.scr.modify(|x| x | 1 << 4);
+    }
+    // Clear the event register, which may be set from some earlier event.
+    cortex_m::asm::sev();
+    cortex_m::asm::wfe();
+    // An interrupt that was already pending won't generate an event, so only
+    // sleep if there isn't one. One arriving after this check still will.
+    if pending_irq().is_none()
+        && !cortex_m::peripheral::SCB::is_pendst_pending()
+    {
+        cortex_m::asm::wfe();
+    }
+}
+
+/// Returns the lowest-numbered hardware interrupt that is both enabled and
+/// pending, if there is one.
+pub fn pending_irq() -> Option<u32> {
+    // Safety: we're only reading these registers.
+    let nvic = unsafe { &*cortex_m::peripheral::NVIC::PTR };
+    cfg_if::cfg_if! {
+        if #[cfg(armv6m)] {
+            let blocks = 1;
+        } else {
+            // Safety: as above.
+            let icb = unsafe { &*cortex_m::peripheral::ICB::PTR };
+            let blocks = (icb.ictr.read() as usize & 0xF) + 1;
+        }
+    }
+    (0..blocks).find_map(|i| {
+        let bits = nvic.ispr[i].read() & nvic.iser[i].read();
+        (bits != 0).then(|| i as u32 * 32 + bits.trailing_zeros())
+    })
+}
+
+/// Cycle count at the most recent kernel entry or exit, used to divide CPU time
+/// between tasks and the kernel.
+///
+/// Like `TICKS`, this is a pair of `AtomicU32` that is only accessed from
+/// contexts that can't be preempted. `[0]` is the least significant part.
+static LAST_ACCOUNTED: [AtomicU32; 2] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU32 = AtomicU32::new(0);
+    [ZERO; 2]
+};
+
+/// Total cycles spent in the kernel since boot, represented like
+/// `LAST_ACCOUNTED`.
+static KERNEL_CYCLES: [AtomicU32; 2] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU32 = AtomicU32::new(0);
+    [ZERO; 2]
+};
+
+fn load_split(x: &[AtomicU32; 2]) -> u64 {
+    u64::from(x[0].load(Ordering::Relaxed))
+        | u64::from(x[1].load(Ordering::Relaxed)) << 32
+}
+
+fn store_split(x: &[AtomicU32; 2], v: u64) {
+    x[0].store(v as u32, Ordering::Relaxed);
+    x[1].store((v >> 32) as u32, Ordering::Relaxed);
+}
+
+/// Charges the cycles elapsed since the last kernel exit to the current task.
+/// This should be called early on every entry into the kernel.
+pub fn account_kernel_entry(tasks: &mut [task::Task]) {
+    // Anything that brings us into the kernel ends a tickless idle period, so
+    // that the tick count is right for whatever we're about to do.
+    exit_tickless(false);
+    let now = cycle_count();
+    let last = load_split(&LAST_ACCOUNTED);
+    store_split(&LAST_ACCOUNTED, now);
+    tasks[current_task_index()].charge_cpu_time(now.wrapping_sub(last));
+}
+
+/// Charges the cycles elapsed since the last kernel entry to the kernel. This
+/// should be called late on every exit from the kernel.
+pub fn account_kernel_exit() {
+    let now = cycle_count();
+    let last = load_split(&LAST_ACCOUNTED);
+    store_split(&LAST_ACCOUNTED, now);
+    let total = load_split(&KERNEL_CYCLES);
+    store_split(&KERNEL_CYCLES, total.wrapping_add(now.wrapping_sub(last)));
+}
+
+/// Returns the total number of cycles spent in the kernel since boot.
+pub fn kernel_cpu_time() -> u64 {
+    load_split(&KERNEL_CYCLES)
+}
+
+/// Pets the hardware watchdog using the routine provided by the board setup
+/// code in `kern::watchdog::configure_watchdog`. If no routine was provided,
+/// this does nothing.
+pub fn pet_watchdog() {
+    if let Some(wd) = crate::watchdog::table() {
+        (wd.pet)()
+    }
+}
+
 fn pend_context_switch_from_isr() {
     // This sets the bit to pend a PendSV interrupt. PendSV will happen after
     // the current ISR (and any chained ISRs) returns, and perform the context
@@ -1036,7 +1741,11 @@ cfg_if::cfg_if! {
                 mrs r12, PSP
                 @ now, store volatile registers, plus the PSP in r12, plus LR.
                 stm r1!, {{r4-r12, lr}}
-                vstm r1, {{s16-s31}}
+                @ save FPU callee-saved registers only if the task has an FPU
+                @ context, as indicated by EXC_RETURN.FType being clear.
+                tst lr, #0x10
+                it eq
+                vstmeq r1, {{s16-s31}}
 
                 bl pendsv_entry
 
@@ -1046,7 +1755,11 @@ cfg_if::cfg_if! {
                 ldr r0, [r0]
                 @ restore volatile registers, plus load PSP into r12
                 ldm r0!, {{r4-r12, lr}}
-                vldm r0, {{s16-s31}}
+                @ likewise, restore FPU registers only if the task has an FPU
+                @ context.
+                tst lr, #0x10
+                it eq
+                vldmeq r0, {{s16-s31}}
                 msr PSP, r12
 
                 @ resume
@@ -1072,6 +1785,7 @@ unsafe extern "C" fn pendsv_entry() {
     let current = usize::from(unsafe { (*current).descriptor().index });
 
     with_task_table(|tasks| {
+        account_kernel_entry(tasks);
         let next = task::select(current, tasks);
         let next = &mut tasks[next];
         apply_memory_protection(next);
@@ -1081,6 +1795,7 @@ unsafe extern "C" fn pendsv_entry() {
             set_current_task(next);
         }
     });
+    account_kernel_exit();
     crate::profiling::event_secondary_syscall_exit();
 }
 
@@ -1120,18 +1835,23 @@ pub unsafe extern "C" fn DefaultHandler() {
         x if x >= 16 => {
             // Hardware interrupt
             let irq_num = exception_num - 16;
-            let owner = crate::startup::HUBRIS_IRQ_TASK_LOOKUP
-                .get(abi::InterruptNum(irq_num))
+            let owner = crate::irq::owner(irq_num)
                 .unwrap_or_else(|| panic!("unhandled IRQ {irq_num}"));
 
             let switch = with_task_table(|tasks| {
+                account_kernel_entry(tasks);
                 disable_irq(irq_num);
+                crate::trace::record(
+                    crate::trace::TraceEvent::Irq,
+                    owner.task as usize,
+                    irq_num,
+                );
 
                 // Now, post the notification and return the
                 // scheduling hint.
-                let n = task::NotificationSet(owner.notification);
-                tasks[owner.task as usize].post(n)
+                task::deliver_irq(tasks, irq_num, &owner)
             });
+            account_kernel_exit();
             if switch {
                 pend_context_switch_from_isr()
             }
@@ -1252,7 +1972,10 @@ global_asm! {"
 
         @ Restore volatile registers, plus load PSP into r12
         ldm r0!, {{r4-r12, lr}}
-        vldm r0, {{s16-s31}}
+        @ Restore FPU registers only if the task has an FPU context.
+        tst lr, #0x10
+        it eq
+        vldmeq r0, {{s16-s31}}
         msr PSP, r12
 
         @ resume
@@ -1374,6 +2097,44 @@ bitflags::bitflags! {
     }
 }
 
+/// Fills in the parts of a `MemoryFaultDetail` that need the faulting task's
+/// state: the faulting PC, from its exception frame (unless `stackinvalid`
+/// says we can't trust it), and the region nearest the faulting address.
+#[cfg(any(armv7m, armv8m))]
+fn complete_memory_fault(
+    task: &task::Task,
+    mut detail: MemoryFaultDetail,
+    stackinvalid: bool,
+) -> MemoryFaultDetail {
+    if !stackinvalid {
+        if let Ok(frame) =
+            USlice::<BaseExceptionFrame>::from_raw(task.save().psp as usize, 1)
+        {
+            detail.pc = task.try_read(&frame).ok().map(|f| f[0].pc);
+        }
+    }
+
+    if let Some(address) = detail.address {
+        let stack_top = task.descriptor().initial_stack as usize;
+        detail.nearest_region = task
+            .region_table()
+            .iter()
+            .min_by_key(|region| {
+                if address < region.base {
+                    region.base - address
+                } else {
+                    address.saturating_sub(region.end_addr().wrapping_sub(1))
+                }
+            })
+            .map(|region| NearestRegion {
+                base: region.base,
+                size: region.size,
+                stack: region.contains(stack_top.saturating_sub(4)),
+            });
+    }
+    detail
+}
+
 /// Rust entry point for fault.
 ///
 /// # Safety
@@ -1455,6 +2216,7 @@ unsafe extern "C" fn handle_fault(task: *mut task::Task) {
 
     // We are now going to force a fault on our current task and directly
     // switch to a task to run.
+    exit_tickless(false);
     with_task_table(|tasks| {
         let next = match task::force_fault(tasks, idx, fault) {
             task::NextTask::Specific(i) => i,
@@ -1544,6 +2306,29 @@ unsafe extern "C" fn handle_fault(
         );
     }
 
+    // A simple data access fault might just mean the task touched one of its
+    // virtual regions that isn't currently loaded. If so, load it and let the
+    // task retry. (Stacking faults are never this: stacks are always resident.)
+    if matches!(fault_type, FaultType::MemoryManagement)
+        && cfsr.contains(Cfsr::DACCVIOL | Cfsr::MMARVALID)
+        && !cfsr.intersects(Cfsr::MSTKERR | Cfsr::MUNSTKERR | Cfsr::MLSPERR)
+    {
+        // Safety: as above, we use the task reference briefly and discard it.
+        let loaded = unsafe { load_virtual_region(&*task, scb.mmfar.read()) };
+        if loaded {
+            // Safety: write-one-to-clear, as below; and as below, the exit
+            // path will reload the task's FPU registers from `fpsave` if it
+            // has an FPU context, so they need to be stored there first.
+            unsafe {
+                scb.cfsr.write(cfsr.bits());
+                if exc_return & 1 << 4 == 0 {
+                    arch::asm!("vstm {0}, {{s16-s31}}", in(reg) fpsave);
+                }
+            }
+            return;
+        }
+    }
+
     // Okay, now that we're confident we came from a task, we need to deal with
     // the case where the fault is **derived.** In ARMvX-M jargon, a derived
     // fault is one produced by attempting to handle a different exception or
@@ -1592,31 +2377,63 @@ unsafe extern "C" fn handle_fault(
             } else if cfsr.contains(Cfsr::IACCVIOL) {
                 (FaultInfo::IllegalText, false)
             } else {
+                let access = if cfsr.contains(Cfsr::MUNSTKERR) {
+                    MemoryAccessKind::Read
+                } else if cfsr.contains(Cfsr::MLSPERR) {
+                    MemoryAccessKind::Write
+                } else {
+                    MemoryAccessKind::Data
+                };
                 (
-                    FaultInfo::MemoryAccess {
+                    FaultInfo::MemoryFault(MemoryFaultDetail {
+                        kind: MemoryFaultKind::Protection,
                         address: if cfsr.contains(Cfsr::MMARVALID) {
                             Some(scb.mmfar.read())
                         } else {
                             None
                         },
-                        source: FaultSource::User,
-                    },
+                        access,
+                        pc: None,
+                        nearest_region: None,
+                    }),
                     false,
                 )
             }
         }
 
-        FaultType::BusFault => (
-            FaultInfo::BusError {
-                address: if cfsr.contains(Cfsr::BFARVALID) {
-                    Some(scb.bfar.read())
-                } else {
-                    None
-                },
-                source: FaultSource::User,
-            },
-            false,
-        ),
+        FaultType::BusFault => {
+            let access = if cfsr.contains(Cfsr::IBUSERR) {
+                MemoryAccessKind::Execute
+            } else if cfsr.intersects(Cfsr::STKERR | Cfsr::LSPERR) {
+                MemoryAccessKind::Write
+            } else if cfsr.contains(Cfsr::UNSTKERR) {
+                MemoryAccessKind::Read
+            } else {
+                MemoryAccessKind::Data
+            };
+            (
+                FaultInfo::MemoryFault(MemoryFaultDetail {
+                    kind: MemoryFaultKind::Bus,
+                    address: if cfsr.contains(Cfsr::BFARVALID) {
+                        Some(scb.bfar.read())
+                    } else {
+                        None
+                    },
+                    access,
+                    pc: None,
+                    nearest_region: None,
+                }),
+                false,
+            )
+        }
+
+        // On ARMv8-M, the stack limit check catches overflow before the MPU
+        // does. As with MSTKERR, the frame may not have been stacked, so don't
+        // trust the stack.
+        #[cfg(armv8m)]
+        FaultType::UsageFault if cfsr.contains(Cfsr::STKOF) => {
+            (FaultInfo::StackOverflow { address: psp }, true)
+        }
 
         FaultType::UsageFault => (
             if cfsr.contains(Cfsr::DIVBYZERO) {
@@ -1654,13 +2471,18 @@ unsafe extern "C" fn handle_fault(
     }
 
     // It's safe to store our floating point registers; store them now to
-    // preserve as much state as possible for debugging.
+    // preserve as much state as possible for debugging. We only do this if the
+    // task has an FPU context (EXC_RETURN.FType clear): otherwise the registers
+    // hold some other task's values, and the restore path won't load them back
+    // anyway.
     //
     // Safety: asm! is always unsafe, obvs, but in this case as long as fpsave
     // points to a correctly aligned area large enough to store 16 floats -- a
     // property our caller is required to ensure -- this is ok.
-    unsafe {
-        arch::asm!("vstm {0}, {{s16-s31}}", in(reg) fpsave);
+    if exc_return & 1 << 4 == 0 {
+        unsafe {
+            arch::asm!("vstm {0}, {{s16-s31}}", in(reg) fpsave);
+        }
     }
 
     // We are now going to force a fault on our current task and directly
@@ -1669,7 +2491,14 @@ unsafe extern "C" fn handle_fault(
     // PSP:  even with PendSV pending, ARMv8-M will generate a MUNSTKERR
     // when returning from an exception with a PSP that generates an MPU
     // fault!)
+    exit_tickless(false);
     with_task_table(|tasks| {
+        let fault = match fault {
+            FaultInfo::MemoryFault(detail) => FaultInfo::MemoryFault(
+                complete_memory_fault(&tasks[idx], detail, stackinvalid),
+            ),
+            other => other,
+        };
         let next = match task::force_fault(tasks, idx, fault) {
             task::NextTask::Specific(i) => i,
             task::NextTask::Other => task::select(idx, tasks),
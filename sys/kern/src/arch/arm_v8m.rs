@@ -0,0 +1,156 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Support specific to ARMv8-M Mainline (Cortex-M33 and friends: the LPC55,
+//! STM32L5, and so on).
+//!
+//! Most of what the kernel needs from an M-profile core is the same from
+//! ARMv6-M through ARMv8-M, and lives in `arm_m`. This module holds the parts
+//! that ARMv8-M does differently, which `arm_m` calls into:
+//!
+//! - The MPU describes a region by base and limit (`RBAR`/`RLAR`), with its
+//!   memory type held separately in the `MAIR` registers, instead of by a
+//!   power-of-two size and inline attributes as on ARMv7-M. Regions need only
+//!   be 32-byte aligned.
+//! - The stack limit registers. We point `PSPLIM` at the bottom of each task's
+//!   stack region, so the processor itself reports a stack overflow (as a
+//!   `STKOF` UsageFault) before the task's stack pointer leaves it.
+//!
+//! # Security state
+//!
+//! The kernel runs in whichever security state it was booted into, and never
+//! touches the SAU. We rely on the SAU/IDAU (as configured by the boot ROM or a
+//! bootloader) classifying all task memory in that same state; the MPU and
+//! stack limit registers we write are the banked ones for the current state.
+//! Tasks run in the same state too, which `arm_m`'s `EXC_RETURN_CONST` bakes
+//! in. Running tasks in the non-secure state under a secure kernel would need
+//! changes there as well as here.
+
+use core::arch;
+
+use crate::descs::{RegionAttributes, RegionDesc};
+use crate::task;
+use armv8_m_mpu::{disable_mpu, enable_mpu};
+
+/// Computes the `RBAR` and `RLAR` values (the latter without the enable bit)
+/// for `region` in MPU slot `slot`, along with its memory attribute byte.
+pub fn region_registers(slot: usize, region: &RegionDesc) -> (u32, u32, u32) {
+    // This MPU requires that all regions are 32-byte aligned...in part because
+    // it stuffs extra stuff into the bottom five bits.
+    debug_assert_eq!(region.base & 0x1F, 0);
+
+    let ratts = region.attributes;
+    let xn = !ratts.contains(RegionAttributes::EXECUTE);
+    // ARMv8m has less granularity than ARMv7m for privilege vs non-privilege
+    // so there's no way to say that privilege can be read write but
+    // non-privilge can only be read only This _should_ be okay?
+    let ap = if ratts.contains(RegionAttributes::WRITE) {
+        0b01 // RW by any privilege level
+    } else if ratts.contains(RegionAttributes::READ) {
+        0b11 // Read only by any privilege level
+    } else {
+        0b00 // RW by privilege code only
+    };
+
+    let (mair, sh) = if ratts.contains(RegionAttributes::DEVICE) {
+        // Device-nGnRE, to match what we use on ARMv7-M: accesses aren't
+        // gathered or reordered, but writes may be buffered. (Shareability is
+        // ignored for device memory, which is always outer shared.)
+        (0b0000_0100, 0b10)
+    } else if ratts.contains(RegionAttributes::DMA) {
+        // Outer/inner non-cacheable, outer shared.
+        (0b01000100, 0b10)
+    } else {
+        let rw = u32::from(ratts.contains(RegionAttributes::READ)) << 1
+            | u32::from(ratts.contains(RegionAttributes::WRITE));
+        // write-back transient, not shared
+        (0b0100_0100 | rw | rw << 4, 0b00)
+    };
+
+    // RLAR = our upper bound
+    let rlar = (region.base + region.size - 32) // upper bound
+        | (slot as u32) << 1; // AttrIndx
+
+    // RBAR = the base
+    let rbar = (xn as u32)
+        | ap << 1
+        | (sh as u32) << 3  // sharability
+        | region.base;
+
+    (rbar, rlar, mair)
+}
+
+/// Loads a whole set of precomputed region settings into the MPU, as produced
+/// by `region_registers`, one slot per entry.
+pub fn load_mpu(rbar: &[u32], rlar: &[u32], mair: &[u32; 2]) {
+    let mpu = unsafe {
+        // At least by not taking a &mut we're confident we're not violating
+        // aliasing....
+        &*cortex_m::peripheral::MPU::PTR
+    };
+    // `disable_mpu` starts with the barrier that lets our stores under the old
+    // memory types finish first.
+    unsafe {
+        disable_mpu(mpu);
+    }
+
+    unsafe {
+        mpu.mair[0].write(mair[0]);
+        mpu.mair[1].write(mair[1]);
+    }
+    for (i, (&rbar, &rlar)) in rbar.iter().zip(rlar).enumerate() {
+        unsafe {
+            mpu.rnr.write(i as u32);
+            mpu.rlar.write(rlar); // configure but leave disabled
+            mpu.rbar.write(rbar);
+            mpu.rlar.write(rlar | 1); // enable the region
+        }
+    }
+
+    unsafe {
+        enable_mpu(mpu, true);
+    }
+}
+
+/// Replaces whatever is in MPU slot `slot` with `region`, leaving the other
+/// slots alone. The caller is responsible for the barrier afterwards.
+pub fn load_region(slot: usize, region: &RegionDesc) {
+    let mpu = unsafe { &*cortex_m::peripheral::MPU::PTR };
+    let (rbar, rlar, mair) = region_registers(slot, region);
+    let (reg, shift) = (slot / 4, (slot % 4) * 8);
+    unsafe {
+        mpu.rnr.write(slot as u32);
+        mpu.rlar.write(rlar); // disable while we reconfigure
+        mpu.mair[reg].modify(|m| m & !(0xFF << shift) | mair << shift);
+        mpu.rbar.write(rbar);
+        mpu.rlar.write(rlar | 1);
+    }
+}
+
+/// Programs `PSPLIM` with the bottom of `task`'s stack region, so that the
+/// processor itself catches the task's stack pointer moving past it (as a
+/// `STKOF` UsageFault) -- including while stacking an exception frame, which
+/// the MPU alone reports much less precisely.
+///
+/// If we can't find the stack region, the limit is set to zero, which disables
+/// the check.
+pub fn set_stack_limit(task: &task::Task) {
+    let initial_stack = task.descriptor().initial_stack as usize;
+    let limit = task
+        .region_table()
+        .iter()
+        .find(|region| region.contains(initial_stack.saturating_sub(4)))
+        .map(|region| region.base)
+        .unwrap_or(0);
+    // Safety: this only affects the process stack, which the kernel doesn't
+    // use, and the limit is below the task's current stack pointer unless the
+    // task has already overflowed.
+    unsafe {
+        arch::asm!(
+            "msr PSPLIM, {}",
+            in(reg) limit,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+}
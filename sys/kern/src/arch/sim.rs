@@ -0,0 +1,919 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Architecture support for running the kernel as a process on a host OS.
+//!
+//! This is for trying out application logic and IPC patterns on a desktop
+//! machine, under a normal debugger, without hardware. It's an approximation:
+//! nothing here is cycle-accurate, and memory protection is much coarser than
+//! an MPU's. Like the kernel as a whole, it needs a 32-bit target, such as
+//! `i686-unknown-linux-gnu`, so that addresses fit in the ABI's `u32`s.
+//!
+//! # Tasks
+//!
+//! Each task runs on its own host thread, but only one thread runs at a time:
+//! the rest wait until `RUNNING` names their task. Task code is linked into
+//! the same process, and the task's `entry_point` is the address of an
+//! `extern "C" fn() -> !`. The task's thread runs on the part of its stack
+//! region below `initial_stack`, so that buffers on the stack are in task
+//! memory as far as the kernel's access checks are concerned, but a task's
+//! statics live in the host image and so can't be lent or sent.
+//!
+//! A thread is started the first time its task is scheduled. When a task is
+//! restarted, its old thread exits without unwinding the next time it wakes,
+//! and a new one is started in its place.
+//!
+//! # Syscalls
+//!
+//! Tasks make syscalls by calling `hubris_sim_syscall` with the syscall number
+//! and eight registers' worth of arguments, which come back holding the
+//! results. The kernel runs on the calling thread.
+//!
+//! # Preemption and time
+//!
+//! The thread that calls `start_kernel` becomes the timer. Each tick, it takes
+//! the kernel over, interrupting the current task's thread with `SIGUSR1` and
+//! waiting until it has stopped, then runs the tick and delivers any pending
+//! interrupts. Interrupts are pended by host code standing in for hardware,
+//! with `pend_software_irq`.
+//!
+//! With the `replay` feature, a log loaded with `replay::load` takes the place
+//! of the timing: scheduling decisions follow the log, and the interrupts it
+//! records are delivered at the same points, until the simulation does
+//! something the log doesn't.
+//!
+//! The "cycle counter" counts host time at the rate given to `set_clock_freq`.
+//!
+//! # Memory protection
+//!
+//! Task regions are mapped at their addresses from the app's memory map when
+//! the kernel starts, and `mprotect` stands in for the MPU: while a task runs,
+//! only its own regions are accessible, and its access to them is as its
+//! region table says. A fault on one of them is delivered to the task as a
+//! `MemoryAccess` fault. The kernel itself opens everything up while it runs.
+//!
+//! There are limits to this. Regions that aren't page-aligned are left open.
+//! Stack regions are left open too, since a thread needs its stack while it
+//! waits its turn. And a region that overlaps memory the host has already
+//! mapped -- the executable's text, say -- is taken to describe host memory,
+//! and left alone.
+
+use std::cell::Cell;
+use std::sync::atomic::{
+    AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicUsize, Ordering,
+};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::atomic::AtomicExt;
+use crate::descs::{RegionAttributes, RegionDesc};
+use crate::startup::{with_task_table, HUBRIS_TASK_COUNT};
+use crate::task;
+use crate::time::Timestamp;
+use abi::{FaultInfo, FaultSource, InterruptNum};
+
+macro_rules! uassert {
+    ($cond : expr) => {
+        if !$cond {
+            panic!("Assertion failed!");
+        }
+    };
+}
+
+/// Pointer to the current task, as on the real architectures.
+#[no_mangle]
+static CURRENT_TASK_PTR: AtomicPtr<task::Task> =
+    AtomicPtr::new(core::ptr::null_mut());
+
+/// To allow our clock frequency to be easily determined from a debugger, we
+/// store it in memory.
+#[no_mangle]
+static CLOCK_FREQ_KHZ: AtomicU32 = AtomicU32::new(0);
+
+/// Number of "cycles" per kernel tick, set by `start_first_task`.
+static TICK_DIVISOR: AtomicU32 = AtomicU32::new(0);
+
+/// Host time at which the kernel started, from which the cycle count runs.
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// Held by whichever thread is running the kernel.
+static KERNEL_LOCK: AtomicBool = AtomicBool::new(false);
+
+/// Index of the task whose thread may run, or `NOBODY` while the kernel does.
+static RUNNING: AtomicUsize = AtomicUsize::new(NOBODY);
+const NOBODY: usize = usize::MAX;
+
+/// What each task's thread is up to, as one of the `MODE_*` values.
+static MODES: [AtomicU32; HUBRIS_TASK_COUNT] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const PARKED: AtomicU32 = AtomicU32::new(MODE_PARKED);
+    [PARKED; HUBRIS_TASK_COUNT]
+};
+/// Running task code, and liable to be interrupted.
+const MODE_USER: u32 = 0;
+/// Waiting for `RUNNING` to name its task.
+const MODE_PARKED: u32 = 1;
+/// Entering the kernel of its own accord, by syscall or fault.
+const MODE_TRAP: u32 = 2;
+
+/// Bumped each time a task is reinitialized, so that its old thread knows to
+/// exit.
+static GENERATIONS: [AtomicU32; HUBRIS_TASK_COUNT] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU32 = AtomicU32::new(0);
+    [ZERO; HUBRIS_TASK_COUNT]
+};
+
+/// Each task's thread, as a `pthread_t`, or 0 if it needs starting.
+static THREADS: [AtomicUsize; HUBRIS_TASK_COUNT] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NONE: AtomicUsize = AtomicUsize::new(0);
+    [NONE; HUBRIS_TASK_COUNT]
+};
+
+/// Regions we've mapped, for `apply_memory_protection` to work through, and
+/// whether each is a task's stack.
+static MAPPED_REGIONS: Mutex<Vec<(&'static RegionDesc, bool)>> =
+    Mutex::new(Vec::new());
+
+/// Interrupts, numbered 0 to 63, that are enabled and pending.
+static IRQ_ENABLED: AtomicU64 = AtomicU64::new(0);
+static IRQ_PENDING: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// The task this thread runs, and its generation, if it's a task thread.
+    static THIS_TASK: Cell<Option<(usize, u32)>> = const { Cell::new(None) };
+}
+
+/// Syscall registers. Arguments and results share the same slots, as they do
+/// on the real architectures.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct SavedState {
+    regs: [u32; 8],
+    nr: u32,
+}
+
+/// Map the registers to (architecture-independent) syscall argument and return
+/// slots.
+impl task::ArchState for SavedState {
+    fn stack_pointer(&self) -> u32 {
+        0
+    }
+
+    /// Reads syscall argument register 0.
+    fn arg0(&self) -> u32 {
+        self.regs[0]
+    }
+    fn arg1(&self) -> u32 {
+        self.regs[1]
+    }
+    fn arg2(&self) -> u32 {
+        self.regs[2]
+    }
+    fn arg3(&self) -> u32 {
+        self.regs[3]
+    }
+    fn arg4(&self) -> u32 {
+        self.regs[4]
+    }
+    fn arg5(&self) -> u32 {
+        self.regs[5]
+    }
+    fn arg6(&self) -> u32 {
+        self.regs[6]
+    }
+
+    fn syscall_descriptor(&self) -> u32 {
+        self.nr
+    }
+
+    /// Writes syscall return argument 0.
+    fn ret0(&mut self, x: u32) {
+        self.regs[0] = x
+    }
+    fn ret1(&mut self, x: u32) {
+        self.regs[1] = x
+    }
+    fn ret2(&mut self, x: u32) {
+        self.regs[2] = x
+    }
+    fn ret3(&mut self, x: u32) {
+        self.regs[3] = x
+    }
+    fn ret4(&mut self, x: u32) {
+        self.regs[4] = x
+    }
+    fn ret5(&mut self, x: u32) {
+        self.regs[5] = x
+    }
+    fn ret6(&mut self, x: u32) {
+        self.regs[6] = x
+    }
+    fn ret7(&mut self, x: u32) {
+        self.regs[7] = x
+    }
+}
+
+pub unsafe fn set_clock_freq(cycles_per_ms: u32) {
+    CLOCK_FREQ_KHZ.store(cycles_per_ms, Ordering::Relaxed);
+}
+
+/// Largest tick divisor we can use; the host timer doesn't care.
+pub const MAX_TICK_DIVISOR: u32 = u32::MAX;
+
+/// Changes the number of cycles in a kernel tick. This takes effect from the
+/// next tick.
+pub fn set_tick_divisor(tick_divisor: u32) {
+    TICK_DIVISOR.store(tick_divisor, Ordering::Relaxed);
+}
+
+/// There's no cache to maintain on behalf of DMA that doesn't exist.
+pub fn dcache_maintain(_op: abi::CacheOp, _base: u32, _len: u32) {}
+
+pub fn reinitialize(task: &mut task::Task) {
+    *task.save_mut() = SavedState::default();
+    let index = usize::from(task.descriptor().index);
+    GENERATIONS[index].fetch_add(1, Ordering::Release);
+    // The old thread, if any, is waiting its turn, and will exit when it sees
+    // the new generation. It has to be gone before `leave_kernel` starts
+    // another on the same stack.
+    let thread = THREADS[index].swap(0, Ordering::Relaxed);
+    if thread != 0 {
+        // Safety: the thread is ours, and nobody else joins it.
+        unsafe {
+            libc::pthread_join(
+                thread as libc::pthread_t,
+                core::ptr::null_mut(),
+            );
+        }
+    }
+}
+
+/// Host stacks aren't watermarked, so we can't say.
+pub fn stack_high_water_mark(_task: &task::Task) -> Option<u32> {
+    None
+}
+
+/// Maps `task`'s regions into our address space at their addresses from the
+/// memory map, unless something's already there.
+///
+/// # Safety
+///
+/// This must only be called during startup, before any task has been
+/// scheduled.
+pub unsafe fn precompute_memory_protection(task: &task::Task) {
+    let mut mapped = MAPPED_REGIONS.lock().unwrap();
+    let desc = task.descriptor();
+    for &region in desc.regions.iter().chain(desc.extra_regions) {
+        if region.size == 0
+            || mapped.iter().any(|(r, _)| core::ptr::eq(*r, region))
+        {
+            continue;
+        }
+        // Safety: MAP_FIXED_NOREPLACE won't disturb anything that's already
+        // mapped; it fails instead, which tells us the region is host memory.
+        let p = unsafe {
+            libc::mmap(
+                region.base as usize as *mut libc::c_void,
+                region.size as usize,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE
+                    | libc::MAP_ANONYMOUS
+                    | libc::MAP_FIXED_NOREPLACE,
+                -1,
+                0,
+            )
+        };
+        if p == region.base as usize as *mut libc::c_void {
+            let is_stack = desc
+                .initial_stack
+                .checked_sub(4)
+                .is_some_and(|sp| region.contains(sp as usize));
+            mapped.push((region, is_stack));
+        } else if p != libc::MAP_FAILED {
+            // An older host kernel, which took the address as a hint.
+            // Safety: we just mapped this, and nothing refers to it.
+            unsafe {
+                libc::munmap(p, region.size as usize);
+            }
+        }
+    }
+}
+
+/// Restricts access to task memory to `task`'s regions, on the terms its region
+/// table gives.
+pub fn apply_memory_protection(task: &task::Task) {
+    let desc = task.descriptor();
+    for &(region, is_stack) in MAPPED_REGIONS.lock().unwrap().iter() {
+        let prot = if is_stack {
+            libc::PROT_READ | libc::PROT_WRITE
+        } else if !desc
+            .regions
+            .iter()
+            .chain(desc.extra_regions)
+            .any(|r| core::ptr::eq(*r, region))
+        {
+            libc::PROT_NONE
+        } else {
+            let a = region.attributes;
+            let mut prot = libc::PROT_NONE;
+            if a.contains(RegionAttributes::READ) {
+                prot |= libc::PROT_READ;
+            }
+            if a.contains(RegionAttributes::WRITE) {
+                prot |= libc::PROT_WRITE;
+            }
+            if a.contains(RegionAttributes::EXECUTE) {
+                prot |= libc::PROT_EXEC;
+            }
+            prot
+        };
+        protect(region, prot);
+    }
+}
+
+/// Opens up all task memory, for the kernel's use.
+fn unprotect_all() {
+    for &(region, _) in MAPPED_REGIONS.lock().unwrap().iter() {
+        protect(region, libc::PROT_READ | libc::PROT_WRITE);
+    }
+}
+
+fn protect(region: &RegionDesc, prot: libc::c_int) {
+    let page = page_size();
+    if region.base as usize % page != 0 || region.size as usize % page != 0 {
+        return;
+    }
+    // Safety: the region is one we mapped, and the kernel doesn't keep
+    // references into task memory between entries.
+    unsafe {
+        libc::mprotect(
+            region.base as usize as *mut libc::c_void,
+            region.size as usize,
+            prot,
+        );
+    }
+}
+
+fn page_size() -> usize {
+    // Safety: sysconf has no preconditions.
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+pub fn start_first_task(tick_divisor: u32, task: &mut task::Task) -> ! {
+    TICK_DIVISOR.store(tick_divisor, Ordering::Relaxed);
+    START.get_or_init(Instant::now);
+    install_signal_handlers();
+
+    enter_kernel(None);
+    // Safety: task is in the task table, and we never return to use it again.
+    unsafe {
+        set_current_task(task);
+    }
+    leave_kernel();
+
+    // This thread is the timer from now on.
+    loop {
+        std::thread::sleep(tick_period());
+        timer_tick();
+    }
+}
+
+/// Real time between kernel ticks.
+fn tick_period() -> Duration {
+    let khz = u64::from(CLOCK_FREQ_KHZ.load(Ordering::Relaxed).max(1));
+    let divisor = u64::from(TICK_DIVISOR.load(Ordering::Relaxed));
+    Duration::from_nanos(divisor * 1_000_000 / khz)
+}
+
+/// Takes the kernel over, stopping the current task's thread if it's running
+/// task code, and opens up task memory.
+///
+/// A task's thread trapping into the kernel passes its task and generation as
+/// `from`. If the timer got in first and switched away from the task, the
+/// thread waits for its turn to come round again before entering.
+fn enter_kernel(from: Option<(usize, u32)>) {
+    loop {
+        while KERNEL_LOCK.swap(true, Ordering::Acquire) {
+            if let Some((index, generation)) = from {
+                exit_if_restarted(index, generation);
+            }
+            std::thread::yield_now();
+        }
+        match from {
+            Some((index, generation))
+                if try_current_task_index() != Some(index) =>
+            {
+                KERNEL_LOCK.store(false, Ordering::Release);
+                park(index, generation);
+            }
+            _ => break,
+        }
+    }
+    RUNNING.store(NOBODY, Ordering::Relaxed);
+    if let Some(current) = try_current_task_index() {
+        if MODES[current].load(Ordering::Acquire) == MODE_USER {
+            let thread = THREADS[current].load(Ordering::Relaxed);
+            // Safety: a thread in MODE_USER is alive, and SIGUSR1 only parks
+            // it.
+            unsafe {
+                libc::pthread_kill(thread as libc::pthread_t, libc::SIGUSR1);
+            }
+            while MODES[current].load(Ordering::Acquire) == MODE_USER {
+                std::thread::yield_now();
+            }
+        }
+    }
+    unprotect_all();
+}
+
+/// Hands the CPU to whichever task is current, starting its thread if needed.
+fn leave_kernel() {
+    #[cfg(feature = "replay")]
+    deliver_replayed_irqs();
+
+    let current = CURRENT_TASK_PTR.load(Ordering::Relaxed);
+    uassert!(!current.is_null());
+    // Safety: we hold the kernel lock, so nothing else is using the task.
+    let task = unsafe { &*current };
+    let index = usize::from(task.descriptor().index);
+    apply_memory_protection(task);
+    if THREADS[index].load(Ordering::Relaxed) == 0 {
+        start_thread(task);
+    }
+    RUNNING.store(index, Ordering::Release);
+    KERNEL_LOCK.store(false, Ordering::Release);
+}
+
+/// Delivers any interrupts that the replay log being played back says come
+/// next, as though they'd arrived just as the kernel was returning to a task.
+#[cfg(feature = "replay")]
+fn deliver_replayed_irqs() {
+    while let Some(n) = crate::replay::next_irq() {
+        let Some(owner) = crate::irq::owner(n) else {
+            break;
+        };
+        let current = current_task_index();
+        with_task_table(|tasks| {
+            disable_irq(n);
+            if task::deliver_irq(tasks, n, &owner) {
+                let next = task::select(current, tasks);
+                // Safety: next is in the task table, and we don't touch it
+                // again.
+                unsafe {
+                    set_current_task(&mut tasks[next]);
+                }
+            }
+        });
+    }
+}
+
+/// Waits until task `index` may run. If the task has been reinitialized since
+/// this thread started, the thread exits instead.
+fn park(index: usize, generation: u32) {
+    loop {
+        exit_if_restarted(index, generation);
+        if RUNNING.load(Ordering::Acquire) == index {
+            return;
+        }
+        let pause = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 20_000,
+        };
+        // Safety: nanosleep is async-signal-safe, which matters since we're
+        // often called from a signal handler.
+        unsafe {
+            libc::nanosleep(&pause, core::ptr::null_mut());
+        }
+    }
+}
+
+/// Ends the calling thread, if task `index` has been reinitialized since the
+/// thread started.
+fn exit_if_restarted(index: usize, generation: u32) {
+    if GENERATIONS[index].load(Ordering::Acquire) != generation {
+        // Safety: nothing on this thread will run again, and its stack is
+        // about to be reused; SYS_exit ends the thread without unwinding or
+        // running destructors, which would touch both.
+        unsafe {
+            libc::syscall(libc::SYS_exit, 0);
+        }
+    }
+}
+
+/// Arguments for a new task thread.
+struct ThreadStart {
+    index: usize,
+    generation: u32,
+    entry_point: u32,
+}
+
+fn start_thread(task: &task::Task) {
+    let desc = task.descriptor();
+    let index = usize::from(desc.index);
+    let stack_top = desc.initial_stack as usize;
+    let stack = task
+        .region_table()
+        .iter()
+        .find(|region| region.contains(stack_top.saturating_sub(4)))
+        .unwrap_or_else(|| panic!("task {index} has no stack region"));
+    let start = Box::new(ThreadStart {
+        index,
+        generation: GENERATIONS[index].load(Ordering::Relaxed),
+        entry_point: desc.entry_point,
+    });
+
+    // Safety: the stack region is task memory that only this thread will use,
+    // and the thread takes ownership of `start`.
+    unsafe {
+        let mut attr = core::mem::zeroed::<libc::pthread_attr_t>();
+        libc::pthread_attr_init(&mut attr);
+        let r = libc::pthread_attr_setstack(
+            &mut attr,
+            stack.base as usize as *mut libc::c_void,
+            stack_top - stack.base as usize,
+        );
+        if r != 0 {
+            panic!("task {index}'s stack is too small for a host thread");
+        }
+        let mut thread = 0;
+        let r = libc::pthread_create(
+            &mut thread,
+            &attr,
+            task_thread,
+            Box::into_raw(start).cast(),
+        );
+        uassert!(r == 0);
+        libc::pthread_attr_destroy(&mut attr);
+        THREADS[index].store(thread as usize, Ordering::Relaxed);
+    }
+}
+
+extern "C" fn task_thread(arg: *mut libc::c_void) -> *mut libc::c_void {
+    // Safety: `start_thread` gave us this box.
+    let start = unsafe { Box::from_raw(arg.cast::<ThreadStart>()) };
+    THIS_TASK.with(|t| t.set(Some((start.index, start.generation))));
+    park(start.index, start.generation);
+    MODES[start.index].store(MODE_USER, Ordering::Release);
+
+    // Safety: the app promises that a task's entry point is one of these.
+    let entry: extern "C" fn() -> ! =
+        unsafe { core::mem::transmute(start.entry_point as usize) };
+    entry()
+}
+
+/// Entry point for syscalls from task code. `regs` holds the arguments on the
+/// way in and the results on the way out.
+///
+/// # Safety
+///
+/// This must be called from a task's thread, with a valid `regs`.
+#[no_mangle]
+pub unsafe extern "C" fn hubris_sim_syscall(nr: u32, regs: *mut [u32; 8]) {
+    let (index, generation) = THIS_TASK
+        .with(Cell::get)
+        .unwrap_or_else(|| panic!("syscall from outside a task"));
+    MODES[index].store(MODE_TRAP, Ordering::Release);
+    enter_kernel(Some((index, generation)));
+
+    let current = CURRENT_TASK_PTR.load(Ordering::Relaxed);
+    // Safety: we hold the kernel lock, so nothing else is using the task, and
+    // our caller vouches for `regs`.
+    unsafe {
+        let save = (*current).save_mut();
+        save.regs = *regs;
+        save.nr = nr;
+        crate::syscalls::syscall_entry(nr, current);
+    }
+
+    MODES[index].store(MODE_PARKED, Ordering::Release);
+    leave_kernel();
+    park(index, generation);
+    MODES[index].store(MODE_USER, Ordering::Release);
+
+    // We're current again, so the kernel is done with our results.
+    let current = CURRENT_TASK_PTR.load(Ordering::Relaxed);
+    // Safety: as above.
+    unsafe {
+        *regs = (*current).save().regs;
+    }
+}
+
+fn install_signal_handlers() {
+    // Safety: these handlers are written to cope with being called at any
+    // point in task code.
+    unsafe {
+        let mut action = core::mem::zeroed::<libc::sigaction>();
+        action.sa_sigaction =
+            preempt_handler as extern "C" fn(libc::c_int) as usize;
+        libc::sigaction(libc::SIGUSR1, &action, core::ptr::null_mut());
+
+        let mut action = core::mem::zeroed::<libc::sigaction>();
+        action.sa_sigaction = fault_handler
+            as extern "C" fn(
+                libc::c_int,
+                *mut libc::siginfo_t,
+                *mut libc::c_void,
+            ) as usize;
+        action.sa_flags = libc::SA_SIGINFO;
+        libc::sigaction(libc::SIGSEGV, &action, core::ptr::null_mut());
+        libc::sigaction(libc::SIGBUS, &action, core::ptr::null_mut());
+    }
+}
+
+/// Parks a task's thread that the kernel has taken the CPU from.
+extern "C" fn preempt_handler(_sig: libc::c_int) {
+    let Some((index, generation)) = THIS_TASK.with(Cell::get) else {
+        return;
+    };
+    // If the thread is already on its way into the kernel, let it carry on.
+    if MODES[index]
+        .compare_exchange(
+            MODE_USER,
+            MODE_PARKED,
+            Ordering::AcqRel,
+            Ordering::Relaxed,
+        )
+        .is_ok()
+    {
+        park(index, generation);
+        MODES[index].store(MODE_USER, Ordering::Release);
+    }
+}
+
+/// Turns a memory fault in task code into a Hubris fault.
+extern "C" fn fault_handler(
+    sig: libc::c_int,
+    info: *mut libc::siginfo_t,
+    _context: *mut libc::c_void,
+) {
+    let task = THIS_TASK.with(Cell::get);
+    let user = task.is_some_and(|(index, _)| {
+        MODES[index].load(Ordering::Acquire) == MODE_USER
+    });
+    let Some((index, generation)) = task.filter(|_| user) else {
+        // This is a fault in the kernel or the host, which we can't recover
+        // from. Put back the default action and return, to fault again.
+        // Safety: restoring the default handler has no preconditions.
+        unsafe {
+            libc::signal(sig, libc::SIG_DFL);
+        }
+        return;
+    };
+    // Safety: the OS gives us a valid siginfo.
+    let address = unsafe { (*info).si_addr() } as usize as u32;
+
+    MODES[index].store(MODE_TRAP, Ordering::Release);
+    enter_kernel(Some((index, generation)));
+    with_task_table(|tasks| {
+        account_kernel_entry(tasks);
+        let fault = FaultInfo::MemoryAccess {
+            address: Some(address),
+            source: FaultSource::User,
+        };
+        let next = match task::force_fault(tasks, index, fault) {
+            task::NextTask::Specific(i) => i,
+            task::NextTask::Other => task::select(index, tasks),
+            task::NextTask::Same => index,
+        };
+        if next == index {
+            panic!("attempt to return to Task #{index} after fault");
+        }
+        // Safety: next is in the task table, and we don't touch it again.
+        unsafe {
+            set_current_task(&mut tasks[next]);
+        }
+    });
+    account_kernel_exit();
+    MODES[index].store(MODE_PARKED, Ordering::Release);
+    leave_kernel();
+
+    // The faulting instruction can't be retried, so the only way out is a
+    // restart, on a new thread.
+    loop {
+        park(index, generation);
+    }
+}
+
+/// Records the address of `task` as the current user task.
+///
+/// # Safety
+///
+/// This records a pointer that aliases `task`. As long as you don't read that
+/// pointer while you have access to `task`, and as long as the `task` being
+/// stored is actually in the task table, you'll be okay.
+pub unsafe fn set_current_task(task: &mut task::Task) {
+    task.note_scheduled();
+    CURRENT_TASK_PTR.store(task, Ordering::Relaxed);
+    crate::trace::record(
+        crate::trace::TraceEvent::ContextSwitch,
+        usize::from(task.descriptor().index),
+        0,
+    );
+    crate::profiling::event_context_switch(task as *mut _ as usize);
+}
+
+/// Returns the index of the current task.
+fn current_task_index() -> usize {
+    let current = try_current_task_index();
+    uassert!(current.is_some()); // trap before kernel started?
+    current.unwrap_or_default()
+}
+
+/// Returns the index of the current task, or `None` if the kernel hasn't
+/// started one yet.
+pub fn try_current_task_index() -> Option<usize> {
+    let current = CURRENT_TASK_PTR.load(Ordering::Relaxed);
+    if current.is_null() {
+        return None;
+    }
+    // Safety: we only read the descriptor, which is never modified.
+    Some(usize::from(unsafe { (*current).descriptor().index }))
+}
+
+/// There are no fault status registers to report.
+pub fn fault_status_registers() -> [u32; 4] {
+    [0; 4]
+}
+
+/// Reads the tick counter.
+pub fn now() -> Timestamp {
+    Timestamp::from(TICKS.load(Ordering::Relaxed))
+}
+
+/// Kernel global for tracking the current timestamp, measured in ticks.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Moves the tick count forward by `ticks`, for time that passed with the
+/// timer stopped (in a low-power state, say).
+pub fn skip_ticks(ticks: u64) {
+    TICKS.fetch_add(ticks, Ordering::Relaxed);
+}
+
+/// Sleeps until an interrupt is pending, or for a tick, whichever is sooner.
+/// The timer can't run while we hold the kernel, so this is as long as it's
+/// sensible to wait.
+pub fn wait_for_pending_interrupt() {
+    let deadline = Instant::now() + tick_period();
+    while pending_irq().is_none() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_micros(20));
+    }
+}
+
+/// Returns the lowest-numbered enabled interrupt that's pending, if any.
+pub fn pending_irq() -> Option<u32> {
+    let ready = IRQ_PENDING.load(Ordering::Relaxed)
+        & IRQ_ENABLED.load(Ordering::Relaxed);
+    (ready != 0).then(|| ready.trailing_zeros())
+}
+
+/// Runs a kernel tick, and delivers any interrupts that have been pended.
+fn timer_tick() {
+    crate::profiling::event_timer_isr_enter();
+    enter_kernel(None);
+    let current = current_task_index();
+    with_task_table(|tasks| {
+        account_kernel_entry(tasks);
+
+        let now = Timestamp::from(TICKS.fetch_add(1, Ordering::Relaxed) + 1);
+        let mut switch = task::process_timers(tasks, now)
+            .combine(task::process_restart_holds(tasks, now))
+            .combine(task::process_ipc_timeouts(tasks, now))
+            .combine(task::process_reply_deadman(tasks, now))
+            .combine(task::timeslice_tick(tasks, current));
+
+        crate::watchdog::tick(tasks, current);
+
+        while let Some(n) = pending_irq() {
+            let owner = crate::irq::owner(n)
+                .unwrap_or_else(|| panic!("unhandled IRQ {n}"));
+            disable_irq(n);
+            IRQ_PENDING.fetch_and(!(1 << n), Ordering::Relaxed);
+            crate::trace::record(
+                crate::trace::TraceEvent::Irq,
+                owner.task as usize,
+                n,
+            );
+            if task::deliver_irq(tasks, n, &owner) {
+                switch = switch.combine(task::NextTask::Other);
+            }
+        }
+
+        if switch != task::NextTask::Same {
+            let next = match switch {
+                task::NextTask::Specific(i) => i,
+                _ => task::select(current, tasks),
+            };
+            // Safety: next is in the task table, and we don't touch it again.
+            unsafe {
+                set_current_task(&mut tasks[next]);
+            }
+        }
+    });
+    account_kernel_exit();
+    leave_kernel();
+    crate::profiling::event_timer_isr_exit();
+}
+
+/// Reads the high-resolution monotonic clock, which is the cycle count.
+pub fn monotonic_time() -> abi::MonotonicTime {
+    abi::MonotonicTime {
+        cycles: cycle_count(),
+        cycles_per_tick: TICK_DIVISOR.load(Ordering::Relaxed),
+        tick_hz: crate::startup::HUBRIS_TICK_HZ,
+    }
+}
+
+/// Counts host time since the kernel started, in cycles at the clock rate given
+/// to `set_clock_freq`.
+pub fn cycle_count() -> u64 {
+    let elapsed = START.get_or_init(Instant::now).elapsed().as_nanos();
+    let khz = u128::from(CLOCK_FREQ_KHZ.load(Ordering::Relaxed));
+    (elapsed * khz / 1_000_000) as u64
+}
+
+/// Cycle count at the most recent kernel entry or exit.
+static LAST_ACCOUNTED: AtomicU64 = AtomicU64::new(0);
+
+/// Total cycles spent in the kernel since boot.
+static KERNEL_CYCLES: AtomicU64 = AtomicU64::new(0);
+
+/// Charges the cycles elapsed since the last kernel exit to the current task.
+/// This should be called early on every entry into the kernel.
+pub fn account_kernel_entry(tasks: &mut [task::Task]) {
+    let now = cycle_count();
+    let last = LAST_ACCOUNTED.swap(now, Ordering::Relaxed);
+    tasks[current_task_index()].charge_cpu_time(now.wrapping_sub(last));
+}
+
+/// Charges the cycles elapsed since the last kernel entry to the kernel. This
+/// should be called late on every exit from the kernel.
+pub fn account_kernel_exit() {
+    let now = cycle_count();
+    let last = LAST_ACCOUNTED.swap(now, Ordering::Relaxed);
+    KERNEL_CYCLES.fetch_add(now.wrapping_sub(last), Ordering::Relaxed);
+}
+
+/// Returns the total number of cycles spent in the kernel since boot.
+pub fn kernel_cpu_time() -> u64 {
+    KERNEL_CYCLES.load(Ordering::Relaxed)
+}
+
+/// Pets the watchdog routine provided by the board setup code, if any.
+pub fn pet_watchdog() {
+    if let Some(wd) = crate::watchdog::table() {
+        (wd.pet)()
+    }
+}
+
+pub fn disable_irq(n: u32) {
+    uassert!(n < 64);
+    IRQ_ENABLED.fetch_and(!(1 << n), Ordering::Relaxed);
+}
+
+pub fn enable_irq(n: u32) {
+    uassert!(n < 64);
+    IRQ_ENABLED.fetch_or(1 << n, Ordering::Relaxed);
+}
+
+/// Returns a cross-platform representation of an interrupt's status.
+pub fn irq_status(n: u32) -> abi::IrqStatus {
+    uassert!(n < 64);
+    let mut status = abi::IrqStatus::empty();
+    status.set(
+        abi::IrqStatus::ENABLED,
+        IRQ_ENABLED.load(Ordering::Relaxed) & (1 << n) != 0,
+    );
+    status.set(
+        abi::IrqStatus::PENDING,
+        IRQ_PENDING.load(Ordering::Relaxed) & (1 << n) != 0,
+    );
+    status
+}
+
+/// Pends an interrupt, to be delivered on the next tick. Host code simulating
+/// hardware can call this from any thread.
+pub fn pend_software_irq(InterruptNum(n): InterruptNum) {
+    uassert!(n < 64);
+    IRQ_PENDING.fetch_or(1 << n, Ordering::Relaxed);
+}
+
+/// There's no system to reset, so this ends the process.
+pub fn reset() -> ! {
+    std::process::exit(0)
+}
+
+impl AtomicExt for AtomicBool {
+    type Primitive = bool;
+
+    #[inline(always)]
+    fn swap_polyfill(
+        &self,
+        value: Self::Primitive,
+        ordering: Ordering,
+    ) -> Self::Primitive {
+        self.swap(value, ordering)
+    }
+}
@@ -42,6 +42,13 @@ pub struct TaskDesc {
     /// (This is why we use pointers into a table, to avoid making many copies
     /// of that region.)
     pub regions: [&'static RegionDesc; REGIONS_PER_TASK],
+    /// Additional regions this task has access to, beyond those that fit in
+    /// `regions`. These are only present for tasks built with virtual regions,
+    /// and are loaded into the MPU on demand: when the task takes a memory
+    /// fault on one of them, the kernel swaps it into one of the slots that
+    /// `regions` leaves as the null region, and resumes the task. Like
+    /// `regions`, these are sorted by base address.
+    pub extra_regions: &'static [&'static RegionDesc],
     /// Address of the task's entry point. This is the first instruction that
     /// will be executed whenever the task is (re)started. It must be within one
     /// of the task's memory regions (the kernel *will* check this).
@@ -66,6 +73,39 @@ pub struct TaskDesc {
     /// The index is a u16 to save space in the `TaskDesc` struct; in practice
     /// other factors limit us to fewer than `2**16` tasks.
     pub index: u16,
+    /// Number of asynchronous messages that can be queued for this task. The
+    /// storage for them is carved out of kernel RAM at startup.
+    pub mailbox_depth: u8,
+    /// Parts of the task's RAM that the kernel initializes whenever the task
+    /// is (re)started, before it runs its first instruction: typically its
+    /// `.data` section, copied from flash, and its `.bss`, zeroed. Each range
+    /// must be word-aligned and lie within one of the task's writable regions
+    /// (the kernel *will* check this).
+    pub ram_init: &'static [RamInit],
+}
+
+/// One range of task RAM to initialize at (re)start.
+#[derive(Copy, Clone, Debug)]
+pub struct RamInit {
+    /// Address of the first byte to initialize.
+    pub base: u32,
+    /// Number of bytes to initialize; a multiple of 4.
+    pub size: u32,
+    /// What to fill it with.
+    pub fill: RamFill,
+}
+
+/// Contents for a `RamInit` range.
+#[derive(Copy, Clone, Debug)]
+pub enum RamFill {
+    /// Fill with zeros, as for `.bss`.
+    Zero,
+    /// Fill every word with the given pattern, which can make uses of
+    /// uninitialized memory easier to spot.
+    Pattern(u32),
+    /// Copy from an image of the same size at `source`, which the task must be
+    /// able to read, as for `.data`.
+    Copy { source: u32 },
 }
 
 bitflags::bitflags! {
@@ -73,7 +113,22 @@ bitflags::bitflags! {
     #[repr(transparent)]
     pub struct TaskFlags: u8 {
         const START_AT_BOOT = 1 << 0;
-        const RESERVED = !1;
+        /// The task uses the floating-point unit, and needs an FPU context
+        /// (extended exception frame and saved `s16`-`s31`) from the start.
+        /// Tasks without this flag begin with the smaller, integer-only
+        /// context.
+        const USES_FPU = 1 << 1;
+        /// The task may read every task's state with the
+        /// `read_task_snapshot` kipc, like the supervisor can.
+        const MONITOR = 1 << 2;
+        /// The task may read the boot measurements with the
+        /// `read_boot_measurements` kipc, like the supervisor can.
+        const ATTESTER = 1 << 3;
+        /// The task owns the clock tree, and may tell the kernel about
+        /// changes to the clock driving its timer with the
+        /// `set_tick_divisor` kipc, as the supervisor can.
+        const CLOCK_OWNER = 1 << 4;
+        const RESERVED = !0b1_1111;
     }
 }
 
@@ -161,18 +216,26 @@ bitflags::bitflags! {
         const WRITE = 1 << 1;
         /// Region can contain executable code for tasks that include it.
         const EXECUTE = 1 << 2;
-        /// Region contains memory mapped registers. This affects cache behavior
-        /// on devices that include it, and discourages the kernel from using
-        /// `memcpy` in the region.
+        /// Region contains memory mapped registers. It's mapped as device
+        /// memory (Device-nGnRE, or Shareable Device on ARMv7-M), which is
+        /// never cached and whose accesses are never merged or reordered, and
+        /// the kernel won't lend it out or `memcpy` in it.
         const DEVICE = 1 << 3;
         /// Region can be used for DMA or communication with other processors.
-        /// This heavily restricts how this memory can be cached and will hurt
-        /// performance if overused.
+        /// It's mapped as shareable normal memory that isn't cached, which
+        /// will hurt performance if overused.
         ///
         /// This is ignored for `DEVICE` memory, which is already not cached.
         const DMA = 1 << 4;
-
-        const RESERVED = !((1 << 5) - 1);
+        /// Region keeps its contents across restarts of the task that owns
+        /// it, so that (say) a driver can remember link statistics, or that
+        /// it has already set up its hardware, after a crash. The kernel
+        /// zeroes it once, at boot, and never again. It must be writable
+        /// normal memory, and none of the task's `ram_init` ranges may
+        /// overlap it.
+        const STICKY = 1 << 5;
+
+        const RESERVED = !((1 << 6) - 1);
     }
 }
 
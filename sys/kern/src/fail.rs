@@ -18,18 +18,118 @@
 //!   this buffer (as UTF-8) as possible, truncating if the buffer fills. The
 //!   number of bytes written isn't recorded anywhere; instead, for printing,
 //!   trim off any trailing NUL bytes.
+//!
+//! - `kern::fail::KERNEL_BOOT_FAULT` is a `BootFaultRecord`. It lives in
+//!   uninitialized RAM, so it is not cleared by reset. If the kernel refuses to
+//!   boot a malformed image, it fills this in with the reason before halting;
+//!   the record is valid only if its `magic` field is `BOOT_FAULT_MAGIC`. The
+//!   next boot can collect (and clear) it with `take_previous_boot_fault`.
+//!
+//! - `kern::fail::KERNEL_CRASH_DUMP` is a `CrashDump`, also in uninitialized
+//!   RAM. The `die` routine fills it in with a summary of the task table, the
+//!   current task's saved registers, the architecture's fault status
+//!   registers, and a copy of the epitaph. It is valid only if its `magic`
+//!   field is `CRASH_DUMP_MAGIC` and its `crc` matches the CRC-32 (IEEE) of its
+//!   `body`. The next boot can collect (and clear) it with
+//!   `take_previous_crash_dump`.
 
+use core::mem::MaybeUninit;
 #[cfg(not(feature = "nano"))]
 use core::{
     fmt::{Display, Write},
     sync::atomic::Ordering,
 };
 
+use crate::startup::BootError;
+#[cfg(not(feature = "nano"))]
+use crate::startup::HUBRIS_TASK_COUNT;
+#[cfg(not(feature = "nano"))]
+use abi::{SchedState, TaskState};
+
 /// Flag that gets set to `true` by all failure reporting functions, giving
 /// tools a one-stop-shop for doing kernel triage.
 #[used]
 static mut KERNEL_HAS_FAILED: bool = false;
 
+/// Value of `BootFaultRecord::magic` that indicates a valid record.
+pub const BOOT_FAULT_MAGIC: u32 = 0xB007_FA17;
+
+/// Structured description of why the kernel refused to boot, for consumption
+/// by tools and by the next boot.
+///
+/// The `reason`, `index`, and `field` are the encoded forms of a `BootError`;
+/// see `BootError::reason_code` and friends.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(C)]
+pub struct BootFaultRecord {
+    /// `BOOT_FAULT_MAGIC` if this record is valid; anything else otherwise.
+    pub magic: u32,
+    /// Which check failed.
+    pub reason: u32,
+    /// Index of the offending descriptor, for reasons that have one.
+    pub index: u32,
+    /// Which field of the offending descriptor was bad, or 0. For header
+    /// errors, the offending format or feature bits.
+    pub field: u32,
+}
+
+/// Record of the last boot failure. This is deliberately placed in `.uninit`
+/// so that it survives the reset that follows a failed boot.
+#[used]
+#[link_section = ".uninit.KERNEL_BOOT_FAULT"]
+static mut KERNEL_BOOT_FAULT: MaybeUninit<BootFaultRecord> =
+    MaybeUninit::uninit();
+
+/// Records `err` in `KERNEL_BOOT_FAULT` and halts.
+pub(crate) fn boot_failed(err: BootError) -> ! {
+    let record = BootFaultRecord {
+        magic: BOOT_FAULT_MAGIC,
+        reason: err.reason_code(),
+        index: err.index(),
+        field: err.field(),
+    };
+    // Safety: we're in `start_kernel`, which has no concurrency and is only
+    // called once, so nothing else can be accessing this. We write it
+    // volatile so that it can't be discarded as dead.
+    unsafe {
+        core::ptr::addr_of_mut!(KERNEL_BOOT_FAULT)
+            .cast::<BootFaultRecord>()
+            .write_volatile(record);
+    }
+
+    #[cfg(not(feature = "nano"))]
+    die(format_args!("boot failed: {err:?}"));
+
+    #[cfg(feature = "nano")]
+    panic!();
+}
+
+/// Returns the boot failure recorded before the most recent reset, if any,
+/// clearing it in the process.
+///
+/// This is intended to be called from an application's `main` before
+/// `start_kernel`, e.g. to report a failed update.
+///
+/// # Safety
+///
+/// This must not be called concurrently with itself or with `start_kernel`.
+pub unsafe fn take_previous_boot_fault() -> Option<BootFaultRecord> {
+    let p =
+        core::ptr::addr_of_mut!(KERNEL_BOOT_FAULT).cast::<BootFaultRecord>();
+    // Safety: the record is made of plain integers, so any bit pattern left
+    // over from before reset is a valid (if meaningless) value, which the magic
+    // check weeds out. Our caller has promised exclusive access.
+    let record = unsafe { p.read_volatile() };
+    if record.magic != BOOT_FAULT_MAGIC {
+        return None;
+    }
+    // Safety: as above.
+    unsafe {
+        core::ptr::addr_of_mut!((*p).magic).write_volatile(0);
+    }
+    Some(record)
+}
+
 #[cfg(not(feature = "nano"))]
 const EPITAPH_LEN: usize = 128;
 
@@ -65,6 +165,210 @@ fn begin_epitaph() -> &'static mut [u8; EPITAPH_LEN] {
     unsafe { &mut *core::ptr::addr_of_mut!(KERNEL_EPITAPH) }
 }
 
+/// Value of `CrashDump::magic` that indicates a valid dump.
+#[cfg(not(feature = "nano"))]
+pub const CRASH_DUMP_MAGIC: u32 = 0xDEAD_D0D0;
+
+/// Layout version of `CrashDump`, bumped on incompatible changes so that tools
+/// can tell what they're looking at.
+#[cfg(not(feature = "nano"))]
+pub const CRASH_DUMP_VERSION: u32 = 1;
+
+/// Number of words of the current task's saved registers we keep. This is
+/// enough for the `SavedState` of every architecture we support; any excess is
+/// zero.
+#[cfg(not(feature = "nano"))]
+pub const CRASH_DUMP_SAVE_WORDS: usize = 32;
+
+/// Compact record of the kernel's state when it died.
+#[cfg(not(feature = "nano"))]
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct CrashDump {
+    /// `CRASH_DUMP_MAGIC` if this dump is valid; anything else otherwise.
+    pub magic: u32,
+    /// `CRASH_DUMP_VERSION` at the time the dump was written.
+    pub version: u32,
+    /// Size of `body` in bytes.
+    pub body_len: u32,
+    /// CRC-32 (IEEE) of `body`.
+    pub crc: u32,
+    pub body: CrashDumpBody,
+}
+
+/// The checksummed part of a `CrashDump`.
+#[cfg(not(feature = "nano"))]
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct CrashDumpBody {
+    /// Index of the task that was current, or `u32::MAX` if none was.
+    pub current_task: u32,
+    /// Number of valid entries in `tasks`; zero if the kernel died before the
+    /// task table was set up.
+    pub task_count: u32,
+    /// Architecture fault status registers; see
+    /// `arch::fault_status_registers` for which.
+    pub fault_status: [u32; 4],
+    /// The current task's `SavedState`, as raw words.
+    pub current_save: [u32; CRASH_DUMP_SAVE_WORDS],
+    pub tasks: [CrashDumpTask; HUBRIS_TASK_COUNT],
+    /// The epitaph, as in `KERNEL_EPITAPH`.
+    pub epitaph: [u8; EPITAPH_LEN],
+}
+
+/// Summary of one task in a `CrashDump`.
+#[cfg(not(feature = "nano"))]
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct CrashDumpTask {
+    /// Scheduling state: 0 stopped, 1 runnable, 2 sending, 3 awaiting reply,
+    /// 4 receiving, 5 faulted.
+    pub state: u8,
+    pub priority: u8,
+    pub generation: u8,
+    /// For states that involve a peer (2-4), its task index; otherwise 0xFF.
+    pub peer: u8,
+}
+
+/// The crash dump, in `.uninit` so that it survives the reset that usually
+/// follows a kernel failure.
+#[cfg(not(feature = "nano"))]
+#[used]
+#[link_section = ".uninit.KERNEL_CRASH_DUMP"]
+static mut KERNEL_CRASH_DUMP: MaybeUninit<CrashDump> = MaybeUninit::uninit();
+
+/// Fills in `KERNEL_CRASH_DUMP` from the current kernel state and `epitaph`.
+///
+/// This runs after the kernel has already failed, so it takes care not to
+/// panic.
+#[cfg(not(feature = "nano"))]
+fn write_crash_dump(epitaph: &[u8; EPITAPH_LEN]) {
+    let mut body = CrashDumpBody {
+        current_task: u32::MAX,
+        task_count: 0,
+        fault_status: crate::arch::fault_status_registers(),
+        current_save: [0; CRASH_DUMP_SAVE_WORDS],
+        tasks: [CrashDumpTask::default(); HUBRIS_TASK_COUNT],
+        epitaph: *epitaph,
+    };
+
+    // Safety: we're dying, and will never touch the task table again, so
+    // looking at it while some caller up the stack holds it is okay.
+    if let Some(tasks) = unsafe { crate::startup::task_table_for_dump() } {
+        body.task_count = tasks.len() as u32;
+        for (out, task) in body.tasks.iter_mut().zip(tasks) {
+            let (state, peer) = match task.state() {
+                TaskState::Healthy(SchedState::Stopped) => (0, None),
+                TaskState::Healthy(SchedState::Runnable) => (1, None),
+                TaskState::Healthy(SchedState::InSend(p)) => (2, Some(*p)),
+                TaskState::Healthy(SchedState::InReply(p)) => (3, Some(*p)),
+                TaskState::Healthy(SchedState::InRecv(p)) => (4, *p),
+                TaskState::Faulted { .. } => (5, None),
+            };
+            *out = CrashDumpTask {
+                state,
+                priority: task.priority().0,
+                generation: task.generation().into(),
+                peer: peer.map(|p| p.index() as u8).unwrap_or(0xFF),
+            };
+        }
+
+        if let Some(task) =
+            crate::arch::try_current_task_index().and_then(|i| tasks.get(i))
+        {
+            body.current_task = u32::from(task.descriptor().index);
+            let words = (core::mem::size_of::<crate::arch::SavedState>() / 4)
+                .min(CRASH_DUMP_SAVE_WORDS);
+            // Safety: SavedState is a repr(C) struct of u32s, so reading its
+            // first `words` words as u32s is fine.
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    task.save() as *const _ as *const u32,
+                    body.current_save.as_mut_ptr(),
+                    words,
+                );
+            }
+        }
+    }
+
+    // Safety: CrashDumpBody is repr(C) and made of integers with no padding
+    // between them, so we can view it as bytes.
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            &body as *const _ as *const u8,
+            core::mem::size_of::<CrashDumpBody>(),
+        )
+    };
+    let dump = CrashDump {
+        magic: CRASH_DUMP_MAGIC,
+        version: CRASH_DUMP_VERSION,
+        body_len: bytes.len() as u32,
+        crc: crc32(bytes),
+        body,
+    };
+    // Safety: only one execution of `die` gets here (see `begin_epitaph`).
+    // We write volatile so that it can't be discarded as dead.
+    unsafe {
+        core::ptr::addr_of_mut!(KERNEL_CRASH_DUMP)
+            .cast::<CrashDump>()
+            .write_volatile(dump);
+    }
+}
+
+/// Returns the crash dump recorded before the most recent reset, if any,
+/// clearing it in the process.
+///
+/// This is intended to be called from an application's `main` before
+/// `start_kernel`, e.g. to stash the dump somewhere a task can report it.
+///
+/// # Safety
+///
+/// This must not be called concurrently with itself or with `start_kernel`.
+#[cfg(not(feature = "nano"))]
+pub unsafe fn take_previous_crash_dump() -> Option<CrashDump> {
+    let p = core::ptr::addr_of_mut!(KERNEL_CRASH_DUMP).cast::<CrashDump>();
+    // Safety: the dump is made of plain integers, so any bit pattern left over
+    // from before reset is a valid (if meaningless) value, which the magic and
+    // CRC checks weed out. Our caller has promised exclusive access.
+    let dump = unsafe { p.read_volatile() };
+    if dump.magic != CRASH_DUMP_MAGIC
+        || dump.version != CRASH_DUMP_VERSION
+        || dump.body_len as usize != core::mem::size_of::<CrashDumpBody>()
+    {
+        return None;
+    }
+    // Safety: as in write_crash_dump.
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            &dump.body as *const _ as *const u8,
+            core::mem::size_of::<CrashDumpBody>(),
+        )
+    };
+    if crc32(bytes) != dump.crc {
+        return None;
+    }
+    // Safety: as above.
+    unsafe {
+        core::ptr::addr_of_mut!((*p).magic).write_volatile(0);
+    }
+    Some(dump)
+}
+
+/// Bitwise CRC-32 (IEEE 802.3, reflected). Slow, but small, and only used when
+/// things have already gone wrong.
+#[cfg(not(feature = "nano"))]
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= u32::from(b);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
 #[cfg(not(feature = "nano"))]
 #[inline(always)]
 pub fn die(msg: impl Display) -> ! {
@@ -77,6 +381,11 @@ fn die_impl(msg: &dyn Display) -> ! {
     let buf = begin_epitaph();
     let mut writer = Eulogist { dest: buf };
     write!(writer, "{}", msg).ok();
+    klog!("kernel died: {}", msg);
+
+    // Safety: begin_epitaph has made us the only code that will ever touch the
+    // epitaph again, and we've finished writing it.
+    write_crash_dump(unsafe { &*core::ptr::addr_of!(KERNEL_EPITAPH) });
 
     loop {
         // Platform-independent NOP
@@ -104,13 +413,13 @@ impl Write for Eulogist {
     }
 }
 
-#[cfg(not(feature = "nano"))]
+#[cfg(all(target_os = "none", not(feature = "nano")))]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo<'_>) -> ! {
     die(info)
 }
 
-#[cfg(feature = "nano")]
+#[cfg(all(target_os = "none", feature = "nano"))]
 #[panic_handler]
 fn panic(_info: &core::panic::PanicInfo<'_>) -> ! {
     unsafe {
@@ -0,0 +1,146 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Board-supplied low-power idle.
+//!
+//! With nothing to do, the idle task waits for interrupts with `WFI`, which
+//! stops the CPU but leaves the rest of the chip running. Deeper sleep states
+//! (STOP2 on the STM32L4, say) save much more power, but they're SoC-specific,
+//! need privileges the idle task doesn't have, and often stop the kernel's
+//! timer along with everything else.
+//!
+//! So, like the watchdog, board setup code can supply a routine in a
+//! `LowPower` struct passed to `configure_idle` before calling
+//! `start_kernel`. The idle task asks the kernel to run it with the `idle`
+//! kipc, which does so only if no other task is runnable, passing it the time
+//! until the kernel next has something to do. The routine is responsible for
+//! saving and restoring whatever the sleep state loses, and for arranging to
+//! wake by that deadline if the kernel's timer won't be running.
+//!
+//! The routine runs in the kernel, where interrupts can't be taken, so it
+//! must sleep with `wait_for_interrupt` rather than a bare `WFI`. Once it
+//! returns, interrupts that arrived while it slept are taken as usual. If it
+//! reports that the kernel's timer was stopped, the kernel moves its clock
+//! forward to make up for it, and fires any timers that came due.
+
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+
+use crate::arch;
+use crate::startup::HUBRIS_TICK_HZ;
+use crate::task::{self, NextTask, Task};
+
+/// Hooks that may be provided by the board setup code to put the system into
+/// a low-power state while it's idle.
+pub struct LowPower {
+    /// Enters a low-power state, sleeps with `wait_for_interrupt`, and
+    /// restores the system on waking. `max_us` is the number of microseconds
+    /// until the kernel's next deadline, or `u64::MAX` if it has none.
+    ///
+    /// Returns the number of microseconds for which the kernel's timer was
+    /// stopped: zero if it kept running, or the whole time asleep if the
+    /// sleep state stopped it.
+    pub sleep: fn(max_us: u64) -> u64,
+}
+
+/// Supplies the kernel with the board's low-power idle routine.
+pub fn configure_idle(low_power: &'static LowPower) {
+    LOW_POWER.store(low_power as *const _ as *mut _, Ordering::Relaxed);
+}
+
+/// Internal pointer written by `configure_idle` and read by `table`. If this
+/// is null, no routine has been provided, and the `idle` kipc returns without
+/// doing anything.
+static LOW_POWER: AtomicPtr<LowPower> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Grabs a reference to the configured low-power routine, if any.
+fn table() -> Option<&'static LowPower> {
+    let p = LOW_POWER.load(Ordering::Relaxed);
+    if p.is_null() {
+        None
+    } else {
+        // We only write this pointer from a valid `&'static`, and we're handing
+        // out a shared reference, so this should be ok...
+        unsafe { Some(&*p) }
+    }
+}
+
+/// Sleeps until an interrupt is pending. This is for use in a `LowPower`
+/// routine: the kernel can't be interrupted, so a bare `WFI` might never
+/// return.
+pub fn wait_for_interrupt() {
+    arch::wait_for_pending_interrupt();
+}
+
+/// Number of times the low-power routine has been run, for debuggers.
+#[no_mangle]
+static IDLE_SLEEPS: AtomicU32 = AtomicU32::new(0);
+
+/// Interrupt that was pending when the low-power routine last returned, or
+/// `u32::MAX` if there wasn't one (say, because the kernel's timer woke us),
+/// for debuggers working out what keeps waking the system.
+#[no_mangle]
+static IDLE_WAKE_IRQ: AtomicU32 = AtomicU32::new(u32::MAX);
+
+/// Microseconds that the kernel's timer was stopped for, but which didn't
+/// add up to a whole tick, carried over to the next sleep.
+static CARRY_US: AtomicU32 = AtomicU32::new(0);
+
+/// Runs the board's low-power routine on behalf of `tasks[caller]`, if there
+/// is one, no other task is runnable, and the next deadline isn't too close
+/// to bother. Returns whether it ran, and which task should run next, since
+/// catching up on time may have fired timers.
+pub(crate) fn sleep(tasks: &mut [Task], caller: usize) -> (bool, NextTask) {
+    let Some(low_power) = table() else {
+        return (false, NextTask::Same);
+    };
+    if tasks
+        .iter()
+        .enumerate()
+        .any(|(i, t)| i != caller && t.is_runnable())
+    {
+        return (false, NextTask::Same);
+    }
+
+    // We don't know how far into the current tick we are, so count from the
+    // end of it; that way we err early.
+    let us_per_tick = u64::from(1_000_000 / HUBRIS_TICK_HZ);
+    let now = arch::now();
+    let max_us = match task::next_deadline(tasks) {
+        Some(d) => {
+            let ticks = u64::from(d).saturating_sub(u64::from(now));
+            if ticks < 2 {
+                return (false, NextTask::Same);
+            }
+            (ticks - 1) * us_per_tick
+        }
+        None => u64::MAX,
+    };
+
+    let stopped_us = (low_power.sleep)(max_us);
+    let sleeps = IDLE_SLEEPS.load(Ordering::Relaxed);
+    IDLE_SLEEPS.store(sleeps.wrapping_add(1), Ordering::Relaxed);
+    let irq = arch::pending_irq();
+    IDLE_WAKE_IRQ.store(irq.unwrap_or(u32::MAX), Ordering::Relaxed);
+    crate::trace::record(
+        crate::trace::TraceEvent::Wake,
+        caller,
+        irq.unwrap_or(u32::MAX),
+    );
+
+    if stopped_us == 0 {
+        return (true, NextTask::Same);
+    }
+    let total =
+        stopped_us.saturating_add(u64::from(CARRY_US.load(Ordering::Relaxed)));
+    CARRY_US.store((total % us_per_tick) as u32, Ordering::Relaxed);
+    arch::skip_ticks(total / us_per_tick);
+
+    // Do what the timer ISR would have done on the ticks we missed.
+    let now = arch::now();
+    let next = task::process_timers(tasks, now)
+        .combine(task::process_restart_holds(tasks, now))
+        .combine(task::process_ipc_timeouts(tasks, now))
+        .combine(task::process_reply_deadman(tasks, now));
+    (true, next)
+}
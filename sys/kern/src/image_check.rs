@@ -0,0 +1,81 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Checking the image against the CRC in its header.
+//!
+//! Flash that has worn out or been disturbed tends to show up as faults that
+//! make no sense, long after boot. If the image was built with
+//! `image-check = true` under `[kernel]`, the build system records a CRC of
+//! it in the image header, and we check the image against that before
+//! starting any tasks. The result doesn't stop the boot -- a damaged image
+//! may well still run, and the supervisor is better placed than we are to
+//! decide what to do -- but we keep it for the `read_image_check` kipc.
+
+use abi::{Crc32, ImageCheck};
+
+/// What `check` found. Written once, before any task runs, and only read
+/// after that.
+static mut RESULT: ImageCheck = ImageCheck::Skipped;
+
+/// Checks the image against the CRC in its header, if there is one.
+pub(crate) fn check() {
+    // Safety: the header is populated by the build system and only ever read.
+    let header = unsafe { &crate::header::HEADER };
+    if header.format < 3 || header.image_check_len == 0 {
+        return;
+    }
+    let expected = header.image_check_crc;
+
+    // The covered range must include the header, which we know is in flash.
+    // Anything else means the header is damaged, and reading from wherever
+    // it points could fault.
+    let field = &header.image_check_crc as *const u32 as usize;
+    let start = header.image_check_start as usize;
+    let len = header.image_check_len as usize;
+    let Some(end) = start.checked_add(len) else {
+        return fail(expected, 0);
+    };
+    if field < start || field + 4 > end {
+        return fail(expected, 0);
+    }
+
+    // Safety: the range is the image's own flash, as recorded by the build
+    // system, which is always readable by the kernel.
+    let image = unsafe { core::slice::from_raw_parts(start as *const u8, len) };
+    let (before, rest) = image.split_at(field - start);
+    let mut crc = Crc32::new();
+    crc.update(before);
+    crc.update(&[0; 4]);
+    crc.update(&rest[4..]);
+    let actual = crc.finish();
+
+    if actual == expected {
+        klog!("image check passed");
+        // Safety: we're in `start_kernel`, before any task runs.
+        unsafe {
+            RESULT = ImageCheck::Passed;
+        }
+    } else {
+        fail(expected, actual);
+    }
+}
+
+fn fail(expected: u32, actual: u32) {
+    klog!(
+        "image check FAILED: expected {:#x}, got {:#x}",
+        expected,
+        actual
+    );
+    // Safety: we're in `start_kernel`, before any task runs.
+    unsafe {
+        RESULT = ImageCheck::Failed { expected, actual };
+    }
+}
+
+/// Returns what `check` found.
+pub(crate) fn result() -> ImageCheck {
+    // Safety: this is only written by `check`, before any task runs, so by
+    // the time a kipc can ask for it, it's only ever read.
+    unsafe { *core::ptr::addr_of!(RESULT) }
+}
@@ -0,0 +1,164 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Interrupt ownership.
+//!
+//! Which task owns each interrupt, and which notification bits it's delivered
+//! on, is fixed in the `app.toml` and compiled into the lookup tables in
+//! `startup`. The supervisor can move an interrupt to another task at runtime
+//! with the `transfer_irq` kipc -- say, to hand a UART from a console task to a
+//! firmware update task. Those moves are recorded in a small table here, which
+//! overrides the compiled-in owner, and all lookups of interrupt ownership go
+//! through this module so that they see it.
+//!
+//! Several interrupts can share a task's notification bits. So that the task
+//! doesn't have to poll every peripheral to find out which one needs it, this
+//! module also keeps a bit per interrupt recording that it fired, which the
+//! owner reads and clears with the `take_fired_irqs` kipc.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use abi::{InterruptNum, InterruptOwner};
+
+use crate::startup::HUBRIS_IRQ_FIRED;
+
+/// Number of interrupts that can be away from their `app.toml` owner at once.
+pub const MAX_TRANSFERS: usize = 8;
+
+/// One interrupt that has been moved away from its `app.toml` owner. These are
+/// only touched from the kernel, which doesn't preempt itself, so `Relaxed`
+/// is fine throughout.
+struct Transfer {
+    /// Interrupt number, or `u32::MAX` if this slot is free.
+    irq: AtomicU32,
+    task: AtomicU32,
+    /// Notification bits, split in two because not all of our targets have
+    /// 64-bit atomics.
+    notification: [AtomicU32; 2],
+}
+
+impl Transfer {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const FREE: Self = Self {
+        irq: AtomicU32::new(u32::MAX),
+        task: AtomicU32::new(0),
+        notification: [AtomicU32::new(0), AtomicU32::new(0)],
+    };
+
+    fn owner(&self) -> InterruptOwner {
+        let [lo, hi] = &self.notification;
+        InterruptOwner {
+            task: self.task.load(Ordering::Relaxed),
+            notification: u64::from(hi.load(Ordering::Relaxed)) << 32
+                | u64::from(lo.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+static TRANSFERS: [Transfer; MAX_TRANSFERS] = [Transfer::FREE; MAX_TRANSFERS];
+
+fn find_transfer(irq: u32) -> Option<&'static Transfer> {
+    TRANSFERS
+        .iter()
+        .find(|t| t.irq.load(Ordering::Relaxed) == irq)
+}
+
+/// Returns the owner of hardware interrupt `irq`, or `None` if the
+/// application doesn't use it.
+pub fn owner(irq: u32) -> Option<InterruptOwner> {
+    if let Some(t) = find_transfer(irq) {
+        return Some(t.owner());
+    }
+    crate::startup::HUBRIS_IRQ_TASK_LOOKUP
+        .get(InterruptNum(irq))
+        .copied()
+}
+
+/// Calls `f` with each interrupt currently owned by `owner` -- that is,
+/// delivered to `owner.task` on exactly the notification bits in
+/// `owner.notification`. Returns `false` if there aren't any.
+pub fn for_each_owned(owner: InterruptOwner, mut f: impl FnMut(u32)) -> bool {
+    let mut any = false;
+    if let Some(irqs) = crate::startup::HUBRIS_TASK_IRQ_LOOKUP.get(owner) {
+        for &InterruptNum(irq) in irqs.iter() {
+            // Skip any that have been moved elsewhere.
+            if find_transfer(irq).is_none() {
+                f(irq);
+                any = true;
+            }
+        }
+    }
+    for t in &TRANSFERS {
+        let irq = t.irq.load(Ordering::Relaxed);
+        if irq != u32::MAX && t.owner() == owner {
+            f(irq);
+            any = true;
+        }
+    }
+    any
+}
+
+/// Records that `irq` has fired and been delivered to its owner.
+pub fn mark_fired(irq: u32) {
+    if let Some(word) = HUBRIS_IRQ_FIRED.get(irq as usize / 32) {
+        // Not `fetch_or`, which ARMv6-M lacks; the kernel doesn't preempt
+        // itself, so this is equivalent.
+        let bits = word.load(Ordering::Relaxed);
+        word.store(bits | 1 << (irq % 32), Ordering::Relaxed);
+    }
+}
+
+/// Clears the record that `irq` has fired, returning whether it had.
+pub fn take_fired(irq: u32) -> bool {
+    let Some(word) = HUBRIS_IRQ_FIRED.get(irq as usize / 32) else {
+        return false;
+    };
+    let bits = word.load(Ordering::Relaxed);
+    let bit = 1 << (irq % 32);
+    word.store(bits & !bit, Ordering::Relaxed);
+    bits & bit != 0
+}
+
+/// Moves interrupt `irq` to `new_owner`. The interrupt is masked, so that the
+/// new owner can set up the peripheral before enabling it.
+///
+/// `irq` must be one the application uses, i.e. `owner(irq)` is `Some`. This
+/// doesn't check that; the `transfer_irq` kipc does before calling it.
+/// `new_owner`, on the other hand, can be any task and notification bits, and
+/// needn't appear in the `app.toml` at all.
+///
+/// Returns `false`, changing nothing, if `MAX_TRANSFERS` interrupts have
+/// already been moved away from their `app.toml` owners.
+pub fn transfer(irq: u32, new_owner: InterruptOwner) -> bool {
+    let home = crate::startup::HUBRIS_IRQ_TASK_LOOKUP
+        .get(InterruptNum(irq))
+        .copied();
+    let slot = if home == Some(new_owner) {
+        // Going home, so we no longer need to remember it.
+        if let Some(t) = find_transfer(irq) {
+            t.irq.store(u32::MAX, Ordering::Relaxed);
+        }
+        None
+    } else if let Some(t) = find_transfer(irq) {
+        Some(t)
+    } else if let Some(t) = find_transfer(u32::MAX) {
+        Some(t)
+    } else {
+        return false;
+    };
+
+    // Mask it before changing hands, so that it can't fire for the new owner
+    // while the peripheral is still set up by the old one. A firing that the
+    // old owner hasn't collected isn't the new owner's business.
+    crate::arch::disable_irq(irq);
+    take_fired(irq);
+    if let Some(t) = slot {
+        t.task.store(new_owner.task, Ordering::Relaxed);
+        let [lo, hi] = &t.notification;
+        lo.store(new_owner.notification as u32, Ordering::Relaxed);
+        hi.store((new_owner.notification >> 32) as u32, Ordering::Relaxed);
+        t.irq.store(irq, Ordering::Relaxed);
+    }
+    true
+}
@@ -4,12 +4,15 @@
 
 //! Implementation of IPC operations on the virtual kernel task.
 
-use abi::{FaultInfo, Kipcnum, SchedState, TaskState, UsageError};
+use abi::{
+    CacheOp, FaultInfo, FaultSource, Kipcnum, SchedState, TaskState, UsageError,
+};
 
 use crate::arch;
+use crate::descs::{RegionAttributes, TaskFlags};
 use crate::err::UserError;
-use crate::task::{current_id, ArchState, NextTask, Task};
-use crate::umem::USlice;
+use crate::task::{current_id, ArchState, NextTask, NotificationSet, Task};
+use crate::umem::{self, USlice};
 use core::mem::size_of;
 
 /// Message dispatcher.
@@ -42,6 +45,61 @@ pub fn handle_kernel_message(
         Ok(Kipcnum::FindFaultedTask) => {
             find_faulted_task(tasks, caller, args.message?, args.response?)
         }
+        Ok(Kipcnum::ReadCpuTime) => {
+            read_cpu_time(tasks, caller, args.message?, args.response?)
+        }
+        Ok(Kipcnum::BroadcastNotification) => {
+            broadcast_notification(tasks, caller, args.message?)
+        }
+        Ok(Kipcnum::ReadTaskStackUsage) => {
+            read_task_stack_usage(tasks, caller, args.message?, args.response?)
+        }
+        Ok(Kipcnum::ReadMonotonicTime) => {
+            read_monotonic_time(tasks, caller, args.response?)
+        }
+        Ok(Kipcnum::SuspendTask) => suspend_task(tasks, caller, args.message?),
+        Ok(Kipcnum::ResumeTask) => resume_task(tasks, caller, args.message?),
+        Ok(Kipcnum::ReadSyscallCounts) => {
+            read_syscall_counts(tasks, caller, args.message?, args.response?)
+        }
+        Ok(Kipcnum::FindStuckReply) => {
+            find_stuck_reply(tasks, caller, args.message?, args.response?)
+        }
+        Ok(Kipcnum::ReadRegisterHash) => {
+            read_register_hash(tasks, caller, args.message?, args.response?)
+        }
+        Ok(Kipcnum::SetTickDivisor) => {
+            set_tick_divisor(tasks, caller, args.message?)
+        }
+        Ok(Kipcnum::CacheMaintenance) => {
+            cache_maintenance(tasks, caller, args.message?)
+        }
+        Ok(Kipcnum::Idle) => idle(tasks, caller, args.response?),
+        Ok(Kipcnum::ReadTaskSnapshot) => {
+            read_task_snapshot(tasks, caller, args.message?, args.response?)
+        }
+        Ok(Kipcnum::TransferIrq) => {
+            transfer_irq(tasks, caller, args.message?, args.response?)
+        }
+        Ok(Kipcnum::TakeFiredIrqs) => {
+            take_fired_irqs(tasks, caller, args.message?, args.response?)
+        }
+        Ok(Kipcnum::ReadPanicRecord) => {
+            read_panic_record(tasks, caller, args.message?, args.response?)
+        }
+        Ok(Kipcnum::WatchTask) => watch_task(tasks, caller, args.message?),
+        Ok(Kipcnum::ReadBootMeasurements) => {
+            read_boot_measurements(tasks, caller, args.response?)
+        }
+        Ok(Kipcnum::ReadImageCheck) => {
+            read_image_check(tasks, caller, args.response?)
+        }
+        Ok(Kipcnum::ReadTaskMemory) => {
+            read_task_memory(tasks, caller, args.message?, args.response?)
+        }
+        Ok(Kipcnum::WriteTaskMemory) => {
+            write_task_memory(tasks, caller, args.message?)
+        }
 
         _ => {
             // Task has sent an unknown message to the kernel. That's bad.
@@ -101,7 +159,7 @@ fn read_task_status(
         )));
     }
     // cache other state before taking out a mutable borrow on tasks
-    let other_state = *tasks[index as usize].state();
+    let other_state = tasks[index as usize].reported_state();
 
     let response_len =
         serialize_response(&mut tasks[caller], response, &other_state)?;
@@ -111,6 +169,47 @@ fn read_task_status(
     Ok(NextTask::Same)
 }
 
+fn read_task_snapshot(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    if caller != 0
+        && !tasks[caller]
+            .descriptor()
+            .flags
+            .contains(TaskFlags::MONITOR)
+    {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::NotMonitor,
+        )));
+    }
+
+    let index: u32 = deserialize_message(&tasks[caller], message)?;
+    let Some(other) = tasks.get(index as usize) else {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::TaskOutOfRange,
+        )));
+    };
+    let (deadline, _) = other.timer();
+    let snapshot = abi::TaskSnapshot {
+        state: (&other.reported_state()).into(),
+        generation: other.generation().into(),
+        priority: other.priority().0,
+        pending_notifications: other.pending_notifications().count_ones() as u8,
+        timer_deadline: deadline.map(u64::from),
+        restart_held: crate::task::restart_held(tasks, index as usize),
+    };
+
+    let response_len =
+        serialize_response(&mut tasks[caller], response, &snapshot)?;
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
+
 fn restart_task(
     tasks: &mut [Task],
     caller: usize,
@@ -124,8 +223,28 @@ fn restart_task(
             UsageError::TaskOutOfRange,
         )));
     }
+    // A task held by the restart throttle stays down until its hold expires;
+    // the supervisor will be notified again then.
+    if crate::task::restart_held(tasks, index) {
+        tasks[caller]
+            .save_mut()
+            .set_send_response_and_length(abi::RESTART_HELD, 0);
+        return Ok(NextTask::Same);
+    }
     let old_id = current_id(tasks, index);
+    // If the task was blocked on another, we'll need to take back the priority
+    // it was lending.
+    let old_peer = match tasks[index].state() {
+        TaskState::Healthy(SchedState::InSend(peer))
+        | TaskState::Healthy(SchedState::InReply(peer)) => Some(peer.index()),
+        _ => None,
+    };
     tasks[index].reinitialize();
+    if let Some(peer) = old_peer {
+        if peer < tasks.len() {
+            crate::task::update_inherited_priority(tasks, peer);
+        }
+    }
     if start {
         tasks[index].set_healthy_state(SchedState::Runnable);
     }
@@ -163,6 +282,20 @@ fn restart_task(
         }
     }
 
+    // Let anyone watching for this know that the task has a new generation.
+    // As with POST, only bother switching if we woke someone more important
+    // than the caller.
+    let caller_p = tasks[caller].priority();
+    let mut next_task = NextTask::Same;
+    for task in tasks.iter_mut() {
+        if let Some(n) = task.watch_notification(index) {
+            let woke = task.post(n);
+            if woke && task.priority().is_more_important_than(caller_p) {
+                next_task = NextTask::Other;
+            }
+        }
+    }
+
     if index == caller {
         // Welp, they've restarted themselves. Best not return anything then.
         if !start {
@@ -173,6 +306,27 @@ fn restart_task(
     } else {
         tasks[caller].save_mut().set_send_response_and_length(0, 0);
     }
+    Ok(next_task)
+}
+
+fn watch_task(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    let (index, notification): (u32, u64) =
+        deserialize_message(&tasks[caller], message)?;
+    let index = index as usize;
+    // Watches are kept as a bitmask, so, like `broadcast_notification`, this
+    // only reaches the first 64 tasks.
+    if index >= tasks.len() || index >= 64 {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::TaskOutOfRange,
+        )));
+    }
+
+    tasks[caller].set_watch(index, NotificationSet(notification));
+    tasks[caller].save_mut().set_send_response_and_length(0, 0);
     Ok(NextTask::Same)
 }
 
@@ -212,6 +366,74 @@ fn fault_task(
     Ok(NextTask::Same)
 }
 
+/// Stops a task from being scheduled, without otherwise disturbing it. Only
+/// the supervisor can do this, and not to itself.
+fn suspend_task(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    if caller != 0 {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::NotSupervisor,
+        )));
+    }
+
+    let index: u32 = deserialize_message(&tasks[caller], message)?;
+    let index = index as usize;
+
+    if index == caller {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::IllegalTask,
+        )));
+    }
+
+    if index >= tasks.len() {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::TaskOutOfRange,
+        )));
+    }
+
+    // The target isn't running (the caller is), so there's no need to
+    // reschedule.
+    tasks[index].set_suspended(true);
+    tasks[caller].save_mut().set_send_response_and_length(0, 0);
+    Ok(NextTask::Same)
+}
+
+/// Undoes `suspend_task`. Resuming a task that isn't suspended does nothing.
+fn resume_task(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    if caller != 0 {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::NotSupervisor,
+        )));
+    }
+
+    let index: u32 = deserialize_message(&tasks[caller], message)?;
+    let index = index as usize;
+
+    if index >= tasks.len() {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::TaskOutOfRange,
+        )));
+    }
+
+    let was_suspended = tasks[index].is_suspended();
+    tasks[index].set_suspended(false);
+    tasks[caller].save_mut().set_send_response_and_length(0, 0);
+
+    // If the task became runnable while it was suspended, it may outrank us.
+    if was_suspended && tasks[index].is_runnable() {
+        Ok(NextTask::Other)
+    } else {
+        Ok(NextTask::Same)
+    }
+}
+
 fn read_image_id(
     tasks: &mut [Task],
     caller: usize,
@@ -442,7 +664,7 @@ fn software_irq(
         )));
     }
 
-    let (index, notification): (u32, u32) =
+    let (index, notification): (u32, u64) =
         deserialize_message(&tasks[caller], message)?;
 
     if index as usize >= tasks.len() {
@@ -451,21 +673,98 @@ fn software_irq(
         )));
     }
 
-    // Look up which IRQs are mapped to the target task.
-    let irqs = crate::startup::HUBRIS_TASK_IRQ_LOOKUP
-        .get(abi::InterruptOwner {
+    // Pend the IRQs mapped to the target task.
+    let owner = abi::InterruptOwner {
+        task: index,
+        notification,
+    };
+    if !crate::irq::for_each_owned(owner, |irq| {
+        crate::arch::pend_software_irq(abi::InterruptNum(irq))
+    }) {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::NoIrq,
+        )));
+    }
+
+    tasks[caller].save_mut().set_send_response_and_length(0, 0);
+    Ok(NextTask::Same)
+}
+
+fn transfer_irq(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    if caller != 0 {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::NotSupervisor,
+        )));
+    }
+
+    let (irq, index, notification): (u32, u32, u64) =
+        deserialize_message(&tasks[caller], message)?;
+
+    if index as usize >= tasks.len() {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::TaskOutOfRange,
+        )));
+    }
+    // Only interrupts the application uses can be moved; we have nowhere to
+    // send any others.
+    if crate::irq::owner(irq).is_none() {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::NoIrq,
+        )));
+    }
+
+    let done = crate::irq::transfer(
+        irq,
+        abi::InterruptOwner {
             task: index,
             notification,
-        })
-        .ok_or(UserError::Unrecoverable(FaultInfo::SyscallUsage(
-            UsageError::NoIrq,
-        )))?;
+        },
+    );
+    let response_len = serialize_response(&mut tasks[caller], response, &done)?;
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
 
-    for &irq in irqs.iter() {
-        crate::arch::pend_software_irq(irq);
+fn take_fired_irqs(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+    mut response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    let notification: u64 = deserialize_message(&tasks[caller], message)?;
+    let owner = abi::InterruptOwner {
+        task: caller as u32,
+        notification,
+    };
+
+    // Write out as many IRQ numbers as fit, leaving the rest marked so that
+    // the caller can collect them next time.
+    let out = tasks[caller].try_write(&mut response)?;
+    let mut len = 0;
+    let owned = crate::irq::for_each_owned(owner, |irq| {
+        if let Some(slot) = out.get_mut(len..len + 4) {
+            if crate::irq::take_fired(irq) {
+                slot.copy_from_slice(&irq.to_ne_bytes());
+                len += 4;
+            }
+        }
+    });
+    if !owned {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::NoIrq,
+        )));
     }
 
-    tasks[caller].save_mut().set_send_response_and_length(0, 0);
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, len);
     Ok(NextTask::Same)
 }
 
@@ -503,3 +802,487 @@ fn find_faulted_task(
         .set_send_response_and_length(0, response_len);
     Ok(NextTask::Same)
 }
+
+fn read_panic_record(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    let index = deserialize_message::<u32>(&tasks[caller], message)? as usize;
+    if index >= tasks.len() {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::TaskOutOfRange,
+        )));
+    }
+
+    // A task that panicked through userlib left its registers pointing at a
+    // `PanicRecord` as the panic message. Anything else -- a task that isn't
+    // faulted, faulted some other way, or passed a message of the wrong size
+    // or that it can't read -- has no record, and gets an empty response.
+    let task = &tasks[index];
+    let record: Option<abi::PanicRecord> = match task.state() {
+        TaskState::Faulted {
+            fault: FaultInfo::Panic,
+            ..
+        } => {
+            let message = task.save().as_panic_args().message;
+            message.ok().and_then(|m| umem::read_struct(task, &m).ok())
+        }
+        _ => None,
+    };
+
+    let response_len = match record {
+        Some(record) => {
+            umem::write_struct(&mut tasks[caller], &response, &record)
+                .map_err(UserError::Unrecoverable)?
+        }
+        None => 0,
+    };
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
+
+fn read_boot_measurements(
+    tasks: &mut [Task],
+    caller: usize,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    if caller != 0
+        && !tasks[caller]
+            .descriptor()
+            .flags
+            .contains(TaskFlags::ATTESTER)
+    {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::NotAttester,
+        )));
+    }
+
+    let response_len = match crate::measurements::get() {
+        Some(record) => {
+            umem::write_struct(&mut tasks[caller], &response, &record)
+                .map_err(UserError::Unrecoverable)?
+        }
+        None => 0,
+    };
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
+
+fn read_image_check(
+    tasks: &mut [Task],
+    caller: usize,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    let result = crate::image_check::result();
+    let response_len =
+        serialize_response(&mut tasks[caller], response, &result)?;
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
+
+/// Checks that `caller` is the supervisor and that `index` names some other
+/// task, for the kipcs that reach into another task's memory.
+fn check_task_memory_target(
+    tasks: &[Task],
+    caller: usize,
+    index: u32,
+) -> Result<usize, UserError> {
+    if caller != 0 {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::NotSupervisor,
+        )));
+    }
+    // As with dumps, the supervisor can't name itself, so the two tasks are
+    // always distinct.
+    let index = index as usize;
+    if index == caller || index >= tasks.len() {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::TaskOutOfRange,
+        )));
+    }
+    Ok(index)
+}
+
+fn read_task_memory(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    use crate::util::index2_distinct;
+
+    let (index, address): (u32, u32) =
+        deserialize_message(&tasks[caller], message)?;
+    let index = check_task_memory_target(tasks, caller, index)?;
+
+    let mut response = response.prefix(abi::TASK_MEMORY_WINDOW);
+    let (caller_task, target_task) = index2_distinct(tasks, caller, index);
+
+    // Problems with the caller's own buffer are the caller's fault, and are
+    // found first. Problems with the window are not: the address usually
+    // comes from a crashed task's symbols, and a supervisor that's collecting
+    // diagnostics shouldn't be brought down by them.
+    let window = USlice::<u8>::from_raw(address as usize, response.len()).ok();
+    if window.as_ref().is_some_and(|w| w.aliases(&response)) {
+        return Err(UserError::Unrecoverable(FaultInfo::MemoryAccess {
+            address: Some(response.base_addr() as u32),
+            source: FaultSource::Kernel,
+        }));
+    }
+    let to = caller_task
+        .try_write(&mut response)
+        .map_err(UserError::Unrecoverable)?;
+
+    // `try_read` is what honors the target's region table: it only succeeds
+    // if the whole window is in regions the target can read, and none of them
+    // are device or DMA memory.
+    let (code, len) = match window.as_ref().map(|w| target_task.try_read(w)) {
+        Some(Ok(from)) => {
+            to.copy_from_slice(from);
+            (0, from.len())
+        }
+        _ => (abi::TASK_MEMORY_INACCESSIBLE, 0),
+    };
+
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(code, len);
+    Ok(NextTask::Same)
+}
+
+fn write_task_memory(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    use crate::util::index2_distinct;
+
+    // The data to write follows the fixed-size request in the message.
+    let (index, address): (u32, u32) =
+        deserialize_message(&tasks[caller], message.clone())?;
+    let index = check_task_memory_target(tasks, caller, index)?;
+
+    let data = message
+        .split_off(size_of::<(u32, u32)>())
+        .prefix(abi::TASK_MEMORY_WINDOW);
+    let (caller_task, target_task) = index2_distinct(tasks, caller, index);
+
+    // See `read_task_memory` for who gets blamed for what.
+    let window = USlice::<u8>::from_raw(address as usize, data.len()).ok();
+    if window.as_ref().is_some_and(|w| w.aliases(&data)) {
+        return Err(UserError::Unrecoverable(FaultInfo::MemoryAccess {
+            address: Some(data.base_addr() as u32),
+            source: FaultSource::Kernel,
+        }));
+    }
+    let from = caller_task
+        .try_read(&data)
+        .map_err(UserError::Unrecoverable)?;
+
+    let code = match window {
+        Some(mut w) => match target_task.try_write(&mut w) {
+            Ok(to) => {
+                to.copy_from_slice(from);
+                0
+            }
+            Err(_) => abi::TASK_MEMORY_INACCESSIBLE,
+        },
+        None => abi::TASK_MEMORY_INACCESSIBLE,
+    };
+
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(code, 0);
+    Ok(NextTask::Same)
+}
+
+fn read_cpu_time(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    let index: u32 = deserialize_message(&tasks[caller], message)?;
+    if index as usize >= tasks.len() {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::TaskOutOfRange,
+        )));
+    }
+
+    let time = abi::CpuTime {
+        task: tasks[index as usize].cpu_time(),
+        kernel: arch::kernel_cpu_time(),
+        total: arch::cycle_count(),
+    };
+
+    let response_len = serialize_response(&mut tasks[caller], response, &time)?;
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
+
+fn read_syscall_counts(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    let index: u32 = deserialize_message(&tasks[caller], message)?;
+    if index as usize >= tasks.len() {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::TaskOutOfRange,
+        )));
+    }
+
+    let counts = crate::syscalls::syscall_counts(index as usize);
+
+    let response_len =
+        serialize_response(&mut tasks[caller], response, &counts)?;
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
+
+fn find_stuck_reply(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    if caller != 0 {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::NotSupervisor,
+        )));
+    }
+
+    let index = deserialize_message::<u32>(&tasks[caller], message)? as usize;
+
+    // As with `find_faulted_task`, index == tasks.len() is allowed, and ends
+    // the search.
+    if index > tasks.len() {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::TaskOutOfRange,
+        )));
+    }
+    let now = arch::now();
+    let stuck = (index..tasks.len())
+        .find_map(|i| crate::task::stuck_reply(tasks, i, now));
+
+    let response_len =
+        serialize_response(&mut tasks[caller], response, &stuck)?;
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
+
+fn read_register_hash(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    let index: u32 = deserialize_message(&tasks[caller], message)?;
+    if index as usize >= tasks.len() {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::TaskOutOfRange,
+        )));
+    }
+
+    // Safety: `SavedState` is `repr(C)` and made entirely of `u32`s on every
+    // architecture, so it has no padding and can be viewed as bytes.
+    let state = tasks[index as usize].save();
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            state as *const arch::SavedState as *const u8,
+            size_of::<arch::SavedState>(),
+        )
+    };
+    // 32-bit FNV-1a. This is a fingerprint for telling crashes apart, not a
+    // security measure.
+    let hash = bytes.iter().fold(0x811c_9dc5_u32, |h, &b| {
+        (h ^ u32::from(b)).wrapping_mul(0x0100_0193)
+    });
+
+    let response_len = serialize_response(&mut tasks[caller], response, &hash)?;
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
+
+fn set_tick_divisor(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    // Every timer and deadline in the system depends on this, so it's only for
+    // whichever task owns the clock tree.
+    if caller != 0
+        && !tasks[caller]
+            .descriptor()
+            .flags
+            .contains(TaskFlags::CLOCK_OWNER)
+    {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::NotClockOwner,
+        )));
+    }
+
+    let cycles_per_ms: u32 = deserialize_message(&tasks[caller], message)?;
+    let Some(divisor) = crate::time::tick_divisor(cycles_per_ms) else {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::BadKernelMessage,
+        )));
+    };
+
+    // Safety: this only records the clock rate for debuggers.
+    unsafe {
+        arch::set_clock_freq(cycles_per_ms);
+    }
+    arch::set_tick_divisor(divisor);
+    tasks[caller].save_mut().set_send_response_and_length(0, 0);
+    Ok(NextTask::Same)
+}
+
+fn cache_maintenance(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    let (op, base, len): (CacheOp, u32, u32) =
+        deserialize_message(&tasks[caller], message)?;
+
+    // Maintenance works on whole cache lines, so invalidating a range that
+    // shares a line with something else would throw away the other thing's
+    // writes. Cleaning is harmless, so only a bare invalidate has to be
+    // aligned.
+    let line = abi::DCACHE_LINE_SIZE;
+    if op == CacheOp::Invalidate && (base % line != 0 || len % line != 0) {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::BadKernelMessage,
+        )));
+    }
+
+    // The caller must be able to write anything it invalidates, since that
+    // can undo writes, but cleaning only needs read access.
+    let slice = USlice::<u8>::from_raw(base as usize, len as usize)
+        .map_err(FaultInfo::SyscallUsage)?;
+    let desired = match op {
+        CacheOp::Clean => RegionAttributes::READ,
+        CacheOp::Invalidate | CacheOp::CleanInvalidate => {
+            RegionAttributes::WRITE
+        }
+    };
+    tasks[caller].check_access(&slice, desired)?;
+
+    arch::dcache_maintain(op, base, len);
+    tasks[caller].save_mut().set_send_response_and_length(0, 0);
+    Ok(NextTask::Same)
+}
+
+fn idle(
+    tasks: &mut [Task],
+    caller: usize,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    let (slept, next) = crate::idle::sleep(tasks, caller);
+    let response_len =
+        serialize_response(&mut tasks[caller], response, &slept)?;
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(next)
+}
+
+fn broadcast_notification(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    if caller != 0 {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::NotSupervisor,
+        )));
+    }
+
+    let (task_set, notification): (u64, u64) =
+        deserialize_message(&tasks[caller], message)?;
+
+    // Check the whole set before posting anything, so that a bad request
+    // doesn't leave the broadcast half-delivered.
+    let valid = if tasks.len() >= 64 {
+        u64::MAX
+    } else {
+        (1 << tasks.len()) - 1
+    };
+    if task_set & !valid != 0 {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::TaskOutOfRange,
+        )));
+    }
+
+    let caller_p = tasks[caller].priority();
+    let mut next_task = NextTask::Same;
+    // Only the first 64 tasks can be named in the set.
+    for (i, task) in tasks.iter_mut().enumerate().take(64) {
+        if task_set & (1 << i) == 0 {
+            continue;
+        }
+        let woke = task.post(NotificationSet(notification));
+        // As with POST, only bother switching if we woke someone more
+        // important than us.
+        if woke && task.priority().is_more_important_than(caller_p) {
+            next_task = NextTask::Other;
+        }
+    }
+
+    tasks[caller].save_mut().set_send_response_and_length(0, 0);
+    Ok(next_task)
+}
+
+fn read_task_stack_usage(
+    tasks: &mut [Task],
+    caller: usize,
+    message: USlice<u8>,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    let index: u32 = deserialize_message(&tasks[caller], message)?;
+    if index as usize >= tasks.len() {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::TaskOutOfRange,
+        )));
+    }
+
+    let usage = arch::stack_high_water_mark(&tasks[index as usize]);
+
+    let response_len =
+        serialize_response(&mut tasks[caller], response, &usage)?;
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
+
+fn read_monotonic_time(
+    tasks: &mut [Task],
+    caller: usize,
+    response: USlice<u8>,
+) -> Result<NextTask, UserError> {
+    let time = arch::monotonic_time();
+    let response_len = serialize_response(&mut tasks[caller], response, &time)?;
+    tasks[caller]
+        .save_mut()
+        .set_send_response_and_length(0, response_len);
+    Ok(NextTask::Same)
+}
@@ -0,0 +1,222 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Kernel log.
+//!
+//! `klog!` formats a line of text, as `println!` would, and sends it out
+//! through a backend chosen at build time with a kernel feature:
+//!
+//! - `klog-semihosting`: ARM semihosting, to the debugger's console. Each
+//!   write halts the core until the debugger has dealt with it, and with no
+//!   debugger attached the kernel will fault, so this is for the bench only.
+//! - `klog-itm`: stimulus port 0 of the ITM, read out over SWO. The kernel
+//!   doesn't set up the ITM, TPIU, or SWO pin; that's left to the debugger, and
+//!   output is dropped until it has. ARMv6-M has no ITM.
+//! - `klog-rtt`: a SEGGER RTT-compatible up-channel, found by debug probes
+//!   that speak RTT through the `_SEGGER_RTT` control block in RAM and read
+//!   while the core runs. Output that doesn't fit in the ring is dropped,
+//!   rather than waiting for the probe to catch up.
+//!
+//! At most one of these can be enabled. With none, `klog!` compiles to nothing
+//! (its arguments are still type-checked).
+//!
+//! Formatting is not cheap, and all of these backends are slow compared to
+//! the kernel's usual work, so `klog!` belongs on rare paths like boot and
+//! faults, never on the syscall or context switch paths. For those, see the
+//! `trace` module.
+
+#[cfg(any(
+    all(feature = "klog-semihosting", feature = "klog-itm"),
+    all(feature = "klog-semihosting", feature = "klog-rtt"),
+    all(feature = "klog-itm", feature = "klog-rtt"),
+))]
+compile_error!("only one klog backend feature can be enabled");
+
+#[cfg(all(feature = "klog-itm", armv6m))]
+compile_error!("ARMv6-M has no ITM; use klog-semihosting or klog-rtt");
+
+/// Writes a line to the kernel log, with arguments as for `format_args!`.
+macro_rules! klog {
+    ($($arg:tt)*) => {
+        $crate::klog::write_line(format_args!($($arg)*))
+    };
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "klog-semihosting")] {
+        const ENABLED: bool = true;
+
+        /// Semihosting handle for the debugger's console, opened on first use
+        /// and kept, since each open uses up a file descriptor on the host.
+        static mut STDOUT: Option<cortex_m_semihosting::hio::HostStream> =
+            None;
+
+        fn write_bytes(bytes: &[u8]) {
+            // Safety: the kernel is single-threaded and not reentrant, so
+            // nothing else is accessing `STDOUT`.
+            let stdout = unsafe { &mut *core::ptr::addr_of_mut!(STDOUT) };
+            if stdout.is_none() {
+                *stdout = cortex_m_semihosting::hio::hstdout().ok();
+            }
+            if let Some(out) = stdout {
+                out.write_all(bytes).ok();
+            }
+        }
+    } else if #[cfg(feature = "klog-itm")] {
+        const ENABLED: bool = true;
+
+        fn write_bytes(bytes: &[u8]) {
+            use cortex_m::peripheral::{itm::RegisterBlock, ITM};
+
+            // Safety: the kernel is single-threaded and not reentrant, and is
+            // the only code that touches the ITM.
+            let itm = unsafe { &mut *(ITM::PTR as *mut RegisterBlock) };
+            // If the debugger hasn't turned on the ITM and port 0, the port
+            // never reports ready, and writing would spin forever.
+            let itm_enabled = itm.tcr.read() & 1 != 0;
+            if !itm_enabled || itm.ter[0].read() & 1 == 0 {
+                return;
+            }
+            cortex_m::itm::write_all(&mut itm.stim[0], bytes);
+        }
+    } else if #[cfg(feature = "klog-rtt")] {
+        const ENABLED: bool = true;
+
+        use rtt::write_bytes;
+        pub(crate) use rtt::init;
+    } else {
+        const ENABLED: bool = false;
+
+        fn write_bytes(_bytes: &[u8]) {}
+    }
+}
+
+/// Sets up the log backend, if it needs it. Called once from `start_kernel`,
+/// before the first `klog!`.
+#[cfg(not(feature = "klog-rtt"))]
+pub(crate) fn init() {}
+
+/// Writes a formatted line to the log. This is the guts of `klog!`; use that
+/// instead.
+#[inline(always)]
+pub(crate) fn write_line(args: core::fmt::Arguments<'_>) {
+    // Checking a constant, rather than leaving formatting to a no-op writer,
+    // lets the formatting machinery be dropped entirely when there's no
+    // backend.
+    if ENABLED {
+        write_line_impl(args);
+    }
+}
+
+#[inline(never)]
+fn write_line_impl(args: core::fmt::Arguments<'_>) {
+    use core::fmt::Write;
+
+    struct Log;
+
+    impl Write for Log {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            write_bytes(s.as_bytes());
+            Ok(())
+        }
+    }
+
+    Log.write_fmt(args).ok();
+    write_bytes(b"\n");
+}
+
+#[cfg(feature = "klog-rtt")]
+mod rtt {
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    /// Size of the ring the log is written into.
+    pub const BUFFER_LEN: usize = 1024;
+
+    /// Channel flag telling the host that writes which don't fit are cut
+    /// short, rather than blocking.
+    const MODE_NO_BLOCK_TRIM: u32 = 1;
+
+    /// An RTT up-channel, as laid out in memory. `write` is only advanced by
+    /// us, and `read` only by the probe.
+    #[repr(C)]
+    pub struct Channel {
+        name: *const u8,
+        buffer: *mut u8,
+        size: u32,
+        write: AtomicU32,
+        read: AtomicU32,
+        flags: u32,
+    }
+
+    /// The RTT control block, with one up-channel and no down-channels.
+    #[repr(C)]
+    pub struct ControlBlock {
+        /// "SEGGER RTT", which probes search RAM for. This is written last,
+        /// so that a probe can't find the block half-built.
+        id: [u8; 16],
+        max_up: u32,
+        max_down: u32,
+        up: Channel,
+    }
+
+    #[used]
+    #[no_mangle]
+    static mut _SEGGER_RTT: ControlBlock = ControlBlock {
+        id: [0; 16],
+        max_up: 1,
+        max_down: 0,
+        up: Channel {
+            name: core::ptr::null(),
+            buffer: core::ptr::null_mut(),
+            size: 0,
+            write: AtomicU32::new(0),
+            read: AtomicU32::new(0),
+            flags: MODE_NO_BLOCK_TRIM,
+        },
+    };
+
+    static mut BUFFER: [u8; BUFFER_LEN] = [0; BUFFER_LEN];
+
+    pub(crate) fn init() {
+        // Safety: we're called once from `start_kernel`, before anything
+        // else can touch the control block, and without the ID a probe won't
+        // be reading it either.
+        unsafe {
+            let cb = &mut *core::ptr::addr_of_mut!(_SEGGER_RTT);
+            cb.up.name = c"Terminal".as_ptr().cast();
+            cb.up.buffer = core::ptr::addr_of_mut!(BUFFER).cast();
+            cb.up.size = BUFFER_LEN as u32;
+            core::sync::atomic::fence(Ordering::SeqCst);
+            let id = core::ptr::addr_of_mut!(cb.id).cast::<u8>();
+            for (i, &b) in b"SEGGER RTT".iter().enumerate() {
+                id.add(i).write_volatile(b);
+            }
+        }
+    }
+
+    pub(super) fn write_bytes(bytes: &[u8]) {
+        // Safety: the kernel is single-threaded and not reentrant, so we're
+        // the only writer; the probe only ever moves `read`, which is atomic.
+        let up = unsafe { &(*core::ptr::addr_of!(_SEGGER_RTT)).up };
+        if up.buffer.is_null() {
+            return;
+        }
+        let read = up.read.load(Ordering::Acquire);
+        let mut write = up.write.load(Ordering::Relaxed);
+        for &b in bytes {
+            let next = (write + 1) % up.size;
+            if next == read {
+                break;
+            }
+            // Safety: `write` is always less than `size`, which is the length
+            // of `BUFFER`, and the probe won't read this byte until we move
+            // `write` past it.
+            unsafe {
+                up.buffer.add(write as usize).write_volatile(b);
+            }
+            write = next;
+        }
+        up.write.store(write, Ordering::Release);
+    }
+}
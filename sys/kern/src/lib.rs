@@ -34,17 +34,26 @@
 
 #[macro_use]
 pub mod arch;
+#[macro_use]
+mod klog;
 
 pub mod atomic;
 mod descs;
 pub mod err;
 pub mod fail;
 pub mod header;
+pub mod idle;
+mod image_check;
+pub mod irq;
 pub mod kipc;
+mod measurements;
 pub mod profiling;
+pub mod replay;
 pub mod startup;
 pub mod syscalls;
 pub mod task;
 pub mod time;
+pub mod trace;
 pub mod umem;
 pub mod util;
+pub mod watchdog;
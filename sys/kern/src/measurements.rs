@@ -0,0 +1,80 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Boot measurements handed over by the bootloader.
+//!
+//! The build system records the address and size of
+//! `HUBRIS_BOOT_MEASUREMENTS` in the image header, where a bootloader can
+//! find it without knowing anything else about the image. It lives in
+//! `.uninit`, so the reset handler leaves what the bootloader wrote there
+//! alone.
+//!
+//! At startup we check that the header really does point here and that the
+//! record is intact, keep a copy for the `read_boot_measurements` kipc, and
+//! wipe the original. Tasks never see the RAM the bootloader wrote.
+
+use abi::BootMeasurements;
+use core::mem::{size_of, MaybeUninit};
+
+/// Where the bootloader leaves its record. The image header points here.
+#[used]
+#[no_mangle]
+#[link_section = ".uninit.HUBRIS_BOOT_MEASUREMENTS"]
+static mut HUBRIS_BOOT_MEASUREMENTS: MaybeUninit<BootMeasurements> =
+    MaybeUninit::uninit();
+
+/// The record as checked at startup, if there was a good one. Written once
+/// by `init`, before any task runs, and only read after that.
+static mut CHECKED: Option<BootMeasurements> = None;
+
+/// Checks and takes the record the bootloader left, if any.
+///
+/// Having no record isn't an error: not every bootloader leaves one. Callers
+/// of the kipc find out that there wasn't one, and can decide for themselves
+/// what that means.
+pub(crate) fn init() {
+    let slot = core::ptr::addr_of_mut!(HUBRIS_BOOT_MEASUREMENTS)
+        .cast::<BootMeasurements>();
+
+    // Safety: the header is populated by the build system and only ever read.
+    let (format, addr, len) = unsafe {
+        (
+            crate::header::HEADER.format,
+            crate::header::HEADER.boot_measurements,
+            crate::header::HEADER.boot_measurements_len,
+        )
+    };
+    if format < 2
+        || addr != slot as u32
+        || len as usize != size_of::<BootMeasurements>()
+    {
+        klog!("image header doesn't point at boot measurements");
+        return;
+    }
+
+    // Safety: we're in `start_kernel`, which is only called once and has no
+    // concurrency. Any bit pattern is a valid `BootMeasurements`, so reading
+    // whatever is in uninitialized RAM is fine; we check it below.
+    let record = unsafe { slot.read_volatile() };
+    // Safety: as above. Wiping the record means that a reset that doesn't go
+    // through the bootloader can't pass a stale one off as fresh.
+    unsafe { slot.write_volatile(BootMeasurements::EMPTY) };
+
+    if record.is_valid() {
+        klog!("boot measurements: flags {:#x}", record.flags);
+        // Safety: as above; no task is running to read this yet.
+        unsafe {
+            CHECKED = Some(record);
+        }
+    } else {
+        klog!("no valid boot measurements");
+    }
+}
+
+/// Returns the record checked at startup, if there was a good one.
+pub(crate) fn get() -> Option<BootMeasurements> {
+    // Safety: this is only written by `init`, before any task runs, so by the
+    // time a kipc can ask for it, it's only ever read.
+    unsafe { *core::ptr::addr_of!(CHECKED) }
+}
@@ -0,0 +1,240 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Scheduling replay log.
+//!
+//! When the kernel is built with the `replay` feature, it records every
+//! scheduling decision, notification post, and interrupt delivery, in order,
+//! into `KERNEL_REPLAY`. Where `trace` is for a person to read, this is for the
+//! `sim` backend to play back: given a log taken from a unit that failed in the
+//! field, the simulator makes the same scheduling decisions and delivers the
+//! same interrupts at the same points, so that a race seen once on hardware
+//! can be reproduced under a debugger.
+//!
+//! Records are eight bytes. The log starts empty at boot and fills from the
+//! front, then wraps; `next` counts every record ever written. Only a log that
+//! hasn't wrapped can be played back, because playback has to start from boot.
+//!
+//! Playback is faithful as long as the tasks do the same thing in response to
+//! the same sequence of events, which holds for tasks that see the world only
+//! through the kernel. Where the simulated system does something the log
+//! didn't record -- a task posts a different notification, or the recorded
+//! choice of task isn't runnable -- playback stops at that record, which
+//! `divergence` reports, and the system carries on scheduling normally.
+
+use crate::task::{NotificationSet, Task};
+
+/// Number of records kept. Once full, older records are overwritten.
+pub const REPLAY_LEN: usize = 512;
+
+/// Kinds of record in the log. The meanings of the `task` and `arg` fields of
+/// a `ReplayRecord` depend on the event.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum ReplayEvent {
+    /// The scheduler chose `task` to run next, searching from the task at
+    /// index `arg`.
+    Select = 1,
+    /// Notification bits `arg` (the low 32) were posted to `task`.
+    Post = 2,
+    /// Follows a `Post` that included any of the high 32 notification bits,
+    /// which are `arg`.
+    PostHigh = 3,
+    /// Hardware interrupt `arg` was delivered to `task`.
+    Irq = 4,
+}
+
+/// A single replay record.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[repr(C)]
+pub struct ReplayRecord {
+    /// Low 16 bits of the kernel tick count when the event was recorded.
+    pub tick: u16,
+    /// A `ReplayEvent`, or 0 for a slot that's never been written.
+    pub event: u8,
+    /// Index of the task involved.
+    pub task: u8,
+    /// Event-specific argument.
+    pub arg: u32,
+}
+
+/// The replay log, as laid out in memory.
+#[repr(C)]
+pub struct ReplayBuffer {
+    /// Total number of records ever written, modulo 2^32. While this is at most
+    /// `REPLAY_LEN`, the log holds everything since boot.
+    pub next: u32,
+    pub records: [ReplayRecord; REPLAY_LEN],
+}
+
+// Task indices are recorded in a byte.
+#[cfg(feature = "replay")]
+const _: () = assert!(crate::startup::HUBRIS_TASK_COUNT <= 256);
+
+#[cfg(feature = "replay")]
+#[used]
+#[no_mangle]
+static mut KERNEL_REPLAY: ReplayBuffer = ReplayBuffer {
+    next: 0,
+    records: [ReplayRecord {
+        tick: 0,
+        event: 0,
+        task: 0,
+        arg: 0,
+    }; REPLAY_LEN],
+};
+
+/// Appends a record to the log, and checks it against the log being played
+/// back, if any.
+#[inline(always)]
+fn record(event: ReplayEvent, task: usize, arg: u32) {
+    #[cfg(feature = "replay")]
+    {
+        let rec = ReplayRecord {
+            tick: u64::from(crate::arch::now()) as u16,
+            event: event as u8,
+            task: task as u8,
+            arg,
+        };
+        // Safety: the kernel is single-threaded and not reentrant, so nothing
+        // else is accessing the buffer.
+        unsafe {
+            let buf = &mut *core::ptr::addr_of_mut!(KERNEL_REPLAY);
+            buf.records[buf.next as usize % REPLAY_LEN] = rec;
+            buf.next = buf.next.wrapping_add(1);
+        }
+        #[cfg(not(target_os = "none"))]
+        player::check(rec);
+    }
+    #[cfg(not(feature = "replay"))]
+    {
+        let _ = (event, task, arg);
+    }
+}
+
+/// Chooses the next task to run, as the log being played back says, or with
+/// `scan` if there isn't one. Records the choice either way.
+#[inline(always)]
+pub(crate) fn select(
+    previous: usize,
+    tasks: &[Task],
+    scan: impl FnOnce() -> usize,
+) -> usize {
+    #[cfg(all(feature = "replay", not(target_os = "none")))]
+    let next = match player::next_select() {
+        Some(i) if tasks.get(i).is_some_and(Task::is_runnable) => i,
+        _ => scan(),
+    };
+    #[cfg(not(all(feature = "replay", not(target_os = "none"))))]
+    let next = {
+        let _ = tasks;
+        scan()
+    };
+    record(ReplayEvent::Select, next, previous as u32);
+    next
+}
+
+/// Records notifications posted to `task`.
+#[inline(always)]
+pub(crate) fn post(task: usize, n: NotificationSet) {
+    record(ReplayEvent::Post, task, n.0 as u32);
+    let high = (n.0 >> 32) as u32;
+    if high != 0 {
+        record(ReplayEvent::PostHigh, task, high);
+    }
+}
+
+/// Records the delivery of interrupt `irq` to `task`.
+#[inline(always)]
+pub(crate) fn irq(task: usize, irq: u32) {
+    record(ReplayEvent::Irq, task, irq);
+}
+
+#[cfg(all(feature = "replay", not(target_os = "none")))]
+pub use player::{divergence, load, next_irq};
+
+/// Playback, for the `sim` backend.
+#[cfg(all(feature = "replay", not(target_os = "none")))]
+mod player {
+    use super::{ReplayEvent, ReplayRecord, REPLAY_LEN};
+    use std::sync::Mutex;
+
+    struct Player {
+        records: Vec<ReplayRecord>,
+        /// Index of the next record we expect to see.
+        cursor: usize,
+        /// Index of the record at which the system stopped following the log.
+        diverged: Option<usize>,
+    }
+
+    static PLAYER: Mutex<Option<Player>> = Mutex::new(None);
+
+    /// Loads a log for the kernel to follow, from a `KERNEL_REPLAY` buffer
+    /// read out of a dump. This must be called before `start_kernel`.
+    ///
+    /// # Panics
+    ///
+    /// If the log has wrapped, and so doesn't start from boot.
+    pub fn load(next: u32, records: &[ReplayRecord]) {
+        let n = next as usize;
+        assert!(n <= REPLAY_LEN, "replay log has wrapped");
+        *PLAYER.lock().unwrap() = Some(Player {
+            records: records[..n].to_vec(),
+            cursor: 0,
+            diverged: None,
+        });
+    }
+
+    /// Returns the index of the record at which playback stopped, if it has
+    /// diverged from the log.
+    pub fn divergence() -> Option<usize> {
+        PLAYER.lock().unwrap().as_ref().and_then(|p| p.diverged)
+    }
+
+    /// Returns the next record, if we're still following the log.
+    fn peek(player: &Player) -> Option<ReplayRecord> {
+        if player.diverged.is_some() {
+            return None;
+        }
+        player.records.get(player.cursor).copied()
+    }
+
+    /// Returns the task the log says the scheduler picks next, if that's what
+    /// the log expects to happen next.
+    pub(super) fn next_select() -> Option<usize> {
+        let guard = PLAYER.lock().unwrap();
+        let rec = peek(guard.as_ref()?)?;
+        (rec.event == ReplayEvent::Select as u8)
+            .then_some(usize::from(rec.task))
+    }
+
+    /// Returns the interrupt the log says is delivered next, if that's what
+    /// the log expects to happen next. The backend should deliver it before
+    /// letting a task run.
+    pub fn next_irq() -> Option<u32> {
+        let guard = PLAYER.lock().unwrap();
+        let rec = peek(guard.as_ref()?)?;
+        (rec.event == ReplayEvent::Irq as u8).then_some(rec.arg)
+    }
+
+    /// Compares a record just made against the log, moving on if it matches
+    /// and stopping playback if it doesn't. Ticks aren't compared, since
+    /// simulated time doesn't keep pace with the hardware's.
+    pub(super) fn check(rec: ReplayRecord) {
+        let mut guard = PLAYER.lock().unwrap();
+        let Some(player) = guard.as_mut() else {
+            return;
+        };
+        let Some(expected) = peek(player) else {
+            return;
+        };
+        if (expected.event, expected.task, expected.arg)
+            == (rec.event, rec.task, rec.arg)
+        {
+            player.cursor += 1;
+        } else {
+            player.diverged = Some(player.cursor);
+        }
+    }
+}
@@ -5,7 +5,9 @@
 //! Kernel startup.
 
 use crate::atomic::AtomicExt;
-use crate::descs::{RegionAttributes, RegionDesc, TaskDesc, TaskFlags};
+use crate::descs::{
+    RamFill, RamInit, RegionAttributes, RegionDesc, TaskDesc, TaskFlags,
+};
 use crate::task::Task;
 use core::mem::MaybeUninit;
 use core::sync::atomic::{AtomicBool, Ordering};
@@ -19,7 +21,42 @@ use core::sync::atomic::{AtomicBool, Ordering};
 /// `false` late in `start_kernel`.
 static TASK_TABLE_IN_USE: AtomicBool = AtomicBool::new(true);
 
-pub const HUBRIS_FAULT_NOTIFICATION: u32 = 1;
+/// Set once the task table has been initialized, never to be cleared. Unlike
+/// `TASK_TABLE_IN_USE`, this tells us whether the table is safe to *look at*
+/// even while someone else holds it, which is what the crash dump needs.
+static TASK_TABLE_READY: AtomicBool = AtomicBool::new(false);
+
+pub const HUBRIS_FAULT_NOTIFICATION: u64 = 1;
+
+/// Image features this kernel knows how to provide. An image requiring
+/// anything outside this set is refused at boot.
+///
+/// This lists features one by one, rather than taking everything in
+/// `abi::ImageFeatures`, so that a bit added there isn't claimed until the
+/// kernel actually implements it.
+pub const SUPPORTED_FEATURES: abi::ImageFeatures = {
+    use abi::ImageFeatures as F;
+    let features = F::MAILBOXES
+        .union(F::RESTART_THROTTLE)
+        .union(F::IRQ_STORM_LIMIT)
+        .union(F::WATCHDOG)
+        .union(F::REPLY_DEADMAN)
+        .union(F::IMAGE_CHECK);
+    // Only the ARMv7-M and ARMv8-M MPU code loads regions on demand.
+    if cfg!(any(armv7m, armv8m)) {
+        features.union(F::VIRTUAL_REGIONS)
+    } else {
+        features
+    }
+};
+
+/// Bits from `abi::ImageFeatures` that are in effect for the running image:
+/// those it asked for, required or optional, that this kernel supports.
+///
+/// This is written once during startup and exists for the benefit of
+/// debuggers and crash dumps.
+#[no_mangle]
+static mut HUBRIS_ACTIVE_FEATURES: u32 = 0;
 
 /// The main kernel entry point.
 ///
@@ -28,9 +65,11 @@ pub const HUBRIS_FAULT_NOTIFICATION: u32 = 1;
 ///
 /// Parameters:
 ///
-/// - `tick_divisor`: a platform-specific way of converting "machine ticks" into
-///   "kernel ticks." On ARM M-profile, this is CPU cycles per tick, where a
-///   tick is typically a millisecond.
+/// - `cycles_per_ms`: the rate of the architecture's timer, in counts per
+///   millisecond. On ARM M-profile, this is the CPU clock in kHz. The kernel
+///   divides this down to the application's tick rate (`tick-hz` in the
+///   `[kernel]` config, by default 1000), and panics if the result is zero or
+///   too big for the timer.
 ///
 /// # Safety
 ///
@@ -39,16 +78,48 @@ pub const HUBRIS_FAULT_NOTIFICATION: u32 = 1;
 /// privileged mode.
 ///
 /// This function may not be called reentrantly or from multiple cores.
-pub unsafe fn start_kernel(tick_divisor: u32) -> ! {
+pub unsafe fn start_kernel(cycles_per_ms: u32) -> ! {
     // Set our clock frequency so debuggers can find it as needed
     //
     // Safety: TODO it is not clear that this operation needs to be unsafe.
     unsafe {
-        crate::arch::set_clock_freq(tick_divisor);
+        crate::arch::set_clock_freq(cycles_per_ms);
     }
+    let Some(tick_divisor) = crate::time::tick_divisor(cycles_per_ms) else {
+        panic!("tick rate out of range for timer");
+    };
+    crate::trace::init();
+    crate::klog::init();
+    klog!("kernel starting at {} kHz", cycles_per_ms);
 
     // Grab references to all our statics.
     let task_descs = &HUBRIS_TASK_DESCS;
+
+    // Make sure the image we've been handed is sane before we start trusting
+    // it.
+    if let Err(e) = validate_image(task_descs, &HUBRIS_REGION_DESCS) {
+        crate::fail::boot_failed(e);
+    }
+    crate::measurements::init();
+    crate::image_check::check();
+
+    // Sticky regions survive task restarts, but not reboots: start them off
+    // zeroed, so their owners can tell that there's nothing in them yet.
+    for region in &HUBRIS_REGION_DESCS {
+        if region.attributes.contains(RegionAttributes::STICKY) {
+            // Safety: validation has checked that this is writable normal
+            // memory belonging to (at most) one task, none of which is
+            // running yet.
+            unsafe {
+                core::ptr::write_bytes(
+                    region.base as *mut u8,
+                    0,
+                    region.size as usize,
+                );
+            }
+        }
+    }
+
     // Safety: this reference will remain unique so long as the "only called
     // once per boot" contract on this function is upheld.
     let task_table =
@@ -56,13 +127,6 @@ pub unsafe fn start_kernel(tick_divisor: u32) -> ! {
 
     // Initialize our RAM data structures.
 
-    // We currently just refer to the RegionDescs in Flash. No additional
-    // preparation of those structures is required here. This will almost
-    // certainly need to change in the future: we can save many cycles by (1)
-    // storing them in an architecture-optimized format for this particular MPU,
-    // and (2) moving them into RAM where random accesses don't imply wait
-    // states.
-
     // Now, generate the task table.
     // Safety: MaybeUninit<[T]> -> [MaybeUninit<T>] is defined as safe.
     let task_table: &mut [MaybeUninit<Task>; HUBRIS_TASK_COUNT] =
@@ -75,17 +139,44 @@ pub unsafe fn start_kernel(tick_divisor: u32) -> ! {
     let task_table: &mut [Task; HUBRIS_TASK_COUNT] =
         unsafe { &mut *(task_table as *mut _ as *mut _) };
 
-    // With that done, set up initial register state etc.
+    // With that done, set up initial memory, register state, etc.
     for task in task_table.iter_mut() {
+        task.init_ram();
         crate::arch::reinitialize(task);
     }
 
+    // Convert each task's RegionDescs (in flash, in an architecture-neutral
+    // format) into the values the memory protection hardware wants, stored in
+    // RAM. This saves a lot of work on every context switch.
+    for task in task_table.iter() {
+        // Safety: no task has been scheduled yet.
+        unsafe {
+            crate::arch::precompute_memory_protection(task);
+        }
+    }
+
+    // Divide the mailbox space up among the tasks that want it.
+    //
+    // Safety: as with the task table, this reference will remain unique so long
+    // as this function is only called once per boot, and we hand out disjoint
+    // pieces of it.
+    let mut mailbox_space: &'static mut [crate::task::AsyncMessage] =
+        unsafe { &mut *core::ptr::addr_of_mut!(HUBRIS_MAILBOX_SPACE) };
+    for task in task_table.iter_mut() {
+        let depth = usize::from(task.descriptor().mailbox_depth);
+        let (slots, rest) =
+            core::mem::take(&mut mailbox_space).split_at_mut(depth);
+        task.set_mailbox(slots);
+        mailbox_space = rest;
+    }
+
     // Great! Pick our first task. We'll act like we're scheduling after the
     // last task, which will cause a scan from 0 on.
     let first_task_index =
         crate::task::select(task_table.len() - 1, task_table);
 
     crate::arch::apply_memory_protection(&task_table[first_task_index]);
+    TASK_TABLE_READY.store(true, Ordering::Release);
     TASK_TABLE_IN_USE.store(false, Ordering::Release);
     crate::arch::start_first_task(
         tick_divisor,
@@ -93,6 +184,273 @@ pub unsafe fn start_kernel(tick_divisor: u32) -> ! {
     )
 }
 
+/// Reasons the kernel may refuse to boot an image.
+///
+/// Most of these indicate a malformed image, which the build system should
+/// never produce; the header errors indicate an image built for a newer
+/// kernel than this one. Rather than panicking with an opaque message, the
+/// kernel records the error in `kern::fail::KERNEL_BOOT_FAULT` before halting.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BootError {
+    /// The image header doesn't contain `abi::HEADER_MAGIC`.
+    BadHeaderMagic,
+    /// The region descriptor at `index` has an invalid `field`.
+    BadRegion { index: usize, field: RegionField },
+    /// The task descriptor at `index` has an invalid `field`.
+    BadTask { index: usize, field: TaskField },
+    /// No task is marked to start at boot, so there's nothing to run.
+    NoRunnableTask,
+    /// The image header's `format` is newer than this kernel understands.
+    UnsupportedHeaderFormat { format: u32 },
+    /// The image requires features, given as `abi::ImageFeatures` bits, that
+    /// this kernel doesn't provide.
+    MissingFeatures { missing: u32 },
+}
+
+impl BootError {
+    /// Numeric code for this error, as stored in a `BootFaultRecord`.
+    pub fn reason_code(&self) -> u32 {
+        match self {
+            Self::BadHeaderMagic => 1,
+            Self::BadRegion { .. } => 2,
+            Self::BadTask { .. } => 3,
+            Self::NoRunnableTask => 4,
+            Self::UnsupportedHeaderFormat { .. } => 5,
+            Self::MissingFeatures { .. } => 6,
+        }
+    }
+
+    /// Index of the offending descriptor, or 0 if not applicable.
+    pub fn index(&self) -> u32 {
+        match self {
+            Self::BadRegion { index, .. } | Self::BadTask { index, .. } => {
+                *index as u32
+            }
+            _ => 0,
+        }
+    }
+
+    /// Numeric code for the offending field, or 0 if not applicable.
+    ///
+    /// For header errors this instead carries the unsupported format number,
+    /// or the missing feature bits.
+    pub fn field(&self) -> u32 {
+        match self {
+            Self::BadRegion { field, .. } => *field as u32,
+            Self::BadTask { field, .. } => *field as u32,
+            Self::UnsupportedHeaderFormat { format } => *format,
+            Self::MissingFeatures { missing } => *missing,
+            _ => 0,
+        }
+    }
+}
+
+/// Fields of a `RegionDesc` that can fail validation.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum RegionField {
+    /// The region runs off the end of the address space.
+    Size = 1,
+    /// Reserved attribute bits are set.
+    Attributes = 2,
+    /// The region is writable normal memory, but is mapped by more than one
+    /// task.
+    Sharing = 3,
+    /// The region is marked sticky, but isn't writable normal memory.
+    Sticky = 4,
+    /// The region overlaps another with a different memory type (device, DMA,
+    /// or normal memory).
+    MemoryType = 5,
+}
+
+/// Fields of a `TaskDesc` that can fail validation.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum TaskField {
+    /// The descriptor's index doesn't match its position in the table.
+    Index = 1,
+    /// The entry point isn't in an executable region of the task.
+    EntryPoint = 2,
+    /// The initial stack pointer isn't in (or just past) a writable region of
+    /// the task.
+    InitialStack = 3,
+    /// The task's regions aren't sorted by base address.
+    Regions = 4,
+    /// Reserved flag bits are set.
+    Flags = 5,
+    /// A `ram_init` range is misaligned, isn't within one writable region of
+    /// the task, or overlaps a sticky region.
+    RamInit = 6,
+}
+
+/// Checks the image header and the task and region tables for problems that
+/// would violate kernel invariants.
+fn validate_image(
+    task_descs: &[TaskDesc],
+    region_descs: &[RegionDesc],
+) -> Result<(), BootError> {
+    // Safety: the header is populated by the build system and only ever read.
+    let magic = unsafe { crate::header::HEADER.magic };
+    if magic != abi::HEADER_MAGIC {
+        return Err(BootError::BadHeaderMagic);
+    }
+
+    // Images predating the format field have zeros in it and in the feature
+    // bits, and need nothing beyond the basics, so they pass these checks.
+    //
+    // Safety: as above.
+    let (format, required, optional) = unsafe {
+        (
+            crate::header::HEADER.format,
+            crate::header::HEADER.required_features,
+            crate::header::HEADER.optional_features,
+        )
+    };
+    if format > abi::HEADER_FORMAT {
+        return Err(BootError::UnsupportedHeaderFormat { format });
+    }
+    let missing = required & !SUPPORTED_FEATURES.bits();
+    if missing != 0 {
+        return Err(BootError::MissingFeatures { missing });
+    }
+    // Safety: we're single-threaded during startup, and nothing else writes
+    // this.
+    unsafe {
+        HUBRIS_ACTIVE_FEATURES =
+            (required | optional) & SUPPORTED_FEATURES.bits();
+    }
+
+    for (index, region) in region_descs.iter().enumerate() {
+        let bad = |field| BootError::BadRegion { index, field };
+        if region.base.checked_add(region.size).is_none() {
+            return Err(bad(RegionField::Size));
+        }
+        if region.attributes.intersects(RegionAttributes::RESERVED) {
+            return Err(bad(RegionField::Attributes));
+        }
+        if region.attributes.contains(RegionAttributes::STICKY)
+            && (!region.attributes.contains(RegionAttributes::WRITE)
+                || region.attributes.intersects(
+                    RegionAttributes::DEVICE | RegionAttributes::DMA,
+                ))
+        {
+            return Err(bad(RegionField::Sticky));
+        }
+        // Tasks can share regions -- that's how peripherals and read-only
+        // tables get mapped into more than one task -- but sharing writable
+        // memory would break isolation between them. (Device and DMA regions
+        // are exempt: those are shared with hardware anyway.)
+        let private = RegionAttributes::WRITE;
+        let exempt = RegionAttributes::DEVICE | RegionAttributes::DMA;
+        if region.attributes.contains(private)
+            && !region.attributes.intersects(exempt)
+        {
+            let users = task_descs
+                .iter()
+                .filter(|t| {
+                    t.regions
+                        .iter()
+                        .chain(t.extra_regions)
+                        .any(|&r| core::ptr::eq(r, region))
+                })
+                .count();
+            if users > 1 {
+                return Err(bad(RegionField::Sharing));
+            }
+        }
+        // The MPU maps memory with the type its region gives it, so the same
+        // address mustn't look cacheable to one task and not to another. Once
+        // the data cache is on, switching between the two would leave stale
+        // lines behind that no one is responsible for.
+        let kind = RegionAttributes::DEVICE | RegionAttributes::DMA;
+        let end = u64::from(region.base) + u64::from(region.size);
+        if region_descs[..index].iter().any(|other| {
+            let other_end = u64::from(other.base) + u64::from(other.size);
+            u64::from(other.base) < end
+                && u64::from(region.base) < other_end
+                && (other.attributes & kind) != (region.attributes & kind)
+        }) {
+            return Err(bad(RegionField::MemoryType));
+        }
+    }
+
+    for (index, task) in task_descs.iter().enumerate() {
+        let bad = |field| BootError::BadTask { index, field };
+        if usize::from(task.index) != index {
+            return Err(bad(TaskField::Index));
+        }
+        if task.flags.intersects(TaskFlags::RESERVED) {
+            return Err(bad(TaskField::Flags));
+        }
+        // The kernel exploits the sort order of regions to do cheaper access
+        // checks, so this is important.
+        if !task.regions.windows(2).all(|w| w[0].base <= w[1].base)
+            || !task
+                .extra_regions
+                .windows(2)
+                .all(|w| w[0].base <= w[1].base)
+        {
+            return Err(bad(TaskField::Regions));
+        }
+        let entry_ok = task.regions.iter().any(|r| {
+            r.attributes.contains(RegionAttributes::EXECUTE)
+                && r.contains(task.entry_point as usize)
+        });
+        if !entry_ok {
+            return Err(bad(TaskField::EntryPoint));
+        }
+        // The initial stack pointer is allowed to point just past the end of
+        // its region, since stacks grow down.
+        let stack_ok = task.regions.iter().any(|r| {
+            r.attributes.contains(RegionAttributes::WRITE)
+                && r.base < task.initial_stack
+                && task.initial_stack <= r.end_addr()
+        });
+        if !stack_ok {
+            return Err(bad(TaskField::InitialStack));
+        }
+        // `Task::init_ram` checks access again before touching anything, but
+        // it's much easier to diagnose a bad range here than as a panic when
+        // the task restarts.
+        let ram_init_ok = task.ram_init.iter().all(|init| {
+            let source_ok = match init.fill {
+                RamFill::Copy { source } => source % 4 == 0,
+                _ => true,
+            };
+            init.base % 4 == 0
+                && init.size % 4 == 0
+                && source_ok
+                && task.regions.iter().chain(task.extra_regions).any(|r| {
+                    r.attributes.contains(RegionAttributes::WRITE)
+                        && r.base <= init.base
+                        && init
+                            .base
+                            .checked_add(init.size)
+                            .is_some_and(|end| end <= r.end_addr())
+                })
+                // Reinitializing part of a sticky region would defeat the
+                // point of it.
+                && !task.regions.iter().chain(task.extra_regions).any(|r| {
+                    r.attributes.contains(RegionAttributes::STICKY)
+                        && init.base < r.end_addr()
+                        && r.base < init.base + init.size
+                })
+        });
+        if !ram_init_ok {
+            return Err(bad(TaskField::RamInit));
+        }
+    }
+
+    if !task_descs
+        .iter()
+        .any(|t| t.flags.contains(TaskFlags::START_AT_BOOT))
+    {
+        return Err(BootError::NoRunnableTask);
+    }
+
+    Ok(())
+}
+
 /// Runs `body` with a reference to the task table.
 ///
 /// To preserve uniqueness of the `&mut` reference passed into `body`, this
@@ -127,4 +485,24 @@ pub(crate) fn with_task_table<R>(body: impl FnOnce(&mut [Task]) -> R) -> R {
     r
 }
 
+/// Returns a shared view of the task table for the crash dump writer, or `None`
+/// if the kernel died before the table was initialized.
+///
+/// # Safety
+///
+/// This ignores `TASK_TABLE_IN_USE`, so the result may alias a `&mut` held
+/// further up the stack. It's only to be used on the way down in
+/// `fail::die`, when nothing will touch the table again.
+#[cfg(not(feature = "nano"))]
+pub(crate) unsafe fn task_table_for_dump() -> Option<&'static [Task]> {
+    if !TASK_TABLE_READY.load(Ordering::Acquire) {
+        return None;
+    }
+    let task_table: *const [Task; HUBRIS_TASK_COUNT] =
+        core::ptr::addr_of!(HUBRIS_TASK_TABLE_SPACE) as _;
+    // Safety: the table is initialized, per TASK_TABLE_READY; our caller has
+    // accepted the aliasing caveat.
+    Some(unsafe { &*task_table })
+}
+
 include!(concat!(env!("OUT_DIR"), "/kconfig.rs"));
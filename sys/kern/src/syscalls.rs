@@ -25,23 +25,26 @@
 //! Arguments to syscalls need to be read from the `task.save()` structure where
 //! the task's registers are stored. Each class of syscall has an *argument
 //! struct* type to make this easy and safe, e.g. `task.save().as_send_args()`.
-//! See the `task::ArchState` trait for details.
+//! See the `task::ArchState` trait for details. The decoding itself lives in
+//! `kerncore::args`, where it can be tested on the host.
 
 #[cfg(hubris_phantom_svc_mitigation)]
 use core::sync::atomic::{AtomicBool, Ordering};
 
 use abi::{
     FaultInfo, IrqStatus, LeaseAttributes, SchedState, Sysnum, TaskId,
-    TaskState, ULease, UsageError,
+    TaskState, UBorrow, ULeaseSegment, UsageError, MAX_BORROW_VECTORS,
 };
-use unwrap_lite::UnwrapLite;
 
 use crate::arch;
 use crate::err::{InteractFault, UserError};
 use crate::startup::with_task_table;
 use crate::task::{self, current_id, ArchState, NextTask, Task};
 use crate::time::Timestamp;
-use crate::umem::{safe_copy, USlice};
+use crate::trace::TraceEvent;
+use crate::umem::{
+    safe_copy, safe_copy_from_lease, safe_copy_to_lease, LeasedArea, USlice,
+};
 
 #[cfg(hubris_phantom_svc_mitigation)]
 pub(crate) static EXPECT_PHANTOM_SYSCALL: AtomicBool = AtomicBool::new(false);
@@ -73,8 +76,11 @@ pub unsafe extern "C" fn syscall_entry(nr: u32, task: *mut Task) {
         let t = unsafe { &*task };
         usize::from(t.descriptor().index)
     };
+    crate::trace::record(TraceEvent::Syscall, idx, nr);
 
     with_task_table(|tasks| {
+        arch::account_kernel_entry(tasks);
+
         // On certain architectures we risk receiving "phantom SVCs" assigned to
         // tasks that are not, in fact, making system calls. As of this writing,
         // this can occur on ARMv6-M (we believe we have fixed it on later ARM
@@ -96,13 +102,15 @@ pub unsafe extern "C" fn syscall_entry(nr: u32, task: *mut Task) {
             // If we're returning to the same task, we're done!
             NextTask::Same => (),
 
-            NextTask::Specific(i) => {
+            // IPC can unblock a task that's been suspended, and recommend it;
+            // in that case we need to pick someone else.
+            NextTask::Specific(i) if tasks[i].is_runnable() => {
                 // Safety: this is a valid task from the tasks table, meeting
                 // switch_to's requirements.
                 unsafe { switch_to(&mut tasks[i]) }
             }
 
-            NextTask::Other => {
+            NextTask::Specific(_) | NextTask::Other => {
                 let next = task::select(idx, tasks);
                 // Safety: this is a valid task from the tasks table, meeting
                 // switch_to's requirements.
@@ -110,18 +118,82 @@ pub unsafe extern "C" fn syscall_entry(nr: u32, task: *mut Task) {
             }
         }
     });
+    arch::account_kernel_exit();
 
     crate::profiling::event_syscall_exit();
 }
 
+/// Kinds of syscall, as counted for each task in `HUBRIS_SYSCALL_COUNTERS`.
+///
+/// These are coarser than `Sysnum`, to keep the counters small; see
+/// `abi::SyscallCounts` for which syscalls land where.
+#[derive(Copy, Clone, counters::Count)]
+pub(crate) enum SyscallKind {
+    Send,
+    Recv,
+    Reply,
+    Timer,
+    Borrow,
+    Irq,
+    Post,
+    Other,
+}
+
+impl SyscallKind {
+    fn of(nr: Result<&Sysnum, &()>) -> Self {
+        match nr {
+            Ok(Sysnum::Send | Sysnum::SendAsync) => Self::Send,
+            Ok(Sysnum::Recv) => Self::Recv,
+            Ok(Sysnum::Reply | Sysnum::ReplyFault | Sysnum::ReplyLease) => {
+                Self::Reply
+            }
+            Ok(
+                Sysnum::SetTimer | Sysnum::SetTimerPeriodic | Sysnum::GetTimer,
+            ) => Self::Timer,
+            Ok(
+                Sysnum::BorrowRead
+                | Sysnum::BorrowWrite
+                | Sysnum::BorrowInfo
+                | Sysnum::BorrowVectored,
+            ) => Self::Borrow,
+            Ok(Sysnum::IrqControl | Sysnum::IrqStatus) => Self::Irq,
+            Ok(Sysnum::Post) => Self::Post,
+            Ok(Sysnum::Panic | Sysnum::RefreshTaskId) | Err(_) => Self::Other,
+        }
+    }
+}
+
+/// Reads out the syscall counters for task `index`.
+pub(crate) fn syscall_counts(index: usize) -> abi::SyscallCounts {
+    use core::sync::atomic::Ordering::Relaxed;
+
+    let c = &crate::startup::HUBRIS_SYSCALL_COUNTERS[index];
+    abi::SyscallCounts {
+        send: c.Send.load(Relaxed),
+        recv: c.Recv.load(Relaxed),
+        reply: c.Reply.load(Relaxed),
+        timer: c.Timer.load(Relaxed),
+        borrow: c.Borrow.load(Relaxed),
+        irq: c.Irq.load(Relaxed),
+        post: c.Post.load(Relaxed),
+        other: c.Other.load(Relaxed),
+    }
+}
+
 /// Factored out of `syscall_entry` to encapsulate the bits that don't need
 /// unsafe.
 fn safe_syscall_entry(nr: u32, current: usize, tasks: &mut [Task]) -> NextTask {
-    let res = match Sysnum::try_from(nr) {
-        Ok(Sysnum::Send) => send(tasks, current),
+    let nr = Sysnum::try_from(nr);
+    counters::count!(
+        crate::startup::HUBRIS_SYSCALL_COUNTERS[current],
+        SyscallKind::of(nr.as_ref())
+    );
+
+    let res = match nr {
+        Ok(Sysnum::Send) => send(tasks, current, arch::now()),
         Ok(Sysnum::Recv) => recv(tasks, current).map_err(UserError::from),
         Ok(Sysnum::Reply) => reply(tasks, current).map_err(UserError::from),
-        Ok(Sysnum::SetTimer) => Ok(set_timer(&mut tasks[current], arch::now())),
+        Ok(Sysnum::SetTimer) => Ok(set_timer(tasks, current, arch::now())),
         Ok(Sysnum::BorrowRead) => borrow_read(tasks, current),
         Ok(Sysnum::BorrowWrite) => borrow_write(tasks, current),
         Ok(Sysnum::BorrowInfo) => borrow_info(tasks, current),
@@ -134,6 +206,12 @@ fn safe_syscall_entry(nr: u32, current: usize, tasks: &mut [Task]) -> NextTask {
             reply_fault(tasks, current).map_err(UserError::from)
         }
         Ok(Sysnum::IrqStatus) => irq_status(tasks, current),
+        Ok(Sysnum::SendAsync) => send_async(tasks, current),
+        Ok(Sysnum::ReplyLease) => reply_lease(tasks, current),
+        Ok(Sysnum::BorrowVectored) => borrow_vectored(tasks, current),
+        Ok(Sysnum::SetTimerPeriodic) => {
+            Ok(set_timer_periodic(tasks, current, arch::now()))
+        }
         Err(_) => {
             // Bogus syscall number! That's a fault.
             Err(FaultInfo::SyscallUsage(UsageError::BadSyscallNumber).into())
@@ -158,9 +236,15 @@ fn safe_syscall_entry(nr: u32, current: usize, tasks: &mut [Task]) -> NextTask {
 /// # Panics
 ///
 /// If `caller` is out of range for `tasks`.
-fn send(tasks: &mut [Task], caller: usize) -> Result<NextTask, UserError> {
+fn send(
+    tasks: &mut [Task],
+    caller: usize,
+    now: Timestamp,
+) -> Result<NextTask, UserError> {
     // Extract callee.
-    let callee_id = tasks[caller].save().as_send_args().callee;
+    let args = tasks[caller].save().as_send_args();
+    let callee_id = args.callee;
+    tasks[caller].set_urgent(args.urgent);
 
     // Check IPC filter - TODO
     // Open question: should out-of-range task IDs be handled by faulting below,
@@ -173,11 +257,29 @@ fn send(tasks: &mut [Task], caller: usize) -> Result<NextTask, UserError> {
 
     // Verify the given callee ID, converting it into a table index on success.
     let callee = task::check_task_id_against_table(tasks, callee_id)?;
+    crate::trace::record(TraceEvent::Send, caller, callee as u32);
+
+    // Arm (or clear) the deadline for this call. It runs from now until the
+    // reply, whether we block here or in the callee's hands.
+    let deadline = (args.timeout != 0)
+        .then(|| Timestamp::from(u64::from(now) + u64::from(args.timeout)));
+    task::set_ipc_deadline(&mut tasks[caller], deadline);
+
+    // A call to a server that has since restarted can't be answered anymore.
+    if let Some(server) = tasks[caller].abandoned_call() {
+        if current_id(tasks, server.index()) != server {
+            tasks[caller].clear_abandoned_call();
+        }
+    }
 
-    // Check for ready peer.
+    // Check for ready peer. A callee that still owes us a reply to a call
+    // that timed out has to send it (to be dropped) before it can have this
+    // message; until then we block as though it were busy.
     let mut next_task = NextTask::Same;
     let caller_id = current_id(tasks, caller);
-    if tasks[callee].state().can_accept_message_from(caller_id) {
+    if tasks[callee].state().can_accept_message_from(caller_id)
+        && tasks[caller].abandoned_call() != Some(callee_id)
+    {
         // Callee is waiting in receive -- either an open receive, or a
         // closed receive from just us. Either way, we can directly deliver the
         // message and switch tasks...unless either task was naughty, in which
@@ -185,7 +287,9 @@ fn send(tasks: &mut [Task], caller: usize) -> Result<NextTask, UserError> {
         match deliver(tasks, caller, callee) {
             Ok(_) => {
                 // Delivery succeeded! The initiating task is now blocked in
-                // reply. Switch directly to the callee.
+                // reply, and lends its priority to the callee. Switch directly
+                // to the callee.
+                task::update_inherited_priority(tasks, callee);
                 return Ok(NextTask::Specific(callee));
             }
             Err(interact) => {
@@ -203,6 +307,9 @@ fn send(tasks: &mut [Task], caller: usize) -> Result<NextTask, UserError> {
     // Caller needs to block sending, callee is either busy or
     // faulted.
     tasks[caller].set_healthy_state(SchedState::InSend(callee_id));
+    // While blocked, the caller lends its priority to the callee, so that a
+    // less important callee can't hold it up indefinitely.
+    task::update_inherited_priority(tasks, callee);
     // We may not know what task to run next, but we're pretty sure it isn't the
     // caller.
     Ok(NextTask::Other.combine(next_task))
@@ -243,13 +350,9 @@ fn recv(tasks: &mut [Task], caller: usize) -> Result<NextTask, UserError> {
     // if notifications are pending.
     if let Some(firing) = tasks[caller].take_notifications() {
         // Pending! Deliver an artificial message from the kernel.
-        tasks[caller].save_mut().set_recv_result(
-            TaskId::KERNEL,
-            firing,
-            0,
-            0,
-            0,
-        );
+        tasks[caller]
+            .save_mut()
+            .set_recv_notification_result(firing);
         return Ok(NextTask::Same);
     }
 
@@ -277,7 +380,7 @@ fn recv(tasks: &mut [Task], caller: usize) -> Result<NextTask, UserError> {
         let sender_idx = task::check_task_id_against_table(tasks, sender_id)?;
 
         // Second possibility: task has a message for us.
-        if tasks[sender_idx].state().is_sending_to(caller_id) {
+        if tasks[sender_idx].can_deliver_to(caller_id) {
             // Oh hello sender!
             match deliver(tasks, sender_idx, caller) {
                 Ok(_) => {
@@ -319,12 +422,19 @@ fn recv(tasks: &mut [Task], caller: usize) -> Result<NextTask, UserError> {
         //   faulted.
         // - No senders were found (after fault processing) and we have to block
         // the caller.
+
+        // Before any of that, queued asynchronous messages take priority over
+        // blocked senders, oldest first. The caller's receive buffer being
+        // bogus is its own problem, so any fault here lands on the caller (and
+        // the message stays queued).
+        if deliver_async(&mut tasks[caller])? {
+            return Ok(next_task);
+        }
+
         let mut last = caller; // keep track of scan position.
 
         // Is anyone blocked waiting to send to us?
-        while let Some(sender) = task::priority_scan(last, tasks, |t| {
-            t.state().is_sending_to(caller_id)
-        }) {
+        while let Some(sender) = task::sender_scan(last, tasks, caller_id) {
             // Oh hello sender!
             match deliver(tasks, sender, caller) {
                 Ok(()) => {
@@ -378,6 +488,11 @@ fn reply(tasks: &mut [Task], caller: usize) -> Result<NextTask, FaultInfo> {
         Ok(x) => x,
     };
 
+    // If this is the late reply to a call that timed out, drop it. This also
+    // lets the callee's next message, if any, through to us.
+    if tasks[callee].take_abandoned_call(caller_id) {
+        return Ok(NextTask::Same);
+    }
     if tasks[callee].state()
         != &TaskState::Healthy(SchedState::InReply(caller_id))
     {
@@ -386,6 +501,12 @@ fn reply(tasks: &mut [Task], caller: usize) -> Result<NextTask, FaultInfo> {
         // chance to reply (e.g. to implement timeouts).
         return Ok(NextTask::Same);
     }
+    // It's waiting for us, but if we cited a nonce, it had better be waiting
+    // for this reply and not one to a later message.
+    tasks[callee]
+        .check_reply_nonce(reply_args.nonce)
+        .map_err(FaultInfo::SyscallUsage)?;
+    crate::trace::record(TraceEvent::Reply, caller, callee as u32);
 
     // Deliver the reply. Note that we can't use `deliver`, which is
     // specific to a pair of tasks that are sending and receiving,
@@ -444,24 +565,115 @@ fn reply(tasks: &mut [Task], caller: usize) -> Result<NextTask, FaultInfo> {
 
     // KEY ASSUMPTION: sends go from less important tasks to more important
     // tasks. As a result, Reply doesn't have scheduling implications unless
-    // the task using it faults.
-    Ok(NextTask::Same)
+    // the task using it faults -- or it was running on a priority inherited
+    // from the callee, which it now gives up.
+    Ok(drop_inherited_priority(tasks, caller))
+}
+
+/// Implementation of the `REPLY_LEASE` syscall.
+///
+/// This replies to `callee` by copying the reply message straight from the
+/// caller's buffer into a writable lease the callee provided, rather than into
+/// the callee's reply buffer. It's equivalent to a `BORROW_WRITE` followed by a
+/// `REPLY` with an empty message, but takes one kernel entry instead of two,
+/// which matters for servers returning large responses.
+///
+/// Unlike `REPLY`, this reports problems with the callee to the caller: the
+/// callee must be waiting for our reply and must have lent a writable lease at
+/// the given index. If not, the caller gets `DEFECT` and the callee is left as
+/// it was, so the caller can fall back to an ordinary reply (or
+/// `REPLY_FAULT`). As with `REPLY`, citing the wrong reply nonce faults the
+/// caller.
+fn reply_lease(
+    tasks: &mut [Task],
+    caller: usize,
+) -> Result<NextTask, UserError> {
+    let args = tasks[caller].save().as_reply_lease_args();
+    let message = args.message?;
+
+    let callee = task::check_task_id_against_table(tasks, args.callee)?;
+
+    // As in `reply`, a late reply to a call that timed out is dropped.
+    let caller_id = current_id(tasks, caller);
+    if tasks[callee].take_abandoned_call(caller_id) {
+        return Err(UserError::Recoverable(abi::DEFECT, NextTask::Same));
+    }
+
+    // This checks that the callee is blocked in reply to us.
+    let lease =
+        borrow_lease(tasks, caller, callee, args.lease_number, args.offset)?;
+    tasks[callee].check_reply_nonce(args.nonce)?;
+    if !lease.attributes.contains(LeaseAttributes::WRITE) {
+        return Err(UserError::Recoverable(abi::DEFECT, NextTask::Same));
+    }
+
+    let amount_copied =
+        match safe_copy_to_lease(tasks, caller, message, callee, &lease) {
+            Ok(n) => n,
+            Err(interact) => {
+                let wake_hint = interact.apply_to_dst(tasks, callee)?;
+                return Err(UserError::Recoverable(abi::DEFECT, wake_hint));
+            }
+        };
+
+    // Only the caller learns how much went into the lease. As far as the
+    // callee's reply buffer is concerned, the reply was empty: reporting the
+    // lease byte count there would let it read past the end of that buffer.
+    tasks[callee]
+        .save_mut()
+        .set_send_response_and_length(args.response_code, 0);
+    tasks[callee].set_healthy_state(SchedState::Runnable);
+    tasks[caller]
+        .save_mut()
+        .set_borrow_response_and_length(0, amount_copied);
+
+    // Same scheduling reasoning as REPLY.
+    Ok(drop_inherited_priority(tasks, caller))
+}
+
+/// Recomputes the priority of `server` after it has finished with a client. If
+/// it was running with an inherited priority that it has now lost, something
+/// more important may need to run.
+fn drop_inherited_priority(tasks: &mut [Task], server: usize) -> NextTask {
+    if task::update_inherited_priority(tasks, server) {
+        NextTask::Other
+    } else {
+        NextTask::Same
+    }
 }
 
 /// Implementation of the `SET_TIMER` syscall.
-fn set_timer(task: &mut Task, now: Timestamp) -> NextTask {
-    let args = task.save().as_set_timer_args();
-    if let Some(deadline) = args.deadline {
+fn set_timer(tasks: &mut [Task], caller: usize, now: Timestamp) -> NextTask {
+    let args = tasks[caller].save().as_set_timer_args();
+    let deadline = args.deadline.map(Timestamp::from);
+    if let Some(deadline) = deadline {
         // timer is being enabled
         if deadline <= now {
             // timer is already expired
-            task.set_timer(None, args.notification);
+            task::set_timer(tasks, caller, None, args.notification);
             // We don't care if we woke the task, because it's already running!
-            let _ = task.post(args.notification);
+            let _ = tasks[caller].post(args.notification);
             return NextTask::Same;
         }
     }
-    task.set_timer(args.deadline, args.notification);
+    task::set_timer(tasks, caller, deadline, args.notification);
+    NextTask::Same
+}
+
+/// Implementation of the `SET_TIMER_PERIODIC` syscall.
+fn set_timer_periodic(
+    tasks: &mut [Task],
+    caller: usize,
+    now: Timestamp,
+) -> NextTask {
+    let args = tasks[caller].save().as_set_timer_periodic_args();
+    task::set_timer_periodic(
+        tasks,
+        caller,
+        now,
+        args.interval,
+        args.notification,
+    );
     NextTask::Same
 }
 
@@ -494,13 +706,12 @@ fn borrow_read(
         return Err(UserError::Recoverable(abi::DEFECT, NextTask::Same));
     }
 
-    let leased_area = USlice::from(&lease);
-
-    // Note: we do not explicitly check that the lender has access to
-    // `leased_area` because `safe_copy` will do it.
+    // Note: we do not explicitly check that the lender has access to the
+    // leased area because `safe_copy_from_lease` will do it.
 
     // Okay, goodness! We're finally getting close!
-    let copy_result = safe_copy(tasks, lender, leased_area, caller, buffer);
+    let copy_result =
+        safe_copy_from_lease(tasks, lender, &lease, caller, buffer);
 
     match copy_result {
         Ok(n) => {
@@ -537,13 +748,11 @@ fn borrow_write(
         return Err(UserError::Recoverable(abi::DEFECT, NextTask::Same));
     }
 
-    let leased_area = USlice::from(&lease);
-
-    // Note: we do not explicitly check that the lender has access to
-    // `leased_area` because `safe_copy` will do it.
+    // Note: we do not explicitly check that the lender has access to the
+    // leased area because `safe_copy_to_lease` will do it.
 
     // Okay, goodness! We're finally getting close!
-    let copy_result = safe_copy(tasks, caller, buffer, lender, leased_area);
+    let copy_result = safe_copy_to_lease(tasks, caller, buffer, lender, &lease);
 
     match copy_result {
         Ok(n) => {
@@ -561,6 +770,86 @@ fn borrow_write(
     }
 }
 
+/// Implementation of the `BORROW_VECTORED` syscall.
+///
+/// This performs a series of `BORROW_READ`s or `BORROW_WRITE`s against a single
+/// lease, as described by a table of `UBorrow`s in the caller's memory, in one
+/// kernel entry. Each transfer is clipped to the end of the lease, exactly as
+/// the single-shot versions are, and the total number of bytes moved is
+/// returned.
+///
+/// Transfers are performed in order. If the lender turns out to be defective
+/// partway through, the caller gets `DEFECT`, and the transfers before the one
+/// that failed have already happened.
+fn borrow_vectored(
+    tasks: &mut [Task],
+    caller: usize,
+) -> Result<NextTask, UserError> {
+    let args = tasks[caller].save().as_borrow_vectored_args();
+    let table = args.transfers?;
+    if table.len() > MAX_BORROW_VECTORS {
+        return Err(FaultInfo::SyscallUsage(UsageError::TooManyBorrows).into());
+    }
+
+    // Take a copy of the transfer table, so that we're not holding a borrow of
+    // the caller's memory while we copy.
+    let mut transfers = [UBorrow {
+        offset: 0,
+        base_address: 0,
+        length: 0,
+    }; MAX_BORROW_VECTORS];
+    let count = table.len();
+    transfers[..count].copy_from_slice(tasks[caller].try_read(&table)?);
+
+    // Validate all the caller's buffers before we move any data, so that a
+    // bogus table can't leave things half-done.
+    let mut buffers: [USlice<u8>; MAX_BORROW_VECTORS] =
+        core::array::from_fn(|_| USlice::empty());
+    for (buf, t) in buffers.iter_mut().zip(&transfers[..count]) {
+        *buf = USlice::from_raw(t.base_address as usize, t.length as usize)?;
+    }
+
+    let lender = task::check_task_id_against_table(tasks, args.lender)?;
+    let lease = borrow_lease(tasks, caller, lender, args.lease_number, 0)?;
+
+    let needed = if args.write {
+        LeaseAttributes::WRITE
+    } else {
+        LeaseAttributes::READ
+    };
+    if !lease.attributes.contains(needed) {
+        return Err(UserError::Recoverable(abi::DEFECT, NextTask::Same));
+    }
+
+    let mut total = 0;
+    for (buf, t) in buffers.into_iter().zip(&transfers[..count]) {
+        let Some(area) = lease.clone().offset(t.offset as usize) else {
+            return Err(
+                FaultInfo::SyscallUsage(UsageError::OffsetOutOfRange).into()
+            );
+        };
+        let copy_result = if args.write {
+            safe_copy_to_lease(tasks, caller, buf, lender, &area)
+                .map_err(|i| i.apply_to_dst(tasks, lender))
+        } else {
+            safe_copy_from_lease(tasks, lender, &area, caller, buf)
+                .map_err(|i| i.apply_to_src(tasks, lender))
+        };
+        match copy_result {
+            Ok(n) => total += n,
+            Err(wake_hint) => {
+                // Copy failed but not our side, report defecting lender.
+                return Err(UserError::Recoverable(abi::DEFECT, wake_hint?));
+            }
+        }
+    }
+
+    tasks[caller]
+        .save_mut()
+        .set_borrow_response_and_length(0, total);
+    Ok(NextTask::Same)
+}
+
 fn borrow_info(
     tasks: &mut [Task],
     caller: usize,
@@ -574,7 +863,7 @@ fn borrow_info(
 
     tasks[caller]
         .save_mut()
-        .set_borrow_info(lease.attributes.bits(), lease.length as usize);
+        .set_borrow_info(lease.attributes.bits(), lease.len());
     Ok(NextTask::Same)
 }
 
@@ -584,7 +873,7 @@ fn borrow_lease(
     lender: usize,
     lease_number: usize,
     offset: usize,
-) -> Result<ULease, UserError> {
+) -> Result<LeasedArea, UserError> {
     let caller_id = current_id(tasks, caller);
 
     // Check state of lender and range of lease table.
@@ -623,30 +912,49 @@ fn borrow_lease(
     // we can do this safely.
     let lease = leases.get(lease_number).cloned();
     // Is the lease number provided by the borrower legitimate?
-    if let Some(mut lease) = lease {
-        // Attempt to offset the lease. Handle cases where the offset is bogus.
-        // First, we must convert to u32, which _should be_ a no-op but we'll do
-        // it the careful way:
-        let offset = u32::try_from(offset).unwrap_lite();
-        // Now, proceed only if both neither the length nor address computation
-        // wrap.
-        if let (Some(off_len), Some(off_addr)) = (
-            lease.length.checked_sub(offset),
-            lease.base_address.checked_add(offset),
-        ) {
-            lease.base_address = off_addr;
-            lease.length = off_len;
-            Ok(lease)
-        } else {
-            Err(FaultInfo::SyscallUsage(UsageError::OffsetOutOfRange).into())
-        }
-    } else {
+    let Some(lease) = lease else {
         // Borrower provided an invalid lease number. Borrower was told the
         // number of leases on successful RECV and should respect that. (Note:
         // if the lender's lease table changed shape, this will fault the
         // borrower, which might be bad.)
-        Err(FaultInfo::SyscallUsage(UsageError::LeaseOutOfRange).into())
-    }
+        return Err(FaultInfo::SyscallUsage(UsageError::LeaseOutOfRange).into());
+    };
+
+    let area = if lease.attributes.contains(LeaseAttributes::SEGMENTED) {
+        // The lease refers to a table of segments, which -- like the lease
+        // table itself -- the lender must be able to read, and must have
+        // gotten right. Problems here are the lender's fault.
+        let table = match USlice::from(&lease).cast::<ULeaseSegment>() {
+            Ok(t) => t,
+            Err(e) => {
+                let wake_hint = task::force_fault(
+                    tasks,
+                    lender,
+                    FaultInfo::SyscallUsage(e),
+                );
+                return Err(UserError::Recoverable(abi::DEFECT, wake_hint));
+            }
+        };
+        let area = match tasks[lender].try_read(&table) {
+            Ok(table) => LeasedArea::from_segments(lease.attributes, table)
+                .map_err(FaultInfo::SyscallUsage),
+            Err(fault) => Err(fault),
+        };
+        match area {
+            Ok(area) => area,
+            Err(fault) => {
+                let wake_hint = task::force_fault(tasks, lender, fault);
+                return Err(UserError::Recoverable(abi::DEFECT, wake_hint));
+            }
+        }
+    } else {
+        LeasedArea::contiguous(lease.attributes, USlice::from(&lease))
+    };
+
+    // Attempt to offset the lease. Handle cases where the offset is bogus.
+    area.offset(offset).ok_or_else(|| {
+        FaultInfo::SyscallUsage(UsageError::OffsetOutOfRange).into()
+    })
 }
 
 /// Performs the architecture-specific bookkeeping to activate `task` on next
@@ -731,13 +1039,16 @@ fn deliver(
     // Okay, ready to attempt the copy.
     let amount_copied =
         safe_copy(tasks, caller, src_slice, callee, dest_slice)?;
-    tasks[callee].save_mut().set_recv_result(
+    let nonce = tasks[caller].begin_reply_wait();
+    let callee_save = tasks[callee].save_mut();
+    callee_save.set_recv_result(
         caller_id,
         u32::from(send_args.operation),
         amount_copied,
         response_capacity,
         lease_count,
     );
+    callee_save.set_recv_reply_nonce(nonce);
 
     let callee_id = current_id(tasks, callee);
     tasks[caller].set_healthy_state(SchedState::InReply(callee_id));
@@ -747,6 +1058,106 @@ fn deliver(
     Ok(())
 }
 
+/// Implementation of the `SEND_ASYNC` IPC primitive.
+///
+/// The message is copied into the callee's mailbox and the caller continues
+/// without waiting. If the callee is already blocked in an open receive, the
+/// message is delivered straight away.
+///
+/// `caller` is a valid task index (i.e. not directly from user code).
+///
+/// # Panics
+///
+/// If `caller` is out of range for `tasks`.
+fn send_async(
+    tasks: &mut [Task],
+    caller: usize,
+) -> Result<NextTask, UserError> {
+    let args = tasks[caller].save().as_send_async_args();
+
+    // Verify the given callee ID, converting it into a table index on success.
+    // This also rejects the kernel, which has no mailbox.
+    let callee = task::check_task_id_against_table(tasks, args.callee)?;
+    if !tasks[callee].mailbox_mut().exists() {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::IllegalTask,
+        )));
+    }
+
+    let sender = current_id(tasks, caller);
+    let message = args.message?;
+    let contents = tasks[caller].try_read(&message)?;
+    if contents.len() > abi::ASYNC_MESSAGE_SIZE {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+            UsageError::InvalidSlice,
+        )));
+    }
+    let mut msg = task::AsyncMessage {
+        sender,
+        operation: args.operation,
+        len: contents.len() as u8,
+        data: [0; abi::ASYNC_MESSAGE_SIZE],
+    };
+    msg.data[..contents.len()].copy_from_slice(contents);
+
+    if !tasks[callee].mailbox_mut().push(msg) {
+        // Not the caller's fault; it gets to decide whether to retry.
+        return Err(UserError::Recoverable(abi::MAILBOX_FULL, NextTask::Same));
+    }
+    tasks[caller].save_mut().set_error_response(0);
+
+    // If the callee is waiting for any message, hand it this one now.
+    if matches!(
+        tasks[callee].state(),
+        TaskState::Healthy(SchedState::InRecv(None))
+    ) {
+        match deliver_async(&mut tasks[callee]) {
+            Ok(_) => {
+                // As with POST, only switch if the callee is more important
+                // than us.
+                let caller_p = tasks[caller].priority();
+                let callee_p = tasks[callee].priority();
+                if callee_p.is_more_important_than(caller_p) {
+                    return Ok(NextTask::Specific(callee));
+                }
+            }
+            Err(fault) => {
+                // The callee's receive buffer is bogus. That's on the callee.
+                return Ok(task::force_fault(tasks, callee, fault));
+            }
+        }
+    }
+    Ok(NextTask::Same)
+}
+
+/// Delivers the oldest message in `task`'s mailbox, if any, into its receive
+/// buffer, as though it had just completed an open RECV.
+///
+/// Returns `Ok(true)` if a message was delivered, `Ok(false)` if the mailbox
+/// was empty, or `Err` if the task's receive buffer can't be written, in which
+/// case the message is left in the mailbox.
+fn deliver_async(task: &mut Task) -> Result<bool, FaultInfo> {
+    let mut buffer = task.save().as_recv_args().buffer?;
+    let Some(msg) = task.mailbox_mut().peek() else {
+        return Ok(false);
+    };
+    let dest = task.try_write(&mut buffer)?;
+    let n = dest.len().min(usize::from(msg.len));
+    dest[..n].copy_from_slice(&msg.contents()[..n]);
+    // Only dequeue the message once it has landed. If the buffer was bad, it
+    // stays queued with the faulted task rather than silently vanishing.
+    task.mailbox_mut().pop();
+    task.save_mut().set_recv_result(
+        msg.sender,
+        u32::from(msg.operation) | abi::ASYNC_OPERATION_FLAG,
+        n,
+        0,
+        0,
+    );
+    task.set_healthy_state(SchedState::Runnable);
+    Ok(true)
+}
+
 fn irq_control(
     tasks: &mut [Task],
     caller: usize,
@@ -765,16 +1176,14 @@ fn irq_control(
 
     let caller = caller as u32;
 
-    let irqs = crate::startup::HUBRIS_TASK_IRQ_LOOKUP
-        .get(abi::InterruptOwner {
-            task: caller,
-            notification: args.notification_bitmask,
-        })
-        .ok_or(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+    let owner = abi::InterruptOwner {
+        task: caller,
+        notification: args.notification_bitmask,
+    };
+    if !crate::irq::for_each_owned(owner, operation) {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
             UsageError::NoIrq,
-        )))?;
-    for i in irqs.iter() {
-        operation(i.0);
+        )));
     }
     Ok(NextTask::Same)
 }
@@ -843,6 +1252,7 @@ fn reply_fault(
     caller: usize,
 ) -> Result<NextTask, FaultInfo> {
     let caller_id = current_id(tasks, caller);
+    let caller_priority = tasks[caller].priority();
 
     // Extract the target of the reply and the cited reason. This also validates
     // the syscall parameters before doing other validation.
@@ -858,6 +1268,10 @@ fn reply_fault(
         Ok(x) => x,
     };
 
+    // As in `reply`, a stale reply is dropped.
+    if tasks[callee].take_abandoned_call(caller_id) {
+        return Ok(NextTask::Same);
+    }
     if tasks[callee].state()
         != &TaskState::Healthy(SchedState::InReply(caller_id))
     {
@@ -866,19 +1280,28 @@ fn reply_fault(
         // chance to reply (e.g. to implement timeouts).
         return Ok(NextTask::Same);
     }
+    tasks[callee]
+        .check_reply_nonce(args.nonce)
+        .map_err(FaultInfo::SyscallUsage)?;
 
     // Check and deliver the fault. We explicitly discard its scheduling hint,
     // because the caller is lower priority than we are.
     let _hint = task::force_fault(
         tasks,
         callee,
-        FaultInfo::FromServer(caller_id, reason),
+        FaultInfo::FromServer(caller_id, reason, args.detail),
     );
 
     // KEY ASSUMPTION: sends go from less important tasks to more important
     // tasks. As a result, Reply doesn't have scheduling implications unless
-    // the task using it faults.
-    Ok(NextTask::Same)
+    // the task using it faults -- or it was running on a priority inherited
+    // from the callee. `force_fault` has already recomputed our priority, so
+    // compare against where we started.
+    if tasks[caller].priority() != caller_priority {
+        Ok(NextTask::Other)
+    } else {
+        Ok(NextTask::Same)
+    }
 }
 
 /// Implementation of the `IRQ_STATUS` syscall.
@@ -903,20 +1326,20 @@ fn irq_status(
 ) -> Result<NextTask, UserError> {
     let args = tasks[caller].save().as_irq_status_args();
 
-    // Look up which IRQs are mapped to the calling task.
-    let irqs = crate::startup::HUBRIS_TASK_IRQ_LOOKUP
-        .get(abi::InterruptOwner {
-            task: caller as u32,
-            notification: args.notification_bitmask,
-        })
-        .ok_or(UserError::Unrecoverable(FaultInfo::SyscallUsage(
+    // Combine the platform-level status of all the IRQs mapped to the calling
+    // task in the notification set.
+    let owner = abi::InterruptOwner {
+        task: caller as u32,
+        notification: args.notification_bitmask,
+    };
+    let mut status = IrqStatus::empty();
+    if !crate::irq::for_each_owned(owner, |irq| {
+        status |= crate::arch::irq_status(irq)
+    }) {
+        return Err(UserError::Unrecoverable(FaultInfo::SyscallUsage(
             UsageError::NoIrq,
-        )))?;
-
-    // Combine the platform-level status of all the IRQs in the notification set.
-    let mut status = irqs.iter().fold(IrqStatus::empty(), |status, irq| {
-        status | crate::arch::irq_status(irq.0)
-    });
+        )));
+    }
 
     // If any bits in the notification mask are set in the caller's notification
     // set, then a notification has been posted to the task and not yet consumed.
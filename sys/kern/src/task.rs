@@ -5,21 +5,36 @@
 //! Implementation of tasks.
 
 use core::ops::Range;
+use core::sync::atomic::{AtomicU32, Ordering};
 
 use abi::{
-    FaultInfo, FaultSource, Generation, ReplyFaultReason, SchedState, TaskId,
-    TaskState, ULease, UsageError,
+    FaultInfo, FaultSource, Generation, SchedState, TaskId, TaskState,
+    UsageError, ASYNC_MESSAGE_SIZE,
 };
 use zerocopy::FromBytes;
 
 use crate::descs::{
-    Priority, RegionAttributes, RegionDesc, TaskDesc, TaskFlags,
+    Priority, RamFill, RegionAttributes, RegionDesc, TaskDesc, TaskFlags,
     REGIONS_PER_TASK,
 };
 use crate::err::UserError;
-use crate::startup::HUBRIS_FAULT_NOTIFICATION;
+use crate::startup::{
+    HUBRIS_FAULT_NOTIFICATION, HUBRIS_IRQ_STORM_COUNTS, HUBRIS_IRQ_STORM_LIMIT,
+    HUBRIS_IRQ_STORM_NOTIFICATION, HUBRIS_REPLY_DEADMAN,
+    HUBRIS_REPLY_DEADMAN_NOTIFICATION, HUBRIS_RESTART_HELD_NOTIFICATION,
+    HUBRIS_RESTART_RELEASED_NOTIFICATION, HUBRIS_RESTART_THROTTLE,
+    HUBRIS_TIMESLICE,
+};
 use crate::time::Timestamp;
 use crate::umem::USlice;
+use unwrap_lite::UnwrapLite;
+
+pub use kerncore::args::{
+    BorrowArgs, BorrowVectoredArgs, IrqArgs, IrqStatusArgs, NotificationSet,
+    PanicArgs, PostArgs, RecvArgs, RefreshTaskIdArgs, ReplyArgs,
+    ReplyFaultArgs, ReplyLeaseArgs, SendArgs, SendAsyncArgs, SetTimerArgs,
+    SetTimerPeriodicArgs, SyscallArgs,
+};
 
 /// Internal representation of a task.
 ///
@@ -33,7 +48,9 @@ pub struct Task {
     /// Saved machine state of the user program.
     save: crate::arch::SavedState,
     // NOTE: it is critical that the above field appear first!
-    /// Current priority of the task.
+    /// Current effective priority of the task. This is the priority from the
+    /// task's descriptor, unless the task has inherited a more important
+    /// priority from a client blocked on it; see `update_inherited_priority`.
     priority: Priority,
     /// State used to make status and scheduling decisions.
     state: TaskState,
@@ -44,7 +61,69 @@ pub struct Task {
     generation: u32,
 
     /// Notification status.
-    notifications: u32,
+    notifications: u64,
+
+    /// Set whenever this task is given the CPU, and cleared by the watchdog
+    /// heartbeat once every task has made progress. See `check_progress`.
+    scheduled: bool,
+
+    /// Set while the task is suspended by the `suspend_task` kipc. This is kept
+    /// apart from `state` so that the task's IPC state (and its registers) are
+    /// left exactly as they were; a suspended task simply isn't scheduled.
+    suspended: bool,
+
+    /// Set of tasks, by index, that this task wants to hear about when they
+    /// restart, and the notifications to post it when one does. See the
+    /// `watch_task` kipc. Like other runtime state, this is cleared when the
+    /// task itself restarts.
+    watching: u64,
+    watch_notification: NotificationSet,
+
+    /// Set if the task's most recent `SEND` was marked urgent. Only meaningful
+    /// while the task is in `InSend`; see `sender_scan`.
+    urgent: bool,
+
+    /// Deadline for the task's most recent `SEND`, if it was given a timeout.
+    /// This is left in place after the call completes; it only takes effect if
+    /// the task is still blocked in `InSend` or `InReply` when it expires. See
+    /// `process_ipc_timeouts`.
+    ipc_deadline: Option<Timestamp>,
+
+    /// The server that had received the task's last `SEND`, but hadn't
+    /// replied, when it timed out. That server still owes a reply to the old
+    /// message, which the kernel drops when it comes; until then, the server
+    /// isn't given another message from this task, so the late reply can't be
+    /// taken for the answer to a new one. Restarting the server (which changes
+    /// its ID) also ends this. See `process_ipc_timeouts`.
+    abandoned_call: Option<TaskId>,
+
+    /// When the task's current message was received, if it's waiting in
+    /// `InReply` and the reply dead-man is configured. See
+    /// `process_reply_deadman`.
+    reply_since: Timestamp,
+
+    /// Set once the reply dead-man has reported the task's current wait, so
+    /// that it's reported only once.
+    deadman_reported: bool,
+
+    /// Nonce identifying the task's most recent message to be received, which
+    /// the server can cite in its reply to make sure it's replying to that
+    /// message and not an earlier one. This counts up, skipping zero, and
+    /// isn't reset on restart, so that a new incarnation doesn't reuse nonces
+    /// from the old one.
+    reply_nonce: u32,
+
+    /// Total CPU time consumed by this task since boot, in
+    /// architecture-specific cycles. This is *not* reset when the task is
+    /// restarted.
+    cpu_time: u64,
+
+    /// Fault history used by the restart throttle. Like `cpu_time`, this
+    /// survives restarts -- that's the whole point.
+    throttle: RestartThrottle,
+
+    /// Queue of asynchronous messages waiting for this task to receive them.
+    mailbox: Mailbox,
 
     /// Pointer to the ROM descriptor used to create this task, so it can be
     /// restarted.
@@ -67,6 +146,19 @@ impl Task {
 
             generation: 0,
             notifications: 0,
+            scheduled: false,
+            suspended: false,
+            watching: 0,
+            watch_notification: NotificationSet::default(),
+            urgent: false,
+            ipc_deadline: None,
+            abandoned_call: None,
+            reply_since: Timestamp::default(),
+            deadman_reported: false,
+            reply_nonce: 0,
+            cpu_time: 0,
+            throttle: RestartThrottle::default(),
+            mailbox: Mailbox::default(),
             save: crate::arch::SavedState::default(),
             timer: crate::task::TimerState::default(),
         }
@@ -146,6 +238,26 @@ impl Task {
         }
     }
 
+    /// Checks that the task `self` has `desired` access to all of `slice`,
+    /// without the kernel touching it. Unlike `try_read` and friends, this
+    /// accepts memory marked `DMA`, since it's meant for operations (like
+    /// cache maintenance) that act on the memory system rather than the
+    /// contents; memory marked `DEVICE` is still refused.
+    pub fn check_access<T>(
+        &self,
+        slice: &USlice<T>,
+        desired: RegionAttributes,
+    ) -> Result<(), FaultInfo> {
+        if self.can_access(slice, desired, RegionAttributes::empty()) {
+            Ok(())
+        } else {
+            Err(FaultInfo::MemoryAccess {
+                address: Some(slice.base_addr() as u32),
+                source: FaultSource::Kernel,
+            })
+        }
+    }
+
     /// Tests whether this task has write access to `slice` as normal memory.
     /// This is used to validate kernel accessses to the memory.
     ///
@@ -218,10 +330,19 @@ impl Task {
 
         // Delegate the actual tests to the kerncore crate, but with our
         // attribute-sensing customization:
-        kerncore::can_access(slice, self.region_table(), |region| {
+        let region_ok = |region: &&RegionDesc| {
             region.attributes.contains(desired)
                 && !region.attributes.intersects(forbidden)
-        })
+        };
+        // A task's virtual regions (if any) are checked separately, so a slice
+        // can't straddle a resident region and a virtual one; nothing needs
+        // that.
+        kerncore::can_access(slice, self.region_table(), region_ok)
+            || kerncore::can_access(
+                slice,
+                self.descriptor.extra_regions,
+                region_ok,
+            )
     }
 
     /// Posts a set of notification bits (which might be empty) to this task. If
@@ -234,6 +355,7 @@ impl Task {
     /// its own global ID, which it does not.
     #[must_use]
     pub fn post(&mut self, n: NotificationSet) -> bool {
+        crate::replay::post(usize::from(self.descriptor.index), n);
         self.notifications |= n.0;
 
         // We only need to check the mask, and make updates, if the task is
@@ -242,9 +364,10 @@ impl Task {
             if let Some(firing) = self.take_notifications() {
                 // A bit the task is interested in has newly become set!
                 // Interrupt it.
-                self.save.set_recv_result(TaskId::KERNEL, firing, 0, 0, 0);
+                self.save.set_recv_notification_result(firing);
                 self.state = TaskState::Healthy(SchedState::Runnable);
-                return true;
+                // A suspended task has been unblocked, but still can't run.
+                return !self.suspended;
             }
         }
         false
@@ -258,7 +381,7 @@ impl Task {
     /// This directly accesses the RECV syscall arguments from the task's saved
     /// state, so it doesn't make sense if the task is not performing a RECV --
     /// but this is not checked.
-    pub fn take_notifications(&mut self) -> Option<u32> {
+    pub fn take_notifications(&mut self) -> Option<u64> {
         let args = self.save.as_recv_args();
 
         let firing = self.notifications & args.notification_mask;
@@ -270,33 +393,154 @@ impl Task {
         }
     }
 
+    /// Returns the notification bits that have been posted to this task but
+    /// not yet received.
+    pub fn pending_notifications(&self) -> u64 {
+        self.notifications
+    }
+
     /// Returns `true` if any of the notification bits in `mask` are set in this
     /// task's notification set.
     ///
     /// This does *not* clear any bits in the task's notification set.
-    pub fn has_notifications(&self, mask: u32) -> bool {
+    pub fn has_notifications(&self, mask: u64) -> bool {
         self.notifications & mask != 0
     }
 
+    /// Checks whether this task has a message for `receiver` that can be
+    /// delivered now: it's blocked sending to `receiver`, and `receiver`
+    /// doesn't still owe it a reply to a call that timed out.
+    pub fn can_deliver_to(&self, receiver: TaskId) -> bool {
+        self.state.is_sending_to(receiver)
+            && self.abandoned_call != Some(receiver)
+    }
+
+    /// Checks whether `server` still owes this task a reply to a call that
+    /// timed out. If so, the reply is stale, and this forgets about it, so
+    /// that the caller can drop it and `server` can get this task's next
+    /// message.
+    pub fn take_abandoned_call(&mut self, server: TaskId) -> bool {
+        if self.abandoned_call == Some(server) {
+            self.abandoned_call = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns the server that still owes this task a reply to a call that
+    /// timed out, if any; see `can_deliver_to`.
+    pub fn abandoned_call(&self) -> Option<TaskId> {
+        self.abandoned_call
+    }
+
+    /// Forgets the call that timed out, when its server has been restarted.
+    pub fn clear_abandoned_call(&mut self) {
+        self.abandoned_call = None;
+    }
+
     /// Checks if this task is in a potentially schedulable state.
     pub fn is_runnable(&self) -> bool {
         self.state == TaskState::Healthy(SchedState::Runnable)
+            && !self.suspended
     }
 
-    /// Configures this task's timer.
+    /// Checks whether this task has been suspended by `suspend_task`.
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// Suspends or resumes this task. This doesn't otherwise change its state:
+    /// a task blocked in IPC can still have the IPC complete while suspended,
+    /// and will find it complete when resumed.
+    pub fn set_suspended(&mut self, suspended: bool) {
+        self.suspended = suspended;
+    }
+
+    /// Returns the state to report to other tasks. This is `state()`, except
+    /// that a healthy task that's suspended shows as `Stopped`, since it won't
+    /// be scheduled until it's resumed.
+    pub fn reported_state(&self) -> TaskState {
+        match self.state {
+            TaskState::Healthy(_) if self.suspended => {
+                TaskState::Healthy(SchedState::Stopped)
+            }
+            s => s,
+        }
+    }
+
+    /// Starts (if `n` is non-empty) or stops watching task `index` for
+    /// restarts. All of a task's watches share one set of notifications, so
+    /// starting a watch replaces the notifications for any others.
     ///
-    /// `deadline` specifies the moment when the timer should fire, in kernel
-    /// time. If `None`, the timer will never fire.
+    /// `index` must be less than 64.
+    pub fn set_watch(&mut self, index: usize, n: NotificationSet) {
+        if n.0 == 0 {
+            self.watching &= !(1 << index);
+        } else {
+            self.watching |= 1 << index;
+            self.watch_notification = n;
+        }
+    }
+
+    /// Returns the notifications to post to this task because task `index`
+    /// restarted, if it's watching it.
+    pub fn watch_notification(&self, index: usize) -> Option<NotificationSet> {
+        (index < 64 && self.watching & (1 << index) != 0)
+            .then_some(self.watch_notification)
+    }
+
+    /// Notes that the task's message has just been received, and it's now
+    /// waiting for a reply, for the benefit of the reply dead-man. Returns the
+    /// nonce identifying the message, which the server must cite if it cites
+    /// one in its reply.
+    pub fn begin_reply_wait(&mut self) -> u32 {
+        if HUBRIS_REPLY_DEADMAN.is_some() {
+            self.reply_since = crate::arch::now();
+            self.deadman_reported = false;
+        }
+        self.reply_nonce = match self.reply_nonce.wrapping_add(1) {
+            0 => 1,
+            n => n,
+        };
+        self.reply_nonce
+    }
+
+    /// Checks a reply nonce cited by a server replying to this task, which
+    /// must be waiting for that server's reply. Zero means the server didn't
+    /// cite one, and always passes.
     ///
-    /// `notifications` is the set of notification bits to be set when the timer
-    /// fires.
-    pub fn set_timer(
-        &mut self,
-        deadline: Option<Timestamp>,
-        notifications: NotificationSet,
-    ) {
-        self.timer.deadline = deadline;
-        self.timer.to_post = notifications;
+    /// Late replies to calls that timed out are dropped before this is
+    /// checked (see `take_abandoned_call`), so a mismatch here means the
+    /// server really is replying to the wrong message.
+    pub fn check_reply_nonce(&self, nonce: u32) -> Result<(), UsageError> {
+        if nonce == 0 || nonce == self.reply_nonce {
+            Ok(())
+        } else {
+            Err(UsageError::WrongReplyNonce)
+        }
+    }
+
+    /// Records whether the task's current `SEND` is urgent.
+    pub fn set_urgent(&mut self, urgent: bool) {
+        self.urgent = urgent;
+    }
+
+    /// Records that this task has been given the CPU, for the benefit of the
+    /// watchdog heartbeat.
+    pub fn note_scheduled(&mut self) {
+        self.scheduled = true;
+    }
+
+    /// Adds `cycles` to this task's CPU time.
+    pub fn charge_cpu_time(&mut self, cycles: u64) {
+        self.cpu_time = self.cpu_time.wrapping_add(cycles);
+    }
+
+    /// Returns the total CPU time consumed by this task since boot, in
+    /// architecture-specific cycles.
+    pub fn cpu_time(&self) -> u64 {
+        self.cpu_time
     }
 
     /// Reads out the state of this task's timer, as previously set by
@@ -308,22 +552,92 @@ impl Task {
     /// Rewrites this task's state back to its initial form, to effect a task
     /// reboot.
     ///
-    /// Note that this only rewrites in-kernel state and relevant parts of
+    /// Note that this only rewrites in-kernel state, the RAM named in the task's
+    /// `ram_init` descriptors (see `init_ram`), and relevant parts of
     /// out-of-kernel state (typically, a stack frame stored on the task stack).
-    /// This does *not* reinitialize application memory or anything else.
+    /// The rest of the task's memory is left as it was.
     ///
     /// This does not honor the `START_AT_BOOT` task flag, because this is not a
     /// system reboot. The task will be left in `Stopped` state. If you would
     /// like to run the task after reinitializing it, you must do so explicitly.
     pub fn reinitialize(&mut self) {
         self.generation = self.generation.wrapping_add(1);
-        self.timer = TimerState::default();
+        // Disarm the timer, but leave the task's place in the timer queue
+        // alone, since other tasks' links may pass through it. The queue will
+        // drop it when it reaches the front, or when the timer is next set.
+        self.timer.deadline = None;
+        self.timer.to_post = NotificationSet::default();
+        self.timer.period = 0;
         self.notifications = 0;
+        self.suspended = false;
+        self.watching = 0;
+        self.watch_notification = NotificationSet::default();
+        self.urgent = false;
+        set_ipc_deadline(self, None);
+        self.abandoned_call = None;
         self.state = TaskState::default();
+        self.priority = Priority(self.descriptor.priority);
+        // Messages queued for the previous incarnation are not for us.
+        self.mailbox.clear();
 
+        self.init_ram();
         crate::arch::reinitialize(self);
     }
 
+    /// Initializes the parts of this task's RAM listed in its descriptor's
+    /// `ram_init` table -- copying in `.data` and zeroing `.bss`, typically.
+    ///
+    /// Doing this here, rather than in the task's startup code, means a task
+    /// can't skip it, and the time it takes to restart a task doesn't depend
+    /// on code the task supplies.
+    pub fn init_ram(&mut self) {
+        for init in self.descriptor.ram_init {
+            let words = init.size as usize / 4;
+            let mut dest = USlice::<u32>::from_raw(init.base as usize, words)
+                .unwrap_lite();
+            // These are checked at boot, but the unsafe code below relies on
+            // them, so we check them again rather than trust that.
+            uassert!(self.can_write(&dest));
+            // Safety (for all three arms): we've checked that `dest` is normal
+            // task memory that the task can write, so it's not kernel memory,
+            // and nothing else references it while we hold the task.
+            match init.fill {
+                RamFill::Zero => unsafe { dest.assume_writable() }.fill(0),
+                RamFill::Pattern(word) => {
+                    unsafe { dest.assume_writable() }.fill(word)
+                }
+                RamFill::Copy { source } => {
+                    let src = USlice::<u32>::from_raw(source as usize, words)
+                        .unwrap_lite();
+                    // The source is normally in flash, but nothing stops a
+                    // descriptor from naming RAM, so make sure it doesn't
+                    // overlap the range we're writing.
+                    uassert!(self.can_read(&src) && !src.aliases(&dest));
+                    // Safety: `src` is checked readable and distinct from
+                    // `dest` just above.
+                    unsafe {
+                        dest.assume_writable()
+                            .copy_from_slice(src.assume_readable());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Gives this task storage for its mailbox. This is done once, at startup.
+    pub fn set_mailbox(&mut self, slots: &'static mut [AsyncMessage]) {
+        self.mailbox = Mailbox {
+            slots,
+            head: 0,
+            len: 0,
+        };
+    }
+
+    /// Returns a mutable reference to this task's mailbox.
+    pub fn mailbox_mut(&mut self) -> &mut Mailbox {
+        &mut self.mailbox
+    }
+
     /// Returns a reference to the `TaskDesc` that was used to initially create
     /// this task.
     pub fn descriptor(&self) -> &'static TaskDesc {
@@ -422,6 +736,25 @@ pub trait ArchState: Default {
     fn ret4(&mut self, _: u32);
     /// Writes syscall return argument 5.
     fn ret5(&mut self, _: u32);
+    /// Writes syscall return argument 6.
+    fn ret6(&mut self, _: u32);
+    /// Writes syscall return argument 7.
+    fn ret7(&mut self, _: u32);
+
+    /// Copies out the syscall argument registers, for decoding by the
+    /// `as_*_args` methods.
+    #[inline(always)]
+    fn syscall_args(&self) -> SyscallArgs {
+        SyscallArgs([
+            self.arg0(),
+            self.arg1(),
+            self.arg2(),
+            self.arg3(),
+            self.arg4(),
+            self.arg5(),
+            self.arg6(),
+        ])
+    }
 
     /// Interprets arguments as for the SEND syscall and returns the results.
     ///
@@ -430,22 +763,7 @@ pub trait ArchState: Default {
     /// of its code be eliminated and makes text smaller.
     #[inline(always)]
     fn as_send_args(&self) -> SendArgs {
-        SendArgs {
-            callee: TaskId((self.arg0() >> 16) as u16),
-            operation: self.arg0() as u16,
-            message: USlice::from_raw(
-                self.arg1() as usize,
-                self.arg2() as usize,
-            ),
-            response: USlice::from_raw(
-                self.arg3() as usize,
-                self.arg4() as usize,
-            ),
-            lease_table: USlice::from_raw(
-                self.arg5() as usize,
-                self.arg6() as usize,
-            ),
-        }
+        self.syscall_args().as_send_args()
     }
 
     /// Interprets arguments as for the RECV syscall and returns the results.
@@ -455,114 +773,81 @@ pub trait ArchState: Default {
     /// of its code be eliminated and makes text smaller.
     #[inline(always)]
     fn as_recv_args(&self) -> RecvArgs {
-        RecvArgs {
-            buffer: USlice::from_raw(
-                self.arg0() as usize,
-                self.arg1() as usize,
-            ),
-            notification_mask: self.arg2(),
-            specific_sender: {
-                let v = self.arg3();
-                if v & (1 << 31) != 0 {
-                    Some(TaskId(v as u16))
-                } else {
-                    None
-                }
-            },
-        }
+        self.syscall_args().as_recv_args()
     }
 
     /// Interprets arguments as for the REPLY syscall and returns the results.
     fn as_reply_args(&self) -> ReplyArgs {
-        ReplyArgs {
-            callee: TaskId(self.arg0() as u16),
-            response_code: self.arg1(),
-            message: USlice::from_raw(
-                self.arg2() as usize,
-                self.arg3() as usize,
-            ),
-        }
+        self.syscall_args().as_reply_args()
     }
 
     /// Interprets arguments as for the `REPLY_FAULT` syscall and returns the
     /// results.
     fn as_reply_fault_args(&self) -> ReplyFaultArgs {
-        ReplyFaultArgs {
-            callee: TaskId(self.arg0() as u16),
-            reason: ReplyFaultReason::try_from(self.arg1())
-                .map_err(|_| UsageError::BadReplyFaultReason),
-        }
+        self.syscall_args().as_reply_fault_args()
     }
 
     /// Interprets arguments as for the `SET_TIMER` syscall and returns the
     /// results.
     fn as_set_timer_args(&self) -> SetTimerArgs {
-        SetTimerArgs {
-            deadline: if self.arg0() != 0 {
-                Some(Timestamp::from(
-                    u64::from(self.arg2()) << 32 | u64::from(self.arg1()),
-                ))
-            } else {
-                None
-            },
-            notification: NotificationSet(self.arg3()),
-        }
+        self.syscall_args().as_set_timer_args()
+    }
+
+    /// Interprets arguments as for the `SET_TIMER_PERIODIC` syscall and
+    /// returns the results.
+    fn as_set_timer_periodic_args(&self) -> SetTimerPeriodicArgs {
+        self.syscall_args().as_set_timer_periodic_args()
     }
 
     /// Interprets arguments as for the `BORROW_*` family of syscalls and
     /// returns the result.
     fn as_borrow_args(&self) -> BorrowArgs {
-        BorrowArgs {
-            lender: TaskId(self.arg0() as u16),
-            lease_number: self.arg1() as usize,
-            offset: self.arg2() as usize,
-            buffer: USlice::from_raw(
-                self.arg3() as usize,
-                self.arg4() as usize,
-            ),
-        }
+        self.syscall_args().as_borrow_args()
+    }
+
+    /// Interprets arguments as for the `BORROW_VECTORED` syscall and returns
+    /// the results.
+    fn as_borrow_vectored_args(&self) -> BorrowVectoredArgs {
+        self.syscall_args().as_borrow_vectored_args()
+    }
+
+    /// Interprets arguments as for the `REPLY_LEASE` syscall and returns the
+    /// results.
+    fn as_reply_lease_args(&self) -> ReplyLeaseArgs {
+        self.syscall_args().as_reply_lease_args()
     }
 
     /// Interprets arguments as for the `IRQ_CONTROL` syscall and returns the
     /// results.
     fn as_irq_args(&self) -> IrqArgs {
-        IrqArgs {
-            notification_bitmask: self.arg0(),
-            control: self.arg1(),
-        }
+        self.syscall_args().as_irq_args()
     }
 
     /// Interprets arguments as for the `PANIC` syscall and returns the results.
     fn as_panic_args(&self) -> PanicArgs {
-        PanicArgs {
-            message: USlice::from_raw(
-                self.arg0() as usize,
-                self.arg1() as usize,
-            ),
-        }
+        self.syscall_args().as_panic_args()
     }
 
     /// Interprets arguments as for the `REFRESH_TASK_ID` syscall and returns
     /// the results.
     fn as_refresh_task_id_args(&self) -> RefreshTaskIdArgs {
-        RefreshTaskIdArgs {
-            task_id: TaskId(self.arg0() as u16),
-        }
+        self.syscall_args().as_refresh_task_id_args()
     }
 
     /// Interprets arguments as for the `POST` syscall and returns the results.
     fn as_post_args(&self) -> PostArgs {
-        PostArgs {
-            task_id: TaskId(self.arg0() as u16),
-            notification_bits: NotificationSet(self.arg1()),
-        }
+        self.syscall_args().as_post_args()
+    }
+
+    /// Interprets arguments as for the `SEND_ASYNC` syscall and returns the
+    /// results.
+    fn as_send_async_args(&self) -> SendAsyncArgs {
+        self.syscall_args().as_send_async_args()
     }
 
     /// Interprets arguments as for the `IRQ_STATUS` syscall and returns the results.
     fn as_irq_status_args(&self) -> IrqStatusArgs {
-        IrqStatusArgs {
-            notification_bitmask: self.arg0(),
-        }
+        self.syscall_args().as_irq_status_args()
     }
 
     /// Sets a recoverable error code using the generic ABI.
@@ -592,6 +877,23 @@ pub trait ArchState: Default {
         self.ret3(length as u32);
         self.ret4(response_capacity as u32);
         self.ret5(lease_count as u32);
+        self.ret6(0);
+        self.ret7(0);
+    }
+
+    /// Sets the reply nonce returned from a RECV that received a message the
+    /// caller must reply to. This must follow `set_recv_result`.
+    fn set_recv_reply_nonce(&mut self, nonce: u32) {
+        self.ret7(nonce);
+    }
+
+    /// Sets the results returned from a RECV that was interrupted by the
+    /// notification bits in `bits`. These are delivered as a message from the
+    /// kernel, with the low 32 bits in the operation, and the high 32 bits in
+    /// an extra register.
+    fn set_recv_notification_result(&mut self, bits: u64) {
+        self.set_recv_result(TaskId::KERNEL, bits as u32, 0, 0, 0);
+        self.ret6((bits >> 32) as u32);
     }
 
     /// Sets the response code and length returned from a BORROW_*.
@@ -622,7 +924,8 @@ pub trait ArchState: Default {
         self.ret2(dl.is_some() as u32);
         self.ret3(dl_u64 as u32);
         self.ret4((dl_u64 >> 32) as u32);
-        self.ret5(not.0);
+        self.ret5(not.0 as u32);
+        self.ret6((not.0 >> 32) as u32);
     }
 
     /// Sets the results of REFRESH_TASK_ID
@@ -636,104 +939,229 @@ pub trait ArchState: Default {
     }
 }
 
-/// Decoded arguments for the `SEND` syscall.
-#[derive(Clone, Debug)]
-pub struct SendArgs {
-    pub callee: TaskId,
-    pub operation: u16,
-    pub message: Result<USlice<u8>, UsageError>,
-    pub response: Result<USlice<u8>, UsageError>,
-    pub lease_table: Result<USlice<ULease>, UsageError>,
+/// State for a task timer.
+///
+/// Task timers are used to multiplex the hardware timer.
+#[derive(Debug, Default)]
+pub struct TimerState {
+    /// Deadline, in kernel time, at which this timer should fire. If `None`,
+    /// the timer is disabled.
+    deadline: Option<Timestamp>,
+    /// Set of notification bits to post to the owning task when this timer
+    /// fires.
+    to_post: NotificationSet,
+    /// If non-zero, the timer is periodic, and is re-armed this many ticks
+    /// after its previous deadline each time it fires.
+    period: u32,
+    /// Whether the owning task is linked into the timer queue. This can be
+    /// true with `deadline` of `None`, if the timer was disarmed by a restart.
+    queued: bool,
+    /// Index of the next task in the timer queue, or `TIMER_QUEUE_END`. Only
+    /// meaningful if `queued`.
+    next: u16,
 }
 
-/// Decoded arguments for the `RECV` syscall.
-#[derive(Clone, Debug)]
-pub struct RecvArgs {
-    pub buffer: Result<USlice<u8>, UsageError>,
-    pub notification_mask: u32,
-    pub specific_sender: Option<TaskId>,
-}
+/// Marks the end of the timer queue.
+const TIMER_QUEUE_END: u16 = u16::MAX;
 
-/// Decoded arguments for the `REPLY` syscall.
-#[derive(Clone, Debug)]
-pub struct ReplyArgs {
-    pub callee: TaskId,
-    pub response_code: u32,
-    pub message: Result<USlice<u8>, UsageError>,
-}
+/// Index of the task at the front of the timer queue, or `TIMER_QUEUE_END` if
+/// it's empty.
+///
+/// The timer queue is a list of tasks with armed timers, linked through their
+/// `TimerState`s and sorted by deadline, earliest first. This lets the tick
+/// path look only at the front of the queue, rather than scanning every task.
+/// Since each task has at most one timer, the queue needs no storage beyond the
+/// task table.
+///
+/// Tasks whose timers were disarmed by a restart may linger in the queue; they
+/// don't disturb the ordering of the others, and are dropped when found.
+static TIMER_QUEUE_HEAD: AtomicU32 = AtomicU32::new(TIMER_QUEUE_END as u32);
 
-/// Decoded arguments for the `REPLY_FAULT` syscall.
-#[derive(Clone, Debug)]
-pub struct ReplyFaultArgs {
-    pub callee: TaskId,
-    pub reason: Result<ReplyFaultReason, UsageError>,
+fn timer_queue_head() -> u16 {
+    TIMER_QUEUE_HEAD.load(Ordering::Relaxed) as u16
 }
 
-/// Decoded arguments for the `SET_TIMER` syscall.
-#[derive(Clone, Debug)]
-pub struct SetTimerArgs {
-    pub deadline: Option<Timestamp>,
-    pub notification: NotificationSet,
+fn set_timer_queue_head(index: u16) {
+    TIMER_QUEUE_HEAD.store(u32::from(index), Ordering::Relaxed);
 }
 
-/// Decoded arguments for the `BORROW_*` syscalls.
-#[derive(Clone, Debug)]
-pub struct BorrowArgs {
-    pub lender: TaskId,
-    pub lease_number: usize,
-    pub offset: usize,
-    pub buffer: Result<USlice<u8>, UsageError>,
+/// Configures the timer of task `index`.
+///
+/// `deadline` specifies the moment when the timer should fire, in kernel time.
+/// If `None`, the timer will never fire.
+///
+/// `notifications` is the set of notification bits to be set when the timer
+/// fires.
+///
+/// This replaces any periodic timer set with `set_timer_periodic`.
+pub fn set_timer(
+    tasks: &mut [Task],
+    index: usize,
+    deadline: Option<Timestamp>,
+    notifications: NotificationSet,
+) {
+    unlink_timer(tasks, index);
+    let timer = &mut tasks[index].timer;
+    timer.deadline = deadline;
+    timer.to_post = notifications;
+    timer.period = 0;
+    if let Some(deadline) = deadline {
+        link_timer(tasks, index, deadline);
+    }
 }
 
-/// Decoded arguments for the `IRQ_CONTROL` syscall.
-#[derive(Clone, Debug)]
-pub struct IrqArgs {
-    pub notification_bitmask: u32,
-    pub control: u32,
+/// Configures the timer of task `index` to fire every `period` ticks, starting
+/// `period` ticks after `now`. A `period` of zero disables the timer.
+///
+/// Each deadline is computed from the previous one, rather than from the time
+/// the task got around to handling the last notification, so the timer
+/// doesn't drift.
+pub fn set_timer_periodic(
+    tasks: &mut [Task],
+    index: usize,
+    now: Timestamp,
+    period: u32,
+    notifications: NotificationSet,
+) {
+    let deadline = (period != 0)
+        .then(|| Timestamp::from(u64::from(now) + u64::from(period)));
+    set_timer(tasks, index, deadline, notifications);
+    tasks[index].timer.period = period;
 }
 
-/// Decoded arguments for the `PANIC` syscall.
-#[derive(Clone, Debug)]
-pub struct PanicArgs {
-    pub message: Result<USlice<u8>, UsageError>,
+/// Inserts task `index` into the timer queue, after any timers due at or before
+/// `deadline`. The task must not already be in the queue.
+fn link_timer(tasks: &mut [Task], index: usize, deadline: Timestamp) {
+    let mut prev = None;
+    let mut cur = timer_queue_head();
+    while cur != TIMER_QUEUE_END {
+        let timer = &tasks[usize::from(cur)].timer;
+        if matches!(timer.deadline, Some(d) if d > deadline) {
+            break;
+        }
+        prev = Some(usize::from(cur));
+        cur = timer.next;
+    }
+
+    let timer = &mut tasks[index].timer;
+    timer.next = cur;
+    timer.queued = true;
+    let index = index as u16;
+    match prev {
+        Some(p) => tasks[p].timer.next = index,
+        None => set_timer_queue_head(index),
+    }
 }
 
-/// Decoded arguments for the `REFRESH_TASK_ID` syscall.
-#[derive(Clone, Debug)]
-pub struct RefreshTaskIdArgs {
-    pub task_id: TaskId,
+/// Removes task `index` from the timer queue, if it's there.
+fn unlink_timer(tasks: &mut [Task], index: usize) {
+    if !tasks[index].timer.queued {
+        return;
+    }
+    let mut prev = None;
+    let mut cur = timer_queue_head();
+    while usize::from(cur) != index {
+        // A queued task is always reachable from the head.
+        uassert!(cur != TIMER_QUEUE_END);
+        prev = Some(usize::from(cur));
+        cur = tasks[usize::from(cur)].timer.next;
+    }
+
+    let timer = &mut tasks[index].timer;
+    timer.queued = false;
+    let next = timer.next;
+    match prev {
+        Some(p) => tasks[p].timer.next = next,
+        None => set_timer_queue_head(next),
+    }
 }
 
-/// Decoded arguments for the `POST` syscall.
-#[derive(Clone, Debug)]
-pub struct PostArgs {
-    pub task_id: TaskId,
-    pub notification_bits: NotificationSet,
+/// A message sent with `SEND_ASYNC`, waiting in a mailbox to be received.
+///
+/// The message contents are copied into the kernel when it's sent, so the
+/// sender is free to carry on (and even reuse its buffer) immediately.
+#[derive(Copy, Clone, Debug)]
+pub struct AsyncMessage {
+    pub sender: TaskId,
+    pub operation: u16,
+    pub len: u8,
+    pub data: [u8; ASYNC_MESSAGE_SIZE],
 }
 
-/// Decoded arguments for the `IRQ_STATUS` syscall.
-#[derive(Clone, Debug)]
-pub struct IrqStatusArgs {
-    pub notification_bitmask: u32,
+impl AsyncMessage {
+    /// Placeholder value for unused mailbox slots.
+    pub const EMPTY: Self = Self {
+        sender: TaskId(0),
+        operation: 0,
+        len: 0,
+        data: [0; ASYNC_MESSAGE_SIZE],
+    };
+
+    /// Returns the valid part of the message contents.
+    pub fn contents(&self) -> &[u8] {
+        &self.data[..usize::from(self.len)]
+    }
 }
 
-/// State for a task timer.
+/// A bounded FIFO of asynchronous messages waiting for a task.
 ///
-/// Task timers are used to multiplex the hardware timer.
+/// The storage for each task's mailbox is carved out of a kernel static at
+/// startup, according to the depth in the task's descriptor. Tasks without a
+/// mailbox get an empty slice, and can never have messages queued.
 #[derive(Debug, Default)]
-pub struct TimerState {
-    /// Deadline, in kernel time, at which this timer should fire. If `None`,
-    /// the timer is disabled.
-    deadline: Option<Timestamp>,
-    /// Set of notification bits to post to the owning task when this timer
-    /// fires.
-    to_post: NotificationSet,
+pub struct Mailbox {
+    slots: &'static mut [AsyncMessage],
+    /// Index of the oldest message in `slots`.
+    head: usize,
+    /// Number of messages currently queued.
+    len: usize,
 }
 
-/// Collection of bits that may be posted to a task's notification word.
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Default)]
-#[repr(transparent)]
-pub struct NotificationSet(pub u32);
+impl Mailbox {
+    /// Checks whether this mailbox can ever hold messages.
+    pub fn exists(&self) -> bool {
+        !self.slots.is_empty()
+    }
+
+    /// Appends `msg` to the mailbox. Returns `false` (and drops the message) if
+    /// the mailbox is full.
+    #[must_use]
+    pub fn push(&mut self, msg: AsyncMessage) -> bool {
+        if self.len == self.slots.len() {
+            return false;
+        }
+        let i = (self.head + self.len) % self.slots.len();
+        self.slots[i] = msg;
+        self.len += 1;
+        true
+    }
+
+    /// Returns a copy of the oldest message in the mailbox, if any, leaving it
+    /// queued.
+    pub fn peek(&self) -> Option<AsyncMessage> {
+        if self.len == 0 {
+            return None;
+        }
+        Some(self.slots[self.head])
+    }
+
+    /// Removes and returns the oldest message in the mailbox, if any.
+    pub fn pop(&mut self) -> Option<AsyncMessage> {
+        if self.len == 0 {
+            return None;
+        }
+        let msg = self.slots[self.head];
+        self.head = (self.head + 1) % self.slots.len();
+        self.len -= 1;
+        Some(msg)
+    }
+
+    /// Discards all queued messages.
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+}
 
 /// Return value for operations that can have scheduling implications. This is
 /// marked `must_use` because forgetting to actually update the scheduler after
@@ -771,26 +1199,177 @@ impl NextTask {
     }
 }
 
-/// Processes all enabled timers in the task table, posting notifications for
-/// any that have expired by `current_time` (and disabling them atomically).
+/// Fires every timer that has expired by `current_time`, posting its
+/// notifications to its task.
+///
+/// Since the timer queue is sorted by deadline, this works from the front and
+/// stops at the first timer that isn't due yet, so it's cheap when none are.
+/// Each timer that fires is taken off the queue and disarmed, unless it's
+/// periodic, in which case it's put back in order at its next deadline. Timers
+/// disarmed by a restart are dropped from the queue as they reach the front,
+/// without posting anything.
 pub fn process_timers(tasks: &mut [Task], current_time: Timestamp) -> NextTask {
     let mut sched_hint = NextTask::Same;
-    for (index, task) in tasks.iter_mut().enumerate() {
-        if let Some(deadline) = task.timer.deadline {
-            if deadline <= current_time {
-                task.timer.deadline = None;
-                let task_hint = if task.post(task.timer.to_post) {
-                    NextTask::Specific(index)
-                } else {
-                    NextTask::Same
-                };
-                sched_hint = sched_hint.combine(task_hint)
+    loop {
+        let head = timer_queue_head();
+        if head == TIMER_QUEUE_END {
+            break;
+        }
+        let index = usize::from(head);
+        let task = &mut tasks[index];
+        if matches!(task.timer.deadline, Some(d) if d > current_time) {
+            break;
+        }
+
+        set_timer_queue_head(task.timer.next);
+        task.timer.queued = false;
+        // If the deadline is None here, this was a timer disarmed by a restart,
+        // and we just drop it.
+        if let Some(deadline) = task.timer.deadline.take() {
+            let task_hint = if task.post(task.timer.to_post) {
+                NextTask::Specific(index)
+            } else {
+                NextTask::Same
+            };
+            sched_hint = sched_hint.combine(task_hint);
+
+            let period = u64::from(task.timer.period);
+            if period != 0 {
+                // Re-arm relative to the deadline we just hit. If we've
+                // fallen more than a period behind (say, because the tick
+                // interrupt was held off), skip the deadlines we missed
+                // rather than firing a burst to catch up; the notification
+                // is a single bit either way.
+                let late = u64::from(current_time) - u64::from(deadline);
+                let next = u64::from(deadline) + (late / period + 1) * period;
+                let next = Timestamp::from(next);
+                task.timer.deadline = Some(next);
+                link_timer(tasks, index, next);
             }
         }
     }
     sched_hint
 }
 
+/// Delivers hardware interrupt `irq` to `owner`, after the architecture code
+/// has masked it. Returns `true` if this woke a task, like `Task::post`.
+///
+/// If the application configures an IRQ storm limit, this also counts how many
+/// times `irq` has fired in the current tick. Past the limit, the owner is not
+/// notified, so the interrupt stays masked until someone re-enables it, and the
+/// supervisor gets `HUBRIS_IRQ_STORM_NOTIFICATION` instead. This keeps a
+/// peripheral that's stuck asserting its interrupt (and a driver that keeps
+/// re-enabling it) from starving everything of lower priority.
+pub fn deliver_irq(
+    tasks: &mut [Task],
+    irq: u32,
+    owner: &abi::InterruptOwner,
+) -> bool {
+    if let (Some(limit), Some(slot)) = (
+        HUBRIS_IRQ_STORM_LIMIT,
+        HUBRIS_IRQ_STORM_COUNTS.get(irq as usize),
+    ) {
+        // Each slot packs the low 16 bits of the tick in which the count was
+        // last reset, with the count, so that we never have to sweep them.
+        let tick = u64::from(crate::arch::now()) as u16;
+        let packed = slot.load(Ordering::Relaxed);
+        let count = if (packed >> 16) as u16 == tick {
+            (packed & 0xFFFF) + 1
+        } else {
+            1
+        };
+        slot.store(
+            u32::from(tick) << 16 | count.min(0xFFFF),
+            Ordering::Relaxed,
+        );
+
+        if count > limit {
+            return tasks[0]
+                .post(NotificationSet(HUBRIS_IRQ_STORM_NOTIFICATION));
+        }
+    }
+    crate::irq::mark_fired(irq);
+    crate::replay::irq(owner.task as usize, irq);
+    tasks[owner.task as usize].post(NotificationSet(owner.notification))
+}
+
+/// Checks whether every task has made forward progress since the last time
+/// this returned `true`, for the watchdog heartbeat.
+///
+/// A task has made progress if it has been scheduled, or if it is not currently
+/// runnable -- either because it's blocked waiting for something, or because
+/// it's stopped or faulted and thus the supervisor's problem. `current` is
+/// treated as scheduled, since it was running when we were called.
+///
+/// If all tasks have made progress, this clears their records and returns
+/// `true`. Otherwise, records are left intact so that the check can be retried.
+pub fn check_progress(tasks: &mut [Task], current: usize) -> bool {
+    tasks[current].scheduled = true;
+    let progressed = tasks.iter().all(|t| t.scheduled || !t.is_runnable());
+    if progressed {
+        for task in tasks {
+            task.scheduled = false;
+        }
+    }
+    progressed
+}
+
+/// Returns the task that `task` is blocked in IPC with -- either sending to, or
+/// awaiting a reply from -- if any.
+fn ipc_peer(task: &Task) -> Option<TaskId> {
+    match task.state {
+        TaskState::Healthy(SchedState::InSend(peer))
+        | TaskState::Healthy(SchedState::InReply(peer)) => Some(peer),
+        _ => None,
+    }
+}
+
+/// Recomputes the effective priority of `tasks[index]` for priority
+/// inheritance.
+///
+/// A task runs at the most important of its own (descriptor) priority and the
+/// effective priorities of all healthy tasks blocked sending to it or waiting
+/// for its reply. This bounds priority inversion when an important client is
+/// stuck behind a less important server.
+///
+/// If the task's priority changes and it is itself blocked on another task, the
+/// change is passed along the chain. The walk is bounded by the number of
+/// tasks, so a cycle of tasks sending to each other can't hang the kernel.
+///
+/// This must be called whenever a task enters or leaves a blocked IPC state
+/// targeting `tasks[index]`. Returns `true` if the priority of `tasks[index]`
+/// itself changed, which may have scheduling implications.
+pub fn update_inherited_priority(tasks: &mut [Task], index: usize) -> bool {
+    let mut index = index;
+    let mut changed_first = false;
+    for step in 0..tasks.len() {
+        let id = current_id(tasks, index);
+        let mut prio = Priority(tasks[index].descriptor.priority);
+        for t in tasks.iter() {
+            if ipc_peer(t) == Some(id)
+                && t.priority.is_more_important_than(prio)
+            {
+                prio = t.priority;
+            }
+        }
+
+        if tasks[index].priority == prio {
+            break;
+        }
+        tasks[index].priority = prio;
+        if step == 0 {
+            changed_first = true;
+        }
+
+        // Pass the change along to whatever this task is blocked on.
+        match ipc_peer(&tasks[index]) {
+            Some(peer) if peer.index() < tasks.len() => index = peer.index(),
+            _ => break,
+        }
+    }
+    changed_first
+}
+
 /// Checks a user-provided `TaskId` for validity against `table`.
 ///
 /// On success, returns an index that can be used to dereference `table` without
@@ -820,9 +1399,64 @@ pub fn check_task_id_against_table(
 /// Selects a new task to run after `previous`. Tries to be fair, kind of.
 ///
 /// If no tasks are runnable, the kernel panics.
+///
+/// Because `priority_scan` prefers the first eligible task _after_ `previous`,
+/// calling this with the current task as `previous` rotates among runnable
+/// tasks of equal priority. The timer ISR uses this, via `timeslice_tick`, to
+/// implement round-robin timeslicing.
 pub fn select(previous: usize, tasks: &[Task]) -> usize {
-    priority_scan(previous, tasks, |t| t.is_runnable())
-        .expect("no tasks runnable")
+    crate::replay::select(previous, tasks, || {
+        priority_scan(previous, tasks, |t| t.is_runnable())
+            .expect("no tasks runnable")
+    })
+}
+
+/// Index of the task whose timeslice is being measured by `timeslice_tick`, and
+/// the number of ticks it has used so far.
+static TIMESLICE: [AtomicU32; 2] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU32 = AtomicU32::new(0);
+    [ZERO; 2]
+};
+
+/// Charges one timer tick to the current task's timeslice, if timeslicing is
+/// configured.
+///
+/// When `current` has run for a full quantum, and another runnable task shares
+/// its priority, this returns `NextTask::Other` to request a reschedule; since
+/// `select` searches onward from the current task, that hands the CPU to the
+/// next of its peers. Otherwise, returns `NextTask::Same`.
+///
+/// This should be called from the timer ISR, once per tick.
+pub fn timeslice_tick(tasks: &[Task], current: usize) -> NextTask {
+    let Some(quantum) = HUBRIS_TIMESLICE else {
+        return NextTask::Same;
+    };
+
+    // If some other task has been switched in since the last tick -- for any
+    // reason -- it starts a fresh slice.
+    let mut used = TIMESLICE[1].load(Ordering::Relaxed);
+    if TIMESLICE[0].load(Ordering::Relaxed) != current as u32 {
+        TIMESLICE[0].store(current as u32, Ordering::Relaxed);
+        used = 0;
+    }
+    used += 1;
+    if used < quantum {
+        TIMESLICE[1].store(used, Ordering::Relaxed);
+        return NextTask::Same;
+    }
+    TIMESLICE[1].store(0, Ordering::Relaxed);
+
+    let prio = tasks[current].priority;
+    let has_peer = tasks
+        .iter()
+        .enumerate()
+        .any(|(i, t)| i != current && t.is_runnable() && t.priority == prio);
+    if has_peer && tasks[current].is_runnable() {
+        NextTask::Other
+    } else {
+        NextTask::Same
+    }
 }
 
 /// Scans `tasks` for the next task, after `previous`, that satisfies `pred`. If
@@ -863,6 +1497,147 @@ pub fn priority_scan(
     choice.map(|(idx, _)| idx)
 }
 
+/// If `tasks[client]` has been waiting for a reply for at least the configured
+/// dead-man limit as of `now`, describes the wait.
+pub fn stuck_reply(
+    tasks: &[Task],
+    client: usize,
+    now: Timestamp,
+) -> Option<abi::StuckReply> {
+    let limit = HUBRIS_REPLY_DEADMAN?;
+    let TaskState::Healthy(SchedState::InReply(server)) = tasks[client].state
+    else {
+        return None;
+    };
+    let waited = u64::from(now) - u64::from(tasks[client].reply_since);
+    (waited >= u64::from(limit)).then(|| abi::StuckReply {
+        client: client as u32,
+        server: server.index() as u32,
+        ticks: u32::try_from(waited).unwrap_or(u32::MAX),
+    })
+}
+
+/// Looks for servers that have held a message for longer than the configured
+/// dead-man limit without replying, and posts the supervisor's
+/// `reply-deadman` notification if it finds any it hasn't already reported.
+/// The supervisor can find the (client, server) pairs with the
+/// `find_stuck_reply` kipc.
+///
+/// A server is allowed to sit on a message forever -- it's how one halts a
+/// misbehaving client -- so this is opt-in, and only reports.
+///
+/// This should be called from the timer ISR, once per tick.
+pub fn process_reply_deadman(tasks: &mut [Task], now: Timestamp) -> NextTask {
+    if HUBRIS_REPLY_DEADMAN.is_none() {
+        return NextTask::Same;
+    }
+
+    let mut found = false;
+    for index in 0..tasks.len() {
+        if !tasks[index].deadman_reported
+            && stuck_reply(tasks, index, now).is_some()
+        {
+            tasks[index].deadman_reported = true;
+            found = true;
+        }
+    }
+    if found
+        && tasks[0].post(NotificationSet(HUBRIS_REPLY_DEADMAN_NOTIFICATION))
+    {
+        NextTask::Specific(0)
+    } else {
+        NextTask::Same
+    }
+}
+
+/// Number of tasks with an IPC deadline armed, so the timer ISR can skip the
+/// scan in the common case.
+static IPC_DEADLINES: AtomicU32 = AtomicU32::new(0);
+
+/// Sets or clears the deadline for `task`'s current `SEND`, keeping
+/// `IPC_DEADLINES` up to date.
+pub fn set_ipc_deadline(task: &mut Task, deadline: Option<Timestamp>) {
+    match (task.ipc_deadline.is_some(), deadline.is_some()) {
+        (false, true) => {
+            IPC_DEADLINES.fetch_add(1, Ordering::Relaxed);
+        }
+        (true, false) => {
+            IPC_DEADLINES.fetch_sub(1, Ordering::Relaxed);
+        }
+        _ => (),
+    }
+    task.ipc_deadline = deadline;
+}
+
+/// Ends any `SEND` whose timeout has expired as of `now` with `IPC_TIMEOUT`,
+/// making the sender runnable again.
+///
+/// This covers both a sender still waiting for the callee to receive and one
+/// waiting for its reply. In the latter case the callee may still reply (or
+/// try to borrow from the sender) later. Borrows fail as they would for any
+/// other abandoned call. The reply is dropped, and until it comes, the callee
+/// isn't given another message from the sender: otherwise, a server replying
+/// by task ID alone could answer the sender's next call with the reply meant
+/// for this one.
+///
+/// This should be called from the timer ISR, once per tick.
+pub fn process_ipc_timeouts(tasks: &mut [Task], now: Timestamp) -> NextTask {
+    if IPC_DEADLINES.load(Ordering::Relaxed) == 0 {
+        return NextTask::Same;
+    }
+
+    let mut hint = NextTask::Same;
+    for index in 0..tasks.len() {
+        if !tasks[index].ipc_deadline.is_some_and(|t| t <= now) {
+            continue;
+        }
+        set_ipc_deadline(&mut tasks[index], None);
+
+        // If the call already finished, there's nothing to time out.
+        let Some(peer) = ipc_peer(&tasks[index]) else {
+            continue;
+        };
+        if tasks[index].state == TaskState::Healthy(SchedState::InReply(peer)) {
+            tasks[index].abandoned_call = Some(peer);
+        }
+        tasks[index]
+            .save_mut()
+            .set_send_response_and_length(abi::IPC_TIMEOUT, 0);
+        tasks[index].set_healthy_state(SchedState::Runnable);
+        if !tasks[index].is_suspended() {
+            hint = hint.combine(NextTask::Specific(index));
+        }
+        // The peer no longer has this task's priority to lean on.
+        if peer.index() < tasks.len()
+            && update_inherited_priority(tasks, peer.index())
+        {
+            hint = hint.combine(NextTask::Other);
+        }
+    }
+    hint
+}
+
+/// Finds the next task, after `previous`, that's blocked sending to `receiver`.
+///
+/// This is `priority_scan` with one refinement: among the most important
+/// senders, one whose message is marked urgent is chosen ahead of those that
+/// aren't. Urgency never lets a sender overtake a more important one.
+pub fn sender_scan(
+    previous: usize,
+    tasks: &[Task],
+    receiver: TaskId,
+) -> Option<usize> {
+    let first = priority_scan(previous, tasks, |t| t.can_deliver_to(receiver))?;
+    if tasks[first].urgent {
+        return Some(first);
+    }
+    let prio = tasks[first].priority;
+    priority_scan(previous, tasks, |t| {
+        t.urgent && t.priority == prio && t.can_deliver_to(receiver)
+    })
+    .or(Some(first))
+}
+
 /// Puts a task into a forced fault condition.
 ///
 /// The task is designated by the `index` parameter. We need access to the
@@ -876,6 +1651,10 @@ pub fn priority_scan(
 /// what state the task was in *before* it faulted, and *erase* the last
 /// fault. These kinds of double-faults are expected to be super rare.
 ///
+/// The supervisor is sent `HUBRIS_FAULT_NOTIFICATION`, unless this fault
+/// trips the restart throttle, in which case it's sent
+/// `HUBRIS_RESTART_HELD_NOTIFICATION` instead.
+///
 /// Returns a `NextTask` under the assumption that, if you're hitting tasks
 /// with faults, at least one of them is probably the current task; this
 /// makes it harder to forget to request rescheduling. If you're faulting
@@ -885,12 +1664,24 @@ pub fn force_fault(
     index: usize,
     fault: FaultInfo,
 ) -> NextTask {
+    crate::trace::record(crate::trace::TraceEvent::Fault, index, 0);
+    klog!("task {} faulted: {:?}", index, fault);
     let task = &mut tasks[index];
+    let peer = ipc_peer(task);
+    let mut notification = HUBRIS_FAULT_NOTIFICATION;
     task.state = match task.state {
-        TaskState::Healthy(sched) => TaskState::Faulted {
-            original_state: sched,
-            fault,
-        },
+        TaskState::Healthy(sched) => {
+            // Whether or not this trips the restart throttle, the fault is
+            // recorded as taken; the hold is tracked separately.
+            if task.throttle.record_fault(crate::arch::now()) {
+                klog!("task {} held by the restart throttle", index);
+                notification = HUBRIS_RESTART_HELD_NOTIFICATION;
+            }
+            TaskState::Faulted {
+                original_state: sched,
+                fault,
+            }
+        }
         TaskState::Faulted { original_state, .. } => {
             // Double fault - fault while faulted
             // Original fault information is lost
@@ -900,8 +1691,14 @@ pub fn force_fault(
             }
         }
     };
-    let supervisor_awoken =
-        tasks[0].post(NotificationSet(HUBRIS_FAULT_NOTIFICATION));
+    // A faulted task no longer lends its priority to whatever it was blocked
+    // on.
+    if let Some(peer) = peer {
+        if peer.index() < tasks.len() {
+            update_inherited_priority(tasks, peer.index());
+        }
+    }
+    let supervisor_awoken = tasks[0].post(NotificationSet(notification));
     if supervisor_awoken {
         NextTask::Specific(0)
     } else {
@@ -909,6 +1706,145 @@ pub fn force_fault(
     }
 }
 
+/// Per-task fault history for the restart throttle.
+///
+/// When `HUBRIS_RESTART_THROTTLE` is configured as `(faults, window)`, a task
+/// that faults more than `faults` times within `window` ticks is held:
+/// attempts to restart it fail with `RESTART_HELD` until the hold expires.
+/// The supervisor is sent `HUBRIS_RESTART_HELD_NOTIFICATION` when the hold
+/// starts, and `HUBRIS_RESTART_RELEASED_NOTIFICATION` when it expires.
+/// Each consecutive hold is twice as long as the last, up to a limit.
+#[derive(Debug, Default)]
+struct RestartThrottle {
+    /// Start of the current counting window.
+    window_start: Timestamp,
+    /// Faults taken since `window_start`.
+    faults: u32,
+    /// Number of times the hold time has doubled.
+    backoff: u32,
+    /// If the task is being held, the time at which the hold expires.
+    held_until: Option<Timestamp>,
+}
+
+/// Cap on `RestartThrottle::backoff`, so that a hold is never more than 256
+/// windows long.
+const MAX_RESTART_BACKOFF: u32 = 8;
+
+/// Number of tasks with a restart hold pending, so the timer ISR can skip the
+/// scan in the common case.
+static HELD_TASKS: AtomicU32 = AtomicU32::new(0);
+
+impl RestartThrottle {
+    /// Notes a fault taken at `now`, returning `true` if this pushes the task
+    /// over the limit and it is now being held.
+    fn record_fault(&mut self, now: Timestamp) -> bool {
+        let Some((limit, window)) = HUBRIS_RESTART_THROTTLE else {
+            return false;
+        };
+        let now = u64::from(now);
+        let start = u64::from(self.window_start);
+        let elapsed = now.saturating_sub(start);
+        if elapsed >= u64::from(window) {
+            // A task that has stayed up for twice as long as its last hold has
+            // earned a clean slate.
+            if elapsed >= u64::from(window) << (self.backoff + 1) {
+                self.backoff = 0;
+            }
+            self.window_start = now.into();
+            self.faults = 0;
+        }
+        self.faults += 1;
+        if self.faults <= limit || self.held_until.is_some() {
+            return false;
+        }
+
+        self.held_until =
+            Some((now + (u64::from(window) << self.backoff)).into());
+        self.backoff = (self.backoff + 1).min(MAX_RESTART_BACKOFF);
+        self.window_start = now.into();
+        self.faults = 0;
+        HELD_TASKS.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+}
+
+/// Checks whether `tasks[index]` is currently held by the restart throttle,
+/// in which case a request to restart it should be refused.
+pub fn restart_held(tasks: &[Task], index: usize) -> bool {
+    tasks[index].throttle.held_until.is_some()
+}
+
+/// Releases any restart holds that have expired as of `now`, posting
+/// `HUBRIS_RESTART_RELEASED_NOTIFICATION` to the supervisor so that it can
+/// restart the affected tasks.
+///
+/// This should be called from the timer ISR, once per tick.
+pub fn process_restart_holds(tasks: &mut [Task], now: Timestamp) -> NextTask {
+    if HELD_TASKS.load(Ordering::Relaxed) == 0 {
+        return NextTask::Same;
+    }
+
+    let mut released = false;
+    for task in tasks.iter_mut() {
+        if task.throttle.held_until.is_some_and(|t| t <= now) {
+            task.throttle.held_until = None;
+            HELD_TASKS.fetch_sub(1, Ordering::Relaxed);
+            released = true;
+        }
+    }
+    if released
+        && tasks[0].post(NotificationSet(HUBRIS_RESTART_RELEASED_NOTIFICATION))
+    {
+        NextTask::Specific(0)
+    } else {
+        NextTask::Same
+    }
+}
+
+/// Returns the earliest time at which the timer ISR has something to do: a
+/// task timer to fire, an IPC to time out, a restart hold to release, or a
+/// stuck reply to report. Returns `None` if nothing is scheduled.
+///
+/// This is used to decide how many ticks can be skipped while idle, so it may
+/// err early, but never late.
+pub fn next_deadline(tasks: &[Task]) -> Option<Timestamp> {
+    let mut next: Option<Timestamp> = None;
+    let mut consider = |t: Timestamp| {
+        next = Some(next.map_or(t, |n| n.min(t)));
+    };
+
+    let head = timer_queue_head();
+    if head != TIMER_QUEUE_END {
+        // A disarmed timer at the head is dropped on the next tick, and may be
+        // hiding a due one behind it, so treat it as due now.
+        consider(tasks[usize::from(head)].timer.deadline.unwrap_or_default());
+    }
+
+    let ipc = IPC_DEADLINES.load(Ordering::Relaxed) != 0;
+    let held = HELD_TASKS.load(Ordering::Relaxed) != 0;
+    for task in tasks {
+        if let Some(t) = task.ipc_deadline.filter(|_| ipc) {
+            consider(t);
+        }
+        if let Some(t) = task.throttle.held_until.filter(|_| held) {
+            consider(t);
+        }
+        if let Some(limit) = HUBRIS_REPLY_DEADMAN {
+            if !task.deadman_reported
+                && matches!(
+                    task.state,
+                    TaskState::Healthy(SchedState::InReply(_))
+                )
+            {
+                consider(Timestamp::from(
+                    u64::from(task.reply_since) + u64::from(limit),
+                ));
+            }
+        }
+    }
+    next
+}
+
 /// Produces a current `TaskId` (i.e. one with the correct generation) for
 /// `tasks[index]`.
 pub fn current_id(tasks: &[Task], index: usize) -> TaskId {
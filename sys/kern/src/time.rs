@@ -4,10 +4,16 @@
 
 //! Implementation of kernel time.
 
+use crate::arch;
+use crate::startup::HUBRIS_TICK_HZ;
+
 /// In-kernel timestamp representation.
 ///
-/// This is currently measured in an arbitrary "tick" unit.
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+/// This counts kernel ticks since boot, at `HUBRIS_TICK_HZ`, and is what
+/// `GET_TIMER` hands to tasks. It only ever advances, and is 64 bits so that it
+/// never needs to wrap: the architecture code that keeps it treats an overflow
+/// as corruption. Code comparing timestamps can rely on both.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
 #[repr(transparent)]
 pub struct Timestamp(u64);
 
@@ -28,3 +34,13 @@ impl From<Timestamp> for u64 {
         v.0
     }
 }
+
+/// Converts the rate of the architecture's timer, in counts per millisecond,
+/// into the number of counts in a kernel tick at the application's tick rate.
+/// Returns `None` if that's zero, or too big for the timer to count.
+pub fn tick_divisor(counts_per_ms: u32) -> Option<u32> {
+    let divisor = u64::from(counts_per_ms) * 1000 / u64::from(HUBRIS_TICK_HZ);
+    u32::try_from(divisor)
+        .ok()
+        .filter(|&d| d != 0 && d <= arch::MAX_TICK_DIVISOR)
+}
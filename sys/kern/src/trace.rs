@@ -0,0 +1,128 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Kernel event trace.
+//!
+//! When the kernel is built with the `trace` feature, it records context
+//! switches, syscalls, IPC, interrupts, and faults into a ring of fixed-size
+//! records in RAM, `KERNEL_TRACE`, which a debugger can find and decode after
+//! the fact. Without the feature, the hooks compile to nothing.
+//!
+//! The ring lives in `.uninit`, like `fail::KERNEL_BOOT_FAULT`, so that the
+//! events leading up to a reset are still there to look at afterwards. A
+//! `Boot` record marks where each boot begins.
+//!
+//! Recording is deliberately cheap: no locking (the kernel can't be preempted
+//! by itself) and no formatting, just a timestamp and three integers. The
+//! timestamp is the low 32 bits of `arch::cycle_count`.
+
+/// Number of records kept in the ring. Older records are overwritten.
+pub const TRACE_LEN: usize = 64;
+
+/// Value of `TraceBuffer::magic` once the buffer has been initialized.
+pub const TRACE_MAGIC: u32 = 0x7ACE_B0F0;
+
+/// Kinds of event recorded in the trace. The meanings of the `task` and `arg`
+/// fields of a `TraceRecord` depend on the event.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u16)]
+pub enum TraceEvent {
+    /// The kernel started. `task` and `arg` are zero.
+    Boot = 1,
+    /// The CPU was given to `task`.
+    ContextSwitch = 2,
+    /// `task` made syscall number `arg`.
+    Syscall = 3,
+    /// `task` sent a message to the task at index `arg`.
+    Send = 4,
+    /// `task` replied to the task at index `arg`.
+    Reply = 5,
+    /// Hardware interrupt `arg` was delivered to `task`.
+    Irq = 6,
+    /// `task` faulted.
+    Fault = 7,
+    /// The board's low-power idle routine, run on behalf of `task`, returned
+    /// with hardware interrupt `arg` pending (or `u32::MAX` for none).
+    Wake = 8,
+}
+
+/// A single trace record.
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct TraceRecord {
+    /// Low 32 bits of the cycle count when the event was recorded.
+    pub timestamp: u32,
+    /// A `TraceEvent`, or 0 for a slot that's never been written.
+    pub event: u16,
+    /// Index of the task involved.
+    pub task: u16,
+    /// Event-specific argument.
+    pub arg: u32,
+}
+
+/// The trace ring, as laid out in memory.
+#[repr(C)]
+pub struct TraceBuffer {
+    /// `TRACE_MAGIC` if the rest of the buffer is meaningful.
+    pub magic: u32,
+    /// Total number of records ever written, modulo 2^32. The most recent
+    /// record is at `(next - 1) % TRACE_LEN`.
+    pub next: u32,
+    pub records: [TraceRecord; TRACE_LEN],
+}
+
+#[cfg(feature = "trace")]
+#[used]
+#[no_mangle]
+#[link_section = ".uninit.KERNEL_TRACE"]
+static mut KERNEL_TRACE: core::mem::MaybeUninit<TraceBuffer> =
+    core::mem::MaybeUninit::uninit();
+
+/// Prepares the trace ring, clearing it if it doesn't hold a trace from a
+/// previous boot, and records a `Boot` event.
+pub(crate) fn init() {
+    #[cfg(feature = "trace")]
+    {
+        // Safety: we're called once from `start_kernel`, before anything else
+        // can touch the buffer. Any bit pattern is a valid `u32`, so reading
+        // the magic out of uninitialized RAM is fine.
+        unsafe {
+            let buf = core::ptr::addr_of_mut!(KERNEL_TRACE).cast::<u32>();
+            if buf.read_volatile() != TRACE_MAGIC {
+                core::ptr::addr_of_mut!(KERNEL_TRACE)
+                    .cast::<u8>()
+                    .write_bytes(0, core::mem::size_of::<TraceBuffer>());
+                buf.write_volatile(TRACE_MAGIC);
+            }
+        }
+    }
+    record(TraceEvent::Boot, 0, 0);
+}
+
+/// Appends an event to the trace ring, if tracing is enabled.
+#[inline(always)]
+pub(crate) fn record(event: TraceEvent, task: usize, arg: u32) {
+    #[cfg(feature = "trace")]
+    {
+        let rec = TraceRecord {
+            timestamp: crate::arch::cycle_count() as u32,
+            event: event as u16,
+            task: task as u16,
+            arg,
+        };
+        // Safety: the kernel is single-threaded and not reentrant, so nothing
+        // else is accessing the buffer, and `init` has made it valid before
+        // the first call here.
+        unsafe {
+            let buf = &mut *core::ptr::addr_of_mut!(KERNEL_TRACE)
+                .cast::<TraceBuffer>();
+            buf.records[buf.next as usize % TRACE_LEN] = rec;
+            buf.next = buf.next.wrapping_add(1);
+        }
+    }
+    #[cfg(not(feature = "trace"))]
+    {
+        let _ = (event, task, arg);
+    }
+}
@@ -4,305 +4,105 @@
 
 //! Support for safely interacting with untrusted/unprivileged/user memory.
 
-use core::marker::PhantomData;
-use core::ops::Range;
-use zerocopy::FromBytes;
-
 use crate::err::InteractFault;
 use crate::task::Task;
 use crate::util::index2_distinct;
-use abi::{FaultInfo, FaultSource, UsageError};
+use abi::{
+    FaultInfo, FaultSource, LeaseAttributes, ULeaseSegment, UsageError,
+    MAX_LEASE_SEGMENTS,
+};
+use zerocopy::{AsBytes, FromBytes};
 
-/// A (user, untrusted, unprivileged) slice.
-///
-/// A `USlice` is passed into the kernel by a task, and is intended to refer to
-/// memory that task controls -- for instance, as a place where the kernel can
-/// deposit a message to that task. However, the `USlice` type itself simply
-/// represents an _allegation_ from the task that a section of address space is
-/// suitable; it does _not_ demonstrate that the task has access to that memory.
-/// It could point into the kernel, to peripherals, etc.
-///
-/// Having a `USlice<T>` tells you the following:
-///
-/// - Some task has claimed it has access to a section of address space
-///   (delimited by the `USlice`).
-/// - The base of the section is correctly aligned for type `T`.
-/// - The section does not wrap around the end of the address space.
+pub use kerncore::{USlice, UserCopyable};
+
+/// The memory covered by a lease, from the borrower's point of view.
 ///
-/// To actually access the memory referred to by a `USlice`, you need to hand it
-/// to `Task::try_read` or `Task::try_write` to validate it.
+/// A simple lease covers one contiguous slice of the lender's memory. A
+/// `SEGMENTED` lease covers up to `MAX_LEASE_SEGMENTS` slices, which the
+/// borrower sees concatenated in order, so that it can do scatter-gather
+/// transfers without the lender copying into a bounce buffer first.
 ///
-/// Note that this same `USlice` type is used for both readable and read-write
-/// contexts -- there is no `USliceMut`. So far, this has not seemed like a
-/// decision that will generate bugs.
-pub struct USlice<T> {
-    /// Base address of the slice.
-    base_address: usize,
-    /// Number of `T` elements in the slice.
-    length: usize,
-    /// since we don't actually use T...
-    _marker: PhantomData<*mut [T]>,
+/// Like `USlice`, a `LeasedArea` is only an allegation; the segments are
+/// checked against the lender's memory when they're actually accessed.
+#[derive(Clone, Debug)]
+pub struct LeasedArea {
+    pub attributes: LeaseAttributes,
+    segments: [USlice<u8>; MAX_LEASE_SEGMENTS],
+    count: usize,
 }
 
-impl<T> USlice<T> {
-    /// Constructs a `USlice` given a base address and length passed from
-    /// untrusted code.
-    ///
-    /// This will only succeed if such a slice would not overlap or touch the
-    /// top of the address space, and if `base_address` is correctly aligned for
-    /// `T`.
-    ///
-    /// This method will categorically reject zero-sized T.
-    pub fn from_raw(
-        base_address: usize,
-        length: usize,
-    ) -> Result<Self, UsageError> {
-        // NOTE: the properties checked here are critical for the correctness of
-        // this type. Think carefully before loosening any of them, or adding a
-        // second way to construct a USlice.
-
-        // ZST check, should resolve at compile time:
-        uassert!(core::mem::size_of::<T>() != 0);
-
-        // Alignment check:
-        if base_address % core::mem::align_of::<T>() != 0 {
-            return Err(UsageError::InvalidSlice);
-        }
-        // Check that a slice of `length` `T`s can even exist starting at
-        // `base_address`, without wrapping around.
-        let size_in_bytes = length
-            .checked_mul(core::mem::size_of::<T>())
-            .ok_or(UsageError::InvalidSlice)?;
-        // Note: this subtraction cannot underflow. You can subtract any usize
-        // from usize::MAX.
-        let highest_possible_base = usize::MAX - size_in_bytes;
-        if base_address <= highest_possible_base {
-            Ok(Self {
-                base_address,
-                length,
-                _marker: PhantomData,
-            })
-        } else {
-            Err(UsageError::InvalidSlice)
-        }
-    }
-
-    /// Constructs an empty `USlice`.
-    ///
-    /// This ensures that the base address is not zero and is properly aligned,
-    /// despite the length being zero, so that it's safe to turn into an empty
-    /// slice.
-    pub fn empty() -> Self {
+impl LeasedArea {
+    /// Creates a `LeasedArea` covering a single slice.
+    pub fn contiguous(attributes: LeaseAttributes, slice: USlice<u8>) -> Self {
+        let mut segments: [USlice<u8>; MAX_LEASE_SEGMENTS] =
+            core::array::from_fn(|_| USlice::empty());
+        segments[0] = slice;
         Self {
-            base_address: core::ptr::NonNull::<T>::dangling().as_ptr() as usize,
-            length: 0,
-            _marker: PhantomData,
+            attributes,
+            segments,
+            count: 1,
         }
     }
 
-    /// Returns `true` if this slice is zero-length, `false` otherwise.
-    pub fn is_empty(&self) -> bool {
-        self.length == 0
-    }
-
-    /// Returns the number of `T`s in this slice.
-    pub fn len(&self) -> usize {
-        self.length
-    }
-
-    /// Returns the bottom address of this slice as a `usize`.
-    pub fn base_addr(&self) -> usize {
-        self.base_address
-    }
-
-    /// Returns the end address of the slice, which is the address one past its
-    /// final byte -- or its base address if it's empty.
-    pub fn end_addr(&self) -> usize {
-        // Compute the size using an unchecked multiplication. Why can we do
-        // this? Because we checked that this multiplication does not overflow
-        // at construction above. Using an unchecked multiply here removes some
-        // instructions.
-        let size_in_bytes = self.length.wrapping_mul(core::mem::size_of::<T>());
-        self.base_address.wrapping_add(size_in_bytes)
-    }
-
-    /// Returns the *highest* address in this slice, inclusive.
+    /// Creates a `LeasedArea` from a lender's segment table.
     ///
-    /// This produces `None` if the slice is empty.
-    pub fn last_byte_addr(&self) -> Option<usize> {
-        // This implementation would be wrong for ZSTs (it would indicate any
-        // slice of ZSTs as empty), but we blocked them at construction.
-
-        // Compute the size using an unchecked multiplication. Why can we do
-        // this? Because we checked that this multiplication does not overflow
-        // at construction above. Using an unchecked multiply here removes some
-        // instructions.
-        let size_in_bytes = self.length.wrapping_mul(core::mem::size_of::<T>());
-        if size_in_bytes == 0 {
-            None
-        } else {
-            Some(
-                // Note: wrapping operations are safe here because we checked
-                // that the slice doesn't overlap the end of the address space
-                // at construction.
-                self.base_address
-                    .wrapping_add(size_in_bytes)
-                    .wrapping_sub(1),
-            )
-        }
-    }
-
-    /// Checks whether this slice aliases (overlaps) `other`.
-    ///
-    /// Empty slices alias no slices, including themselves.
-    pub fn aliases(&self, other: &Self) -> bool {
-        // This test is made slightly involved by a desire to support slices
-        // that end at the top of the address space. We've already verified at
-        // construction that the range is valid.
-
-        match (self.last_byte_addr(), other.last_byte_addr()) {
-            (Some(self_end), Some(other_end)) => {
-                self_end >= other.base_address && other_end >= self.base_address
-            }
-            // One slice or the other was empty
-            _ => false,
+    /// This fails if the table has too many entries, if any segment would wrap
+    /// the address space, or if the total length doesn't fit in a `u32`. All of
+    /// these are the lender's fault.
+    pub fn from_segments(
+        attributes: LeaseAttributes,
+        table: &[ULeaseSegment],
+    ) -> Result<Self, UsageError> {
+        if table.len() > MAX_LEASE_SEGMENTS {
+            return Err(UsageError::BadLeaseSegments);
         }
-    }
-}
-
-impl<T> USlice<T>
-where
-    T: FromBytes,
-{
-    /// Converts this into an _actual_ slice that can be directly read by the
-    /// kernel.
-    ///
-    /// If you are implementing a syscall, please have a look at
-    /// `Task::try_read` instead.
-    ///
-    /// # Safety
-    ///
-    /// This operation is totally unchecked, so to use it safely, you must first
-    /// convince yourself of the following.
-    ///
-    /// 1. That the memory region this `USlice` describes is actual memory.
-    /// 2. That this memory is legally readable by whatever task you're doing
-    ///    work on behalf of.
-    /// 3. That it contains bytes that are valid `T`s. (The `FromBytes`
-    ///    constraint ensures this statically.)
-    /// 4. That it does not alias any slice you intend to `&mut`-reference with
-    ///    `assume_writable`, or any kernel memory.
-    pub unsafe fn assume_readable(&self) -> &[T] {
-        // Safety: this function's contract ensures that the slice we produce
-        // here is valid.
-        unsafe {
-            core::slice::from_raw_parts(
-                self.base_address as *const T,
-                self.length,
+        let mut segments: [USlice<u8>; MAX_LEASE_SEGMENTS] =
+            core::array::from_fn(|_| USlice::empty());
+        let mut total = 0u32;
+        for (seg, ent) in segments.iter_mut().zip(table) {
+            *seg = USlice::from_raw(
+                ent.base_address as usize,
+                ent.length as usize,
             )
+            .map_err(|_| UsageError::BadLeaseSegments)?;
+            total = total
+                .checked_add(ent.length)
+                .ok_or(UsageError::BadLeaseSegments)?;
         }
+        Ok(Self {
+            attributes,
+            segments,
+            count: table.len(),
+        })
     }
 
-    /// Converts this into an _actual_ slice that can be directly read and
-    /// written by the kernel.
-    ///
-    /// If you are implementing a syscall, please have a look at
-    /// `Task::try_write` instead.
-    ///
-    /// # Safety
-    ///
-    /// This operation is totally unchecked, so to use it safely, you must first
-    /// convince yourself of the following:
-    ///
-    /// 1. That the memory region this `USlice` describes is actual memory.
-    /// 2. That this memory is legally writable by whatever task you're doing
-    ///    work on behalf of.
-    /// 3. That it contains bytes that are valid `T`s. (The `FromBytes`
-    ///    constraint ensures this statically.)
-    /// 4. That it does not alias any other slice you intend to access, or any
-    ///    kernel memory.
-    pub unsafe fn assume_writable(&mut self) -> &mut [T] {
-        // Safety: this function's contract ensures that the slice we produce
-        // here is valid.
-        unsafe {
-            core::slice::from_raw_parts_mut(
-                self.base_address as *mut T,
-                self.length,
-            )
-        }
+    /// Total length of the leased area, in bytes.
+    pub fn len(&self) -> usize {
+        self.segments().iter().map(USlice::len).sum()
     }
 
-    /// Converts this into a raw slice, which could be used for raw pointer
-    /// accesses.
-    ///
-    /// If you are implementing a syscall, please have a look at
-    /// `Task::try_read_dma` instead.
-    ///
-    /// # Safety
-    ///
-    /// This operation is totally unchecked, so to use it safely, you must first
-    /// convince yourself of the following.
-    ///
-    /// 1. That the memory region this `USlice` describes is actual memory.
-    /// 2. That this memory is legally readable by whatever task you're doing
-    ///    work on behalf of.
-    /// 3. That it contains bytes that are valid `T`s. (The `FromBytes`
-    ///    constraint ensures this statically.)
-    /// 4. That it does not alias any slice you intend to `&mut`-reference with
-    ///    `assume_writable`, or any kernel memory.
-    pub unsafe fn assume_readable_raw(&self) -> Range<*const T> {
-        let p = self.base_address as *const T;
-        // Safety: this is unsafe because the pointer addition might overflow.
-        // It won't though, due to the invariants on this type and the required
-        // preconditions for this function.
-        unsafe { p..p.add(self.length) }
+    /// Returns the segments making up the leased area, in order.
+    pub fn segments(&self) -> &[USlice<u8>] {
+        &self.segments[..self.count]
     }
-}
 
-impl<T> Clone for USlice<T> {
-    fn clone(&self) -> Self {
-        Self {
-            base_address: self.base_address,
-            length: self.length,
-            _marker: PhantomData,
+    /// Drops the first `offset` bytes of the leased area. Returns `None` if
+    /// `offset` is greater than the length of the area.
+    pub fn offset(mut self, mut offset: usize) -> Option<Self> {
+        if offset > self.len() {
+            return None;
         }
-    }
-}
-
-/// Can't `derive(Debug)` for `USlice` because that puts a `Debug` requirement
-/// on `T`, and that's silly.
-impl<T> core::fmt::Debug for USlice<T> {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        f.debug_struct("USlice")
-            .field("base_address", &self.base_address)
-            .field("length", &self.length)
-            .finish()
-    }
-}
-
-/// Extracts the base/bound part of a `ULease` as a `USlice` of bytes.
-impl<'a> From<&'a abi::ULease> for USlice<u8> {
-    fn from(lease: &'a abi::ULease) -> Self {
-        Self {
-            base_address: lease.base_address as usize,
-            length: lease.length as usize,
-            _marker: PhantomData,
+        let mut first = 0;
+        while offset != 0 && offset >= self.segments[first].len() {
+            offset -= self.segments[first].len();
+            first += 1;
         }
-    }
-}
-
-/// Compatibility with the generic portable algorithms in `kerncore`.
-impl<T> kerncore::UserSlice for USlice<T> {
-    fn is_empty(&self) -> bool {
-        self.is_empty()
-    }
-
-    fn base_addr(&self) -> usize {
-        self.base_addr()
-    }
-
-    fn end_addr(&self) -> usize {
-        self.end_addr()
+        if first < self.count {
+            self.segments[first] = self.segments[first].split_off(offset);
+        }
+        self.segments[..self.count].rotate_left(first);
+        self.count -= first;
+        Some(self)
     }
 }
 
@@ -338,7 +138,7 @@ pub fn safe_copy(
     // arbitrary.
     let dst = if from_slice.aliases(&to_slice) {
         Err(FaultInfo::MemoryAccess {
-            address: Some(to_slice.base_address as u32),
+            address: Some(to_slice.base_addr() as u32),
             source: FaultSource::Kernel,
         })
     } else {
@@ -383,7 +183,7 @@ pub fn safe_copy_dma(
     // arbitrary.
     let dst = if from_slice.aliases(&to_slice) {
         Err(FaultInfo::MemoryAccess {
-            address: Some(to_slice.base_address as u32),
+            address: Some(to_slice.base_addr() as u32),
             source: FaultSource::Kernel,
         })
     } else {
@@ -395,6 +195,18 @@ pub fn safe_copy_dma(
             // We are now convinced, after querying the tasks, that these RAM
             // areas are legit.
             //
+            // Unless the source task is current, we're reading it through the
+            // default memory map, which caches memory that the task itself
+            // sees as non-cacheable `DMA` memory. Lines from an earlier read
+            // of ours could be stale by now, so drop them first. (Normal
+            // memory just gets written back; this path is rare enough that we
+            // don't bother telling the two apart.)
+            crate::arch::dcache_maintain(
+                abi::CacheOp::CleanInvalidate,
+                from.start as u32,
+                copy_len as u32,
+            );
+
             // Safety: copy_nonoverlapping is unsafe because it can do arbitrary
             // memory-to-memory transfers. In this case, we've checked that both
             // the source and destination addresses are valid, and rounded down
@@ -416,3 +228,96 @@ pub fn safe_copy_dma(
         }),
     }
 }
+
+/// Copies bytes out of a (possibly segmented) leased area in
+/// `tasks[lender_index]` into `tasks[to_index]` at region `to_slice`.
+///
+/// Segments are copied in order until either they or `to_slice` run out, and
+/// the total number of bytes copied is returned. If any segment faults, the
+/// copy stops there and the `InteractFault` is returned; bytes from earlier
+/// segments will have been copied already.
+pub fn safe_copy_from_lease(
+    tasks: &mut [Task],
+    lender_index: usize,
+    area: &LeasedArea,
+    to_index: usize,
+    to_slice: USlice<u8>,
+) -> Result<usize, InteractFault> {
+    let mut done = 0;
+    for seg in area.segments() {
+        if done == to_slice.len() {
+            break;
+        }
+        let rest = to_slice.split_off(done);
+        done += safe_copy(tasks, lender_index, seg.clone(), to_index, rest)?;
+    }
+    Ok(done)
+}
+
+/// Copies bytes from `tasks[from_index]` in region `from_slice` into a
+/// (possibly segmented) leased area in `tasks[lender_index]`.
+///
+/// See `safe_copy_from_lease` for the details, which are symmetric.
+pub fn safe_copy_to_lease(
+    tasks: &mut [Task],
+    from_index: usize,
+    from_slice: USlice<u8>,
+    lender_index: usize,
+    area: &LeasedArea,
+) -> Result<usize, InteractFault> {
+    let mut done = 0;
+    for seg in area.segments() {
+        if done == from_slice.len() {
+            break;
+        }
+        let rest = from_slice.split_off(done);
+        done += safe_copy(tasks, from_index, rest, lender_index, seg.clone())?;
+    }
+    Ok(done)
+}
+
+/// Copies a `T` out of `task`'s memory, from bytes that the task has said hold
+/// one -- a message, say, or a lease.
+///
+/// `bytes` must be aligned for `T` and exactly its size; otherwise this is a
+/// usage error, as is memory the task can't read. `T` is checked at compile
+/// time as described under `UserCopyable`.
+pub fn read_struct<T>(task: &Task, bytes: &USlice<u8>) -> Result<T, FaultInfo>
+where
+    T: FromBytes + Copy,
+{
+    let slice = bytes.cast::<T>().map_err(FaultInfo::SyscallUsage)?;
+    match task.try_read(&slice)? {
+        [value] => Ok(*value),
+        _ => Err(FaultInfo::SyscallUsage(UsageError::InvalidSlice)),
+    }
+}
+
+/// Copies `value` into `task`'s memory, at the start of `bytes`, and returns
+/// the number of bytes written.
+///
+/// `bytes` must be aligned for `T` and at least its size, and writable by the
+/// task; otherwise this is a usage error. As with `read_struct`, `T` is checked
+/// at compile time. `AsBytes` means `T` has no padding, which could otherwise
+/// carry kernel data out to the task.
+pub fn write_struct<T>(
+    task: &mut Task,
+    bytes: &USlice<u8>,
+    value: &T,
+) -> Result<usize, FaultInfo>
+where
+    T: AsBytes + FromBytes + Copy,
+{
+    let size = core::mem::size_of::<T>();
+    let mut slice = bytes
+        .prefix(size)
+        .cast::<T>()
+        .map_err(FaultInfo::SyscallUsage)?;
+    match task.try_write(&mut slice)? {
+        [slot] => {
+            *slot = *value;
+            Ok(size)
+        }
+        _ => Err(FaultInfo::SyscallUsage(UsageError::InvalidSlice)),
+    }
+}
@@ -0,0 +1,95 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Kernel-driven hardware watchdog heartbeat.
+//!
+//! If the application configures `[kernel.watchdog]` in its `app.toml`, the
+//! kernel takes responsibility for petting the hardware watchdog (e.g. the IWDG
+//! on STM32, or the WWDT on LPC55). Petting it unconditionally from the timer
+//! interrupt would only prove that interrupts still arrive, so instead the
+//! kernel pets it only after observing forward progress: every task that is
+//! runnable must have been scheduled at least once within the configured
+//! window. Tasks that are blocked in send, receive, or reply have yielded the
+//! CPU and count as having made progress; a task that is runnable but starved
+//! (say, by a higher priority task stuck in a loop) does not.
+//!
+//! Stopped and faulted tasks are ignored. Restarting them is the supervisor's
+//! job -- and since the supervisor is a task too, it still has to be scheduled
+//! for the watchdog to be petted.
+//!
+//! Because the kernel is SoC-independent, it doesn't know how to pet any
+//! particular watchdog. Board setup code must supply that routine in a
+//! `Watchdog` struct passed to `configure_watchdog` before calling
+//! `start_kernel`. The board is also responsible for configuring and starting
+//! the watchdog peripheral itself, and for granting no task access to it.
+
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+
+use crate::startup::HUBRIS_WATCHDOG_WINDOW;
+use crate::task::{self, Task};
+
+/// Hooks that must be provided by the board setup code if the application has
+/// enabled the watchdog heartbeat.
+pub struct Watchdog {
+    /// Pets (reloads, feeds, kicks) the hardware watchdog. This is called from
+    /// the kernel's timer ISR and should be quick.
+    pub pet: fn(),
+}
+
+/// Supplies the kernel with the board's watchdog routines.
+pub fn configure_watchdog(watchdog: &'static Watchdog) {
+    WATCHDOG.store(watchdog as *const _ as *mut _, Ordering::Relaxed);
+}
+
+/// Internal pointer written by `configure_watchdog` and read by `table`. If
+/// this is null, no watchdog has been provided, and the heartbeat will never
+/// pet anything.
+static WATCHDOG: AtomicPtr<Watchdog> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Grabs a reference to the configured watchdog, if any.
+pub(crate) fn table() -> Option<&'static Watchdog> {
+    let p = WATCHDOG.load(Ordering::Relaxed);
+    if p.is_null() {
+        None
+    } else {
+        // We only write this pointer from a valid `&'static`, and we're handing
+        // out a shared reference, so this should be ok...
+        unsafe { Some(&*p) }
+    }
+}
+
+/// Number of ticks since the watchdog was last petted (or since boot).
+///
+/// This is only accessed from the timer ISR, which cannot be preempted by other
+/// kernel code.
+static ELAPSED: AtomicU32 = AtomicU32::new(0);
+
+/// Advances the heartbeat by one tick, petting the watchdog if the window has
+/// elapsed and all tasks have made progress.
+///
+/// `current` is the index of the task that was interrupted by the tick; it's
+/// definitely running, even if it hasn't been switched to recently.
+///
+/// If the window elapses without progress, we keep checking on every tick, so
+/// that the watchdog gets petted as soon as the stalled task is scheduled. If
+/// it never is, the hardware watchdog will eventually reset the system, which
+/// is the point.
+pub(crate) fn tick(tasks: &mut [Task], current: usize) {
+    let Some(window) = HUBRIS_WATCHDOG_WINDOW else {
+        return;
+    };
+
+    let elapsed = ELAPSED.load(Ordering::Relaxed).saturating_add(1);
+    if elapsed < window {
+        ELAPSED.store(elapsed, Ordering::Relaxed);
+        return;
+    }
+
+    if task::check_progress(tasks, current) {
+        crate::arch::pet_watchdog();
+        ELAPSED.store(0, Ordering::Relaxed);
+    } else {
+        ELAPSED.store(elapsed, Ordering::Relaxed);
+    }
+}
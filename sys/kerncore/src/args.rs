@@ -0,0 +1,517 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Decoding of syscall arguments.
+//!
+//! Syscall arguments arrive in registers, which the calling task can fill with
+//! anything it likes. The kernel copies them into a `SyscallArgs` and decodes
+//! them here, into an *argument struct* for each class of syscall. Decoding is
+//! a pure function of the register values, so that it can be exercised on the
+//! host with arbitrary input.
+//!
+//! Decoding checks what can be checked without knowing about tasks: slices
+//! must be aligned and mustn't wrap around the address space, and enumerated
+//! values must be in range. Problems are reported per-field, as a `Result`,
+//! because some syscalls only look at some of their arguments, and a bad
+//! field that's never used shouldn't fault the caller. Whether the caller can
+//! actually get at the memory it names is for the kernel to check later.
+
+use abi::{
+    ReplyFaultDetail, ReplyFaultReason, TaskId, UBorrow, ULease, UsageError,
+};
+
+use crate::USlice;
+
+/// Collection of bits that may be posted to a task's notification word.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Default)]
+#[repr(transparent)]
+pub struct NotificationSet(pub u64);
+
+/// The argument registers of a syscall, as the calling task left them.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SyscallArgs(pub [u32; 7]);
+
+impl SyscallArgs {
+    /// Interprets arguments as for the SEND syscall and returns the results.
+    ///
+    /// This is inlined because it's called from several places, and most of
+    /// those places only use _part_ of its result -- so inlining it lets most
+    /// of its code be eliminated and makes text smaller.
+    #[inline(always)]
+    pub fn as_send_args(&self) -> SendArgs {
+        let r = &self.0;
+        SendArgs {
+            callee: TaskId((r[0] >> 16) as u16),
+            operation: r[0] as u16,
+            message: USlice::from_raw(r[1] as usize, r[2] as usize),
+            response: USlice::from_raw(r[3] as usize, r[4] as usize),
+            lease_table: USlice::from_raw(
+                r[5] as usize,
+                (r[6] & abi::SEND_LEASE_COUNT_MASK) as usize,
+            ),
+            urgent: r[6] & abi::SEND_URGENT_FLAG != 0,
+            timeout: (r[6] >> abi::SEND_TIMEOUT_SHIFT) & abi::SEND_TIMEOUT_MAX,
+        }
+    }
+
+    /// Interprets arguments as for the RECV syscall and returns the results.
+    ///
+    /// This is inlined for the same reason as `as_send_args`.
+    #[inline(always)]
+    pub fn as_recv_args(&self) -> RecvArgs {
+        let r = &self.0;
+        RecvArgs {
+            buffer: USlice::from_raw(r[0] as usize, r[1] as usize),
+            notification_mask: u64::from(r[4]) << 32 | u64::from(r[2]),
+            specific_sender: if r[3] & (1 << 31) != 0 {
+                Some(TaskId(r[3] as u16))
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Interprets arguments as for the REPLY syscall and returns the results.
+    pub fn as_reply_args(&self) -> ReplyArgs {
+        let r = &self.0;
+        ReplyArgs {
+            callee: TaskId(r[0] as u16),
+            response_code: r[1],
+            message: USlice::from_raw(r[2] as usize, r[3] as usize),
+            nonce: r[4],
+        }
+    }
+
+    /// Interprets arguments as for the `REPLY_FAULT` syscall and returns the
+    /// results.
+    pub fn as_reply_fault_args(&self) -> ReplyFaultArgs {
+        let r = &self.0;
+        ReplyFaultArgs {
+            callee: TaskId(r[0] as u16),
+            reason: ReplyFaultReason::try_from(r[1])
+                .map_err(|_| UsageError::BadReplyFaultReason),
+            nonce: r[2],
+            detail: if r[3] != 0 {
+                Some(ReplyFaultDetail {
+                    argument: r[4],
+                    value: r[5],
+                })
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Interprets arguments as for the `SET_TIMER` syscall and returns the
+    /// results.
+    pub fn as_set_timer_args(&self) -> SetTimerArgs {
+        let r = &self.0;
+        SetTimerArgs {
+            deadline: if r[0] != 0 {
+                Some(u64::from(r[2]) << 32 | u64::from(r[1]))
+            } else {
+                None
+            },
+            notification: NotificationSet(
+                u64::from(r[4]) << 32 | u64::from(r[3]),
+            ),
+        }
+    }
+
+    /// Interprets arguments as for the `SET_TIMER_PERIODIC` syscall and
+    /// returns the results.
+    pub fn as_set_timer_periodic_args(&self) -> SetTimerPeriodicArgs {
+        let r = &self.0;
+        SetTimerPeriodicArgs {
+            interval: r[0],
+            notification: NotificationSet(
+                u64::from(r[2]) << 32 | u64::from(r[1]),
+            ),
+        }
+    }
+
+    /// Interprets arguments as for the `BORROW_*` family of syscalls and
+    /// returns the result.
+    pub fn as_borrow_args(&self) -> BorrowArgs {
+        let r = &self.0;
+        BorrowArgs {
+            lender: TaskId(r[0] as u16),
+            lease_number: r[1] as usize,
+            offset: r[2] as usize,
+            buffer: USlice::from_raw(r[3] as usize, r[4] as usize),
+        }
+    }
+
+    /// Interprets arguments as for the `BORROW_VECTORED` syscall and returns
+    /// the results.
+    pub fn as_borrow_vectored_args(&self) -> BorrowVectoredArgs {
+        let r = &self.0;
+        BorrowVectoredArgs {
+            lender: TaskId(r[0] as u16),
+            lease_number: r[1] as usize,
+            write: r[2] != 0,
+            transfers: USlice::from_raw(r[3] as usize, r[4] as usize),
+        }
+    }
+
+    /// Interprets arguments as for the `REPLY_LEASE` syscall and returns the
+    /// results.
+    pub fn as_reply_lease_args(&self) -> ReplyLeaseArgs {
+        let r = &self.0;
+        ReplyLeaseArgs {
+            callee: TaskId(r[0] as u16),
+            response_code: r[1],
+            lease_number: r[2] as usize,
+            offset: r[3] as usize,
+            message: USlice::from_raw(r[4] as usize, r[5] as usize),
+            nonce: r[6],
+        }
+    }
+
+    /// Interprets arguments as for the `IRQ_CONTROL` syscall and returns the
+    /// results.
+    pub fn as_irq_args(&self) -> IrqArgs {
+        let r = &self.0;
+        IrqArgs {
+            notification_bitmask: u64::from(r[2]) << 32 | u64::from(r[0]),
+            control: r[1],
+        }
+    }
+
+    /// Interprets arguments as for the `PANIC` syscall and returns the results.
+    pub fn as_panic_args(&self) -> PanicArgs {
+        let r = &self.0;
+        PanicArgs {
+            message: USlice::from_raw(r[0] as usize, r[1] as usize),
+        }
+    }
+
+    /// Interprets arguments as for the `REFRESH_TASK_ID` syscall and returns
+    /// the results.
+    pub fn as_refresh_task_id_args(&self) -> RefreshTaskIdArgs {
+        RefreshTaskIdArgs {
+            task_id: TaskId(self.0[0] as u16),
+        }
+    }
+
+    /// Interprets arguments as for the `POST` syscall and returns the results.
+    pub fn as_post_args(&self) -> PostArgs {
+        let r = &self.0;
+        PostArgs {
+            task_id: TaskId(r[0] as u16),
+            notification_bits: NotificationSet(
+                u64::from(r[2]) << 32 | u64::from(r[1]),
+            ),
+        }
+    }
+
+    /// Interprets arguments as for the `SEND_ASYNC` syscall and returns the
+    /// results.
+    pub fn as_send_async_args(&self) -> SendAsyncArgs {
+        let r = &self.0;
+        SendAsyncArgs {
+            callee: TaskId((r[0] >> 16) as u16),
+            operation: r[0] as u16,
+            message: USlice::from_raw(r[1] as usize, r[2] as usize),
+        }
+    }
+
+    /// Interprets arguments as for the `IRQ_STATUS` syscall and returns the
+    /// results.
+    pub fn as_irq_status_args(&self) -> IrqStatusArgs {
+        let r = &self.0;
+        IrqStatusArgs {
+            notification_bitmask: u64::from(r[1]) << 32 | u64::from(r[0]),
+        }
+    }
+}
+
+/// Decoded arguments for the `SEND` syscall.
+#[derive(Clone, Debug)]
+pub struct SendArgs {
+    pub callee: TaskId,
+    pub operation: u16,
+    pub message: Result<USlice<u8>, UsageError>,
+    pub response: Result<USlice<u8>, UsageError>,
+    pub lease_table: Result<USlice<ULease>, UsageError>,
+    pub urgent: bool,
+    /// Timeout in ticks, or 0 for none.
+    pub timeout: u32,
+}
+
+/// Decoded arguments for the `RECV` syscall.
+#[derive(Clone, Debug)]
+pub struct RecvArgs {
+    pub buffer: Result<USlice<u8>, UsageError>,
+    pub notification_mask: u64,
+    pub specific_sender: Option<TaskId>,
+}
+
+/// Decoded arguments for the `REPLY` syscall.
+#[derive(Clone, Debug)]
+pub struct ReplyArgs {
+    pub callee: TaskId,
+    pub response_code: u32,
+    pub message: Result<USlice<u8>, UsageError>,
+    pub nonce: u32,
+}
+
+/// Decoded arguments for the `REPLY_FAULT` syscall.
+#[derive(Clone, Debug)]
+pub struct ReplyFaultArgs {
+    pub callee: TaskId,
+    pub reason: Result<ReplyFaultReason, UsageError>,
+    pub nonce: u32,
+    pub detail: Option<ReplyFaultDetail>,
+}
+
+/// Decoded arguments for the `SET_TIMER` syscall.
+#[derive(Clone, Debug)]
+pub struct SetTimerArgs {
+    /// Deadline in kernel ticks, if the timer is being enabled.
+    pub deadline: Option<u64>,
+    pub notification: NotificationSet,
+}
+
+/// Decoded arguments for the `SET_TIMER_PERIODIC` syscall.
+#[derive(Clone, Debug)]
+pub struct SetTimerPeriodicArgs {
+    pub interval: u32,
+    pub notification: NotificationSet,
+}
+
+/// Decoded arguments for the `BORROW_*` syscalls.
+#[derive(Clone, Debug)]
+pub struct BorrowArgs {
+    pub lender: TaskId,
+    pub lease_number: usize,
+    pub offset: usize,
+    pub buffer: Result<USlice<u8>, UsageError>,
+}
+
+/// Decoded arguments for the `BORROW_VECTORED` syscall.
+#[derive(Clone, Debug)]
+pub struct BorrowVectoredArgs {
+    pub lender: TaskId,
+    pub lease_number: usize,
+    pub write: bool,
+    pub transfers: Result<USlice<UBorrow>, UsageError>,
+}
+
+/// Decoded arguments for the `REPLY_LEASE` syscall.
+#[derive(Clone, Debug)]
+pub struct ReplyLeaseArgs {
+    pub callee: TaskId,
+    pub response_code: u32,
+    pub lease_number: usize,
+    pub offset: usize,
+    pub message: Result<USlice<u8>, UsageError>,
+    pub nonce: u32,
+}
+
+/// Decoded arguments for the `IRQ_CONTROL` syscall.
+#[derive(Clone, Debug)]
+pub struct IrqArgs {
+    pub notification_bitmask: u64,
+    pub control: u32,
+}
+
+/// Decoded arguments for the `PANIC` syscall.
+#[derive(Clone, Debug)]
+pub struct PanicArgs {
+    pub message: Result<USlice<u8>, UsageError>,
+}
+
+/// Decoded arguments for the `REFRESH_TASK_ID` syscall.
+#[derive(Clone, Debug)]
+pub struct RefreshTaskIdArgs {
+    pub task_id: TaskId,
+}
+
+/// Decoded arguments for the `POST` syscall.
+#[derive(Clone, Debug)]
+pub struct PostArgs {
+    pub task_id: TaskId,
+    pub notification_bits: NotificationSet,
+}
+
+/// Decoded arguments for the `SEND_ASYNC` syscall.
+#[derive(Clone, Debug)]
+pub struct SendAsyncArgs {
+    pub callee: TaskId,
+    pub operation: u16,
+    pub message: Result<USlice<u8>, UsageError>,
+}
+
+/// Decoded arguments for the `IRQ_STATUS` syscall.
+#[derive(Clone, Debug)]
+pub struct IrqStatusArgs {
+    pub notification_bitmask: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UserSlice;
+    use proptest::prelude::*;
+
+    /// Checks the invariants `USlice::from_raw` promises for anything it
+    /// accepts, and that it accepts everything it should.
+    fn check_slice<T>(
+        base: usize,
+        len: usize,
+        s: &Result<USlice<T>, UsageError>,
+    ) {
+        let size = core::mem::size_of::<T>();
+        let fits = base % core::mem::align_of::<T>() == 0
+            && len
+                .checked_mul(size)
+                .and_then(|n| base.checked_add(n))
+                .is_some();
+        match s {
+            Ok(s) => {
+                assert!(fits, "accepted bad slice {s:?}");
+                assert_eq!(s.base_addr(), base);
+                assert_eq!(s.len(), len);
+                assert!(s.end_addr() >= s.base_addr());
+                assert_eq!(s.end_addr() - s.base_addr(), len * size);
+                assert_eq!(
+                    UserSlice::is_empty(s),
+                    s.base_addr() == s.end_addr()
+                );
+            }
+            Err(e) => {
+                assert!(!fits, "rejected good slice {base:#x}+{len:#x}");
+                assert_eq!(*e, UsageError::InvalidSlice);
+            }
+        }
+    }
+
+    /// Register values that are more likely than uniform ones to land near
+    /// the edges the decoders care about.
+    fn reg() -> impl Strategy<Value = u32> {
+        prop_oneof![
+            any::<u32>(),
+            0u32..16,
+            (u32::MAX - 16)..=u32::MAX,
+            (0u32..32).prop_map(|n| 1 << n),
+        ]
+    }
+
+    fn args() -> impl Strategy<Value = SyscallArgs> {
+        proptest::array::uniform7(reg()).prop_map(SyscallArgs)
+    }
+
+    proptest! {
+        /// Registers are 32 bits, but the host's `usize` may not be, so try
+        /// slices across the whole of it too.
+        #[test]
+        fn slices(base in any::<usize>(), len in any::<usize>()) {
+            check_slice(base, len, &USlice::<u8>::from_raw(base, len));
+            check_slice(base, len, &USlice::<ULease>::from_raw(base, len));
+            check_slice(
+                base,
+                usize::MAX - len,
+                &USlice::<UBorrow>::from_raw(base, usize::MAX - len),
+            );
+        }
+
+        #[test]
+        fn casts(base in any::<usize>(), len in any::<usize>()) {
+            if let Ok(bytes) = USlice::<u8>::from_raw(base, len) {
+                let size = core::mem::size_of::<ULease>();
+                let cast = bytes.cast::<ULease>();
+                if len % size == 0 {
+                    check_slice(base, len / size, &cast);
+                } else {
+                    prop_assert_eq!(cast.err(), Some(UsageError::InvalidSlice));
+                }
+
+                let n = len / 2;
+                let p = bytes.prefix(n);
+                prop_assert_eq!(p.base_addr(), base);
+                prop_assert_eq!(p.len(), n);
+                prop_assert_eq!(bytes.prefix(usize::MAX).len(), len);
+            }
+        }
+
+        #[test]
+        fn send(a in args()) {
+            let r = a.0;
+            let s = a.as_send_args();
+            prop_assert_eq!(s.callee.0, (r[0] >> 16) as u16);
+            prop_assert_eq!(s.operation, r[0] as u16);
+            check_slice(r[1] as usize, r[2] as usize, &s.message);
+            check_slice(r[3] as usize, r[4] as usize, &s.response);
+            check_slice(
+                r[5] as usize,
+                (r[6] & abi::SEND_LEASE_COUNT_MASK) as usize,
+                &s.lease_table,
+            );
+            prop_assert!(s.timeout <= abi::SEND_TIMEOUT_MAX);
+            prop_assert_eq!(s.urgent, r[6] >> 31 != 0);
+        }
+
+        #[test]
+        fn recv(a in args()) {
+            let r = a.0;
+            let s = a.as_recv_args();
+            check_slice(r[0] as usize, r[1] as usize, &s.buffer);
+            prop_assert_eq!(s.notification_mask as u32, r[2]);
+            prop_assert_eq!((s.notification_mask >> 32) as u32, r[4]);
+            prop_assert_eq!(s.specific_sender.is_some(), r[3] >> 31 != 0);
+        }
+
+        #[test]
+        fn reply(a in args()) {
+            let r = a.0.map(|r| r as usize);
+            check_slice(r[2], r[3], &a.as_reply_args().message);
+            check_slice(r[4], r[5], &a.as_reply_lease_args().message);
+            check_slice(r[1], r[2], &a.as_send_async_args().message);
+            check_slice(r[0], r[1], &a.as_panic_args().message);
+        }
+
+        #[test]
+        fn reply_fault(a in args()) {
+            let r = a.0;
+            let s = a.as_reply_fault_args();
+            match s.reason {
+                Ok(reason) => prop_assert_eq!(reason as u32, r[1]),
+                Err(e) => {
+                    prop_assert_eq!(e, UsageError::BadReplyFaultReason)
+                }
+            }
+            prop_assert_eq!(s.detail.is_some(), r[3] != 0);
+        }
+
+        #[test]
+        fn borrow(a in args()) {
+            let r = a.0.map(|r| r as usize);
+            check_slice(r[3], r[4], &a.as_borrow_args().buffer);
+            check_slice(r[3], r[4], &a.as_borrow_vectored_args().transfers);
+        }
+
+        #[test]
+        fn timers(a in args()) {
+            let r = a.0;
+            let s = a.as_set_timer_args();
+            prop_assert_eq!(s.deadline.is_some(), r[0] != 0);
+            prop_assert_eq!(s.notification.0 as u32, r[3]);
+            prop_assert_eq!(
+                a.as_set_timer_periodic_args().notification.0 >> 32,
+                u64::from(r[2])
+            );
+        }
+
+        /// Everything else is plain bit-shuffling, so it's enough that it
+        /// doesn't panic.
+        #[test]
+        fn others(a in args()) {
+            a.as_irq_args();
+            a.as_irq_status_args();
+            a.as_refresh_task_id_args();
+            a.as_post_args();
+        }
+    }
+}
@@ -9,6 +9,11 @@
 #![cfg_attr(not(test), no_std)]
 #![forbid(clippy::wildcard_imports)]
 
+pub mod args;
+mod uslice;
+
+pub use uslice::{USlice, UserCopyable};
+
 /// Describes types that act as "slices" (in the very abstract sense) referenced
 /// by tasks in syscalls.
 ///
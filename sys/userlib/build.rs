@@ -5,6 +5,17 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     build_util::expose_m_profile()?;
 
+    // The kernel tick rate, as set by the app's `tick-hz`. This isn't set when
+    // building outside of `xtask` (for clippy, say), so fall back to the
+    // default there.
+    let tick_hz = build_util::env_var("HUBRIS_TICK_HZ")
+        .unwrap_or_else(|_| "1000".to_string());
+    let tick_hz: u32 = tick_hz.parse()?;
+    std::fs::write(
+        build_util::out_dir().join("tick_hz.rs"),
+        format!("{tick_hz}"),
+    )?;
+
     // Do an architecture check.
     if build_util::target_os() != "none" {
         eprintln!("***********************************************");
@@ -0,0 +1,327 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A small cooperative executor for writing tasks as `async` code.
+//!
+//! A server that has to juggle interrupts, timeouts, and incoming messages is
+//! usually written as a state machine around `sys_recv`. This module lets it
+//! be written instead as a handful of `async fn`s -- one waiting on an IRQ,
+//! one on a timer, one serving clients -- which the `Executor` runs
+//! concurrently on the task's single thread.
+//!
+//! The executor owns the task's `RECV`: when every future is waiting, it makes
+//! one `RECV` with the union of the notification bits they're waiting for, and
+//! hands whatever arrives to the futures interested in it. Notification bits
+//! are mapped to the futures that asked for them, so only those are polled
+//! again.
+//!
+//! ```ignore
+//! let exec = Executor::new(TIMER_NOTIFICATION);
+//! exec.run(&mut [
+//!     pin!(async {
+//!         loop {
+//!             exec.notification(IRQ_NOTIFICATION).await;
+//!             handle_irq();
+//!         }
+//!     }),
+//!     pin!(async {
+//!         let mut buf = [0; 16];
+//!         loop {
+//!             let msg = exec.recv(&mut buf).await;
+//!             handle_message(msg, &buf);
+//!         }
+//!     }),
+//! ]);
+//! ```
+//!
+//! There's no allocator, so the set of futures is fixed when `run` is called,
+//! and limited to `MAX_FUTURES`. The executor isn't reentrant, and a task
+//! should have only one.
+
+use core::cell::Cell;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use crate::{
+    sys_get_timer, sys_recv_notification, sys_recv_open, sys_set_timer,
+    RecvMessage, TaskId,
+};
+
+/// Maximum number of futures a single `Executor::run` can drive.
+pub const MAX_FUTURES: usize = 8;
+
+/// Futures woken through their `Waker`, rather than by a notification or
+/// message. Since a task is single-threaded and has only one executor, this
+/// can be a plain static; it's atomic only to be `Sync`.
+static WOKEN: AtomicU32 = AtomicU32::new(0);
+
+/// Runs a fixed set of futures to completion, waking them on notifications,
+/// timer deadlines, and messages.
+pub struct Executor {
+    /// Notification bits reserved for the executor's timer.
+    timer_mask: u32,
+    /// Index of the future being polled, so that the futures created by this
+    /// executor can register interest on its behalf.
+    current: Cell<usize>,
+    /// Notification bits each future is waiting for.
+    masks: [Cell<u32>; MAX_FUTURES],
+    /// Deadline each future is waiting for, if any.
+    deadlines: [Cell<Option<u64>>; MAX_FUTURES],
+    /// Notification bits that have arrived but not yet been claimed.
+    fired: Cell<u32>,
+    /// Buffer registered by a pending `recv`, and the future it belongs to.
+    recv_buf: Cell<Option<(*mut u8, usize, usize)>>,
+    /// Message received on behalf of the pending `recv`.
+    received: Cell<Option<RecvMessage>>,
+}
+
+impl Executor {
+    /// Creates an executor that uses the notification bits in `timer_mask` for
+    /// `sleep_until` and `sleep_for`. The executor takes over the task's timer.
+    pub fn new(timer_mask: u32) -> Self {
+        Self {
+            timer_mask,
+            current: Cell::new(0),
+            masks: Default::default(),
+            deadlines: Default::default(),
+            fired: Cell::new(0),
+            recv_buf: Cell::new(None),
+            received: Cell::new(None),
+        }
+    }
+
+    /// Runs `futures` until all of them have completed.
+    ///
+    /// # Panics
+    ///
+    /// If given more than `MAX_FUTURES` futures.
+    pub fn run(&self, futures: &mut [Pin<&mut dyn Future<Output = ()>>]) {
+        assert!(futures.len() <= MAX_FUTURES);
+        let all = (1u32 << futures.len()) - 1;
+        let mut done = 0u32;
+        let mut ready = all;
+
+        loop {
+            while ready != 0 {
+                for (i, fut) in futures.iter_mut().enumerate() {
+                    let bit = 1 << i;
+                    if ready & bit == 0 || done & bit != 0 {
+                        continue;
+                    }
+                    ready &= !bit;
+                    // The future will re-register whatever it still wants.
+                    self.masks[i].set(0);
+                    self.deadlines[i].set(None);
+                    self.current.set(i);
+
+                    let waker = waker_for(i);
+                    let mut cx = Context::from_waker(&waker);
+                    if fut.as_mut().poll(&mut cx).is_ready() {
+                        done |= bit;
+                    }
+                }
+                ready |= WOKEN.load(Ordering::Relaxed) & !done;
+                WOKEN.store(0, Ordering::Relaxed);
+            }
+
+            if done == all {
+                return;
+            }
+
+            // Everyone's waiting. Work out what for.
+            let mut mask = 0;
+            let mut deadline: Option<u64> = None;
+            for i in 0..futures.len() {
+                if done & (1 << i) != 0 {
+                    continue;
+                }
+                mask |= self.masks[i].get();
+                if let Some(d) = self.deadlines[i].get() {
+                    deadline = Some(deadline.map_or(d, |e| e.min(d)));
+                }
+            }
+            if deadline.is_some() {
+                mask |= self.timer_mask;
+            }
+            sys_set_timer(deadline, self.timer_mask);
+
+            let bits = match self.recv_buf.get() {
+                Some((ptr, len, owner)) => {
+                    // Safety: the buffer is borrowed by the `Recv` future that
+                    // registered it, which deregisters it when dropped, so
+                    // it's still valid and not otherwise in use.
+                    let buf =
+                        unsafe { core::slice::from_raw_parts_mut(ptr, len) };
+                    let msg = sys_recv_open(buf, mask);
+                    if msg.sender == TaskId::KERNEL {
+                        msg.operation
+                    } else {
+                        self.recv_buf.set(None);
+                        self.received.set(Some(msg));
+                        ready |= 1 << owner;
+                        0
+                    }
+                }
+                None => sys_recv_notification(mask),
+            };
+
+            // Timer bits are consumed here, by waking the sleepers, rather than
+            // left for `notification` futures to claim.
+            self.fired.set(self.fired.get() | (bits & !self.timer_mask));
+            for i in 0..futures.len() {
+                let waiting_on_timer = self.deadlines[i].get().is_some();
+                if self.masks[i].get() & bits != 0
+                    || (waiting_on_timer && bits & self.timer_mask != 0)
+                {
+                    ready |= 1 << i;
+                }
+            }
+            ready &= !done;
+        }
+    }
+
+    /// Waits for any of the notification bits in `mask`, returning the ones
+    /// that arrived.
+    ///
+    /// If several futures wait for the same bit, whichever is polled first
+    /// claims it.
+    pub fn notification(&self, mask: u32) -> Notification<'_> {
+        Notification { exec: self, mask }
+    }
+
+    /// Waits until the kernel time is at least `deadline`.
+    pub fn sleep_until(&self, deadline: u64) -> Sleep<'_> {
+        Sleep {
+            exec: self,
+            deadline,
+        }
+    }
+
+    /// Waits for at least `ticks` kernel ticks. See `hl::sleep_for`.
+    pub fn sleep_for(&self, ticks: u64) -> Sleep<'_> {
+        let now = sys_get_timer().now;
+        self.sleep_until(now.saturating_add(ticks).saturating_add(1))
+    }
+
+    /// Waits for a message from any task, receiving it into `buffer`.
+    ///
+    /// The caller must reply to the message as usual. Only one `recv` may be
+    /// pending at a time.
+    pub fn recv<'b>(&self, buffer: &'b mut [u8]) -> Recv<'_, 'b> {
+        Recv {
+            exec: self,
+            buffer,
+            registered: false,
+        }
+    }
+}
+
+/// Future returned by `Executor::notification`.
+pub struct Notification<'e> {
+    exec: &'e Executor,
+    mask: u32,
+}
+
+impl Future for Notification<'_> {
+    type Output = u32;
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<u32> {
+        let exec = self.exec;
+        let fired = exec.fired.get();
+        if fired & self.mask != 0 {
+            exec.fired.set(fired & !self.mask);
+            return Poll::Ready(fired & self.mask);
+        }
+        let slot = &exec.masks[exec.current.get()];
+        slot.set(slot.get() | self.mask);
+        Poll::Pending
+    }
+}
+
+/// Future returned by `Executor::sleep_until` and `Executor::sleep_for`.
+pub struct Sleep<'e> {
+    exec: &'e Executor,
+    deadline: u64,
+}
+
+impl Future for Sleep<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+        if sys_get_timer().now >= self.deadline {
+            return Poll::Ready(());
+        }
+        let slot = &self.exec.deadlines[self.exec.current.get()];
+        let deadline =
+            slot.get().map_or(self.deadline, |d| d.min(self.deadline));
+        slot.set(Some(deadline));
+        Poll::Pending
+    }
+}
+
+/// Future returned by `Executor::recv`.
+pub struct Recv<'e, 'b> {
+    exec: &'e Executor,
+    buffer: &'b mut [u8],
+    registered: bool,
+}
+
+impl Future for Recv<'_, '_> {
+    type Output = RecvMessage;
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Self::Output> {
+        let exec = self.exec;
+        if self.registered {
+            if let Some(msg) = exec.received.take() {
+                self.registered = false;
+                return Poll::Ready(msg);
+            }
+        } else {
+            assert!(exec.recv_buf.get().is_none(), "concurrent recv");
+            self.registered = true;
+        }
+        // Re-register each time, since the owning future's index is only
+        // known while it's being polled.
+        let ptr = self.buffer.as_mut_ptr();
+        let len = self.buffer.len();
+        exec.recv_buf.set(Some((ptr, len, exec.current.get())));
+        Poll::Pending
+    }
+}
+
+impl Drop for Recv<'_, '_> {
+    fn drop(&mut self) {
+        if self.registered {
+            // Don't leave the executor holding a dangling buffer, or a message
+            // that nobody will reply to. Dropping a `Recv` with a message
+            // waiting in it is a bug in the caller.
+            self.exec.recv_buf.set(None);
+            assert!(self.exec.received.take().is_none());
+        }
+    }
+}
+
+/// Makes a `Waker` that marks future `index` as ready to be polled.
+fn waker_for(index: usize) -> Waker {
+    fn clone(data: *const ()) -> RawWaker {
+        RawWaker::new(data, &VTABLE)
+    }
+    fn wake(data: *const ()) {
+        let bit = 1 << (data as usize);
+        WOKEN.store(WOKEN.load(Ordering::Relaxed) | bit, Ordering::Relaxed);
+    }
+    fn drop(_data: *const ()) {}
+    static VTABLE: RawWakerVTable =
+        RawWakerVTable::new(clone, wake, wake, drop);
+
+    // Safety: the vtable functions don't dereference the data pointer (it's
+    // just an index), so they're trivially thread-safe and the contract of
+    // RawWaker is upheld.
+    unsafe { Waker::from_raw(RawWaker::new(index as *const (), &VTABLE)) }
+}
@@ -7,14 +7,15 @@
 //! This is intended to provide a more ergonomic interface than the raw
 //! syscalls.
 
-use abi::{Generation, TaskId};
+use abi::{Generation, LeaseAttributes, ReplyFaultReason, TaskId};
 use core::marker::PhantomData;
 use zerocopy::{AsBytes, FromBytes, LayoutVerified};
 
 use crate::{
     sys_borrow_info, sys_borrow_read, sys_borrow_write, sys_get_timer,
-    sys_recv, sys_recv_closed, sys_recv_open, sys_reply, sys_reply_fault,
-    sys_set_timer, BorrowInfo, ClosedRecvError, FromPrimitive,
+    sys_recv, sys_recv_closed, sys_recv_open, sys_reply, sys_reply_checked,
+    sys_reply_fault, sys_send, sys_set_timer, sys_set_timer_wide, BorrowInfo,
+    ClosedRecvError, FromPrimitive, Lease, TICK_HZ,
 };
 
 const INTERNAL_TIMER_NOTIFICATION: u32 = 1 << 31;
@@ -100,6 +101,7 @@ pub fn recv<'a, O, E, S>(
                 sender: rm.sender,
                 response_capacity: rm.response_capacity,
                 lease_count: rm.lease_count,
+                reply_nonce: rm.reply_nonce(),
             };
             if let Err(e) = msg(state, op, m) {
                 sys_reply(sender, e.into(), &[]);
@@ -159,6 +161,7 @@ where
                 sender: rm.sender,
                 response_capacity: rm.response_capacity,
                 lease_count: rm.lease_count,
+                reply_nonce: rm.reply_nonce(),
             };
             if let Err(e) = msg(state, op, m) {
                 sys_reply(sender, e.into(), &[]);
@@ -187,6 +190,134 @@ where
     recv_from(source, buffer, 0, (), |_, _| (), |_, op, m| msg(op, m))
 }
 
+/// Describes the messages a server accepts for one operation, for `dispatch`.
+pub struct OpSpec<O> {
+    /// The operation this describes.
+    pub op: O,
+    /// Exact length of the message, in bytes.
+    pub message_len: usize,
+    /// Minimum room the caller must leave for the reply, in bytes.
+    pub response_len: usize,
+    /// The leases the caller must send, in order. The caller must send
+    /// exactly this many.
+    pub leases: &'static [LeaseSpec],
+}
+
+/// Describes one lease expected by an `OpSpec`.
+pub struct LeaseSpec {
+    /// Attributes the lease must have. It may have others as well.
+    pub attributes: LeaseAttributes,
+    /// Minimum length of the lease, in bytes.
+    pub min_len: usize,
+}
+
+impl LeaseSpec {
+    /// A lease the server will read from, of any length.
+    pub const READ: Self = Self {
+        attributes: LeaseAttributes::READ,
+        min_len: 0,
+    };
+    /// A lease the server will write to, of any length.
+    pub const WRITE: Self = Self {
+        attributes: LeaseAttributes::WRITE,
+        min_len: 0,
+    };
+}
+
+/// Variant of `recv` that checks each message against a table describing the
+/// server's operations, `map`, before handing it to `msg`.
+///
+/// Most servers start every handler by checking the message length, reply
+/// buffer size and leases, and it's easy to get one of those checks wrong or
+/// leave it out. `dispatch` does them all from `map`, so that by the time
+/// `msg` runs, the message is the length given by the operation's `OpSpec`,
+/// the caller has room for the reply, and every lease exists, has at least
+/// the required attributes, and is long enough. The handler still has to
+/// check that the message *contents* make sense, and must be prepared for
+/// borrows to fail if the caller is killed in the meantime.
+///
+/// A message that fails these checks gets a reply fault, so that the client
+/// learns what it did wrong the same way whichever server it talked to:
+///
+/// - `UndefinedOperation` if the operation code doesn't decode as an `O`, or
+///   `map` has no entry for it,
+/// - `BadMessageSize` if the message is the wrong length,
+/// - `ReplyBufferTooSmall` if the reply buffer is too small, and
+/// - `BadLeases` if there are the wrong number of leases, or any is missing
+///   required attributes or too short.
+///
+/// Notifications, and errors returned from `msg`, are handled as by `recv`.
+pub fn dispatch<'a, O, E, S>(
+    buffer: &'a mut [u8],
+    mask: u32,
+    map: &[OpSpec<O>],
+    state: S,
+    notify: impl FnOnce(S, u32),
+    msg: impl FnOnce(S, O, Message<'a>) -> Result<(), E>,
+) where
+    O: FromPrimitive + PartialEq,
+    E: Into<u32>,
+{
+    let rm = sys_recv_open(buffer, mask);
+    let sender = rm.sender;
+    if sender == TaskId::KERNEL {
+        notify(state, rm.operation);
+        return;
+    }
+
+    let Some((op, spec)) = O::from_u32(rm.operation)
+        .and_then(|op| map.iter().find(|s| s.op == op).map(|s| (op, s)))
+    else {
+        sys_reply_fault(sender, ReplyFaultReason::UndefinedOperation);
+        return;
+    };
+    if let Err(reason) = check_message(&rm, buffer.len(), spec) {
+        sys_reply_fault(sender, reason);
+        return;
+    }
+
+    let m = Message {
+        // `check_message` has made sure that this fits in the buffer.
+        buffer: &buffer[..rm.message_len],
+        sender,
+        response_capacity: rm.response_capacity,
+        lease_count: rm.lease_count,
+        reply_nonce: rm.reply_nonce(),
+    };
+    if let Err(e) = msg(state, op, m) {
+        sys_reply(sender, e.into(), &[]);
+    }
+}
+
+/// Checks a received message against `spec`, for `dispatch`.
+fn check_message<O>(
+    rm: &crate::RecvMessage,
+    buffer_len: usize,
+    spec: &OpSpec<O>,
+) -> Result<(), ReplyFaultReason> {
+    // A message too big for our buffer got truncated, which we treat as
+    // being the wrong size even if the spec (wrongly) allows for it.
+    if rm.message_len != spec.message_len || rm.message_len > buffer_len {
+        return Err(ReplyFaultReason::BadMessageSize);
+    }
+    if rm.response_capacity < spec.response_len {
+        return Err(ReplyFaultReason::ReplyBufferTooSmall);
+    }
+    if rm.lease_count != spec.leases.len() {
+        return Err(ReplyFaultReason::BadLeases);
+    }
+    for (i, lease) in spec.leases.iter().enumerate() {
+        let info =
+            sys_borrow_info(rm.sender, i).ok_or(ReplyFaultReason::BadLeases)?;
+        if !info.attributes.contains(lease.attributes)
+            || info.len < lease.min_len
+        {
+            return Err(ReplyFaultReason::BadLeases);
+        }
+    }
+    Ok(())
+}
+
 /// Represents a received message (not a notification).
 ///
 /// This type gets passed by `recv` (and related operations) into the message
@@ -200,6 +331,7 @@ pub struct Message<'a> {
     response_capacity: usize,
     lease_count: usize,
     sender: TaskId,
+    reply_nonce: u32,
 }
 
 impl<'a> Message<'a> {
@@ -226,7 +358,11 @@ impl<'a> Message<'a> {
         M: FromBytes,
         R: AsBytes,
     {
-        let caller = Caller::from(self.sender);
+        let caller = Caller {
+            id: self.sender,
+            nonce: self.reply_nonce,
+            _phantom: PhantomData,
+        };
         if self.buffer.len() != core::mem::size_of::<M>()
             || self.response_capacity < core::mem::size_of::<R>()
         {
@@ -265,16 +401,20 @@ impl<'a> Message<'a> {
 /// A typed handle to a task, used to send a single reply of type `R`.
 pub struct Caller<R> {
     id: TaskId,
+    /// Reply nonce of the message being replied to, or zero if unknown.
+    nonce: u32,
     _phantom: PhantomData<fn(R)>,
 }
 
 /// This impl is available if you want to synthesize a `Caller` for some unusual
 /// reason, but in general, you should get your `Caller` from operations like
-/// `Message::fixed`.
+/// `Message::fixed`. A synthesized `Caller` doesn't know the message's reply
+/// nonce, so the kernel can't check that its reply goes to the right message.
 impl<R> From<TaskId> for Caller<R> {
     fn from(id: TaskId) -> Self {
         Caller {
             id,
+            nonce: 0,
             _phantom: PhantomData,
         }
     }
@@ -286,7 +426,7 @@ impl<R> Caller<R> {
     where
         R: AsBytes,
     {
-        sys_reply(self.id, 0, message.as_bytes())
+        sys_reply_checked(self.id, self.nonce, 0, message.as_bytes())
     }
 
     /// Sends a failure message with response code `rc`, consuming the handle.
@@ -294,7 +434,7 @@ impl<R> Caller<R> {
     /// Because a response code of 0 conventionally means "success," `rc` should
     /// not convert to 0, or things will get weird for you.
     pub fn reply_fail(self, rc: impl Into<u32>) {
-        sys_reply(self.id, rc.into(), &[]);
+        sys_reply_checked(self.id, self.nonce, rc.into(), &[]);
     }
 
     /// Derives a borrow handle to borrow number `index`.
@@ -418,10 +558,20 @@ impl Borrow<'_> {
     }
 }
 
+/// Converts a duration in milliseconds into kernel ticks, rounding up so that
+/// waiting that many ticks takes at least as long.
+pub const fn ms_to_ticks(ms: u64) -> u64 {
+    ms.saturating_mul(TICK_HZ as u64).div_ceil(1000)
+}
+
+/// Converts a number of kernel ticks into milliseconds, rounding down.
+pub const fn ticks_to_ms(ticks: u64) -> u64 {
+    ticks.saturating_mul(1000) / TICK_HZ as u64
+}
+
 /// Suspends the calling task until the kernel time is `>= time`.
 ///
-/// TODO: once we figure out how to convert between ticks and seconds here, this
-/// should take a real unit instead of a tick count.
+/// To sleep until a `time::Instant`, see `time::sleep_until`.
 pub fn sleep_until(time: u64) {
     let prev = sys_get_timer();
     sys_set_timer(Some(time), INTERNAL_TIMER_NOTIFICATION);
@@ -444,7 +594,7 @@ pub fn sleep_until(time: u64) {
     }
     // Restore previous timer deadline and notifications
     if let Some(deadline) = prev.deadline {
-        sys_set_timer(Some(deadline), prev.on_dl);
+        sys_set_timer_wide(Some(deadline), prev.on_dl);
     }
 }
 
@@ -454,8 +604,7 @@ pub fn sleep_until(time: u64) {
 /// the u64 kernel timer overflows. You won't notice this in practice because
 /// it's a very, very long time from now.
 ///
-/// TODO: once we figure out how to convert between ticks and seconds here, this
-/// needs to take `Duration`.
+/// To sleep for a `Duration`, see `time::sleep`.
 pub fn sleep_for(ticks: u64) {
     // By definition, when we observe the kernel time as being some value T, we
     // are some amount of time into the tick that began at T (the time required
@@ -471,3 +620,108 @@ pub fn sleep_for(ticks: u64) {
     let deadline = sys_get_timer().now.saturating_add(ticks).saturating_add(1);
     sleep_until(deadline)
 }
+
+/// A lease whose direction and length are part of its type.
+///
+/// `READ` and `WRITE` give the access the borrower gets, and `LEN` is the exact
+/// length of the lent buffer, so a client can spell out the leases an
+/// operation expects -- say, `(ReadLease<'_, 64>, WriteLease<'_, 4>)` -- and
+/// have the compiler reject a call that lends the wrong buffer, in the wrong
+/// direction, or in the wrong position. Use the `ReadLease`, `WriteLease`, and
+/// `ReadWriteLease` aliases rather than naming this directly, and pass a tuple
+/// of them to `send_with_leases`.
+pub struct TypedLease<'a, const READ: bool, const WRITE: bool, const LEN: usize>(
+    Lease<'a>,
+);
+
+/// A lease the borrower can only read, of exactly `LEN` bytes.
+pub type ReadLease<'a, const LEN: usize> = TypedLease<'a, true, false, LEN>;
+/// A lease the borrower can only write, of exactly `LEN` bytes.
+pub type WriteLease<'a, const LEN: usize> = TypedLease<'a, false, true, LEN>;
+/// A lease the borrower can read and write, of exactly `LEN` bytes.
+pub type ReadWriteLease<'a, const LEN: usize> = TypedLease<'a, true, true, LEN>;
+
+impl<'a, const LEN: usize> TypedLease<'a, true, false, LEN> {
+    pub fn new(buf: &'a [u8; LEN]) -> Self {
+        const { assert!(LEN <= u32::MAX as usize, "lease too long") };
+        Self(Lease::read_only(buf))
+    }
+}
+
+impl<'a, const LEN: usize> TypedLease<'a, false, true, LEN> {
+    pub fn new(buf: &'a mut [u8; LEN]) -> Self {
+        const { assert!(LEN <= u32::MAX as usize, "lease too long") };
+        Self(Lease::write_only(buf))
+    }
+}
+
+impl<'a, const LEN: usize> TypedLease<'a, true, true, LEN> {
+    pub fn new(buf: &'a mut [u8; LEN]) -> Self {
+        const { assert!(LEN <= u32::MAX as usize, "lease too long") };
+        Self(Lease::read_write(buf))
+    }
+}
+
+impl<'a, const READ: bool, const WRITE: bool, const LEN: usize>
+    From<TypedLease<'a, READ, WRITE, LEN>> for Lease<'a>
+{
+    fn from(l: TypedLease<'a, READ, WRITE, LEN>) -> Self {
+        l.0
+    }
+}
+
+/// A fixed set of `TypedLease`s, in the order they're passed to the server.
+///
+/// This is implemented for tuples of up to four `TypedLease`s.
+pub trait LeaseList<'a> {
+    /// The array of raw leases handed to the kernel.
+    type Array: AsRef<[Lease<'a>]>;
+
+    fn into_leases(self) -> Self::Array;
+}
+
+macro_rules! impl_lease_list {
+    ($n:literal; $($name:ident: $r:ident, $w:ident, $len:ident),+) => {
+        impl<'a, $(const $r: bool, const $w: bool, const $len: usize),+>
+            LeaseList<'a> for ($(TypedLease<'a, $r, $w, $len>,)+)
+        {
+            type Array = [Lease<'a>; $n];
+
+            fn into_leases(self) -> Self::Array {
+                let ($($name,)+) = self;
+                [$($name.into()),+]
+            }
+        }
+    };
+}
+
+impl_lease_list!(1; a: RA, WA, LA);
+impl_lease_list!(2; a: RA, WA, LA, b: RB, WB, LB);
+impl_lease_list!(3; a: RA, WA, LA, b: RB, WB, LB, c: RC, WC, LC);
+impl_lease_list!(4; a: RA, WA, LA, b: RB, WB, LB, c: RC, WC, LC, d: RD, WD, LD);
+
+/// Sends a message with a fixed set of typed leases, and waits for the reply.
+///
+/// This is `sys_send` with the lease array built from `leases`, a tuple of
+/// `ReadLease`, `WriteLease`, and `ReadWriteLease`. Annotating the tuple's type
+/// (or taking it as a parameter in a client wrapper) pins down the direction
+/// and length of each lease at compile time:
+///
+/// ```ignore
+/// let mut status = [0u8; 4];
+/// let leases: (ReadLease<'_, 64>, WriteLease<'_, 4>) =
+///     (ReadLease::new(&block), WriteLease::new(&mut status));
+/// let (rc, len) = hl::send_with_leases(server, OP_WRITE, &[], &mut [], leases);
+/// ```
+///
+/// Returns the response code and length, as `sys_send` does.
+pub fn send_with_leases<'a, L: LeaseList<'a>>(
+    target: TaskId,
+    operation: u16,
+    outgoing: &[u8],
+    incoming: &mut [u8],
+    leases: L,
+) -> (u32, usize) {
+    let leases = leases.into_leases();
+    sys_send(target, operation, outgoing, incoming, leases.as_ref())
+}
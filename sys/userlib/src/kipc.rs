@@ -93,7 +93,14 @@ pub fn read_task_dump_region(
     len
 }
 
-pub fn restart_task(task: usize, start: bool) {
+/// Reinitializes the task with index `task`, and starts it if `start` is
+/// `true`.
+///
+/// Returns `false`, having done nothing, if the kernel's restart throttle is
+/// holding the task down; the supervisor is notified again when the hold
+/// expires.
+#[must_use]
+pub fn restart_task(task: usize, start: bool) -> bool {
     // Coerce `task` to a known size (Rust doesn't assume that usize == u32)
     let msg = (task as u32, start);
     let mut buf = [0; core::mem::size_of::<(u32, bool)>()];
@@ -105,7 +112,11 @@ pub fn restart_task(task: usize, start: bool) {
         &mut [],
         &[],
     );
-    assert_eq!(rc, 0);
+    match rc {
+        0 => true,
+        abi::RESTART_HELD => false,
+        _ => panic!(),
+    }
 }
 
 pub fn fault_task(task: usize) {
@@ -121,6 +132,42 @@ pub fn fault_task(task: usize) {
     assert_eq!(rc, 0);
 }
 
+/// Stops `task` from being scheduled until it's passed to `resume_task`.
+///
+/// The task's registers and IPC state are left alone: anything it was blocked
+/// on can still complete while it's suspended, and it picks up from there when
+/// resumed. Restarting the task also resumes it. While it's suspended, its
+/// status reads as `Stopped`.
+///
+/// Only the supervisor can make this call.
+pub fn suspend_task(task: usize) {
+    // Coerce `task` to a known size (Rust doesn't assume that usize == u32)
+    let task = task as u32;
+    let (rc, _len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::SuspendTask as u16,
+        task.as_bytes(),
+        &mut [],
+        &[],
+    );
+    assert_eq!(rc, 0);
+}
+
+/// Allows a task stopped by `suspend_task` to be scheduled again. Only the
+/// supervisor can make this call.
+pub fn resume_task(task: usize) {
+    // Coerce `task` to a known size (Rust doesn't assume that usize == u32)
+    let task = task as u32;
+    let (rc, _len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::ResumeTask as u16,
+        task.as_bytes(),
+        &mut [],
+        &[],
+    );
+    assert_eq!(rc, 0);
+}
+
 pub fn system_restart() -> ! {
     let _ = sys_send(TaskId::KERNEL, Kipcnum::Reset as u16, &[], &mut [], &[]);
     panic!();
@@ -143,8 +190,8 @@ pub fn read_image_id() -> u64 {
 /// Trigger the interrupt(s) mapped to the given task's notification mask.
 pub fn software_irq(task: usize, mask: u32) {
     // Coerce `task` to a known size (Rust doesn't assume that usize == u32)
-    let msg = (task as u32, mask);
-    let mut buf = [0; core::mem::size_of::<(u32, u32)>()];
+    let msg = (task as u32, u64::from(mask));
+    let mut buf = [0; core::mem::size_of::<(u32, u64)>()];
     ssmarshal::serialize(&mut buf, &msg).unwrap_lite();
 
     let (rc, _len) = sys_send(
@@ -156,3 +203,427 @@ pub fn software_irq(task: usize, mask: u32) {
     );
     assert_eq!(rc, 0);
 }
+
+/// Reads CPU time accounting for the task at index `task`, along with the time
+/// spent in the kernel and the total time elapsed since boot.
+///
+/// Sampling this periodically and comparing the deltas lets a supervisor work
+/// out per-task utilization, or notice a task that is hogging the CPU.
+pub fn read_cpu_time(task: usize) -> abi::CpuTime {
+    // Coerce `task` to a known size (Rust doesn't assume that usize == u32)
+    let task = task as u32;
+    let mut response = [0; core::mem::size_of::<abi::CpuTime>()];
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::ReadCpuTime as u16,
+        task.as_bytes(),
+        &mut response,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    ssmarshal::deserialize(&response[..len]).unwrap_lite().0
+}
+
+/// Posts `mask` to the notification word of every task whose index is set in
+/// `task_set`, in a single kernel entry.
+///
+/// Bit `n` of `task_set` selects the task with index `n`, so this can only
+/// address the first 64 tasks. Naming a task index that doesn't exist is
+/// treated as a bug, and faults the caller before anything is posted.
+pub fn broadcast_notification(task_set: u64, mask: u32) {
+    let msg = (task_set, u64::from(mask));
+    let mut buf = [0; core::mem::size_of::<(u64, u64)>()];
+    ssmarshal::serialize(&mut buf, &msg).unwrap_lite();
+
+    let (rc, _len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::BroadcastNotification as u16,
+        &buf,
+        &mut [],
+        &[],
+    );
+    assert_eq!(rc, 0);
+}
+
+/// Returns the peak stack usage, in bytes, of the task at index `task` since it
+/// was last started, or `None` if the kernel can't find its stack.
+///
+/// The kernel fills unused stack with a watermark pattern when a task starts,
+/// and this scans for the deepest point where that pattern was overwritten.
+pub fn read_task_stack_usage(task: usize) -> Option<u32> {
+    // Coerce `task` to a known size (Rust doesn't assume that usize == u32)
+    let task = task as u32;
+    let mut response = [0; core::mem::size_of::<Option<u32>>()];
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::ReadTaskStackUsage as u16,
+        task.as_bytes(),
+        &mut response,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    ssmarshal::deserialize(&response[..len]).unwrap_lite().0
+}
+
+/// Reads the kernel's high-resolution monotonic clock.
+///
+/// This is much finer-grained than the millisecond kernel tick returned by
+/// `sys_get_timer`, which makes it useful for timestamping events. Use
+/// `MonotonicTime::as_micros` to convert it to microseconds.
+pub fn read_monotonic_time() -> abi::MonotonicTime {
+    let mut response = [0; core::mem::size_of::<abi::MonotonicTime>()];
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::ReadMonotonicTime as u16,
+        &[],
+        &mut response,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    ssmarshal::deserialize(&response[..len]).unwrap_lite().0
+}
+
+/// Scans forward from index `task` for a client that has been waiting longer
+/// than the reply dead-man limit for a server's reply.
+///
+/// `task` may equal the number of tasks in the system, in which case this
+/// returns `None`. Only the supervisor may call this.
+pub fn find_stuck_reply(task: usize) -> Option<abi::StuckReply> {
+    // Coerce `task` to a known size (Rust doesn't assume that usize == u32)
+    let task = task as u32;
+    let mut response = [0; core::mem::size_of::<Option<abi::StuckReply>>()];
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::FindStuckReply as u16,
+        task.as_bytes(),
+        &mut response,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    ssmarshal::deserialize(&response[..len]).unwrap_lite().0
+}
+
+/// Reads a hash of `task`'s saved registers. For a faulted task, these are the
+/// registers at the time of the fault, so the hash makes a compact fingerprint
+/// for telling one crash from another.
+pub fn read_register_hash(task: usize) -> u32 {
+    // Coerce `task` to a known size (Rust doesn't assume that usize == u32)
+    let task = task as u32;
+    let mut response = [0; core::mem::size_of::<u32>()];
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::ReadRegisterHash as u16,
+        task.as_bytes(),
+        &mut response,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    ssmarshal::deserialize(&response[..len]).unwrap_lite().0
+}
+
+/// Tells the kernel how fast its timer counts, in counts per millisecond, after
+/// the clock driving it has been changed. On ARM-M, that's the CPU clock, in
+/// kHz. The kernel divides this down to its configured tick rate.
+///
+/// Only the supervisor, and a task marked `clock-owner = true` in the
+/// `app.toml`, can make this call.
+pub fn set_tick_divisor(cycles_per_ms: u32) {
+    let (rc, _len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::SetTickDivisor as u16,
+        cycles_per_ms.as_bytes(),
+        &mut [],
+        &[],
+    );
+    assert_eq!(rc, 0);
+}
+
+fn cache_maintenance(op: abi::CacheOp, range: *const [u8]) {
+    let msg = (op, range as *const u8 as u32, range.len() as u32);
+    let mut buf = [0; core::mem::size_of::<(abi::CacheOp, u32, u32)>()];
+    ssmarshal::serialize(&mut buf, &msg).unwrap_lite();
+
+    let (rc, _len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::CacheMaintenance as u16,
+        &buf,
+        &mut [],
+        &[],
+    );
+    assert_eq!(rc, 0);
+}
+
+/// Writes any dirty data cache lines covering `range` back to memory. Call
+/// this after filling a buffer and before starting a DMA transfer that reads
+/// from it.
+///
+/// This does nothing on parts without a data cache, or with it turned off.
+pub fn dcache_clean(range: *const [u8]) {
+    cache_maintenance(abi::CacheOp::Clean, range);
+}
+
+/// Discards any data cache lines covering `range`, so that reads see what's in
+/// memory. Call this after a DMA transfer into the buffer has finished, and
+/// before reading it (and, if anything else might touch it in the meantime,
+/// before starting the transfer too).
+///
+/// `range` must start and end on a cache line boundary
+/// (`abi::DCACHE_LINE_SIZE`), or the kernel will fault the caller: discarding
+/// a line that's shared with something else would lose that thing's writes.
+/// Use [`dcache_clean_invalidate`] for buffers that aren't aligned.
+pub fn dcache_invalidate(range: *mut [u8]) {
+    cache_maintenance(abi::CacheOp::Invalidate, range);
+}
+
+/// Writes back, then discards, any data cache lines covering `range`. This is
+/// [`dcache_invalidate`] for buffers that may share cache lines with other
+/// data, at the cost of writing back anything the CPU stored to the buffer.
+pub fn dcache_clean_invalidate(range: *mut [u8]) {
+    cache_maintenance(abi::CacheOp::CleanInvalidate, range);
+}
+
+/// Reads a compact summary of the state of the task with index `task`.
+///
+/// Only the supervisor, and tasks marked `monitor = true` in the `app.toml`,
+/// may call this; the kernel faults anyone else.
+pub fn read_task_snapshot(task: usize) -> abi::TaskSnapshot {
+    // Coerce `task` to a known size (Rust doesn't assume that usize == u32)
+    let task = task as u32;
+    let mut response = [0; core::mem::size_of::<abi::TaskSnapshot>()];
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::ReadTaskSnapshot as u16,
+        task.as_bytes(),
+        &mut response,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    ssmarshal::deserialize(&response[..len]).unwrap_lite().0
+}
+
+/// Moves hardware interrupt `irq` to the task with index `task`, which will
+/// receive it on the notification bits in `notification`, and control it with
+/// `sys_irq_control` on the same bits. Only the supervisor may call this.
+///
+/// The interrupt is left disabled, so that the new owner can set up the
+/// peripheral before enabling it. The move lasts until the next one, even if
+/// either task restarts; move it back to its `app.toml` owner to undo it.
+///
+/// Returns `false`, moving nothing, if the kernel's table of moved interrupts
+/// is full.
+pub fn transfer_irq(irq: u32, task: usize, notification: u64) -> bool {
+    // Coerce `task` to a known size (Rust doesn't assume that usize == u32)
+    let msg = (irq, task as u32, notification);
+    let mut buf = [0; core::mem::size_of::<(u32, u32, u64)>()];
+    ssmarshal::serialize(&mut buf, &msg).unwrap_lite();
+
+    let mut response = [0; core::mem::size_of::<bool>()];
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::TransferIrq as u16,
+        &buf,
+        &mut response,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    ssmarshal::deserialize(&response[..len]).unwrap_lite().0
+}
+
+/// Finds out which of the interrupts delivered to the caller on the
+/// notification bits in `notification` have fired since this was last called
+/// for them, and forgets that they did.
+///
+/// The IRQ numbers are written to `out`, and the number written is returned.
+/// If `out` is too short to hold them all, the rest are kept for next time.
+///
+/// This is for tasks that share one notification bit between several
+/// interrupts, and would otherwise have to check every peripheral's status
+/// registers when it's posted. `notification` must match the bits given in the
+/// `app.toml` exactly, as with `sys_irq_control`, or the kernel will fault the
+/// caller.
+pub fn take_fired_irqs(notification: u64, out: &mut [u32]) -> usize {
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::TakeFiredIrqs as u16,
+        notification.as_bytes(),
+        out.as_bytes_mut(),
+        &[],
+    );
+    assert_eq!(rc, 0);
+    len / core::mem::size_of::<u32>()
+}
+
+/// Asks the kernel to run the board's low-power idle routine, if it has one
+/// and nothing else is runnable. Returns `true` if the routine ran, in which
+/// case the system has already slept; otherwise, the caller should wait for an
+/// interrupt itself.
+///
+/// This is meant for the idle task.
+pub fn idle() -> bool {
+    let mut response = [0; core::mem::size_of::<bool>()];
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::Idle as u16,
+        &[],
+        &mut response,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    ssmarshal::deserialize(&response[..len]).unwrap_lite().0
+}
+
+/// Reads the number of syscalls of each kind that `task` has made since boot.
+pub fn read_syscall_counts(task: usize) -> abi::SyscallCounts {
+    // Coerce `task` to a known size (Rust doesn't assume that usize == u32)
+    let task = task as u32;
+    let mut response = [0; core::mem::size_of::<abi::SyscallCounts>()];
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::ReadSyscallCounts as u16,
+        task.as_bytes(),
+        &mut response,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    ssmarshal::deserialize(&response[..len]).unwrap_lite().0
+}
+
+/// Reads the panic record left by `task`, if it's faulted because it panicked
+/// and its panic handler recorded where and why.
+///
+/// Returns `None` if the task isn't in that state, which includes tasks built
+/// with `panic-messages`, whose panic message is text instead.
+pub fn read_panic_record(task: usize) -> Option<abi::PanicRecord> {
+    // Coerce `task` to a known size (Rust doesn't assume that usize == u32)
+    let task = task as u32;
+    let mut response = abi::PanicRecord::EMPTY;
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::ReadPanicRecord as u16,
+        task.as_bytes(),
+        response.as_bytes_mut(),
+        &[],
+    );
+    assert_eq!(rc, 0);
+    (len == core::mem::size_of::<abi::PanicRecord>()
+        && response.magic == abi::PANIC_RECORD_MAGIC)
+        .then_some(response)
+}
+
+/// Reads the measurements the bootloader handed to the kernel, if it left a
+/// valid record. Only the supervisor, and tasks marked `attester = true` in
+/// the `app.toml`, may call this; others are faulted.
+pub fn read_boot_measurements() -> Option<abi::BootMeasurements> {
+    let mut response = abi::BootMeasurements::EMPTY;
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::ReadBootMeasurements as u16,
+        &[],
+        response.as_bytes_mut(),
+        &[],
+    );
+    assert_eq!(rc, 0);
+    (len == core::mem::size_of::<abi::BootMeasurements>()
+        && response.is_valid())
+    .then_some(response)
+}
+
+/// Reads what the kernel found when it checked the image against the CRC in
+/// its header at startup.
+pub fn read_image_check() -> abi::ImageCheck {
+    let mut response = [0; core::mem::size_of::<abi::ImageCheck>()];
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::ReadImageCheck as u16,
+        &[],
+        &mut response,
+        &[],
+    );
+    assert_eq!(rc, 0);
+    ssmarshal::deserialize(&response[..len]).unwrap_lite().0
+}
+
+/// Asks the kernel to post `mask` to the caller whenever the task at index
+/// `task` is restarted, or, if `mask` is 0, to stop.
+///
+/// All of a task's watches share one notification mask, so this replaces the
+/// mask for any other tasks being watched. Watches are cleared when the caller
+/// restarts, so they should be set up at startup. Only the first 64 tasks can
+/// be watched.
+pub fn watch_task(task: usize, mask: u32) {
+    let msg = (task as u32, u64::from(mask));
+    let mut buf = [0; core::mem::size_of::<(u32, u64)>()];
+    ssmarshal::serialize(&mut buf, &msg).unwrap_lite();
+
+    let (rc, _len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::WatchTask as u16,
+        &buf,
+        &mut [],
+        &[],
+    );
+    assert_eq!(rc, 0);
+}
+
+/// Copies memory of the task at index `task`, starting at `address`, into
+/// `buf`, and returns how much was copied. At most `abi::TASK_MEMORY_WINDOW`
+/// bytes are copied, however long `buf` is.
+///
+/// Returns `None` if the task can't itself read the whole window as normal
+/// memory. Only the supervisor can make this call, and not on itself.
+pub fn read_task_memory(
+    task: usize,
+    address: u32,
+    buf: &mut [u8],
+) -> Option<usize> {
+    let msg = (task as u32, address);
+    let mut msg_buf = [0; core::mem::size_of::<(u32, u32)>()];
+    ssmarshal::serialize(&mut msg_buf, &msg).unwrap_lite();
+
+    let (rc, len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::ReadTaskMemory as u16,
+        &msg_buf,
+        buf,
+        &[],
+    );
+    match rc {
+        0 => Some(len),
+        abi::TASK_MEMORY_INACCESSIBLE => None,
+        _ => panic!(),
+    }
+}
+
+/// Copies `data` into the memory of the task at index `task`, starting at
+/// `address`, and returns how much was copied. At most
+/// `abi::TASK_MEMORY_WINDOW` bytes are copied, however long `data` is.
+///
+/// Returns `None`, having written nothing, if the task can't itself write the
+/// whole window as normal memory. Only the supervisor can make this call, and
+/// not on itself.
+pub fn write_task_memory(
+    task: usize,
+    address: u32,
+    data: &[u8],
+) -> Option<usize> {
+    const HEADER: usize = core::mem::size_of::<(u32, u32)>();
+    let data = &data[..data.len().min(abi::TASK_MEMORY_WINDOW)];
+
+    let msg = (task as u32, address);
+    let mut buf = [0; HEADER + abi::TASK_MEMORY_WINDOW];
+    ssmarshal::serialize(&mut buf, &msg).unwrap_lite();
+    buf[HEADER..HEADER + data.len()].copy_from_slice(data);
+
+    let (rc, _len) = sys_send(
+        TaskId::KERNEL,
+        Kipcnum::WriteTaskMemory as u16,
+        &buf[..HEADER + data.len()],
+        &mut [],
+        &[],
+    );
+    match rc {
+        0 => Some(data.len()),
+        abi::TASK_MEMORY_INACCESSIBLE => None,
+        _ => panic!(),
+    }
+}
@@ -39,14 +39,23 @@ pub use unwrap_lite::UnwrapLite;
 use core::arch;
 use core::marker::PhantomData;
 
+pub mod exec;
 pub mod hl;
 pub mod kipc;
 pub mod task_slot;
+pub mod time;
 pub mod units;
 
 #[cfg(feature = "critical-section")]
 pub mod critical_section;
 
+/// The kernel tick rate, in ticks per second, as set by `tick-hz` in the
+/// application's `[kernel]` config. Kernel timestamps and timer deadlines count
+/// these ticks; by default there are 1000 of them a second, so a tick is a
+/// millisecond. See `time::Instant` for working in real units, or
+/// `hl::ms_to_ticks` for converting.
+pub const TICK_HZ: u32 = include!(concat!(env!("OUT_DIR"), "/tick_hz.rs"));
+
 #[derive(Debug)]
 #[repr(transparent)]
 pub struct Lease<'a> {
@@ -87,6 +96,85 @@ impl<'a> Lease<'a> {
             _marker: PhantomData,
         }
     }
+
+    /// Lends a read-only scatter-gather list. The borrower sees the contents
+    /// of `segments` concatenated in order.
+    ///
+    /// The kernel accepts at most `abi::MAX_LEASE_SEGMENTS` segments; lending
+    /// more will fault the lender when the borrower tries to use the lease.
+    pub fn gather(segments: &'a [LeaseSegment<'a>]) -> Self {
+        Self::segmented(
+            LeaseAttributes::READ | LeaseAttributes::SEGMENTED,
+            segments,
+        )
+    }
+
+    /// Lends a read-write scatter-gather list. The borrower sees the contents
+    /// of `segments` concatenated in order.
+    ///
+    /// See `Lease::gather` for the segment limit.
+    pub fn scatter(segments: &'a [MutLeaseSegment<'a>]) -> Self {
+        // Safety: MutLeaseSegment is a transparent wrapper around
+        // LeaseSegment, which only adds the requirement that the memory be
+        // writable.
+        let segments = unsafe {
+            core::slice::from_raw_parts(
+                segments.as_ptr() as *const LeaseSegment<'a>,
+                segments.len(),
+            )
+        };
+        Self::segmented(
+            LeaseAttributes::READ
+                | LeaseAttributes::WRITE
+                | LeaseAttributes::SEGMENTED,
+            segments,
+        )
+    }
+
+    fn segmented(
+        attributes: LeaseAttributes,
+        segments: &'a [LeaseSegment<'a>],
+    ) -> Self {
+        Self {
+            _kern_rep: abi::ULease {
+                attributes,
+                base_address: segments.as_ptr() as u32,
+                length: core::mem::size_of_val(segments) as u32,
+            },
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// One piece of a read-only scatter-gather lease; see `Lease::gather`.
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct LeaseSegment<'a> {
+    _kern_rep: abi::ULeaseSegment,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl<'a> From<&'a [u8]> for LeaseSegment<'a> {
+    fn from(x: &'a [u8]) -> Self {
+        Self {
+            _kern_rep: abi::ULeaseSegment {
+                base_address: x.as_ptr() as u32,
+                length: x.len() as u32,
+            },
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// One piece of a read-write scatter-gather lease; see `Lease::scatter`.
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct MutLeaseSegment<'a>(LeaseSegment<'a>, PhantomData<&'a mut ()>);
+
+impl<'a> From<&'a mut [u8]> for MutLeaseSegment<'a> {
+    fn from(x: &'a mut [u8]) -> Self {
+        Self(LeaseSegment::from(&*x), PhantomData)
+    }
 }
 
 impl<'a> From<&'a [u8]> for Lease<'a> {
@@ -138,6 +226,84 @@ pub fn sys_send(
     unsafe { sys_send_stub(&mut args).into() }
 }
 
+/// Like `sys_send`, but marks the message urgent: if several tasks of the same
+/// priority are waiting to send to `target`, urgent senders are received
+/// first. This is meant for things like fault reports that share a server with
+/// bulk traffic; it doesn't let a sender jump ahead of more important ones.
+#[inline(always)]
+pub fn sys_send_urgent(
+    target: TaskId,
+    operation: u16,
+    outgoing: &[u8],
+    incoming: &mut [u8],
+    leases: &[Lease<'_>],
+) -> (u32, usize) {
+    sys_send_with_flags(
+        target,
+        operation,
+        outgoing,
+        incoming,
+        leases,
+        abi::SEND_URGENT_FLAG,
+    )
+}
+
+/// Like `sys_send`, but gives up if `target` hasn't replied within `timeout`
+/// ticks, returning `IPC_TIMEOUT` with a length of 0.
+///
+/// The timeout covers the whole call, including time spent waiting for the
+/// target to receive the message. If it expires after the target has received
+/// the message, the target isn't told, and may still act on it. The kernel
+/// drops its late reply, and doesn't give it another message from this task
+/// until it's sent it.
+///
+/// # Panics
+///
+/// If `timeout` is 0 or greater than `SEND_TIMEOUT_MAX`.
+#[inline(always)]
+pub fn sys_send_timeout(
+    target: TaskId,
+    operation: u16,
+    outgoing: &[u8],
+    incoming: &mut [u8],
+    leases: &[Lease<'_>],
+    timeout: u32,
+) -> (u32, usize) {
+    assert!(timeout != 0 && timeout <= abi::SEND_TIMEOUT_MAX);
+    sys_send_with_flags(
+        target,
+        operation,
+        outgoing,
+        incoming,
+        leases,
+        timeout << abi::SEND_TIMEOUT_SHIFT,
+    )
+}
+
+/// Common implementation of the `SEND` variants that pass flags in the upper
+/// bits of the lease count.
+#[inline(always)]
+fn sys_send_with_flags(
+    target: TaskId,
+    operation: u16,
+    outgoing: &[u8],
+    incoming: &mut [u8],
+    leases: &[Lease<'_>],
+    flags: u32,
+) -> (u32, usize) {
+    let mut args = SendArgs {
+        packed_target_operation: u32::from(target.0) << 16
+            | u32::from(operation),
+        outgoing_ptr: outgoing.as_ptr(),
+        outgoing_len: outgoing.len(),
+        incoming_ptr: incoming.as_mut_ptr(),
+        incoming_len: incoming.len(),
+        lease_ptr: leases.as_ptr(),
+        lease_len: leases.len() | flags as usize,
+    };
+    unsafe { sys_send_stub(&mut args).into() }
+}
+
 #[allow(dead_code)] // this gets used from asm
 #[repr(C)] // field order matters
 struct SendArgs<'a> {
@@ -297,6 +463,18 @@ pub fn sys_recv(
     buffer: &mut [u8],
     notification_mask: u32,
     specific_sender: Option<TaskId>,
+) -> Result<RecvMessage, u32> {
+    sys_recv_wide(buffer, u64::from(notification_mask), specific_sender)
+}
+
+/// Version of `sys_recv` that can unmask any of the 64 notification bits. If
+/// the task is woken by notifications, all of them are available from
+/// `RecvMessage::notifications`.
+#[inline(always)]
+pub fn sys_recv_wide(
+    buffer: &mut [u8],
+    notification_mask: u64,
+    specific_sender: Option<TaskId>,
 ) -> Result<RecvMessage, u32> {
     use core::mem::MaybeUninit;
 
@@ -310,8 +488,9 @@ pub fn sys_recv(
         sys_recv_stub(
             buffer.as_mut_ptr(),
             buffer.len(),
-            notification_mask,
+            notification_mask as u32,
             specific_sender_bits,
+            (notification_mask >> 32) as u32,
             out.as_mut_ptr(),
         )
     };
@@ -327,6 +506,8 @@ pub fn sys_recv(
             message_len: out.message_len,
             response_capacity: out.response_capacity,
             lease_count: out.lease_count,
+            notifications_hi: out.notifications_hi,
+            reply_nonce: out.reply_nonce,
         })
     } else {
         Err(rc)
@@ -338,12 +519,14 @@ pub fn sys_recv(
 /// never panicking and not returning a `Result` that must be checked.
 #[inline(always)]
 pub fn sys_recv_notification(notification_mask: u32) -> u32 {
-    match sys_recv(&mut [], notification_mask, Some(TaskId::KERNEL)) {
-        Ok(rm) => {
-            // The notification bits come back from the kernel in the operation
-            // code field.
-            rm.operation
-        }
+    sys_recv_notification_wide(u64::from(notification_mask)) as u32
+}
+
+/// Version of `sys_recv_notification` for any of the 64 notification bits.
+#[inline(always)]
+pub fn sys_recv_notification_wide(notification_mask: u64) -> u64 {
+    match sys_recv_wide(&mut [], notification_mask, Some(TaskId::KERNEL)) {
+        Ok(rm) => rm.notifications(),
         Err(_) => {
             // Safety: Because we passed Some(TaskId::KERNEL), this is defined
             // as not being able to happen.
@@ -358,6 +541,30 @@ pub struct RecvMessage {
     pub message_len: usize,
     pub response_capacity: usize,
     pub lease_count: usize,
+    /// Upper half of the notification bits, if this is a notification from
+    /// the kernel; `operation` holds the lower half.
+    notifications_hi: u32,
+    /// Nonce identifying this message, for `reply_nonce`.
+    reply_nonce: u32,
+}
+
+impl RecvMessage {
+    /// If this is a notification from the kernel, returns the full set of
+    /// notification bits that woke the task.
+    pub fn notifications(&self) -> u64 {
+        u64::from(self.notifications_hi) << 32 | u64::from(self.operation)
+    }
+
+    /// Returns the nonce the kernel assigned this message, for replying with
+    /// `sys_reply_checked` and friends. The kernel then makes sure that the
+    /// reply goes to this message, and not to a later message from the same
+    /// sender, faulting the server if not.
+    ///
+    /// This is zero for notifications and asynchronous messages, which aren't
+    /// replied to.
+    pub fn reply_nonce(&self) -> u32 {
+        self.reply_nonce
+    }
 }
 
 /// Core implementation of the RECV syscall.
@@ -368,8 +575,9 @@ pub struct RecvMessage {
 unsafe extern "C" fn sys_recv_stub(
     _buffer_ptr: *mut u8,
     _buffer_len: usize,
-    _notification_mask: u32,
+    _notification_mask_lo: u32,
     _specific_sender: u32,
+    _notification_mask_hi: u32,
     _out: *mut RawRecvMessage,
 ) -> u32 {
     cfg_if::cfg_if! {
@@ -386,15 +594,19 @@ unsafe extern "C" fn sys_recv_stub(
                 eors r4, r4
                 adds r4, #{sysnum}
                 mov r11, r4
+                @ Read the upper notification mask word from the stack.
+                @ Since we just pushed a bunch of stuff, we need to read
+                @ *past* it.
+                ldr r4, [sp, #(9 * 4)]
+                mov r8, r4
                 @ Move register arguments into their proper positions.
                 mov r4, r0
                 mov r5, r1
                 mov r6, r2
                 mov r7, r3
                 @ Read output buffer pointer from stack into a register that
-                @ is preserved during our syscall. Since we just pushed a
-                @ bunch of stuff, we need to read *past* it.
-                ldr r3, [sp, #(9 * 4)]
+                @ is preserved during our syscall.
+                ldr r3, [sp, #(10 * 4)]
 
                 @ To the kernel!
                 svc #0
@@ -406,7 +618,10 @@ unsafe extern "C" fn sys_recv_stub(
                 stm r3!, {{r5-r7}}
                 mov r5, r8
                 mov r6, r9
-                stm r3!, {{r5-r6}}
+                mov r7, r10
+                stm r3!, {{r5-r7}}
+                mov r5, r11
+                str r5, [r3]
 
                 @ Restore the registers we used.
                 pop {{r4-r7}}
@@ -428,10 +643,12 @@ unsafe extern "C" fn sys_recv_stub(
                 mov r5, r1
                 mov r6, r2
                 mov r7, r3
-                @ Read output buffer pointer from stack into a register that
-                @ is preserved during our syscall. Since we just pushed a
-                @ bunch of stuff, we need to read *past* it.
-                ldr r3, [sp, #(8 * 4)]
+                @ Read the upper notification mask word and output buffer
+                @ pointer from the stack, the latter into a register that is
+                @ preserved during our syscall. Since we just pushed a bunch
+                @ of stuff, we need to read *past* it.
+                ldr r8, [sp, #(8 * 4)]
+                ldr r3, [sp, #(9 * 4)]
                 @ Load the constant syscall number.
                 mov r11, {sysnum}
 
@@ -442,7 +659,7 @@ unsafe extern "C" fn sys_recv_stub(
                 @ position
                 mov r0, r4
                 @ Write all the results out into the raw output buffer.
-                stm r3, {{r5-r9}}
+                stm r3, {{r5-r11}}
                 @ Restore the registers we used.
                 pop {{r4-r11}}
                 @ Fin.
@@ -468,12 +685,31 @@ struct RawRecvMessage {
     pub message_len: usize,
     pub response_capacity: usize,
     pub lease_count: usize,
+    pub notifications_hi: u32,
+    pub reply_nonce: u32,
 }
 
 #[inline(always)]
 pub fn sys_reply(peer: TaskId, code: u32, message: &[u8]) {
+    sys_reply_checked(peer, 0, code, message)
+}
+
+/// Version of `sys_reply` that cites the reply nonce of the message being
+/// replied to, from `RecvMessage::reply_nonce`. If `peer` is waiting for us to
+/// reply to a different message, the kernel faults us rather than delivering
+/// the reply.
+///
+/// A `nonce` of zero isn't checked, making this equivalent to `sys_reply`.
+#[inline(always)]
+pub fn sys_reply_checked(peer: TaskId, nonce: u32, code: u32, message: &[u8]) {
     unsafe {
-        sys_reply_stub(peer.0 as u32, code, message.as_ptr(), message.len())
+        sys_reply_stub(
+            peer.0 as u32,
+            code,
+            message.as_ptr(),
+            message.len(),
+            nonce,
+        )
     }
 }
 
@@ -486,6 +722,7 @@ unsafe extern "C" fn sys_reply_stub(
     _code: u32,
     _message_ptr: *const u8,
     _message_len: usize,
+    _nonce: u32,
 ) {
     cfg_if::cfg_if! {
         if #[cfg(armv6m)] {
@@ -494,13 +731,18 @@ unsafe extern "C" fn sys_reply_stub(
                 @ that we're being clever and pushing only the registers we
                 @ need; this means the pop sequence at the end needs to match!
                 push {{r4-r7, lr}}
-                mov r4, r11
-                push {{r4}}
+                mov r4, r8
+                mov r5, r11
+                push {{r4, r5}}
 
                 @ Load the constant syscall number.
                 eors r4, r4
                 adds r4, #{sysnum}
                 mov r11, r4
+                @ Read the fifth argument from the stack, past what we just
+                @ pushed.
+                ldr r4, [sp, #(7 * 4)]
+                mov r8, r4
                 @ Move register arguments into place.
                 mov r4, r0
                 mov r5, r1
@@ -513,8 +755,9 @@ unsafe extern "C" fn sys_reply_stub(
                 @ This call has no results.
 
                 @ Restore the registers we used and return.
-                pop {{r4}}
-                mov r11, r4
+                pop {{r4, r5}}
+                mov r8, r4
+                mov r11, r5
                 pop {{r4-r7, pc}}
                 ",
                 sysnum = const Sysnum::Reply as u32,
@@ -525,13 +768,16 @@ unsafe extern "C" fn sys_reply_stub(
                 @ Spill the registers we're about to use to pass stuff. Note
                 @ that we're being clever and pushing only the registers we
                 @ need; this means the pop sequence at the end needs to match!
-                push {{r4-r7, r11, lr}}
+                push {{r4-r8, r11, lr}}
 
                 @ Move register arguments into place.
                 mov r4, r0
                 mov r5, r1
                 mov r6, r2
                 mov r7, r3
+                @ Read the fifth argument from the stack, past what we just
+                @ pushed.
+                ldr r8, [sp, #(7 * 4)]
                 @ Load the constant syscall number.
                 mov r11, {sysnum}
 
@@ -541,7 +787,7 @@ unsafe extern "C" fn sys_reply_stub(
                 @ This call has no results.
 
                 @ Restore the registers we used and return.
-                pop {{r4-r7, r11, pc}}
+                pop {{r4-r8, r11, pc}}
                 ",
                 sysnum = const Sysnum::Reply as u32,
                 options(noreturn),
@@ -565,13 +811,20 @@ unsafe extern "C" fn sys_reply_stub(
 /// enabled.
 #[inline(always)]
 pub fn sys_set_timer(deadline: Option<u64>, notifications: u32) {
+    sys_set_timer_wide(deadline, u64::from(notifications))
+}
+
+/// Version of `sys_set_timer` that can post any of the 64 notification bits.
+#[inline(always)]
+pub fn sys_set_timer_wide(deadline: Option<u64>, notifications: u64) {
     let raw_deadline = deadline.unwrap_or(0);
     unsafe {
         sys_set_timer_stub(
             deadline.is_some() as u32,
             raw_deadline as u32,
             (raw_deadline >> 32) as u32,
-            notifications,
+            notifications as u32,
+            (notifications >> 32) as u32,
         )
     }
 }
@@ -601,20 +854,26 @@ unsafe extern "C" fn sys_set_timer_stub(
     _set_timer: u32,
     _deadline_lo: u32,
     _deadline_hi: u32,
-    _notification: u32,
+    _notification_lo: u32,
+    _notification_hi: u32,
 ) {
     cfg_if::cfg_if! {
         if #[cfg(armv6m)] {
             arch::asm!("
                 @ Spill the registers we're about to use to pass stuff.
                 push {{r4-r7, lr}}
-                mov r4, r11
-                push {{r4}}
+                mov r4, r8
+                mov r5, r11
+                push {{r4, r5}}
 
                 @ Load the constant syscall number.
                 eors r4, r4
                 adds r4, #{sysnum}
                 mov r11, r4
+                @ Read the fifth argument from the stack, past what we just
+                @ pushed.
+                ldr r4, [sp, #(7 * 4)]
+                mov r8, r4
                 @ Move register arguments into place.
                 mov r4, r0
                 mov r5, r1
@@ -627,8 +886,9 @@ unsafe extern "C" fn sys_set_timer_stub(
                 @ This call has no results.
 
                 @ Restore the registers we used and return.
-                pop {{r4}}
-                mov r11, r4
+                pop {{r4, r5}}
+                mov r8, r4
+                mov r11, r5
                 pop {{r4-r7, pc}}
                 ",
                 sysnum = const Sysnum::SetTimer as u32,
@@ -637,13 +897,16 @@ unsafe extern "C" fn sys_set_timer_stub(
         } else if #[cfg(any(armv7m, armv8m))] {
             arch::asm!("
                 @ Spill the registers we're about to use to pass stuff.
-                push {{r4-r7, r11, lr}}
+                push {{r4-r8, r11, lr}}
 
                 @ Move register arguments into place.
                 mov r4, r0
                 mov r5, r1
                 mov r6, r2
                 mov r7, r3
+                @ Read the fifth argument from the stack, past what we just
+                @ pushed.
+                ldr r8, [sp, #(7 * 4)]
                 @ Load the constant syscall number.
                 mov r11, {sysnum}
 
@@ -653,7 +916,7 @@ unsafe extern "C" fn sys_set_timer_stub(
                 @ This call has no results.
 
                 @ Restore the registers we used and return.
-                pop {{r4-r7, r11, pc}}
+                pop {{r4-r8, r11, pc}}
                 ",
                 sysnum = const Sysnum::SetTimer as u32,
                 options(noreturn),
@@ -664,6 +927,102 @@ unsafe extern "C" fn sys_set_timer_stub(
     }
 }
 
+/// Sets this task's timer to fire every `interval` ticks, starting `interval`
+/// ticks from now, posting `notifications` each time.
+///
+/// Unlike re-arming the timer with `sys_set_timer` on each wakeup, the kernel
+/// computes each deadline from the previous one, so the period doesn't drift
+/// however late the task is to handle the notification. If the task falls
+/// more than a whole period behind, the missed deadlines are skipped.
+///
+/// An `interval` of zero disables the timer. Any later call to `sys_set_timer`
+/// replaces the periodic timer.
+#[inline(always)]
+pub fn sys_set_timer_periodic(interval: u32, notifications: u32) {
+    sys_set_timer_periodic_wide(interval, u64::from(notifications))
+}
+
+/// Version of `sys_set_timer_periodic` that can post any of the 64
+/// notification bits.
+#[inline(always)]
+pub fn sys_set_timer_periodic_wide(interval: u32, notifications: u64) {
+    unsafe {
+        sys_set_timer_periodic_stub(
+            interval,
+            notifications as u32,
+            (notifications >> 32) as u32,
+        )
+    }
+}
+
+/// Core implementation of the SET_TIMER_PERIODIC syscall.
+///
+/// See the note on syscall stubs at the top of this module for rationale.
+#[naked]
+unsafe extern "C" fn sys_set_timer_periodic_stub(
+    _interval: u32,
+    _notifications_lo: u32,
+    _notifications_hi: u32,
+) {
+    cfg_if::cfg_if! {
+        if #[cfg(armv6m)] {
+            arch::asm!("
+                @ Spill the registers we're about to use to pass stuff.
+                push {{r4-r6, lr}}
+                mov r4, r11
+                push {{r4}}
+
+                @ Load the constant syscall number.
+                eors r4, r4
+                adds r4, #{sysnum}
+                mov r11, r4
+                @ Move register arguments into place.
+                mov r4, r0
+                mov r5, r1
+                mov r6, r2
+
+                @ To the kernel!
+                svc #0
+
+                @ This call has no results.
+
+                @ Restore the registers we used and return.
+                pop {{r4}}
+                mov r11, r4
+                pop {{r4-r6, pc}}
+                ",
+                sysnum = const Sysnum::SetTimerPeriodic as u32,
+                options(noreturn),
+            )
+        } else if #[cfg(any(armv7m, armv8m))] {
+            arch::asm!("
+                @ Spill the registers we're about to use to pass stuff.
+                push {{r4-r6, r11, lr}}
+
+                @ Move register arguments into place.
+                mov r4, r0
+                mov r5, r1
+                mov r6, r2
+                @ Load the constant syscall number.
+                mov r11, {sysnum}
+
+                @ To the kernel!
+                svc #0
+
+                @ This call has no results.
+
+                @ Restore the registers we used and return.
+                pop {{r4-r6, r11, pc}}
+                ",
+                sysnum = const Sysnum::SetTimerPeriodic as u32,
+                options(noreturn),
+            )
+        } else {
+            compile_error!("missing sys_set_timer_periodic_stub for ARM profile")
+        }
+    }
+}
+
 #[inline(always)]
 pub fn sys_borrow_read(
     lender: TaskId,
@@ -857,60 +1216,220 @@ struct BorrowWriteArgs {
     src_len: usize,
 }
 
+/// Reads from lease `index` of `lender` into several local buffers in one
+/// kernel entry. Each entry in `chunks` gives an offset into the lease and the
+/// buffer to fill from there.
+///
+/// This behaves like calling `sys_borrow_read` on each chunk in turn, except
+/// that it returns the total number of bytes read. If the lender turns out to
+/// be defective partway through, the chunks before the failing one have
+/// already been read.
+///
+/// # Panics
+///
+/// If `chunks` has more than `MAX_BORROW_VECTORS` entries.
 #[inline(always)]
-pub fn sys_borrow_info(lender: TaskId, index: usize) -> Option<BorrowInfo> {
-    use core::mem::MaybeUninit;
-
-    let mut raw = MaybeUninit::<RawBorrowInfo>::uninit();
-    unsafe {
-        sys_borrow_info_stub(lender.0 as u32, index, raw.as_mut_ptr());
-    }
-    // Safety: stub completely initializes record
-    let raw = unsafe { raw.assume_init() };
-
-    if raw.rc == 0 {
-        Some(BorrowInfo {
-            attributes: abi::LeaseAttributes::from_bits_truncate(raw.atts),
-            len: raw.length,
-        })
-    } else {
-        None
+pub fn sys_borrow_read_vectored(
+    lender: TaskId,
+    index: usize,
+    chunks: &mut [(usize, &mut [u8])],
+) -> (u32, usize) {
+    let mut table = [UBorrow {
+        offset: 0,
+        base_address: 0,
+        length: 0,
+    }; MAX_BORROW_VECTORS];
+    for (t, (offset, buf)) in table[..chunks.len()].iter_mut().zip(chunks) {
+        *t = UBorrow {
+            offset: *offset as u32,
+            base_address: buf.as_mut_ptr() as u32,
+            length: buf.len() as u32,
+        };
     }
+    let mut args = BorrowVectoredArgs {
+        lender: u32::from(lender.0),
+        index,
+        write: 0,
+        table: table.as_ptr(),
+        table_len: chunks.len(),
+    };
+    unsafe { sys_borrow_vectored_stub(&mut args).into() }
 }
 
-#[repr(C)]
-struct RawBorrowInfo {
-    rc: u32,
-    atts: u32,
-    length: usize,
-}
-
-/// Information record returned by `sys_borrow_info`.
-pub struct BorrowInfo {
-    /// Attributes of the lease.
-    pub attributes: abi::LeaseAttributes,
-    /// Length of borrowed memory, in bytes.
-    pub len: usize,
+/// Writes several local buffers into lease `index` of `lender` in one kernel
+/// entry. Each entry in `chunks` gives an offset into the lease and the data
+/// to write there.
+///
+/// This behaves like calling `sys_borrow_write` on each chunk in turn, except
+/// that it returns the total number of bytes written. If the lender turns out
+/// to be defective partway through, the chunks before the failing one have
+/// already been written.
+///
+/// # Panics
+///
+/// If `chunks` has more than `MAX_BORROW_VECTORS` entries.
+#[inline(always)]
+pub fn sys_borrow_write_vectored(
+    lender: TaskId,
+    index: usize,
+    chunks: &[(usize, &[u8])],
+) -> (u32, usize) {
+    let mut table = [UBorrow {
+        offset: 0,
+        base_address: 0,
+        length: 0,
+    }; MAX_BORROW_VECTORS];
+    for (t, (offset, buf)) in table[..chunks.len()].iter_mut().zip(chunks) {
+        *t = UBorrow {
+            offset: *offset as u32,
+            base_address: buf.as_ptr() as u32,
+            length: buf.len() as u32,
+        };
+    }
+    let mut args = BorrowVectoredArgs {
+        lender: u32::from(lender.0),
+        index,
+        write: 1,
+        table: table.as_ptr(),
+        table_len: chunks.len(),
+    };
+    unsafe { sys_borrow_vectored_stub(&mut args).into() }
 }
 
-/// Core implementation of the BORROW_INFO syscall.
+/// Core implementation of the BORROW_VECTORED syscall.
 ///
 /// See the note on syscall stubs at the top of this module for rationale.
 #[naked]
-unsafe extern "C" fn sys_borrow_info_stub(
-    _lender: u32,
-    _index: usize,
-    _out: *mut RawBorrowInfo,
-) {
+unsafe extern "C" fn sys_borrow_vectored_stub(
+    _args: *mut BorrowVectoredArgs,
+) -> RcLen {
     cfg_if::cfg_if! {
         if #[cfg(armv6m)] {
             arch::asm!("
                 @ Spill the registers we're about to use to pass stuff.
-                push {{r4-r6, lr}}
-                mov r4, r11
-                push {{r4}}
-
-                @ Load the constant syscall number.
+                push {{r4-r7, lr}}
+                mov r4, r8
+                mov r5, r11
+                push {{r4, r5}}
+
+                @ Load the constant syscall number.
+                eors r4, r4
+                adds r4, #{sysnum}
+                mov r11, r4
+                @ Move register arguments into place.
+                ldm r0!, {{r4-r7}}
+                ldm r0, {{r0}}
+                mov r8, r0
+
+                @ To the kernel!
+                svc #0
+
+                @ Move the results into place.
+                mov r0, r4
+                mov r1, r5
+
+                @ Restore the registers we used and return.
+                pop {{r4, r5}}
+                mov r11, r5
+                mov r8, r4
+                pop {{r4-r7, pc}}
+                ",
+                sysnum = const Sysnum::BorrowVectored as u32,
+                options(noreturn),
+            )
+        } else if #[cfg(any(armv7m, armv8m))] {
+            arch::asm!("
+                @ Spill the registers we're about to use to pass stuff.
+                push {{r4-r8, r11}}
+
+                @ Move register arguments into place.
+                ldm r0, {{r4-r8}}
+                @ Load the constant syscall number.
+                mov r11, {sysnum}
+
+                @ To the kernel!
+                svc #0
+
+                @ Move the results into place.
+                mov r0, r4
+                mov r1, r5
+
+                @ Restore the registers we used and return.
+                pop {{r4-r8, r11}}
+                bx lr
+                ",
+                sysnum = const Sysnum::BorrowVectored as u32,
+                options(noreturn),
+            )
+        } else {
+            compile_error!("missing sys_borrow_vectored_stub for ARM profile")
+        }
+    }
+}
+
+#[repr(C)]
+struct BorrowVectoredArgs {
+    lender: u32,
+    index: usize,
+    write: u32,
+    table: *const UBorrow,
+    table_len: usize,
+}
+
+#[inline(always)]
+pub fn sys_borrow_info(lender: TaskId, index: usize) -> Option<BorrowInfo> {
+    use core::mem::MaybeUninit;
+
+    let mut raw = MaybeUninit::<RawBorrowInfo>::uninit();
+    unsafe {
+        sys_borrow_info_stub(lender.0 as u32, index, raw.as_mut_ptr());
+    }
+    // Safety: stub completely initializes record
+    let raw = unsafe { raw.assume_init() };
+
+    if raw.rc == 0 {
+        Some(BorrowInfo {
+            attributes: abi::LeaseAttributes::from_bits_truncate(raw.atts),
+            len: raw.length,
+        })
+    } else {
+        None
+    }
+}
+
+#[repr(C)]
+struct RawBorrowInfo {
+    rc: u32,
+    atts: u32,
+    length: usize,
+}
+
+/// Information record returned by `sys_borrow_info`.
+pub struct BorrowInfo {
+    /// Attributes of the lease.
+    pub attributes: abi::LeaseAttributes,
+    /// Length of borrowed memory, in bytes.
+    pub len: usize,
+}
+
+/// Core implementation of the BORROW_INFO syscall.
+///
+/// See the note on syscall stubs at the top of this module for rationale.
+#[naked]
+unsafe extern "C" fn sys_borrow_info_stub(
+    _lender: u32,
+    _index: usize,
+    _out: *mut RawBorrowInfo,
+) {
+    cfg_if::cfg_if! {
+        if #[cfg(armv6m)] {
+            arch::asm!("
+                @ Spill the registers we're about to use to pass stuff.
+                push {{r4-r6, lr}}
+                mov r4, r11
+                push {{r4}}
+
+                @ Load the constant syscall number.
                 eors r4, r4
                 adds r4, #{sysnum}
                 mov r11, r4
@@ -964,8 +1483,15 @@ unsafe extern "C" fn sys_borrow_info_stub(
 
 #[inline(always)]
 pub fn sys_irq_control(mask: u32, enable: bool) {
+    sys_irq_control_wide(u64::from(mask), enable)
+}
+
+/// Version of `sys_irq_control` for interrupts on any of the 64 notification
+/// bits.
+#[inline(always)]
+pub fn sys_irq_control_wide(mask: u64, enable: bool) {
     unsafe {
-        sys_irq_control_stub(mask, enable as u32);
+        sys_irq_control_stub(mask as u32, enable as u32, (mask >> 32) as u32);
     }
 }
 
@@ -973,12 +1499,16 @@ pub fn sys_irq_control(mask: u32, enable: bool) {
 ///
 /// See the note on syscall stubs at the top of this module for rationale.
 #[naked]
-unsafe extern "C" fn sys_irq_control_stub(_mask: u32, _enable: u32) {
+unsafe extern "C" fn sys_irq_control_stub(
+    _mask_lo: u32,
+    _enable: u32,
+    _mask_hi: u32,
+) {
     cfg_if::cfg_if! {
         if #[cfg(armv6m)] {
             arch::asm!("
                 @ Spill the registers we're about to use to pass stuff.
-                push {{r4, r5, lr}}
+                push {{r4-r6, lr}}
                 mov r4, r11
                 push {{r4}}
 
@@ -989,6 +1519,7 @@ unsafe extern "C" fn sys_irq_control_stub(_mask: u32, _enable: u32) {
                 @ Move register arguments into place.
                 mov r4, r0
                 mov r5, r1
+                mov r6, r2
 
                 @ To the kernel!
                 svc #0
@@ -998,7 +1529,7 @@ unsafe extern "C" fn sys_irq_control_stub(_mask: u32, _enable: u32) {
                 @ Restore the registers we used and return.
                 pop {{r4}}
                 mov r11, r4
-                pop {{r4, r5, pc}}
+                pop {{r4-r6, pc}}
                 ",
                 sysnum = const Sysnum::IrqControl as u32,
                 options(noreturn),
@@ -1006,11 +1537,12 @@ unsafe extern "C" fn sys_irq_control_stub(_mask: u32, _enable: u32) {
         } else if #[cfg(any(armv7m, armv8m))] {
             arch::asm!("
                 @ Spill the registers we're about to use to pass stuff.
-                push {{r4, r5, r11, lr}}
+                push {{r4-r6, r11, lr}}
 
                 @ Move register arguments into place.
                 mov r4, r0
                 mov r5, r1
+                mov r6, r2
                 @ Load the constant syscall number.
                 mov r11, {sysnum}
 
@@ -1020,7 +1552,7 @@ unsafe extern "C" fn sys_irq_control_stub(_mask: u32, _enable: u32) {
                 @ This call returns no results.
 
                 @ Restore the registers we used and return.
-                pop {{r4, r5, r11, pc}}
+                pop {{r4-r6, r11, pc}}
                 ",
                 sysnum = const Sysnum::IrqControl as u32,
                 options(noreturn),
@@ -1103,7 +1635,10 @@ unsafe extern "C" fn sys_panic_stub(_msg: *const u8, _len: usize) -> ! {
 ///
 /// `deadline` and `on_dl` are as configured by `sys_set_timer`.
 ///
-/// `now` is monotonically advancing and can't be changed.
+/// `now` is monotonically advancing and can't be changed. It's read in one
+/// go, all 64 bits, so it doesn't need care about the low half rolling over
+/// -- and it won't itself overflow, so a later reading is never smaller. See
+/// `time::Instant` for a type that wraps it.
 #[inline(always)]
 pub fn sys_get_timer() -> TimerState {
     use core::mem::MaybeUninit;
@@ -1122,7 +1657,7 @@ pub fn sys_get_timer() -> TimerState {
         } else {
             None
         },
-        on_dl: out.on_dl,
+        on_dl: u64::from(out.on_dl_lo) | u64::from(out.on_dl_hi) << 32,
     }
 }
 
@@ -1133,7 +1668,7 @@ pub struct TimerState {
     /// Current deadline, or `None` if the deadline is not pending.
     pub deadline: Option<u64>,
     /// Notifications to be delivered if the deadline is reached.
-    pub on_dl: u32,
+    pub on_dl: u64,
 }
 
 #[repr(C)] // loaded from assembly, field order must not change
@@ -1143,7 +1678,8 @@ struct RawTimerState {
     set: u32,
     dl_lo: u32,
     dl_hi: u32,
-    on_dl: u32,
+    on_dl_lo: u32,
+    on_dl_hi: u32,
 }
 
 /// Core implementation of the GET_TIMER syscall.
@@ -1173,7 +1709,8 @@ unsafe extern "C" fn sys_get_timer_stub(_out: *mut RawTimerState) {
                 stm r0!, {{r4-r7}}
                 mov r4, r8
                 mov r5, r9
-                stm r0!, {{r4, r5}}
+                mov r6, r10
+                stm r0!, {{r4-r6}}
                 @ Restore the registers we used.
                 pop {{r4-r7}}
                 mov r11, r7
@@ -1196,7 +1733,7 @@ unsafe extern "C" fn sys_get_timer_stub(_out: *mut RawTimerState) {
                 svc #0
 
                 @ Write all the results out into the raw output buffer.
-                stm r0, {{r4-r9}}
+                stm r0, {{r4-r10}}
                 @ Restore the registers we used.
                 pop {{r4-r11}}
                 @ Fin.
@@ -1211,8 +1748,10 @@ unsafe extern "C" fn sys_get_timer_stub(_out: *mut RawTimerState) {
     }
 }
 
-/// This is the entry point for the task, invoked by the kernel. Its job is to
-/// set up our memory before jumping to user-defined `main`.
+/// This is the entry point for the task, invoked by the kernel. By the time
+/// we get here, the kernel has already initialized our `.data` and zeroed our
+/// `.bss` (see `ram_init` in the kernel's `TaskDesc`), so all that's left is to
+/// jump to user-defined `main`.
 #[doc(hidden)]
 #[no_mangle]
 #[link_section = ".text.start"]
@@ -1224,105 +1763,11 @@ pub unsafe extern "C" fn _start() -> ! {
     }
 
     cfg_if::cfg_if! {
-        if #[cfg(armv6m)] {
-            arch::asm!("
-                @ Copy data initialization image into data section.
-                @ Note: this assumes that both source and destination are 32-bit
-                @ aligned and padded to 4-byte boundary.
-
-                ldr r0, =__edata            @ upper bound in r0
-                ldr r1, =__sidata           @ source in r1
-                ldr r2, =__sdata            @ dest in r2
-
-                b 1f                        @ check for zero-sized data
-
-            2:  ldm r1!, {{r3}}             @ read and advance source
-                stm r2!, {{r3}}             @ write and advance dest
-
-            1:  cmp r2, r0                  @ has dest reached the upper bound?
-                bne 2b                      @ if not, repeat
-
-                @ Zero BSS section.
-
-                ldr r0, =__ebss             @ upper bound in r0
-                ldr r1, =__sbss             @ base in r1
-
-                movs r2, #0                 @ materialize a zero
-
-                b 1f                        @ check for zero-sized BSS
-
-            2:  stm r1!, {{r2}}             @ zero one word and advance
-
-            1:  cmp r1, r0                  @ has base reached bound?
-                bne 2b                      @ if not, repeat
-
-                @ Be extra careful to ensure that those side effects are
-                @ visible to the user program.
-
-                dsb         @ complete all writes
-                isb         @ and flush the pipeline
-
-                @ Now, to the user entry point. We call it in case it
-                @ returns. (It's not supposed to.) We reference it through
-                @ a sym operand because it's a Rust func and may be mangled.
-                bl {main}
-
-                @ The noreturn option below will automatically generate an
-                @ undefined instruction trap past this point, should main
-                @ return.
-                ",
-                main = sym main,
-                options(noreturn),
-            )
-        } else if #[cfg(any(armv7m, armv8m))] {
+        if #[cfg(any(armv6m, armv7m, armv8m))] {
             arch::asm!("
-                @ Copy data initialization image into data section.
-                @ Note: this assumes that both source and destination are 32-bit
-                @ aligned and padded to 4-byte boundary.
-
-                movw r0, #:lower16:__edata  @ upper bound in r0
-                movt r0, #:upper16:__edata
-
-                movw r1, #:lower16:__sidata @ source in r1
-                movt r1, #:upper16:__sidata
-
-                movw r2, #:lower16:__sdata  @ dest in r2
-                movt r2, #:upper16:__sdata
-
-                b 1f                        @ check for zero-sized data
-
-            2:  ldr r3, [r1], #4            @ read and advance source
-                str r3, [r2], #4            @ write and advance dest
-
-            1:  cmp r2, r0                  @ has dest reached the upper bound?
-                bne 2b                      @ if not, repeat
-
-                @ Zero BSS section.
-
-                movw r0, #:lower16:__ebss   @ upper bound in r0
-                movt r0, #:upper16:__ebss
-
-                movw r1, #:lower16:__sbss   @ base in r1
-                movt r1, #:upper16:__sbss
-
-                movs r2, #0                 @ materialize a zero
-
-                b 1f                        @ check for zero-sized BSS
-
-            2:  str r2, [r1], #4            @ zero one word and advance
-
-            1:  cmp r1, r0                  @ has base reached bound?
-                bne 2b                      @ if not, repeat
-
-                @ Be extra careful to ensure that those side effects are
-                @ visible to the user program.
-
-                dsb         @ complete all writes
-                isb         @ and flush the pipeline
-
-                @ Now, to the user entry point. We call it in case it
-                @ returns. (It's not supposed to.) We reference it through
-                @ a sym operand because it's a Rust func and may be mangled.
+                @ To the user entry point. We call it in case it returns.
+                @ (It's not supposed to.) We reference it through a sym
+                @ operand because it's a Rust func and may be mangled.
                 bl {main}
 
                 @ The noreturn option below will automatically generate an
@@ -1500,12 +1945,54 @@ fn panic(info: &core::panic::PanicInfo<'_>) -> ! {
 }
 
 /// Panic handler for tasks without the `panic-messages` feature enabled. This
-/// kills the task with a fixed message, `"PANIC"`. While this is less helpful
-/// than a proper panic message, the stack trace can still be informative.
+/// fills in an `abi::PanicRecord` with the location of the panic and, if it's a
+/// plain string, the message, and kills the task with the record as its panic
+/// message. Unlike `panic-messages`, this doesn't pull in the formatting
+/// machinery, so it costs little flash, and the supervisor can read the record
+/// back with `kipc::read_panic_record`.
 #[cfg(all(not(feature = "no-panic"), not(feature = "panic-messages")))]
 #[panic_handler]
-fn panic(_: &core::panic::PanicInfo<'_>) -> ! {
-    sys_panic(b"PANIC")
+fn panic(info: &core::panic::PanicInfo<'_>) -> ! {
+    use abi::{PanicRecord, PANIC_RECORD_MAGIC};
+    use zerocopy::AsBytes;
+
+    /// Copies as much of `src` into `dst` as fits, returning the number of
+    /// bytes copied. `tail` selects whether to keep the end of `src` rather
+    /// than its start.
+    fn copy_clipped(dst: &mut [u8], src: &[u8], tail: bool) -> u8 {
+        let n = src.len().min(dst.len());
+        let src = if tail {
+            &src[src.len() - n..]
+        } else {
+            &src[..n]
+        };
+        dst[..n].copy_from_slice(src);
+        n as u8
+    }
+
+    // The record lives in a static, rather than on the stack, so that it has
+    // a fixed address for the debugger and doesn't need stack we may not have.
+    #[no_mangle]
+    static mut HUBRIS_PANIC_RECORD: PanicRecord = PanicRecord::EMPTY;
+
+    // Safety: this is the only reference to the static, which is lexically
+    // confined to this function. It could only be aliased if the code below
+    // panicked, and it's written so as not to.
+    let record = unsafe { &mut *core::ptr::addr_of_mut!(HUBRIS_PANIC_RECORD) };
+
+    record.magic = PANIC_RECORD_MAGIC;
+    if let Some(loc) = info.location() {
+        record.line = loc.line();
+        record.column = loc.column();
+        record.file_len =
+            copy_clipped(&mut record.file, loc.file().as_bytes(), true);
+    }
+    if let Some(msg) = info.message().as_str() {
+        record.message_len =
+            copy_clipped(&mut record.message, msg.as_bytes(), false);
+    }
+
+    sys_panic(record.as_bytes())
 }
 
 /// Panic handler for when panics are not permitted in a task. This is enabled
@@ -1594,19 +2081,29 @@ unsafe extern "C" fn sys_refresh_task_id_stub(_tid: u32) -> u32 {
 
 #[inline(always)]
 pub fn sys_post(task_id: TaskId, bits: u32) -> u32 {
-    unsafe { sys_post_stub(task_id.0 as u32, bits) }
+    sys_post_wide(task_id, u64::from(bits))
+}
+
+/// Version of `sys_post` that can post any of the 64 notification bits.
+#[inline(always)]
+pub fn sys_post_wide(task_id: TaskId, bits: u64) -> u32 {
+    unsafe { sys_post_stub(task_id.0 as u32, bits as u32, (bits >> 32) as u32) }
 }
 
 /// Core implementation of the POST syscall.
 ///
 /// See the note on syscall stubs at the top of this module for rationale.
 #[naked]
-unsafe extern "C" fn sys_post_stub(_tid: u32, _mask: u32) -> u32 {
+unsafe extern "C" fn sys_post_stub(
+    _tid: u32,
+    _mask_lo: u32,
+    _mask_hi: u32,
+) -> u32 {
     cfg_if::cfg_if! {
         if #[cfg(armv6m)] {
             arch::asm!("
                 @ Spill the registers we're about to use to pass stuff.
-                push {{r4, r5, lr}}
+                push {{r4-r6, lr}}
                 mov r4, r11
                 push {{r4}}
 
@@ -1618,6 +2115,7 @@ unsafe extern "C" fn sys_post_stub(_tid: u32, _mask: u32) -> u32 {
                 @ Move register arguments into place.
                 mov r4, r0
                 mov r5, r1
+                mov r6, r2
 
                 @ To the kernel!
                 svc #0
@@ -1628,7 +2126,7 @@ unsafe extern "C" fn sys_post_stub(_tid: u32, _mask: u32) -> u32 {
                 @ Restore the registers we used and return.
                 pop {{r4}}
                 mov r11, r4
-                pop {{r4, r5, pc}}
+                pop {{r4-r6, pc}}
                 ",
                 sysnum = const Sysnum::Post as u32,
                 options(noreturn),
@@ -1636,11 +2134,12 @@ unsafe extern "C" fn sys_post_stub(_tid: u32, _mask: u32) -> u32 {
         } else if #[cfg(any(armv7m, armv8m))] {
             arch::asm!("
                 @ Spill the registers we're about to use to pass stuff.
-                push {{r4, r5, r11, lr}}
+                push {{r4-r6, r11, lr}}
 
                 @ Move register arguments into place.
                 mov r4, r0
                 mov r5, r1
+                mov r6, r2
                 @ Load the constant syscall number.
                 mov r11, {sysnum}
 
@@ -1651,7 +2150,7 @@ unsafe extern "C" fn sys_post_stub(_tid: u32, _mask: u32) -> u32 {
                 mov r0, r4
 
                 @ Restore the registers we used and return.
-                pop {{r4, r5, r11, pc}}
+                pop {{r4-r6, r11, pc}}
                 ",
                 sysnum = const Sysnum::Post as u32,
                 options(noreturn),
@@ -1662,21 +2161,53 @@ unsafe extern "C" fn sys_post_stub(_tid: u32, _mask: u32) -> u32 {
     }
 }
 
+/// Sends a short message to `task` without waiting for it to be received.
+///
+/// The message is copied into the recipient's mailbox, and this returns
+/// immediately. The recipient sees it from an open `sys_recv` with the
+/// operation code tagged with `abi::ASYNC_OPERATION_FLAG`. Async messages can't
+/// carry leases, and aren't replied to.
+///
+/// `message` must be no longer than `abi::ASYNC_MESSAGE_SIZE` bytes, and the
+/// recipient must have a mailbox (configured with `mailbox` in its
+/// `app.toml` entry); violating either of these faults the caller.
+///
+/// If the recipient's mailbox is full, returns `Err(abi::MAILBOX_FULL)`. If the
+/// recipient has restarted since `task` was obtained, returns the dead code.
 #[inline(always)]
-pub fn sys_reply_fault(task_id: TaskId, reason: ReplyFaultReason) {
-    unsafe { sys_reply_fault_stub(task_id.0 as u32, reason as u32) }
+pub fn sys_send_async(
+    task: TaskId,
+    operation: u16,
+    message: &[u8],
+) -> Result<(), u32> {
+    let rc = unsafe {
+        sys_send_async_stub(
+            (u32::from(task.0) << 16) | u32::from(operation),
+            message.as_ptr(),
+            message.len(),
+        )
+    };
+    if rc == 0 {
+        Ok(())
+    } else {
+        Err(rc)
+    }
 }
 
-/// Core implementation of the REPLY_FAULT syscall.
+/// Core implementation of the SEND_ASYNC syscall.
 ///
 /// See the note on syscall stubs at the top of this module for rationale.
 #[naked]
-unsafe extern "C" fn sys_reply_fault_stub(_tid: u32, _reason: u32) {
+unsafe extern "C" fn sys_send_async_stub(
+    _target_and_op: u32,
+    _msg_ptr: *const u8,
+    _msg_len: usize,
+) -> u32 {
     cfg_if::cfg_if! {
         if #[cfg(armv6m)] {
             arch::asm!("
                 @ Spill the registers we're about to use to pass stuff.
-                push {{r4, r5, lr}}
+                push {{r4-r6, lr}}
                 mov r4, r11
                 push {{r4}}
 
@@ -1684,41 +2215,296 @@ unsafe extern "C" fn sys_reply_fault_stub(_tid: u32, _reason: u32) {
                 movs r4, #0
                 adds r4, #{sysnum}
                 mov r11, r4
+
                 @ Move register arguments into place.
                 mov r4, r0
                 mov r5, r1
+                mov r6, r2
 
                 @ To the kernel!
                 svc #0
 
-                @ This syscall has no results.
+                @ Move result into place.
+                mov r0, r4
 
                 @ Restore the registers we used and return.
                 pop {{r4}}
                 mov r11, r4
-                pop {{r4, r5, pc}}
+                pop {{r4-r6, pc}}
                 ",
-                sysnum = const Sysnum::ReplyFault as u32,
+                sysnum = const Sysnum::SendAsync as u32,
                 options(noreturn),
             )
         } else if #[cfg(any(armv7m, armv8m))] {
             arch::asm!("
                 @ Spill the registers we're about to use to pass stuff.
-                push {{r4, r5, r11, lr}}
+                push {{r4-r6, r11, lr}}
 
                 @ Move register arguments into place.
                 mov r4, r0
                 mov r5, r1
+                mov r6, r2
                 @ Load the constant syscall number.
                 mov r11, {sysnum}
 
                 @ To the kernel!
                 svc #0
 
+                @ Move result into place.
+                mov r0, r4
+
+                @ Restore the registers we used and return.
+                pop {{r4-r6, r11, pc}}
+                ",
+                sysnum = const Sysnum::SendAsync as u32,
+                options(noreturn),
+            )
+        } else {
+            compile_error!("missing sys_send_async_stub for ARM profile")
+        }
+    }
+}
+
+/// Replies to `peer` by writing `message` directly into the writable lease
+/// `index` that `peer` lent us, starting at `offset`, instead of into its reply
+/// buffer.
+///
+/// This does the job of `sys_borrow_write` followed by `sys_reply` with an
+/// empty message, in one kernel entry. On success, returns `Ok` with the number
+/// of bytes written; `peer` sees a reply of length zero, as it would after an
+/// empty `sys_reply`.
+///
+/// If `peer` is not waiting for our reply, or its lease `index` doesn't exist or
+/// isn't writable, this returns `Err(DEFECT)` (or a dead code if `peer` has
+/// restarted) and `peer` is left waiting, so you can still reply to it some
+/// other way.
+#[inline(always)]
+pub fn sys_reply_lease(
+    peer: TaskId,
+    code: u32,
+    index: usize,
+    offset: usize,
+    message: &[u8],
+) -> Result<usize, u32> {
+    sys_reply_lease_checked(peer, 0, code, index, offset, message)
+}
+
+/// Version of `sys_reply_lease` that cites the reply nonce of the message
+/// being replied to. See `sys_reply_checked`.
+#[inline(always)]
+pub fn sys_reply_lease_checked(
+    peer: TaskId,
+    nonce: u32,
+    code: u32,
+    index: usize,
+    offset: usize,
+    message: &[u8],
+) -> Result<usize, u32> {
+    let mut args = ReplyLeaseArgs {
+        peer: u32::from(peer.0),
+        code,
+        index,
+        offset,
+        message: message.as_ptr(),
+        message_len: message.len(),
+        nonce,
+    };
+    let (rc, len) = unsafe { sys_reply_lease_stub(&mut args).into() };
+    if rc == 0 {
+        Ok(len)
+    } else {
+        Err(rc)
+    }
+}
+
+#[allow(dead_code)] // this gets used from asm
+#[repr(C)] // field order matters
+struct ReplyLeaseArgs {
+    peer: u32,
+    code: u32,
+    index: usize,
+    offset: usize,
+    message: *const u8,
+    message_len: usize,
+    nonce: u32,
+}
+
+/// Core implementation of the REPLY_LEASE syscall.
+///
+/// See the note on syscall stubs at the top of this module for rationale.
+#[naked]
+unsafe extern "C" fn sys_reply_lease_stub(_args: *mut ReplyLeaseArgs) -> RcLen {
+    cfg_if::cfg_if! {
+        if #[cfg(armv6m)] {
+            arch::asm!("
+                @ Spill the registers we're about to use to pass stuff.
+                push {{r4-r7, lr}}
+                mov r4, r8
+                mov r5, r9
+                mov r6, r10
+                mov r7, r11
+                push {{r4-r7}}
+                @ Load the constant syscall number.
+                eors r4, r4
+                adds r4, #{sysnum}
+                mov r11, r4
+                @ Load in args from the struct.
+                ldm r0!, {{r4-r7}}
+                ldm r0, {{r0-r2}}
+                mov r8, r0
+                mov r9, r1
+                mov r10, r2
+
+                @ To the kernel!
+                svc #0
+
+                @ Move the two results back into their return positions.
+                mov r0, r4
+                mov r1, r5
+                @ Restore the registers we used.
+                pop {{r4-r7}}
+                mov r8, r4
+                mov r9, r5
+                mov r10, r6
+                mov r11, r7
+                pop {{r4-r7, pc}}
+                ",
+                sysnum = const Sysnum::ReplyLease as u32,
+                options(noreturn),
+            )
+        } else if #[cfg(any(armv7m, armv8m))] {
+            arch::asm!("
+                @ Spill the registers we're about to use to pass stuff.
+                push {{r4-r11}}
+                @ Load in args from the struct.
+                ldm r0, {{r4-r10}}
+                @ Load the constant syscall number.
+                mov r11, {sysnum}
+
+                @ To the kernel!
+                svc #0
+
+                @ Move the two results back into their return positions.
+                mov r0, r4
+                mov r1, r5
+                @ Restore the registers we used.
+                pop {{r4-r11}}
+                bx lr
+                ",
+                sysnum = const Sysnum::ReplyLease as u32,
+                options(noreturn),
+            )
+        } else {
+            compile_error!("missing sys_reply_lease_stub for ARM profile");
+        }
+    }
+}
+
+#[inline(always)]
+pub fn sys_reply_fault(task_id: TaskId, reason: ReplyFaultReason) {
+    sys_reply_fault_checked(task_id, 0, reason)
+}
+
+/// Version of `sys_reply_fault` that cites the reply nonce of the message
+/// being replied to. See `sys_reply_checked`.
+#[inline(always)]
+pub fn sys_reply_fault_checked(
+    task_id: TaskId,
+    nonce: u32,
+    reason: ReplyFaultReason,
+) {
+    sys_reply_fault_detail(task_id, nonce, reason, None)
+}
+
+/// Version of `sys_reply_fault_checked` that attaches `detail` to the fault
+/// record of `task_id`, where debuggers and the supervisor can find it.
+#[inline(always)]
+pub fn sys_reply_fault_detail(
+    task_id: TaskId,
+    nonce: u32,
+    reason: ReplyFaultReason,
+    detail: Option<ReplyFaultDetail>,
+) {
+    let d = detail.unwrap_or_default();
+    let args = ReplyFaultArgs {
+        tid: u32::from(task_id.0),
+        reason: reason as u32,
+        nonce,
+        has_detail: detail.is_some() as u32,
+        argument: d.argument,
+        value: d.value,
+    };
+    unsafe { sys_reply_fault_stub(&args) }
+}
+
+#[allow(dead_code)] // this gets used from asm
+#[repr(C)] // field order matters
+struct ReplyFaultArgs {
+    tid: u32,
+    reason: u32,
+    nonce: u32,
+    has_detail: u32,
+    argument: u32,
+    value: u32,
+}
+
+/// Core implementation of the REPLY_FAULT syscall.
+///
+/// See the note on syscall stubs at the top of this module for rationale.
+#[naked]
+unsafe extern "C" fn sys_reply_fault_stub(_args: *const ReplyFaultArgs) {
+    cfg_if::cfg_if! {
+        if #[cfg(armv6m)] {
+            arch::asm!("
+                @ Spill the registers we're about to use to pass stuff.
+                push {{r4-r7, lr}}
+                mov r4, r8
+                mov r5, r9
+                mov r6, r11
+                push {{r4-r6}}
+
+                @ Load the constant syscall number.
+                movs r4, #0
+                adds r4, #{sysnum}
+                mov r11, r4
+                @ Load in args from the struct.
+                ldm r0!, {{r4-r7}}
+                ldm r0, {{r0-r1}}
+                mov r8, r0
+                mov r9, r1
+
+                @ To the kernel!
+                svc #0
+
                 @ This syscall has no results.
 
                 @ Restore the registers we used and return.
-                pop {{r4, r5, r11, pc}}
+                pop {{r4-r6}}
+                mov r8, r4
+                mov r9, r5
+                mov r11, r6
+                pop {{r4-r7, pc}}
+                ",
+                sysnum = const Sysnum::ReplyFault as u32,
+                options(noreturn),
+            )
+        } else if #[cfg(any(armv7m, armv8m))] {
+            arch::asm!("
+                @ Spill the registers we're about to use to pass stuff.
+                push {{r4-r9, r11, lr}}
+
+                @ Load in args from the struct.
+                ldm r0, {{r4-r9}}
+                @ Load the constant syscall number.
+                mov r11, {sysnum}
+
+                @ To the kernel!
+                svc #0
+
+                @ This syscall has no results.
+
+                @ Restore the registers we used and return.
+                pop {{r4-r9, r11, pc}}
                 ",
                 sysnum = const Sysnum::ReplyFault as u32,
                 options(noreturn),
@@ -1747,7 +2533,15 @@ unsafe extern "C" fn sys_reply_fault_stub(_tid: u32, _reason: u32) {
 /// mapped to an interrupt in this task.
 #[inline(always)]
 pub fn sys_irq_status(mask: u32) -> abi::IrqStatus {
-    let status = unsafe { sys_irq_status_stub(mask) };
+    sys_irq_status_wide(u64::from(mask))
+}
+
+/// Version of `sys_irq_status` for interrupts on any of the 64 notification
+/// bits.
+#[inline(always)]
+pub fn sys_irq_status_wide(mask: u64) -> abi::IrqStatus {
+    let status =
+        unsafe { sys_irq_status_stub(mask as u32, (mask >> 32) as u32) };
     abi::IrqStatus::from_bits_truncate(status)
 }
 
@@ -1755,12 +2549,12 @@ pub fn sys_irq_status(mask: u32) -> abi::IrqStatus {
 ///
 /// See the note on syscall stubs at the top of this module for rationale.
 #[naked]
-unsafe extern "C" fn sys_irq_status_stub(_mask: u32) -> u32 {
+unsafe extern "C" fn sys_irq_status_stub(_mask_lo: u32, _mask_hi: u32) -> u32 {
     cfg_if::cfg_if! {
         if #[cfg(armv6m)] {
             arch::asm!("
                 @ Spill the registers we're about to use to pass stuff.
-                push {{r4, lr}}
+                push {{r4, r5, lr}}
                 mov r4, r11
                 push {{r4}}
 
@@ -1770,6 +2564,7 @@ unsafe extern "C" fn sys_irq_status_stub(_mask: u32) -> u32 {
                 mov r11, r4
                 @ Move register arguments into place.
                 mov r4, r0
+                mov r5, r1
 
                 @ To the kernel!
                 svc #0
@@ -1780,7 +2575,7 @@ unsafe extern "C" fn sys_irq_status_stub(_mask: u32) -> u32 {
                 @ Restore the registers we used and return.
                 pop {{r4}}
                 mov r11, r4
-                pop {{r4, pc}}
+                pop {{r4, r5, pc}}
                 ",
                 sysnum = const Sysnum::IrqStatus as u32,
                 options(noreturn),
@@ -1788,10 +2583,11 @@ unsafe extern "C" fn sys_irq_status_stub(_mask: u32) -> u32 {
         } else if #[cfg(any(armv7m, armv8m))] {
             arch::asm!("
                 @ Spill the registers we're about to use to pass stuff.
-                push {{r4, r11, lr}}
+                push {{r4, r5, r11, lr}}
 
                 @ Move register arguments into place.
                 mov r4, r0
+                mov r5, r1
                 @ Load the constant syscall number.
                 mov r11, {sysnum}
 
@@ -1802,7 +2598,7 @@ unsafe extern "C" fn sys_irq_status_stub(_mask: u32) -> u32 {
                 mov r0, r4
 
                 @ Restore the registers we used and return.
-                pop {{r4, r11, pc}}
+                pop {{r4, r5, r11, pc}}
                 ",
                 sysnum = const Sysnum::IrqStatus as u32,
                 options(noreturn),
@@ -0,0 +1,167 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Points in kernel time, and spans between them.
+//!
+//! The kernel counts time as a 64-bit number of ticks since boot, at
+//! [`TICK_HZ`](crate::TICK_HZ) ticks a second. `GET_TIMER` reads all 64 bits
+//! at once, and the count never goes backwards; it would take more than half
+//! a billion years to wrap at the default 1 kHz, so it doesn't, and nothing
+//! here handles it wrapping. Compare `Instant`s directly, or subtract them:
+//! there's no need to keep a 32-bit timestamp and `wrapping_sub` it.
+//!
+//! Spans of time are `core::time::Duration`s, converted to ticks by rounding
+//! up, so that waiting a `Duration` waits at least that long.
+
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+use core::time::Duration;
+
+use crate::{hl, sys_get_timer, UnwrapLite, TICK_HZ};
+
+/// A point in kernel time, in ticks since boot.
+///
+/// This is the same unit as `sys_get_timer().now` and the deadlines given to
+/// `sys_set_timer`, and converts to and from them with `ticks` and
+/// `from_ticks`. Instants are only comparable within one boot.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[repr(transparent)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// The instant the kernel started counting.
+    pub const BOOT: Self = Self(0);
+
+    /// Reads the current kernel time.
+    pub fn now() -> Self {
+        Self(sys_get_timer().now)
+    }
+
+    pub const fn from_ticks(ticks: u64) -> Self {
+        Self(ticks)
+    }
+
+    pub const fn ticks(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the time from `earlier` to `self`, or zero if `earlier` is
+    /// actually later.
+    pub fn duration_since(self, earlier: Self) -> Duration {
+        ticks_to_duration(self.0.saturating_sub(earlier.0))
+    }
+
+    /// Returns the time from `earlier` to `self`, or `None` if `earlier` is
+    /// actually later.
+    pub fn checked_duration_since(self, earlier: Self) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(ticks_to_duration)
+    }
+
+    /// Returns how long it's been since `self`.
+    pub fn elapsed(self) -> Duration {
+        Self::now().duration_since(self)
+    }
+
+    /// Returns `self` plus `d`, rounded up to a whole tick, or `None` if
+    /// that's past the end of time.
+    pub fn checked_add(self, d: Duration) -> Option<Self> {
+        duration_to_ticks(d)
+            .and_then(|t| self.0.checked_add(t))
+            .map(Self)
+    }
+
+    /// Returns `self` minus `d`, rounded up to a whole tick, or `None` if
+    /// that's before boot.
+    pub fn checked_sub(self, d: Duration) -> Option<Self> {
+        duration_to_ticks(d)
+            .and_then(|t| self.0.checked_sub(t))
+            .map(Self)
+    }
+
+    /// Returns `self` plus `d`, stopping at the end of time -- which, as a
+    /// deadline, never arrives.
+    pub fn saturating_add(self, d: Duration) -> Self {
+        self.checked_add(d).unwrap_or(Self(u64::MAX))
+    }
+
+    /// Returns `self` minus `d`, stopping at boot.
+    pub fn saturating_sub(self, d: Duration) -> Self {
+        self.checked_sub(d).unwrap_or(Self::BOOT)
+    }
+}
+
+impl From<Instant> for u64 {
+    fn from(t: Instant) -> Self {
+        t.0
+    }
+}
+
+/// Panics if the result would be past the end of time. Use `saturating_add`
+/// for a deadline that might be.
+impl Add<Duration> for Instant {
+    type Output = Self;
+
+    fn add(self, d: Duration) -> Self {
+        self.checked_add(d).unwrap_lite()
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, d: Duration) {
+        *self = *self + d;
+    }
+}
+
+/// Panics if the result would be before boot.
+impl Sub<Duration> for Instant {
+    type Output = Self;
+
+    fn sub(self, d: Duration) -> Self {
+        self.checked_sub(d).unwrap_lite()
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, d: Duration) {
+        *self = *self - d;
+    }
+}
+
+/// Saturates at zero, like `duration_since`.
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Self) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// Converts a `Duration` into kernel ticks, rounding up, or returns `None` if
+/// it's too long to count.
+pub const fn duration_to_ticks(d: Duration) -> Option<u64> {
+    let hz = TICK_HZ as u64;
+    let Some(whole) = d.as_secs().checked_mul(hz) else {
+        return None;
+    };
+    // Below 2^30 nanoseconds times a 32-bit rate, this can't overflow.
+    let part = (d.subsec_nanos() as u64 * hz).div_ceil(1_000_000_000);
+    whole.checked_add(part)
+}
+
+/// Converts a number of kernel ticks into a `Duration`.
+pub const fn ticks_to_duration(ticks: u64) -> Duration {
+    let hz = TICK_HZ as u64;
+    let secs = ticks / hz;
+    let nanos = (ticks % hz) * 1_000_000_000 / hz;
+    Duration::new(secs, nanos as u32)
+}
+
+/// Suspends the calling task until the kernel time reaches `t`.
+pub fn sleep_until(t: Instant) {
+    hl::sleep_until(t.0)
+}
+
+/// Suspends the calling task for at least `d`.
+pub fn sleep(d: Duration) {
+    hl::sleep_for(duration_to_ticks(d).unwrap_or(u64::MAX))
+}
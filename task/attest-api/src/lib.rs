@@ -6,6 +6,7 @@
 
 #![no_std]
 
+pub use drv_lpc55_update_api::{Fwid, ImageVersion, SlotId};
 use hubpack::SerializedSize;
 use serde::{Deserialize, Serialize};
 use userlib::sys_send;
@@ -34,6 +35,8 @@ pub enum AttestError {
     SerializeLog,
     SerializeSignature,
     SignatureTooBig,
+    /// The update server couldn't tell us about the firmware in flash
+    NoFirmwareInfo,
 }
 
 impl From<idol_runtime::ServerDeath> for AttestError {
@@ -49,6 +52,22 @@ pub enum HashAlgorithm {
     Sha3_256,
 }
 
+/// The firmware that makes up the RoT: the version of the running image, and a
+/// digest of each image in flash. A verifier can check these against the
+/// measurement log (which covers the SP's firmware too) and against the
+/// versions it expects.
+#[derive(Clone, Deserialize, Serialize, SerializedSize)]
+pub struct FirmwareVersions {
+    /// Version of the running Hubris image
+    pub version: ImageVersion,
+    /// The slot of the running image
+    pub active: SlotId,
+    pub slot_a: Fwid,
+    pub slot_b: Fwid,
+    pub stage0: Fwid,
+    pub stage0next: Fwid,
+}
+
 pub const NONCE_MIN_SIZE: usize = 32;
 pub const NONCE_MAX_SIZE: usize = 128;
 
@@ -11,24 +11,31 @@
 
 mod config;
 
-use attest_api::{AttestError, HashAlgorithm, NONCE_MAX_SIZE, NONCE_MIN_SIZE};
+use attest_api::{
+    AttestError, FirmwareVersions, HashAlgorithm, NONCE_MAX_SIZE,
+    NONCE_MIN_SIZE,
+};
 use attest_data::{
     Attestation, Ed25519Signature, Log, Measurement, Sha3_256Digest,
 };
 use config::DataRegion;
 use core::slice;
+use drv_lpc55_update_api::{Update, VersionedRotBootInfo};
 use hubpack::SerializedSize;
 use idol_runtime::{
     ClientError, Leased, LenLimit, NotificationHandler, RequestError, R, W,
 };
+use lib_crypto::ed25519::Keypair;
+use lib_crypto::{Digest as CryptDigest, Sha3_256};
 use lib_dice::{AliasData, CertData, SeedBuf};
 use ringbuf::{ringbuf, ringbuf_entry};
-use salty::signature::Keypair;
 use serde::Deserialize;
-use sha3::{Digest as CryptDigest, Sha3_256};
 use stage0_handoff::{HandoffData, HandoffDataLoadError};
+use userlib::task_slot;
 use zerocopy::AsBytes;
 
+task_slot!(UPDATE_SERVER, update_server);
+
 // This file is generated by the crate build.rs. It contains instances of
 // config::DataRegion structs describing regions of memory configured &
 // exposed to this task by the hubris build.
@@ -98,6 +105,7 @@ struct AttestServer {
     buf: &'static mut [u8; Log::MAX_SIZE],
     cert_data: Option<CertData>,
     measurements: Log,
+    update: Update,
 }
 impl AttestServer {
     /// Claims static resources and loads data.
@@ -128,6 +136,7 @@ impl AttestServer {
             buf,
             cert_data: load_data_from_region(&CERT_DATA),
             measurements: Log::default(),
+            update: Update::from(UPDATE_SERVER.get_task_id()),
         }
     }
 
@@ -540,6 +549,32 @@ impl idl::InOrderAttestImpl for AttestServer {
 
         Ok(len)
     }
+
+    fn firmware_versions(
+        &mut self,
+        _: &userlib::RecvMessage,
+    ) -> Result<FirmwareVersions, RequestError<AttestError>> {
+        let version = self.update.current_version();
+        // Only V2 has digests of the stage0 slots. An update server too old
+        // to give us those can't tell a verifier everything that's running.
+        let info = match self.update.versioned_rot_boot_info(2) {
+            Ok(VersionedRotBootInfo::V2(info)) => info,
+            Ok(_) | Err(_) => {
+                let err = AttestError::NoFirmwareInfo;
+                ringbuf_entry!(Trace::AttestError(err));
+                return Err(err.into());
+            }
+        };
+
+        Ok(FirmwareVersions {
+            version,
+            active: info.active,
+            slot_a: info.slot_a_fwid,
+            slot_b: info.slot_b_fwid,
+            stage0: info.stage0_fwid,
+            stage0next: info.stage0next_fwid,
+        })
+    }
 }
 
 impl NotificationHandler for AttestServer {
@@ -565,7 +600,7 @@ fn main() -> ! {
 }
 
 mod idl {
-    use super::{AttestError, HashAlgorithm};
+    use super::{AttestError, FirmwareVersions, HashAlgorithm};
 
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
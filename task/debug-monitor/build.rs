@@ -0,0 +1,21 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::Result;
+use std::io::Write;
+
+fn main() -> Result<()> {
+    build_util::build_notifications()?;
+
+    // Task names, so that commands can take them as well as indices.
+    let tasks = build_util::env_var("HUBRIS_TASKS")?;
+    let out_dir = build_util::out_dir();
+    let mut out = std::fs::File::create(out_dir.join("task_names.rs"))?;
+    writeln!(out, "pub const TASK_NAMES: &[&str] = &[")?;
+    for name in tasks.split(',') {
+        writeln!(out, "    {name:?},")?;
+    }
+    writeln!(out, "];")?;
+    Ok(())
+}
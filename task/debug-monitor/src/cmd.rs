@@ -0,0 +1,212 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Monitor commands.
+//!
+//! Each command is one line of words. Tasks can be named by index or by
+//! name, and numbers can be given in decimal or, with a `0x` prefix, in hex.
+//! Everything here goes through the supervisor's Idol interface, except for
+//! reading task states, which any task can ask the kernel for.
+
+use core::fmt::{self, Write};
+use task_jefe_api::{Disposition, Jefe};
+use userlib::{kipc, SchedState, TaskState, TASK_MEMORY_WINDOW};
+
+use crate::generated::TASK_NAMES;
+
+/// How many crash journal entries `journal` shows, if not told otherwise.
+const JOURNAL_ENTRIES: u32 = 8;
+
+/// How many bytes `peek` shows, if not told otherwise.
+const PEEK_LEN: usize = 64;
+
+const HELP: &str = "\
+tasks                      list tasks
+status <task>              show a task's state and its last fault
+peek <task> <addr> [len]   dump up to 256 bytes of a task's memory
+hold <task>                hold the task at its next fault
+release <task>             restart the task on faults (and now, if held)
+restart <task>             restart the task
+journal [count]            show the most recent crash journal entries
+";
+
+/// Runs the command in `line`, writing what it has to say to `out`.
+pub fn run(jefe: &Jefe, line: &str, out: &mut impl Write) -> fmt::Result {
+    let mut words = line.split_whitespace();
+    let Some(cmd) = words.next() else {
+        return Ok(());
+    };
+    let mut arg = || words.next();
+
+    match cmd {
+        "help" | "?" => out.write_str(HELP),
+        "tasks" => tasks(jefe, out),
+        "status" => match task(arg()) {
+            Some(t) => status(jefe, t, out),
+            None => usage(out, "status <task>"),
+        },
+        "peek" => {
+            let t = task(arg());
+            let addr = arg().and_then(number);
+            let len = match arg() {
+                Some(s) => number(s).map(|n| n as usize),
+                None => Some(PEEK_LEN),
+            };
+            match (t, addr, len) {
+                (Some(t), Some(addr), Some(len)) => {
+                    peek(jefe, t, addr, len, out)
+                }
+                _ => usage(out, "peek <task> <addr> [len]"),
+            }
+        }
+        "hold" | "release" => {
+            let disposition = if cmd == "hold" {
+                Disposition::Hold
+            } else {
+                Disposition::Restart
+            };
+            match task(arg()) {
+                Some(t) => {
+                    match jefe.set_task_disposition(t as u32, disposition) {
+                        Ok(()) => Ok(()),
+                        Err(e) => writeln!(out, "error: {e:?}"),
+                    }
+                }
+                None => usage(out, "hold|release <task>"),
+            }
+        }
+        "restart" => match task(arg()) {
+            Some(t) => match jefe.restart_task(t as u32) {
+                Ok(()) => Ok(()),
+                Err(e) => writeln!(out, "error: {e:?}"),
+            },
+            None => usage(out, "restart <task>"),
+        },
+        "journal" => match arg() {
+            Some(s) => match number(s) {
+                Some(n) => journal(jefe, n, out),
+                None => usage(out, "journal [count]"),
+            },
+            None => journal(jefe, JOURNAL_ENTRIES, out),
+        },
+        _ => writeln!(out, "unknown command; try `help`"),
+    }
+}
+
+fn usage(out: &mut impl Write, usage: &str) -> fmt::Result {
+    writeln!(out, "usage: {usage}")
+}
+
+/// Parses a task name or index.
+fn task(word: Option<&str>) -> Option<usize> {
+    let word = word?;
+    TASK_NAMES.iter().position(|&n| n == word).or_else(|| {
+        number(word)
+            .map(|n| n as usize)
+            .filter(|&n| n < TASK_NAMES.len())
+    })
+}
+
+/// Parses a decimal number, or a hex one with a `0x` prefix.
+fn number(word: &str) -> Option<u32> {
+    match word.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => word.parse().ok(),
+    }
+}
+
+fn sched_state(s: SchedState) -> &'static str {
+    match s {
+        SchedState::Stopped => "stopped",
+        SchedState::Runnable => "ready",
+        SchedState::InSend(_) => "send",
+        SchedState::InReply(_) => "reply",
+        SchedState::InRecv(_) => "recv",
+    }
+}
+
+fn tasks(jefe: &Jefe, out: &mut impl Write) -> fmt::Result {
+    writeln!(out, " ID TASK                 STATE    FAULTS")?;
+    for (i, name) in TASK_NAMES.iter().enumerate() {
+        let state = match kipc::read_task_status(i) {
+            TaskState::Healthy(s) => sched_state(s),
+            TaskState::Faulted { .. } => "FAULT",
+        };
+        write!(out, "{i:>3} {name:<20} {state:<8}")?;
+        // The supervisor doesn't report on itself.
+        if let Ok(status) = jefe.get_task_status(i as u32) {
+            write!(out, " {}", status.fault_count)?;
+            if status.holding_fault {
+                write!(out, " (held)")?;
+            } else if status.disposition == Disposition::Hold {
+                write!(out, " (hold)")?;
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn status(jefe: &Jefe, task: usize, out: &mut impl Write) -> fmt::Result {
+    let status = match jefe.get_task_status(task as u32) {
+        Ok(status) => status,
+        Err(e) => return writeln!(out, "error: {e:?}"),
+    };
+    writeln!(out, "task:        {task} ({})", TASK_NAMES[task])?;
+    writeln!(out, "state:       {:?}", status.state)?;
+    writeln!(out, "disposition: {:?}", status.disposition)?;
+    writeln!(out, "held:        {}", status.holding_fault)?;
+    writeln!(out, "faults:      {}", status.fault_count)?;
+    match status.last_fault {
+        Some(fault) => writeln!(out, "last fault:  {fault:?}"),
+        None => writeln!(out, "last fault:  none"),
+    }
+}
+
+fn peek(
+    jefe: &Jefe,
+    task: usize,
+    addr: u32,
+    len: usize,
+    out: &mut impl Write,
+) -> fmt::Result {
+    let mut buf = [0u8; TASK_MEMORY_WINDOW];
+    let len = len.min(buf.len());
+    let n = match jefe.read_task_memory(task as u32, addr, &mut buf[..len]) {
+        Ok(n) => n as usize,
+        Err(e) => return writeln!(out, "error: {e:?}"),
+    };
+
+    for (i, line) in buf[..n].chunks(16).enumerate() {
+        write!(out, "{:08x}:", addr.wrapping_add(i as u32 * 16))?;
+        for b in line {
+            write!(out, " {b:02x}")?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn journal(jefe: &Jefe, count: u32, out: &mut impl Write) -> fmt::Result {
+    for i in 0..count {
+        let r = match jefe.read_crash_journal(i) {
+            Ok(r) => r,
+            Err(e) => {
+                // Running off the end of the journal is how this normally
+                // stops.
+                if i == 0 {
+                    writeln!(out, "error: {e:?}")?;
+                }
+                break;
+            }
+        };
+        let name = TASK_NAMES.get(usize::from(r.task)).unwrap_or(&"?");
+        writeln!(
+            out,
+            "#{} at {}: task {} ({name}) gen {} cause {} arg {:#x}",
+            r.seq, r.timestamp, r.task, r.generation, r.cause, r.fault_arg,
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,232 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! On-target debug monitor.
+//!
+//! This gives a console on a UART for looking at a running system without a
+//! debug probe: listing tasks, reading their last faults and the crash
+//! journal, dumping their memory (their ringbufs and counters, say), and
+//! holding and restarting them. See `cmd` for the commands, and the README
+//! for what it needs from the supervisor.
+
+#![no_std]
+#![no_main]
+
+mod cmd;
+
+#[cfg(any(feature = "stm32h743", feature = "stm32h753"))]
+use drv_stm32h7_usart as drv_usart;
+
+use core::fmt::{self, Write};
+use drv_usart::Usart;
+use heapless::{Deque, Vec};
+use ringbuf::*;
+use task_jefe_api::Jefe;
+use userlib::*;
+
+task_slot!(SYS, sys);
+task_slot!(JEFE, jefe);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Trace {
+    None,
+    Command(usize),
+    RxOverrun,
+    OutputFull,
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+/// Longest command line we take; anything typed past this is dropped.
+const LINE_LEN: usize = 80;
+
+/// Size of our output buffer. This has to hold everything one command says,
+/// since commands run to completion before any of it is sent; anything past
+/// this is dropped.
+const OUT_LEN: usize = 2048;
+
+const PROMPT: &str = "> ";
+
+#[export_name = "main"]
+fn main() -> ! {
+    let uart = configure_uart_device();
+    let jefe = Jefe::from(JEFE.get_task_id());
+    let mut line = Vec::<u8, LINE_LEN>::new();
+    let mut out = Output(Deque::new());
+
+    // Start with a prompt, so that whoever's connected knows we're here.
+    let _ = out.write_str(PROMPT);
+    uart.enable_tx_fifo_empty_interrupt();
+    sys_irq_control(notifications::USART_IRQ_MASK, true);
+
+    loop {
+        sys_recv_notification(notifications::USART_IRQ_MASK);
+
+        // Output from the last command goes out before we take any more
+        // input; the input waits in the RX FIFO meanwhile.
+        out.flush(&uart);
+        if !out.0.is_empty() {
+            sys_irq_control(notifications::USART_IRQ_MASK, true);
+            continue;
+        }
+        uart.disable_tx_fifo_empty_interrupt();
+
+        if uart.check_and_clear_rx_overrun() {
+            ringbuf_entry!(Trace::RxOverrun);
+        }
+
+        while let Some(byte) = uart.try_rx_pop() {
+            match byte {
+                // As with `uartecho`, a line ends at `\r`, which is all that
+                // minicom sends.
+                b'\r' => {
+                    ringbuf_entry!(Trace::Command(line.len()));
+                    let _ = out.write_str("\n");
+                    // We only ever put printable ASCII in `line`.
+                    let cmd = core::str::from_utf8(&line).unwrap_or("");
+                    let _ = cmd::run(&jefe, cmd, &mut out);
+                    let _ = out.write_str(PROMPT);
+                    line.clear();
+                    break;
+                }
+                // Backspace and delete.
+                0x08 | 0x7f => {
+                    if line.pop().is_some() {
+                        let _ = out.write_str("\x08 \x08");
+                    }
+                }
+                b' '..=b'~' => {
+                    if line.push(byte).is_ok() {
+                        out.push(byte);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        out.flush(&uart);
+        if !out.0.is_empty() {
+            uart.enable_tx_fifo_empty_interrupt();
+        }
+        sys_irq_control(notifications::USART_IRQ_MASK, true);
+    }
+}
+
+/// Output waiting to be sent. Commands write lines ending in `\n`, which
+/// terminals want as `\r\n`.
+struct Output(Deque<u8, OUT_LEN>);
+
+impl Output {
+    fn push(&mut self, byte: u8) {
+        if self.0.push_back(byte).is_err() {
+            ringbuf_entry!(Trace::OutputFull);
+        }
+    }
+
+    /// Pushes as much output as fits into `uart`'s TX FIFO.
+    fn flush(&mut self, uart: &Usart) {
+        while let Some(&byte) = self.0.front() {
+            if !uart.try_tx_push(byte) {
+                break;
+            }
+            self.0.pop_front();
+        }
+    }
+}
+
+impl Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            if byte == b'\n' {
+                self.push(b'\r');
+            }
+            self.push(byte);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "stm32h743", feature = "stm32h753"))]
+fn configure_uart_device() -> Usart {
+    use drv_usart::device;
+    use drv_usart::drv_stm32xx_sys_api::*;
+
+    // TODO: this module should _not_ know our clock rate. That's a hack.
+    const CLOCK_HZ: u32 = 100_000_000;
+
+    #[cfg(feature = "baud_rate_115_200")]
+    const BAUD_RATE: u32 = 115_200;
+    #[cfg(feature = "baud_rate_3M")]
+    const BAUD_RATE: u32 = 3_000_000;
+
+    let hardware_flow_control = cfg!(feature = "hardware_flow_control");
+
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "usart1")] {
+            const PINS: &[(PinSet, Alternate)] = {
+                if cfg!(feature = "hardware_flow_control") {
+                    // NOTE: These pins are for gimletlet, not gimlet!
+                    &[
+                        // TX, RX
+                        (Port::B.pin(6).and_pin(7), Alternate::AF7),
+                        // CTS, RTS
+                        (Port::A.pin(11).and_pin(12), Alternate::AF7),
+                    ]
+                } else {
+                    &[(Port::B.pin(6).and_pin(7), Alternate::AF7)]
+                }
+            };
+
+            // Safety: this is needlessly unsafe in the API. The USART is
+            // essentially a static, and we access it through a & reference so
+            // aliasing is not a concern.
+            let usart = unsafe { &*device::USART1::ptr() };
+            let peripheral = Peripheral::Usart1;
+        } else if #[cfg(feature = "usart2")] {
+            const PINS: &[(PinSet, Alternate)] = {
+                if cfg!(feature = "hardware_flow_control") {
+                    &[(
+                        Port::D.pin(3).and_pin(4).and_pin(5).and_pin(6),
+                        Alternate::AF7
+                    )]
+                } else {
+                    &[(Port::D.pin(5).and_pin(6), Alternate::AF7)]
+                }
+            };
+            let usart = unsafe { &*device::USART2::ptr() };
+            let peripheral = Peripheral::Usart2;
+        } else if #[cfg(feature = "uart7")] {
+            const PINS: &[(PinSet, Alternate)] = {
+                if cfg!(feature = "hardware_flow_control") {
+                    &[(
+                        Port::E.pin(7).and_pin(8).and_pin(9).and_pin(10),
+                        Alternate::AF7
+                    )]
+                } else {
+                    &[(Port::E.pin(7).and_pin(8), Alternate::AF7)]
+                }
+            };
+            let usart = unsafe { &*device::UART7::ptr() };
+            let peripheral = Peripheral::Uart7;
+        } else {
+            compile_error!("no usartX/uartX feature specified");
+        }
+    }
+
+    Usart::turn_on(
+        &Sys::from(SYS.get_task_id()),
+        usart,
+        peripheral,
+        PINS,
+        CLOCK_HZ,
+        BAUD_RATE,
+        hardware_flow_control,
+    )
+}
+
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/task_names.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
@@ -0,0 +1,83 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for the filesystem task.
+//!
+//! Each client has a directory of its own, and names its files within it;
+//! it can't see any other client's files. Names are up to `MAX_NAME_LEN`
+//! bytes of printable ASCII, other than `/`.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::*;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Longest file name, in bytes.
+pub const MAX_NAME_LEN: usize = 32;
+
+/// Most bytes that can be read or written in one call.
+pub const MAX_TRANSFER: usize = 512;
+
+#[derive(
+    Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError, counters::Count,
+)]
+pub enum FsError {
+    /// There's no file by that name
+    NotFound = 1,
+    /// The name is empty, too long, or has a character it can't have
+    BadName,
+    /// The flags passed to `open` have bits that don't mean anything
+    BadFlags,
+    /// The handle isn't one of the caller's open files
+    BadHandle,
+    /// The caller already has as many files open as it's allowed
+    TooManyHandles,
+    /// The file wasn't opened for writing
+    ReadOnly,
+    /// The file is open, and so can't be renamed or removed
+    Busy,
+    /// The write would take the caller's files over its quota
+    QuotaExceeded,
+    /// The filesystem is full
+    NoSpace,
+    /// The filesystem's metadata is damaged
+    Corrupt,
+    /// The storage underneath reported a failure
+    Io,
+    /// The caller isn't configured as a client of the filesystem
+    NotClient,
+    /// The filesystem couldn't be mounted at boot
+    Unavailable,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+bitflags::bitflags! {
+    /// How to open a file, passed to `open` as its bits.
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub struct OpenFlags: u32 {
+        /// Allow writes through the handle, as well as reads.
+        const WRITE = 1 << 0;
+        /// Create the file if it doesn't exist.
+        const CREATE = 1 << 1;
+        /// Throw away the file's contents on opening it.
+        const TRUNCATE = 1 << 2;
+        /// Make every write go at the end of the file, whatever offset is
+        /// given.
+        const APPEND = 1 << 3;
+    }
+}
+
+#[derive(Copy, Clone, Debug, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Usage {
+    /// Bytes taken up by the caller's files.
+    pub used: u32,
+    /// Bytes the caller's files may take up.
+    pub quota: u32,
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -0,0 +1,105 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct TaskConfig {
+    /// First block of the filesystem on the device
+    #[serde(default)]
+    start_block: u32,
+    /// Number of blocks in the filesystem
+    blocks: u32,
+    /// Whether to format the filesystem if it won't mount
+    #[serde(default)]
+    format: bool,
+    /// Tasks allowed to keep files, by name
+    clients: BTreeMap<String, ClientConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ClientConfig {
+    /// Bytes the client's files may take up
+    quota: u32,
+    /// Files the client may have open at once
+    #[serde(default = "default_handles")]
+    handles: u8,
+}
+
+fn default_handles() -> u8 {
+    2
+}
+
+/// Longest task name that can be used as a client's directory.
+const MAX_DIR_LEN: usize = 32;
+
+fn generate_fs_config(config: &TaskConfig) -> Result<()> {
+    if config.blocks < 2 {
+        bail!("the filesystem needs at least 2 blocks");
+    }
+    if config.start_block.checked_add(config.blocks).is_none() {
+        bail!("the filesystem runs off the end of the device");
+    }
+
+    let task_ids = build_util::task_ids();
+    let out = build_util::out_dir().join("fs_config.rs");
+    let mut file =
+        std::fs::File::create(out).context("creating fs_config.rs")?;
+    writeln!(file, "pub const START_BLOCK: u32 = {};", config.start_block)?;
+    writeln!(file, "pub const BLOCKS: usize = {};", config.blocks)?;
+    writeln!(file, "pub const FORMAT: bool = {};", config.format)?;
+    writeln!(file, "pub const MAX_DIR_LEN: usize = {MAX_DIR_LEN};")?;
+
+    let handles: usize = config
+        .clients
+        .values()
+        .map(|c| usize::from(c.handles))
+        .sum();
+    writeln!(file, "pub const MAX_HANDLES: usize = {handles};")?;
+
+    let count = config.clients.len();
+    writeln!(file, "pub const CLIENT_COUNT: usize = {count};")?;
+    writeln!(file, "pub static CLIENTS: [Client; {count}] = [")?;
+    for (name, client) in &config.clients {
+        let Some(task) = task_ids.get(name) else {
+            bail!("unknown client task `{name}`");
+        };
+        if name.len() > MAX_DIR_LEN {
+            bail!("client task name `{name}` is too long for a directory");
+        }
+        writeln!(
+            file,
+            "    Client {{ task: {task}, dir: b\"{name}\", quota: {}, \
+             handles: {} }},",
+            client.quota, client.handles,
+        )?;
+    }
+    writeln!(file, "];")?;
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    idol::Generator::new()
+        .with_counters(
+            idol::CounterSettings::default().with_server_counters(false),
+        )
+        .build_server_support(
+            "../../idl/fs.idol",
+            "server_stub.rs",
+            idol::server::ServerStyle::InOrder,
+        )?;
+
+    build_util::build_notifications()?;
+
+    let task_config = build_util::task_config::<TaskConfig>()?;
+    generate_fs_config(&task_config)?;
+
+    Ok(())
+}
@@ -0,0 +1,622 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Filesystem server.
+//!
+//! This task keeps a littlefs filesystem on a run of blocks of a block device
+//! -- the SD card server, in the `device` task slot -- so that other tasks
+//! can keep logs and configuration in files rather than each laying out
+//! flash in its own way. littlefs survives losing power at any point, and
+//! commits a file's changes when the file is closed.
+//!
+//! Only the tasks listed as clients in the config can keep files. Each client
+//! gets a directory of its own, named for the task, and can't see outside it;
+//! the files in it can take up no more than the client's quota of bytes, and
+//! the client can have no more than a set number of them open at once:
+//!
+//! ```toml
+//! [tasks.fs.config]
+//! start-block = 0
+//! blocks = 65536
+//! format = true
+//!
+//! [tasks.fs.config.clients]
+//! logger = { quota = 1048576, handles = 4 }
+//! config = { quota = 16384 }
+//! ```
+//!
+//! `start-block` and `blocks` say where on the device the filesystem lives,
+//! and `format` lets the task format it if it won't mount -- on first boot
+//! with a new card, say.
+//!
+//! A handle names a file and how it was opened, but doesn't hold the file
+//! open in littlefs: each read or write opens the file, does its work at the
+//! offset it was given, and closes it again. This keeps littlefs's file state
+//! out of the handle table, means a write is committed by the time it
+//! returns, and makes reads (and writes that don't append) safe to retry.
+//! Handles held by a task that has since restarted are reclaimed when they're
+//! next needed.
+
+#![no_std]
+#![no_main]
+
+mod storage;
+
+use drv_sdmmc_api::Sdmmc;
+use idol_runtime::{
+    ClientError, Leased, LenLimit, NotificationHandler, RequestError, R, W,
+};
+use littlefs2::fs::{Allocation, Filesystem};
+use littlefs2::io::{self, Read, Seek, SeekFrom, Write};
+use littlefs2::path::Path;
+use ringbuf::*;
+use storage::BlockStorage;
+use task_fs_api::{FsError, OpenFlags, Usage, MAX_NAME_LEN, MAX_TRANSFER};
+use userlib::*;
+
+task_slot!(DEVICE, device);
+
+type Storage = BlockStorage<Sdmmc>;
+type Fs = Filesystem<'static, Storage>;
+
+/// Longest path we make: a client's directory and a file name, each with a
+/// leading `/`, and a NUL.
+const PATH_LEN: usize = config::MAX_DIR_LEN + MAX_NAME_LEN + 3;
+
+// Handles keep their slot in the bottom byte.
+const _: () = assert!(config::MAX_HANDLES <= 256);
+
+/// A task allowed to keep files.
+pub struct Client {
+    /// Task index
+    task: usize,
+    /// Name of the client's directory
+    dir: &'static [u8],
+    /// Bytes the client's files may take up
+    quota: u32,
+    /// Files the client may have open at once
+    handles: u8,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Formatted,
+    Mounted,
+    MountFailed(FsError),
+    Usage { client: u8, used: u32 },
+    WriteFailed(FsError),
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+/// A file name, as given by a client.
+#[derive(Copy, Clone, PartialEq)]
+struct Name {
+    bytes: [u8; MAX_NAME_LEN],
+    len: usize,
+}
+
+impl Name {
+    /// Reads a name from a lease, and checks it's one a client can use.
+    fn read(
+        lease: LenLimit<Leased<R, [u8]>, MAX_NAME_LEN>,
+    ) -> Result<Self, RequestError<FsError>> {
+        let lease = lease.into_inner();
+        let mut name = Name {
+            bytes: [0; MAX_NAME_LEN],
+            len: lease.len(),
+        };
+        lease
+            .read_range(0..name.len, &mut name.bytes[..name.len])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+
+        let name_ok = match name.as_bytes() {
+            [] | b"." | b".." => false,
+            bytes => bytes.iter().all(|&b| b.is_ascii_graphic() && b != b'/'),
+        };
+        if !name_ok {
+            return Err(FsError::BadName.into());
+        }
+        Ok(name)
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// An absolute path in the filesystem, NUL-terminated as littlefs likes.
+struct FilePath {
+    buf: [u8; PATH_LEN],
+    len: usize,
+}
+
+impl FilePath {
+    /// Makes the path of a client's file, or of its directory if `name` is
+    /// `None`.
+    fn new(client: &Client, name: Option<&Name>) -> Self {
+        let mut path = FilePath {
+            buf: [0; PATH_LEN],
+            len: 0,
+        };
+        path.push(b"/");
+        path.push(client.dir);
+        if let Some(name) = name {
+            path.push(b"/");
+            path.push(name.as_bytes());
+        }
+        path.push(b"\0");
+        path
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    fn as_path(&self) -> Result<&Path, FsError> {
+        Path::from_bytes_with_nul(&self.buf[..self.len])
+            .map_err(|_| FsError::BadName)
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Handle {
+    owner: TaskId,
+    /// Index of the owner in `config::CLIENTS`
+    client: usize,
+    name: Name,
+    flags: OpenFlags,
+    /// Distinguishes this handle from others that have used the same slot.
+    serial: u32,
+}
+
+fn fs_error(e: io::Error) -> FsError {
+    match e {
+        io::Error::NoSuchEntry => FsError::NotFound,
+        io::Error::NoSpace => FsError::NoSpace,
+        io::Error::Corruption => FsError::Corrupt,
+        io::Error::FilenameTooLong
+        | io::Error::PathIsDir
+        | io::Error::PathNotDir => FsError::BadName,
+        _ => FsError::Io,
+    }
+}
+
+/// Returns the length of the file at `path`, or `None` if there's nothing
+/// there.
+fn file_len(fs: &Fs, path: &Path) -> Result<Option<usize>, FsError> {
+    match fs.metadata(path) {
+        Ok(m) if m.is_file() => Ok(Some(m.len())),
+        Ok(_) => Err(FsError::BadName),
+        Err(io::Error::NoSuchEntry) => Ok(None),
+        Err(e) => Err(fs_error(e)),
+    }
+}
+
+struct ServerImpl {
+    /// The filesystem, unless it failed to mount
+    fs: Option<Fs>,
+    handles: [Option<Handle>; config::MAX_HANDLES],
+    next_serial: u32,
+    /// Bytes taken up by each client's files
+    used: [u32; config::CLIENT_COUNT],
+    buf: [u8; MAX_TRANSFER],
+}
+
+impl ServerImpl {
+    fn fs(&self) -> Result<&Fs, FsError> {
+        self.fs.as_ref().ok_or(FsError::Unavailable)
+    }
+
+    /// Finds the caller in the config, returning its index.
+    fn client(msg: &RecvMessage) -> Result<usize, FsError> {
+        config::CLIENTS
+            .iter()
+            .position(|c| c.task == msg.sender.index())
+            .ok_or(FsError::NotClient)
+    }
+
+    /// Looks up one of the caller's handles.
+    fn handle(&self, msg: &RecvMessage, id: u32) -> Result<Handle, FsError> {
+        let slot = (id & 0xFF) as usize;
+        match self.handles.get(slot) {
+            Some(Some(h)) if h.owner == msg.sender && h.serial == id >> 8 => {
+                Ok(*h)
+            }
+            _ => Err(FsError::BadHandle),
+        }
+    }
+
+    /// Frees handles whose owners have restarted since opening them.
+    fn reap(&mut self) {
+        for slot in &mut self.handles {
+            if let Some(h) = slot {
+                if sys_refresh_task_id(h.owner) != h.owner {
+                    *slot = None;
+                }
+            }
+        }
+    }
+
+    /// Checks whether a client has a file open.
+    fn is_open(&self, client: usize, name: &Name) -> bool {
+        self.handles
+            .iter()
+            .flatten()
+            .any(|h| h.client == client && h.name == *name)
+    }
+
+    /// Takes `len` bytes of removed or truncated files off what a client's
+    /// files take up.
+    fn release(&mut self, client: usize, len: usize) {
+        self.used[client] = self.used[client].saturating_sub(len as u32);
+    }
+}
+
+impl idl::InOrderFsImpl for ServerImpl {
+    fn open(
+        &mut self,
+        msg: &RecvMessage,
+        flags: u32,
+        name: LenLimit<Leased<R, [u8]>, MAX_NAME_LEN>,
+    ) -> Result<u32, RequestError<FsError>> {
+        let client = Self::client(msg)?;
+        let flags = OpenFlags::from_bits(flags).ok_or(FsError::BadFlags)?;
+        let name = Name::read(name)?;
+
+        self.reap();
+        let open = self
+            .handles
+            .iter()
+            .flatten()
+            .filter(|h| h.client == client)
+            .count();
+        if open >= usize::from(config::CLIENTS[client].handles) {
+            return Err(FsError::TooManyHandles.into());
+        }
+        let slot = self
+            .handles
+            .iter()
+            .position(Option::is_none)
+            .ok_or(FsError::TooManyHandles)?;
+
+        let fs = self.fs()?;
+        let path = FilePath::new(&config::CLIENTS[client], Some(&name));
+        let path = path.as_path()?;
+        let truncated = match file_len(fs, path)? {
+            None if !flags.contains(OpenFlags::CREATE) => {
+                return Err(FsError::NotFound.into());
+            }
+            None => {
+                fs.open_file_with_options_and_then(
+                    |o| o.write(true).create(true),
+                    path,
+                    |_| Ok(()),
+                )
+                .map_err(fs_error)?;
+                0
+            }
+            Some(len) if flags.contains(OpenFlags::TRUNCATE) => {
+                fs.open_file_with_options_and_then(
+                    |o| o.write(true).truncate(true),
+                    path,
+                    |_| Ok(()),
+                )
+                .map_err(fs_error)?;
+                len
+            }
+            Some(_) => 0,
+        };
+        self.release(client, truncated);
+
+        let serial = self.next_serial;
+        self.next_serial = (serial + 1) & 0x00FF_FFFF;
+        self.handles[slot] = Some(Handle {
+            owner: msg.sender,
+            client,
+            name,
+            flags,
+            serial,
+        });
+        Ok(serial << 8 | slot as u32)
+    }
+
+    fn close(
+        &mut self,
+        msg: &RecvMessage,
+        handle: u32,
+    ) -> Result<(), RequestError<FsError>> {
+        self.handle(msg, handle)?;
+        self.handles[(handle & 0xFF) as usize] = None;
+        Ok(())
+    }
+
+    fn read(
+        &mut self,
+        msg: &RecvMessage,
+        handle: u32,
+        offset: u32,
+        dest: LenLimit<Leased<W, [u8]>, MAX_TRANSFER>,
+    ) -> Result<u32, RequestError<FsError>> {
+        let h = self.handle(msg, handle)?;
+        let dest = dest.into_inner();
+        let fs = self.fs.as_ref().ok_or(FsError::Unavailable)?;
+        let path = FilePath::new(&config::CLIENTS[h.client], Some(&h.name));
+        let buf = &mut self.buf[..dest.len()];
+
+        let n = fs
+            .open_file_with_options_and_then(
+                |o| o.read(true),
+                path.as_path()?,
+                |f| {
+                    f.seek(SeekFrom::Start(offset))?;
+                    let mut n = 0;
+                    while n < buf.len() {
+                        match f.read(&mut buf[n..])? {
+                            0 => break,
+                            k => n += k,
+                        }
+                    }
+                    Ok(n)
+                },
+            )
+            .map_err(fs_error)?;
+
+        dest.write_range(0..n, &buf[..n])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        Ok(n as u32)
+    }
+
+    fn write(
+        &mut self,
+        msg: &RecvMessage,
+        handle: u32,
+        offset: u32,
+        source: LenLimit<Leased<R, [u8]>, MAX_TRANSFER>,
+    ) -> Result<(), RequestError<FsError>> {
+        let h = self.handle(msg, handle)?;
+        if !h.flags.contains(OpenFlags::WRITE) {
+            return Err(FsError::ReadOnly.into());
+        }
+        let source = source.into_inner();
+        let data = &mut self.buf[..source.len()];
+        source
+            .read_range(0..data.len(), data)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+
+        let fs = self.fs.as_ref().ok_or(FsError::Unavailable)?;
+        let client = &config::CLIENTS[h.client];
+        let path = FilePath::new(client, Some(&h.name));
+        let path = path.as_path()?;
+
+        // Work out how much the file grows, and whether the client can
+        // afford it, before touching it.
+        let size = file_len(fs, path)?.ok_or(FsError::NotFound)?;
+        let start = if h.flags.contains(OpenFlags::APPEND) {
+            size
+        } else {
+            offset as usize
+        };
+        let growth = (start + data.len()).saturating_sub(size);
+        let used = self.used[h.client] as usize + growth;
+        if used > client.quota as usize {
+            return Err(FsError::QuotaExceeded.into());
+        }
+
+        fs.open_file_with_options_and_then(
+            |o| o.write(true),
+            path,
+            |f| {
+                f.seek(SeekFrom::Start(start as u32))?;
+                let mut n = 0;
+                while n < data.len() {
+                    n += f.write(&data[n..])?;
+                }
+                Ok(())
+            },
+        )
+        .map_err(|e| {
+            let e = fs_error(e);
+            ringbuf_entry!(Trace::WriteFailed(e));
+            e
+        })?;
+
+        self.used[h.client] = used as u32;
+        Ok(())
+    }
+
+    fn rename(
+        &mut self,
+        msg: &RecvMessage,
+        from: LenLimit<Leased<R, [u8]>, MAX_NAME_LEN>,
+        to: LenLimit<Leased<R, [u8]>, MAX_NAME_LEN>,
+    ) -> Result<(), RequestError<FsError>> {
+        let client = Self::client(msg)?;
+        let from = Name::read(from)?;
+        let to = Name::read(to)?;
+
+        self.reap();
+        if self.is_open(client, &from) || self.is_open(client, &to) {
+            return Err(FsError::Busy.into());
+        }
+
+        let fs = self.fs()?;
+        let from_path = FilePath::new(&config::CLIENTS[client], Some(&from));
+        let from_path = from_path.as_path()?;
+        let to_path = FilePath::new(&config::CLIENTS[client], Some(&to));
+        let to_path = to_path.as_path()?;
+        file_len(fs, from_path)?.ok_or(FsError::NotFound)?;
+        if from == to {
+            return Ok(());
+        }
+
+        // Whatever was called `to` goes away, and stops counting against
+        // the quota.
+        let replaced = file_len(fs, to_path)?.unwrap_or(0);
+        fs.rename(from_path, to_path).map_err(fs_error)?;
+        self.release(client, replaced);
+        Ok(())
+    }
+
+    fn remove(
+        &mut self,
+        msg: &RecvMessage,
+        name: LenLimit<Leased<R, [u8]>, MAX_NAME_LEN>,
+    ) -> Result<(), RequestError<FsError>> {
+        let client = Self::client(msg)?;
+        let name = Name::read(name)?;
+
+        self.reap();
+        if self.is_open(client, &name) {
+            return Err(FsError::Busy.into());
+        }
+
+        let fs = self.fs()?;
+        let path = FilePath::new(&config::CLIENTS[client], Some(&name));
+        let path = path.as_path()?;
+        let len = file_len(fs, path)?.ok_or(FsError::NotFound)?;
+        fs.remove(path).map_err(fs_error)?;
+        self.release(client, len);
+        Ok(())
+    }
+
+    fn stat(
+        &mut self,
+        msg: &RecvMessage,
+        name: LenLimit<Leased<R, [u8]>, MAX_NAME_LEN>,
+    ) -> Result<u32, RequestError<FsError>> {
+        let client = Self::client(msg)?;
+        let name = Name::read(name)?;
+
+        let fs = self.fs()?;
+        let path = FilePath::new(&config::CLIENTS[client], Some(&name));
+        let len = file_len(fs, path.as_path()?)?.ok_or(FsError::NotFound)?;
+        Ok(len as u32)
+    }
+
+    fn usage(
+        &mut self,
+        msg: &RecvMessage,
+    ) -> Result<Usage, RequestError<FsError>> {
+        let client = Self::client(msg)?;
+        self.fs()?;
+        Ok(Usage {
+            used: self.used[client],
+            quota: config::CLIENTS[client].quota,
+        })
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        0
+    }
+
+    fn handle_notification(&mut self, _bits: u32) {
+        unreachable!()
+    }
+}
+
+/// Mounts the filesystem, formatting it first if it won't mount and the
+/// config says we may.
+fn mount(
+    alloc: &'static mut Allocation<Storage>,
+    storage: &'static mut Storage,
+) -> Result<Fs, FsError> {
+    if config::FORMAT && !Filesystem::is_mountable(storage) {
+        Filesystem::format(storage).map_err(fs_error)?;
+        ringbuf_entry!(Trace::Formatted);
+    }
+    Filesystem::mount(alloc, storage).map_err(fs_error)
+}
+
+/// Makes sure each client has a directory, and adds up what's in it.
+fn scan(
+    fs: &Fs,
+    used: &mut [u32; config::CLIENT_COUNT],
+) -> Result<(), FsError> {
+    for (i, client) in config::CLIENTS.iter().enumerate() {
+        let dir = FilePath::new(client, None);
+        let dir = dir.as_path()?;
+        match fs.create_dir(dir) {
+            Ok(()) | Err(io::Error::EntryAlreadyExisted) => (),
+            Err(e) => return Err(fs_error(e)),
+        }
+        let total = fs
+            .read_dir_and_then(dir, |entries| {
+                let mut total = 0;
+                for entry in entries {
+                    let m = entry?.metadata();
+                    if m.is_file() {
+                        total += m.len();
+                    }
+                }
+                Ok(total)
+            })
+            .map_err(fs_error)?;
+        used[i] = total as u32;
+        ringbuf_entry!(Trace::Usage {
+            client: i as u8,
+            used: used[i],
+        });
+    }
+    Ok(())
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let ([alloc], [storage]) = mutable_statics::mutable_statics! {
+        static mut ALLOCATION: [Allocation<Storage>; 1] =
+            [Filesystem::allocate; _];
+        static mut STORAGE: [Storage; 1] =
+            [|| BlockStorage::new(Sdmmc::from(DEVICE.get_task_id())); _];
+    };
+
+    let mut used = [0; config::CLIENT_COUNT];
+    let fs = match mount(alloc, storage) {
+        Ok(fs) => match scan(&fs, &mut used) {
+            Ok(()) => {
+                ringbuf_entry!(Trace::Mounted);
+                Some(fs)
+            }
+            Err(e) => {
+                ringbuf_entry!(Trace::MountFailed(e));
+                None
+            }
+        },
+        Err(e) => {
+            ringbuf_entry!(Trace::MountFailed(e));
+            None
+        }
+    };
+
+    let mut server = ServerImpl {
+        fs,
+        handles: [None; config::MAX_HANDLES],
+        next_serial: 0,
+        used,
+        buf: [0; MAX_TRANSFER],
+    };
+
+    let mut buffer = [0; idl::INCOMING_SIZE];
+    loop {
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
+
+mod config {
+    use super::Client;
+
+    include!(concat!(env!("OUT_DIR"), "/fs_config.rs"));
+}
+
+mod idl {
+    use task_fs_api::{FsError, Usage};
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
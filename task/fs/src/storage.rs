@@ -0,0 +1,69 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A run of blocks on a block device, as storage for littlefs.
+//!
+//! littlefs blocks are device blocks, so it reads and programs whole blocks
+//! at a time. Block devices don't need erasing before they're written, so
+//! erase does nothing. The device does its own wear leveling (SD cards, at
+//! least), so littlefs's is turned off.
+
+use crate::config;
+use block_device::{Block, BlockDevice, BLOCK_SIZE};
+use littlefs2::consts::{U512, U8};
+use littlefs2::driver::Storage;
+use littlefs2::io::{Error, Result};
+
+pub struct BlockStorage<D> {
+    dev: D,
+}
+
+impl<D: BlockDevice> BlockStorage<D> {
+    pub fn new(dev: D) -> Self {
+        Self { dev }
+    }
+
+    /// Works out the device block for the `i`th block of a transfer at byte
+    /// offset `off` into the filesystem.
+    fn block(off: usize, i: usize) -> u32 {
+        config::START_BLOCK + (off / BLOCK_SIZE + i) as u32
+    }
+}
+
+impl<D: BlockDevice> Storage for BlockStorage<D> {
+    const READ_SIZE: usize = BLOCK_SIZE;
+    const WRITE_SIZE: usize = BLOCK_SIZE;
+    const BLOCK_SIZE: usize = BLOCK_SIZE;
+    const BLOCK_COUNT: usize = config::BLOCKS;
+    const BLOCK_CYCLES: isize = -1;
+
+    type CACHE_SIZE = U512;
+    /// In units of 64 blocks.
+    type LOOKAHEAD_SIZE = U8;
+
+    fn read(&mut self, off: usize, buf: &mut [u8]) -> Result<usize> {
+        for (i, chunk) in buf.chunks_exact_mut(BLOCK_SIZE).enumerate() {
+            let block: &mut Block =
+                chunk.try_into().map_err(|_| Error::Invalid)?;
+            self.dev
+                .read(Self::block(off, i), core::slice::from_mut(block))
+                .map_err(|_| Error::Io)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn write(&mut self, off: usize, data: &[u8]) -> Result<usize> {
+        for (i, chunk) in data.chunks_exact(BLOCK_SIZE).enumerate() {
+            let block: &Block = chunk.try_into().map_err(|_| Error::Invalid)?;
+            self.dev
+                .write(Self::block(off, i), core::slice::from_ref(block))
+                .map_err(|_| Error::Io)?;
+        }
+        Ok(data.len())
+    }
+
+    fn erase(&mut self, _off: usize, len: usize) -> Result<usize> {
+        Ok(len)
+    }
+}
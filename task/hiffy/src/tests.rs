@@ -32,7 +32,9 @@ pub(crate) fn run_a_test(
         }
     };
 
-    userlib::kipc::restart_task(TEST_TASK.get_task_index().into(), true);
+    if !userlib::kipc::restart_task(TEST_TASK.get_task_index().into(), true) {
+        return Err(Failure::FunctionError(userlib::RESTART_HELD));
+    }
 
     ringbuf_entry!(Trace::RunTest(id));
     let (rc, _len) = sys_send(
@@ -23,9 +23,13 @@ fn main() -> ! {
             // So, do not get clever and remove this.
             cortex_m::asm::nop();
         } else {
-            // Wait For Interrupt to pause the processor until an ISR arrives,
-            // which could wake some higher-priority task.
-            cortex_m::asm::wfi();
+            // Give the kernel a chance to put the system into a deeper sleep,
+            // if the board has told it how. Otherwise, Wait For Interrupt to
+            // pause the processor until an ISR arrives, which could wake some
+            // higher-priority task.
+            if !userlib::kipc::idle() {
+                cortex_m::asm::wfi();
+            }
         }
     }
 }
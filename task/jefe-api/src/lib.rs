@@ -8,8 +8,10 @@
 
 use derive_idol_err::IdolError;
 pub use dump_agent_api::DumpAgentError;
+use hubpack::SerializedSize;
 use serde::{Deserialize, Serialize};
 use userlib::*;
+use zerocopy::{AsBytes, FromBytes};
 
 /// Platform-agnostic (but heavily influenced) reset status bits.
 #[derive(
@@ -38,6 +40,107 @@ pub enum DumpAreaError {
     AlreadyInUse,
 }
 
+/// What the supervisor does when a task faults.
+#[derive(
+    Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize,
+)]
+pub enum Disposition {
+    /// Restart the task right away.
+    #[default]
+    Restart,
+    /// Leave the task faulted, so that it can be inspected.
+    Hold,
+}
+
+/// A task's state as seen by the supervisor, returned by
+/// `Jefe::get_task_status`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TaskControlStatus {
+    /// The task's current state, as reported by the kernel.
+    pub state: TaskState,
+    /// What the supervisor will do the next time the task faults.
+    pub disposition: Disposition,
+    /// Whether the supervisor is holding the task at a fault.
+    pub holding_fault: bool,
+    /// Number of faults the supervisor has handled for this task since boot.
+    pub fault_count: u32,
+    /// The most recent of those faults. Unlike the fault in `state`, this
+    /// survives the task being restarted.
+    pub last_fault: Option<FaultInfo>,
+}
+
+/// Errors from the task control operations.
+#[derive(
+    Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError, counters::Count,
+)]
+#[repr(C)]
+pub enum TaskControlError {
+    /// The operation can't be applied to the supervisor itself.
+    IllegalTask = 1,
+    /// No task has the given index.
+    BadTask,
+    /// The task can't itself read the memory asked for, as normal memory.
+    Inaccessible,
+    /// The kernel's restart throttle is holding the task down after it faulted
+    /// too often. The supervisor will restart it when the hold expires.
+    RestartHeld,
+}
+
+/// An entry in the crash journal, as stored in flash and returned by
+/// `Jefe::read_crash_journal`.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Eq,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    SerializedSize,
+    AsBytes,
+    FromBytes,
+)]
+#[repr(C)]
+pub struct CrashRecord {
+    /// Kernel time of the fault, in ticks since boot.
+    pub timestamp: u64,
+    /// Sequence number of this entry. These increase by one with each entry,
+    /// across reboots.
+    pub seq: u32,
+    /// Hash of the task's registers at the time of the fault; see
+    /// `kipc::read_register_hash`.
+    pub regs_hash: u32,
+    /// Detail of the fault; see `cause`. For a panic, this is the line number
+    /// from the task's panic record, or 0 if it didn't leave one.
+    pub fault_arg: u32,
+    /// Index of the task that faulted.
+    pub task: u16,
+    /// Generation of the task that faulted.
+    pub generation: u8,
+    /// Kind of fault, as the position of its variant in `FaultInfo`, counting
+    /// from 1.
+    pub cause: u8,
+    /// Unused; written as 0.
+    pub reserved: u32,
+    /// Check value, used to tell a valid entry from erased or torn flash.
+    pub check: u32,
+}
+
+/// Errors from reading the crash journal.
+#[derive(
+    Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError, counters::Count,
+)]
+#[repr(C)]
+pub enum CrashJournalError {
+    /// This image doesn't keep a crash journal.
+    Unsupported = 1,
+    /// The journal doesn't have that many entries.
+    NoSuchEntry,
+    /// The flash holding the journal couldn't be read.
+    FlashError,
+}
+
 impl Jefe {
     /// Asks the supervisor to restart the current task without recording a
     /// fault.
@@ -62,8 +62,59 @@ fn main() -> Result<()> {
         writeln!(out, "];")?;
     }
 
+    {
+        let cascades = restart_cascades(&cfg.dependencies)?;
+        let count = cascades.len();
+        let action = "crate::cascade::Action";
+        writeln!(
+            out,
+            "pub(crate) const CASCADES: [({task}, &[({task}, {action})]); \
+             {count}] = [",
+        )?;
+        for (root, steps) in cascades {
+            writeln!(out, "    ({task}::{root}, &[")?;
+            for (name, notify) in steps {
+                match notify {
+                    None => writeln!(
+                        out,
+                        "        ({task}::{name}, {action}::Restart),"
+                    )?,
+                    Some(n) => writeln!(
+                        out,
+                        "        ({task}::{name}, {action}::Notify(\
+                         crate::notifications::{name}::{}_MASK)),",
+                        n.to_ascii_uppercase().replace('-', "_"),
+                    )?,
+                }
+            }
+            writeln!(out, "    ]),")?;
+        }
+        writeln!(out, "];")?;
+    }
+
     #[cfg(feature = "dump")]
     output_dump_areas(&mut out)?;
+
+    #[cfg(feature = "crash-journal")]
+    {
+        let journal = cfg.crash_journal.context(
+            "jefe is configured with a crash journal, but has no \
+             `crash-journal` section in its config",
+        )?;
+        if journal.sectors < 2 {
+            anyhow::bail!("jefe's crash journal needs at least 2 sectors");
+        }
+        writeln!(
+            out,
+            "pub(crate) const CRASH_JOURNAL_SLOT: u32 = {};",
+            journal.slot
+        )?;
+        writeln!(
+            out,
+            "pub(crate) const CRASH_JOURNAL_SECTORS: u32 = {};",
+            journal.sectors
+        )?;
+    }
     Ok(())
 }
 
@@ -82,6 +133,113 @@ struct Config {
     /// failure, unless overridden at runtime through Humility.
     #[serde(default)]
     tasks_to_hold: BTreeSet<String>,
+    /// Map from task name to the tasks it depends on, which it should be
+    /// restarted or notified along with.
+    #[serde(default)]
+    dependencies: BTreeMap<String, Dependency>,
+    /// Where to keep the crash journal, if the `crash-journal` feature is on.
+    #[allow(dead_code)]
+    crash_journal: Option<CrashJournalConfig>,
+}
+
+/// What a task depends on, and what to do to it when one of those restarts.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Dependency {
+    /// Names of the tasks this one depends on.
+    on: Vec<String>,
+    /// Notification (in this task) to post when one of them restarts. If this
+    /// is missing, this task is restarted instead -- and then, in turn, so are
+    /// its own dependents.
+    #[serde(default)]
+    notify: Option<String>,
+}
+
+/// Works out, for each task that has dependents, what has to happen when it
+/// restarts: a list of its direct and indirect dependents, each with the
+/// notification to post to it, or `None` to restart it.
+///
+/// Restarts are ordered so that every task comes after all of the tasks it
+/// depends on, and notifications come after all of the restarts, so that no
+/// task is told to go looking for a server that's about to be restarted
+/// underneath it.
+fn restart_cascades(
+    deps: &BTreeMap<String, Dependency>,
+) -> Result<Vec<(String, Vec<(String, Option<String>)>)>> {
+    let mut dependents: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for (name, dep) in deps {
+        for on in &dep.on {
+            if on == name {
+                anyhow::bail!("jefe: task {name} depends on itself");
+            }
+            dependents
+                .entry(on.as_str())
+                .or_default()
+                .insert(name.as_str());
+        }
+    }
+    let restarts =
+        |name: &str| deps.get(name).map_or(true, |d| d.notify.is_none());
+
+    let mut cascades = vec![];
+    for &root in dependents.keys() {
+        // Find everything this reaches: dependents that get restarted pass the
+        // restart on, and dependents that get notified don't.
+        let mut restarted = BTreeSet::new();
+        let mut notified = BTreeSet::new();
+        let mut stack = vec![root];
+        while let Some(t) = stack.pop() {
+            for &d in dependents.get(t).into_iter().flatten() {
+                if d == root && restarts(d) {
+                    anyhow::bail!(
+                        "jefe: task {root} depends, through its dependents, \
+                         on itself"
+                    );
+                }
+                if !restarts(d) {
+                    notified.insert(d);
+                } else if restarted.insert(d) {
+                    stack.push(d);
+                }
+            }
+        }
+
+        // Order the restarts so that each task follows its dependencies, by
+        // repeatedly taking the tasks that don't depend on anything left.
+        let mut steps = vec![];
+        let mut left = restarted;
+        while !left.is_empty() {
+            let ready: Vec<&str> = left
+                .iter()
+                .copied()
+                .filter(|t| {
+                    deps[*t].on.iter().all(|o| !left.contains(o.as_str()))
+                })
+                .collect();
+            if ready.is_empty() {
+                anyhow::bail!("jefe: dependencies among {left:?} form a cycle");
+            }
+            for t in ready {
+                left.remove(t);
+                steps.push((t.to_string(), None));
+            }
+        }
+        for t in notified {
+            steps.push((t.to_string(), deps[t].notify.clone()));
+        }
+        cascades.push((root.to_string(), steps));
+    }
+    Ok(cascades)
+}
+
+/// Location of the crash journal: the first `sectors` sectors of auxiliary
+/// flash slot `slot`.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+#[allow(dead_code)]
+struct CrashJournalConfig {
+    slot: u32,
+    sectors: u32,
 }
 
 #[cfg(feature = "dump")]
@@ -0,0 +1,109 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Restart cascades.
+//!
+//! An app can tell the supervisor which tasks depend on which others, in the
+//! `dependencies` section of its config:
+//!
+//! ```toml
+//! [tasks.jefe.config.dependencies]
+//! net = { on = ["eth"] }
+//! udpecho = { on = ["net"], notify = "net-restarted" }
+//! ```
+//!
+//! Whenever the supervisor restarts a task, for whatever reason, it then
+//! restarts the task's dependents, and their dependents in turn, so that they
+//! start over against the fresh server instead of limping along with stale
+//! task IDs. A dependent with a `notify` entry is sent that notification
+//! instead, and is left to recover by itself. The build script works out the
+//! order ahead of time; see `restart_cascades` there.
+//!
+//! A dependent that's being held at a fault is left alone, so that it can
+//! still be inspected; its own dependents are restarted regardless. So is one
+//! that the kernel's restart throttle won't let us restart yet.
+//!
+//! Each step is counted in `CASCADE_EVENTS`.
+
+use crate::generated::CASCADES;
+use crate::TaskStatus;
+use userlib::{kipc, Generation, TaskId};
+
+/// What to do to a dependent when a task it depends on restarts.
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum Action {
+    Restart,
+    /// Post these notification bits to it.
+    Notify(u32),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, counters::Count)]
+enum Event {
+    /// A restarted task had dependents to deal with.
+    Cascade,
+    /// A dependent was restarted.
+    Restarted,
+    /// A dependent was notified.
+    Notified,
+    /// A dependent was held at a fault, and so wasn't restarted.
+    Held,
+    /// A task couldn't be restarted because the kernel's restart throttle is
+    /// holding it down.
+    Throttled,
+}
+
+counters::counters!(CASCADE_EVENTS, Event);
+
+/// Restarts task `index`, then deals with its dependents.
+///
+/// Every restart done by the supervisor should go through here.
+///
+/// Returns `false`, doing nothing, if the kernel's restart throttle is holding
+/// the task down. It stays faulted, and the kernel posts our `restart-released`
+/// notification when the hold expires.
+pub(crate) fn restart(states: &mut [TaskStatus], index: usize) -> bool {
+    if !restart_one(states, index) {
+        return false;
+    }
+
+    let Some((_, steps)) = CASCADES.iter().find(|(t, _)| *t as usize == index)
+    else {
+        return true;
+    };
+    counters::count!(CASCADE_EVENTS, Event::Cascade);
+
+    for &(task, action) in *steps {
+        let i = task as usize;
+        match action {
+            Action::Restart => {
+                if states[i].holding_fault {
+                    counters::count!(CASCADE_EVENTS, Event::Held);
+                    continue;
+                }
+                if restart_one(states, i) {
+                    counters::count!(CASCADE_EVENTS, Event::Restarted);
+                }
+            }
+            Action::Notify(mask) => {
+                let id = userlib::sys_refresh_task_id(
+                    TaskId::for_index_and_gen(i, Generation::ZERO),
+                );
+                userlib::sys_post(id, mask);
+                counters::count!(CASCADE_EVENTS, Event::Notified);
+            }
+        }
+    }
+    true
+}
+
+/// Restarts task `index` alone, noting whether the kernel's restart throttle
+/// stopped us.
+fn restart_one(states: &mut [TaskStatus], index: usize) -> bool {
+    let restarted = kipc::restart_task(index, true);
+    if !restarted {
+        counters::count!(CASCADE_EVENTS, Event::Throttled);
+    }
+    states[index].restart_held = !restarted;
+    restarted
+}
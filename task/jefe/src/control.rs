@@ -0,0 +1,91 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Task control operations.
+//!
+//! These are the operations behind both the task control part of the Idol
+//! interface and the external (debugger) interface in `external`, so that a
+//! unit in the field can be handled with the same controls as one on the
+//! bench, whichever way its operator happens to reach it.
+
+use crate::{cascade, TaskStatus};
+use task_jefe_api::{Disposition, TaskControlError, TaskControlStatus};
+use userlib::kipc;
+
+/// Looks up the status of task `index`, refusing the supervisor itself.
+fn task_mut(
+    states: &mut [TaskStatus],
+    index: usize,
+) -> Result<&mut TaskStatus, TaskControlError> {
+    if index == 0 {
+        return Err(TaskControlError::IllegalTask);
+    }
+    states.get_mut(index).ok_or(TaskControlError::BadTask)
+}
+
+/// Reports the supervisor's view of task `index`.
+pub(crate) fn status(
+    states: &mut [TaskStatus],
+    index: usize,
+) -> Result<TaskControlStatus, TaskControlError> {
+    let status = task_mut(states, index)?;
+    Ok(TaskControlStatus {
+        state: kipc::read_task_status(index),
+        disposition: status.disposition,
+        holding_fault: status.holding_fault,
+        fault_count: status.fault_count,
+        last_fault: status.last_fault,
+    })
+}
+
+/// Sets what happens when task `index` next faults.
+///
+/// This is just a bookkeeping change, except when releasing a task that's
+/// currently held at a fault: that has to restart it too.
+pub(crate) fn set_disposition(
+    states: &mut [TaskStatus],
+    index: usize,
+    disposition: Disposition,
+) -> Result<(), TaskControlError> {
+    let status = task_mut(states, index)?;
+    status.disposition = disposition;
+    if disposition == Disposition::Restart && status.holding_fault {
+        status.holding_fault = false;
+        cascade::restart(states, index);
+    }
+    Ok(())
+}
+
+/// Restarts task `index`, or starts it if it isn't running.
+///
+/// This doesn't change the task's disposition, so it's useful for starting a
+/// task while still catching it on its _next_ fault.
+pub(crate) fn restart(
+    states: &mut [TaskStatus],
+    index: usize,
+) -> Result<(), TaskControlError> {
+    let status = task_mut(states, index)?;
+    status.holding_fault = false;
+    if cascade::restart(states, index) {
+        Ok(())
+    } else {
+        Err(TaskControlError::RestartHeld)
+    }
+}
+
+/// Copies memory of task `index`, starting at `address`, into `buf`, and
+/// returns how much was copied; see `kipc::read_task_memory` for the limits.
+///
+/// This is how tasks other than the supervisor get at the counters and
+/// ringbufs of a task that's held at a fault, before it's restarted.
+pub(crate) fn read_memory(
+    states: &mut [TaskStatus],
+    index: usize,
+    address: u32,
+    buf: &mut [u8],
+) -> Result<usize, TaskControlError> {
+    task_mut(states, index)?;
+    kipc::read_task_memory(index, address, buf)
+        .ok_or(TaskControlError::Inaccessible)
+}
@@ -42,16 +42,23 @@
 //! additional warning, surely fated to become half sunk in the lone and level
 //! sands...
 //!
+//! The requests themselves are the same task control operations that Jefe
+//! offers over Idol (see `control`), so a unit in the field can be handled
+//! like one on the bench. `Status` leaves its answer in
+//! `JEFE_EXTERNAL_RESPONSE`, as the ssmarshal encoding of a
+//! `TaskControlStatus`, the same as `Jefe::get_task_status` would return.
+//!
 
-use crate::{Disposition, TaskStatus};
+use crate::{control, TaskStatus};
 use core::sync::atomic::{AtomicU32, Ordering};
+use task_jefe_api::{Disposition, TaskControlError, TaskControlStatus};
 
 // This trait may not be needed, if compiling for a non-armv6m target.
 #[allow(unused_imports)]
 use armv6m_atomic_hack::AtomicU32Ext;
 
 use ringbuf::{ringbuf, ringbuf_entry};
-use userlib::{kipc, FromPrimitive};
+use userlib::{kipc, FromPrimitive, UnwrapLite};
 
 /// The actual requests that we honor from an external source entity
 #[derive(FromPrimitive, Copy, Clone, Debug, Eq, PartialEq)]
@@ -61,6 +68,7 @@ enum Request {
     Hold = 2,
     Release = 3,
     Fault = 4,
+    Status = 5,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -68,6 +76,8 @@ enum Error {
     IllegalTask,
     BadTask,
     BadRequest,
+    Inaccessible,
+    RestartHeld,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -96,6 +106,28 @@ static JEFE_EXTERNAL_REQUESTS: AtomicU32 = AtomicU32::new(0);
 #[no_mangle]
 static JEFE_EXTERNAL_ERRORS: AtomicU32 = AtomicU32::new(0);
 
+/// Number of words needed to hold an encoded `TaskControlStatus`.
+const RESPONSE_WORDS: usize =
+    core::mem::size_of::<TaskControlStatus>().div_ceil(4);
+
+#[no_mangle]
+static JEFE_EXTERNAL_RESPONSE: [AtomicU32; RESPONSE_WORDS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU32 = AtomicU32::new(0);
+    [ZERO; RESPONSE_WORDS]
+};
+
+impl From<TaskControlError> for Error {
+    fn from(e: TaskControlError) -> Self {
+        match e {
+            TaskControlError::IllegalTask => Error::IllegalTask,
+            TaskControlError::BadTask => Error::BadTask,
+            TaskControlError::Inaccessible => Error::Inaccessible,
+            TaskControlError::RestartHeld => Error::RestartHeld,
+        }
+    }
+}
+
 ///
 /// Checks for any external requests for change in task disposition,
 /// potentially modifying the passed array.  Returns a boolean to indicate if
@@ -129,13 +161,15 @@ fn check_inner(states: &mut [TaskStatus]) -> Result<bool, Error> {
     let request = Request::from_u32(val).ok_or(Error::BadRequest)?;
     let ndx = JEFE_EXTERNAL_TASKINDEX.load(Ordering::SeqCst) as usize;
 
-    // Do not allow requests to alter the supervisor (us).
+    // Do not allow requests to alter the supervisor (us), and ensure the task
+    // index is in range. (The control operations check this too, but we want
+    // to refuse even `None`.)
     if ndx == 0 {
         return Err(Error::IllegalTask);
     }
-
-    // Ensure the task index is in range.
-    let state = states.get_mut(ndx).ok_or(Error::BadTask)?;
+    if ndx >= states.len() {
+        return Err(Error::BadTask);
+    }
 
     let task = TaskIndex(ndx as u16);
     ringbuf_entry!(Trace::Request(request, task));
@@ -146,7 +180,7 @@ fn check_inner(states: &mut [TaskStatus]) -> Result<bool, Error> {
         Request::Hold => {
             // This is just a bookkeeping state update, we do not interrupt or
             // fault the task in response to this one.
-            state.disposition = Disposition::Hold;
+            control::set_disposition(states, ndx, Disposition::Hold)?;
         }
 
         Request::Start => {
@@ -161,31 +195,41 @@ fn check_inner(states: &mut [TaskStatus]) -> Result<bool, Error> {
             // Note that this command does _not_ clear task holds! For that, you
             // must issue Release, below. This means it's useful for starting
             // the task but still catching it on the _next_ fault.
-            kipc::restart_task(ndx, true);
+            control::restart(states, ndx)?;
         }
 
         Request::Release => {
             // This reverses the effect of Hold. Note that this has to reverse
             // not only the disposition change, but may also have to restart the
             // task to clear a held fault.
-            state.disposition = Disposition::Restart;
-            if state.holding_fault {
-                state.holding_fault = false;
-                kipc::restart_task(ndx, true);
-            }
+            control::set_disposition(states, ndx, Disposition::Restart)?;
         }
 
         Request::Fault => {
             // Indicate that the task has faulted on purpose:
-            state.disposition = Disposition::Hold;
+            control::set_disposition(states, ndx, Disposition::Hold)?;
             // And make its day substantially worse. This will cause us
             // to be notified, and the fault will be processed and
             // logged on the next iteration through the server loop.
             kipc::fault_task(ndx);
         }
+
+        Request::Status => {
+            let status = control::status(states, ndx)?;
+            let mut buf = [0u8; RESPONSE_WORDS * 4];
+            // The buffer is sized for the type, which is always enough for
+            // its encoding.
+            ssmarshal::serialize(&mut buf, &status).unwrap_lite();
+            for (word, chunk) in
+                JEFE_EXTERNAL_RESPONSE.iter().zip(buf.chunks_exact(4))
+            {
+                let bytes = [chunk[0], chunk[1], chunk[2], chunk[3]];
+                word.store(u32::from_le_bytes(bytes), Ordering::SeqCst);
+            }
+        }
     }
 
-    ringbuf_entry!(Trace::Disposition(task, state.disposition));
+    ringbuf_entry!(Trace::Disposition(task, states[ndx].disposition));
     Ok(true)
 }
 
@@ -0,0 +1,277 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Persistent crash journal.
+//!
+//! When a task faults, we record a compact `CrashRecord` for it in a ring of
+//! flash sectors at the start of an auxiliary flash slot, so that the history
+//! of faults survives a reboot and can be read back (with
+//! `Jefe::read_crash_journal`) for failure analysis in the field.
+//!
+//! Records are written in order around the ring, one page at a time, and each
+//! sector is erased just before the first record goes into it. This spreads
+//! the wear evenly over the journal's sectors, at the cost of losing the oldest
+//! sector's worth of records each time we wrap. Each record carries a sequence
+//! number, so at boot we can find where we left off by scanning for the
+//! highest one.
+//!
+//! Writing flash means sending to the auxflash server, which the supervisor
+//! would rather not do from its fault path. So faults are queued in RAM when
+//! they're handled, and written out from the timer path. If the queue fills
+//! up before it can be drained, further records are dropped and counted in
+//! `JEFE_CRASH_JOURNAL_DROPPED`.
+
+use crate::generated::{CRASH_JOURNAL_SECTORS, CRASH_JOURNAL_SLOT};
+use core::sync::atomic::{AtomicU32, Ordering};
+use drv_auxflash_api::{AuxFlash, PAGE_SIZE_BYTES, SECTOR_SIZE_BYTES};
+use task_jefe_api::{CrashJournalError, CrashRecord};
+use userlib::{kipc, task_slot, FaultInfo, Generation, TaskId};
+use zerocopy::{AsBytes, FromBytes};
+
+// This trait may not be needed, if compiling for a non-armv6m target.
+#[allow(unused_imports)]
+use armv6m_atomic_hack::AtomicU32Ext;
+
+task_slot!(AUXFLASH, auxflash);
+
+const RECORD_SIZE: usize = core::mem::size_of::<CrashRecord>();
+const RECORDS_PER_PAGE: usize = PAGE_SIZE_BYTES / RECORD_SIZE;
+const RECORDS_PER_SECTOR: usize = SECTOR_SIZE_BYTES / RECORD_SIZE;
+const CAPACITY: usize = CRASH_JOURNAL_SECTORS as usize * RECORDS_PER_SECTOR;
+
+/// Number of records we can hold in RAM waiting to be written.
+const QUEUE_DEPTH: usize = 4;
+
+/// Mixed into each record's check value, so that a page of zeros doesn't
+/// look valid.
+const CHECK_MAGIC: u32 = 0x4A45_4645;
+
+const _: () = assert!(PAGE_SIZE_BYTES % RECORD_SIZE == 0);
+// With a single sector, wrapping around would erase the whole journal.
+const _: () = assert!(CRASH_JOURNAL_SECTORS >= 2);
+
+#[no_mangle]
+static JEFE_CRASH_JOURNAL_DROPPED: AtomicU32 = AtomicU32::new(0);
+
+pub(crate) struct Journal {
+    flash: AuxFlash,
+    /// Where we are in the ring, once we've found out.
+    position: Option<Position>,
+    queue: [CrashRecord; QUEUE_DEPTH],
+    queued: usize,
+}
+
+#[derive(Copy, Clone)]
+struct Position {
+    /// Index of the next record slot to write.
+    next: usize,
+    /// Sequence number of the most recent record, or 0 if there are none.
+    last_seq: u32,
+}
+
+impl Journal {
+    pub(crate) fn new() -> Self {
+        Self {
+            flash: AuxFlash::from(AUXFLASH.get_task_id()),
+            position: None,
+            queue: [CrashRecord::default(); QUEUE_DEPTH],
+            queued: 0,
+        }
+    }
+
+    /// Queues a record of the fault `fault` taken by task `index`.
+    pub(crate) fn record(&mut self, index: usize, fault: &FaultInfo) {
+        if self.queued == QUEUE_DEPTH {
+            JEFE_CRASH_JOURNAL_DROPPED.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let (cause, mut fault_arg) = fault_code(fault);
+        if let FaultInfo::Panic = fault {
+            // Which panic is best told by where it was.
+            fault_arg = kipc::read_panic_record(index).map_or(0, |r| r.line);
+        }
+        let id = userlib::sys_refresh_task_id(TaskId::for_index_and_gen(
+            index,
+            Generation::ZERO,
+        ));
+        self.queue[self.queued] = CrashRecord {
+            timestamp: userlib::sys_get_timer().now,
+            seq: 0, // assigned when written
+            regs_hash: kipc::read_register_hash(index),
+            fault_arg,
+            task: index as u16,
+            generation: id.generation().into(),
+            cause,
+            reserved: 0,
+            check: 0,
+        };
+        self.queued += 1;
+    }
+
+    /// Writes any queued records to flash.
+    pub(crate) fn flush(&mut self) {
+        if self.queued == 0 {
+            return;
+        }
+        let Ok(mut pos) = self.position() else {
+            return;
+        };
+        for i in 0..self.queued {
+            let mut rec = self.queue[i];
+            rec.seq = pos.last_seq.wrapping_add(1);
+            rec.check = check_value(&rec);
+            if self.write(pos.next, &rec).is_err() {
+                // Keep the rest queued, and try again next time.
+                self.queue.copy_within(i..self.queued, 0);
+                self.queued -= i;
+                self.position = Some(pos);
+                return;
+            }
+            pos = Position {
+                next: (pos.next + 1) % CAPACITY,
+                last_seq: rec.seq,
+            };
+        }
+        self.queued = 0;
+        self.position = Some(pos);
+    }
+
+    /// Reads the record `back` entries before the most recent one.
+    pub(crate) fn read(
+        &mut self,
+        back: u32,
+    ) -> Result<CrashRecord, CrashJournalError> {
+        let pos = self.position()?;
+        let back = back as usize;
+        if back >= CAPACITY || back >= pos.last_seq as usize {
+            return Err(CrashJournalError::NoSuchEntry);
+        }
+        let index = (pos.next + CAPACITY - 1 - back) % CAPACITY;
+        let rec = self.read_record(index)?;
+        // Records in the sector we're about to overwrite next are gone once
+        // it's erased; the sequence number tells us whether this is still the
+        // record we're looking for.
+        if !is_valid(&rec) || rec.seq != pos.last_seq - back as u32 {
+            return Err(CrashJournalError::NoSuchEntry);
+        }
+        Ok(rec)
+    }
+
+    /// Returns our position in the ring, scanning the flash to find it if we
+    /// haven't already.
+    fn position(&mut self) -> Result<Position, CrashJournalError> {
+        if let Some(pos) = self.position {
+            return Ok(pos);
+        }
+        let mut pos = Position {
+            next: 0,
+            last_seq: 0,
+        };
+        let mut page = [0u8; PAGE_SIZE_BYTES];
+        for p in 0..CAPACITY / RECORDS_PER_PAGE {
+            self.flash
+                .read_slot_with_offset(
+                    CRASH_JOURNAL_SLOT,
+                    (p * PAGE_SIZE_BYTES) as u32,
+                    &mut page,
+                )
+                .map_err(|_| CrashJournalError::FlashError)?;
+            for (i, chunk) in page.chunks_exact(RECORD_SIZE).enumerate() {
+                let Some(rec) = CrashRecord::read_from(chunk) else {
+                    continue;
+                };
+                if is_valid(&rec) && rec.seq > pos.last_seq {
+                    pos = Position {
+                        next: (p * RECORDS_PER_PAGE + i + 1) % CAPACITY,
+                        last_seq: rec.seq,
+                    };
+                }
+            }
+        }
+        self.position = Some(pos);
+        Ok(pos)
+    }
+
+    fn read_record(
+        &mut self,
+        index: usize,
+    ) -> Result<CrashRecord, CrashJournalError> {
+        let mut page = [0u8; PAGE_SIZE_BYTES];
+        let page_offset = index / RECORDS_PER_PAGE * PAGE_SIZE_BYTES;
+        self.flash
+            .read_slot_with_offset(
+                CRASH_JOURNAL_SLOT,
+                page_offset as u32,
+                &mut page,
+            )
+            .map_err(|_| CrashJournalError::FlashError)?;
+        let start = index % RECORDS_PER_PAGE * RECORD_SIZE;
+        CrashRecord::read_from(&page[start..start + RECORD_SIZE])
+            .ok_or(CrashJournalError::FlashError)
+    }
+
+    /// Writes `rec` into record slot `index`, erasing its sector first if it's
+    /// the first in the sector.
+    ///
+    /// Pages are written whole, so we read back the page first. Rewriting the
+    /// records already in it doesn't change them, since programming can only
+    /// clear bits, and they're the same bits.
+    fn write(
+        &mut self,
+        index: usize,
+        rec: &CrashRecord,
+    ) -> Result<(), CrashJournalError> {
+        if index % RECORDS_PER_SECTOR == 0 {
+            let sector_offset = index / RECORDS_PER_SECTOR * SECTOR_SIZE_BYTES;
+            self.flash
+                .slot_sector_erase(CRASH_JOURNAL_SLOT, sector_offset as u32)
+                .map_err(|_| CrashJournalError::FlashError)?;
+        }
+
+        let mut page = [0u8; PAGE_SIZE_BYTES];
+        let page_offset = (index / RECORDS_PER_PAGE * PAGE_SIZE_BYTES) as u32;
+        self.flash
+            .read_slot_with_offset(CRASH_JOURNAL_SLOT, page_offset, &mut page)
+            .map_err(|_| CrashJournalError::FlashError)?;
+        let start = index % RECORDS_PER_PAGE * RECORD_SIZE;
+        page[start..start + RECORD_SIZE].copy_from_slice(rec.as_bytes());
+        self.flash
+            .write_slot_with_offset(CRASH_JOURNAL_SLOT, page_offset, &page)
+            .map_err(|_| CrashJournalError::FlashError)
+    }
+}
+
+/// Computes the check value for `rec`: a 32-bit FNV-1a hash of everything
+/// before the `check` field, mixed with `CHECK_MAGIC`.
+fn check_value(rec: &CrashRecord) -> u32 {
+    let bytes = &rec.as_bytes()[..RECORD_SIZE - 4];
+    let hash = bytes.iter().fold(0x811c_9dc5_u32, |h, &b| {
+        (h ^ u32::from(b)).wrapping_mul(0x0100_0193)
+    });
+    hash ^ CHECK_MAGIC
+}
+
+fn is_valid(rec: &CrashRecord) -> bool {
+    rec.seq != 0 && rec.check == check_value(rec)
+}
+
+/// Boils a fault down to the `cause` and `fault_arg` fields of a record.
+fn fault_code(fault: &FaultInfo) -> (u8, u32) {
+    match *fault {
+        FaultInfo::MemoryAccess { address, .. } => (1, address.unwrap_or(0)),
+        FaultInfo::StackOverflow { address } => (2, address),
+        FaultInfo::BusError { address, .. } => (3, address.unwrap_or(0)),
+        FaultInfo::DivideByZero => (4, 0),
+        FaultInfo::IllegalText => (5, 0),
+        FaultInfo::IllegalInstruction => (6, 0),
+        FaultInfo::InvalidOperation(code) => (7, code),
+        FaultInfo::SyscallUsage(e) => (8, e as u32),
+        FaultInfo::Panic => (9, 0),
+        FaultInfo::Injected(TaskId(id)) => (10, u32::from(id)),
+        FaultInfo::FromServer(TaskId(id), reason, _) => {
+            (11, u32::from(id) | (reason as u32) << 16)
+        }
+        FaultInfo::MemoryFault(d) => (12, d.address.unwrap_or(0)),
+    }
+}
@@ -30,22 +30,22 @@
 #[cfg(feature = "dump")]
 mod dump;
 
+mod cascade;
+mod control;
 mod external;
+#[cfg(feature = "crash-journal")]
+mod journal;
 
 use core::convert::Infallible;
 
 use hubris_num_tasks::NUM_TASKS;
 use humpty::DumpArea;
-use idol_runtime::RequestError;
-use task_jefe_api::{DumpAgentError, ResetReason};
-use userlib::{kipc, Generation, TaskId};
-
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Default)]
-pub enum Disposition {
-    #[default]
-    Restart,
-    Hold,
-}
+use idol_runtime::{ClientError, Leased, RequestError, W};
+use task_jefe_api::{
+    CrashJournalError, CrashRecord, Disposition, DumpAgentError, ResetReason,
+    TaskControlError, TaskControlStatus,
+};
+use userlib::{kipc, FaultInfo, Generation, TaskId, TaskState};
 
 // We install a timeout to periodically check for an external direction
 // of our task disposition (e.g., via Humility).  This timeout should
@@ -73,6 +73,8 @@ fn main() -> ! {
         reset_reason: ResetReason::Unknown,
         #[cfg(feature = "dump")]
         dump_areas: dump::initialize_dump_areas(),
+        #[cfg(feature = "crash-journal")]
+        journal: journal::Journal::new(),
     };
     let mut buf = [0u8; idl::INCOMING_SIZE];
 
@@ -88,6 +90,8 @@ struct ServerImpl<'s> {
     reset_reason: ResetReason,
     #[cfg(feature = "dump")]
     dump_areas: u32,
+    #[cfg(feature = "crash-journal")]
+    journal: journal::Journal,
 }
 
 impl idl::InOrderJefeImpl for ServerImpl<'_> {
@@ -141,11 +145,82 @@ impl idl::InOrderJefeImpl for ServerImpl<'_> {
         Ok(())
     }
 
+    fn get_task_status(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        task_index: u32,
+    ) -> Result<TaskControlStatus, RequestError<TaskControlError>> {
+        control::status(self.task_states, task_index as usize)
+            .map_err(RequestError::from)
+    }
+
+    fn set_task_disposition(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        task_index: u32,
+        disposition: Disposition,
+    ) -> Result<(), RequestError<TaskControlError>> {
+        control::set_disposition(
+            self.task_states,
+            task_index as usize,
+            disposition,
+        )
+        .map_err(RequestError::from)
+    }
+
+    fn restart_task(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        task_index: u32,
+    ) -> Result<(), RequestError<TaskControlError>> {
+        control::restart(self.task_states, task_index as usize)
+            .map_err(RequestError::from)
+    }
+
+    fn read_task_memory(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        task_index: u32,
+        address: u32,
+        dest: Leased<W, [u8]>,
+    ) -> Result<u32, RequestError<TaskControlError>> {
+        let mut buf = [0u8; userlib::TASK_MEMORY_WINDOW];
+        let len = dest.len().min(buf.len());
+        let len = control::read_memory(
+            self.task_states,
+            task_index as usize,
+            address,
+            &mut buf[..len],
+        )?;
+        dest.write_range(0..len, &buf[..len])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        Ok(len as u32)
+    }
+
+    #[cfg(feature = "crash-journal")]
+    fn read_crash_journal(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        index: u32,
+    ) -> Result<CrashRecord, RequestError<CrashJournalError>> {
+        self.journal.read(index).map_err(RequestError::from)
+    }
+
+    #[cfg(not(feature = "crash-journal"))]
+    fn read_crash_journal(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        _index: u32,
+    ) -> Result<CrashRecord, RequestError<CrashJournalError>> {
+        Err(CrashJournalError::Unsupported.into())
+    }
+
     fn restart_me_raw(
         &mut self,
         msg: &userlib::RecvMessage,
     ) -> Result<(), RequestError<Infallible>> {
-        kipc::restart_task(msg.sender.index(), true);
+        // The caller is running, so the restart throttle can't be holding it.
+        cascade::restart(self.task_states, msg.sender.index());
 
         // Note: the returned value here won't go anywhere because we just
         // unblocked the caller. So this is doing a small amount of unnecessary
@@ -281,11 +356,37 @@ impl idl::InOrderJefeImpl for ServerImpl<'_> {
 struct TaskStatus {
     disposition: Disposition,
     holding_fault: bool,
+    /// Set when the kernel's restart throttle refused to let us restart the
+    /// task. Its fault has already been handled; we just need to try the
+    /// restart again when the kernel tells us the hold has expired.
+    restart_held: bool,
+    /// Number of faults we've handled for this task.
+    fault_count: u32,
+    /// The most recent of those, kept so that it can be read back after the
+    /// task has been restarted.
+    last_fault: Option<FaultInfo>,
 }
 
+/// Notification bits that mean some task has faulted. A fault that trips the
+/// kernel's restart throttle is reported with its own bit.
+#[cfg(not(feature = "restart-throttle"))]
+const FAULTED_MASK: u32 = notifications::FAULT_MASK;
+#[cfg(feature = "restart-throttle")]
+const FAULTED_MASK: u32 =
+    notifications::FAULT_MASK | notifications::RESTART_HELD_MASK;
+
 impl idol_runtime::NotificationHandler for ServerImpl<'_> {
     fn current_notification_mask(&self) -> u32 {
-        notifications::FAULT_MASK | notifications::TIMER_MASK
+        #[cfg(not(feature = "restart-throttle"))]
+        {
+            FAULTED_MASK | notifications::TIMER_MASK
+        }
+        #[cfg(feature = "restart-throttle")]
+        {
+            FAULTED_MASK
+                | notifications::TIMER_MASK
+                | notifications::RESTART_RELEASED_MASK
+        }
     }
 
     fn handle_notification(&mut self, bits: u32) {
@@ -300,9 +401,32 @@ impl idol_runtime::NotificationHandler for ServerImpl<'_> {
                     notifications::TIMER_MASK,
                 );
             }
+
+            // Write out any crashes recorded since the last tick. This is
+            // deferred to here so that handling a fault never waits on flash.
+            #[cfg(feature = "crash-journal")]
+            self.journal.flush();
+        }
+
+        // The kernel has lifted the restart throttle's hold on some task we
+        // tried and failed to restart. Try again, unless we've since been told
+        // to hold it.
+        #[cfg(feature = "restart-throttle")]
+        if bits & notifications::RESTART_RELEASED_MASK != 0 {
+            for i in 0..self.task_states.len() {
+                if !self.task_states[i].restart_held {
+                    continue;
+                }
+                if self.task_states[i].disposition == Disposition::Restart {
+                    cascade::restart(self.task_states, i);
+                } else {
+                    self.task_states[i].restart_held = false;
+                    self.task_states[i].holding_fault = true;
+                }
+            }
         }
 
-        if bits & notifications::FAULT_MASK != 0 {
+        if bits & FAULTED_MASK != 0 {
             // Work out who faulted. It's theoretically possible for more than
             // one task to have faulted since we last looked, but it's somewhat
             // unlikely since a fault causes us to immediately preempt. In any
@@ -315,11 +439,23 @@ impl idol_runtime::NotificationHandler for ServerImpl<'_> {
                 let status = &mut self.task_states[fault_index];
 
                 // If we're aware that this task is in a fault state, don't
-                // bother making a syscall to enquire.
-                if status.holding_fault {
+                // bother making a syscall to enquire. A task whose restart the
+                // kernel refused is still sitting on the fault we've already
+                // recorded, and is retried when the hold is released.
+                if status.holding_fault || status.restart_held {
                     continue;
                 }
 
+                if let TaskState::Faulted { fault, .. } =
+                    kipc::read_task_status(fault_index)
+                {
+                    status.fault_count = status.fault_count.wrapping_add(1);
+                    status.last_fault = Some(fault);
+
+                    #[cfg(feature = "crash-journal")]
+                    self.journal.record(fault_index, &fault);
+                }
+
                 #[cfg(feature = "dump")]
                 {
                     // We'll ignore the result of dumping; it could fail
@@ -332,7 +468,7 @@ impl idol_runtime::NotificationHandler for ServerImpl<'_> {
 
                 if status.disposition == Disposition::Restart {
                     // Stand it back up
-                    kipc::restart_task(fault_index, true);
+                    cascade::restart(self.task_states, fault_index);
                 } else {
                     // Mark this one off so we don't revisit it until
                     // requested.
@@ -352,6 +488,9 @@ include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
 
 // And the Idol bits
 mod idl {
-    use task_jefe_api::{DumpAgentError, ResetReason};
+    use task_jefe_api::{
+        CrashJournalError, CrashRecord, Disposition, DumpAgentError,
+        ResetReason, TaskControlError, TaskControlStatus,
+    };
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
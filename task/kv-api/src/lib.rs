@@ -0,0 +1,40 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Client API for the key-value store task.
+
+#![no_std]
+
+use derive_idol_err::IdolError;
+use userlib::*;
+
+/// Largest value that can be stored, in bytes.
+pub const MAX_VALUE_LEN: usize = 256;
+
+#[derive(
+    Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError, counters::Count,
+)]
+pub enum KvError {
+    /// There's no value for this key
+    NotFound = 1,
+    /// The value is longer than `MAX_VALUE_LEN`
+    TooLong,
+    /// The lease is too small for the value
+    BufferTooSmall,
+    /// The store has no room for another key
+    IndexFull,
+    /// The values already stored leave no room for this one
+    StoreFull,
+    /// The value's record failed its check when read back
+    Corrupt,
+    /// The flash reported a failure
+    FlashError,
+    /// The store couldn't be mounted at boot
+    Unavailable,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
@@ -0,0 +1,227 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The second bank of internal flash, as storage for the store.
+
+use crate::{device, notifications};
+use kvstore::{Flash, FlashError};
+use userlib::{sys_irq_control, sys_recv_notification};
+
+// Keys constants are defined in RM0433 Rev 7
+// Section 4.9.2
+const FLASH_KEY1: u32 = 0x4567_0123;
+const FLASH_KEY2: u32 = 0xCDEF_89AB;
+
+/// Where bank 2 starts when the banks aren't swapped, which is how the sector
+/// numbers in `FLASH_CR2` count.
+const BANK2_ADDR: u32 = 0x0810_0000;
+
+const SECTOR_SIZE: u32 = 128 * 1024;
+const FLASH_WORD_BYTES: usize = 32;
+
+extern "C" {
+    // Symbols injected by the linker.
+    //
+    // This requires adding `extern-regions = ["bank2"]` to the task config
+    static mut __REGION_BANK2_BASE: [u32; 0];
+    static mut __REGION_BANK2_END: [u32; 0];
+}
+
+pub struct Bank2 {
+    flash: &'static device::flash::RegisterBlock,
+    /// Where the store starts
+    base: u32,
+    len: u32,
+}
+
+impl Bank2 {
+    pub fn new() -> Self {
+        // These are _philosophically_ constants, but since they're generated
+        // by taking the address of a linker-generated symbol, we can't define
+        // them as `const` values.
+        //
+        // SAFETY: these are symbols populated by the linker.
+        let base = unsafe { __REGION_BANK2_BASE.as_ptr() } as u32;
+        let end = unsafe { __REGION_BANK2_END.as_ptr() } as u32;
+
+        let this = Self {
+            flash: unsafe { &*device::FLASH::ptr() },
+            base,
+            len: end - base,
+        };
+        this.unlock();
+        this
+    }
+
+    // All sequences can be found in RM0433 Rev 7
+    fn unlock(&self) {
+        if !self.flash.bank2().cr.read().lock().bit() {
+            return;
+        }
+        self.flash
+            .bank2()
+            .keyr
+            .write(|w| unsafe { w.keyr().bits(FLASH_KEY1) });
+        self.flash
+            .bank2()
+            .keyr
+            .write(|w| unsafe { w.keyr().bits(FLASH_KEY2) });
+    }
+
+    fn clear_errors(&self) {
+        // Speculative reads can set RDPERR and RDSERR behind our backs; see
+        // the same workaround in `stm32h7-update-server`. The rest are left
+        // over from an operation that failed.
+        self.flash.bank2().ccr.write(|w| {
+            w.clr_rdperr()
+                .set_bit()
+                .clr_rdserr()
+                .set_bit()
+                .clr_wrperr()
+                .set_bit()
+                .clr_pgserr()
+                .set_bit()
+                .clr_strberr()
+                .set_bit()
+                .clr_incerr()
+                .set_bit()
+                .clr_operr()
+                .set_bit()
+        });
+    }
+
+    fn status(&self) -> Result<(), FlashError> {
+        let sr = self.flash.bank2().sr.read();
+        if sr.operr().bit()
+            || sr.incerr().bit()
+            || sr.strberr().bit()
+            || sr.pgserr().bit()
+            || sr.wrperr().bit()
+        {
+            Err(FlashError)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_range(&self, addr: u32, len: usize) -> Result<u32, FlashError> {
+        match addr.checked_add(len as u32) {
+            Some(end) if end <= self.len => Ok(self.base + addr),
+            _ => Err(FlashError),
+        }
+    }
+
+    // RM0433 Rev 7 section 4.3.9
+    // Following Single write sequence
+    fn write_word(
+        &mut self,
+        addr: u32,
+        words: &[u32; FLASH_WORD_BYTES / 4],
+    ) -> Result<(), FlashError> {
+        self.clear_errors();
+        self.flash.bank2().cr.write(|w| {
+            // SAFETY: `0b11` selects 64-bit parallelism, as in
+            // `stm32h7-update-server`.
+            unsafe { w.psize().bits(0b11) }.pg().set_bit()
+        });
+        for (i, &word) in words.iter().enumerate() {
+            // SAFETY: we're running from bank 1, and `addr` has been checked
+            // against the bounds of our region in bank 2.
+            unsafe {
+                core::ptr::write_volatile((addr as *mut u32).add(i), word);
+            }
+        }
+        // See `stm32h7-update-server` for why we don't wait for QW to be set
+        // first.
+        while self.flash.bank2().sr.read().qw().bit() {}
+        self.flash.bank2().cr.modify(|_, w| w.pg().clear_bit());
+        self.status()
+    }
+}
+
+impl Flash for Bank2 {
+    const SECTOR_SIZE: u32 = SECTOR_SIZE;
+    const WRITE_SIZE: u32 = FLASH_WORD_BYTES as u32;
+
+    fn sector_count(&self) -> u32 {
+        self.len / SECTOR_SIZE
+    }
+
+    fn read(&mut self, addr: u32, buf: &mut [u8]) -> Result<(), FlashError> {
+        let addr = self.check_range(addr, buf.len())?;
+        // SAFETY: the range is within our region, which the kernel has mapped
+        // for us to read.
+        //
+        // A flash word that lost power while being programmed can have an
+        // ECC error that can't be corrected, which faults here rather than
+        // reporting an error; we'll keep faulting on it until the sector is
+        // erased.
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                addr as *const u8,
+                buf.as_mut_ptr(),
+                buf.len(),
+            );
+        }
+        Ok(())
+    }
+
+    fn program(&mut self, addr: u32, data: &[u8]) -> Result<(), FlashError> {
+        let addr = self.check_range(addr, data.len())?;
+        for (i, chunk) in data.chunks(FLASH_WORD_BYTES).enumerate() {
+            let mut words = [u32::MAX; FLASH_WORD_BYTES / 4];
+            for (w, bytes) in words.iter_mut().zip(chunk.chunks(4)) {
+                let mut b = [0xFF; 4];
+                b[..bytes.len()].copy_from_slice(bytes);
+                *w = u32::from_le_bytes(b);
+            }
+            self.write_word(addr + (i * FLASH_WORD_BYTES) as u32, &words)?;
+        }
+        Ok(())
+    }
+
+    fn erase(&mut self, sector: u32) -> Result<(), FlashError> {
+        if sector >= self.sector_count() {
+            return Err(FlashError);
+        }
+        let snb = (self.base - BANK2_ADDR) / SECTOR_SIZE + sector;
+
+        self.clear_errors();
+        sys_irq_control(notifications::FLASH_IRQ_MASK, true);
+        self.flash.bank2().cr.modify(|_, w| {
+            w.eopie()
+                .set_bit()
+                .wrperrie()
+                .set_bit()
+                .pgserrie()
+                .set_bit()
+                .strberrie()
+                .set_bit()
+                .incerrie()
+                .set_bit()
+                .operrie()
+                .set_bit()
+        });
+        self.flash
+            .bank2()
+            .cr
+            .modify(|_, w| unsafe { w.snb().bits(snb as u8) }.ser().set_bit());
+        self.flash.bank2().cr.modify(|_, w| w.start().set_bit());
+
+        // Erasing takes a second or two; wait for the EOP interrupt rather
+        // than spinning.
+        loop {
+            sys_recv_notification(notifications::FLASH_IRQ_MASK);
+            if self.flash.bank2().sr.read().eop().bit()
+                || self.status().is_err()
+            {
+                break;
+            }
+            sys_irq_control(notifications::FLASH_IRQ_MASK, true);
+        }
+        self.flash.bank2().ccr.write(|w| w.clr_eop().set_bit());
+        self.flash.bank2().cr.modify(|_, w| w.ser().clear_bit());
+        self.status()
+    }
+}
@@ -0,0 +1,164 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Key-value store server.
+//!
+//! This task keeps a [`kvstore::Store`] in the second bank of internal flash,
+//! giving other tasks somewhere to persist calibration data, counters and
+//! configuration across reboots. It needs `extern-regions = ["bank2"]`, and
+//! so can't go in an image alongside `stm32h7-update-server`, which uses the
+//! same bank to stage updates.
+
+#![no_std]
+#![no_main]
+
+mod bank2;
+
+use bank2::Bank2;
+use idol_runtime::{
+    ClientError, Leased, LenLimit, NotificationHandler, RequestError, R, W,
+};
+use kvstore::Store;
+use ringbuf::*;
+use task_kv_api::{KvError, MAX_VALUE_LEN};
+use userlib::*;
+
+#[cfg(feature = "h743")]
+use stm32h7::stm32h743 as device;
+
+#[cfg(feature = "h753")]
+use stm32h7::stm32h753 as device;
+
+/// Number of keys the store can hold.
+const INDEX_SIZE: usize = 64;
+
+const _: () = assert!(MAX_VALUE_LEN == kvstore::MAX_VALUE_LEN);
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Mounted { keys: usize },
+    MountFailed(kvstore::Error),
+    WriteFailed(u32, kvstore::Error),
+    RemoveFailed(u32, kvstore::Error),
+}
+
+ringbuf!(Trace, 16, Trace::None);
+
+struct ServerImpl {
+    /// The store, unless it failed to mount
+    store: Option<Store<Bank2, INDEX_SIZE>>,
+    buf: [u8; MAX_VALUE_LEN],
+}
+
+impl ServerImpl {
+    fn store(&mut self) -> Result<&mut Store<Bank2, INDEX_SIZE>, KvError> {
+        self.store.as_mut().ok_or(KvError::Unavailable)
+    }
+}
+
+fn kv_error(e: kvstore::Error) -> KvError {
+    match e {
+        kvstore::Error::Flash => KvError::FlashError,
+        kvstore::Error::NotFound => KvError::NotFound,
+        kvstore::Error::TooLong => KvError::TooLong,
+        kvstore::Error::IndexFull => KvError::IndexFull,
+        kvstore::Error::StoreFull => KvError::StoreFull,
+        kvstore::Error::Corrupt => KvError::Corrupt,
+    }
+}
+
+impl idl::InOrderKvImpl for ServerImpl {
+    fn read(
+        &mut self,
+        _: &RecvMessage,
+        key: u32,
+        value: Leased<W, [u8]>,
+    ) -> Result<usize, RequestError<KvError>> {
+        let v = self.store()?.read(key).map_err(kv_error)?;
+        if v.len() > value.len() {
+            return Err(KvError::BufferTooSmall.into());
+        }
+        value
+            .write_range(0..v.len(), v)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        Ok(v.len())
+    }
+
+    fn write(
+        &mut self,
+        _: &RecvMessage,
+        key: u32,
+        value: LenLimit<Leased<R, [u8]>, MAX_VALUE_LEN>,
+    ) -> Result<(), RequestError<KvError>> {
+        let len = value.len();
+        value
+            .read_range(0..len, &mut self.buf[..len])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        let store = self.store.as_mut().ok_or(KvError::Unavailable)?;
+        store.write(key, &self.buf[..len]).map_err(|e| {
+            ringbuf_entry!(Trace::WriteFailed(key, e));
+            kv_error(e).into()
+        })
+    }
+
+    fn remove(
+        &mut self,
+        _: &RecvMessage,
+        key: u32,
+    ) -> Result<(), RequestError<KvError>> {
+        self.store()?.remove(key).map_err(|e| {
+            if e != kvstore::Error::NotFound {
+                ringbuf_entry!(Trace::RemoveFailed(key, e));
+            }
+            kv_error(e).into()
+        })
+    }
+
+    fn count(&mut self, _: &RecvMessage) -> Result<u32, RequestError<KvError>> {
+        Ok(self.store()?.len() as u32)
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        // The flash interrupt is only waited on while erasing.
+        0
+    }
+
+    fn handle_notification(&mut self, _bits: u32) {
+        unreachable!()
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let store = match Store::mount(Bank2::new()) {
+        Ok(store) => {
+            ringbuf_entry!(Trace::Mounted { keys: store.len() });
+            Some(store)
+        }
+        Err(e) => {
+            ringbuf_entry!(Trace::MountFailed(e));
+            None
+        }
+    };
+    let mut server = ServerImpl {
+        store,
+        buf: [0; MAX_VALUE_LEN],
+    };
+
+    let mut buffer = [0; idl::INCOMING_SIZE];
+    loop {
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
+
+mod idl {
+    use task_kv_api::KvError;
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
@@ -40,7 +40,8 @@ pub enum SendError {
 pub enum RecvError {
     /// The incoming RX queue is empty; there are no packets able to be received
     /// from this socket. You can wait on the notification and try again if you
-    /// like.
+    /// like. (Packets that arrived while the queue was full are dropped, and
+    /// counted; see `Net::rx_dropped`.)
     QueueEmpty = 1,
 
     /// The server has restarted. Clients may or may not actually care about
@@ -50,6 +51,56 @@ pub enum RecvError {
     ServerRestarted = 2,
 }
 
+/// Errors that can occur when using a TCP socket.
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError, counters::Count,
+)]
+#[repr(u32)]
+pub enum TcpError {
+    /// The socket isn't in a state that allows the operation: for instance,
+    /// sending on a socket that isn't connected, or listening on one that is
+    /// already open.
+    InvalidState = 1,
+
+    /// The remote address or port can't be connected to, or the socket has
+    /// no port to listen on.
+    Unaddressable,
+
+    /// The socket's transmit buffer is full. Wait until you get a notification
+    /// and try again.
+    QueueFull,
+
+    /// There's no data waiting to be received. Wait until you get a
+    /// notification and try again.
+    QueueEmpty,
+
+    /// The remote end has closed the connection, and all of the data it sent
+    /// has been received.
+    Finished,
+
+    /// The server has restarted, and any connection the socket had is gone.
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
+/// The state of a TCP socket, as in RFC 793.
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, Serialize, SerializedSize, Deserialize,
+)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
 /// Errors that can occur when trying to set a VLAN as trusted
 #[derive(
     Copy, Clone, PartialEq, Eq, FromPrimitive, IdolError, counters::Count,
@@ -130,6 +181,31 @@ impl From<ksz8463::KszRawMacTableEntry> for KszMacTableEntry {
 #[repr(C)]
 pub struct MacAddress(pub [u8; 6]);
 
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, SerializedSize, Deserialize,
+)]
+pub enum LinkSpeed {
+    Mbps10,
+    Mbps100,
+    Mbps1000,
+}
+
+/// The state of the link on one of the SP's ports.
+#[derive(
+    Copy, Clone, Debug, Eq, PartialEq, Serialize, SerializedSize, Deserialize,
+)]
+pub struct LinkStatus {
+    pub up: bool,
+    /// Whether autonegotiation is enabled and has completed; if so, `speed`
+    /// and `full_duplex` are what it settled on, and otherwise they're what
+    /// the PHY has been told to use.
+    pub autoneg_complete: bool,
+    pub speed: LinkSpeed,
+    pub full_duplex: bool,
+    /// How many times the link has gone down after being up
+    pub flaps: u32,
+}
+
 #[derive(
     Copy, Clone, Debug, Default, Serialize, SerializedSize, Deserialize,
 )]
@@ -189,6 +265,22 @@ pub enum MgmtError {
     ServerRestarted,
 }
 
+/// Errors that can occur when asking after the DHCP client's address.
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, FromPrimitive, IdolError, counters::Count,
+)]
+#[repr(u32)]
+pub enum DhcpError {
+    /// The net task was built without its DHCP client.
+    NotAvailable = 1,
+
+    /// The DHCP client hasn't bound an address, or has lost its lease.
+    Unbound,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(
@@ -258,6 +350,10 @@ impl TryFrom<smoltcp::wire::IpAddress> for Address {
 
         match a {
             IpAddress::Ipv6(a) => Ok(Self::Ipv6(a.into())),
+            // Sockets are bound to IPv6 addresses, so IPv4 (which only
+            // appears alongside DHCP) never makes it this far.
+            #[cfg(feature = "ipv4")]
+            IpAddress::Ipv4(_) => Err(AddressUnspecified),
         }
     }
 }
@@ -285,6 +381,33 @@ impl From<Ipv6Address> for smoltcp::wire::Ipv6Address {
     }
 }
 
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+#[serde(transparent)]
+pub struct Ipv4Address(pub [u8; 4]);
+
+#[cfg(feature = "ipv4")]
+impl From<smoltcp::wire::Ipv4Address> for Ipv4Address {
+    fn from(a: smoltcp::wire::Ipv4Address) -> Self {
+        Self(a.0)
+    }
+}
+
+/// IPv4 addressing, as bound by the net task's DHCP client.
+#[derive(
+    Copy, Clone, Debug, Serialize, SerializedSize, Deserialize, PartialEq, Eq,
+)]
+pub struct Ipv4Config {
+    pub address: Ipv4Address,
+    pub prefix_len: u8,
+    /// The default router, or all zeros if the server didn't name one
+    pub router: Ipv4Address,
+    /// Incremented each time the addressing changes, so that a task woken by
+    /// the change notification can tell whether it has already seen it
+    pub generation: u32,
+}
+
 /// Upstream SP port
 ///
 /// Values are based on the KSZ8463's numbering (1-3); port 3 is connected to
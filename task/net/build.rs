@@ -40,7 +40,7 @@ fn generate_net_config(config: &NetConfig) -> Result<()> {
         "{}",
         quote::quote! {
             use core::sync::atomic::{AtomicBool, Ordering};
-            use smoltcp::socket::udp;
+            use smoltcp::socket::{tcp, udp};
 
             pub const SOCKET_COUNT: usize = #socket_count;
         }
@@ -59,11 +59,16 @@ fn generate_net_config(config: &NetConfig) -> Result<()> {
     }
     writeln!(out, "{}", generate_state_struct(config))?;
     writeln!(out, "{}", generate_constructor(config)?)?;
-    writeln!(out, "{}", generate_owner_info(config)?)?;
+    writeln!(out, "{}", generate_client_info(config)?)?;
+    writeln!(out, "{}", generate_client_queues(config))?;
     writeln!(out, "{}", generate_port_table(config)?)?;
+    writeln!(out, "{}", generate_tcp_sockets(config)?)?;
+    writeln!(out, "{}", generate_dhcp_config(config)?)?;
+    writeln!(out, "{}", generate_mdns_config(config))?;
 
     build_net::generate_port_consts(config, &mut out)?;
     build_net::generate_socket_enum(config, &mut out)?;
+    build_net::generate_tcp_socket_enum(config, &mut out)?;
 
     drop(out);
 
@@ -87,38 +92,287 @@ fn generate_port_table(config: &NetConfig) -> Result<TokenStream> {
     })
 }
 
-fn generate_owner_info(config: &NetConfig) -> Result<TokenStream> {
-    let consts: Vec<_> = config
-        .sockets
-        .values()
-        .map(|socket| {
-            let task: syn::Ident = syn::parse_str(&socket.owner.name).unwrap();
+fn generate_client_info(config: &NetConfig) -> Result<TokenStream> {
+    let mut consts = vec![];
+    for (i, (name, socket)) in config.sockets.iter().enumerate() {
+        let mut seen = std::collections::BTreeSet::new();
+        for (task_name, notification, _) in socket.all_clients() {
+            if !seen.insert(task_name) {
+                bail!("task {task_name} is a client of socket {name} twice");
+            }
+            let task: syn::Ident = syn::parse_str(task_name).unwrap();
             let note: syn::Ident = syn::parse_str(&format!(
                 "{}_MASK",
-                socket.owner.notification.to_uppercase().replace('-', "_")
+                notification.to_uppercase().replace('-', "_")
             ))
             .unwrap();
-            Ok(quote::quote! {
+            consts.push(quote::quote! {
                 (
+                    #i,
                     userlib::TaskId::for_index_and_gen(
                         hubris_num_tasks::Task::#task as usize,
                         userlib::Generation::ZERO,
                     ),
                     crate::notifications::#task::#note,
                 )
-            })
-        })
-        .collect::<Result<Vec<_>>>()?;
+            });
+        }
+    }
 
-    let n = config.sockets.len();
+    let n = consts.len();
 
     Ok(quote::quote! {
-        pub(crate) const SOCKET_OWNERS: [(userlib::TaskId, u32); #n] = [
+        pub(crate) const CLIENT_COUNT: usize = #n;
+
+        /// The socket index, task and notification of each client, in
+        /// socket order.
+        pub(crate) const CLIENTS: [(usize, userlib::TaskId, u32); #n] = [
             #( #consts ),*
         ];
     })
 }
 
+fn generate_client_queues(config: &NetConfig) -> TokenStream {
+    let mut statics = vec![];
+    let mut queues = vec![];
+    for (name, socket) in &config.sockets {
+        for (task_name, _, rx) in socket.all_clients() {
+            let upname = format!(
+                "{}_{}",
+                name.to_ascii_uppercase(),
+                task_name.to_ascii_uppercase()
+            );
+            let hdrname: syn::Ident =
+                syn::parse_str(&format!("CLIENT_RX_HDR_{upname}")).unwrap();
+            let bufname: syn::Ident =
+                syn::parse_str(&format!("CLIENT_RX_DAT_{upname}")).unwrap();
+            let pktcnt = rx.packets;
+            let bytecnt = rx.bytes;
+            statics.push(quote::quote! {
+                static mut #hdrname: [crate::mux::RxMetadata; #pktcnt] =
+                    [crate::mux::RxMetadata::EMPTY; #pktcnt];
+                static mut #bufname: [u8; #bytecnt] = [0u8; #bytecnt];
+            });
+            queues.push(quote::quote! {
+                crate::mux::RxQueue::new(
+                    unsafe { &mut #hdrname[..] },
+                    unsafe { &mut #bufname[..] },
+                )
+            });
+        }
+    }
+    quote::quote! {
+        #( #statics )*
+
+        static QUEUE_CTOR_FLAG: AtomicBool = AtomicBool::new(false);
+        pub(crate) fn construct_rx_queues(
+        ) -> [crate::mux::RxQueue<'static>; CLIENT_COUNT] {
+            let second_time = QUEUE_CTOR_FLAG.swap(true, Ordering::Relaxed);
+            if second_time { panic!() }
+
+            [
+                #( #queues ),*
+            ]
+        }
+    }
+}
+
+fn generate_tcp_sockets(config: &NetConfig) -> Result<TokenStream> {
+    let mut statics = vec![];
+    let mut sockets = vec![];
+    let mut owners = vec![];
+    let mut ports = vec![];
+    let mut vlans = vec![];
+    for (name, socket) in &config.tcp_sockets {
+        let upname = name.to_ascii_uppercase();
+        let rxname: syn::Ident =
+            syn::parse_str(&format!("TCP_RX_DAT_{upname}")).unwrap();
+        let txname: syn::Ident =
+            syn::parse_str(&format!("TCP_TX_DAT_{upname}")).unwrap();
+        let (rx, tx) = (socket.rx_bytes, socket.tx_bytes);
+        statics.push(quote::quote! {
+            static mut #rxname: [u8; #rx] = [0u8; #rx];
+            static mut #txname: [u8; #tx] = [0u8; #tx];
+        });
+        sockets.push(quote::quote! {
+            tcp::Socket::new(
+                tcp::SocketBuffer::new(unsafe { &mut #rxname[..] }),
+                tcp::SocketBuffer::new(unsafe { &mut #txname[..] }),
+            )
+        });
+
+        let task: syn::Ident = syn::parse_str(&socket.owner.name).unwrap();
+        let note: syn::Ident = syn::parse_str(&format!(
+            "{}_MASK",
+            socket.owner.notification.to_uppercase().replace('-', "_")
+        ))
+        .unwrap();
+        owners.push(quote::quote! {
+            (
+                userlib::TaskId::for_index_and_gen(
+                    hubris_num_tasks::Task::#task as usize,
+                    userlib::Generation::ZERO,
+                ),
+                crate::notifications::#task::#note,
+            )
+        });
+
+        ports.push(socket.port);
+
+        let vlan: syn::Ident = match &socket.vlan {
+            Some(v) => syn::parse_str(&build_net::vlan_variant(v)).unwrap(),
+            None => syn::parse_str("None").unwrap(),
+        };
+        vlans.push(quote::quote! { task_net_api::VLanId::#vlan });
+    }
+
+    let n = config.tcp_sockets.len();
+    Ok(quote::quote! {
+        #( #statics )*
+
+        pub(crate) const TCP_SOCKET_OWNERS: [(userlib::TaskId, u32); #n] = [
+            #( #owners ),*
+        ];
+        pub(crate) const TCP_SOCKET_PORTS: [u16; #n] = [
+            #( #ports ),*
+        ];
+        pub(crate) const TCP_SOCKET_VLANS: [task_net_api::VLanId; #n] = [
+            #( #vlans ),*
+        ];
+
+        static TCP_CTOR_FLAG: AtomicBool = AtomicBool::new(false);
+        pub(crate) fn construct_tcp_sockets() -> [tcp::Socket<'static>; #n] {
+            let second_time = TCP_CTOR_FLAG.swap(true, Ordering::Relaxed);
+            if second_time { panic!() }
+
+            [
+                #( #sockets ),*
+            ]
+        }
+    })
+}
+
+fn generate_dhcp_config(config: &NetConfig) -> Result<TokenStream> {
+    let dhcp = match (build_util::has_feature("dhcp"), &config.dhcp) {
+        (true, Some(dhcp)) => dhcp,
+        (false, None) => return Ok(TokenStream::new()),
+        (true, None) => {
+            bail!("DHCP feature is enabled, but dhcp is missing from config")
+        }
+        (false, Some(_)) => {
+            bail!("DHCP feature is disabled, but dhcp is present in config")
+        }
+    };
+
+    let vlan: syn::Ident = match &dhcp.vlan {
+        Some(v) => syn::parse_str(&build_net::vlan_variant(v)).unwrap(),
+        None => syn::parse_str("None").unwrap(),
+    };
+    let notify = dhcp.on_address_change.iter().map(|(task, notification)| {
+        let task: syn::Ident = syn::parse_str(task).unwrap();
+        let note: syn::Ident = syn::parse_str(&format!(
+            "{}_MASK",
+            notification.to_uppercase().replace('-', "_")
+        ))
+        .unwrap();
+        quote::quote! {
+            (
+                userlib::TaskId::for_index_and_gen(
+                    hubris_num_tasks::Task::#task as usize,
+                    userlib::Generation::ZERO,
+                ),
+                crate::notifications::#task::#note,
+            )
+        }
+    });
+    let n = dhcp.on_address_change.len();
+
+    Ok(quote::quote! {
+        pub(crate) const DHCP_VLAN: task_net_api::VLanId =
+            task_net_api::VLanId::#vlan;
+
+        /// Tasks to notify when the DHCP client's addressing changes.
+        pub(crate) const DHCP_NOTIFY: [(userlib::TaskId, u32); #n] = [
+            #( #notify ),*
+        ];
+    })
+}
+
+fn generate_mdns_config(config: &NetConfig) -> TokenStream {
+    let vlan_count = config.vlans.len().max(1);
+    let Some(mdns) = &config.mdns else {
+        return quote::quote! {
+            pub(crate) const MDNS_SOCKET_COUNT: usize = 0;
+            pub(crate) const MDNS_INSTANCE_COUNT: usize = 0;
+            pub(crate) const MDNS_HOSTNAME: &str = "";
+            pub(crate) const MDNS_SERVICE: &str = "";
+            pub(crate) const MDNS_INSTANCES: [(&str, u16); 0] = [];
+
+            pub(crate) fn construct_mdns_sockets(
+            ) -> [[udp::Socket<'static>; 0]; #vlan_count] {
+                [[]; #vlan_count]
+            }
+        };
+    };
+
+    let hostname = &mdns.hostname;
+    let service = &mdns.service;
+    let instances = mdns.sockets.iter().map(|s| {
+        let port = config.sockets[s].port;
+        quote::quote! { (#s, #port) }
+    });
+    let n = mdns.sockets.len();
+    let sockets = (0..vlan_count).map(|i| {
+        quote::quote! {
+            [udp::Socket::new(
+                udp::PacketBuffer::new(
+                    unsafe { &mut MDNS_RX_HDR[#i][..] },
+                    unsafe { &mut MDNS_RX_DAT[#i][..] },
+                ),
+                udp::PacketBuffer::new(
+                    unsafe { &mut MDNS_TX_HDR[#i][..] },
+                    unsafe { &mut MDNS_TX_DAT[#i][..] },
+                ),
+            )]
+        }
+    });
+
+    // Queries and responses are small, and if we miss one, it'll be asked
+    // again; a couple of each is plenty.
+    let pktcnt = 2usize;
+    let bytecnt = 1024usize;
+    quote::quote! {
+        pub(crate) const MDNS_SOCKET_COUNT: usize = 1;
+        pub(crate) const MDNS_INSTANCE_COUNT: usize = #n;
+        pub(crate) const MDNS_HOSTNAME: &str = #hostname;
+        pub(crate) const MDNS_SERVICE: &str = #service;
+        /// The name and port of each socket to advertise.
+        pub(crate) const MDNS_INSTANCES: [(&str, u16); #n] = [
+            #( #instances ),*
+        ];
+
+        static mut MDNS_RX_HDR: [[udp::PacketMetadata; #pktcnt]; #vlan_count] =
+            [[udp::PacketMetadata::EMPTY; #pktcnt]; #vlan_count];
+        static mut MDNS_RX_DAT: [[u8; #bytecnt]; #vlan_count] =
+            [[0u8; #bytecnt]; #vlan_count];
+        static mut MDNS_TX_HDR: [[udp::PacketMetadata; #pktcnt]; #vlan_count] =
+            [[udp::PacketMetadata::EMPTY; #pktcnt]; #vlan_count];
+        static mut MDNS_TX_DAT: [[u8; #bytecnt]; #vlan_count] =
+            [[0u8; #bytecnt]; #vlan_count];
+
+        static MDNS_CTOR_FLAG: AtomicBool = AtomicBool::new(false);
+        pub(crate) fn construct_mdns_sockets(
+        ) -> [[udp::Socket<'static>; 1]; #vlan_count] {
+            let second_time = MDNS_CTOR_FLAG.swap(true, Ordering::Relaxed);
+            if second_time { panic!() }
+
+            [
+                #( #sockets ),*
+            ]
+        }
+    }
+}
+
 fn generate_socket_state(
     name: &str,
     config: &SocketConfig,
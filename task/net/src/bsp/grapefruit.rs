@@ -63,6 +63,7 @@ pub struct BspImpl {
 impl bsp_support::Bsp for BspImpl {
     // This system wants to be woken periodically to do logging
     const WAKE_INTERVAL: Option<u64> = Some(5000);
+    const LINK_POLL_INTERVAL: Option<u64> = Some(500);
 
     fn preinit() {}
 
@@ -161,6 +162,14 @@ impl bsp_support::Bsp for BspImpl {
         Err(PhyError::NotImplemented)
     }
 
+    fn link_status(
+        &self,
+        port: u8,
+        _eth: &eth::Ethernet,
+    ) -> Result<eth::phy::LinkStatus, PhyError> {
+        crate::bsp_support::ksz8463_link_status(self.ksz8463(), port)
+    }
+
     fn ksz8463(&self) -> &Ksz8463 {
         &self.ksz8463
     }
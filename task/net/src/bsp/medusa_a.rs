@@ -29,6 +29,7 @@ pub struct BspImpl(mgmt::Bsp);
 impl bsp_support::Bsp for BspImpl {
     // This system wants to be woken periodically to do logging
     const WAKE_INTERVAL: Option<u64> = Some(500);
+    const LINK_POLL_INTERVAL: Option<u64> = Some(500);
 
     /// Stateless function to configure ethernet pins before the Bsp struct
     /// is actually constructed
@@ -110,6 +111,14 @@ impl bsp_support::Bsp for BspImpl {
         self.0.phy_write(port, reg, value, eth)
     }
 
+    fn link_status(
+        &self,
+        port: u8,
+        _eth: &eth::Ethernet,
+    ) -> Result<eth::phy::LinkStatus, PhyError> {
+        crate::bsp_support::ksz8463_link_status(self.ksz8463(), port)
+    }
+
     fn ksz8463(&self) -> &Ksz8463 {
         &self.0.ksz8463
     }
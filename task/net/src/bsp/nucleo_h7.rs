@@ -20,6 +20,8 @@ const PHYADDR: u8 = 0x0;
 pub struct BspImpl;
 
 impl crate::bsp_support::Bsp for BspImpl {
+    const LINK_POLL_INTERVAL: Option<u64> = Some(500);
+
     fn preinit() {}
 
     fn configure_ethernet_pins(sys: &Sys) {
@@ -61,6 +63,19 @@ impl crate::bsp_support::Bsp for BspImpl {
         Self {}
     }
 
+    fn link_status(
+        &self,
+        port: u8,
+        eth: &eth::Ethernet,
+    ) -> Result<eth::phy::LinkStatus, PhyError> {
+        use eth::phy::Phy;
+        if port != 0 {
+            return Err(PhyError::InvalidPort);
+        }
+        let phy = eth::phy::Lan87xx(eth::phy::SmiPhy { eth, addr: PHYADDR });
+        phy.link_status().map_err(|e| match e {})
+    }
+
     fn phy_read(
         &mut self,
         port: u8,
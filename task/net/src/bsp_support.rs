@@ -16,6 +16,7 @@
 
 use drv_stm32h7_eth as eth;
 use drv_stm32xx_sys_api::Sys;
+use eth::phy;
 use task_net_api::PhyError;
 use vsc7448_pac::types::PhyRegisterAddress;
 
@@ -49,6 +50,61 @@ cfg_if::cfg_if! {
     }
 }
 
+/// The basic registers of one of the KSZ8463's PHYs, which the switch exposes
+/// among its own.
+#[cfg(feature = "ksz8463")]
+pub struct KszPhy<'a> {
+    pub ksz8463: &'a Ksz8463,
+    pub port: ksz8463::KszPhyPort,
+}
+
+#[cfg(feature = "ksz8463")]
+impl KszPhy<'_> {
+    fn register(&self, reg: u8) -> Result<ksz8463::Register, PhyError> {
+        use ksz8463::Register;
+        match reg {
+            phy::BMCR => Ok(Register::PxMBCR(self.port)),
+            phy::BMSR => Ok(Register::PxMBSR(self.port)),
+            phy::ANAR => Ok(Register::PxANAR(self.port)),
+            phy::ANLPAR => Ok(Register::PxANLPR(self.port)),
+            _ => Err(PhyError::NotImplemented),
+        }
+    }
+}
+
+#[cfg(feature = "ksz8463")]
+impl phy::PhyRegs for KszPhy<'_> {
+    type Error = PhyError;
+
+    fn read(&self, reg: u8) -> Result<u16, PhyError> {
+        self.ksz8463
+            .read(self.register(reg)?)
+            .map_err(|_| PhyError::Other)
+    }
+
+    fn write(&self, reg: u8, value: u16) -> Result<(), PhyError> {
+        self.ksz8463
+            .write(self.register(reg)?, value)
+            .map_err(|_| PhyError::Other)
+    }
+}
+
+/// Reads the link state of the KSZ8463 PHY facing SP port `port`; the SP's
+/// ports are numbered after the switch's.
+#[cfg(feature = "ksz8463")]
+pub fn ksz8463_link_status(
+    ksz8463: &Ksz8463,
+    port: u8,
+) -> Result<phy::LinkStatus, PhyError> {
+    use eth::phy::Phy;
+    let port = match port {
+        0 => ksz8463::KszPhyPort::One,
+        1 => ksz8463::KszPhyPort::Two,
+        _ => return Err(PhyError::InvalidPort),
+    };
+    phy::Generic(KszPhy { ksz8463, port }).link_status()
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[cfg(feature = "mgmt")]
@@ -66,6 +122,11 @@ pub trait Bsp: Sized {
     /// `wake`. If you change one, change the other.
     const WAKE_INTERVAL: Option<u64> = None;
 
+    /// How often to poll the link state of the SP's ports. `None` (the
+    /// default) tells the netstack not to, which goes along with the default
+    /// impl for `link_status`.
+    const LINK_POLL_INTERVAL: Option<u64> = None;
+
     /// Opportunity to do any work before the Ethernet peripheral is turned on.
     fn preinit();
 
@@ -85,6 +146,16 @@ pub trait Bsp: Sized {
         panic!();
     }
 
+    /// Reads the link state of SP port `port`, numbered from 0 (see
+    /// `SpPort`).
+    fn link_status(
+        &self,
+        _port: u8,
+        _eth: &eth::Ethernet,
+    ) -> Result<phy::LinkStatus, PhyError> {
+        Err(PhyError::NotImplemented)
+    }
+
     fn phy_read(
         &mut self,
         port: u8,
@@ -0,0 +1,81 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! DHCPv4 client.
+//!
+//! With the `dhcp` feature, the net task runs a DHCP client on one interface
+//! (the VLAN named in `config.net.dhcp`, if there are VLANs) alongside the
+//! IPv6 link-local address that it always has. smoltcp's DHCP socket does the
+//! work of acquiring a lease and renewing it before it runs out, and tells us
+//! when the result changes; we apply each change to the interface and post to
+//! the tasks listed in `on-address-change`, who can then ask for the new
+//! addressing.
+//!
+//! UDP sockets remain bound to the IPv6 address; the IPv4 address is of use to
+//! TCP sockets, which listen on every address, and answers pings.
+
+use crate::generated::DHCP_NOTIFY;
+use smoltcp::iface::{Interface, SocketHandle};
+use smoltcp::socket::dhcpv4;
+use smoltcp::wire::{IpCidr, Ipv4Address};
+use task_net_api::Ipv4Config;
+use userlib::{sys_post, sys_refresh_task_id, UnwrapLite};
+
+pub struct Dhcp {
+    pub handle: SocketHandle,
+    /// The addressing we've bound, if any
+    pub config: Option<Ipv4Config>,
+    generation: u32,
+}
+
+impl Dhcp {
+    pub fn new(handle: SocketHandle) -> Self {
+        Self {
+            handle,
+            config: None,
+            generation: 0,
+        }
+    }
+
+    /// Applies any change reported by the DHCP socket to `iface`, and tells
+    /// the tasks that want to know. Returns true if anything changed.
+    pub fn poll(
+        &mut self,
+        socket: &mut dhcpv4::Socket<'_>,
+        iface: &mut Interface,
+    ) -> bool {
+        let bound = match socket.poll() {
+            None => return false,
+            Some(dhcpv4::Event::Configured(c)) => Some((c.address, c.router)),
+            Some(dhcpv4::Event::Deconfigured) => None,
+        };
+
+        iface.update_ip_addrs(|addrs| {
+            addrs.retain(|a| !matches!(a, IpCidr::Ipv4(_)));
+            if let Some((cidr, _)) = bound {
+                addrs.push(IpCidr::Ipv4(cidr)).unwrap_lite();
+            }
+        });
+        iface.routes_mut().remove_default_ipv4_route();
+        if let Some((_, Some(router))) = bound {
+            iface
+                .routes_mut()
+                .add_default_ipv4_route(router)
+                .unwrap_lite();
+        }
+
+        self.generation = self.generation.wrapping_add(1);
+        self.config = bound.map(|(cidr, router)| Ipv4Config {
+            address: cidr.address().into(),
+            prefix_len: cidr.prefix_len(),
+            router: router.unwrap_or(Ipv4Address::UNSPECIFIED).into(),
+            generation: self.generation,
+        });
+
+        for &(task_id, notification) in &DHCP_NOTIFY {
+            sys_post(sys_refresh_task_id(task_id), notification);
+        }
+        true
+    }
+}
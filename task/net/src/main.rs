@@ -9,8 +9,13 @@ pub mod pins;
 
 mod bsp_support;
 mod buf;
+#[cfg(feature = "dhcp")]
+mod dhcp;
+mod mdns;
 mod miim_bridge;
+mod mux;
 mod server;
+mod tcp;
 
 // Select the BSP based on the target board
 #[cfg_attr(
@@ -57,9 +62,10 @@ pub(crate) mod mgmt;
 
 mod idl {
     use task_net_api::{
-        KszError, KszMacTableEntry, LargePayloadBehavior, MacAddress,
-        MacAddressBlock, ManagementCounters, ManagementLinkStatus, MgmtError,
-        PhyError, SocketName, UdpMetadata, VLanId,
+        Address, DhcpError, Ipv4Config, KszError, KszMacTableEntry,
+        LargePayloadBehavior, LinkStatus, MacAddress, MacAddressBlock,
+        ManagementCounters, ManagementLinkStatus, MgmtError, PhyError,
+        SocketName, TcpSocketName, TcpState, UdpMetadata, VLanId,
     };
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
@@ -220,11 +226,13 @@ fn main() -> ! {
     // Turn on our IRQ.
     userlib::sys_irq_control(notifications::ETH_IRQ_MASK, true);
 
-    // We use only one timer, but we're using a multitimer in case we need to
-    // add a second one again (we previously had a second one for the watchdog):
     #[derive(Copy, Clone, Enum)]
     enum Timers {
         Wake,
+        /// The stack's next deadline, for TCP retransmission and the like.
+        Stack,
+        /// Checking the PHYs for link changes.
+        Link,
     }
     let mut multitimer =
         Multitimer::<Timers>::new(notifications::WAKE_TIMER_BIT);
@@ -241,6 +249,14 @@ fn main() -> ! {
         );
     }
 
+    if let Some(link_interval) = BspImpl::LINK_POLL_INTERVAL {
+        multitimer.set_timer(
+            Timers::Link,
+            now,
+            Some(Repeat::AfterWake(link_interval)),
+        );
+    }
+
     // Ensure that sockets are woken at least once at startup, so that anyone
     // who was waiting to hear back on their TX queue becoming non-full will
     // snap out of it.
@@ -270,8 +286,14 @@ fn main() -> ! {
                         server.wake();
                         // timer is set to auto-repeat
                     }
+                    // Our next trip around the loop will poll the stack.
+                    Timers::Stack => (),
+                    Timers::Link => server.poll_links(sys_get_timer().now),
                 }
             }
+            if let Some(t) = server.poll_at(now) {
+                multitimer.set_timer(Timers::Stack, t, None);
+            }
             let mut msgbuf = [0u8; idl::INCOMING_SIZE];
             idol_runtime::dispatch(&mut msgbuf, &mut server);
         }
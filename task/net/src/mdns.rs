@@ -0,0 +1,436 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! mDNS / DNS-SD responder.
+//!
+//! When `config.net.mdns` is present, the net task answers multicast DNS
+//! queries on each trusted VLAN, so that boards can be found on a LAN by name
+//! rather than by serial console and bookkeeping. It advertises:
+//!
+//! - `<hostname>-xxxxxx.local`, with an AAAA record for the VLAN's link-local
+//!   address (`xxxxxx` being the bottom half of the MAC address, so that
+//!   boards of a kind can be told apart);
+//! - for each socket listed, an instance `<hostname>-xxxxxx-<socket>` of the
+//!   configured service type, with an SRV record giving the socket's port;
+//! - the service type itself, for browsers enumerating services.
+//!
+//! Records are announced twice when the stack starts, and thereafter sent in
+//! answer to queries. Every record we hold goes into every answer: there are
+//! few enough of them that this costs little, and it saves the querier a
+//! round trip for each.
+
+use crate::generated::{
+    MDNS_HOSTNAME, MDNS_INSTANCES, MDNS_INSTANCE_COUNT, MDNS_SERVICE,
+};
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::udp;
+use smoltcp::wire::{EthernetAddress, IpEndpoint, Ipv6Address};
+
+pub const PORT: u16 = 5353;
+
+/// `ff02::fb`
+const GROUP: Ipv6Address = Ipv6Address::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+
+/// Record lifetime in seconds, as RFC 6762 recommends for host records.
+const TTL: u32 = 120;
+/// Record lifetime in replies to legacy (non-mDNS) resolvers, who must not
+/// cache them for long.
+const LEGACY_TTL: u32 = 10;
+
+const ANNOUNCEMENTS: u8 = 2;
+const ANNOUNCE_INTERVAL_MS: u64 = 1000;
+
+/// Big enough for a response with a handful of instances, and small enough
+/// to not need fragmenting.
+pub const MAX_MESSAGE: usize = 512;
+const MAX_NAME: usize = 96;
+
+const HEADER_LEN: usize = 12;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+
+const CLASS_IN: u16 = 1;
+/// In a record, marks it as the only one of its name and type; in a
+/// question, asks for a unicast reply (which we don't give).
+const CLASS_TOP_BIT: u16 = 0x8000;
+
+/// A domain name in wire format.
+#[derive(Copy, Clone)]
+pub struct Name {
+    buf: [u8; MAX_NAME],
+    len: usize,
+}
+
+impl Name {
+    const fn empty() -> Self {
+        Self {
+            buf: [0; MAX_NAME],
+            len: 0,
+        }
+    }
+
+    /// Builds a name from labels, each given as pieces to be joined.
+    fn new(labels: &[&[&[u8]]]) -> Self {
+        let mut name = Self::empty();
+        for pieces in labels {
+            let start = name.len;
+            name.len += 1;
+            for p in *pieces {
+                name.buf[name.len..][..p.len()].copy_from_slice(p);
+                name.len += p.len();
+            }
+            name.buf[start] = (name.len - start - 1) as u8;
+        }
+        // The root label.
+        name.len += 1;
+        name
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    fn matches(&self, other: &Name) -> bool {
+        // Label lengths are under 64, so can't be mistaken for letters.
+        self.as_bytes().eq_ignore_ascii_case(other.as_bytes())
+    }
+
+    /// Reads a name starting at `pos` in `msg`, following compression
+    /// pointers, and returns it with the position just past it.
+    fn parse(msg: &[u8], mut pos: usize) -> Option<(Self, usize)> {
+        let mut name = Self::empty();
+        let mut end = None;
+        // Bound the pointers we'll follow, lest they loop.
+        let mut hops = 0;
+        loop {
+            let len = *msg.get(pos)? as usize;
+            match len & 0xC0 {
+                0x00 if len == 0 => {
+                    name.buf[name.len] = 0;
+                    name.len += 1;
+                    return Some((name, end.unwrap_or(pos + 1)));
+                }
+                0x00 => {
+                    let label = msg.get(pos..pos + 1 + len)?;
+                    // Leave room for the root label.
+                    if name.len + label.len() >= MAX_NAME {
+                        return None;
+                    }
+                    name.buf[name.len..][..label.len()].copy_from_slice(label);
+                    name.len += label.len();
+                    pos += label.len();
+                }
+                0xC0 => {
+                    hops += 1;
+                    if hops > 8 {
+                        return None;
+                    }
+                    let lo = *msg.get(pos + 1)? as usize;
+                    end.get_or_insert(pos + 2);
+                    pos = ((len & 0x3F) << 8) | lo;
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// The names we answer to, which are the same on every VLAN.
+pub struct Names {
+    host: Name,
+    service: Name,
+    enumeration: Name,
+    instances: [Name; MDNS_INSTANCE_COUNT],
+}
+
+impl Names {
+    pub fn new(mac: EthernetAddress) -> Self {
+        let mut suffix = [b'-'; 7];
+        for (i, b) in mac.0[3..].iter().enumerate() {
+            const HEX: &[u8; 16] = b"0123456789abcdef";
+            suffix[1 + 2 * i] = HEX[usize::from(b >> 4)];
+            suffix[2 + 2 * i] = HEX[usize::from(b & 0xF)];
+        }
+        let host = MDNS_HOSTNAME.as_bytes();
+        // The service type is two labels, e.g. `_hubris._udp`.
+        let (app, proto) = MDNS_SERVICE.split_once('.').unwrap();
+        let (app, proto) = (app.as_bytes(), proto.as_bytes());
+
+        Self {
+            host: Name::new(&[&[host, &suffix], &[b"local"]]),
+            service: Name::new(&[&[app], &[proto], &[b"local"]]),
+            enumeration: Name::new(&[
+                &[b"_services"],
+                &[b"_dns-sd"],
+                &[b"_udp"],
+                &[b"local"],
+            ]),
+            instances: MDNS_INSTANCES.map(|(socket, _)| {
+                Name::new(&[
+                    &[host, &suffix, b"-", socket.as_bytes()],
+                    &[app],
+                    &[proto],
+                    &[b"local"],
+                ])
+            }),
+        }
+    }
+}
+
+/// Which of our records a query is after.
+#[derive(Copy, Clone, Default)]
+struct Wanted {
+    enumeration: bool,
+    service: bool,
+    instances: u32,
+    host: bool,
+}
+
+impl Wanted {
+    fn any(&self) -> bool {
+        self.enumeration || self.service || self.instances != 0 || self.host
+    }
+
+    fn all() -> Self {
+        Self {
+            enumeration: true,
+            service: true,
+            instances: !0,
+            host: true,
+        }
+    }
+}
+
+/// The responder on one VLAN.
+pub struct Responder {
+    pub handle: SocketHandle,
+    addr: Ipv6Address,
+    announcements_left: u8,
+    next_announce: u64,
+}
+
+impl Responder {
+    pub fn new(handle: SocketHandle, addr: Ipv6Address) -> Self {
+        Self {
+            handle,
+            addr,
+            announcements_left: ANNOUNCEMENTS,
+            next_announce: 0,
+        }
+    }
+
+    /// Starts announcing afresh, as when the link comes (back) up.
+    pub fn reannounce(&mut self, now: u64) {
+        self.announcements_left = ANNOUNCEMENTS;
+        self.next_announce = now;
+    }
+
+    /// Returns when we next need to be polled to make an announcement.
+    pub fn poll_at(&self) -> Option<u64> {
+        (self.announcements_left > 0).then_some(self.next_announce)
+    }
+
+    /// Answers any queries that have arrived, and makes any announcement
+    /// that's due. If `trusted` is false, queries are discarded unanswered,
+    /// and nothing is announced. Returns true if anything was received or
+    /// sent.
+    pub fn poll(
+        &mut self,
+        names: &Names,
+        socket: &mut udp::Socket<'_>,
+        now: u64,
+        trusted: bool,
+    ) -> bool {
+        let mut any = false;
+        let mut msg = [0; MAX_MESSAGE];
+
+        while let Ok((n, from)) = socket.recv_slice(&mut msg) {
+            any = true;
+            if !trusted {
+                continue;
+            }
+            // Queries not from port 5353 come from simple resolvers, which
+            // expect a reply of their own, as for unicast DNS.
+            let legacy = from.port != PORT;
+            let Some((id, wanted)) = parse_query(names, &msg[..n]) else {
+                continue;
+            };
+            let to = if legacy { from } else { (GROUP, PORT).into() };
+            self.send(names, socket, &mut msg, id, wanted, legacy, to);
+        }
+
+        if trusted && self.announcements_left > 0 && now >= self.next_announce {
+            let to = (GROUP, PORT).into();
+            let wanted = Wanted::all();
+            self.send(names, socket, &mut msg, 0, wanted, false, to);
+            self.announcements_left -= 1;
+            self.next_announce = now + ANNOUNCE_INTERVAL_MS;
+            any = true;
+        }
+        any
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn send(
+        &self,
+        names: &Names,
+        socket: &mut udp::Socket<'_>,
+        buf: &mut [u8; MAX_MESSAGE],
+        id: u16,
+        wanted: Wanted,
+        legacy: bool,
+        to: IpEndpoint,
+    ) {
+        let mut w = Writer { buf, pos: 0 };
+        if self
+            .write_response(names, &mut w, id, wanted, legacy)
+            .is_some()
+        {
+            // If the socket's queue is full, the querier will ask again.
+            let _ = socket.send_slice(&w.buf[..w.pos], to);
+        }
+    }
+
+    fn write_response(
+        &self,
+        names: &Names,
+        w: &mut Writer<'_>,
+        id: u16,
+        wanted: Wanted,
+        legacy: bool,
+    ) -> Option<()> {
+        let (ttl, unique) = if legacy {
+            (LEGACY_TTL, 0)
+        } else {
+            (TTL, CLASS_TOP_BIT)
+        };
+
+        w.pos = HEADER_LEN;
+        let mut count = 0u16;
+        if wanted.enumeration {
+            w.record(&names.enumeration, TYPE_PTR, 0, ttl, |w| {
+                w.put(names.service.as_bytes())
+            })?;
+            count += 1;
+        }
+        for (i, (instance, &(_, port))) in
+            names.instances.iter().zip(&MDNS_INSTANCES).enumerate()
+        {
+            if wanted.service {
+                w.record(&names.service, TYPE_PTR, 0, ttl, |w| {
+                    w.put(instance.as_bytes())
+                })?;
+                count += 1;
+            }
+            if wanted.service || wanted.instances & (1 << i) != 0 {
+                w.record(instance, TYPE_SRV, unique, ttl, |w| {
+                    // Priority and weight, then the port and host.
+                    w.put(&[0; 4])?;
+                    w.put(&port.to_be_bytes())?;
+                    w.put(names.host.as_bytes())
+                })?;
+                // DNS-SD requires a TXT record, if only an empty one.
+                w.record(instance, TYPE_TXT, unique, ttl, |w| w.put(&[0]))?;
+                count += 2;
+            }
+        }
+        if wanted.host || wanted.service || wanted.instances != 0 {
+            w.record(&names.host, TYPE_AAAA, unique, ttl, |w| {
+                w.put(self.addr.as_bytes())
+            })?;
+            count += 1;
+        }
+
+        let flags = FLAG_RESPONSE | FLAG_AUTHORITATIVE;
+        w.buf[0..2].copy_from_slice(&id.to_be_bytes());
+        w.buf[2..4].copy_from_slice(&flags.to_be_bytes());
+        w.buf[4..6].fill(0);
+        w.buf[6..8].copy_from_slice(&count.to_be_bytes());
+        w.buf[8..12].fill(0);
+        Some(())
+    }
+}
+
+/// Works out which of our records a query wants. Returns the query's ID and
+/// that, or `None` if it isn't a query or wants nothing we have.
+fn parse_query(names: &Names, msg: &[u8]) -> Option<(u16, Wanted)> {
+    let header = msg.get(..HEADER_LEN)?;
+    let id = u16::from_be_bytes([header[0], header[1]]);
+    let flags = u16::from_be_bytes([header[2], header[3]]);
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    if flags & FLAG_RESPONSE != 0 {
+        return None;
+    }
+
+    let mut wanted = Wanted::default();
+    let mut pos = HEADER_LEN;
+    for _ in 0..questions {
+        let (name, next) = Name::parse(msg, pos)?;
+        let fixed = msg.get(next..next + 4)?;
+        let qtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let qclass = u16::from_be_bytes([fixed[2], fixed[3]]) & !CLASS_TOP_BIT;
+        pos = next + 4;
+        if qclass != CLASS_IN && qclass != TYPE_ANY {
+            continue;
+        }
+
+        let is = |t| qtype == t || qtype == TYPE_ANY;
+        if is(TYPE_PTR) && name.matches(&names.enumeration) {
+            wanted.enumeration = true;
+        }
+        if is(TYPE_PTR) && name.matches(&names.service) {
+            wanted.service = true;
+        }
+        for (i, instance) in names.instances.iter().enumerate() {
+            if (is(TYPE_SRV) || is(TYPE_TXT)) && name.matches(instance) {
+                wanted.instances |= 1 << i;
+            }
+        }
+        if is(TYPE_AAAA) && name.matches(&names.host) {
+            wanted.host = true;
+        }
+    }
+    wanted.any().then_some((id, wanted))
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8; MAX_MESSAGE],
+    pos: usize,
+}
+
+impl Writer<'_> {
+    fn put(&mut self, bytes: &[u8]) -> Option<()> {
+        let dest = self.buf.get_mut(self.pos..self.pos + bytes.len())?;
+        dest.copy_from_slice(bytes);
+        self.pos += bytes.len();
+        Some(())
+    }
+
+    /// Writes a resource record, with data written by `data`.
+    fn record(
+        &mut self,
+        name: &Name,
+        rtype: u16,
+        class_bits: u16,
+        ttl: u32,
+        data: impl FnOnce(&mut Self) -> Option<()>,
+    ) -> Option<()> {
+        self.put(name.as_bytes())?;
+        self.put(&rtype.to_be_bytes())?;
+        self.put(&(CLASS_IN | class_bits).to_be_bytes())?;
+        self.put(&ttl.to_be_bytes())?;
+        let len_at = self.pos;
+        self.put(&[0; 2])?;
+        data(self)?;
+        let len = (self.pos - len_at - 2) as u16;
+        self.buf[len_at..len_at + 2].copy_from_slice(&len.to_be_bytes());
+        Some(())
+    }
+}
@@ -0,0 +1,76 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Per-client receive queues.
+//!
+//! Each UDP socket has an owner and, optionally, further clients (see
+//! `clients` in the socket's config). Rather than leaving incoming packets in
+//! the socket until its owner gets around to asking for them, we move each
+//! packet out of the socket as soon as the stack has received it, and copy it
+//! into the receive queue of every client of the socket. Each client's queue
+//! is sized independently, so that one client falling behind fills only its
+//! own queue: once that's full, further packets for it are dropped -- and
+//! counted, so the lag is visible -- while the socket and its other clients
+//! carry on.
+
+use crate::generated::{CLIENTS, CLIENT_COUNT};
+use counters::CounterSet;
+use smoltcp::storage::{PacketBuffer, PacketMetadata};
+use task_net_api::UdpMetadata;
+use userlib::TaskId;
+
+pub type RxMetadata = PacketMetadata<UdpMetadata>;
+pub type RxQueue<'a> = PacketBuffer<'a, UdpMetadata>;
+
+#[derive(Copy, Clone, PartialEq, counters::Count)]
+pub enum ClientEvent {
+    /// A packet was put in the client's queue.
+    Queued,
+    /// A packet was dropped because the client's queue was full.
+    Dropped,
+    /// A packet was discarded because it was larger than the buffer the
+    /// client offered for it.
+    Discarded,
+}
+
+type ClientCounters = <ClientEvent as counters::Count>::Counters;
+
+/// Receive events, by client.
+#[used]
+static CLIENT_COUNTERS: [ClientCounters; CLIENT_COUNT] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NEW: ClientCounters = <ClientEvent as counters::Count>::NEW_COUNTERS;
+    [NEW; CLIENT_COUNT]
+};
+
+pub fn count(client: usize, event: ClientEvent) {
+    counters::count!(CLIENT_COUNTERS[client], event);
+}
+
+/// Returns the number of packets dropped for `client` because its queue was
+/// full.
+pub fn dropped(client: usize) -> u32 {
+    // The counters are iterated over in the order of `ClientEvent`.
+    CLIENT_COUNTERS[client]
+        .iter()
+        .nth(1)
+        .map(|(_, n)| n)
+        .unwrap_or(0)
+}
+
+/// Returns the index of `task`'s client of socket `socket`, if it is one.
+pub fn client_index(socket: usize, task: TaskId) -> Option<usize> {
+    CLIENTS
+        .iter()
+        .position(|&(s, t, _)| s == socket && t.index() == task.index())
+}
+
+/// Returns the indices of the clients of socket `socket`.
+pub fn clients_of(socket: usize) -> impl Iterator<Item = usize> {
+    CLIENTS
+        .iter()
+        .enumerate()
+        .filter(move |(_, &(s, _, _))| s == socket)
+        .map(|(i, _)| i)
+}
@@ -3,18 +3,26 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::bsp_support;
-use crate::generated::{self, SOCKET_COUNT};
+use crate::generated::{
+    self, CLIENTS, CLIENT_COUNT, SOCKET_COUNT, TCP_SOCKET_COUNT,
+};
+use crate::mdns;
+use crate::mux::{self, ClientEvent, RxQueue};
 use crate::notifications;
+use crate::tcp::{self, TcpSlot};
 use crate::{idl, link_local_iface_addr, MacAddressBlock};
 
 use drv_stm32h7_eth as eth;
 use enum_map::Enum;
+use eth::phy::{self, LinkEvent, LinkMonitor};
 use idol_runtime::{ClientError, RequestError};
 use ringbuf::{counted_ringbuf, ringbuf_entry};
 use task_net_api::{
-    KszError, KszMacTableEntry, LargePayloadBehavior, MacAddress,
+    Address, DhcpError, Ipv4Config, KszError, KszMacTableEntry,
+    LargePayloadBehavior, LinkSpeed, LinkStatus, MacAddress,
     ManagementCounters, ManagementLinkStatus, MgmtError, PhyError, RecvError,
-    SendError, SocketName, TrustError, UdpMetadata, VLanId,
+    SendError, SocketName, TcpError, TcpSocketName, TcpState, TrustError,
+    UdpMetadata, VLanId,
 };
 
 #[allow(dead_code)]
@@ -43,6 +51,19 @@ enum Trace {
         #[count(children)]
         vid: VLanId,
     },
+    TcpState {
+        socket: u8,
+        state: TcpState,
+    },
+    LinkUp {
+        port: u8,
+    },
+    LinkDown {
+        port: u8,
+    },
+    LinkBounced {
+        port: u8,
+    },
 }
 counted_ringbuf!(Trace, 16, Trace::None);
 
@@ -51,7 +72,7 @@ use heapless::Vec;
 use smoltcp::iface::{Interface, SocketHandle, SocketStorage};
 use smoltcp::socket::udp;
 use smoltcp::wire::{EthernetAddress, Ipv6Cidr};
-use userlib::{sys_get_timer, sys_post, sys_refresh_task_id, UnwrapLite};
+use userlib::{sys_post, sys_refresh_task_id, UnwrapLite};
 use zerocopy::byteorder::U16;
 
 /// Implementation of the Net Idol interface.
@@ -80,6 +101,130 @@ where
         self.net_send_packet(msg, socket, metadata, payload)
     }
 
+    fn tcp_listen(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: TcpSocketName,
+    ) -> Result<(), RequestError<TcpError>> {
+        let port = generated::TCP_SOCKET_PORTS[socket.index()];
+        let (socket, _) = self.tcp_socket(msg, socket)?;
+        if port == 0 {
+            return Err(TcpError::Unaddressable.into());
+        }
+        socket.listen(port).map_err(|e| match e {
+            smoltcp::socket::tcp::ListenError::InvalidState => {
+                TcpError::InvalidState.into()
+            }
+            smoltcp::socket::tcp::ListenError::Unaddressable => {
+                TcpError::Unaddressable.into()
+            }
+        })
+    }
+
+    fn tcp_connect(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: TcpSocketName,
+        addr: Address,
+        port: u16,
+    ) -> Result<(), RequestError<TcpError>> {
+        let local_port = match generated::TCP_SOCKET_PORTS[socket.index()] {
+            0 => self.ephemeral_port(),
+            p => p,
+        };
+        let (socket, cx) = self.tcp_socket(msg, socket)?;
+        let remote: smoltcp::wire::IpAddress = addr.into();
+        socket
+            .connect(cx, (remote, port), local_port)
+            .map_err(|e| match e {
+                smoltcp::socket::tcp::ConnectError::InvalidState => {
+                    TcpError::InvalidState.into()
+                }
+                smoltcp::socket::tcp::ConnectError::Unaddressable => {
+                    TcpError::Unaddressable.into()
+                }
+            })
+    }
+
+    fn tcp_send(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: TcpSocketName,
+        payload: idol_runtime::Leased<idol_runtime::R, [u8]>,
+    ) -> Result<u32, RequestError<TcpError>> {
+        let index = socket.index();
+        let (socket, _) = self.tcp_socket(msg, socket)?;
+        let r = tcp::send(socket, payload);
+        self.tcp[index].waiting_to_send =
+            matches!(r, Err(RequestError::Runtime(TcpError::QueueFull)));
+        r
+    }
+
+    fn tcp_recv(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: TcpSocketName,
+        payload: idol_runtime::Leased<idol_runtime::W, [u8]>,
+    ) -> Result<u32, RequestError<TcpError>> {
+        let (socket, _) = self.tcp_socket(msg, socket)?;
+        tcp::recv(socket, payload)
+    }
+
+    fn tcp_close(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: TcpSocketName,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        let (socket, _) = self.tcp_socket(msg, socket)?;
+        socket.close();
+        Ok(())
+    }
+
+    fn tcp_abort(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: TcpSocketName,
+    ) -> Result<(), RequestError<core::convert::Infallible>> {
+        let (socket, _) = self.tcp_socket(msg, socket)?;
+        socket.abort();
+        Ok(())
+    }
+
+    fn tcp_state(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: TcpSocketName,
+    ) -> Result<TcpState, RequestError<core::convert::Infallible>> {
+        let (socket, _) = self.tcp_socket(msg, socket)?;
+        Ok(tcp::state(socket.state()))
+    }
+
+    #[cfg(feature = "dhcp")]
+    fn ipv4_config(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<Ipv4Config, RequestError<DhcpError>> {
+        self.dhcp.config.ok_or_else(|| DhcpError::Unbound.into())
+    }
+
+    #[cfg(not(feature = "dhcp"))]
+    fn ipv4_config(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<Ipv4Config, RequestError<DhcpError>> {
+        Err(DhcpError::NotAvailable.into())
+    }
+
+    fn rx_dropped(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: SocketName,
+    ) -> Result<u32, RequestError<core::convert::Infallible>> {
+        let client = mux::client_index(socket as usize, msg.sender)
+            .ok_or_else(|| ClientError::AccessViolation.fail())?;
+        Ok(mux::dropped(client))
+    }
+
     fn smi_read(
         &mut self,
         _msg: &userlib::RecvMessage,
@@ -103,6 +248,26 @@ where
         Ok(())
     }
 
+    fn link_status(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        port: u8,
+    ) -> Result<LinkStatus, RequestError<PhyError>> {
+        let now = userlib::sys_get_timer().now;
+        let s = self.poll_link(port, now)?;
+        Ok(LinkStatus {
+            up: s.up,
+            autoneg_complete: s.autoneg_complete,
+            speed: match s.speed {
+                phy::Speed::Mbps10 => LinkSpeed::Mbps10,
+                phy::Speed::Mbps100 => LinkSpeed::Mbps100,
+                phy::Speed::Mbps1000 => LinkSpeed::Mbps1000,
+            },
+            full_duplex: s.full_duplex,
+            flaps: self.links[usize::from(port)].flaps(),
+        })
+    }
+
     fn read_phy_reg(
         &mut self,
         _msg: &userlib::RecvMessage,
@@ -331,7 +496,14 @@ where
     eth: &'a eth::Ethernet,
 
     vlan_state: enum_map::EnumMap<VLanId, VLanState<E>>,
-    client_waiting_to_send: [bool; SOCKET_COUNT],
+    rx_queues: [RxQueue<'static>; CLIENT_COUNT],
+    client_waiting_to_send: [bool; CLIENT_COUNT],
+    tcp: [TcpSlot; TCP_SOCKET_COUNT],
+    next_ephemeral_port: u16,
+    #[cfg(feature = "dhcp")]
+    dhcp: crate::dhcp::Dhcp,
+    mdns_names: mdns::Names,
+    links: [LinkMonitor; generated::PORT_COUNT],
     bsp: B,
 
     mac: EthernetAddress,
@@ -358,6 +530,8 @@ where
     iface: &'static mut Interface,
     device: E,
     trust: VLanTrust,
+    /// The mDNS responder, if `config.net.mdns` is present
+    mdns: Option<mdns::Responder>,
 
     /// Used to detect stuck queues (due to smoltcp#594)
     queue_watchdog: [QueueWatchdog; SOCKET_COUNT],
@@ -379,6 +553,17 @@ impl<E: DeviceExt> VLanState<E> {
             }
         }
     }
+
+    /// Answers any mDNS queries, and makes any announcement that's due.
+    fn poll_mdns(&mut self, names: &mdns::Names, now: u64) -> bool {
+        let trusted = self.check_trust(now);
+        let Some(responder) = &mut self.mdns else {
+            return false;
+        };
+        let socket =
+            self.socket_set.get_mut::<udp::Socket<'_>>(responder.handle);
+        responder.poll(names, socket, now, trusted)
+    }
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -494,6 +679,12 @@ where
             mac[3..].copy_from_slice(&next_mac.to_be_bytes()[1..]);
         }
 
+        let mut tcp_sockets = generated::construct_tcp_sockets().map(Some);
+        let mut tcp_handles = [None; TCP_SOCKET_COUNT];
+        #[cfg(feature = "dhcp")]
+        let mut dhcp_handle = None;
+        let mut mdns_sockets = generated::construct_mdns_sockets().into_iter();
+
         // Each of these is replicated once per VID. Loop over them in lockstep.
         for (i, (sockets, storage)) in zip(sockets.0, storage).enumerate() {
             #[cfg(feature = "vlan")]
@@ -545,6 +736,23 @@ where
                     .bind((ipv6_addr, port))
                     .unwrap_lite();
             }
+            // TCP sockets live on just one VLAN apiece.
+            for (j, socket) in tcp_sockets.iter_mut().enumerate() {
+                if generated::TCP_SOCKET_VLANS[j] == vlan_id {
+                    let socket = socket.take().unwrap_lite();
+                    tcp_handles[j] = Some(socket_set.add(socket));
+                }
+            }
+            let mdns = mdns_sockets.next().unwrap_lite().into_iter().next();
+            let mdns = mdns.map(|mut socket| {
+                socket.bind((ipv6_addr, mdns::PORT)).unwrap_lite();
+                mdns::Responder::new(socket_set.add(socket), ipv6_addr)
+            });
+            #[cfg(feature = "dhcp")]
+            if vlan_id == generated::DHCP_VLAN {
+                let socket = smoltcp::socket::dhcpv4::Socket::new();
+                dhcp_handle = Some(socket_set.add(socket));
+            }
 
             vlan_state
                 .push(VLanState {
@@ -553,6 +761,7 @@ where
                     iface,
                     device,
                     trust,
+                    mdns,
                     socket_set,
                     queue_watchdog: [QueueWatchdog::Nominal; SOCKET_COUNT],
                 })
@@ -561,9 +770,23 @@ where
 
         Self {
             eth,
-            // The 'true' here is load-bearing: it ensures that sockets receive
+            rx_queues: generated::construct_rx_queues(),
+            // The 'true' here is load-bearing: it ensures that clients receive
             // a notification on stack restart.
-            client_waiting_to_send: [true; SOCKET_COUNT],
+            client_waiting_to_send: [true; CLIENT_COUNT],
+            tcp: core::array::from_fn(|j| TcpSlot {
+                vid: generated::TCP_SOCKET_VLANS[j],
+                handle: tcp_handles[j].unwrap_lite(),
+                state: smoltcp::socket::tcp::State::Closed,
+                waiting_to_send: false,
+            }),
+            next_ephemeral_port: tcp::EPHEMERAL_PORT_START,
+            #[cfg(feature = "dhcp")]
+            dhcp: crate::dhcp::Dhcp::new(dhcp_handle.unwrap_lite()),
+            mdns_names: mdns::Names::new(EthernetAddress::from_bytes(
+                &mac_address_block.base_mac,
+            )),
+            links: Default::default(),
             vlan_state: enum_map::EnumMap::from_array(
                 vlan_state.into_array().unwrap_lite(),
             ),
@@ -592,41 +815,227 @@ where
             );
             // Test and clear our receive activity flag.
             ip |= vlan.check_socket_watchdog();
+            ip |= vlan.poll_mdns(&self.mdns_names, t);
+        }
+        #[cfg(feature = "dhcp")]
+        {
+            let vlan = &mut self.vlan_state[generated::DHCP_VLAN];
+            let socket = vlan
+                .socket_set
+                .get_mut::<smoltcp::socket::dhcpv4::Socket<'_>>(
+                    self.dhcp.handle,
+                );
+            ip |= self.dhcp.poll(socket, vlan.iface);
         }
+        ip |= self.deliver_packets(t);
 
         crate::Activity { ip }
     }
 
-    /// Iterate over sockets, waking any that can do work.
+    /// Moves every packet that the stack has received out of its socket, and
+    /// into the receive queues of the socket's clients. Returns true if any
+    /// packets were moved.
+    fn deliver_packets(&mut self, now: u64) -> bool {
+        let mut any = false;
+        for vlan in self.vlan_state.values_mut() {
+            for socket_index in 0..SOCKET_COUNT {
+                let h = vlan.socket_handles[socket_index];
+                // Decide whether to pass packets on to clients, depending on
+                // whether we trust the VLAN or not.  Sockets can be configured
+                // to accept even untrusted packets (e.g. control_plane_agent
+                // needs to receive an unlock message).
+                let trust = vlan.check_trust(now)
+                    | generated::SOCKET_ALLOW_UNTRUSTED[socket_index];
+                let socket = vlan.socket_set.get_mut::<udp::Socket<'_>>(h);
+                while let Ok((body, endp)) = socket.recv() {
+                    any = true;
+
+                    // Drop packets from untrusted VLANs after receiving them
+                    // (to avoid clogging the queue)
+                    if !trust {
+                        ringbuf_entry!(Trace::SkipReceiveUntrustedPacket {
+                            vid: vlan.vid
+                        });
+                        continue;
+                    }
+
+                    let meta = vlan.device.make_meta(
+                        endp.port,
+                        body.len(),
+                        endp.addr.try_into().map_err(|_| ()).unwrap(),
+                    );
+                    for client in mux::clients_of(socket_index) {
+                        match self.rx_queues[client].enqueue(body.len(), meta) {
+                            Ok(buf) => {
+                                buf.copy_from_slice(body);
+                                mux::count(client, ClientEvent::Queued);
+                            }
+                            Err(_) => mux::count(client, ClientEvent::Dropped),
+                        }
+                    }
+                }
+            }
+        }
+        any
+    }
+
+    /// Iterate over socket clients, waking any that can do work.
     ///
-    /// A task can do work if...
+    /// A client can do work if...
     ///
-    /// - any of its sockets (on any VLAN) have incoming packets waiting, or
+    /// - its receive queue has packets waiting, or
     ///
-    /// - it is waiting to send on some socket S, and _all_ of the copies of S
+    /// - it is waiting to send on its socket S, and _all_ of the copies of S
     ///   across all VLANs can accept an outgoing packet. (The "all" is
     ///   important here since we don't keep track of which one it's trying to
     ///   send through.)
     pub fn wake_sockets(&mut self) {
-        for i in 0..SOCKET_COUNT {
-            // recv wake depends only on the state of the sockets.
-            let recv_wake = self
-                .vlan_state
-                .values_mut()
-                .any(|v| v.get_socket_mut(i).unwrap().can_recv());
+        for (c, &(i, task_id, notification)) in CLIENTS.iter().enumerate() {
+            // recv wake depends only on the state of the client's queue.
+            let recv_wake = !self.rx_queues[c].is_empty();
             // send wake only happens if the wait flag is set.
-            let send_wake = self.client_waiting_to_send[i]
+            let send_wake = self.client_waiting_to_send[c]
                 && self
                     .vlan_state
                     .values_mut()
                     .all(|v| v.get_socket_mut(i).unwrap().can_send());
 
             if recv_wake || send_wake {
-                let (task_id, notification) = generated::SOCKET_OWNERS[i];
                 let task_id = sys_refresh_task_id(task_id);
                 sys_post(task_id, notification);
             }
         }
+
+        // TCP sockets wake their owners on any change of state, as well as
+        // when they have data to receive or room to send.
+        for (j, slot) in self.tcp.iter_mut().enumerate() {
+            let socket =
+                self.vlan_state[slot.vid]
+                    .socket_set
+                    .get_mut::<smoltcp::socket::tcp::Socket<'_>>(slot.handle);
+            let state = socket.state();
+            let changed = state != slot.state;
+            if changed {
+                ringbuf_entry!(Trace::TcpState {
+                    socket: j as u8,
+                    state: tcp::state(state),
+                });
+                slot.state = state;
+            }
+            let recv_wake = socket.can_recv();
+            let send_wake = slot.waiting_to_send && socket.can_send();
+
+            if changed || recv_wake || send_wake {
+                let (task_id, notification) = generated::TCP_SOCKET_OWNERS[j];
+                let task_id = sys_refresh_task_id(task_id);
+                sys_post(task_id, notification);
+            }
+        }
+    }
+
+    /// Returns when the stack next needs to be polled (to retransmit, or to
+    /// make an mDNS announcement, say) if nothing else happens before then.
+    pub(crate) fn poll_at(&mut self, t: u64) -> Option<u64> {
+        let instant = smoltcp::time::Instant::from_millis(t as i64);
+        self.vlan_state
+            .values_mut()
+            .flat_map(|v| {
+                let stack = v
+                    .iface
+                    .poll_at(instant, &v.socket_set)
+                    .map(|i| i.total_millis() as u64);
+                let mdns = v.mdns.as_ref().and_then(|m| m.poll_at());
+                stack.into_iter().chain(mdns)
+            })
+            .min()
+    }
+
+    /// Checks the link on each of the SP's ports, acting on any changes.
+    pub(crate) fn poll_links(&mut self, now: u64) {
+        for port in 0..generated::PORT_COUNT {
+            // A PHY that can't be read now will be tried again next time.
+            let _ = self.poll_link(port as u8, now);
+        }
+    }
+
+    /// Reads the link state of SP port `port`, and acts on any change since
+    /// it was last read.
+    fn poll_link(
+        &mut self,
+        port: u8,
+        now: u64,
+    ) -> Result<phy::LinkStatus, PhyError> {
+        if usize::from(port) >= generated::PORT_COUNT {
+            return Err(PhyError::InvalidPort);
+        }
+        let status = self.bsp.link_status(port, self.eth)?;
+        match self.links[usize::from(port)].update(status) {
+            None => (),
+            Some(LinkEvent::Down) => ringbuf_entry!(Trace::LinkDown { port }),
+            Some(e) => {
+                if e == LinkEvent::Up {
+                    ringbuf_entry!(Trace::LinkUp { port });
+                } else {
+                    ringbuf_entry!(Trace::LinkBounced { port });
+                }
+                self.link_came_up(port, now);
+            }
+        }
+        Ok(status)
+    }
+
+    /// Starts over anything on `port` that depends on what's at the other
+    /// end of the link, which may have changed while it was down.
+    fn link_came_up(&mut self, port: u8, now: u64) {
+        for (vid, vlan) in self.vlan_state.iter_mut() {
+            if sp_port(vid) != port {
+                continue;
+            }
+            if let Some(responder) = &mut vlan.mdns {
+                responder.reannounce(now);
+            }
+            #[cfg(feature = "dhcp")]
+            if vid == generated::DHCP_VLAN {
+                vlan.socket_set
+                    .get_mut::<smoltcp::socket::dhcpv4::Socket<'_>>(
+                        self.dhcp.handle,
+                    )
+                    .reset();
+            }
+        }
+    }
+
+    /// Looks up TCP socket `socket`, checking that the sender owns it, and
+    /// returns it along with its interface's context.
+    fn tcp_socket<Err>(
+        &mut self,
+        msg: &userlib::RecvMessage,
+        socket: TcpSocketName,
+    ) -> Result<
+        (
+            &mut smoltcp::socket::tcp::Socket<'static>,
+            &mut smoltcp::iface::Context,
+        ),
+        RequestError<Err>,
+    > {
+        let index = socket.index();
+        if generated::TCP_SOCKET_OWNERS[index].0.index() != msg.sender.index() {
+            return Err(ClientError::AccessViolation.fail());
+        }
+        let slot = &self.tcp[index];
+        let vlan = &mut self.vlan_state[slot.vid];
+        let socket = vlan
+            .socket_set
+            .get_mut::<smoltcp::socket::tcp::Socket<'static>>(slot.handle);
+        Ok((socket, vlan.iface.context()))
+    }
+
+    /// Picks a local port for a connection from a socket without one.
+    fn ephemeral_port(&mut self) -> u16 {
+        let port = self.next_ephemeral_port;
+        self.next_ephemeral_port =
+            port.checked_add(1).unwrap_or(tcp::EPHEMERAL_PORT_START);
+        port
     }
 
     pub fn wake(&self) {
@@ -641,8 +1050,8 @@ where
         &self.mac
     }
 
-    /// Requests that a packet waiting in the rx queue of `socket` be delivered
-    /// into loaned memory at `payload`.
+    /// Requests that a packet waiting in the caller's receive queue for
+    /// `socket` be delivered into loaned memory at `payload`.
     ///
     /// If a packet is available and fits, copies it into `payload` and returns
     /// its `UdpMetadata`. Otherwise, leaves `payload` untouched and returns an
@@ -654,69 +1063,25 @@ where
         large_payload_behavior: LargePayloadBehavior,
         payload: idol_runtime::Leased<idol_runtime::W, [u8]>,
     ) -> Result<UdpMetadata, RequestError<RecvError>> {
-        let socket_index = socket as usize;
-
-        if generated::SOCKET_OWNERS[socket_index].0.index()
-            != msg.sender.index()
-        {
-            return Err(ClientError::AccessViolation.fail());
-        }
-        let now = sys_get_timer().now;
-
-        // Iterate over all of the per-VLAN sockets, returning the first
-        // available packet with a bonus `vid` tag attached in the metadata.
-        for vlan in self.vlan_state.values_mut() {
-            // Decide whether to pass this packet to the socket, depending on
-            // whether we trust the VLAN or not.  Sockets can be configured to
-            // accept even untrusted packets (e.g. control_plane_agent needs to
-            // receive an unlock message).
-            let trust = vlan.check_trust(now)
-                | generated::SOCKET_ALLOW_UNTRUSTED[socket_index];
-            let vid = vlan.vid; // for logging
-
-            let socket = vlan
-                .get_socket_mut(socket_index)
-                .ok_or(RequestError::Fail(ClientError::BadMessageContents))?;
-            #[allow(clippy::while_let_loop)]
-            loop {
-                match socket.recv() {
-                    Ok((body, endp)) => {
-                        // Drop packets from untrusted VLANs after receiving
-                        // them (to avoid clogging the queue)
-                        if !trust {
-                            ringbuf_entry!(Trace::SkipReceiveUntrustedPacket {
-                                vid
-                            });
-                            continue;
-                        }
-
-                        if payload.len() < body.len() {
-                            match large_payload_behavior {
-                                LargePayloadBehavior::Discard => continue,
-                                // If we add a `::Fail` case, we will need to
-                                // allow for caller retries (possibly by peeking
-                                // on the socket instead of recving)
-                            }
-                        }
-                        payload
-                            .write_range(0..body.len(), body)
-                            .map_err(|_| RequestError::went_away())?;
-
-                        // Release borrow on self/socket
-                        let body_len = body.len();
-
-                        return Ok(vlan.device.make_meta(
-                            endp.port,
-                            body_len,
-                            endp.addr.try_into().map_err(|_| ()).unwrap(),
-                        ));
-                    }
-                    Err(udp::RecvError::Exhausted) => {
-                        // Move on to next vid
-                        break;
-                    }
+        let client = mux::client_index(socket as usize, msg.sender)
+            .ok_or_else(|| ClientError::AccessViolation.fail())?;
+
+        let queue = &mut self.rx_queues[client];
+        while let Ok((meta, body)) = queue.dequeue() {
+            if payload.len() < body.len() {
+                match large_payload_behavior {
+                    LargePayloadBehavior::Discard => {
+                        mux::count(client, ClientEvent::Discarded);
+                        continue;
+                    } // If we add a `::Fail` case, we will need to allow for
+                      // caller retries (possibly by peeking on the queue instead
+                      // of dequeueing)
                 }
             }
+            payload
+                .write_range(0..body.len(), body)
+                .map_err(|_| RequestError::went_away())?;
+            return Ok(meta);
         }
         Err(RecvError::QueueEmpty.into())
     }
@@ -731,11 +1096,8 @@ where
         payload: idol_runtime::Leased<idol_runtime::R, [u8]>,
     ) -> Result<(), RequestError<SendError>> {
         let socket_index = socket as usize;
-        if generated::SOCKET_OWNERS[socket_index].0.index()
-            != msg.sender.index()
-        {
-            return Err(ClientError::AccessViolation.fail());
-        }
+        let client = mux::client_index(socket_index, msg.sender)
+            .ok_or_else(|| ClientError::AccessViolation.fail())?;
 
         let now = userlib::sys_get_timer().now;
 
@@ -767,7 +1129,7 @@ where
                 payload
                     .read_range(0..payload.len(), buf)
                     .map_err(|_| RequestError::went_away())?;
-                self.client_waiting_to_send[socket_index] = false;
+                self.client_waiting_to_send[client] = false;
                 vlan.queue_watchdog[socket_index] = QueueWatchdog::Nominal;
                 Ok(())
             }
@@ -790,7 +1152,7 @@ where
                     }
                     QueueWatchdog::QueueFullTimeout => (),
                 }
-                self.client_waiting_to_send[socket_index] = true;
+                self.client_waiting_to_send[client] = true;
                 Err(SendError::QueueFull.into())
             }
             Err(udp::SendError::Unaddressable) => {
@@ -820,6 +1182,21 @@ where
     }
 }
 
+/// Returns the index of the SP port that VLAN `vid` is on.
+fn sp_port(vid: VLanId) -> u8 {
+    #[cfg(feature = "vlan")]
+    match vid.cfg().port {
+        task_net_api::SpPort::One => 0,
+        task_net_api::SpPort::Two => 1,
+    }
+
+    #[cfg(not(feature = "vlan"))]
+    {
+        let _ = vid;
+        0
+    }
+}
+
 impl<B, E> idol_runtime::NotificationHandler for GenServerImpl<'_, B, E>
 where
     E: DeviceExt,
@@ -838,8 +1215,15 @@ where
     }
 }
 
+/// Room for the DHCP client's socket, if we have one.
+const DHCP_SOCKET_COUNT: usize = cfg!(feature = "dhcp") as usize;
+
 pub struct Storage {
-    sockets: [SocketStorage<'static>; SOCKET_COUNT],
+    sockets: [SocketStorage<'static>;
+        SOCKET_COUNT
+            + TCP_SOCKET_COUNT
+            + DHCP_SOCKET_COUNT
+            + generated::MDNS_SOCKET_COUNT],
     iface: core::mem::MaybeUninit<Interface>,
 }
 
@@ -0,0 +1,94 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! TCP sockets.
+//!
+//! TCP sockets are configured in `config.net.tcp-sockets`, each with an owner
+//! task, a port, and buffer sizes. Unlike UDP sockets, each lives on a single
+//! VLAN, since a connection can't span more than one. Data moves between the
+//! socket's buffers and its owner by way of leases, a piece at a time: `send`
+//! and `recv` move as much as will fit, and report how much that was. The
+//! owner's notification is posted whenever the socket changes state, has
+//! data waiting, or (if the owner found it full) has room to send again.
+
+use idol_runtime::{Leased, RequestError, R, W};
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::tcp;
+use task_net_api::{TcpError, TcpState, VLanId};
+
+/// The first of the ports from which we connect sockets that don't have one
+/// of their own; this is the start of the IANA dynamic range.
+pub const EPHEMERAL_PORT_START: u16 = 49152;
+
+/// A TCP socket, and what we know about it.
+pub struct TcpSlot {
+    pub vid: VLanId,
+    pub handle: SocketHandle,
+    /// The state of the socket when we last told its owner about it
+    pub state: tcp::State,
+    /// Whether the owner has found the transmit buffer full, and is waiting
+    /// to hear that it has room
+    pub waiting_to_send: bool,
+}
+
+pub fn state(s: tcp::State) -> TcpState {
+    match s {
+        tcp::State::Closed => TcpState::Closed,
+        tcp::State::Listen => TcpState::Listen,
+        tcp::State::SynSent => TcpState::SynSent,
+        tcp::State::SynReceived => TcpState::SynReceived,
+        tcp::State::Established => TcpState::Established,
+        tcp::State::FinWait1 => TcpState::FinWait1,
+        tcp::State::FinWait2 => TcpState::FinWait2,
+        tcp::State::CloseWait => TcpState::CloseWait,
+        tcp::State::Closing => TcpState::Closing,
+        tcp::State::LastAck => TcpState::LastAck,
+        tcp::State::TimeWait => TcpState::TimeWait,
+    }
+}
+
+/// Queues as much of `payload` as fits into the socket's transmit buffer.
+pub fn send(
+    socket: &mut tcp::Socket<'_>,
+    payload: Leased<R, [u8]>,
+) -> Result<u32, RequestError<TcpError>> {
+    if !socket.may_send() {
+        return Err(TcpError::InvalidState.into());
+    }
+    let result = socket.send(|buf| {
+        let n = buf.len().min(payload.len());
+        match payload.read_range(0..n, &mut buf[..n]) {
+            Ok(()) => (n, Ok(n)),
+            Err(()) => (0, Err(RequestError::went_away())),
+        }
+    });
+    match result {
+        Ok(Ok(0)) if !payload.is_empty() => Err(TcpError::QueueFull.into()),
+        Ok(Ok(n)) => Ok(n as u32),
+        Ok(Err(e)) => Err(e),
+        Err(tcp::SendError::InvalidState) => Err(TcpError::InvalidState.into()),
+    }
+}
+
+/// Moves as much data as fits from the socket's receive buffer into
+/// `payload`.
+pub fn recv(
+    socket: &mut tcp::Socket<'_>,
+    payload: Leased<W, [u8]>,
+) -> Result<u32, RequestError<TcpError>> {
+    let result = socket.recv(|buf| {
+        let n = buf.len().min(payload.len());
+        match payload.write_range(0..n, &buf[..n]) {
+            Ok(()) => (n, Ok(n)),
+            Err(()) => (0, Err(RequestError::went_away())),
+        }
+    });
+    match result {
+        Ok(Ok(0)) if !payload.is_empty() => Err(TcpError::QueueEmpty.into()),
+        Ok(Ok(n)) => Ok(n as u32),
+        Ok(Err(e)) => Err(e),
+        Err(tcp::RecvError::InvalidState) => Err(TcpError::InvalidState.into()),
+        Err(tcp::RecvError::Finished) => Err(TcpError::Finished.into()),
+    }
+}
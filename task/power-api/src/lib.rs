@@ -6,12 +6,13 @@
 
 #![no_std]
 
+use derive_idol_err::IdolError;
 pub use drv_i2c_api::ResponseCode;
 use hubpack::SerializedSize;
 use serde::{Deserialize, Serialize};
 use serde_big_array::BigArray;
 pub use task_sensor_api::SensorId;
-use userlib::sys_send;
+use userlib::{sys_send, FromPrimitive};
 use zerocopy::{AsBytes, FromBytes};
 
 #[derive(Debug, Clone, Copy, Deserialize, Serialize, SerializedSize)]
@@ -187,4 +188,35 @@ impl RenesasBlackbox {
     }
 }
 
+/// State of a rail under the control of the power sequencer, which brings up
+/// and shuts down the rails described in the power task's config.
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize, SerializedSize,
+)]
+pub enum RailState {
+    Off,
+    On,
+    /// The rail was enabled, but its power-good didn't assert in time, so it
+    /// was disabled again.
+    PowerGoodTimeout,
+    /// Driving the rail's enable failed.
+    EnableFailed(ResponseCode),
+    /// A rail that this one depends on isn't on, so this one was left off.
+    DependencyOff,
+}
+
+#[derive(
+    Copy, Clone, Debug, FromPrimitive, Eq, PartialEq, IdolError, counters::Count,
+)]
+pub enum SequencerError {
+    /// There's no rail with that index.
+    NoSuchRail = 1,
+    /// Sequencing finished, but some rails didn't reach the requested state;
+    /// `rail_state` says which, and why.
+    RailsFaulted,
+
+    #[idol(server_death)]
+    ServerRestarted,
+}
+
 include!(concat!(env!("OUT_DIR"), "/client_stub.rs"));
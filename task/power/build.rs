@@ -2,6 +2,180 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::io::Write;
+
+/// Configuration for the power sequencer, which is only used with the
+/// `sequencer` feature. Rails are listed in the task's config, e.g.
+///
+/// ```toml
+/// [[tasks.power.config.rails]]
+/// name = "v3p3_sys"
+/// enable = { gpio = { port = "C", pin = 4 } }
+/// power-good = { port = "C", pin = 5 }
+/// timeout-ms = 10
+///
+/// [[tasks.power.config.rails]]
+/// name = "v1p8_sys"
+/// enable = { pmbus = { rail = "V1P8_SYS", paged = true } }
+/// depends-on = ["v3p3_sys"]
+/// delay-ms = 2
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct TaskConfig {
+    rails: Vec<RailConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RailConfig {
+    name: String,
+    enable: Enable,
+    /// Input that asserts once the rail is good
+    #[serde(default)]
+    power_good: Option<PinConfig>,
+    /// Rails that must be on before this one is enabled, and that stay on
+    /// until it's disabled
+    #[serde(default)]
+    depends_on: Vec<String>,
+    /// How long to wait after the rail comes up (or goes down) before moving
+    /// on to the next one
+    #[serde(default)]
+    delay_ms: u32,
+    /// How long the rail's power-good can take to assert
+    #[serde(default = "default_timeout_ms")]
+    timeout_ms: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+enum Enable {
+    Gpio {
+        port: char,
+        pin: u8,
+        #[serde(default)]
+        active_low: bool,
+    },
+    /// A PMBus rail from the I2C config, switched with its OPERATION
+    /// command; `paged` rails are selected with PAGE first.
+    Pmbus {
+        rail: String,
+        #[serde(default)]
+        paged: bool,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct PinConfig {
+    port: char,
+    pin: u8,
+}
+
+fn default_timeout_ms() -> u32 {
+    100
+}
+
+fn pin(port: char, pin: u8) -> Result<String> {
+    if !port.is_ascii_alphabetic() || pin > 15 {
+        bail!("bad pin {port}{pin}");
+    }
+    Ok(format!(
+        "drv_stm32xx_sys_api::Port::{}.pin({pin})",
+        port.to_ascii_uppercase()
+    ))
+}
+
+/// Returns the rails in an order in which each comes after everything it
+/// depends on, keeping to the config's order where the graph allows.
+fn bring_up_order(deps: &[Vec<usize>]) -> Result<Vec<usize>> {
+    let mut order = vec![];
+    let mut placed = vec![false; deps.len()];
+    while order.len() < deps.len() {
+        let next = (0..deps.len())
+            .find(|&i| !placed[i] && deps[i].iter().all(|&d| placed[d]));
+        match next {
+            Some(i) => {
+                placed[i] = true;
+                order.push(i);
+            }
+            None => bail!("rail dependencies form a cycle"),
+        }
+    }
+    Ok(order)
+}
+
+fn generate_sequencer_config(config: &TaskConfig) -> Result<()> {
+    if config.rails.len() > usize::from(u8::MAX) {
+        bail!("too many rails");
+    }
+
+    let mut deps = vec![];
+    for r in &config.rails {
+        if config.rails.iter().filter(|o| o.name == r.name).count() > 1 {
+            bail!("duplicate rail {}", r.name);
+        }
+        let mut d = vec![];
+        for name in &r.depends_on {
+            match config.rails.iter().position(|o| o.name == *name) {
+                Some(i) => d.push(i),
+                None => bail!("rail {}: no such rail {name}", r.name),
+            }
+        }
+        deps.push(d);
+    }
+    let order = bring_up_order(&deps)?;
+
+    let out = build_util::out_dir().join("sequencer_config.rs");
+    let mut file = std::fs::File::create(out)?;
+    writeln!(file, "pub const NUM_RAILS: usize = {};", config.rails.len())?;
+    writeln!(file, "pub static RAILS: [RailConfig; NUM_RAILS] = [")?;
+    for (r, d) in config.rails.iter().zip(&deps) {
+        let enable = match &r.enable {
+            Enable::Gpio {
+                port,
+                pin: p,
+                active_low,
+            } => format!(
+                "Enable::Gpio {{
+            pin: {},
+            active_low: {active_low},
+        }}",
+                pin(*port, *p)?
+            ),
+            Enable::Pmbus { rail, paged } => format!(
+                "Enable::Pmbus {{
+            builder: crate::i2c_config::pmbus::{},
+            paged: {paged},
+        }}",
+                rail.to_lowercase()
+            ),
+        };
+        let power_good = match &r.power_good {
+            Some(p) => format!("Some({})", pin(p.port, p.pin)?),
+            None => "None".to_string(),
+        };
+        writeln!(
+            file,
+            "    // {}
+    RailConfig {{
+        enable: {enable},
+        power_good: {power_good},
+        depends_on: &{d:?},
+        delay_ms: {},
+        timeout_ms: {},
+    }},",
+            r.name, r.delay_ms, r.timeout_ms,
+        )?;
+    }
+    writeln!(file, "];")?;
+    writeln!(file, "pub static ORDER: [usize; NUM_RAILS] = {order:?};")?;
+
+    Ok(())
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     build_util::expose_target_board();
     build_util::build_notifications()?;
@@ -18,5 +192,10 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
     build_i2c::codegen(build_i2c::Disposition::Sensors)?;
 
+    if std::env::var("CARGO_FEATURE_SEQUENCER").is_ok() {
+        let task_config = build_util::task_config::<TaskConfig>()?;
+        generate_sequencer_config(&task_config)?;
+    }
+
     Ok(())
 }
@@ -6,6 +6,8 @@
 //!
 //! This is a primordial power monitoring task.
 //!
+//! With the `sequencer` feature, it also brings the board's rails up and down
+//! in the order given by the app config; see the `sequencer` module.
 
 #![no_std]
 #![no_main]
@@ -16,12 +18,14 @@ use drv_i2c_devices::isl68224::*;
 use drv_i2c_devices::ltc4282::*;
 use drv_i2c_devices::max5970::*;
 use drv_i2c_devices::mwocp68::*;
+use drv_i2c_devices::pmbus_generic::{PmbusDevice, Quirks};
 use drv_i2c_devices::raa229618::*;
 use drv_i2c_devices::tps546b24a::*;
 use pmbus::Phase;
 use ringbuf::*;
 use task_power_api::{
-    Bmr491Event, PmbusValue, RawPmbusBlock, RenesasBlackbox, MAX_BLOCK_LEN,
+    Bmr491Event, PmbusValue, RailState, RawPmbusBlock, RenesasBlackbox,
+    SequencerError, MAX_BLOCK_LEN,
 };
 use task_sensor_api as sensor_api;
 use userlib::units::*;
@@ -55,6 +59,8 @@ const TIMER_INTERVAL: u32 = 1000;
 
 task_slot!(I2C, i2c_driver);
 task_slot!(SENSOR, sensor);
+#[cfg(feature = "sequencer")]
+task_slot!(SYS, sys);
 
 include!(concat!(env!("OUT_DIR"), "/i2c_config.rs"));
 
@@ -72,6 +78,8 @@ enum DeviceType {
     HotSwapIO(Ohms),
     HotSwapQSFP(Ohms),
     PowerShelf,
+    /// Any regulator that the generic PMBus driver can handle
+    Pmbus(&'static Quirks),
 }
 
 struct PowerControllerConfig {
@@ -95,6 +103,7 @@ enum Device {
     Max5970(Max5970),
     Mwocp68(Mwocp68),
     Ltc4282(Ltc4282),
+    Pmbus(PmbusDevice),
 }
 
 impl Device {
@@ -105,6 +114,7 @@ impl Device {
             Device::Isl68224(dev) => dev.read_temperature()?,
             Device::Tps546B24A(dev) => dev.read_temperature()?,
             Device::Adm1272(dev) => dev.read_temperature()?,
+            Device::Pmbus(dev) => dev.read_temperature()?,
             Device::Mwocp68(..) => {
                 // The MWOCP68 actually has three temperature sensors, but they
                 // aren't associated with power rails, so we don't read them
@@ -128,6 +138,7 @@ impl Device {
             Device::Max5970(dev) => dev.read_iout()?,
             Device::Mwocp68(dev) => dev.read_iout()?,
             Device::Ltc4282(dev) => dev.read_iout()?,
+            Device::Pmbus(dev) => dev.read_iout()?,
         };
         Ok(r)
     }
@@ -142,6 +153,7 @@ impl Device {
             Device::Max5970(dev) => dev.read_vout()?,
             Device::Mwocp68(dev) => dev.read_vout()?,
             Device::Ltc4282(dev) => dev.read_vout()?,
+            Device::Pmbus(dev) => dev.read_vout()?,
         };
         Ok(r)
     }
@@ -160,8 +172,9 @@ impl Device {
     fn read_vin(&self) -> Result<Volts, ResponseCode> {
         let r = match &self {
             Device::Mwocp68(dev) => dev.read_vin()?,
+            Device::Pmbus(dev) => dev.read_vin()?,
             // Do any other devices have VIN? For now we only added support to
-            // MWOCP68
+            // MWOCP68 and the generic driver
             _ => return Err(ResponseCode::NoDevice),
         };
         Ok(r)
@@ -170,8 +183,9 @@ impl Device {
     fn read_iin(&self) -> Result<Amperes, ResponseCode> {
         let r = match &self {
             Device::Mwocp68(dev) => dev.read_iin()?,
+            Device::Pmbus(dev) => dev.read_iin()?,
             // Do any other devices have IIN? For now we only added support to
-            // MWOCP68
+            // MWOCP68 and the generic driver
             _ => return Err(ResponseCode::NoDevice),
         };
         Ok(r)
@@ -189,7 +203,8 @@ impl Device {
             | Device::Tps546B24A(_)
             | Device::Adm1272(_)
             | Device::Ltc4282(_)
-            | Device::Max5970(_) => {
+            | Device::Max5970(_)
+            | Device::Pmbus(_) => {
                 return Err(ResponseCode::OperationNotSupported)
             }
         };
@@ -203,6 +218,7 @@ impl Device {
             Device::Raa229618(dev) => dev.read_mode()?,
             Device::Isl68224(dev) => dev.read_mode()?,
             Device::Tps546B24A(dev) => dev.read_mode()?,
+            Device::Pmbus(dev) => dev.read_mode()?,
             Device::Adm1272(..) | Device::Ltc4282(..) | Device::Max5970(..) => {
                 return Err(ResponseCode::OperationNotSupported)
             }
@@ -220,6 +236,7 @@ impl Device {
             Device::Adm1272(dev) => dev.i2c_device(),
             Device::Ltc4282(dev) => dev.i2c_device(),
             Device::Max5970(dev) => dev.i2c_device(),
+            Device::Pmbus(dev) => dev.i2c_device(),
         }
     }
 }
@@ -246,6 +263,9 @@ impl PowerControllerConfig {
             DeviceType::HotSwapQSFP(sense) => {
                 Device::Ltc4282(Ltc4282::new(&dev, *sense))
             }
+            DeviceType::Pmbus(quirks) => {
+                Device::Pmbus(PmbusDevice::new(&dev, rail, quirks))
+            }
         }
     }
 }
@@ -373,6 +393,29 @@ macro_rules! mwocp68_controller {
     };
 }
 
+#[allow(unused_macros)]
+macro_rules! pmbus_controller {
+    ($dev:ident, $rail:ident, $state:ident) => {
+        paste::paste! {
+            PowerControllerConfig {
+                state: PowerState::$state,
+                device: DeviceType::Pmbus(
+                    &drv_i2c_devices::pmbus_generic::[<$dev:upper>]
+                ),
+                builder: i2c_config::pmbus::$rail,
+                voltage: sensors::[<$dev:upper _ $rail:upper _VOLTAGE_SENSOR>],
+                input_voltage: None,
+                current: sensors::[<$dev:upper _ $rail:upper _CURRENT_SENSOR>],
+                input_current: None,
+                temperature: Some(
+                    sensors::[<$dev:upper _ $rail:upper _TEMPERATURE_SENSOR>]
+                ),
+                phases: None,
+            }
+        }
+    };
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Board-specific behavior is isolated into a `bsp` module, which is picked
 // based on the target_board name.
@@ -401,17 +444,31 @@ macro_rules! mwocp68_controller {
 #[cfg_attr(target_board = "gimletlet-2", path = "bsp/gimletlet_2.rs")]
 mod bsp;
 
+#[cfg(feature = "sequencer")]
+mod sequencer;
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[export_name = "main"]
 fn main() -> ! {
     let i2c_task = I2C.get_task_id();
 
+    // Bring the rails up before anything tries to talk to what's on them.
+    #[cfg(feature = "sequencer")]
+    let sequencer = {
+        let sys = drv_stm32xx_sys_api::Sys::from(SYS.get_task_id());
+        let mut sequencer = sequencer::Sequencer::new(sys, i2c_task);
+        sequencer.sequence_up();
+        sequencer
+    };
+
     let mut server = ServerImpl {
         i2c_task,
         sensor: sensor_api::Sensor::from(SENSOR.get_task_id()),
         devices: claim_devices(i2c_task),
         bsp: bsp::State::init(),
+        #[cfg(feature = "sequencer")]
+        sequencer,
     };
     let mut buffer = [0; idl::INCOMING_SIZE];
 
@@ -426,6 +483,8 @@ struct ServerImpl {
     sensor: sensor_api::Sensor,
     devices: &'static mut [Device; bsp::CONTROLLER_CONFIG_LEN],
     bsp: bsp::State,
+    #[cfg(feature = "sequencer")]
+    sequencer: sequencer::Sequencer,
 }
 
 impl ServerImpl {
@@ -1051,6 +1110,91 @@ impl idl::InOrderPowerImpl for ServerImpl {
         )?;
         Ok(())
     }
+
+    fn rail_count(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<u8, idol_runtime::RequestError<core::convert::Infallible>> {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "sequencer")] {
+                // The build checks that this fits.
+                Ok(self.sequencer.rail_count() as u8)
+            } else {
+                Err(idol_runtime::ClientError::UnknownOperation.fail())
+            }
+        }
+    }
+
+    fn rail_state(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        rail: u8,
+    ) -> Result<RailState, idol_runtime::RequestError<SequencerError>> {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "sequencer")] {
+                Ok(self
+                    .sequencer
+                    .state(usize::from(rail))
+                    .ok_or(SequencerError::NoSuchRail)?)
+            } else {
+                let _ = rail;
+                Err(idol_runtime::ClientError::UnknownOperation.fail())
+            }
+        }
+    }
+
+    fn sequence_up(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<(), idol_runtime::RequestError<SequencerError>> {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "sequencer")] {
+                if self.sequencer.sequence_up() {
+                    Ok(())
+                } else {
+                    Err(SequencerError::RailsFaulted.into())
+                }
+            } else {
+                Err(idol_runtime::ClientError::UnknownOperation.fail())
+            }
+        }
+    }
+
+    fn sequence_down(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+    ) -> Result<(), idol_runtime::RequestError<SequencerError>> {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "sequencer")] {
+                if self.sequencer.sequence_down() {
+                    Ok(())
+                } else {
+                    Err(SequencerError::RailsFaulted.into())
+                }
+            } else {
+                Err(idol_runtime::ClientError::UnknownOperation.fail())
+            }
+        }
+    }
+
+    fn force_rail(
+        &mut self,
+        _msg: &userlib::RecvMessage,
+        rail: u8,
+        on: bool,
+    ) -> Result<RailState, idol_runtime::RequestError<SequencerError>> {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "sequencer")] {
+                Ok(self
+                    .sequencer
+                    .force(usize::from(rail), on)
+                    .ok_or(SequencerError::NoSuchRail)?)
+            } else {
+                let _ = (rail, on);
+                Err(idol_runtime::ClientError::UnknownOperation.fail())
+            }
+        }
+    }
 }
 
 /// Claims a mutable buffer of Devices, built from CONTROLLER_CONFIG.
@@ -0,0 +1,243 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Power sequencing for boards whose rails are described in the `app.toml`.
+//!
+//! Each rail has an enable (a GPIO, or a PMBus rail's OPERATION command), an
+//! optional power-good input, and a list of rails it depends on. Bringing the
+//! rails up walks the dependency graph from the bottom: a rail is enabled only
+//! once everything it depends on is on, and is given until its timeout for
+//! power-good to assert. A rail that fails is turned back off and marked as
+//! faulted, and the rails that depend on it are left off, but the walk carries
+//! on with everything else. Shutting down walks the graph the other way.
+//!
+//! When the task starts, it drives every GPIO enable to off before bringing the
+//! rails up, so restarting the task power-cycles the rails.
+
+use drv_i2c_api::{I2cDevice, ResponseCode};
+use drv_stm32xx_sys_api::{OutputType, PinSet, Pull, Speed, Sys};
+use pmbus::commands::{OPERATION, PAGE};
+use ringbuf::*;
+use task_power_api::RailState;
+use userlib::{hl, sys_get_timer, TaskId};
+
+pub(crate) enum Enable {
+    Gpio {
+        pin: PinSet,
+        active_low: bool,
+    },
+    Pmbus {
+        builder: fn(TaskId) -> (I2cDevice, u8),
+        paged: bool,
+    },
+}
+
+pub(crate) struct RailConfig {
+    pub enable: Enable,
+    pub power_good: Option<PinSet>,
+    /// Indices of the rails this one depends on
+    pub depends_on: &'static [usize],
+    pub delay_ms: u32,
+    pub timeout_ms: u32,
+}
+
+/// Values of the PMBus OPERATION command: immediate on and off, with the
+/// output at its nominal voltage.
+const OPERATION_ON: u8 = 0x80;
+const OPERATION_OFF: u8 = 0x00;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    Enabled(usize),
+    Disabled(usize),
+    Faulted(usize, RailState),
+    DependencyOff(usize),
+    /// A rail was left on because a rail that depends on it couldn't be
+    /// turned off.
+    KeptOn(usize),
+}
+
+ringbuf!(Trace, 32, Trace::None);
+
+pub(crate) struct Sequencer {
+    sys: Sys,
+    i2c_task: TaskId,
+    states: [RailState; config::NUM_RAILS],
+}
+
+impl Sequencer {
+    pub(crate) fn new(sys: Sys, i2c_task: TaskId) -> Self {
+        for rail in &config::RAILS {
+            if let Enable::Gpio { pin, active_low } = rail.enable {
+                // Set the level before making it an output, so the rail
+                // doesn't glitch on.
+                sys.gpio_set_to(pin, active_low);
+                sys.gpio_configure_output(
+                    pin,
+                    OutputType::PushPull,
+                    Speed::Low,
+                    Pull::None,
+                );
+            }
+            if let Some(pg) = rail.power_good {
+                sys.gpio_configure_input(pg, Pull::None);
+            }
+        }
+
+        Self {
+            sys,
+            i2c_task,
+            states: [RailState::Off; config::NUM_RAILS],
+        }
+    }
+
+    pub(crate) fn rail_count(&self) -> usize {
+        config::NUM_RAILS
+    }
+
+    pub(crate) fn state(&self, rail: usize) -> Option<RailState> {
+        self.states.get(rail).copied()
+    }
+
+    /// Brings up every rail that isn't already on, in dependency order.
+    /// Returns `false` if any rail didn't come on.
+    pub(crate) fn sequence_up(&mut self) -> bool {
+        for &i in &config::ORDER {
+            if self.states[i] == RailState::On {
+                continue;
+            }
+            let rail = &config::RAILS[i];
+            if rail
+                .depends_on
+                .iter()
+                .any(|&d| self.states[d] != RailState::On)
+            {
+                ringbuf_entry!(Trace::DependencyOff(i));
+                self.states[i] = RailState::DependencyOff;
+                continue;
+            }
+            self.states[i] = self.bring_up(i);
+        }
+        self.states.iter().all(|&s| s == RailState::On)
+    }
+
+    /// Shuts down every rail, in reverse dependency order. A rail that can't
+    /// be turned off keeps the rails it depends on up, too. Returns `false`
+    /// if any rail might still be on.
+    pub(crate) fn sequence_down(&mut self) -> bool {
+        for &i in config::ORDER.iter().rev() {
+            let needed = config::RAILS
+                .iter()
+                .zip(&self.states)
+                .any(|(r, &s)| may_be_on(s) && r.depends_on.contains(&i));
+            if needed {
+                ringbuf_entry!(Trace::KeptOn(i));
+                continue;
+            }
+            self.states[i] = self.shut_down(i);
+        }
+        !self.states.iter().any(|&s| may_be_on(s))
+    }
+
+    /// Turns a rail on or off without regard to its dependencies, replacing
+    /// whatever state it was in.
+    pub(crate) fn force(&mut self, rail: usize, on: bool) -> Option<RailState> {
+        if rail >= config::NUM_RAILS {
+            return None;
+        }
+        let state = if on {
+            self.bring_up(rail)
+        } else {
+            self.shut_down(rail)
+        };
+        self.states[rail] = state;
+        Some(state)
+    }
+
+    fn bring_up(&self, i: usize) -> RailState {
+        let rail = &config::RAILS[i];
+        if let Err(e) = self.set_enable(rail, true) {
+            // It's not clear how far the enable got, so make sure of off.
+            let _ = self.set_enable(rail, false);
+            return self.fault(i, RailState::EnableFailed(e));
+        }
+
+        if let Some(pg) = rail.power_good {
+            let deadline = sys_get_timer().now
+                + hl::ms_to_ticks(u64::from(rail.timeout_ms));
+            while self.sys.gpio_read(pg) == 0 {
+                if sys_get_timer().now >= deadline {
+                    let _ = self.set_enable(rail, false);
+                    return self.fault(i, RailState::PowerGoodTimeout);
+                }
+                hl::sleep_for(1);
+            }
+        }
+
+        ringbuf_entry!(Trace::Enabled(i));
+        hl::sleep_for(hl::ms_to_ticks(u64::from(rail.delay_ms)));
+        RailState::On
+    }
+
+    fn shut_down(&self, i: usize) -> RailState {
+        let rail = &config::RAILS[i];
+        let was_on = self.states[i] == RailState::On;
+        match self.set_enable(rail, false) {
+            Ok(()) => {
+                ringbuf_entry!(Trace::Disabled(i));
+                if was_on {
+                    hl::sleep_for(hl::ms_to_ticks(u64::from(rail.delay_ms)));
+                }
+                RailState::Off
+            }
+            // If the rail was off, a failure to turn it off again doesn't
+            // tell us it's on.
+            Err(e) if was_on => self.fault(i, RailState::EnableFailed(e)),
+            Err(_) => self.states[i],
+        }
+    }
+
+    fn fault(&self, i: usize, state: RailState) -> RailState {
+        ringbuf_entry!(Trace::Faulted(i, state));
+        state
+    }
+
+    fn set_enable(
+        &self,
+        rail: &RailConfig,
+        on: bool,
+    ) -> Result<(), ResponseCode> {
+        match rail.enable {
+            Enable::Gpio { pin, active_low } => {
+                self.sys.gpio_set_to(pin, on != active_low);
+                Ok(())
+            }
+            Enable::Pmbus { builder, paged } => {
+                let (dev, page) = builder(self.i2c_task);
+                let op = [
+                    OPERATION::CommandData::code(),
+                    if on { OPERATION_ON } else { OPERATION_OFF },
+                ];
+                if paged {
+                    dev.write_write(&[PAGE::CommandData::code(), page], &op)
+                } else {
+                    dev.write(&op)
+                }
+            }
+        }
+    }
+}
+
+/// Whether a rail in state `s` might be on: a rail whose enable failed might
+/// have got as far as turning on.
+fn may_be_on(s: RailState) -> bool {
+    matches!(s, RailState::On | RailState::EnableFailed(_))
+}
+
+mod config {
+    use super::*;
+
+    include!(concat!(env!("OUT_DIR"), "/sequencer_config.rs"));
+}
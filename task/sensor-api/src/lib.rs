@@ -118,11 +118,21 @@ impl<'de> Deserialize<'de> for SensorId {
 pub struct Reading {
     pub timestamp: u64,
     pub value: f32,
+    /// How old the reading was when it was handed out, in milliseconds.  A
+    /// sensor that has started failing may (depending on its policy) keep
+    /// reporting its last good value for a while; this is how a consumer
+    /// tells that apart from a fresh one.
+    pub age: u64,
 }
 
 impl Reading {
+    /// Returns a fresh reading.
     pub fn new(value: f32, timestamp: u64) -> Self {
-        Self { timestamp, value }
+        Self {
+            timestamp,
+            value,
+            age: 0,
+        }
     }
 }
 
@@ -141,6 +151,7 @@ impl Reading {
     Serialize,
     Deserialize,
     SerializedSize,
+    counters::Count,
 )]
 #[repr(u8)]
 pub enum NoData {
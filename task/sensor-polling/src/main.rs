@@ -74,6 +74,11 @@ impl TemperatureSensor {
         match &self.device {
             Device::Mwocp68 => {
                 for (i, &s) in self.temperature_sensors.iter().enumerate() {
+                    // Leave sensors that have been failing alone for a
+                    // while, as the sensors task asks.
+                    if !sensor_api.poll_due(s) {
+                        continue;
+                    }
                     let m = Mwocp68::new(&dev, i.try_into().unwrap());
                     match m.read_temperature() {
                         Ok(v) => sensor_api.post_now(s, v.0),
@@ -85,6 +90,11 @@ impl TemperatureSensor {
                     }
                 }
                 for (i, &s) in self.speed_sensors.iter().enumerate() {
+                    // Leave sensors that have been failing alone for a
+                    // while, as the sensors task asks.
+                    if !sensor_api.poll_due(s) {
+                        continue;
+                    }
                     let m = Mwocp68::new(&dev, i.try_into().unwrap());
                     match m.read_speed() {
                         Ok(v) => sensor_api.post_now(s, v.0),
@@ -2,6 +2,35 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use anyhow::Context;
+use serde::Deserialize;
+use std::io::Write;
+
+/// The default policy for every sensor, which clients can change per-sensor
+/// at runtime with `set_policy`.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct TaskConfig {
+    /// How long a poller should wait after a sensor's first error before
+    /// trying it again; each further error doubles this.
+    min_backoff_ms: u32,
+    /// The longest a poller should wait between attempts; 0 disables backoff.
+    max_backoff_ms: u32,
+    /// How long after its last good reading a failing sensor's reading is
+    /// still handed out (with its age); 0 reports the error at once.
+    stale_ms: u32,
+}
+
+impl Default for TaskConfig {
+    fn default() -> Self {
+        Self {
+            min_backoff_ms: 1000,
+            max_backoff_ms: 60_000,
+            stale_ms: 0,
+        }
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     build_util::expose_target_board();
     idol::Generator::new()
@@ -13,5 +42,33 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             "server_stub.rs",
             idol::server::ServerStyle::InOrder,
         )?;
+
+    let cfg =
+        build_util::task_maybe_config::<TaskConfig>()?.unwrap_or_default();
+    generate_policy_config(&cfg)?;
+    Ok(())
+}
+
+fn generate_policy_config(cfg: &TaskConfig) -> anyhow::Result<()> {
+    if cfg.min_backoff_ms == 0 && cfg.max_backoff_ms != 0 {
+        anyhow::bail!("min-backoff-ms must be non-zero to back off");
+    }
+
+    let out_dir = build_util::out_dir();
+    let dest_path = out_dir.join("sensor_policy.rs");
+    let mut out = std::fs::File::create(dest_path)
+        .context("creating sensor_policy.rs")?;
+
+    writeln!(
+        out,
+        "pub const MIN_BACKOFF_MS: u32 = {};",
+        cfg.min_backoff_ms
+    )?;
+    writeln!(
+        out,
+        "pub const MAX_BACKOFF_MS: u32 = {};",
+        cfg.max_backoff_ms
+    )?;
+    writeln!(out, "pub const STALE_MS: u32 = {};", cfg.stale_ms)?;
     Ok(())
 }
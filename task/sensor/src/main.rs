@@ -3,6 +3,16 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 //! Sensor management
+//!
+//! Besides holding the latest reading for each sensor, we keep track of how
+//! each one is failing. A sensor that errors repeatedly is backed off: after
+//! each consecutive error, the time before a poller should try it again
+//! (`poll_due`) doubles, from `min-backoff-ms` up to the sensor's maximum.
+//! A sensor may also be given a staleness window, within which a reading
+//! request is answered with its last good value (and that value's age)
+//! rather than its latest error. Both the maximum backoff and the window
+//! default to the task's configuration, and can be changed per-sensor with
+//! `set_policy`.
 
 #![no_std]
 #![no_main]
@@ -14,6 +24,16 @@ use userlib::*;
 
 use task_sensor_api::config::NUM_SENSORS;
 
+type NoDataCounters = <NoData as counters::Count>::Counters;
+
+/// Errors of each class, by sensor.
+#[used]
+static NODATA_COUNTERS: [NoDataCounters; NUM_SENSORS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NEW: NoDataCounters = <NoData as counters::Count>::NEW_COUNTERS;
+    [NEW; NUM_SENSORS]
+};
+
 #[derive(Copy, Clone)]
 enum LastReading {
     /// We have only seen a data reading
@@ -71,6 +91,13 @@ struct ServerImpl {
     err_time: SensorArray<u64>,
 
     nerrors: SensorArray<u32>,
+
+    /// Errors since the last data reading
+    consecutive_errors: SensorArray<u8>,
+    /// The time before which pollers should leave the sensor alone
+    retry_time: SensorArray<u64>,
+    max_backoff_ms: SensorArray<u32>,
+    stale_ms: SensorArray<u32>,
 }
 
 impl idl::InOrderSensorImpl for ServerImpl {
@@ -90,8 +117,29 @@ impl idl::InOrderSensorImpl for ServerImpl {
         let (reading, timestamp) = self
             .raw_reading(id)
             .ok_or(RequestError::Runtime(SensorError::NoReading))?;
-        let value = reading.map_err(SensorError::from)?;
-        Ok(Reading { value, timestamp })
+        let now = sys_get_timer().now;
+        let (value, timestamp) = match reading {
+            Ok(value) => (value, timestamp),
+            Err(nodata) => {
+                // If we have data recent enough to be within the sensor's
+                // staleness window, hand that out instead of the error.
+                let data_time = *self.data_time.get(id);
+                let stale_ms = u64::from(*self.stale_ms.get(id));
+                match self.last_reading.get(id) {
+                    Some(LastReading::Error)
+                        if now.saturating_sub(data_time) <= stale_ms =>
+                    {
+                        (*self.data_value.get(id), data_time)
+                    }
+                    _ => return Err(SensorError::from(nodata).into()),
+                }
+            }
+        };
+        Ok(Reading {
+            value,
+            timestamp,
+            age: now.saturating_sub(timestamp),
+        })
     }
 
     fn get_raw_reading(
@@ -162,6 +210,8 @@ impl idl::InOrderSensorImpl for ServerImpl {
         *self.last_reading.get_mut(id) = Some(r);
         *self.data_value.get_mut(id) = value;
         *self.data_time.get_mut(id) = timestamp;
+        *self.consecutive_errors.get_mut(id) = 0;
+        *self.retry_time.get_mut(id) = 0;
 
         let min_value = self.min_value.get_mut(id);
         if value < *min_value {
@@ -212,6 +262,13 @@ impl idl::InOrderSensorImpl for ServerImpl {
         if *nerrors & bitmask != bitmask {
             *nerrors = nerrors.wrapping_add(incr);
         }
+        counters::count!(NODATA_COUNTERS[usize::from(id)], nodata);
+
+        let errors = self.consecutive_errors.get_mut(id);
+        *errors = errors.saturating_add(1);
+        let errors = *errors;
+        *self.retry_time.get_mut(id) =
+            timestamp.saturating_add(self.backoff_ms(id, errors));
 
         Ok(())
     }
@@ -223,9 +280,48 @@ impl idl::InOrderSensorImpl for ServerImpl {
     ) -> Result<u32, RequestError<Infallible>> {
         Ok(*self.nerrors.get_mut(id))
     }
+
+    fn poll_due(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+    ) -> Result<bool, RequestError<Infallible>> {
+        Ok(sys_get_timer().now >= *self.retry_time.get(id))
+    }
+
+    fn set_policy(
+        &mut self,
+        _: &RecvMessage,
+        id: SensorId,
+        max_backoff_ms: u32,
+        stale_ms: u32,
+    ) -> Result<(), RequestError<Infallible>> {
+        *self.max_backoff_ms.get_mut(id) = max_backoff_ms;
+        *self.stale_ms.get_mut(id) = stale_ms;
+
+        // Don't leave a sensor that is already backed off waiting for longer
+        // than its new policy allows.
+        if *self.consecutive_errors.get(id) > 0 {
+            let latest = self
+                .err_time
+                .get(id)
+                .saturating_add(u64::from(max_backoff_ms));
+            let retry_time = self.retry_time.get_mut(id);
+            *retry_time = (*retry_time).min(latest);
+        }
+        Ok(())
+    }
 }
 
 impl ServerImpl {
+    /// Returns how long a poller should leave the sensor alone after
+    /// `errors` consecutive errors.
+    fn backoff_ms(&self, id: SensorId, errors: u8) -> u64 {
+        let max = u64::from(*self.max_backoff_ms.get(id));
+        let shift = u32::from(errors.saturating_sub(1)).min(16);
+        (u64::from(config::MIN_BACKOFF_MS) << shift).min(max)
+    }
+
     fn raw_reading(&self, id: SensorId) -> Option<(Result<f32, NoData>, u64)> {
         Some(match (*self.last_reading.get(id))? {
             LastReading::Data | LastReading::DataOnly => {
@@ -281,6 +377,10 @@ fn main() -> ! {
         err_value: NoData = NoData::DeviceUnavailable;
         err_time: u64 = 0;
         nerrors: u32 = 0;
+        consecutive_errors: u8 = 0u8;
+        retry_time: u64 = 0u64;
+        max_backoff_ms: u32 = config::MAX_BACKOFF_MS;
+        stale_ms: u32 = config::STALE_MS;
     );
 
     let mut buffer = [0; idl::INCOMING_SIZE];
@@ -290,6 +390,10 @@ fn main() -> ! {
     }
 }
 
+mod config {
+    include!(concat!(env!("OUT_DIR"), "/sensor_policy.rs"));
+}
+
 mod idl {
     // Clippy doesn't like some of the IPC API's return types when nested inside
     // of a `Result<..., RequestError<Infallible>`. For now, let's allow the
@@ -27,6 +27,7 @@ pub enum ThermalError {
     InvalidParameter = 8,
     InvalidIndex = 9,
     FanControllerUninitialized = 10,
+    TableFull = 11,
 
     #[idol(server_death)]
     ServerDeath,
@@ -79,6 +80,52 @@ pub enum ThermalAutoState {
     Uncontrollable,
 }
 
+/// The strategy the control loop uses to turn temperatures into a fan PWM
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    FromPrimitive,
+    Eq,
+    PartialEq,
+    AsBytes,
+    Serialize,
+    Deserialize,
+    SerializedSize,
+    counters::Count,
+)]
+#[repr(u8)]
+pub enum ThermalController {
+    /// A PID loop, driving the worst margin towards the target margin.
+    Pid = 0,
+    /// A table of fan PWMs against margin, interpolating between points.
+    Table = 1,
+}
+
+/// A point in the fan table used by `ThermalController::Table`
+#[derive(Clone, Copy, Debug, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct TablePoint {
+    /// How far below its target temperature the hottest part is (that is,
+    /// the worst margin, less the target margin)
+    pub margin: Celsius,
+
+    /// Fan PWM at this margin, from 0 to 100
+    pub pwm: u8,
+
+    pub _reserved: [u8; 3],
+}
+
+impl TablePoint {
+    pub const fn new(margin: f32, pwm: u8) -> Self {
+        Self {
+            margin: Celsius(margin),
+            pwm,
+            _reserved: [0; 3],
+        }
+    }
+}
+
 /// Properties for a particular part in the system
 #[derive(Clone, Copy, AsBytes, FromBytes)]
 #[repr(C)]
@@ -2,6 +2,61 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use std::io::Write;
+
+/// Must match `MAX_TABLE_POINTS` in `control.rs`.
+const MAX_TABLE_POINTS: usize = 8;
+
+/// The control strategy, which otherwise defaults to a PID loop using the
+/// BSP's gains. For example, to run the fans from a table:
+///
+/// ```toml
+/// [tasks.thermal.config]
+/// controller = "table"
+/// table = [
+///     { margin = 15.0, pwm = 20 },
+///     { margin = 5.0, pwm = 50 },
+///     { margin = 0.0, pwm = 100 },
+/// ]
+/// ```
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct TaskConfig {
+    controller: Controller,
+    /// Gains for the PID loop, in place of the BSP's
+    pid: Option<PidGains>,
+    /// Points for the table controller, which needs at least one if it's
+    /// selected; clients can change them at runtime.
+    table: Vec<TablePoint>,
+}
+
+#[derive(Copy, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Controller {
+    #[default]
+    Pid,
+    Table,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PidGains {
+    zero: f32,
+    p: f32,
+    i: f32,
+    d: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TablePoint {
+    /// Degrees below target
+    margin: f32,
+    pwm: u8,
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     build_util::expose_target_board();
     build_util::build_notifications()?;
@@ -17,5 +72,68 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             idol::server::ServerStyle::InOrder,
         )?;
 
+    let cfg =
+        build_util::task_maybe_config::<TaskConfig>()?.unwrap_or_default();
+    generate_controller_config(&cfg)?;
+
+    Ok(())
+}
+
+fn generate_controller_config(cfg: &TaskConfig) -> anyhow::Result<()> {
+    if let Some(g) = &cfg.pid {
+        // These are the checks `set_pid` makes at runtime.
+        if !(g.p > 0.0 && g.i >= 0.0 && g.d >= 0.0)
+            || ![g.zero, g.p, g.i, g.d].iter().all(|v| v.is_finite())
+        {
+            bail!("PID gains must be finite, with p > 0 and i, d >= 0");
+        }
+    }
+    if cfg.table.len() > MAX_TABLE_POINTS {
+        bail!("the fan table can have at most {MAX_TABLE_POINTS} points");
+    }
+    if cfg.controller == Controller::Table && cfg.table.is_empty() {
+        bail!("the table controller needs a table");
+    }
+    for (i, p) in cfg.table.iter().enumerate() {
+        if !p.margin.is_finite() || p.pwm > 100 {
+            bail!("fan table point {i} is out of range");
+        }
+        if cfg.table[..i].iter().any(|q| q.margin == p.margin) {
+            bail!("fan table has two points at {} degrees", p.margin);
+        }
+    }
+
+    let out_dir = build_util::out_dir();
+    let dest_path = out_dir.join("thermal_config.rs");
+    let mut out = std::fs::File::create(dest_path)
+        .context("creating thermal_config.rs")?;
+
+    let controller = match cfg.controller {
+        Controller::Pid => "Pid",
+        Controller::Table => "Table",
+    };
+    writeln!(
+        out,
+        "pub const CONTROLLER: ThermalController = \
+         ThermalController::{controller};"
+    )?;
+    match &cfg.pid {
+        Some(g) => writeln!(
+            out,
+            "pub const PID_GAINS: Option<(f32, f32, f32, f32)> = \
+             Some(({:?}, {:?}, {:?}, {:?}));",
+            g.zero, g.p, g.i, g.d
+        )?,
+        None => writeln!(
+            out,
+            "pub const PID_GAINS: Option<(f32, f32, f32, f32)> = None;"
+        )?,
+    }
+    writeln!(out, "pub const TABLE: &[TablePoint] = &[")?;
+    for p in &cfg.table {
+        writeln!(out, "    TablePoint::new({:?}, {}),", p.margin, p.pwm)?;
+    }
+    writeln!(out, "];")?;
+
     Ok(())
 }
@@ -21,11 +21,14 @@ use drv_i2c_devices::{
 
 use ringbuf::ringbuf_entry_root as ringbuf_entry;
 use task_sensor_api::{Reading, Sensor as SensorApi, SensorError, SensorId};
-use task_thermal_api::{SensorReadError, ThermalAutoState, ThermalProperties};
+use task_thermal_api::{
+    SensorReadError, TablePoint, ThermalAutoState, ThermalController,
+    ThermalProperties,
+};
 use userlib::{
     sys_get_timer,
     units::{Celsius, PWMDuty, Rpm},
-    TaskId,
+    TaskId, UnwrapLite,
 };
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -464,8 +467,9 @@ pub(crate) struct ThermalControl<'a> {
     /// Most recent power mode mask
     power_mode: PowerBitmask,
 
-    /// PID parameters, pulled from the BSP by default but user-modifiable
-    pid_config: PidConfig,
+    /// Control strategy and its parameters, pulled from the BSP and task
+    /// config by default but user-modifiable
+    controller: Controller,
 
     /// Dynamic inputs are fixed in number but configured at runtime.
     ///
@@ -604,6 +608,170 @@ impl Default for OneSidedPidState {
     }
 }
 
+/// Most points a fan table can have
+pub const MAX_TABLE_POINTS: usize = 8;
+
+/// A table of fan PWMs against margin, for `ThermalController::Table`
+///
+/// Between points, the PWM is interpolated linearly; beyond the first and last
+/// points, it's that of the nearest point.
+#[derive(Copy, Clone)]
+struct FanTable {
+    /// Points in order of decreasing margin (i.e. coolest first); only the
+    /// first `len` are used.
+    points: [TablePoint; MAX_TABLE_POINTS],
+    len: usize,
+}
+
+impl FanTable {
+    fn new(points: &[TablePoint]) -> Self {
+        let mut table = Self {
+            points: [TablePoint::new(0.0, 0); MAX_TABLE_POINTS],
+            len: 0,
+        };
+        // The build has checked that these fit.
+        for &p in points {
+            table.insert(p).unwrap_lite();
+        }
+        table
+    }
+
+    fn points(&self) -> &[TablePoint] {
+        &self.points[..self.len]
+    }
+
+    /// Adds `point`, or replaces the point with the same margin
+    fn insert(&mut self, point: TablePoint) -> Result<(), ThermalError> {
+        let pos = self
+            .points()
+            .iter()
+            .position(|p| p.margin.0 <= point.margin.0)
+            .unwrap_or(self.len);
+        if pos < self.len && self.points[pos].margin.0 == point.margin.0 {
+            self.points[pos] = point;
+            return Ok(());
+        }
+        if self.len == MAX_TABLE_POINTS {
+            return Err(ThermalError::TableFull);
+        }
+        self.points.copy_within(pos..self.len, pos + 1);
+        self.points[pos] = point;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes the point with margin `margin`
+    fn remove(&mut self, margin: f32) -> Result<(), ThermalError> {
+        let pos = self
+            .points()
+            .iter()
+            .position(|p| p.margin.0 == margin)
+            .ok_or(ThermalError::InvalidParameter)?;
+        self.points.copy_within(pos + 1..self.len, pos);
+        self.len -= 1;
+        Ok(())
+    }
+
+    /// Looks up the PWM for `margin`; an empty table runs the fans flat out.
+    fn run(&self, margin: f32) -> f32 {
+        let points = self.points();
+        let (Some(first), Some(last)) = (points.first(), points.last()) else {
+            return 100.0;
+        };
+        if margin >= first.margin.0 {
+            return f32::from(first.pwm);
+        }
+        for pair in points.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            if margin >= b.margin.0 {
+                let frac = (a.margin.0 - margin) / (a.margin.0 - b.margin.0);
+                let (a_pwm, b_pwm) = (f32::from(a.pwm), f32::from(b.pwm));
+                return a_pwm + frac * (b_pwm - a_pwm);
+            }
+        }
+        // This also catches a NaN margin, which we treat as hot.
+        f32::from(last.pwm)
+    }
+}
+
+/// The control loop's strategy for turning the worst margin into a fan PWM,
+/// along with the parameters for each strategy, so that switching between
+/// them doesn't lose anything.
+#[derive(Copy, Clone)]
+struct Controller {
+    kind: ThermalController,
+    pid: PidConfig,
+    table: FanTable,
+}
+
+impl Controller {
+    /// Builds the controller described by the task config, starting from
+    /// the BSP's PID configuration.
+    fn new(bsp_pid: PidConfig) -> Self {
+        let mut pid = bsp_pid;
+        if let Some((zero, p, i, d)) = config::PID_GAINS {
+            pid.zero = zero;
+            pid.gain_p = p;
+            pid.gain_i = i;
+            pid.gain_d = d;
+        }
+        Self {
+            kind: config::CONTROLLER,
+            pid,
+            table: FanTable::new(config::TABLE),
+        }
+    }
+
+    /// The highest PWM the controller will ask for
+    fn max_output(&self) -> f32 {
+        match self.kind {
+            ThermalController::Pid => self.pid.max_output,
+            ThermalController::Table => self
+                .table
+                .points()
+                .iter()
+                .map(|p| f32::from(p.pwm))
+                .fold(0.0, f32::max),
+        }
+    }
+
+    /// Runs one step of the controller.
+    ///
+    /// `error` is the target margin less the worst margin, so it's positive
+    /// when parts are hotter than we'd like; `pid` is the PID loop's state,
+    /// which the other strategies leave alone.
+    fn run(&self, pid: &mut OneSidedPidState, error: f32) -> f32 {
+        let out = match self.kind {
+            ThermalController::Pid => pid.run(&self.pid, error),
+            ThermalController::Table => self.table.run(-error),
+        };
+        if out >= self.max_output() {
+            counters::count!(LimitEvent::MaxOutput);
+        }
+        out
+    }
+}
+
+/// Times the control loop runs up against a limit, counted for the debugger
+#[derive(Copy, Clone, Eq, PartialEq, counters::Count)]
+enum LimitEvent {
+    /// The controller asked for its highest PWM
+    MaxOutput,
+    /// A part reached its critical temperature, so we entered `Overheated`
+    Critical,
+    /// A part reached its power-down temperature
+    PowerDownTemperature,
+    /// We stayed `Overheated` for too long
+    OverheatTimeout,
+}
+counters::counters!(LimitEvent);
+
+mod config {
+    use task_thermal_api::{TablePoint, ThermalController};
+
+    include!(concat!(env!("OUT_DIR"), "/thermal_config.rs"));
+}
+
 const TEMPERATURE_ARRAY_SIZE: usize =
     bsp::NUM_TEMPERATURE_INPUTS + bsp::NUM_DYNAMIC_TEMPERATURE_INPUTS;
 
@@ -700,7 +868,7 @@ impl<'a> ThermalControl<'a> {
                 ClaimOnceCell::new([ThermalSensorErrors::new(); 2]);
             BLACKBOXEN.claim()
         };
-        let pid_config = bsp.pid_config;
+        let controller = Controller::new(bsp.pid_config);
 
         Self {
             bsp,
@@ -710,7 +878,7 @@ impl<'a> ThermalControl<'a> {
             state: ThermalControlState::Boot {
                 values: [None; TEMPERATURE_ARRAY_SIZE],
             },
-            pid_config,
+            controller,
 
             overheat_hysteresis: Celsius(1.0),
             overheat_timeout_ms: 60_000,
@@ -754,10 +922,10 @@ impl<'a> ThermalControl<'a> {
             }
         }
 
-        self.pid_config.zero = z;
-        self.pid_config.gain_p = p;
-        self.pid_config.gain_i = i;
-        self.pid_config.gain_d = d;
+        self.controller.pid.zero = z;
+        self.controller.pid.gain_p = p;
+        self.controller.pid.gain_i = i;
+        self.controller.pid.gain_d = d;
 
         Ok(())
     }
@@ -774,12 +942,73 @@ impl<'a> ThermalControl<'a> {
         self.target_margin.0
     }
 
-    /// Resets the control state and the PID configuration
+    pub fn get_controller(&self) -> ThermalController {
+        self.controller.kind
+    }
+
+    pub fn set_controller(
+        &mut self,
+        kind: ThermalController,
+    ) -> Result<(), ThermalError> {
+        if kind == ThermalController::Table
+            && self.controller.table.points().is_empty()
+        {
+            return Err(ThermalError::InvalidParameter);
+        }
+        if kind != self.controller.kind {
+            self.controller.kind = kind;
+
+            // Start the PID loop afresh, rather than from wherever it was when
+            // it was last in charge.
+            if let ThermalControlState::Running { pid, .. } = &mut self.state {
+                *pid = OneSidedPidState::default();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn table_point(&self, index: u8) -> Result<TablePoint, ThermalError> {
+        self.controller
+            .table
+            .points()
+            .get(usize::from(index))
+            .copied()
+            .ok_or(ThermalError::InvalidIndex)
+    }
+
+    pub fn set_table_point(
+        &mut self,
+        margin: f32,
+        pwm: u8,
+    ) -> Result<(), ThermalError> {
+        if !margin.is_finite() {
+            return Err(ThermalError::InvalidParameter);
+        }
+        if pwm > 100 {
+            return Err(ThermalError::InvalidPWM);
+        }
+        self.controller.table.insert(TablePoint::new(margin, pwm))
+    }
+
+    pub fn remove_table_point(
+        &mut self,
+        margin: f32,
+    ) -> Result<(), ThermalError> {
+        // Don't leave the table controller with nothing to go on.
+        if self.controller.kind == ThermalController::Table
+            && self.controller.table.points().len() == 1
+        {
+            return Err(ThermalError::InvalidParameter);
+        }
+        self.controller.table.remove(margin)
+    }
+
+    /// Resets the control state and the controller configuration
     pub fn reset(&mut self) {
         self.reset_state();
 
-        // Reset the PID configuration from the BSP
-        self.pid_config = self.bsp.pid_config;
+        // Reset the controller configuration from the BSP and task config
+        self.controller = Controller::new(self.bsp.pid_config);
 
         // Set the target_margin to 0, indicating no overcooling
         self.target_margin = Celsius(0.0f32);
@@ -1036,18 +1265,18 @@ impl<'a> ThermalControl<'a> {
                 }
 
                 if any_power_down {
+                    counters::count!(LimitEvent::PowerDownTemperature);
                     self.state = ThermalControlState::Uncontrollable;
                     ringbuf_entry!(Trace::AutoState(self.get_state()));
 
                     ControlResult::PowerDown
                 } else if all_some {
                     // Transition to the Running state and run a single
-                    // iteration of the PID control loop.
+                    // iteration of the controller.
                     let mut pid = OneSidedPidState::default();
-                    let pwm = pid.run(
-                        &self.pid_config,
-                        self.target_margin.0 - worst_margin,
-                    );
+                    let pwm = self
+                        .controller
+                        .run(&mut pid, self.target_margin.0 - worst_margin);
                     self.state = ThermalControlState::Running {
                         values: values.map(Option::unwrap),
                         pid,
@@ -1080,11 +1309,13 @@ impl<'a> ThermalControl<'a> {
                 }
 
                 if any_power_down {
+                    counters::count!(LimitEvent::PowerDownTemperature);
                     self.state = ThermalControlState::Uncontrollable;
                     ringbuf_entry!(Trace::AutoState(self.get_state()));
 
                     ControlResult::PowerDown
                 } else if any_critical {
+                    counters::count!(LimitEvent::Critical);
                     self.state = ThermalControlState::Overheated {
                         values: *values,
                         start_time: now_ms,
@@ -1097,15 +1328,14 @@ impl<'a> ThermalControl<'a> {
                     // margin, which must be > 0.  This effectively tells the
                     // control loop to overcool the system.
                     //
-                    // `PidControl::run` expects the sign of the input and
+                    // `Controller::run` expects the sign of the input and
                     // output to match, so we negate things here: if the worst
                     // margin is negative (i.e. the system is overheating), then
                     // the input to `run` is positive, because we want a
                     // positive fan speed.
-                    let pwm = pid.run(
-                        &self.pid_config,
-                        self.target_margin.0 - worst_margin,
-                    );
+                    let pwm = self
+                        .controller
+                        .run(pid, self.target_margin.0 - worst_margin);
                     ControlResult::Pwm(PWMDuty(pwm as u8))
                 }
             }
@@ -1128,18 +1358,18 @@ impl<'a> ThermalControl<'a> {
                 }
 
                 if any_power_down {
+                    counters::count!(LimitEvent::PowerDownTemperature);
                     self.state = ThermalControlState::Uncontrollable;
                     ringbuf_entry!(Trace::AutoState(self.get_state()));
 
                     ControlResult::PowerDown
                 } else if all_subcritical {
                     // Transition to the Running state and run a single
-                    // iteration of the PID control loop.
+                    // iteration of the controller.
                     let mut pid = OneSidedPidState::default();
-                    let pwm = pid.run(
-                        &self.pid_config,
-                        self.target_margin.0 - worst_margin,
-                    );
+                    let pwm = self
+                        .controller
+                        .run(&mut pid, self.target_margin.0 - worst_margin);
                     self.state = ThermalControlState::Running {
                         values: *values,
                         pid,
@@ -1150,6 +1380,7 @@ impl<'a> ThermalControl<'a> {
                 } else if now_ms > *start_time + self.overheat_timeout_ms {
                     // If blasting the fans hasn't cooled us down in this amount
                     // of time, then something is terribly wrong - abort!
+                    counters::count!(LimitEvent::OverheatTimeout);
                     self.state = ThermalControlState::Uncontrollable;
                     ringbuf_entry!(Trace::AutoState(self.get_state()));
 
@@ -45,8 +45,8 @@ use idol_runtime::{NotificationHandler, RequestError};
 use ringbuf::*;
 use task_sensor_api::{Sensor as SensorApi, SensorId};
 use task_thermal_api::{
-    SensorReadError, ThermalAutoState, ThermalError, ThermalMode,
-    ThermalProperties,
+    SensorReadError, TablePoint, ThermalAutoState, ThermalController,
+    ThermalError, ThermalMode, ThermalProperties,
 };
 use userlib::units::PWMDuty;
 use userlib::*;
@@ -296,6 +296,64 @@ impl<'a> idl::InOrderThermalImpl for ServerImpl<'a> {
     ) -> Result<u64, RequestError<ThermalError>> {
         Ok(self.runtime)
     }
+
+    fn get_controller(
+        &mut self,
+        _: &RecvMessage,
+    ) -> Result<ThermalController, RequestError<ThermalError>> {
+        if self.mode != ThermalMode::Auto {
+            return Err(ThermalError::NotInAutoMode.into());
+        }
+        Ok(self.control.get_controller())
+    }
+
+    fn set_controller(
+        &mut self,
+        _: &RecvMessage,
+        controller: ThermalController,
+    ) -> Result<(), RequestError<ThermalError>> {
+        if self.mode != ThermalMode::Auto {
+            return Err(ThermalError::NotInAutoMode.into());
+        }
+        self.control.set_controller(controller)?;
+        Ok(())
+    }
+
+    fn get_table_point(
+        &mut self,
+        _: &RecvMessage,
+        index: u8,
+    ) -> Result<TablePoint, RequestError<ThermalError>> {
+        if self.mode != ThermalMode::Auto {
+            return Err(ThermalError::NotInAutoMode.into());
+        }
+        self.control.table_point(index).map_err(RequestError::from)
+    }
+
+    fn set_table_point(
+        &mut self,
+        _: &RecvMessage,
+        margin: f32,
+        pwm: u8,
+    ) -> Result<(), RequestError<ThermalError>> {
+        if self.mode != ThermalMode::Auto {
+            return Err(ThermalError::NotInAutoMode.into());
+        }
+        self.control.set_table_point(margin, pwm)?;
+        Ok(())
+    }
+
+    fn remove_table_point(
+        &mut self,
+        _: &RecvMessage,
+        margin: f32,
+    ) -> Result<(), RequestError<ThermalError>> {
+        if self.mode != ThermalMode::Auto {
+            return Err(ThermalError::NotInAutoMode.into());
+        }
+        self.control.remove_table_point(margin)?;
+        Ok(())
+    }
 }
 
 impl<'a> NotificationHandler for ServerImpl<'a> {
@@ -382,7 +440,8 @@ fn main() -> ! {
 
 mod idl {
     use super::{
-        ThermalAutoState, ThermalError, ThermalMode, ThermalProperties,
+        TablePoint, ThermalAutoState, ThermalController, ThermalError,
+        ThermalMode, ThermalProperties,
     };
     include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
 }
@@ -0,0 +1,324 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! USB CDC-ACM ("virtual serial port") class task.
+//!
+//! This task implements the two interfaces of a CDC Abstract Control Model
+//! function on top of the USB controller task, and serves the `Uart`
+//! interface, so that anything that can talk to a UART server (a console,
+//! say) can talk to the host over USB instead.
+//!
+//! Bytes from the host are collected into a ring; we only take a packet from
+//! the controller when there's room for it in the ring, so a slow reader
+//! makes the host wait rather than losing data, and `read` never reports
+//! `Overrun`. Bytes to the host are sent as they're written. If the host
+//! hasn't configured the device -- because nothing is plugged in, say --
+//! writes are discarded, so that a console doesn't block on an absent
+//! host.
+//!
+//! The line coding the host sets is remembered (and reported back), but has
+//! no effect; there's no line.
+//!
+//! The task needs notifications named `usb-control` and `usb-data`, and a
+//! task slot for `usb`.
+
+#![no_std]
+#![no_main]
+
+use drv_uart_api::UartError;
+use drv_usb_api::{
+    class::{self, ControlHandler},
+    descriptor, ControlRequest, Direction, EndpointKind, Usb, UsbError,
+    MAX_PACKET,
+};
+use heapless::Deque;
+use idol_runtime::{
+    ClientError, Leased, LenLimit, NotificationHandler, RequestError, R, W,
+};
+use ringbuf::*;
+use userlib::*;
+
+task_slot!(USB, usb);
+
+/// Size of the ring of bytes received from the host.
+const RING_SIZE: usize = 512;
+
+const TIMER_NOTIFICATION: u32 = 1 << 31;
+
+// CDC class codes (CDC 1.2 tables 2-4 and 6).
+const CDC_CLASS: u8 = 0x02;
+const ACM_SUBCLASS: u8 = 0x02;
+/// AT commands (V.250); hosts are more willing to bind to this than to "no
+/// protocol".
+const ACM_PROTOCOL: u8 = 0x01;
+const DATA_CLASS: u8 = 0x0A;
+
+// CDC functional descriptor subtypes.
+const HEADER: u8 = 0x00;
+const CALL_MANAGEMENT: u8 = 0x01;
+const ACM: u8 = 0x02;
+const UNION: u8 = 0x06;
+
+// ACM requests (PSTN 1.2 table 13).
+const SET_LINE_CODING: u8 = 0x20;
+const GET_LINE_CODING: u8 = 0x21;
+const SET_CONTROL_LINE_STATE: u8 = 0x22;
+
+/// Max packet size for the notification endpoint; we never send
+/// notifications, but ACM requires the endpoint.
+const NOTIFY_PACKET: u16 = 8;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Trace {
+    None,
+    LineCoding(u32),
+    LineState(u16),
+    Received(u8),
+    Discarded(u16),
+    Error(UsbError),
+}
+
+ringbuf!(Trace, 32, Trace::None);
+
+struct Handler {
+    /// The line coding (rate, stop bits, parity, data bits) last set.
+    line_coding: [u8; 7],
+}
+
+impl ControlHandler for Handler {
+    fn control(
+        &mut self,
+        request: &ControlRequest,
+        data: &[u8],
+        reply: &mut [u8],
+    ) -> Option<usize> {
+        if !request.is_class() {
+            return None;
+        }
+        match request.request {
+            SET_LINE_CODING if data.len() == self.line_coding.len() => {
+                self.line_coding.copy_from_slice(data);
+                let [a, b, c, d, ..] = self.line_coding;
+                ringbuf_entry!(Trace::LineCoding(u32::from_le_bytes([
+                    a, b, c, d
+                ])));
+                Some(0)
+            }
+            GET_LINE_CODING => {
+                reply[..self.line_coding.len()]
+                    .copy_from_slice(&self.line_coding);
+                Some(self.line_coding.len())
+            }
+            SET_CONTROL_LINE_STATE => {
+                ringbuf_entry!(Trace::LineState(request.value));
+                Some(0)
+            }
+            _ => None,
+        }
+    }
+}
+
+struct ServerImpl {
+    usb: Usb,
+    handler: Handler,
+    in_ep: u8,
+    out_ep: u8,
+    ring: Deque<u8, RING_SIZE>,
+}
+
+impl ServerImpl {
+    /// Takes packets from the host for as long as there's room for them.
+    fn pull(&mut self) {
+        let mut buf = [0u8; MAX_PACKET];
+        while RING_SIZE - self.ring.len() >= MAX_PACKET {
+            match self.usb.read(self.out_ep, &mut buf) {
+                Ok(n) => {
+                    ringbuf_entry!(Trace::Received(n as u8));
+                    for &b in &buf[..n] {
+                        // We checked there was room above.
+                        let _ = self.ring.push_back(b);
+                    }
+                }
+                Err(UsbError::Empty | UsbError::NotConfigured) => break,
+                Err(e) => {
+                    ringbuf_entry!(Trace::Error(e));
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Sends one packet, waiting for the previous one to go first. Returns
+    /// false if the host isn't listening.
+    fn send(&mut self, packet: &[u8]) -> bool {
+        loop {
+            match self.usb.write(self.in_ep, packet) {
+                Ok(()) => return true,
+                Err(UsbError::Busy) => self.wait(0),
+                Err(e) => {
+                    ringbuf_entry!(Trace::Error(e));
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// Waits for something to happen, and deals with it.
+    fn wait(&mut self, extra: u32) {
+        let bits = sys_recv_notification(
+            notifications::USB_CONTROL_MASK
+                | notifications::USB_DATA_MASK
+                | extra,
+        );
+        self.handle_notification(bits);
+    }
+}
+
+impl idl::InOrderUartImpl for ServerImpl {
+    fn read(
+        &mut self,
+        _: &RecvMessage,
+        timeout_ms: u32,
+        dest: LenLimit<Leased<W, [u8]>, 256>,
+    ) -> Result<usize, RequestError<UartError>> {
+        let dest = dest.into_inner();
+        let deadline =
+            sys_get_timer().now.saturating_add(u64::from(timeout_ms));
+
+        loop {
+            self.pull();
+            if !self.ring.is_empty() {
+                break;
+            }
+            if sys_get_timer().now >= deadline {
+                return Ok(0);
+            }
+            sys_set_timer(Some(deadline), TIMER_NOTIFICATION);
+            self.wait(TIMER_NOTIFICATION);
+        }
+        sys_set_timer(None, TIMER_NOTIFICATION);
+
+        let n = self.ring.len().min(dest.len());
+        let (a, b) = self.ring.as_slices();
+        let first = n.min(a.len());
+        dest.write_range(0..first, &a[..first])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        dest.write_range(first..n, &b[..n - first])
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+        for _ in 0..n {
+            self.ring.pop_front();
+        }
+
+        // We may have left packets with the controller for want of room.
+        self.pull();
+        Ok(n)
+    }
+
+    fn write(
+        &mut self,
+        _: &RecvMessage,
+        source: LenLimit<Leased<R, [u8]>, 256>,
+    ) -> Result<(), RequestError<UartError>> {
+        let source = source.into_inner();
+        let mut buf = [0u8; 256];
+        let buf = &mut buf[..source.len()];
+        source
+            .read_range(0..buf.len(), buf)
+            .map_err(|_| RequestError::Fail(ClientError::WentAway))?;
+
+        for packet in buf.chunks(MAX_PACKET) {
+            if !self.send(packet) {
+                ringbuf_entry!(Trace::Discarded(buf.len() as u16));
+                return Ok(());
+            }
+        }
+        // The host only knows a transfer is over when it sees a short
+        // packet.
+        if buf.len() % MAX_PACKET == 0 && !buf.is_empty() {
+            self.send(&[]);
+        }
+        Ok(())
+    }
+}
+
+impl NotificationHandler for ServerImpl {
+    fn current_notification_mask(&self) -> u32 {
+        notifications::USB_CONTROL_MASK | notifications::USB_DATA_MASK
+    }
+
+    fn handle_notification(&mut self, bits: u32) {
+        if bits & notifications::USB_CONTROL_MASK != 0 {
+            if let Err(e) = class::handle_control(&self.usb, &mut self.handler)
+            {
+                ringbuf_entry!(Trace::Error(e));
+            }
+        }
+        if bits & notifications::USB_DATA_MASK != 0 {
+            self.pull();
+        }
+    }
+}
+
+#[export_name = "main"]
+fn main() -> ! {
+    let usb = Usb::from(USB.get_task_id());
+
+    let comm = usb
+        .claim_interfaces(2, notifications::USB_CONTROL_MASK)
+        .unwrap_lite();
+    let data = comm + 1;
+    let claim = |kind, direction, max_packet| {
+        usb.claim_endpoint(
+            kind,
+            direction,
+            max_packet,
+            notifications::USB_DATA_MASK,
+        )
+        .unwrap_lite()
+    };
+    let notify_ep =
+        claim(EndpointKind::Interrupt, Direction::In, NOTIFY_PACKET);
+    let in_ep = claim(EndpointKind::Bulk, Direction::In, MAX_PACKET as u16);
+    let out_ep = claim(EndpointKind::Bulk, Direction::Out, MAX_PACKET as u16);
+
+    let mut buf = [0u8; 128];
+    let mut w = descriptor::Writer::new(&mut buf);
+    w.association(comm, 2, CDC_CLASS, ACM_SUBCLASS, ACM_PROTOCOL)
+        .interface(comm, 1, CDC_CLASS, ACM_SUBCLASS, ACM_PROTOCOL)
+        // CDC 1.10
+        .descriptor(descriptor::CS_INTERFACE, &[HEADER, 0x10, 0x01])
+        // We don't do call management, but say which interface carries data.
+        .descriptor(descriptor::CS_INTERFACE, &[CALL_MANAGEMENT, 0x00, data])
+        // We support the line coding and control line state requests.
+        .descriptor(descriptor::CS_INTERFACE, &[ACM, 0x02])
+        .descriptor(descriptor::CS_INTERFACE, &[UNION, comm, data])
+        .endpoint(notify_ep, EndpointKind::Interrupt, NOTIFY_PACKET, 255)
+        .interface(data, 2, DATA_CLASS, 0, 0)
+        .endpoint(out_ep, EndpointKind::Bulk, MAX_PACKET as u16, 0)
+        .endpoint(in_ep, EndpointKind::Bulk, MAX_PACKET as u16, 0);
+    usb.add_descriptors(w.finish()).unwrap_lite();
+
+    let mut server = ServerImpl {
+        usb,
+        handler: Handler {
+            // 115200 baud, 1 stop bit, no parity, 8 data bits
+            line_coding: [0x00, 0xC2, 0x01, 0x00, 0, 0, 8],
+        },
+        in_ep,
+        out_ep,
+        ring: Deque::new(),
+    };
+    let mut buffer = [0; idl::INCOMING_SIZE];
+    loop {
+        idol_runtime::dispatch(&mut buffer, &mut server);
+    }
+}
+
+mod idl {
+    use drv_uart_api::UartError;
+
+    include!(concat!(env!("OUT_DIR"), "/server_stub.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/notifications.rs"));
@@ -27,9 +27,9 @@ use hubris_num_tasks::NUM_TASKS;
 use ringbuf::{ringbuf, ringbuf_entry};
 use test_api::{AssistOp, RunnerOp, SuiteOp};
 use userlib::{
-    hl, kipc, task_slot, FaultInfo, FaultSource, Generation, IrqStatus,
-    LeaseAttributes, ReplyFaultReason, SchedState, TaskId, TaskState,
-    UsageError,
+    hl, kipc, task_slot, FaultInfo, Generation, IrqStatus, LeaseAttributes,
+    MemoryFaultDetail, MemoryFaultKind, ReplyFaultDetail, ReplyFaultReason,
+    SchedState, TaskId, TaskState, UsageError,
 };
 use zerocopy::AsBytes;
 
@@ -78,6 +78,7 @@ test_cases! {
     test_send,
     test_recv_reply,
     test_recv_reply_fault,
+    test_recv_reply_fault_detail,
     #[cfg(any(armv7m, armv8m))]
     test_floating_point_lowregs,
     #[cfg(any(armv7m, armv8m))]
@@ -237,7 +238,58 @@ fn test_recv_reply_fault() {
                 fault,
                 FaultInfo::FromServer(
                     this_task,
-                    ReplyFaultReason::AccessViolation
+                    ReplyFaultReason::AccessViolation,
+                    None,
+                )
+            );
+        }
+        _ => {
+            panic!("expected fault");
+        }
+    }
+}
+
+/// Tests that detail attached to a reply fault ends up in the client's fault
+/// record.
+fn test_recv_reply_fault_detail() {
+    let assist = assist_task_id();
+
+    let challenge = 0xCAFE_F00Du32;
+    let mut response = 0_u32;
+    let (rc, len) = userlib::sys_send(
+        assist,
+        AssistOp::SendBack as u16,
+        &challenge.to_le_bytes(),
+        response.as_bytes_mut(),
+        &[],
+    );
+    assert_eq!(rc, 0);
+    assert_eq!(len, 4);
+
+    let rm = userlib::sys_recv_open(response.as_bytes_mut(), 0);
+    let detail = ReplyFaultDetail {
+        argument: 1,
+        value: 0x1234,
+    };
+    userlib::sys_reply_fault_detail(
+        assist,
+        rm.reply_nonce(),
+        ReplyFaultReason::BadLeases,
+        Some(detail),
+    );
+
+    let status = kipc::read_task_status(ASSIST.get_task_index().into());
+    let this_task = TaskId::for_index_and_gen(1, Generation::default());
+    let this_task = userlib::sys_refresh_task_id(this_task);
+
+    match status {
+        TaskState::Faulted { fault, .. } => {
+            assert_eq!(
+                fault,
+                FaultInfo::FromServer(
+                    this_task,
+                    ReplyFaultReason::BadLeases,
+                    Some(detail),
                 )
             );
         }
@@ -307,13 +359,16 @@ fn test_fault_badmem() {
     let bad_address = BAD_ADDRESS;
     let fault = test_fault(AssistOp::BadMemory, bad_address);
 
-    assert_fault_eq!(
-        fault,
-        FaultInfo::MemoryAccess {
-            address: Some(bad_address),
-            source: FaultSource::User,
+    match fault {
+        FaultInfo::MemoryFault(detail) => {
+            assert_eq!(detail.kind, MemoryFaultKind::Protection);
+            assert_eq!(detail.address, Some(bad_address));
+            assert!(detail.pc.is_some());
         }
-    );
+        _ => {
+            panic!("expected MemoryFault; found {:?}", fault);
+        }
+    }
 }
 
 fn test_fault_stackoverflow() {
@@ -355,11 +410,11 @@ fn test_fault_textoob() {
     let fault = test_fault(AssistOp::TextOutOfBounds, BAD_ADDRESS);
 
     match fault {
-        FaultInfo::BusError { .. } | FaultInfo::MemoryAccess { .. } => {}
+        FaultInfo::MemoryFault(_) => {}
         #[cfg(armv6m)]
         FaultInfo::InvalidOperation(_) => {}
         _ => {
-            panic!("expected BusFault or MemoryAccess; found {:?}", fault);
+            panic!("expected MemoryFault; found {:?}", fault);
         }
     }
 }
@@ -367,11 +422,14 @@ fn test_fault_textoob() {
 fn test_fault_stackoob() {
     let fault = test_fault(AssistOp::StackOutOfBounds, 0);
     match fault {
-        FaultInfo::MemoryAccess { .. } => {}
+        FaultInfo::MemoryFault(MemoryFaultDetail {
+            kind: MemoryFaultKind::Protection,
+            ..
+        }) => {}
         #[cfg(armv6m)]
         FaultInfo::InvalidOperation(_) => {}
         _ => {
-            panic!("expected MemoryAccess; found {:?}", fault);
+            panic!("expected MemoryFault; found {:?}", fault);
         }
     }
 }
@@ -380,11 +438,14 @@ fn test_fault_buserror() {
     let fault = test_fault(AssistOp::BusError, 0);
 
     match fault {
-        FaultInfo::BusError { .. } => {}
+        FaultInfo::MemoryFault(MemoryFaultDetail {
+            kind: MemoryFaultKind::Bus,
+            ..
+        }) => {}
         #[cfg(armv6m)]
         FaultInfo::InvalidOperation(_) => {}
         _ => {
-            panic!("expected BusFault; found {:?}", fault);
+            panic!("expected MemoryFault; found {:?}", fault);
         }
     }
 }
@@ -1546,7 +1607,7 @@ fn idol_handle() -> test_idol_api::IdolTest {
 
 /// Restarts the assistant task.
 fn restart_assistant() {
-    kipc::restart_task(ASSIST.get_task_index().into(), true);
+    assert!(kipc::restart_task(ASSIST.get_task_index().into(), true));
 }
 
 /// Contacts the runner task to read (and clear) its accumulated set of
@@ -1568,7 +1629,7 @@ fn main() -> ! {
     // Work out the assistant generation. Restart it to ensure it's running
     // before we try talking to it. TODO: this is kind of gross, we need a way
     // to just ask.
-    kipc::restart_task(ASSIST.get_task_index().into(), true);
+    assert!(kipc::restart_task(ASSIST.get_task_index().into(), true));
     loop {
         let assist = assist_task_id();
         let challenge = 0xDEADBEEF_u32;